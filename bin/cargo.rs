@@ -1,3 +1,3 @@
 fn main() {
-    cargo_rbrew::run(argp::cargo_parse_args_or_exit())
+    cargo_rbrew::run(cargo_rbrew::parse_args_or_exit(true))
 }
@@ -1,5 +1,3 @@
-use argp::HelpStyle;
-
 fn main() {
-    cargo_rbrew::run(argp::parse_args_or_exit(&HelpStyle::default()))
+    cargo_rbrew::run(cargo_rbrew::parse_args_or_exit(false))
 }
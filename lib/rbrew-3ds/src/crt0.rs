@@ -0,0 +1,32 @@
+//! The entry point, at the very start of the code segment.
+//!
+//! Homebrew loaders look for the `_prm` block right after the first instruction and fill
+//! it in with the services and arguments they pass to the program.
+
+#[cfg(target_arch = "arm")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.start, "ax"
+    .arm
+    .global __start
+__start:
+    b 1f
+    .ascii "_prm"
+    @ The service handle override list, set when launched by a homebrew loader.
+    .word 0
+    @ The APT application ID.
+    .word 0x300
+    @ The sizes of the heap and the linear heap.
+    .word 24 * 1024 * 1024
+    .word 32 * 1024 * 1024
+    @ The argument list and run flags.
+    .word 0
+    .word 0
+1:
+    ldr r0, =main
+    blx r0
+2:
+    b 2b
+    .ltorg
+"#
+);
@@ -0,0 +1,11 @@
+/*!
+rbrew-3ds is a library for writing homebrew 3ds programs in Rust.
+
+Programs are linked with `targets/3ds.ld` and provide their entry point as
+`#[no_mangle] extern "C" fn main() -> !`, which crt0 calls on the stack the loader set up.
+The loader maps the segments and zeroes `.bss` itself.
+*/
+
+#![no_std]
+
+mod crt0;
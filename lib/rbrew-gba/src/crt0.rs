@@ -0,0 +1,50 @@
+//! The cartridge entry point.
+//!
+//! The BIOS jumps to the first word of the ROM in ARM mode. The rest of the 192-byte
+//! header is reserved here and filled in by rbrew when converting to `.gba`.
+
+#[cfg(target_arch = "arm")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.header, "ax"
+    .arm
+    .global __start
+__start:
+    b __rbrew_start
+    .space 188
+
+__rbrew_start:
+    @ IRQ mode stack
+    mov r0, #0x12
+    msr cpsr_c, r0
+    ldr sp, =0x03007fa0
+    @ System mode stack
+    mov r0, #0x1f
+    msr cpsr_c, r0
+    ldr sp, =0x03007f00
+
+    ldr r0, =__data_load
+    ldr r1, =__data_start
+    ldr r2, =__data_end
+1:
+    cmp r1, r2
+    ldrlo r3, [r0], #4
+    strlo r3, [r1], #4
+    blo 1b
+
+    ldr r1, =__bss_start
+    ldr r2, =__bss_end
+    mov r3, #0
+2:
+    cmp r1, r2
+    strlo r3, [r1], #4
+    blo 2b
+
+    ldr r0, =main
+    mov lr, pc
+    bx r0
+3:
+    b 3b
+    .ltorg
+"#
+);
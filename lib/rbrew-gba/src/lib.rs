@@ -0,0 +1,11 @@
+/*!
+rbrew-gba is a library for writing homebrew gba programs in Rust.
+
+Programs are linked with `targets/gba.ld` and provide their entry point as
+`#[no_mangle] extern "C" fn main() -> !`, which crt0 calls in System mode once `.data`
+has been copied to IWRAM and `.bss` has been cleared.
+*/
+
+#![no_std]
+
+mod crt0;
@@ -0,0 +1,395 @@
+//! ARAM, the 16 MiB of auxiliary RAM behind the DSP, which the processor reaches only
+//! through DMA.
+//!
+//! ARAM is allocated as [`AramHandle`]s, which samples and other cold data are stored in
+//! and loaded back from. The transfers are queued and run one after the other, each
+//! calling its callback as it completes:
+//!
+//! ```ignore
+//! use rbrew_gc::aram::AramContext;
+//!
+//! #[repr(C, align(32))]
+//! struct Level([u8; 0x1000]);
+//!
+//! let mut level = Level([0; 0x1000]);
+//! let cold = AramContext::global().alloc::<u8>(level.0.len()).unwrap();
+//! cold.store(0, &level.0);
+//! // ...
+//! cold.load(0, &mut level.0);
+//! ```
+
+use crate::{
+    audio::{dsp::DspContext, update_cr, DspArDmaCountHigh, DSP},
+    cache,
+    interrupt::{self, Source},
+};
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+use rbrew_shared::interrupt;
+use spin::Mutex;
+
+/// The bytes of ARAM.
+pub const ARAM_SIZE: u32 = 0x100_0000;
+/// The start of ARAM, which the DSP's microcode conventionally uses, and which is not
+/// allocated.
+pub const ARAM_RESERVED: u32 = 0x4000;
+/// The allocations that can be live at once.
+pub const MAX_ALLOCATIONS: usize = 64;
+/// The transfers that can be queued at once.
+pub const QUEUE_LEN: usize = 16;
+
+/// The allocations, sorted by address.
+struct Allocator {
+    blocks: [(u32, u32); MAX_ALLOCATIONS],
+    len: usize,
+}
+
+impl Allocator {
+    /// The address of a new allocation of `size` bytes, the first gap it fits in.
+    fn alloc(&mut self, size: u32) -> Option<u32> {
+        if self.len == MAX_ALLOCATIONS {
+            return None;
+        }
+        let mut start = ARAM_RESERVED;
+        for i in 0..=self.len {
+            let next = (i < self.len).then(|| self.blocks[i]);
+            if next.map_or(ARAM_SIZE, |(address, _)| address) - start >= size {
+                self.blocks.copy_within(i..self.len, i + 1);
+                self.blocks[i] = (start, size);
+                self.len += 1;
+                return Some(start);
+            }
+            if let Some((address, len)) = next {
+                start = address + len;
+            }
+        }
+        None
+    }
+
+    fn free(&mut self, address: u32) {
+        if let Some(i) = self.blocks[..self.len]
+            .iter()
+            .position(|&(a, _)| a == address)
+        {
+            self.blocks.copy_within(i + 1..self.len, i);
+            self.len -= 1;
+        }
+    }
+
+    /// The bytes not allocated.
+    fn available(&self) -> u32 {
+        let used: u32 = self.blocks[..self.len].iter().map(|&(_, len)| len).sum();
+        ARAM_SIZE - ARAM_RESERVED - used
+    }
+}
+
+static ALLOCATOR: Mutex<Allocator> = Mutex::new(Allocator {
+    blocks: [(0, 0); MAX_ALLOCATIONS],
+    len: 0,
+});
+
+#[derive(Clone, Copy)]
+struct Request {
+    memory: u32,
+    aram: u32,
+    len: u32,
+    to_memory: bool,
+    callback: Option<fn()>,
+}
+
+/// The transfers queued, the first one running.
+struct Queue {
+    requests: [Option<Request>; QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    requests: [None; QUEUE_LEN],
+    head: 0,
+    len: 0,
+});
+/// The transfers queued and completed since the start, which tickets are numbered by.
+static QUEUED: AtomicU32 = AtomicU32::new(0);
+static COMPLETED: AtomicU32 = AtomicU32::new(0);
+
+/// Starts the DMA of `request`.
+fn start(request: &Request) {
+    unsafe {
+        DSP::ar_dma_memory_high_write((request.memory >> 16) as u16);
+        DSP::ar_dma_memory_low_write(request.memory as u16);
+        DSP::ar_dma_aram_high_write((request.aram >> 16) as u16);
+        DSP::ar_dma_aram_low_write(request.aram as u16);
+        DSP::ar_dma_count_high_write(
+            DspArDmaCountHigh::default()
+                .with_length((request.len >> 16) as u16)
+                .with_to_memory(request.to_memory)
+                .0,
+        );
+        DSP::ar_dma_count_low_write(request.len as u16);
+    }
+}
+
+/// Takes the transfer that completed, starting the next one, and calls its callback,
+/// acknowledging the interrupt.
+fn complete() {
+    if !unsafe { DSP::cr_ar_int_read() } {
+        return;
+    }
+    update_cr(|cr| cr.with_ar_int(true));
+    let callback = {
+        let mut queue = QUEUE.lock();
+        if queue.len == 0 {
+            return;
+        }
+        let head = queue.head;
+        let done = queue.requests[head].take();
+        queue.head = (head + 1) % QUEUE_LEN;
+        queue.len -= 1;
+        COMPLETED.fetch_add(1, Ordering::Release);
+        if let Some(next) = queue.requests[queue.head].filter(|_| queue.len > 0) {
+            start(&next);
+        }
+        done.and_then(|request| request.callback)
+    };
+    if let Some(callback) = callback {
+        callback();
+    }
+}
+
+#[interrupt(Source::Dsp)]
+fn dma_interrupt() {
+    complete();
+}
+
+/// Queues `request`, waiting for a free slot if the queue is full.
+fn queue(request: Request) -> Transfer {
+    loop {
+        let ticket = interrupt::free(|| {
+            let mut queue = QUEUE.lock();
+            if queue.len == QUEUE_LEN {
+                return None;
+            }
+            let tail = (queue.head + queue.len) % QUEUE_LEN;
+            queue.requests[tail] = Some(request);
+            queue.len += 1;
+            if queue.len == 1 {
+                start(&request);
+            }
+            Some(QUEUED.fetch_add(1, Ordering::AcqRel) + 1)
+        });
+        match ticket {
+            Some(ticket) => return Transfer(ticket),
+            None => poll(),
+        }
+    }
+}
+
+/// Takes the transfers that completed, for programs that do not enable interrupts.
+#[inline]
+pub fn poll() {
+    interrupt::free(complete);
+}
+
+/// A transfer queued, which completes in the order it was queued in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[must_use]
+pub struct Transfer(u32);
+
+impl Transfer {
+    /// Whether the transfer completed, polling the DMA first.
+    pub fn is_done(self) -> bool {
+        poll();
+        COMPLETED.load(Ordering::Acquire).wrapping_sub(self.0) as i32 >= 0
+    }
+
+    pub fn wait(self) {
+        while !self.is_done() {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// `len` values of `T` allocated in ARAM, freed when dropped.
+///
+/// Transfers are in 32-byte units: the memory they go to or from is 32-byte aligned, and
+/// their offsets and lengths in bytes are multiples of 32.
+#[derive(Debug)]
+pub struct AramHandle<T> {
+    address: u32,
+    len: usize,
+    _mark: PhantomData<T>,
+}
+
+impl<T: Copy> AramHandle<T> {
+    /// The address in ARAM, which the DSP's accelerator reads from.
+    #[inline]
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The transfer of `data` from the value `offset`.
+    ///
+    /// Panics if it is not in 32-byte units or does not fit.
+    fn request(&self, offset: usize, data: *const T, len: usize, to_memory: bool) -> Request {
+        let (offset, bytes) = (offset * size_of::<T>(), len * size_of::<T>());
+        assert!(
+            (data as usize).is_multiple_of(32)
+                && offset.is_multiple_of(32)
+                && bytes.is_multiple_of(32),
+            "aram transfers are in 32-byte units"
+        );
+        assert!(
+            offset + bytes <= self.len * size_of::<T>(),
+            "the transfer does not fit in the allocation"
+        );
+        Request {
+            memory: data as u32 & 0x3fff_ffff,
+            aram: self.address + offset as u32,
+            len: bytes as u32,
+            to_memory,
+            callback: None,
+        }
+    }
+
+    /// Stores `data` from the value `offset`, waiting for the transfer.
+    ///
+    /// Panics if it is not in 32-byte units or does not fit.
+    pub fn store(&self, offset: usize, data: &[T]) {
+        unsafe { self.store_async(offset, data, None) }.wait();
+    }
+
+    /// Loads `data` from the value `offset`, waiting for the transfer.
+    ///
+    /// Panics if it is not in 32-byte units or does not fit.
+    pub fn load(&self, offset: usize, data: &mut [T]) {
+        unsafe { self.load_async(offset, data, None) }.wait();
+    }
+
+    /// Queues storing `data` from the value `offset`, flushing it from the cache.
+    /// `callback` is called as the transfer completes, by the DMA's interrupt handler with
+    /// interrupts disabled, or from [`poll`].
+    ///
+    /// Panics if it is not in 32-byte units or does not fit.
+    ///
+    /// # Safety
+    /// `data` must stay in memory, unchanged, until the transfer completes.
+    pub unsafe fn store_async(
+        &self,
+        offset: usize,
+        data: &[T],
+        callback: Option<fn()>,
+    ) -> Transfer {
+        let request = self.request(offset, data.as_ptr(), data.len(), false);
+        cache::flush_data_range(data.as_ptr().cast(), request.len as usize);
+        queue(Request {
+            callback,
+            ..request
+        })
+    }
+
+    /// Queues loading `data` from the value `offset`, invalidating it from the cache.
+    /// `callback` is called as the transfer completes, by the DMA's interrupt handler with
+    /// interrupts disabled, or from [`poll`].
+    ///
+    /// Panics if it is not in 32-byte units or does not fit.
+    ///
+    /// # Safety
+    /// `data` must stay in memory, and be left alone, until the transfer completes.
+    pub unsafe fn load_async(
+        &self,
+        offset: usize,
+        data: &mut [T],
+        callback: Option<fn()>,
+    ) -> Transfer {
+        let request = self.request(offset, data.as_ptr(), data.len(), true);
+        cache::flush_data_range(data.as_ptr().cast(), request.len as usize);
+        queue(Request {
+            callback,
+            ..request
+        })
+    }
+}
+
+impl<T> Drop for AramHandle<T> {
+    fn drop(&mut self) {
+        interrupt::free(|| ALLOCATOR.lock().free(self.address));
+    }
+}
+
+static IS_INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum AramInitError {
+    AlreadyInitialized,
+}
+
+#[derive(Clone, Copy)]
+pub struct AramContext {
+    // Makes the type non-trivially constructible.
+    _mark: PhantomData<()>,
+}
+
+impl AramContext {
+    fn init_aram() -> Result<(), AramInitError> {
+        // Which sets up ARAM.
+        DspContext::global();
+        update_cr(|cr| cr.with_ar_int(true).with_ar_int_mask(true));
+        Ok(())
+    }
+
+    /// Sets up ARAM and the interrupt of its DMA.
+    pub fn init() -> Result<(), AramInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
+            Err(AramInitError::AlreadyInitialized)
+        } else {
+            Self::init_aram()
+        }
+    }
+
+    /// The global ARAM context, initialized if it was not.
+    pub fn global() -> Self {
+        #[allow(unreachable_patterns)]
+        match Self::init() {
+            Err(AramInitError::AlreadyInitialized) | Ok(_) => {}
+            Err(e) => panic!("aram failed to initialize: {e:?}"),
+        }
+        unsafe { Self::global_unchecked() }
+    }
+
+    /// # Safety
+    /// Requires that the global ARAM context has been initialized.
+    /// This is ensured by [`Self::global`] or [`Self::init`].
+    pub unsafe fn global_unchecked() -> Self {
+        Self { _mark: PhantomData }
+    }
+
+    /// Allocates `len` values of `T`, in whole 32-byte units, or `None` if they do not fit
+    /// or [`MAX_ALLOCATIONS`] are live.
+    pub fn alloc<T: Copy>(self, len: usize) -> Option<AramHandle<T>> {
+        let size = (len * size_of::<T>()).next_multiple_of(32).max(32);
+        let address = interrupt::free(|| ALLOCATOR.lock().alloc(size as u32))?;
+        Some(AramHandle {
+            address,
+            len,
+            _mark: PhantomData,
+        })
+    }
+
+    /// The bytes of ARAM not allocated, which may be split between gaps.
+    pub fn available(self) -> u32 {
+        interrupt::free(|| ALLOCATOR.lock().available())
+    }
+}
@@ -0,0 +1,88 @@
+//! Sound: the audio interface, which plays the samples the DSP interface's DMA reads from
+//! memory, and the DSP, which runs the microcode mixing them. Without microcode, the
+//! [`mixer`] mixes them on the processor, from memory or [`stream`]ed from files, and
+//! plays one-shot [`sfx`].
+
+use rbrew_shared::iotype;
+
+pub mod ai;
+pub mod dsp;
+pub mod mixer;
+pub mod sfx;
+pub mod stream;
+
+iotype! {
+    /// The DSP interface, which also runs the DMA of the samples the audio interface plays.
+    pub type DSP: 0x0c005000, 0x40, cached 0x8000_0000, uncached 0xc000_0000 {
+        /// The mail to the DSP, sent by writing its low half. Its high bit stays set until
+        /// the DSP reads it.
+        mail_to_high: mut u16 = 0x00,
+        mail_to_low: mut u16 = 0x02,
+        /// The mail from the DSP, its high bit set until its low half is read.
+        mail_from_high: const u16 = 0x04,
+        mail_from_low: const u16 = 0x06,
+        /// The control and the interrupts of the DSP, the audio DMA and ARAM. The
+        /// interrupts are acknowledged by writing them set.
+        cr: mut u16 = 0x0a {
+            reset: 0,
+            /// Raises the DSP's interrupt.
+            dsp_assert: 1,
+            halt: 2,
+            /// The audio DMA started a block.
+            ai_int: 3,
+            ai_int_mask: 4,
+            /// An ARAM DMA completed.
+            ar_int: 5,
+            ar_int_mask: 6,
+            /// The DSP raised its interrupt.
+            dsp_int: 7,
+            dsp_int_mask: 8,
+            /// Set while an ARAM DMA runs.
+            ar_dma: 9,
+            /// Resets the DSP into its boot ROM.
+            reset_init: 11,
+        },
+        /// The size of ARAM and of its expansion, which the DSP's accelerator reads.
+        ar_size: mut u16 = 0x12,
+        ar_mode: mut u16 = 0x16,
+        /// The refresh period of ARAM, in cycles of its clock.
+        ar_refresh: mut u16 = 0x1a,
+        /// The physical address in memory of an ARAM DMA, 32-byte aligned.
+        ar_dma_memory_high: mut u16 = 0x20,
+        ar_dma_memory_low: mut u16 = 0x22,
+        /// The address in ARAM of the DMA, 32-byte aligned.
+        ar_dma_aram_high: mut u16 = 0x24,
+        ar_dma_aram_low: mut u16 = 0x26,
+        /// The length of the DMA in bytes, a multiple of 32, which writing its low half
+        /// starts.
+        ar_dma_count_high: mut u16 = 0x28 {
+            length: 0..=14,
+            /// From ARAM to memory rather than to ARAM.
+            to_memory: 15,
+        },
+        ar_dma_count_low: mut u16 = 0x2a,
+        /// The physical address of the samples the audio DMA reads, 32-byte aligned.
+        dma_start_high: mut u16 = 0x30,
+        dma_start_low: mut u16 = 0x32,
+        /// The length of the block, latched when the DMA starts it, and whether it runs.
+        dma_control: mut u16 = 0x36 {
+            /// In 32-byte units.
+            length: 0..=14,
+            enable: 15,
+        },
+        /// The 32-byte units left of the block playing.
+        dma_left: const u16 = 0x3a,
+    }
+}
+
+/// The interrupts of the control register, which writing it set acknowledges.
+const CR_INTERRUPTS: u16 = 1 << 3 | 1 << 5 | 1 << 7;
+
+/// Writes the control register as `f` changes it, acknowledging only the interrupts `f`
+/// sets.
+pub(crate) fn update_cr(f: impl FnOnce(DspCr) -> DspCr) {
+    unsafe {
+        let cr = DspCr(DSP::cr_read() & !CR_INTERRUPTS);
+        DSP::cr_write(f(cr).0);
+    }
+}
@@ -0,0 +1,334 @@
+//! The audio interface, which plays 16-bit stereo samples at 32 or 48 kHz.
+//!
+//! The samples are read from memory by the audio DMA, a block at a time. The DMA latches
+//! the block to play next when it starts one, raising its interrupt, and repeats the last
+//! block it was given until it is given another. [`AiContext::start`] plays two buffers in
+//! turn, refilling the one that finished while the other plays:
+//!
+//! ```ignore
+//! use rbrew_gc::audio::ai::{AiContext, Frame};
+//!
+//! fn fill(frames: &mut [Frame]) {
+//!     for frame in frames {
+//!         // A square wave, in fixed point: the callback runs without the FPU.
+//!         *frame = Frame::mono(next_sample());
+//!     }
+//! }
+//!
+//! AiContext::global().start(fill);
+//! ```
+//!
+//! The callback is called as the DMA starts playing one buffer, with the other, and has
+//! until the first ends to return: [`SampleRate::buffer_micros`], about 10.7 ms at 48 kHz.
+//! If it takes longer, the DMA plays the first buffer again. It runs with interrupts
+//! disabled and without the FPU, and is the only code touching the buffer it is given.
+//! The [`mixer`](super::mixer) fills the buffers itself, unless its callback is replaced
+//! with [`AiContext::set_callback`].
+
+use super::{update_cr, DspDmaControl, DSP};
+use crate::{
+    cache,
+    interrupt::{self, Source},
+};
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, Ordering},
+};
+use rbrew_shared::{interrupt, iotype};
+
+iotype! {
+    pub type AI: 0x0c006c00, 0x20, cached 0x8000_0000, uncached 0xc000_0000 {
+        cr: mut u32 {
+            /// Plays the audio streamed from the disc.
+            play: 0,
+            /// The rate of the streamed audio, 48 kHz rather than 32 kHz.
+            stream_48khz: 1,
+            /// The interrupt of the streamed sample count reaching the one of `it`.
+            int_mask: 2,
+            int: 3,
+            int_valid: 4,
+            /// Resets the streamed sample count.
+            reset_count: 5,
+            /// The rate of the samples of the audio DMA, 32 kHz rather than 48 kHz.
+            dma_32khz: 6,
+        },
+        /// The volume of the streamed audio, up to 255.
+        vr: mut u32 {
+            left: 0..=7,
+            right: 8..=15,
+        },
+        /// The samples streamed since the count was reset.
+        scnt: const u32,
+        it: mut u32,
+    }
+}
+
+/// The rate the samples of the audio DMA play at.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleRate {
+    Hz32000,
+    #[default]
+    Hz48000,
+}
+
+impl SampleRate {
+    #[inline]
+    pub const fn hz(self) -> u32 {
+        match self {
+            SampleRate::Hz32000 => 32000,
+            SampleRate::Hz48000 => 48000,
+        }
+    }
+
+    /// How long a buffer of [`BUFFER_FRAMES`] plays, in microseconds.
+    #[inline]
+    pub const fn buffer_micros(self) -> u32 {
+        (BUFFER_FRAMES as u64 * 1_000_000 / self.hz() as u64) as u32
+    }
+}
+
+/// A sample of each channel, in the order the DMA reads them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct Frame {
+    pub right: i16,
+    pub left: i16,
+}
+
+impl Frame {
+    #[inline]
+    pub const fn new(left: i16, right: i16) -> Self {
+        Self { right, left }
+    }
+
+    /// The same sample on both channels.
+    #[inline]
+    pub const fn mono(sample: i16) -> Self {
+        Self::new(sample, sample)
+    }
+}
+
+/// The frames of each buffer of [`AiContext::start`], about 10 ms at 48 kHz.
+pub const BUFFER_FRAMES: usize = 512;
+
+/// A buffer the DMA reads, in 32-byte units.
+#[repr(C, align(32))]
+struct Buffer([Frame; BUFFER_FRAMES]);
+
+static mut BUFFERS: [Buffer; 2] = [const { Buffer([Frame::new(0, 0); BUFFER_FRAMES]) }; 2];
+/// The buffer refilled at the next interrupt, the one not playing.
+static NEXT: AtomicU8 = AtomicU8::new(0);
+/// The `fn(&mut [Frame])` filling the buffers, null if they are not played.
+static FILL: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// The largest block of the DMA, in bytes.
+pub const MAX_DMA_LEN: usize = 0x7fff * 32;
+
+/// Gives the DMA the block of `len` bytes at `start`, which it plays from the block after
+/// the one playing.
+///
+/// # Safety
+/// The block must stay in memory, flushed from the cache, while it plays.
+unsafe fn set_dma(start: *const u8, len: usize) {
+    let address = start as u32 & 0x3fff_ffff;
+    unsafe {
+        DSP::dma_start_high_write((address >> 16) as u16);
+        DSP::dma_start_low_write(address as u16);
+        let enable = DSP::dma_control_enable_read();
+        DSP::dma_control_write(
+            DspDmaControl::default()
+                .with_length((len / 32) as u16)
+                .with_enable(enable)
+                .0,
+        );
+    }
+}
+
+/// Fills the buffer `index` with `fill` and gives it to the DMA.
+fn queue(index: u8, fill: fn(&mut [Frame])) {
+    let buffer = unsafe { &raw mut BUFFERS[index as usize] };
+    fill(unsafe { &mut (*buffer).0 });
+    cache::flush_data_range(buffer.cast(), size_of::<Buffer>());
+    unsafe { set_dma(buffer.cast(), size_of::<Buffer>()) };
+}
+
+/// Refills the buffer that finished if the DMA started the other, acknowledging its
+/// interrupt.
+fn refill() {
+    if !unsafe { DSP::cr_ai_int_read() } {
+        return;
+    }
+    update_cr(|cr| cr.with_ai_int(true));
+    let fill = FILL.load(Ordering::Acquire);
+    if fill.is_null() {
+        return;
+    }
+    let fill = unsafe { core::mem::transmute::<*mut (), fn(&mut [Frame])>(fill) };
+    let next = NEXT.load(Ordering::Relaxed);
+    queue(next, fill);
+    NEXT.store(next ^ 1, Ordering::Relaxed);
+}
+
+#[interrupt(Source::Dsp)]
+fn dma_interrupt() {
+    refill();
+}
+
+static IS_INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum AiInitError {
+    AlreadyInitialized,
+}
+
+#[derive(Clone, Copy)]
+pub struct AiContext {
+    // Makes the type non-trivially constructible.
+    _mark: PhantomData<()>,
+}
+
+impl AiContext {
+    fn init_ai(rate: SampleRate) -> Result<(), AiInitError> {
+        unsafe {
+            DSP::dma_control_write(0);
+            AI::cr_write(
+                AiCr::default()
+                    .with_reset_count(true)
+                    .with_dma_32khz(rate == SampleRate::Hz32000)
+                    .0,
+            );
+            AI::vr_write(0);
+        }
+        update_cr(|cr| cr.with_ai_int(true).with_ai_int_mask(false));
+        Ok(())
+    }
+
+    /// Stops the audio DMA and the streamed audio, the DMA's samples playing at `rate`
+    /// once started.
+    pub fn init(rate: SampleRate) -> Result<(), AiInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
+            Err(AiInitError::AlreadyInitialized)
+        } else {
+            Self::init_ai(rate)
+        }
+    }
+
+    /// The global audio interface context, initialized at 48 kHz if it was not.
+    pub fn global() -> Self {
+        #[allow(unreachable_patterns)]
+        match Self::init(SampleRate::default()) {
+            Err(AiInitError::AlreadyInitialized) | Ok(_) => {}
+            Err(e) => panic!("the audio interface failed to initialize: {e:?}"),
+        }
+        unsafe { Self::global_unchecked() }
+    }
+
+    /// # Safety
+    /// Requires that the global audio interface context has been initialized.
+    /// This is ensured by [`Self::global`] or [`Self::init`].
+    pub unsafe fn global_unchecked() -> Self {
+        Self { _mark: PhantomData }
+    }
+
+    pub fn set_sample_rate(self, rate: SampleRate) {
+        interrupt::free(|| unsafe {
+            // Keeping the interrupt of the streamed audio pending.
+            AI::cr_write(
+                AiCr(AI::cr_read() & !(1 << 3))
+                    .with_dma_32khz(rate == SampleRate::Hz32000)
+                    .0,
+            )
+        });
+    }
+
+    #[inline]
+    pub fn sample_rate(self) -> SampleRate {
+        if unsafe { AI::cr_dma_32khz_read() } {
+            SampleRate::Hz32000
+        } else {
+            SampleRate::Hz48000
+        }
+    }
+
+    /// Gives the DMA `block`, which it plays from the next block, or at once if it is
+    /// stopped, and repeats until it is given another.
+    ///
+    /// Panics if `block` is not 32-byte aligned, or its length is not a multiple of 32 up
+    /// to [`MAX_DMA_LEN`].
+    ///
+    /// # Safety
+    /// `block` must stay in memory, flushed from the cache, while it plays.
+    pub unsafe fn set_dma(self, block: &[u8]) {
+        assert!(
+            (block.as_ptr() as usize).is_multiple_of(32),
+            "the audio dma reads 32-byte aligned blocks"
+        );
+        assert!(
+            block.len().is_multiple_of(32) && block.len() <= MAX_DMA_LEN,
+            "the audio dma reads blocks of 32-byte units up to 0x7fff"
+        );
+        interrupt::free(|| unsafe { set_dma(block.as_ptr(), block.len()) });
+    }
+
+    /// Starts or stops the DMA. Stopped, it finishes the block playing first.
+    pub fn set_dma_enabled(self, enabled: bool) {
+        interrupt::free(|| unsafe { DSP::dma_control_enable_write(enabled) });
+    }
+
+    /// The bytes of the block playing that are left to play.
+    #[inline]
+    pub fn dma_left(self) -> usize {
+        unsafe { DSP::dma_left_read() as usize * 32 }
+    }
+
+    /// Plays the buffers of [`BUFFER_FRAMES`] that `fill` fills, in turn, from now on.
+    ///
+    /// `fill` is called by the DMA's interrupt handler, with interrupts disabled and
+    /// without the FPU, once [`interrupt::init`](crate::interrupt::init) installed it and
+    /// interrupts are enabled. Until then it is called from [`Self::poll`], which
+    /// programs poll more often than a buffer lasts.
+    pub fn start(self, fill: fn(&mut [Frame])) {
+        interrupt::free(|| {
+            FILL.store(fill as *mut (), Ordering::Release);
+            queue(0, fill);
+            NEXT.store(1, Ordering::Relaxed);
+            update_cr(|cr| cr.with_ai_int(true).with_ai_int_mask(true));
+            unsafe { DSP::dma_control_enable_write(true) };
+        });
+    }
+
+    /// Stops the DMA after the block playing, and the buffers of [`Self::start`].
+    pub fn stop(self) {
+        interrupt::free(|| {
+            update_cr(|cr| cr.with_ai_int_mask(false));
+            unsafe { DSP::dma_control_enable_write(false) };
+            FILL.store(ptr::null_mut(), Ordering::Release);
+        });
+    }
+
+    /// Sets the function filling the buffers of [`Self::start`], returning the one set
+    /// before. It starts them if they were not playing, and `None` stops them.
+    ///
+    /// Changed while the buffers play, the new function fills the next buffer, without a
+    /// gap.
+    pub fn set_callback(self, callback: Option<fn(&mut [Frame])>) -> Option<fn(&mut [Frame])> {
+        interrupt::free(|| {
+            let old = FILL.load(Ordering::Acquire);
+            match callback {
+                Some(fill) if !old.is_null() => FILL.store(fill as *mut (), Ordering::Release),
+                Some(fill) => self.start(fill),
+                None => self.stop(),
+            }
+            (!old.is_null())
+                .then(|| unsafe { core::mem::transmute::<*mut (), fn(&mut [Frame])>(old) })
+        })
+    }
+
+    /// Refills the buffers of [`Self::start`] if the DMA started the other, for
+    /// programs that do not enable interrupts.
+    #[inline]
+    pub fn poll(self) {
+        interrupt::free(refill);
+    }
+}
@@ -0,0 +1,277 @@
+//! The DSP, which runs the microcode the processor uploads, and exchanges 32-bit mail with
+//! it.
+//!
+//! Reset, the DSP runs its boot ROM, which loads the microcode from memory into its
+//! instruction RAM, and optionally data into its data RAM, with its own DMA, then jumps to
+//! its entry point:
+//!
+//! ```ignore
+//! use rbrew_gc::audio::dsp::{DspContext, Microcode};
+//!
+//! #[repr(C, align(32))]
+//! struct Code([u8; 0x1000]);
+//! static MIXER: Code = Code(*include_bytes!("mixer.bin"));
+//!
+//! let dsp = DspContext::global();
+//! dsp.boot(&Microcode::new(&MIXER.0).with_entry(0x10))?;
+//! dsp.send_mail(0xcdd1_0001);
+//! let reply = dsp.wait_for_mail();
+//! ```
+
+use super::{update_cr, DSP};
+use crate::{
+    cache,
+    interrupt::{self, Source},
+};
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+use rbrew_shared::interrupt;
+
+/// The mail the boot ROM sends once it waits for microcode.
+const ROM_READY: u32 = 0x8071_feed;
+
+/// The parameters of the boot ROM's loader, each sent before its value: the memory the
+/// instruction RAM is loaded from, its length and its address in the DSP, the same for the
+/// data RAM, and the entry point.
+const IRAM_SOURCE: u32 = 0x80f3_a001;
+const IRAM_LENGTH: u32 = 0x80f3_a002;
+const IRAM_DESTINATION: u32 = 0x80f3_c002;
+const DRAM_SOURCE: u32 = 0x80f3_b001;
+const DRAM_LENGTH: u32 = 0x80f3_b002;
+const DRAM_DESTINATION: u32 = 0x80f3_c001;
+const ENTRY: u32 = 0x80f3_d001;
+
+/// The refresh period of ARAM at its 81 MHz clock.
+const AR_REFRESH: u16 = 156;
+/// The sizes of ARAM and of its expansion, 16 MiB and none.
+const AR_SIZE: u16 = 0x23;
+
+/// Microcode to boot the DSP with, and the data it starts with.
+#[derive(Clone, Copy, Debug)]
+pub struct Microcode<'a> {
+    iram: &'a [u8],
+    iram_address: u16,
+    dram: &'a [u8],
+    dram_address: u16,
+    entry: u16,
+}
+
+impl<'a> Microcode<'a> {
+    /// The code `iram`, loaded at the start of the instruction RAM and entered there.
+    #[inline]
+    pub const fn new(iram: &'a [u8]) -> Self {
+        Self {
+            iram,
+            iram_address: 0,
+            dram: &[],
+            dram_address: 0,
+            entry: 0,
+        }
+    }
+
+    /// Loads the code at the word `address` of the instruction RAM instead.
+    #[inline]
+    pub const fn with_iram_address(self, address: u16) -> Self {
+        Self {
+            iram_address: address,
+            ..self
+        }
+    }
+
+    /// Loads `dram` at the word `address` of the data RAM too.
+    #[inline]
+    pub const fn with_dram(self, dram: &'a [u8], address: u16) -> Self {
+        Self {
+            dram,
+            dram_address: address,
+            ..self
+        }
+    }
+
+    /// Enters the code at the word `entry` of the instruction RAM.
+    #[inline]
+    pub const fn with_entry(self, entry: u16) -> Self {
+        Self { entry, ..self }
+    }
+}
+
+/// The `fn(u32)` called with the mail the DSP sends with its interrupt, null if there is
+/// none.
+static MAIL_CALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+#[interrupt(Source::Dsp)]
+fn mail_interrupt() {
+    if !unsafe { DSP::cr_dsp_int_read() } {
+        return;
+    }
+    update_cr(|cr| cr.with_dsp_int(true));
+    let callback = MAIL_CALLBACK.load(Ordering::Acquire);
+    if callback.is_null() {
+        return;
+    }
+    let callback = unsafe { core::mem::transmute::<*mut (), fn(u32)>(callback) };
+    while let Some(mail) = receive_mail() {
+        callback(mail);
+    }
+}
+
+/// The mail the DSP sent, if it was not read.
+fn receive_mail() -> Option<u32> {
+    unsafe {
+        let high = DSP::mail_from_high_read();
+        (high & 0x8000 != 0).then(|| (high as u32) << 16 | DSP::mail_from_low_read() as u32)
+    }
+}
+
+static IS_INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum DspInitError {
+    AlreadyInitialized,
+}
+
+#[derive(Debug)]
+pub enum DspBootError {
+    /// The DSP sent other mail than its boot ROM's when reset.
+    UnexpectedMail(u32),
+    /// The microcode is not 32-byte aligned, or not in 32-byte units.
+    Misaligned,
+}
+
+#[derive(Clone, Copy)]
+pub struct DspContext {
+    // Makes the type non-trivially constructible.
+    _mark: PhantomData<()>,
+}
+
+impl DspContext {
+    fn init_dsp() -> Result<(), DspInitError> {
+        unsafe {
+            DSP::ar_size_write(DSP::ar_size_read() & !0x3f | AR_SIZE);
+            DSP::ar_refresh_write(AR_REFRESH);
+        }
+        update_cr(|cr| cr.with_dsp_int(true).with_dsp_int_mask(false));
+        Ok(())
+    }
+
+    /// Sets up ARAM for the DSP's accelerator to read. The DSP is left running what it was.
+    pub fn init() -> Result<(), DspInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
+            Err(DspInitError::AlreadyInitialized)
+        } else {
+            Self::init_dsp()
+        }
+    }
+
+    /// The global DSP context, initialized if it was not.
+    pub fn global() -> Self {
+        #[allow(unreachable_patterns)]
+        match Self::init() {
+            Err(DspInitError::AlreadyInitialized) | Ok(_) => {}
+            Err(e) => panic!("the dsp failed to initialize: {e:?}"),
+        }
+        unsafe { Self::global_unchecked() }
+    }
+
+    /// # Safety
+    /// Requires that the global DSP context has been initialized.
+    /// This is ensured by [`Self::global`] or [`Self::init`].
+    pub unsafe fn global_unchecked() -> Self {
+        Self { _mark: PhantomData }
+    }
+
+    /// Resets the DSP, which halts what it ran, and boots it into `microcode`, flushing it
+    /// from the cache for the boot ROM to load. Returns once the ROM took the parameters,
+    /// before the microcode starts.
+    pub fn boot(self, microcode: &Microcode) -> Result<(), DspBootError> {
+        let aligned = |code: &[u8]| {
+            (code.as_ptr() as usize).is_multiple_of(32) && code.len().is_multiple_of(32)
+        };
+        if !aligned(microcode.iram) || !aligned(microcode.dram) {
+            return Err(DspBootError::Misaligned);
+        }
+        cache::flush_data_range(microcode.iram.as_ptr(), microcode.iram.len());
+        cache::flush_data_range(microcode.dram.as_ptr(), microcode.dram.len());
+
+        interrupt::free(|| {
+            update_cr(|cr| cr.with_reset_init(true).with_halt(true));
+            update_cr(|cr| cr.with_halt(false));
+        });
+        match self.wait_for_mail() {
+            ROM_READY => {}
+            mail => return Err(DspBootError::UnexpectedMail(mail)),
+        }
+        let address = |code: &[u8]| code.as_ptr() as u32 & 0x3fff_ffff;
+        let parameters = [
+            (IRAM_SOURCE, address(microcode.iram)),
+            (IRAM_DESTINATION, microcode.iram_address as u32),
+            (IRAM_LENGTH, microcode.iram.len() as u32),
+            (DRAM_SOURCE, address(microcode.dram)),
+            (DRAM_DESTINATION, microcode.dram_address as u32),
+            (DRAM_LENGTH, microcode.dram.len() as u32),
+            (ENTRY, microcode.entry as u32),
+        ];
+        // Without data, only its length of 0 is sent.
+        let has_dram = !microcode.dram.is_empty();
+        let parameters = parameters.into_iter().filter(|&(parameter, _)| {
+            has_dram || !matches!(parameter, DRAM_SOURCE | DRAM_DESTINATION)
+        });
+        for (parameter, value) in parameters {
+            self.send_mail(parameter);
+            self.send_mail(value);
+        }
+        while self.is_mail_pending() {
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    /// Sends `mail` to the DSP, once it read the mail sent before.
+    pub fn send_mail(self, mail: u32) {
+        while self.is_mail_pending() {
+            core::hint::spin_loop();
+        }
+        unsafe {
+            DSP::mail_to_high_write((mail >> 16) as u16);
+            DSP::mail_to_low_write(mail as u16);
+        }
+    }
+
+    /// Whether the DSP has not read the mail sent last.
+    #[inline]
+    pub fn is_mail_pending(self) -> bool {
+        unsafe { DSP::mail_to_high_read() & 0x8000 != 0 }
+    }
+
+    /// The mail the DSP sent, if it was not read.
+    #[inline]
+    pub fn receive_mail(self) -> Option<u32> {
+        receive_mail()
+    }
+
+    /// Waits for the DSP to send mail.
+    pub fn wait_for_mail(self) -> u32 {
+        loop {
+            if let Some(mail) = receive_mail() {
+                return mail;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Sets the function called with the mail the DSP sends with its interrupt, returning
+    /// the one set before. `None` masks the interrupt.
+    ///
+    /// It is called by the DSP's interrupt handler, with interrupts disabled, once
+    /// [`interrupt::init`](crate::interrupt::init) installed it and interrupts are
+    /// enabled. The mail it reads is no longer returned by [`Self::receive_mail`].
+    pub fn set_mail_callback(self, callback: Option<fn(u32)>) -> Option<fn(u32)> {
+        let new = callback.map_or(ptr::null_mut(), |callback| callback as *mut ());
+        let old = MAIL_CALLBACK.swap(new, Ordering::AcqRel);
+        interrupt::free(|| update_cr(|cr| cr.with_dsp_int_mask(callback.is_some())));
+        (!old.is_null()).then(|| unsafe { core::mem::transmute::<*mut (), fn(u32)>(old) })
+    }
+}
@@ -0,0 +1,690 @@
+//! A software mixer, which decodes DSP-ADPCM and PCM16 samples and mixes up to [`VOICES`]
+//! of them into the buffers the audio interface plays.
+//!
+//! The mixer runs in the audio DMA's interrupt, without the FPU, so it works in fixed
+//! point: volumes and pans are 8-bit, and pitches are 16.16 multiples of a sample's rate.
+//!
+//! ```ignore
+//! use rbrew_gc::audio::mixer::{MixerContext, Sample};
+//!
+//! static MUSIC: &[u8] = include_bytes!("music.dsp");
+//!
+//! let music = Sample::from_dsp(MUSIC).unwrap();
+//! let voice = MixerContext::global().voice(&music).unwrap();
+//! voice.set_volume(192, 0);
+//! voice.set_looping(true);
+//! voice.play();
+//! ```
+
+use super::{
+    ai::{AiContext, Frame, BUFFER_FRAMES},
+    stream::StreamBuffer,
+};
+use crate::interrupt;
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use spin::Mutex;
+
+/// The voices that can play at once.
+pub const VOICES: usize = 32;
+
+/// The pitch playing a sample at its own rate.
+pub const UNITY_PITCH: u32 = 1 << 16;
+/// The highest pitch, bounding the samples decoded for each one played.
+pub const MAX_PITCH: u32 = 4 << 16;
+
+/// The samples of a DSP-ADPCM frame, which starts with a byte of the predictor and the
+/// scale and holds a nibble for each.
+const ADPCM_FRAME_SAMPLES: u32 = 14;
+const ADPCM_FRAME_LEN: usize = 8;
+/// The length of the header of a DSP-ADPCM file.
+pub(super) const DSP_HEADER_LEN: usize = 0x60;
+
+#[derive(Clone, Copy, Debug)]
+enum Data {
+    Pcm16(&'static [i16]),
+    Adpcm {
+        frames: &'static [u8],
+        /// The two coefficients of each of the eight predictors.
+        coefficients: [[i16; 2]; 8],
+    },
+    /// Big-endian PCM16, or DSP-ADPCM if there are coefficients.
+    Stream {
+        buffer: &'static StreamBuffer,
+        coefficients: Option<[[i16; 2]; 8]>,
+    },
+}
+
+/// Sound a voice plays: mono samples at a rate, and where they loop back to.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    data: Data,
+    len: u32,
+    rate: u32,
+    /// The history the decoder of DSP-ADPCM starts with.
+    history: [i16; 2],
+    loop_start: u32,
+    looping: bool,
+}
+
+/// The fields of the header of a DSP-ADPCM file.
+pub(super) struct DspHeader {
+    pub len: u32,
+    /// The bytes of the frames following the header.
+    pub frames_len: usize,
+    pub rate: u32,
+    pub coefficients: [i16; 16],
+    pub history: [i16; 2],
+    pub loop_start: Option<u32>,
+}
+
+const fn be16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+const fn be32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+impl DspHeader {
+    /// The header the standard encoders write at the start of `file`, or `None` if it is
+    /// not one.
+    pub const fn parse(file: &[u8]) -> Option<Self> {
+        // Only ADPCM is stored in them.
+        if file.len() < DSP_HEADER_LEN || be16(file, 0x0e) != 0 {
+            return None;
+        }
+        let mut coefficients = [0; 16];
+        let mut i = 0;
+        while i < 16 {
+            coefficients[i] = be16(file, 0x1c + 2 * i) as i16;
+            i += 1;
+        }
+        // The loop is given in nibbles, counting the frames' header bytes.
+        let nibble = be32(file, 0x10);
+        let loop_start = nibble / 16 * ADPCM_FRAME_SAMPLES + (nibble % 16).saturating_sub(2);
+        Some(Self {
+            len: be32(file, 0x00),
+            frames_len: (be32(file, 0x04) as usize).div_ceil(2),
+            rate: be32(file, 0x08),
+            coefficients,
+            history: [be16(file, 0x40) as i16, be16(file, 0x42) as i16],
+            loop_start: if be16(file, 0x0c) != 0 {
+                Some(loop_start)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// The two coefficients of each predictor, from the order of DSP-ADPCM headers.
+const fn predictors(coefficients: [i16; 16]) -> [[i16; 2]; 8] {
+    let mut pairs = [[0; 2]; 8];
+    let mut i = 0;
+    while i < 8 {
+        pairs[i] = [coefficients[2 * i], coefficients[2 * i + 1]];
+        i += 1;
+    }
+    pairs
+}
+
+impl Sample {
+    /// The 16-bit `samples` played at `rate` Hz.
+    #[inline]
+    pub const fn pcm16(samples: &'static [i16], rate: u32) -> Self {
+        Self::with_data(Data::Pcm16(samples), samples.len() as u32, rate)
+    }
+
+    /// The `len` samples encoded in the DSP-ADPCM `frames`, with the `coefficients` of
+    /// their predictors, played at `rate` Hz.
+    #[inline]
+    pub const fn adpcm(
+        frames: &'static [u8],
+        coefficients: [i16; 16],
+        len: u32,
+        rate: u32,
+    ) -> Self {
+        let data = Data::Adpcm {
+            frames,
+            coefficients: predictors(coefficients),
+        };
+        Self::with_data(data, len, rate)
+    }
+
+    const fn with_data(data: Data, len: u32, rate: u32) -> Self {
+        Self {
+            data,
+            len,
+            rate,
+            history: [0; 2],
+            loop_start: 0,
+            looping: false,
+        }
+    }
+
+    /// The samples of a DSP-ADPCM file, with the header the standard encoders write, or
+    /// `None` if it is not one. It loops if its header marks a loop.
+    ///
+    /// Files are best included with [`include_sample!`](crate::include_sample), which
+    /// checks them as the program is built.
+    pub const fn from_dsp(file: &'static [u8]) -> Option<Self> {
+        let Some(header) = DspHeader::parse(file) else {
+            return None;
+        };
+        let frames = file.split_at(DSP_HEADER_LEN).1;
+        if frames.len() < header.frames_len {
+            return None;
+        }
+        let frames = frames.split_at(header.frames_len).0;
+        let sample = Self::adpcm(frames, header.coefficients, header.len, header.rate);
+        Some(sample.with_header(&header))
+    }
+
+    /// The samples streamed into `buffer`, in DSP-ADPCM if there are `coefficients`.
+    pub(super) fn stream(
+        buffer: &'static StreamBuffer,
+        coefficients: Option<[i16; 16]>,
+        len: u32,
+        rate: u32,
+    ) -> Self {
+        let coefficients = coefficients.map(predictors);
+        Self::with_data(
+            Data::Stream {
+                buffer,
+                coefficients,
+            },
+            len,
+            rate,
+        )
+    }
+
+    /// Takes the history and the loop of `header`.
+    pub(super) const fn with_header(self, header: &DspHeader) -> Self {
+        let sample = Self {
+            history: header.history,
+            ..self
+        };
+        match header.loop_start {
+            Some(start) => sample.with_loop(start),
+            None => sample,
+        }
+    }
+
+    /// Loops back to the sample `start` after the last, from the start if it is past the
+    /// end.
+    #[inline]
+    pub const fn with_loop(self, start: u32) -> Self {
+        Self {
+            loop_start: if start < self.len { start } else { 0 },
+            looping: true,
+            ..self
+        }
+    }
+
+    /// The samples, not counting loops.
+    #[inline]
+    pub const fn len(&self) -> u32 {
+        self.len
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The rate the samples play at, in Hz.
+    #[inline]
+    pub const fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Whether voices loop back to [`Self::loop_start`] by default.
+    #[inline]
+    pub const fn is_looping(&self) -> bool {
+        self.looping
+    }
+
+    #[inline]
+    pub const fn loop_start(&self) -> u32 {
+        self.loop_start
+    }
+
+    /// The offset of the bytes encoding the sample `index`, in its frame for DSP-ADPCM.
+    pub(super) const fn offset(&self, index: u32) -> u32 {
+        match self.data {
+            Data::Pcm16(_)
+            | Data::Stream {
+                coefficients: None, ..
+            } => index * 2,
+            Data::Adpcm { .. } | Data::Stream { .. } => {
+                index / ADPCM_FRAME_SAMPLES * ADPCM_FRAME_LEN as u32
+            }
+        }
+    }
+}
+
+/// Decodes the sample `nibble` of the DSP-ADPCM `frame` from the two decoded before it.
+fn decode_adpcm(
+    frame: &[u8],
+    coefficients: &[[i16; 2]; 8],
+    nibble: usize,
+    history: [i16; 2],
+) -> Option<i16> {
+    let header = *frame.first()?;
+    let byte = *frame.get(1 + nibble / 2)?;
+    let nibble = if nibble.is_multiple_of(2) {
+        byte >> 4
+    } else {
+        byte & 0xf
+    };
+    // Sign-extends the nibble.
+    let delta = ((nibble << 4) as i8 >> 4) as i32;
+    let [c1, c2] = coefficients[(header >> 4 & 7) as usize];
+    let predicted = c1 as i32 * history[0] as i32 + c2 as i32 * history[1] as i32;
+    let sample = ((delta << (header & 0xf)) << 11) + 1024 + predicted;
+    Some((sample >> 11).clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+}
+
+/// Why a voice has no next sample.
+enum Missing {
+    End,
+    /// The stream it plays was not read in time.
+    Underrun,
+}
+
+#[derive(Clone, Copy)]
+struct Channel {
+    /// The sample played, `None` if the channel is free.
+    sample: Option<Sample>,
+    /// Counts the voices the channel played, so handles to those that ended do nothing.
+    generation: u16,
+    playing: bool,
+    looping: bool,
+    /// The sample decoded next.
+    index: u32,
+    /// The last two samples decoded, the latest first.
+    history: [i16; 2],
+    /// The history at the sample looped back to.
+    loop_history: [i16; 2],
+    /// The samples the output is interpolated between, and how far it is between them,
+    /// in 16 bits.
+    current: i16,
+    next: i16,
+    fraction: u32,
+    /// Whether `next` is past the end.
+    ending: bool,
+    /// Whether the voice waits for its stream to be read.
+    stalled: bool,
+    /// The gain of each channel, up to 255 * 255.
+    gains: [i32; 2],
+    pitch: u32,
+}
+
+impl Channel {
+    const FREE: Channel = Channel {
+        sample: None,
+        generation: 0,
+        playing: false,
+        looping: false,
+        index: 0,
+        history: [0; 2],
+        loop_history: [0; 2],
+        current: 0,
+        next: 0,
+        fraction: 0,
+        ending: false,
+        stalled: false,
+        gains: [0; 2],
+        pitch: UNITY_PITCH,
+    };
+
+    /// Starts playing `sample` from its start, paused.
+    fn start(&mut self, sample: Sample) {
+        *self = Channel {
+            sample: Some(sample),
+            generation: self.generation.wrapping_add(1),
+            looping: sample.looping,
+            history: sample.history,
+            loop_history: sample.history,
+            // Decodes the first two samples when it is first mixed.
+            fraction: 2 << 16,
+            stalled: true,
+            ..Channel::FREE
+        };
+        self.set_gains(255, 0);
+        if let Data::Stream { buffer, .. } = sample.data {
+            let loop_start = sample.looping.then(|| sample.offset(sample.loop_start));
+            buffer.restart(loop_start);
+        }
+    }
+
+    fn free(&mut self) {
+        *self = Channel {
+            generation: self.generation,
+            ..Channel::FREE
+        };
+    }
+
+    fn set_gains(&mut self, volume: u8, pan: i8) {
+        // Centered, both channels play at the full volume. Panned, one is attenuated.
+        let right = pan as i32 + 128;
+        let weights = [(2 * (255 - right)).min(255), (2 * right).min(255)];
+        self.gains = weights.map(|weight| volume as i32 * weight);
+    }
+
+    /// The next sample decoded, looping back if the voice loops.
+    fn decode(&mut self, sample: &Sample) -> Result<i16, Missing> {
+        if self.index >= sample.len {
+            if !self.looping {
+                return Err(Missing::End);
+            }
+            self.index = sample.loop_start;
+            self.history = self.loop_history;
+        }
+        if self.index == sample.loop_start {
+            self.loop_history = self.history;
+        }
+        let index = self.index;
+        let offset = sample.offset(index) as usize;
+        let nibble = (index % ADPCM_FRAME_SAMPLES) as usize;
+        let decoded = match sample.data {
+            Data::Pcm16(samples) => samples.get(index as usize).copied(),
+            Data::Adpcm {
+                frames,
+                ref coefficients,
+            } => frames
+                .get(offset..)
+                .and_then(|frame| decode_adpcm(frame, coefficients, nibble, self.history)),
+            Data::Stream {
+                buffer,
+                ref coefficients,
+            } => {
+                let len = if coefficients.is_some() {
+                    ADPCM_FRAME_LEN
+                } else {
+                    2
+                };
+                let bytes = buffer.read(offset, len).ok_or(Missing::Underrun)?;
+                match coefficients {
+                    Some(coefficients) => decode_adpcm(bytes, coefficients, nibble, self.history),
+                    None => Some(i16::from_be_bytes([bytes[0], bytes[1]])),
+                }
+            }
+        }
+        .ok_or(Missing::End)?;
+        self.index += 1;
+        self.history = [decoded, self.history[0]];
+        Ok(decoded)
+    }
+
+    /// Decodes the samples the output moved past. Returns `false` if the voice ended, or
+    /// its stream was not read in time, which stalls it until it is.
+    fn catch_up(&mut self, sample: &Sample) -> bool {
+        while self.fraction >= 1 << 16 {
+            if self.ending {
+                self.free();
+                return false;
+            }
+            let next = match self.decode(sample) {
+                Ok(next) => next,
+                Err(Missing::End) => {
+                    self.ending = true;
+                    0
+                }
+                Err(Missing::Underrun) => {
+                    self.stalled = true;
+                    return false;
+                }
+            };
+            self.current = self.next;
+            self.next = next;
+            self.fraction -= 1 << 16;
+        }
+        self.stalled = false;
+        true
+    }
+
+    /// Adds the voice to `mix`, at the output `rate`.
+    fn mix(&mut self, rate: u32, mix: &mut [[i32; 2]]) {
+        let Some(sample) = self.sample else {
+            return;
+        };
+        let step = (sample.rate as u64 * self.pitch as u64 / rate as u64) as u32;
+        for out in mix {
+            if self.stalled && !self.catch_up(&sample) {
+                if self.sample.is_none() {
+                    return;
+                }
+                continue;
+            }
+            // In 15 bits so the product fits.
+            let slope = self.next as i32 - self.current as i32;
+            let value = self.current as i32 + ((slope * (self.fraction >> 1) as i32) >> 15);
+            out[0] += (value * self.gains[0]) >> 16;
+            out[1] += (value * self.gains[1]) >> 16;
+            self.fraction += step;
+            if !self.catch_up(&sample) && self.sample.is_none() {
+                return;
+            }
+        }
+    }
+}
+
+struct Mixer {
+    channels: [Channel; VOICES],
+    /// The sum of the voices, before it is clamped to the frames.
+    mix: [[i32; 2]; BUFFER_FRAMES],
+}
+
+static MIXER: Mutex<Mixer> = Mutex::new(Mixer {
+    channels: [Channel::FREE; VOICES],
+    mix: [[0; 2]; BUFFER_FRAMES],
+});
+
+/// Mixes the voices playing into `frames`, called with the audio DMA's buffers.
+fn fill(frames: &mut [Frame]) {
+    let rate = unsafe { AiContext::global_unchecked() }.sample_rate().hz();
+    let mut mixer = MIXER.lock();
+    let Mixer { channels, mix } = &mut *mixer;
+    for frames in frames.chunks_mut(BUFFER_FRAMES) {
+        let mix = &mut mix[..frames.len()];
+        mix.fill([0; 2]);
+        for channel in channels.iter_mut().filter(|channel| channel.playing) {
+            channel.mix(rate, mix);
+        }
+        let clamp = |sample: i32| sample.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        for (frame, &mut [left, right]) in frames.iter_mut().zip(mix) {
+            *frame = Frame::new(clamp(left), clamp(right));
+        }
+    }
+}
+
+/// Stops the voices playing the stream of `buffer`.
+pub(super) fn stop_stream(buffer: &StreamBuffer) {
+    interrupt::free(|| {
+        for channel in &mut MIXER.lock().channels {
+            if let Some(Sample {
+                data: Data::Stream { buffer: played, .. },
+                ..
+            }) = channel.sample
+            {
+                if ptr::eq(played, buffer) {
+                    channel.free();
+                }
+            }
+        }
+    });
+}
+
+/// Includes the DSP-ADPCM file at `path` as a [`Sample`], failing the build if it is not
+/// one.
+///
+/// ```ignore
+/// use rbrew_gc::{audio::mixer::Sample, include_sample};
+///
+/// static JUMP: Sample = include_sample!("jump.dsp");
+/// ```
+#[macro_export]
+macro_rules! include_sample {
+    ($path:literal) => {
+        const {
+            match $crate::audio::mixer::Sample::from_dsp(::core::include_bytes!($path)) {
+                ::core::option::Option::Some(sample) => sample,
+                ::core::option::Option::None => {
+                    ::core::panic!(::core::concat!($path, " is not a DSP-ADPCM file"))
+                }
+            }
+        }
+    };
+}
+
+/// A sample a voice of the mixer plays, until it ends or is stopped. Once it is, the
+/// handle does nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Voice {
+    index: u8,
+    generation: u16,
+}
+
+impl Voice {
+    /// Runs `f` on the voice's channel, if it has not ended.
+    fn with<R>(self, f: impl FnOnce(&mut Channel) -> R) -> Option<R> {
+        interrupt::free(|| {
+            let mut mixer = MIXER.lock();
+            let channel = &mut mixer.channels[self.index as usize];
+            (channel.sample.is_some() && channel.generation == self.generation).then(|| f(channel))
+        })
+    }
+
+    /// Plays the voice from where it was paused.
+    #[inline]
+    pub fn play(self) {
+        self.with(|channel| channel.playing = true);
+    }
+
+    /// Pauses the voice, which [`Self::play`] resumes.
+    #[inline]
+    pub fn pause(self) {
+        self.with(|channel| channel.playing = false);
+    }
+
+    /// Stops the voice, freeing it for another sample.
+    #[inline]
+    pub fn stop(self) {
+        self.with(Channel::free);
+    }
+
+    /// Whether the voice plays, rather than being paused or having ended.
+    #[inline]
+    pub fn is_playing(self) -> bool {
+        self.with(|channel| channel.playing).unwrap_or(false)
+    }
+
+    /// Whether the voice has ended or was stopped.
+    #[inline]
+    pub fn is_stopped(self) -> bool {
+        self.with(|_| ()).is_none()
+    }
+
+    /// Loops the voice back to its sample's loop start, or its start, rather than ending.
+    /// Turned off while looping, it ends after the last sample.
+    #[inline]
+    pub fn set_looping(self, looping: bool) {
+        self.with(|channel| channel.looping = looping);
+    }
+
+    /// Sets the volume, up to 255, and the pan, from -128 for the left channel only to
+    /// 127 for the right one.
+    #[inline]
+    pub fn set_volume(self, volume: u8, pan: i8) {
+        self.with(|channel| channel.set_gains(volume, pan));
+    }
+
+    /// Sets the pitch, a multiple of the sample's rate in 16.16 fixed point, up to
+    /// [`MAX_PITCH`].
+    #[inline]
+    pub fn set_pitch(self, pitch: u32) {
+        self.with(|channel| channel.pitch = pitch.min(MAX_PITCH));
+    }
+}
+
+static IS_INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum MixerInitError {
+    AlreadyInitialized,
+}
+
+#[derive(Clone, Copy)]
+pub struct MixerContext {
+    // Makes the type non-trivially constructible.
+    _mark: PhantomData<()>,
+}
+
+impl MixerContext {
+    fn init_mixer() -> Result<(), MixerInitError> {
+        AiContext::global().start(fill);
+        Ok(())
+    }
+
+    /// Starts the buffers of the audio interface, which the mixer fills from then on, see
+    /// [`AiContext::start`].
+    pub fn init() -> Result<(), MixerInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
+            Err(MixerInitError::AlreadyInitialized)
+        } else {
+            Self::init_mixer()
+        }
+    }
+
+    /// The global mixer context, initialized if it was not.
+    pub fn global() -> Self {
+        #[allow(unreachable_patterns)]
+        match Self::init() {
+            Err(MixerInitError::AlreadyInitialized) | Ok(_) => {}
+            Err(e) => panic!("the mixer failed to initialize: {e:?}"),
+        }
+        unsafe { Self::global_unchecked() }
+    }
+
+    /// # Safety
+    /// Requires that the global mixer context has been initialized.
+    /// This is ensured by [`Self::global`] or [`Self::init`].
+    pub unsafe fn global_unchecked() -> Self {
+        Self { _mark: PhantomData }
+    }
+
+    /// A voice of `sample`, paused at its start, at the full volume, centered and at its
+    /// own pitch. `None` if all [`VOICES`] are taken.
+    pub fn voice(self, sample: &Sample) -> Option<Voice> {
+        interrupt::free(|| {
+            let mut mixer = MIXER.lock();
+            let (index, channel) = mixer
+                .channels
+                .iter_mut()
+                .enumerate()
+                .find(|(_, channel)| channel.sample.is_none())?;
+            channel.start(*sample);
+            Some(Voice {
+                index: index as u8,
+                generation: channel.generation,
+            })
+        })
+    }
+
+    /// Stops every voice.
+    pub fn stop_all(self) {
+        interrupt::free(|| {
+            MIXER.lock().channels.iter_mut().for_each(Channel::free);
+        });
+    }
+}
@@ -0,0 +1,33 @@
+//! Sound effects played once on a voice of the [`mixer`](super::mixer), which frees it
+//! when they end, so programs do not manage voices.
+//!
+//! ```ignore
+//! use rbrew_gc::{
+//!     audio::{mixer::Sample, sfx::Sfx},
+//!     include_sample,
+//! };
+//!
+//! static JUMP: Sample = include_sample!("jump.dsp");
+//!
+//! Sfx::play(&JUMP, 255, 0);
+//! ```
+
+use super::mixer::{MixerContext, Sample, Voice};
+
+/// One-shot playback of samples.
+pub struct Sfx;
+
+impl Sfx {
+    /// Plays `sample` once from its start, at `volume` up to 255 and `pan` from -128 for
+    /// the left channel only to 127 for the right one, starting the mixer if it was not.
+    ///
+    /// Returns the voice, to stop it early or change it while it plays, or `None` if all
+    /// the mixer's voices play and the sound is dropped.
+    pub fn play(sample: &Sample, volume: u8, pan: i8) -> Option<Voice> {
+        let voice = MixerContext::global().voice(sample)?;
+        voice.set_looping(false);
+        voice.set_volume(volume, pan);
+        voice.play();
+        Some(voice)
+    }
+}
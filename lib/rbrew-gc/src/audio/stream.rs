@@ -0,0 +1,368 @@
+//! Streaming of samples too large to keep in memory, read from a file a chunk at a time
+//! into a ring of [`STREAM_CHUNKS`] the mixer plays from.
+//!
+//! [`Stream::update`], which programs call every frame, starts reading the next chunk as
+//! soon as the voice is done with the oldest. A [`StreamSource`] reads in the background,
+//! like the disc's DMA, so the main loop does not wait for it. If the voice reaches a chunk
+//! that was not read in time, it holds silent until it is, counted by
+//! [`Stream::underruns`].
+//!
+//! ```ignore
+//! use rbrew_gc::audio::{
+//!     mixer::MixerContext,
+//!     stream::{Stream, StreamFormat},
+//! };
+//!
+//! static MUSIC: Stream<File> = Stream::new();
+//!
+//! let sample = MUSIC.open(file, StreamFormat::Dsp)?;
+//! MixerContext::global().voice(&sample).unwrap().play();
+//! loop {
+//!     MUSIC.update()?;
+//!     // ...
+//! }
+//! ```
+
+use super::mixer::{self, DspHeader, Sample, DSP_HEADER_LEN};
+use crate::cache;
+use core::{
+    cell::UnsafeCell,
+    fmt,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    task::Poll,
+};
+use spin::Mutex;
+
+/// The chunks of a stream's ring.
+pub const STREAM_CHUNKS: usize = 4;
+/// The bytes of a chunk, about a third of a second of PCM16 at 48 kHz, or a second of
+/// DSP-ADPCM.
+pub const STREAM_CHUNK_LEN: usize = 0x8000;
+
+/// The loop chunk of streams that do not loop.
+const NO_LOOP: u32 = u32::MAX;
+
+/// A file read in the background.
+pub trait StreamSource {
+    type Error;
+
+    /// Starts reading `len` bytes from `offset` in the file into `buffer`, which is
+    /// flushed from the cache. `len` is a multiple of 32, and may go past the end of the
+    /// file: the bytes past it are not played.
+    ///
+    /// # Safety
+    /// `buffer` is 32-byte aligned and valid for `len` bytes until the read completes.
+    unsafe fn start_read(
+        &mut self,
+        offset: u64,
+        buffer: *mut u8,
+        len: usize,
+    ) -> Result<(), Self::Error>;
+
+    /// The result of the read started last, once it completed.
+    fn poll_read(&mut self) -> Poll<Result<(), Self::Error>>;
+}
+
+/// What a stream's file holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamFormat {
+    /// DSP-ADPCM, with the header the standard encoders write. It loops if its header
+    /// marks a loop.
+    Dsp,
+    /// `len` big-endian 16-bit samples from `offset`, played at `rate` Hz.
+    Pcm16 { offset: u64, len: u32, rate: u32 },
+}
+
+#[derive(Debug)]
+pub enum StreamOpenError<E> {
+    Source(E),
+    /// The file is not DSP-ADPCM.
+    InvalidHeader,
+}
+
+#[repr(C, align(32))]
+struct Chunk([u8; STREAM_CHUNK_LEN]);
+
+/// The ring of a stream, which the voice playing it reads from its interrupt.
+///
+/// The voice goes through the chunks of the file in order, then from the one it loops
+/// back to. Its position counts the chunks it went through, and the chunk at each
+/// position is read into the slot of the position modulo [`STREAM_CHUNKS`].
+pub struct StreamBuffer {
+    chunks: [UnsafeCell<Chunk>; STREAM_CHUNKS],
+    /// The position of the chunk in each slot plus one, 0 while none is.
+    filled: [AtomicU32; STREAM_CHUNKS],
+    /// The position of the voice, and the chunk of the file there.
+    position: AtomicU32,
+    chunk: AtomicU32,
+    /// The chunk the voice loops back to, [`NO_LOOP`] if it does not.
+    loop_chunk: AtomicU32,
+    /// Counts the restarts, so the reads started before one are dropped.
+    generation: AtomicU32,
+    /// Whether the voice read nothing since the restart.
+    fresh: AtomicBool,
+    /// Whether the voice waits for a chunk.
+    starved: AtomicBool,
+    underruns: AtomicU32,
+}
+
+// The slots are written by the reader only while no voice reads them.
+unsafe impl Sync for StreamBuffer {}
+
+impl fmt::Debug for StreamBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamBuffer")
+            .field("position", &self.position)
+            .field("underruns", &self.underruns)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StreamBuffer {
+    const fn new() -> Self {
+        Self {
+            chunks: [const { UnsafeCell::new(Chunk([0; STREAM_CHUNK_LEN])) }; STREAM_CHUNKS],
+            filled: [const { AtomicU32::new(0) }; STREAM_CHUNKS],
+            position: AtomicU32::new(0),
+            chunk: AtomicU32::new(0),
+            loop_chunk: AtomicU32::new(NO_LOOP),
+            generation: AtomicU32::new(0),
+            fresh: AtomicBool::new(true),
+            starved: AtomicBool::new(false),
+            underruns: AtomicU32::new(0),
+        }
+    }
+
+    fn slot(&self, position: u32) -> *mut u8 {
+        self.chunks[position as usize % STREAM_CHUNKS].get().cast()
+    }
+
+    /// Moves the voice back to the start, looping back to the chunk of the byte
+    /// `loop_start`, dropping what was read.
+    fn reset(&self, loop_start: Option<u32>) {
+        let loop_chunk = loop_start.map_or(NO_LOOP, |offset| offset / STREAM_CHUNK_LEN as u32);
+        self.loop_chunk.store(loop_chunk, Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.filled
+            .iter()
+            .for_each(|filled| filled.store(0, Ordering::Release));
+        self.position.store(0, Ordering::Release);
+        self.chunk.store(0, Ordering::Relaxed);
+        self.fresh.store(true, Ordering::Relaxed);
+        self.starved.store(false, Ordering::Relaxed);
+    }
+
+    /// Moves the voice back to the start, looping back to the chunk of the byte
+    /// `loop_start`, keeping what was read if it was not played yet. Called with
+    /// interrupts disabled.
+    pub(super) fn restart(&self, loop_start: Option<u32>) {
+        let loop_chunk = loop_start.map_or(NO_LOOP, |offset| offset / STREAM_CHUNK_LEN as u32);
+        if !self.fresh.load(Ordering::Relaxed)
+            || self.loop_chunk.load(Ordering::Relaxed) != loop_chunk
+        {
+            self.reset(loop_start);
+        }
+    }
+
+    /// The `len` bytes at `offset` in the file, moving the voice to their chunk, or `None`
+    /// if it was not read yet. Called from the mixer's interrupt.
+    pub(super) fn read(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        self.fresh.store(false, Ordering::Relaxed);
+        let chunk = (offset / STREAM_CHUNK_LEN) as u32;
+        let mut position = self.position.load(Ordering::Relaxed);
+        if chunk != self.chunk.load(Ordering::Relaxed) {
+            position += 1;
+            self.position.store(position, Ordering::Release);
+            self.chunk.store(chunk, Ordering::Relaxed);
+        }
+        let slot = position as usize % STREAM_CHUNKS;
+        if self.filled[slot].load(Ordering::Acquire) != position + 1 {
+            if !self.starved.swap(true, Ordering::Relaxed) {
+                self.underruns.fetch_add(1, Ordering::Relaxed);
+            }
+            return None;
+        }
+        self.starved.store(false, Ordering::Relaxed);
+        let within = offset % STREAM_CHUNK_LEN;
+        let chunk = unsafe { &(*self.chunks[slot].get()).0 };
+        chunk.get(within..within + len)
+    }
+}
+
+struct Reader<S> {
+    source: S,
+    /// Where the samples start in the file, and their bytes.
+    offset: u64,
+    len: u32,
+    /// The generation of the buffer the reads are for.
+    generation: u32,
+    /// The position read next, and the one being read with its generation.
+    next: u32,
+    pending: Option<(u32, u32)>,
+}
+
+impl<S: StreamSource> Reader<S> {
+    /// The chunk of the file at `position`, `None` past the end if the stream does not
+    /// loop.
+    fn chunk_at(&self, position: u32, loop_chunk: u32) -> Option<u32> {
+        let last = self.len.div_ceil(STREAM_CHUNK_LEN as u32).checked_sub(1)?;
+        if position <= last {
+            return Some(position);
+        }
+        if loop_chunk > last {
+            return None;
+        }
+        Some(loop_chunk + (position - last - 1) % (last - loop_chunk + 1))
+    }
+
+    /// Waits for the read started last, dropping its result.
+    fn finish(&mut self) {
+        if self.pending.take().is_some() {
+            while self.source.poll_read().is_pending() {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+/// Samples streamed from a file by a [`StreamSource`], which one voice plays at a time.
+pub struct Stream<S> {
+    buffer: StreamBuffer,
+    reader: Mutex<Option<Reader<S>>>,
+}
+
+impl<S: StreamSource> Stream<S> {
+    /// A stream without a file, which [`Self::open`] opens.
+    pub const fn new() -> Self {
+        Self {
+            buffer: StreamBuffer::new(),
+            reader: Mutex::new(None),
+        }
+    }
+
+    /// Streams the samples of `source` from now on, returning the sample voices play them
+    /// as. The voices playing the stream before are stopped.
+    ///
+    /// The header of DSP-ADPCM files is read at once, the samples by [`Self::update`].
+    pub fn open(
+        &'static self,
+        mut source: S,
+        format: StreamFormat,
+    ) -> Result<Sample, StreamOpenError<S::Error>> {
+        let mut reader = self.reader.lock();
+        if let Some(reader) = reader.as_mut() {
+            reader.finish();
+        }
+        *reader = None;
+        mixer::stop_stream(&self.buffer);
+
+        let (sample, offset, len) = match format {
+            StreamFormat::Dsp => {
+                let slot = self.buffer.slot(0);
+                cache::flush_data_range(slot, DSP_HEADER_LEN);
+                unsafe { source.start_read(0, slot, DSP_HEADER_LEN) }
+                    .map_err(StreamOpenError::Source)?;
+                let result = loop {
+                    if let Poll::Ready(result) = source.poll_read() {
+                        break result;
+                    }
+                    core::hint::spin_loop();
+                };
+                result.map_err(StreamOpenError::Source)?;
+                cache::flush_data_range(slot, DSP_HEADER_LEN);
+                let header = unsafe { core::slice::from_raw_parts(slot, DSP_HEADER_LEN) };
+                let header = DspHeader::parse(header).ok_or(StreamOpenError::InvalidHeader)?;
+                let sample = Sample::stream(
+                    &self.buffer,
+                    Some(header.coefficients),
+                    header.len,
+                    header.rate,
+                )
+                .with_header(&header);
+                (sample, DSP_HEADER_LEN as u64, header.frames_len as u32)
+            }
+            StreamFormat::Pcm16 { offset, len, rate } => {
+                let sample = Sample::stream(&self.buffer, None, len, rate);
+                (sample, offset, len * 2)
+            }
+        };
+
+        self.buffer.reset(
+            sample
+                .is_looping()
+                .then(|| sample.offset(sample.loop_start())),
+        );
+        *reader = Some(Reader {
+            source,
+            offset,
+            len,
+            generation: self.buffer.generation.load(Ordering::Acquire),
+            next: 0,
+            pending: None,
+        });
+        Ok(sample)
+    }
+
+    /// Takes the chunk read, if the read completed, and starts reading the next one if the
+    /// voice is done with the slot it goes in. Does nothing without a file.
+    pub fn update(&'static self) -> Result<(), S::Error> {
+        let mut reader = self.reader.lock();
+        let Some(reader) = reader.as_mut() else {
+            return Ok(());
+        };
+        let generation = self.buffer.generation.load(Ordering::Acquire);
+        if reader.generation != generation {
+            reader.generation = generation;
+            reader.next = 0;
+        }
+        if let Some((read_generation, position)) = reader.pending {
+            let Poll::Ready(result) = reader.source.poll_read() else {
+                return Ok(());
+            };
+            reader.pending = None;
+            result?;
+            if read_generation == generation {
+                let slot = position as usize % STREAM_CHUNKS;
+                cache::flush_data_range(self.buffer.slot(position), STREAM_CHUNK_LEN);
+                self.buffer.filled[slot].store(position + 1, Ordering::Release);
+                reader.next = position + 1;
+            }
+        }
+
+        let next = reader.next;
+        if next >= self.buffer.position.load(Ordering::Acquire) + STREAM_CHUNKS as u32 {
+            return Ok(());
+        }
+        let loop_chunk = self.buffer.loop_chunk.load(Ordering::Relaxed);
+        let Some(chunk) = reader.chunk_at(next, loop_chunk) else {
+            return Ok(());
+        };
+        let start = chunk * STREAM_CHUNK_LEN as u32;
+        let len = (reader.len - start).min(STREAM_CHUNK_LEN as u32) as usize;
+        let slot = self.buffer.slot(next);
+        self.buffer.filled[next as usize % STREAM_CHUNKS].store(0, Ordering::Release);
+        cache::flush_data_range(slot, STREAM_CHUNK_LEN);
+        unsafe {
+            reader.source.start_read(
+                reader.offset + start as u64,
+                slot,
+                len.next_multiple_of(32),
+            )?
+        };
+        reader.pending = Some((generation, next));
+        Ok(())
+    }
+
+    /// The times the voice reached a chunk that was not read yet.
+    #[inline]
+    pub fn underruns(&self) -> u32 {
+        self.buffer.underruns.load(Ordering::Relaxed)
+    }
+}
+
+impl<S: StreamSource> Default for Stream<S> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
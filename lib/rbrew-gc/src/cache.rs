@@ -0,0 +1,60 @@
+//! Gekko L1 cache maintenance.
+//!
+//! Every function operates on whole 32-byte cache lines overlapping the given range.
+
+pub const LINE_SIZE: usize = 32;
+
+#[inline(always)]
+fn lines(start: *const u8, len: usize) -> impl Iterator<Item = usize> {
+    let first = start as usize & !(LINE_SIZE - 1);
+    let end = start as usize + len;
+    (first..end).step_by(LINE_SIZE)
+}
+
+/// Writes back any dirty data cache lines in the range to memory.
+pub fn store_data_range(start: *const u8, len: usize) {
+    for _line in lines(start, len) {
+        #[cfg(target_arch = "powerpc")]
+        unsafe {
+            core::arch::asm!("dcbst 0, {0}", in(reg) _line)
+        };
+    }
+    sync();
+}
+
+/// Writes back and invalidates the data cache lines in the range.
+pub fn flush_data_range(start: *const u8, len: usize) {
+    for _line in lines(start, len) {
+        #[cfg(target_arch = "powerpc")]
+        unsafe {
+            core::arch::asm!("dcbf 0, {0}", in(reg) _line)
+        };
+    }
+    sync();
+}
+
+/// Makes code written through the data cache visible to instruction fetch.
+///
+/// This must be called after copying or patching code in memory, before executing it.
+pub fn sync_code_range(start: *const u8, len: usize) {
+    store_data_range(start, len);
+    for _line in lines(start, len) {
+        #[cfg(target_arch = "powerpc")]
+        unsafe {
+            core::arch::asm!("icbi 0, {0}", in(reg) _line)
+        };
+    }
+    sync();
+    #[cfg(target_arch = "powerpc")]
+    unsafe {
+        core::arch::asm!("isync")
+    };
+}
+
+#[inline(always)]
+fn sync() {
+    #[cfg(target_arch = "powerpc")]
+    unsafe {
+        core::arch::asm!("sync")
+    };
+}
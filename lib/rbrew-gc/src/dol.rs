@@ -0,0 +1,177 @@
+//! Loading of secondary DOLs built with rbrew's multi-DOL support.
+//!
+//! Sections are read straight to their load addresses, so a DOL can be streamed from disc
+//! or SD without buffering the whole file. rbrew links every secondary DOL into its own
+//! range, clear of the primary that loads it.
+
+use crate::cache;
+
+pub const MAX_TEXT_SECTIONS: usize = 7;
+pub const MAX_DATA_SECTIONS: usize = 11;
+
+/// The memory DOL sections may be loaded into, cached MEM1.
+const LOAD_START: u32 = 0x8000_0000;
+const LOAD_END: u32 = 0x8180_0000;
+
+/// The header at the start of every DOL.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DolHeader {
+    pub text_offsets: [u32; MAX_TEXT_SECTIONS],
+    pub data_offsets: [u32; MAX_DATA_SECTIONS],
+    pub text_addresses: [u32; MAX_TEXT_SECTIONS],
+    pub data_addresses: [u32; MAX_DATA_SECTIONS],
+    pub text_sizes: [u32; MAX_TEXT_SECTIONS],
+    pub data_sizes: [u32; MAX_DATA_SECTIONS],
+    pub bss_address: u32,
+    pub bss_size: u32,
+    pub entry: u32,
+    _padding: [u32; 7],
+}
+
+pub const HEADER_SIZE: usize = core::mem::size_of::<DolHeader>();
+
+impl DolHeader {
+    /// Parses a header from the first [`HEADER_SIZE`] bytes of a DOL.
+    pub fn parse(bytes: &[u8; HEADER_SIZE]) -> Self {
+        let mut words = bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]));
+        let mut next = || words.next().unwrap_or_default();
+        Self {
+            text_offsets: core::array::from_fn(|_| next()),
+            data_offsets: core::array::from_fn(|_| next()),
+            text_addresses: core::array::from_fn(|_| next()),
+            data_addresses: core::array::from_fn(|_| next()),
+            text_sizes: core::array::from_fn(|_| next()),
+            data_sizes: core::array::from_fn(|_| next()),
+            bss_address: next(),
+            bss_size: next(),
+            entry: next(),
+            _padding: [0; 7],
+        }
+    }
+
+    /// Every non-empty section as `(file offset, load address, size, is text)`.
+    pub fn sections(&self) -> impl Iterator<Item = (u32, u32, u32, bool)> + '_ {
+        let text = (0..MAX_TEXT_SECTIONS).map(|i| {
+            (
+                self.text_offsets[i],
+                self.text_addresses[i],
+                self.text_sizes[i],
+                true,
+            )
+        });
+        let data = (0..MAX_DATA_SECTIONS).map(|i| {
+            (
+                self.data_offsets[i],
+                self.data_addresses[i],
+                self.data_sizes[i],
+                false,
+            )
+        });
+        text.chain(data).filter(|section| section.2 != 0)
+    }
+}
+
+#[derive(Debug)]
+pub enum DolLoadError<E> {
+    /// Reading from the source failed.
+    Read(E),
+    /// A section or the bss lies outside of MEM1.
+    OutOfRange(u32),
+    /// The entry point is not inside a text section.
+    InvalidEntry(u32),
+}
+
+fn check_range<E>(address: u32, size: u32) -> Result<(), DolLoadError<E>> {
+    match address.checked_add(size) {
+        Some(end) if address >= LOAD_START && end <= LOAD_END => Ok(()),
+        _ => Err(DolLoadError::OutOfRange(address)),
+    }
+}
+
+/// A DOL loaded into memory.
+#[derive(Clone, Copy)]
+pub struct Dol {
+    entry: u32,
+}
+
+impl Dol {
+    #[inline]
+    pub fn entry(self) -> u32 {
+        self.entry
+    }
+
+    /// Calls the DOL's entry point.
+    ///
+    /// # Safety
+    /// The DOL must still be loaded, and its entry point must be sound to call from the
+    /// running program.
+    pub unsafe fn call(self) {
+        let entry: extern "C" fn() = core::mem::transmute(self.entry as usize);
+        entry()
+    }
+}
+
+/// Loads a DOL through `read`, which fills a buffer from the given file offset.
+///
+/// Every section is read directly to its load address and the bss is zeroed. The caches
+/// are synchronized afterwards, so the DOL can be called right away.
+///
+/// # Safety
+/// The sections and the bss of the DOL must only overlap memory that is not in use,
+/// including by the code and data of `read`.
+pub unsafe fn load<E>(
+    mut read: impl FnMut(u32, &mut [u8]) -> Result<(), E>,
+) -> Result<Dol, DolLoadError<E>> {
+    let mut bytes = [0; HEADER_SIZE];
+    read(0, &mut bytes).map_err(DolLoadError::Read)?;
+    let header = DolHeader::parse(&bytes);
+
+    for (_, address, size, _) in header.sections() {
+        check_range(address, size)?;
+    }
+    if header.bss_size != 0 {
+        check_range(header.bss_address, header.bss_size)?;
+    }
+    let entry_mapped = header.sections().any(|(_, address, size, text)| {
+        text && address <= header.entry && header.entry < address + size
+    });
+    if !entry_mapped {
+        return Err(DolLoadError::InvalidEntry(header.entry));
+    }
+
+    // The bss may overlap data sections, which have to be read after zeroing it.
+    if header.bss_size != 0 {
+        let bss = header.bss_address as usize as *mut u8;
+        core::ptr::write_bytes(bss, 0, header.bss_size as usize);
+        cache::store_data_range(bss, header.bss_size as usize);
+    }
+    for (offset, address, size, text) in header.sections() {
+        let dest = core::slice::from_raw_parts_mut(address as usize as *mut u8, size as usize);
+        read(offset, dest).map_err(DolLoadError::Read)?;
+        if text {
+            cache::sync_code_range(dest.as_ptr(), dest.len());
+        } else {
+            cache::store_data_range(dest.as_ptr(), dest.len());
+        }
+    }
+
+    Ok(Dol {
+        entry: header.entry,
+    })
+}
+
+/// Loads a DOL held in memory.
+///
+/// # Safety
+/// See [`load`]; the sections must not overlap `image` either.
+pub unsafe fn load_from_slice(image: &[u8]) -> Result<Dol, DolLoadError<()>> {
+    load(|offset, buf: &mut [u8]| {
+        let start = offset as usize;
+        let src = image.get(start..start + buf.len()).ok_or(())?;
+        buf.copy_from_slice(src);
+        Ok(())
+    })
+}
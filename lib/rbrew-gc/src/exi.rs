@@ -0,0 +1,491 @@
+//! The external interface, the serial bus of the memory cards, the RTC and SRAM, and the
+//! other devices of the console's ports.
+//!
+//! Each of its three channels selects one of its devices at a time, so drivers lock the
+//! channel for as long as they talk to a device:
+//!
+//! ```ignore
+//! let mut bus = exi::Bus::lock(0);
+//! bus.select(2, exi::Clock::Mhz32);
+//! bus.write(&[0x00, 0x00]);
+//! let id = bus.read_word(4);
+//! // dropping the lock deselects the device
+//! ```
+//!
+//! The channels are the memory card slot A with the RTC and SRAM and the serial port 1,
+//! the slot B, and the serial port 2.
+
+use crate::{cache, interrupt};
+use core::sync::atomic::{AtomicBool, Ordering};
+use rbrew_shared::iotype;
+
+iotype! {
+    pub(crate) type EXI: 0x0c006800, 0x40, cached 0x8000_0000, uncached 0xc000_0000 {
+        /// The channels of the bus: the memory card slot A and the RTC and SRAM, slot B,
+        /// and the serial port 1.
+        channel: [block; 3] = 0x00 stride 0x14 {
+            /// The channel's status and the device selected.
+            csr: mut u32 {
+                int_mask: 0,
+                int: 1,
+                /// The transfer complete interrupt.
+                tc_int_mask: 2,
+                tc_int: 3,
+                /// The clock of the transfers, from 1 MHz doubling up to 32 MHz.
+                clock: 4..=6,
+                /// The devices selected, a bit each.
+                select: 7..=9,
+                /// The interrupt raised when a device is attached or removed.
+                ext_int_mask: 10,
+                ext_int: 11,
+                /// Set while a device is attached.
+                attached: 12,
+                /// Disables the IPL's boot ROM descrambler, on channel 0 only.
+                rom_disable: 13,
+            },
+            /// The physical address of a DMA transfer, 32-byte aligned.
+            mar: mut u32,
+            /// The length of a DMA transfer, in 32-byte units.
+            length: mut u32,
+            /// Starts a transfer.
+            cr: mut u32 {
+                /// Set while the transfer runs.
+                start: 0,
+                dma: 1,
+                /// A read, a write, or both at once for immediate transfers.
+                kind: 2..=3,
+                /// The bytes of an immediate transfer, minus one.
+                length: 4..=5,
+            },
+            /// The bytes of an immediate transfer, from the most significant.
+            data: mut u32,
+        },
+    }
+}
+
+/// The interrupt masks of the channel's status, kept when a device is selected or
+/// deselected.
+const CSR_MASKS: u32 = 0x405;
+
+/// The number of channels.
+pub const CHANNELS: usize = 3;
+
+/// The clock of the transfers with a device. SD cards start at 1 MHz, the RTC and SRAM
+/// run at 8 MHz, the memory cards and SD cards at 16 MHz, and the broadband adapter at
+/// 32 MHz.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Clock {
+    Mhz1,
+    Mhz2,
+    Mhz4,
+    Mhz8,
+    Mhz16,
+    Mhz32,
+}
+
+const READ: u32 = 0;
+const WRITE: u32 = 1;
+const READ_WRITE: u32 = 2;
+
+/// Selects `device` of channel `N`, clocking it at `clock`.
+fn select<const N: usize>(device: u32, clock: Clock) {
+    unsafe {
+        let masks = ExiChannel::<N>::csr_read() & CSR_MASKS;
+        ExiChannel::<N>::csr_write(
+            ExiChannelCsr(masks)
+                .with_clock(clock as u32)
+                .with_select(1 << device)
+                .0,
+        );
+    }
+}
+
+/// Clocks channel `N` at `clock` without selecting a device, which SD cards need as
+/// they start.
+fn select_none<const N: usize>(clock: Clock) {
+    unsafe {
+        let masks = ExiChannel::<N>::csr_read() & CSR_MASKS;
+        ExiChannel::<N>::csr_write(ExiChannelCsr(masks).with_clock(clock as u32).0);
+    }
+}
+
+fn deselect<const N: usize>() {
+    unsafe {
+        let masks = ExiChannel::<N>::csr_read() & CSR_MASKS;
+        ExiChannel::<N>::csr_write(masks);
+    }
+}
+
+/// Runs an immediate transfer of `len` bytes of `data` on channel `N`, returning the
+/// bytes read into a word from its most significant byte.
+fn transfer<const N: usize>(data: u32, len: usize, kind: u32) -> u32 {
+    unsafe {
+        ExiChannel::<N>::data_write(data);
+        ExiChannel::<N>::cr_write(
+            ExiChannelCr::default()
+                .with_start(true)
+                .with_kind(kind)
+                .with_length(len as u32 - 1)
+                .0,
+        );
+        while ExiChannel::<N>::cr_start_read() {
+            core::hint::spin_loop();
+        }
+        ExiChannel::<N>::data_read()
+    }
+}
+
+/// Runs a DMA transfer of `len` bytes at the physical address `address` on channel `N`.
+fn dma<const N: usize>(address: u32, len: usize, kind: u32) {
+    unsafe {
+        ExiChannel::<N>::mar_write(address);
+        ExiChannel::<N>::length_write(len as u32);
+        ExiChannel::<N>::cr_write(
+            ExiChannelCr::default()
+                .with_start(true)
+                .with_dma(true)
+                .with_kind(kind)
+                .0,
+        );
+        while ExiChannel::<N>::cr_start_read() {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Runs `f` with channel `channel` as the const parameter of the functions above.
+macro_rules! on_channel {
+    ($channel:expr, $f:ident ( $($arg:expr),* )) => {
+        match $channel {
+            0 => $f::<0>($($arg),*),
+            1 => $f::<1>($($arg),*),
+            _ => $f::<2>($($arg),*),
+        }
+    };
+}
+
+// The functions below run on a channel whose lock the caller holds, see [`Bus`].
+
+/// Selects `device` of `channel`, clocking it at `clock`.
+pub(crate) fn select_on(channel: usize, device: u32, clock: Clock) {
+    on_channel!(channel, select(device, clock))
+}
+
+/// Clocks `channel` at `clock` without selecting a device.
+pub(crate) fn select_none_on(channel: usize, clock: Clock) {
+    on_channel!(channel, select_none(clock))
+}
+
+pub(crate) fn deselect_on(channel: usize) {
+    on_channel!(channel, deselect())
+}
+
+/// Writes the `len` most significant bytes of `data` on `channel`.
+pub(crate) fn write_on(channel: usize, data: u32, len: usize) {
+    on_channel!(channel, transfer(data, len, WRITE));
+}
+
+/// Reads `len` bytes on `channel` into a word, from its most significant byte.
+pub(crate) fn read_on(channel: usize, len: usize) -> u32 {
+    on_channel!(channel, transfer(0, len, READ))
+}
+
+/// Writes the `len` most significant bytes of `data` on `channel` while reading as many,
+/// as SPI devices do.
+pub(crate) fn exchange_on(channel: usize, data: u32, len: usize) -> u32 {
+    on_channel!(channel, transfer(data, len, READ_WRITE))
+}
+
+/// Takes the interrupt of the device on channel `N`, returning whether it was raised.
+fn take_int<const N: usize>() -> bool {
+    unsafe {
+        let csr = ExiChannelCsr(ExiChannel::<N>::csr_read());
+        if !csr.int() {
+            return false;
+        }
+        // The other interrupts are acknowledged by writing them too.
+        ExiChannel::<N>::csr_write(csr.with_tc_int(false).with_ext_int(false).0);
+    }
+    true
+}
+
+fn set_int_mask<const N: usize>(enabled: bool) {
+    unsafe { ExiChannel::<N>::csr_int_mask_write(enabled) };
+}
+
+fn attached<const N: usize>() -> bool {
+    unsafe { ExiChannel::<N>::csr_attached_read() }
+}
+
+/// Whether a device is attached to `channel`, as in the memory card slots.
+pub(crate) fn is_attached_on(channel: usize) -> bool {
+    on_channel!(channel, attached())
+}
+
+/// Takes the interrupt a device raised on `channel`, which needs no lock.
+pub(crate) fn take_int_on(channel: usize) -> bool {
+    on_channel!(channel, take_int())
+}
+
+/// Masks or unmasks the interrupt devices raise on `channel`.
+pub(crate) fn set_int_mask_on(channel: usize, enabled: bool) {
+    on_channel!(channel, set_int_mask(enabled))
+}
+
+/// Whether a device is attached to `channel`, as the memory card slots detect. The serial
+/// ports always report none.
+///
+/// Panics if `channel` is not below [`CHANNELS`].
+pub fn is_attached(channel: usize) -> bool {
+    assert!(channel < CHANNELS, "the EXI has {CHANNELS} channels");
+    is_attached_on(channel)
+}
+
+static LOCKS: [AtomicBool; CHANNELS] = [const { AtomicBool::new(false) }; CHANNELS];
+
+/// A channel locked for the transfers with its devices, deselecting them and unlocking it
+/// as it is dropped.
+///
+/// Transfers wait for their end. A DMA transfer is for 32-byte aligned buffers of 32-byte
+/// units, and an immediate transfer for up to 4 bytes, which the methods on byte slices
+/// split longer data into.
+#[derive(Debug)]
+pub struct Bus {
+    channel: usize,
+}
+
+impl Bus {
+    /// Locks `channel`, or returns `None` if it is locked.
+    ///
+    /// Panics if `channel` is not below [`CHANNELS`].
+    pub fn try_lock(channel: usize) -> Option<Self> {
+        assert!(channel < CHANNELS, "the EXI has {CHANNELS} channels");
+        if LOCKS[channel].swap(true, Ordering::Acquire) {
+            return None;
+        }
+        Some(Self { channel })
+    }
+
+    /// Locks `channel`, waiting while it is locked.
+    ///
+    /// Interrupt handlers use [`Self::try_lock`] instead, since the code they interrupt
+    /// may hold the lock.
+    ///
+    /// Panics if `channel` is not below [`CHANNELS`].
+    pub fn lock(channel: usize) -> Self {
+        loop {
+            if let Some(bus) = Self::try_lock(channel) {
+                return bus;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    #[inline]
+    pub fn channel(&self) -> usize {
+        self.channel
+    }
+
+    /// Whether a device is attached to the channel, see [`is_attached`].
+    #[inline]
+    pub fn is_attached(&self) -> bool {
+        is_attached_on(self.channel)
+    }
+
+    /// Selects `device`, 0 to 2 on channel 0 and 0 on the others, clocking it at `clock`.
+    /// The device selected before is deselected.
+    ///
+    /// Panics if the channel has no such device.
+    pub fn select(&mut self, device: u32, clock: Clock) {
+        let devices = if self.channel == 0 { 3 } else { 1 };
+        assert!(
+            device < devices,
+            "the channel {} has {devices} devices",
+            self.channel
+        );
+        select_on(self.channel, device, clock);
+    }
+
+    /// Clocks the channel at `clock` with no device selected, as SD cards need as they
+    /// start.
+    #[inline]
+    pub fn select_none(&mut self, clock: Clock) {
+        select_none_on(self.channel, clock);
+    }
+
+    #[inline]
+    pub fn deselect(&mut self) {
+        deselect_on(self.channel);
+    }
+
+    /// Writes the `len` most significant bytes of `data`, up to 4.
+    ///
+    /// Panics if `len` is not from 1 to 4.
+    pub fn write_word(&mut self, data: u32, len: usize) {
+        assert!(
+            (1..=4).contains(&len),
+            "immediate transfers are of 1 to 4 bytes"
+        );
+        write_on(self.channel, data, len);
+    }
+
+    /// Reads `len` bytes, up to 4, into a word from its most significant byte.
+    ///
+    /// Panics if `len` is not from 1 to 4.
+    pub fn read_word(&mut self, len: usize) -> u32 {
+        assert!(
+            (1..=4).contains(&len),
+            "immediate transfers are of 1 to 4 bytes"
+        );
+        read_on(self.channel, len)
+    }
+
+    /// Writes the `len` most significant bytes of `data`, up to 4, while reading as many.
+    ///
+    /// Panics if `len` is not from 1 to 4.
+    pub fn exchange_word(&mut self, data: u32, len: usize) -> u32 {
+        assert!(
+            (1..=4).contains(&len),
+            "immediate transfers are of 1 to 4 bytes"
+        );
+        exchange_on(self.channel, data, len)
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(4) {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            write_on(self.channel, u32::from_be_bytes(word), chunk.len());
+        }
+    }
+
+    pub fn read(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(4) {
+            let word = read_on(self.channel, chunk.len()).to_be_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+    }
+
+    /// Writes `bytes` while reading as many in their place.
+    pub fn exchange(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(4) {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            let word = exchange_on(self.channel, u32::from_be_bytes(word), chunk.len());
+            chunk.copy_from_slice(&word.to_be_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn check_dma(buffer: &[u8]) {
+        assert!(
+            (buffer.as_ptr() as usize).is_multiple_of(32) && buffer.len().is_multiple_of(32),
+            "DMA transfers are of 32-byte aligned buffers of 32-byte units"
+        );
+    }
+
+    /// Writes `data` by DMA.
+    ///
+    /// Panics if `data` is not 32-byte aligned and a multiple of 32 bytes.
+    pub fn write_dma(&mut self, data: &[u8]) {
+        Self::check_dma(data);
+        cache::store_data_range(data.as_ptr(), data.len());
+        let address = data.as_ptr() as u32 & 0x3fff_ffff;
+        on_channel!(self.channel, dma(address, data.len(), WRITE));
+    }
+
+    /// Reads `buffer` by DMA.
+    ///
+    /// Panics if `buffer` is not 32-byte aligned and a multiple of 32 bytes.
+    pub fn read_dma(&mut self, buffer: &mut [u8]) {
+        Self::check_dma(buffer);
+        cache::flush_data_range(buffer.as_ptr(), buffer.len());
+        let address = buffer.as_ptr() as u32 & 0x3fff_ffff;
+        on_channel!(self.channel, dma(address, buffer.len(), READ));
+        cache::flush_data_range(buffer.as_ptr(), buffer.len());
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        deselect_on(self.channel);
+        LOCKS[self.channel].store(false, Ordering::Release);
+    }
+}
+
+/// The size of SRAM, the settings the console keeps powered by its battery.
+pub(crate) const SRAM_SIZE: usize = 64;
+
+/// The commands SRAM is read and written at, in the RTC's address space.
+const SRAM_READ: u32 = 0x2000_0100;
+const SRAM_WRITE: u32 = 0xa000_0100;
+
+/// The checksums at the start of SRAM, the sum of the half-words from 0x0c to 0x14 and of
+/// their complements.
+fn sram_checksums(sram: &[u8; SRAM_SIZE]) -> [u8; 4] {
+    let (sum, inverse) = sram[0x0c..0x14]
+        .chunks_exact(2)
+        .map(|half| u16::from_be_bytes([half[0], half[1]]))
+        .fold((0u16, 0u16), |(sum, inverse), half| {
+            (sum.wrapping_add(half), inverse.wrapping_add(!half))
+        });
+    let ([s0, s1], [i0, i1]) = (sum.to_be_bytes(), inverse.to_be_bytes());
+    [s0, s1, i0, i1]
+}
+
+/// Reads SRAM, the device 1 of channel 0. Returns `None` if its checksums do not match,
+/// as without a battery.
+pub(crate) fn read_sram() -> Option<[u8; SRAM_SIZE]> {
+    let mut sram = [0; SRAM_SIZE];
+    interrupt::free(|| {
+        let mut bus = Bus::lock(0);
+        bus.select(1, Clock::Mhz8);
+        bus.write_word(SRAM_READ, 4);
+        bus.read(&mut sram);
+    });
+    (sram[..4] == sram_checksums(&sram)).then_some(sram)
+}
+
+/// Writes `sram` to SRAM, updating its checksums first.
+pub(crate) fn write_sram(sram: &mut [u8; SRAM_SIZE]) {
+    let checksums = sram_checksums(sram);
+    sram[..4].copy_from_slice(&checksums);
+    interrupt::free(|| {
+        let mut bus = Bus::lock(0);
+        bus.select(1, Clock::Mhz8);
+        bus.write_word(SRAM_WRITE, 4);
+        bus.write(sram);
+    });
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use rbrew_shared::io::mock;
+
+    // The locks are global while the mocked registers are per thread, so each test uses
+    // its own channel.
+
+    #[test]
+    fn select_keeps_the_interrupt_masks() {
+        mock::reset();
+        unsafe { ExiChannel::<1>::csr_write(CSR_MASKS) };
+        let mut bus = Bus::lock(1);
+        bus.select(0, Clock::Mhz16);
+        let csr = unsafe { ExiChannel::<1>::csr_read() };
+        assert_eq!(csr & CSR_MASKS, CSR_MASKS);
+        assert_eq!(ExiChannelCsr(csr).clock(), Clock::Mhz16 as u32);
+        assert_eq!(ExiChannelCsr(csr).select(), 1);
+
+        drop(bus);
+        assert_eq!(unsafe { ExiChannel::<1>::csr_read() }, CSR_MASKS);
+    }
+
+    #[test]
+    fn locks_are_exclusive() {
+        let bus = Bus::try_lock(2).unwrap();
+        assert!(Bus::try_lock(2).is_none());
+        drop(bus);
+        assert!(Bus::try_lock(2).is_some());
+    }
+}
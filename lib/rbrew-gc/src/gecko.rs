@@ -0,0 +1,140 @@
+//! The USB Gecko, the adapter in a memory card slot that links the console to a host
+//! computer through USB, as a serial port.
+//!
+//! The adapter buffers the bytes each way in FIFOs. Sending and receiving either wait
+//! for all the bytes, or take as many as the FIFOs have room or bytes for:
+//!
+//! ```ignore
+//! use core::fmt::Write;
+//!
+//! let mut gecko = UsbGecko::open(Slot::B).expect("no USB Gecko in slot B");
+//! writeln!(gecko, "hello from the console").unwrap();
+//!
+//! let mut command = [0; 64];
+//! let len = gecko.try_receive(&mut command);
+//! ```
+
+use crate::{
+    exi::{Bus, Clock},
+    interrupt,
+    storage::card::Slot,
+};
+
+/// The commands of the adapter, in the top nibble of the half-word exchanged.
+const CMD_IDENTIFY: u32 = 0x9000_0000;
+const CMD_RECEIVE: u32 = 0xa000_0000;
+const CMD_SEND: u32 = 0xb000_0000;
+const CMD_SEND_READY: u32 = 0xc000_0000;
+const CMD_RECEIVE_READY: u32 = 0xd000_0000;
+
+/// The reply to [`CMD_IDENTIFY`].
+const IDENTITY: u32 = 0x0470_0000;
+/// The bits of the replies set when a byte was sent, or there is room to; and when a byte
+/// was received.
+const SENT: u32 = 0x0400_0000;
+const RECEIVED: u32 = 0x0800_0000;
+
+/// A USB Gecko, device 0 of its slot's channel.
+#[derive(Debug)]
+pub struct UsbGecko {
+    channel: usize,
+}
+
+impl UsbGecko {
+    /// Opens the USB Gecko in `slot`, or returns `None` if there is none.
+    pub fn open(slot: Slot) -> Option<Self> {
+        let gecko = Self {
+            channel: slot as usize,
+        };
+        (gecko.command(CMD_IDENTIFY) == IDENTITY).then_some(gecko)
+    }
+
+    /// Whether there is a USB Gecko in `slot`.
+    #[inline]
+    pub fn is_present(slot: Slot) -> bool {
+        Self::open(slot).is_some()
+    }
+
+    /// Exchanges the half-word `command`, returning the reply.
+    fn command(&self, command: u32) -> u32 {
+        interrupt::free(|| {
+            let mut bus = Bus::lock(self.channel);
+            bus.select(0, Clock::Mhz32);
+            bus.exchange_word(command, 2)
+        })
+    }
+
+    /// Whether the adapter has room to send a byte.
+    #[inline]
+    pub fn can_send(&self) -> bool {
+        self.command(CMD_SEND_READY) & SENT != 0
+    }
+
+    /// Whether the adapter has received a byte.
+    #[inline]
+    pub fn can_receive(&self) -> bool {
+        self.command(CMD_RECEIVE_READY) & SENT != 0
+    }
+
+    /// Sends `byte`, or returns `false` if the adapter has no room for it.
+    #[inline]
+    pub fn try_send_byte(&mut self, byte: u8) -> bool {
+        self.command(CMD_SEND | (byte as u32) << 20) & SENT != 0
+    }
+
+    /// Receives a byte, or returns `None` if no byte was received.
+    #[inline]
+    pub fn try_receive_byte(&mut self) -> Option<u8> {
+        let reply = self.command(CMD_RECEIVE);
+        (reply & RECEIVED != 0).then_some((reply >> 16) as u8)
+    }
+
+    /// Sends `data` while the adapter has room for it, returning the bytes sent.
+    pub fn try_send(&mut self, data: &[u8]) -> usize {
+        data.iter()
+            .take_while(|&&byte| self.try_send_byte(byte))
+            .count()
+    }
+
+    /// Receives into `buffer` the bytes the adapter received, returning how many.
+    pub fn try_receive(&mut self, buffer: &mut [u8]) -> usize {
+        let mut len = 0;
+        while let Some(slot) = buffer.get_mut(len) {
+            let Some(byte) = self.try_receive_byte() else {
+                break;
+            };
+            *slot = byte;
+            len += 1;
+        }
+        len
+    }
+
+    /// Sends `data`, waiting while the adapter has no room for it, as when the host does
+    /// not read.
+    pub fn send(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            data = &data[self.try_send(data)..];
+        }
+    }
+
+    /// Fills `buffer`, waiting for the host to send the bytes.
+    pub fn receive(&mut self, buffer: &mut [u8]) {
+        let mut len = 0;
+        while len < buffer.len() {
+            len += self.try_receive(&mut buffer[len..]);
+        }
+    }
+
+    /// Discards the bytes received.
+    pub fn discard(&mut self) {
+        while self.try_receive_byte().is_some() {}
+    }
+}
+
+impl core::fmt::Write for UsbGecko {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.send(s.as_bytes());
+        Ok(())
+    }
+}
@@ -1 +1,6 @@
+pub mod color;
+pub mod console;
+pub mod gx;
+pub mod progressive;
+pub mod sprite;
 pub mod video;
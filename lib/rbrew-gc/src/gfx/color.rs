@@ -0,0 +1,104 @@
+//! Colors, and their conversion to the YUV the external framebuffer holds.
+//!
+//! The conversion is BT.601's, to the 16..=235 range of luma and 16..=240 of chroma the VI
+//! outputs.
+
+/// A color with 8 bits per channel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// A color as luma and blue and red chroma.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Yuv {
+    pub y: u8,
+    pub u: u8,
+    pub v: u8,
+}
+
+/// Clamps `value` to a channel.
+const fn channel(value: i32) -> u8 {
+    if value < 0 {
+        0
+    } else if value > 255 {
+        255
+    } else {
+        value as u8
+    }
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0, 0, 0);
+    pub const WHITE: Rgb = Rgb::new(255, 255, 255);
+    pub const RED: Rgb = Rgb::new(255, 0, 0);
+    pub const GREEN: Rgb = Rgb::new(0, 255, 0);
+    pub const BLUE: Rgb = Rgb::new(0, 0, 255);
+
+    #[inline]
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    pub const fn to_yuv(self) -> Yuv {
+        let (r, g, b) = (self.r as i32, self.g as i32, self.b as i32);
+        Yuv {
+            y: channel(16 + ((66 * r + 129 * g + 25 * b + 128) >> 8)),
+            u: channel(128 + ((-38 * r - 74 * g + 112 * b + 128) >> 8)),
+            v: channel(128 + ((112 * r - 94 * g - 18 * b + 128) >> 8)),
+        }
+    }
+}
+
+impl Yuv {
+    pub const fn to_rgb(self) -> Rgb {
+        let c = self.y as i32 - 16;
+        let (d, e) = (self.u as i32 - 128, self.v as i32 - 128);
+        Rgb {
+            r: channel((298 * c + 409 * e + 128) >> 8),
+            g: channel((298 * c - 100 * d - 208 * e + 128) >> 8),
+            b: channel((298 * c + 516 * d + 128) >> 8),
+        }
+    }
+
+    /// Two pixels as a word of the external framebuffer, `Y0 U Y1 V`, sharing the
+    /// average of their chroma.
+    pub const fn pair(left: Yuv, right: Yuv) -> u32 {
+        let u = (left.u as u32 + right.u as u32) / 2;
+        let v = (left.v as u32 + right.v as u32) / 2;
+        (left.y as u32) << 24 | u << 16 | (right.y as u32) << 8 | v
+    }
+
+    /// The pixels of a word of the external framebuffer, both with its chroma.
+    pub const fn unpair(word: u32) -> (Yuv, Yuv) {
+        let (u, v) = ((word >> 16) as u8, word as u8);
+        (
+            Yuv {
+                y: (word >> 24) as u8,
+                u,
+                v,
+            },
+            Yuv {
+                y: (word >> 8) as u8,
+                u,
+                v,
+            },
+        )
+    }
+}
+
+impl From<Rgb> for Yuv {
+    #[inline]
+    fn from(rgb: Rgb) -> Self {
+        rgb.to_yuv()
+    }
+}
+
+impl From<Yuv> for Rgb {
+    #[inline]
+    fn from(yuv: Yuv) -> Self {
+        yuv.to_rgb()
+    }
+}
@@ -0,0 +1,253 @@
+//! A text console drawn to the framebuffer, in a built-in font.
+//!
+//! [`print!`](crate::print) and [`println!`](crate::println) write to a global console
+//! covering the screen, drawing it to the back buffer and swapping every time:
+//!
+//! ```ignore
+//! use rbrew_gc::println;
+//!
+//! println!("Hello, {}!", "world");
+//! ```
+//!
+//! Programs drawing more than text keep a [`Console`] of their own instead, drawing it
+//! with [`Console::draw`] before they swap.
+
+use super::{
+    color::{Rgb, Yuv},
+    video::{BackBuffer, Framebuffer, VideoMode},
+};
+use core::fmt;
+use spin::Mutex;
+
+pub(super) mod font;
+
+/// The width of a character, in pixels.
+pub const CHAR_WIDTH: usize = 8;
+/// The height of a character, in pixels. Each line of the font is drawn twice, so both
+/// fields of an interlaced mode show all of it.
+pub const CHAR_HEIGHT: usize = 16;
+/// The characters of a line, in every mode.
+pub const COLUMNS: usize = 640 / CHAR_WIDTH;
+/// The lines of the tallest mode.
+const MAX_ROWS: usize = 576 / CHAR_HEIGHT;
+/// The columns tabs stop at a multiple of.
+const TAB_WIDTH: usize = 8;
+
+#[derive(Clone, Copy)]
+struct Cell {
+    byte: u8,
+    foreground: Yuv,
+    background: Yuv,
+}
+
+/// A screen of text, wrapping lines at the right edge and scrolling up at the bottom.
+///
+/// Characters outside of printable ASCII are printed as `?`, except for `\n`, `\r` and
+/// `\t`.
+pub struct Console {
+    cells: [[Cell; COLUMNS]; MAX_ROWS],
+    rows: usize,
+    column: usize,
+    row: usize,
+    foreground: Yuv,
+    background: Yuv,
+}
+
+impl Console {
+    /// A blank console of white text on black, filling the screen of `mode`.
+    pub const fn new(mode: VideoMode) -> Self {
+        let foreground = Rgb::WHITE.to_yuv();
+        let background = Rgb::BLACK.to_yuv();
+        let blank = Cell {
+            byte: b' ',
+            foreground,
+            background,
+        };
+        Self {
+            cells: [[blank; COLUMNS]; MAX_ROWS],
+            rows: mode.height() / CHAR_HEIGHT,
+            column: 0,
+            row: 0,
+            foreground,
+            background,
+        }
+    }
+
+    #[inline]
+    pub fn columns(&self) -> usize {
+        COLUMNS
+    }
+
+    #[inline]
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// The column and row the next character is printed at.
+    #[inline]
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.column, self.row)
+    }
+
+    /// Moves the cursor to `column`, `row`, clamped to the console.
+    pub fn set_cursor(&mut self, column: usize, row: usize) {
+        self.column = column.min(COLUMNS);
+        self.row = row.min(self.rows - 1);
+    }
+
+    /// Sets the colors of the text printed from now on.
+    pub fn set_colors(&mut self, foreground: Rgb, background: Rgb) {
+        self.foreground = foreground.to_yuv();
+        self.background = background.to_yuv();
+    }
+
+    /// Blanks the console in the background color, moving the cursor to the top left.
+    pub fn clear(&mut self) {
+        let blank = self.blank();
+        for line in &mut self.cells[..self.rows] {
+            line.fill(blank);
+        }
+        self.column = 0;
+        self.row = 0;
+    }
+
+    pub fn print(&mut self, text: &str) {
+        for c in text.chars() {
+            match c {
+                '\n' => self.new_line(),
+                '\r' => self.column = 0,
+                '\t' => {
+                    let stop = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                    while self.column < stop.min(COLUMNS) {
+                        self.put(b' ');
+                    }
+                }
+                ' '..='~' => self.put(c as u8),
+                _ => self.put(b'?'),
+            }
+        }
+    }
+
+    /// Draws the console to the top left of `buffer`, as much of it as fits.
+    pub fn draw(&self, buffer: &mut BackBuffer) {
+        let width = buffer.width();
+        let rows = self.rows.min(buffer.height() / CHAR_HEIGHT);
+        for (row, line) in self.cells[..rows].iter().enumerate() {
+            for (column, cell) in line.iter().enumerate() {
+                let (fg, bg) = (cell.foreground, cell.background);
+                // The words of two pixels, by whether the left and right one are set.
+                let words = [
+                    Yuv::pair(bg, bg),
+                    Yuv::pair(bg, fg),
+                    Yuv::pair(fg, bg),
+                    Yuv::pair(fg, fg),
+                ];
+                let glyph = &font::GLYPHS[(cell.byte - b' ') as usize];
+                for (y, bits) in glyph.iter().flat_map(|bits| [bits, bits]).enumerate() {
+                    let start = ((row * CHAR_HEIGHT + y) * width + column * CHAR_WIDTH) / 2;
+                    for (i, word) in buffer[start..start + CHAR_WIDTH / 2].iter_mut().enumerate() {
+                        *word = words[(bits >> (6 - 2 * i)) as usize & 3];
+                    }
+                }
+            }
+        }
+    }
+
+    fn blank(&self) -> Cell {
+        Cell {
+            byte: b' ',
+            foreground: self.foreground,
+            background: self.background,
+        }
+    }
+
+    /// Prints `byte` at the cursor, wrapping it to the next line first if the cursor is
+    /// past the right edge.
+    fn put(&mut self, byte: u8) {
+        if self.column == COLUMNS {
+            self.new_line();
+        }
+        self.cells[self.row][self.column] = Cell {
+            byte,
+            ..self.blank()
+        };
+        self.column += 1;
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.cells.copy_within(1..self.rows, 0);
+            self.cells[self.rows - 1] = [self.blank(); COLUMNS];
+        }
+    }
+
+    /// Fits the console to the screen of `mode`, dropping the lines at the top that no
+    /// longer fit.
+    fn fit(&mut self, mode: VideoMode) {
+        let rows = mode.height() / CHAR_HEIGHT;
+        if rows < self.rows {
+            let dropped = self.rows - rows;
+            self.cells.copy_within(dropped..self.rows, 0);
+            self.row = self.row.saturating_sub(dropped);
+        } else {
+            let blank = self.blank();
+            for line in &mut self.cells[self.rows..rows] {
+                line.fill(blank);
+            }
+        }
+        self.rows = rows;
+    }
+}
+
+impl fmt::Write for Console {
+    #[inline]
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.print(text);
+        Ok(())
+    }
+}
+
+/// The console [`print!`](crate::print) writes to.
+static CONSOLE: Mutex<Console> = Mutex::new(Console::new(VideoMode::Ntsc480i));
+
+/// Runs `f` on the console [`print!`](crate::print) writes to, say to set its colors or
+/// clear it, which shows from the next print.
+pub fn with_global<R>(f: impl FnOnce(&mut Console) -> R) -> R {
+    f(&mut CONSOLE.lock())
+}
+
+/// Prints to the global console, draws it to the back buffer and swaps.
+///
+/// Panics if the back buffer is borrowed.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let framebuffer = Framebuffer::global();
+    let mut console = CONSOLE.lock();
+    console.fit(framebuffer.mode());
+    let _ = fmt::Write::write_fmt(&mut *console, args);
+    console.draw(&mut framebuffer.back());
+    framebuffer.swap();
+}
+
+/// Prints to the global [`Console`](crate::gfx::console::Console), which is then displayed.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::gfx::console::_print(::core::format_args!($($arg)*))
+    };
+}
+
+/// Prints a line to the global [`Console`](crate::gfx::console::Console), which is then
+/// displayed.
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::gfx::console::_print(::core::format_args!("{}\n", ::core::format_args!($($arg)*)))
+    };
+}
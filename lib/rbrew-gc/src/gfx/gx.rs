@@ -0,0 +1,365 @@
+//! The graphics processor, GX, which draws to the embedded framebuffer.
+//!
+//! Commands are written to a FIFO the command processor reads as they are written.
+//! Draws send the vertices laid out by the vertex descriptor and one of the vertex
+//! formats:
+//!
+//! ```ignore
+//! use rbrew_gc::gfx::gx::{vertex::*, GxContext};
+//!
+//! let gx = GxContext::global();
+//! gx.set_vertex_descriptor(&VertexDescriptor::new().with_color(0, AttributeType::Direct));
+//! gx.set_vertex_format(VertexFormatIndex::Format0, &VertexFormat::new());
+//!
+//! let mut draw = gx.begin(Primitive::Triangles, VertexFormatIndex::Format0, 3);
+//! draw.write([0.0f32, 0.5, -0.5]).write(0xff0000ffu32);
+//! draw.write([-0.5f32, -0.5, -0.5]).write(0x00ff00ffu32);
+//! draw.write([0.5f32, -0.5, -0.5]).write(0x0000ffffu32);
+//! draw.end();
+//! gx.flush();
+//! ```
+//!
+//! Until other matrices are loaded positions are in clip space, from -1 to 1 left to
+//! right and bottom to top, and from -1 to 0 near to far. The color of the first channel
+//! is drawn as it is, with depth testing, until [`tev`] stages combine it with [`texture`]s.
+//! Frames are displayed by [`copy`]ing them to the external framebuffer.
+
+use crate::{cache, gfx::color::Rgb};
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use matrix::{Mtx34, Mtx44};
+use spin::Mutex;
+use vertex::{
+    ArrayAttribute, Component, NormalComponents, Primitive, VertexDescriptor, VertexFormat,
+    VertexFormatIndex,
+};
+
+pub mod copy;
+mod fifo;
+pub mod matrix;
+pub mod pixel;
+pub mod tev;
+pub mod texture;
+pub mod vertex;
+
+/// The offset of coordinates in the raster pipeline's registers, which keeps them positive
+/// past the edges of the embedded framebuffer.
+const COORDINATE_OFFSET: u32 = 342;
+/// The largest depth.
+const Z_MAX: f32 = 16_777_215.0;
+/// The matrix index of the identity, which texture coordinates are generated with.
+const IDENTITY_MATRIX: u8 = 60;
+
+/// Loads the matrix indices of the vertices without them: `position`'s for positions and
+/// normals, the identity's for texture coordinates.
+fn load_matrix_index(position: u8) {
+    let identity = IDENTITY_MATRIX as u32;
+    let a = (0..4).fold(position as u32, |a, index| a | identity << (6 + 6 * index));
+    let b = (0..4).fold(0, |b, index| b | identity << (6 * index));
+    fifo::load_cp(0x30, a);
+    fifo::load_cp(0x40, b);
+    fifo::load_xf(0x1018, &[a, b]);
+}
+
+/// The vertex descriptor and formats set, loaded into the command processor before the
+/// next draw.
+struct State {
+    descriptor: VertexDescriptor,
+    formats: [VertexFormat; 8],
+    descriptor_dirty: bool,
+    /// A bit for each format.
+    formats_dirty: u8,
+    /// The vertex specification loaded into the transform unit.
+    xf_spec: u32,
+    /// The depth test and update, and the blending and color update, in the raster
+    /// pipeline, which clearing copies override.
+    z_mode: u32,
+    blend_mode: u32,
+    /// The weights of the copy filter's lines, which copies to the external framebuffer
+    /// scale by the display's brightness.
+    copy_vertical: [u8; 7],
+    tex_gens: u8,
+    tev_stages: u8,
+    /// The sizes of the textures loaded, and the map each texture coordinate samples
+    /// first, which its coordinates are scaled by.
+    texture_sizes: [[u16; 2]; 8],
+    coord_maps: [Option<u8>; 8],
+    tex_sizes_dirty: bool,
+}
+
+impl State {
+    /// Loads what changed for a draw in `format`, returning the bytes of its vertices.
+    fn flush(&mut self, format: VertexFormatIndex) -> usize {
+        if self.descriptor_dirty {
+            let (low, high) = self.descriptor.registers();
+            fifo::load_cp(0x50, low);
+            fifo::load_cp(0x60, high);
+            self.descriptor_dirty = false;
+        }
+        for index in (0..8).filter(|index| self.formats_dirty & 1 << index != 0) {
+            let [a, b, c] = self.formats[index as usize].registers();
+            fifo::load_cp(0x70 + index, a);
+            fifo::load_cp(0x80 + index, b);
+            fifo::load_cp(0x90 + index, c);
+        }
+        self.formats_dirty = 0;
+
+        let format = &self.formats[format as usize];
+        let xf_spec = self.descriptor.xf_spec(format);
+        if xf_spec != self.xf_spec {
+            fifo::load_xf(0x1008, &[xf_spec]);
+            self.xf_spec = xf_spec;
+        }
+        if self.tex_sizes_dirty {
+            for (coord, map) in self.coord_maps.iter().enumerate() {
+                if let &Some(map) = map {
+                    let [width, height] = self.texture_sizes[map as usize];
+                    let coord = coord as u8;
+                    fifo::load_bp(0x30 + 2 * coord, width as u32 - 1);
+                    fifo::load_bp(0x31 + 2 * coord, height as u32 - 1);
+                }
+            }
+            self.tex_sizes_dirty = false;
+        }
+
+        self.descriptor.vertex_size(format)
+    }
+
+    /// Loads the counts of the raster pipeline: of texture coordinates generated, of color
+    /// channels, one, and of TEV stages, culling nothing.
+    fn load_gen_mode(&self) {
+        let stages = self.tev_stages as u32 - 1;
+        fifo::load_bp(0x00, self.tex_gens as u32 | 1 << 4 | stages << 10);
+    }
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    descriptor: VertexDescriptor::new(),
+    formats: [VertexFormat::new(); 8],
+    descriptor_dirty: true,
+    formats_dirty: 0xff,
+    xf_spec: u32::MAX,
+    // Tested less or equal and updated, colors and alpha written without blending.
+    z_mode: 1 | 3 << 1 | 1 << 4,
+    blend_mode: 1 << 3 | 1 << 4,
+    copy_vertical: copy::CopyFilter::PROGRESSIVE.vertical,
+    tex_gens: 0,
+    tev_stages: 1,
+    texture_sizes: [[1; 2]; 8],
+    coord_maps: [None; 8],
+    tex_sizes_dirty: false,
+});
+
+static IS_INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum GxInitError {
+    AlreadyInitialized,
+}
+
+#[derive(Clone, Copy)]
+pub struct GxContext {
+    // Makes the type non-trivially constructible.
+    _mark: PhantomData<()>,
+}
+
+impl GxContext {
+    fn init_gx() -> Result<(), GxInitError> {
+        unsafe { fifo::init() };
+        let gx = unsafe { Self::global_unchecked() };
+
+        // Positions by the first matrix, the identity, texture coordinates by the identity.
+        gx.load_position_matrix(0, &Mtx34::IDENTITY);
+        gx.load_normal_matrix(0, &Mtx34::IDENTITY);
+        matrix::load_rows(IDENTITY_MATRIX, &Mtx34::IDENTITY);
+        gx.set_position_matrix(0);
+        gx.set_projection(&Mtx44::IDENTITY);
+
+        // A color channel of the vertices' colors, unlit, and no texture coordinates.
+        fifo::load_xf(0x1009, &[1]);
+        fifo::load_xf(0x100e, &[1]);
+        fifo::load_xf(0x1010, &[1]);
+        gx.set_tex_coord_gens(&[]);
+        // A stage passing the channel's color and alpha.
+        gx.set_tev_stages(&[tev::TevStage::new()]);
+        {
+            let state = STATE.lock();
+            fifo::load_bp(0x40, state.z_mode);
+            fifo::load_bp(0x41, state.blend_mode);
+        }
+        // RGB8 with a 24-bit depth, tested before texturing.
+        fifo::load_bp(0x43, 1 << 6);
+        // Copies unfiltered, clearing to black and the farthest depth.
+        gx.set_copy_filter(&copy::CopyFilter::PROGRESSIVE);
+        gx.set_copy_clear(Rgb::BLACK, 0xff, 0xff_ffff);
+
+        let offset = COORDINATE_OFFSET >> 1;
+        fifo::load_bp(0x59, offset | offset << 10);
+        gx.set_viewport(0.0, 0.0, 640.0, 480.0, 0.0, 1.0);
+        gx.set_scissor(0, 0, 640, 480);
+        gx.flush();
+        Ok(())
+    }
+
+    /// Points the graphics processor at the FIFO, and sets the state documented on the
+    /// [module](self) for a 640x480 embedded framebuffer.
+    pub fn init() -> Result<(), GxInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
+            Err(GxInitError::AlreadyInitialized)
+        } else {
+            Self::init_gx()
+        }
+    }
+
+    /// The global GX context, initialized if it was not.
+    pub fn global() -> Self {
+        #[allow(unreachable_patterns)]
+        match Self::init() {
+            Err(GxInitError::AlreadyInitialized) | Ok(_) => {}
+            Err(e) => panic!("GX failed to initialize: {e:?}"),
+        }
+        unsafe { Self::global_unchecked() }
+    }
+
+    /// # Safety
+    /// Requires that the global GX context has been initialized.
+    /// This is ensured by [`Self::global`] or [`Self::init`].
+    pub unsafe fn global_unchecked() -> Self {
+        Self { _mark: PhantomData }
+    }
+
+    /// Sets the attributes of the vertices of the next draws.
+    ///
+    /// Panics if `descriptor` has no position.
+    pub fn set_vertex_descriptor(self, descriptor: &VertexDescriptor) {
+        assert!(
+            descriptor.position != vertex::AttributeType::None,
+            "vertices need a position"
+        );
+        let mut state = STATE.lock();
+        state.descriptor = *descriptor;
+        state.descriptor_dirty = true;
+    }
+
+    /// Sets the encoding of the attributes of the draws in the format `index`.
+    ///
+    /// Panics if the normals are unsigned, or fractional bits are past 31.
+    pub fn set_vertex_format(self, index: VertexFormatIndex, format: &VertexFormat) {
+        use vertex::ComponentType;
+        assert!(
+            !matches!(format.normal.ty, ComponentType::U8 | ComponentType::U16),
+            "normals are signed"
+        );
+        assert!(
+            format.position.frac < 32 && format.tex_coords.iter().all(|tex| tex.frac < 32),
+            "components have up to 31 fractional bits"
+        );
+        assert!(
+            !format.normal.index3
+                || format.normal.components == NormalComponents::NormalBinormalTangent,
+            "only normals with binormals and tangents have three indices"
+        );
+        let mut state = STATE.lock();
+        state.formats[index as usize] = *format;
+        state.formats_dirty |= 1 << index as u8;
+    }
+
+    /// Sets the array the indices of `attribute` index into, its elements `stride` bytes
+    /// apart, writing it back from the data cache for the graphics processor.
+    pub fn set_array<T>(self, attribute: ArrayAttribute, array: &'static [T], stride: u8) {
+        let ptr = array.as_ptr().cast::<u8>();
+        cache::store_data_range(ptr, size_of_val(array));
+        let index = attribute.index();
+        fifo::load_cp(0xa0 + index, ptr as u32 & 0x3fff_ffff);
+        fifo::load_cp(0xb0 + index, stride as u32);
+        // Drops what the vertex cache held of the previous array.
+        fifo::write_u8(0x48);
+    }
+
+    /// Maps clip space to the rectangle of the embedded framebuffer from `x`, `y` of
+    /// `width` by `height` pixels, and depths from `near` to `far`, between 0 and 1.
+    pub fn set_viewport(self, x: f32, y: f32, width: f32, height: f32, near: f32, far: f32) {
+        let offset = COORDINATE_OFFSET as f32;
+        let viewport = [
+            width * 0.5,
+            -height * 0.5,
+            (far - near) * Z_MAX,
+            x + width * 0.5 + offset,
+            y + height * 0.5 + offset,
+            far * Z_MAX,
+        ];
+        fifo::load_xf(0x101a, &viewport.map(f32::to_bits));
+    }
+
+    /// Limits drawing to the rectangle of the embedded framebuffer from `x`, `y` of
+    /// `width` by `height` pixels.
+    ///
+    /// Panics if the rectangle is empty, or past 1024 pixels from the top left.
+    pub fn set_scissor(self, x: u32, y: u32, width: u32, height: u32) {
+        assert!(width > 0 && height > 0, "the scissor rectangle is empty");
+        assert!(
+            x + width <= 1024 && y + height <= 1024,
+            "the scissor rectangle is past 1024 pixels"
+        );
+        let top = y + COORDINATE_OFFSET;
+        let left = x + COORDINATE_OFFSET;
+        fifo::load_bp(0x20, top | left << 12);
+        fifo::load_bp(0x21, (top + height - 1) | (left + width - 1) << 12);
+    }
+
+    /// Starts a draw of `vertices` vertices as `primitive`, encoded in `format`, which
+    /// are written to the returned [`Draw`] before it is ended.
+    pub fn begin(self, primitive: Primitive, format: VertexFormatIndex, vertices: u16) -> Draw {
+        let size = STATE.lock().flush(format);
+        fifo::write_u8(primitive as u8 | format as u8);
+        fifo::write_u16(vertices);
+        Draw {
+            remaining: size * vertices as usize,
+        }
+    }
+
+    /// Makes the commands written so far reach the FIFO, rather than wait in the
+    /// write-gather pipe for more.
+    #[inline]
+    pub fn flush(self) {
+        fifo::flush();
+    }
+}
+
+/// A draw being written, the components of each vertex following the attributes of the
+/// vertex descriptor, encoded as the vertex format says.
+#[must_use = "a draw is ended once its vertices are written"]
+pub struct Draw {
+    /// The bytes of vertices left to write.
+    remaining: usize,
+}
+
+impl Draw {
+    /// Writes the next component, or components if it is an array.
+    ///
+    /// Debug builds panic if it is past the vertices of the draw.
+    #[inline]
+    pub fn write<T: Component>(&mut self, value: T) -> &mut Self {
+        debug_assert!(
+            self.remaining >= T::SIZE,
+            "written past the vertices of the draw"
+        );
+        self.remaining = self.remaining.wrapping_sub(T::SIZE);
+        value.write();
+        self
+    }
+
+    /// Ends the draw.
+    ///
+    /// Debug builds panic if vertices are missing.
+    #[inline]
+    pub fn end(self) {
+        debug_assert!(
+            self.remaining == 0,
+            "the draw is missing {} bytes of vertices",
+            self.remaining
+        );
+    }
+}
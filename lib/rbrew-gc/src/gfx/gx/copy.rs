@@ -0,0 +1,295 @@
+//! Copies of the embedded framebuffer to the external framebuffer or to textures, which
+//! can clear it as they go.
+//!
+//! A frame drawn by GX is displayed by copying it to the back buffer and swapping:
+//!
+//! ```ignore
+//! gx.set_copy_clear(Rgb::BLACK, 0xff, 0xff_ffff);
+//! loop {
+//!     // Draw the frame...
+//!     gx.present(framebuffer);
+//! }
+//! ```
+
+use super::{fifo, texture::TextureFormat, GxContext, STATE};
+use crate::{
+    cache,
+    gfx::{
+        color::Rgb,
+        video::{BackBuffer, Framebuffer, VideoContext},
+    },
+};
+use rbrew_shared::iotype;
+
+iotype! {
+    /// The interrupts of the pixel engine.
+    pub type PE: 0x0c001000, 0x100, cached 0x8000_0000, uncached 0xc000_0000 {
+        reserved 0x00..0x0a,
+        isr: mut u16 {
+            token_enable: 0,
+            finish_enable: 1,
+            /// Set by a draw token, cleared by writing 1.
+            token: 2,
+            /// Set once the draws before a draw done command are, cleared by writing 1.
+            finish: 3,
+        },
+    }
+}
+
+/// The lines of the embedded framebuffer. Copies to PAL's 576 lines are scaled from them.
+pub const EFB_MAX_HEIGHT: usize = 528;
+
+// The bits of the copy command.
+const CLAMP_TOP_BOTTOM: u32 = 0b11;
+const HALF_SCALE: u32 = 1 << 9;
+const VERTICAL_SCALE: u32 = 1 << 10;
+const CLEAR: u32 = 1 << 11;
+const TO_XFB: u32 = 1 << 14;
+const INTENSITY: u32 = 1 << 15;
+const AUTO_CONVERSION: u32 = 1 << 16;
+
+/// Loads the weights of the copy filter's lines, scaled by `brightness`.
+fn load_vertical(weights: [u8; 7], brightness: f32) {
+    let [v0, v1, v2, v3, v4, v5, v6] =
+        weights.map(|weight| (weight as f32 * brightness + 0.5).min(63.0) as u32);
+    fifo::load_bp(0x53, v0 | v1 << 6 | v2 << 12 | v3 << 18);
+    fifo::load_bp(0x54, v4 | v5 << 6 | v6 << 12);
+}
+
+/// The filter pixels are copied through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CopyFilter {
+    /// The positions of the three samples of each pixel of a 2x2 quad, in twelfths of a
+    /// pixel from its top left, for antialiased framebuffers.
+    pub samples: [[u8; 2]; 12],
+    /// The weights of the lines from three above to three below, in 64ths, each up to 63.
+    pub vertical: [u8; 7],
+}
+
+impl CopyFilter {
+    /// The lightest vertical filter there is, the line's weight not fitting 64.
+    pub const PROGRESSIVE: CopyFilter = CopyFilter {
+        samples: [[6, 6]; 12],
+        vertical: [0, 0, 21, 22, 21, 0, 0],
+    };
+
+    /// A vertical filter keeping the thin lines of interlaced modes from flickering.
+    pub const DEFLICKER: CopyFilter = CopyFilter {
+        samples: [[6, 6]; 12],
+        vertical: [8, 8, 10, 12, 10, 8, 8],
+    };
+}
+
+/// The texture formats the embedded framebuffer is copied to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum CopyFormat {
+    I4 = 0,
+    I8 = 1,
+    Ia4 = 2,
+    Ia8 = 3,
+    Rgb565 = 4,
+    Rgb5a3 = 5,
+    Rgba8 = 6,
+}
+
+impl CopyFormat {
+    /// The bytes of a texture of `width` by `height` pixels, in whole tiles.
+    #[inline]
+    pub fn size(self, width: usize, height: usize) -> usize {
+        TextureFormat::from(self).size(width, height)
+    }
+}
+
+impl From<CopyFormat> for TextureFormat {
+    fn from(format: CopyFormat) -> Self {
+        match format {
+            CopyFormat::I4 => TextureFormat::I4,
+            CopyFormat::I8 => TextureFormat::I8,
+            CopyFormat::Ia4 => TextureFormat::Ia4,
+            CopyFormat::Ia8 => TextureFormat::Ia8,
+            CopyFormat::Rgb565 => TextureFormat::Rgb565,
+            CopyFormat::Rgb5a3 => TextureFormat::Rgb5a3,
+            CopyFormat::Rgba8 => TextureFormat::Rgba8,
+        }
+    }
+}
+
+/// The rectangle of the embedded framebuffer copied to a texture, and how.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureCopy {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub format: CopyFormat,
+    /// Averages each 2x2 quad into a pixel, for the level of a mipmap below.
+    pub half: bool,
+}
+
+impl TextureCopy {
+    /// The size of the texture copied to.
+    pub fn texture_size(&self) -> (usize, usize) {
+        let (width, height) = (self.width as usize, self.height as usize);
+        if self.half {
+            (width / 2, height / 2)
+        } else {
+            (width, height)
+        }
+    }
+}
+
+/// Sets the rectangle of the embedded framebuffer copied.
+fn set_source(x: u16, y: u16, width: u16, height: u16) {
+    assert!(width > 0 && height > 0, "the copied rectangle is empty");
+    assert!(
+        x as usize + width as usize <= 640 && y as usize + height as usize <= EFB_MAX_HEIGHT,
+        "the copied rectangle is past the embedded framebuffer"
+    );
+    fifo::load_bp(0x49, x as u32 | (y as u32) << 10);
+    fifo::load_bp(0x4a, (width as u32 - 1) | (height as u32 - 1) << 10);
+}
+
+/// Copies to the physical address `dest` with the command `control`, clearing what was
+/// copied if `clear` is set.
+fn execute(dest: u32, mut control: u32, clear: bool) {
+    let state = STATE.lock();
+    fifo::load_bp(0x4b, dest >> 5);
+    if clear {
+        // Depths are cleared whatever the test, and colors without blending.
+        fifo::load_bp(0x40, state.z_mode & !0xf | 0xf);
+        fifo::load_bp(0x41, state.blend_mode & !0b11);
+        control |= CLEAR;
+    }
+    fifo::load_bp(0x52, control);
+    if clear {
+        fifo::load_bp(0x40, state.z_mode);
+        fifo::load_bp(0x41, state.blend_mode);
+    }
+}
+
+impl GxContext {
+    /// Sets the color and depth copies clear the embedded framebuffer to.
+    pub fn set_copy_clear(self, color: Rgb, alpha: u8, z: u32) {
+        assert!(z < 1 << 24, "depths are 24-bit");
+        fifo::load_bp(0x4f, color.r as u32 | (alpha as u32) << 8);
+        fifo::load_bp(0x50, color.b as u32 | (color.g as u32) << 8);
+        fifo::load_bp(0x51, z);
+    }
+
+    /// Sets the filter of the copies to the external framebuffer.
+    ///
+    /// Panics if a sample is past the quad, or a weight past 63.
+    pub fn set_copy_filter(self, filter: &CopyFilter) {
+        assert!(
+            filter
+                .samples
+                .iter()
+                .flatten()
+                .all(|&position| position < 16),
+            "samples are up to 15 twelfths from the top left"
+        );
+        assert!(
+            filter.vertical.iter().all(|&weight| weight < 64),
+            "weights are up to 63"
+        );
+        for (index, samples) in filter.samples.chunks(3).enumerate() {
+            let value = samples.iter().enumerate().fold(0, |value, (i, &[x, y])| {
+                value | (x as u32) << (8 * i) | (y as u32) << (8 * i + 4)
+            });
+            fifo::load_bp(0x01 + index as u8, value);
+        }
+        STATE.lock().copy_vertical = filter.vertical;
+        load_vertical(filter.vertical, 1.0);
+    }
+
+    /// Copies the top of the embedded framebuffer to `buffer`, scaling its lines to PAL's,
+    /// and waits for the copy to be done. The colors are corrected by the
+    /// [gamma](VideoContext::set_gamma) and [brightness](VideoContext::set_brightness) of
+    /// the display.
+    pub fn copy_to_xfb(self, buffer: &mut BackBuffer, clear: bool) {
+        let (width, height) = (buffer.width(), buffer.height());
+        let efb_height = height.min(EFB_MAX_HEIGHT);
+        set_source(0, 0, width as u16, efb_height as u16);
+        // The stride in 32-byte units, and the step through the copied lines for each
+        // line written, 256 being 1:1.
+        fifo::load_bp(0x4d, (width * 2 / 32) as u32);
+        let step = (256 * (efb_height - 1)).div_ceil(height - 1) as u32;
+        fifo::load_bp(0x4e, step);
+
+        // The lines the processor wrote are written back rather than over the copy.
+        let words: &[u32] = buffer;
+        cache::flush_data_range(words.as_ptr().cast(), size_of_val(words));
+        let video = VideoContext::global();
+        load_vertical(STATE.lock().copy_vertical, video.brightness());
+        let mut control = CLAMP_TOP_BOTTOM | TO_XFB | (video.gamma() as u32) << 7;
+        if step != 256 {
+            control |= VERTICAL_SCALE;
+        }
+        execute(words.as_ptr() as u32 & 0x3fff_ffff, control, clear);
+        self.wait_for_draw_done();
+    }
+
+    /// Copies the embedded framebuffer to the back buffer, clearing it, and swaps.
+    ///
+    /// Panics if the back buffer is borrowed.
+    pub fn present(self, framebuffer: Framebuffer) {
+        self.copy_to_xfb(&mut framebuffer.back(), true);
+        framebuffer.swap();
+    }
+
+    /// Copies the rectangle of `copy` to `texture`, in 32-byte aligned tiles of its
+    /// format, and waits for the copy to be done. The texture is loaded with the
+    /// [`TextureFormat`] of the copy's.
+    ///
+    /// Panics if `texture` is not aligned or too small for the copy.
+    pub fn copy_to_texture(self, copy: &TextureCopy, texture: &mut [u8], clear: bool) {
+        let (width, height) = copy.texture_size();
+        assert!(
+            (texture.as_ptr() as usize).is_multiple_of(32),
+            "textures are 32-byte aligned"
+        );
+        assert!(
+            texture.len() >= copy.format.size(width, height),
+            "the texture is too small for the copy"
+        );
+        set_source(copy.x, copy.y, copy.width, copy.height);
+        let (tile_width, _, planes) = TextureFormat::from(copy.format).tile();
+        fifo::load_bp(0x4d, (width.div_ceil(tile_width) * planes) as u32);
+
+        cache::flush_data_range(texture.as_ptr(), texture.len());
+        load_vertical(STATE.lock().copy_vertical, 1.0);
+        // The format's bits are rotated, its top bit the lowest.
+        let format = copy.format as u32;
+        let mut control = CLAMP_TOP_BOTTOM | (format >> 3) << 3 | (format & 0x7) << 4;
+        if copy.half {
+            control |= HALF_SCALE;
+        }
+        control |= match copy.format {
+            CopyFormat::I4 | CopyFormat::I8 | CopyFormat::Ia4 | CopyFormat::Ia8 => {
+                INTENSITY | AUTO_CONVERSION
+            }
+            _ => AUTO_CONVERSION,
+        };
+        execute(texture.as_ptr() as u32 & 0x3fff_ffff, control, clear);
+        self.wait_for_draw_done();
+        // The texture cache may hold what the texture was.
+        self.invalidate_textures();
+    }
+
+    /// Waits for the graphics processor to be done with the commands written so far.
+    ///
+    /// The pixel engine's finish interrupt is left for this to poll, a handler of it
+    /// acknowledging it would keep this waiting.
+    pub fn wait_for_draw_done(self) {
+        unsafe {
+            let isr = PeIsr(PE::isr_read()).with_token(false).with_finish(true);
+            PE::isr_write(isr.0);
+        }
+        fifo::load_bp(0x45, 2);
+        self.flush();
+        while !unsafe { PE::isr_finish_read() } {
+            core::hint::spin_loop();
+        }
+    }
+}
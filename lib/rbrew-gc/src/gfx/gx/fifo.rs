@@ -0,0 +1,200 @@
+//! The FIFO commands are written to, in immediate mode: the processor writes it through
+//! the write-gather pipe while the command processor reads it, wrapping around at its end.
+
+use crate::interrupt::PI;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rbrew_shared::iotype;
+
+iotype! {
+    /// The command processor, reading the FIFO.
+    pub type CP: 0x0c000000, 0x80, cached 0x8000_0000, uncached 0xc000_0000 {
+        sr: const u16 {
+            /// The FIFO is past its high watermark.
+            overflow: 0,
+            /// The FIFO is under its low watermark.
+            underflow: 1,
+            read_idle: 2,
+            command_idle: 3,
+            breakpoint: 4,
+        },
+        cr: mut u16 {
+            /// Reads commands from the FIFO.
+            read_enable: 0,
+            break_enable: 1,
+            overflow_interrupt: 2,
+            underflow_interrupt: 3,
+            /// Follows the processor interface's write pointer, for immediate mode.
+            link_enable: 4,
+            break_interrupt: 5,
+        },
+        clear: mut u16 {
+            overflow: 0,
+            underflow: 1,
+        },
+        reserved 0x06..0x20,
+        /// The FIFO's registers, as the low and high halves of physical addresses and
+        /// distances.
+        fifo_base: mut [u16; 2],
+        fifo_end: mut [u16; 2],
+        fifo_high_watermark: mut [u16; 2],
+        fifo_low_watermark: mut [u16; 2],
+        /// The bytes written and not read yet.
+        fifo_distance: mut [u16; 2],
+        fifo_write: mut [u16; 2],
+        fifo_read: mut [u16; 2],
+    }
+}
+
+/// The write-gather pipe, in the uncached mirror. The processor gathers the stores to it
+/// and writes them to the FIFO 32 bytes at a time.
+const PIPE: usize = 0xcc00_8000;
+
+/// The size of the FIFO, libogc's.
+const SIZE: usize = 256 * 1024;
+/// The distance past which writes wait for the command processor to catch up, down to
+/// the low watermark, leaving room for the writes between checks.
+const HIGH_WATERMARK: usize = SIZE - 16 * 1024;
+const LOW_WATERMARK: usize = SIZE / 2;
+/// The bytes written between checks of the distance.
+const CHECK_INTERVAL: usize = 4 * 1024;
+
+#[repr(C, align(32))]
+struct Buffer([u8; SIZE]);
+
+static mut BUFFER: Buffer = Buffer([0; SIZE]);
+
+/// The bytes written since the distance was last checked.
+static UNCHECKED: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes `value` to the two halves of a register of the command processor.
+unsafe fn write_halves(write: unsafe fn(usize, u16), value: u32) {
+    write(0, value as u16);
+    write(1, (value >> 16) as u16);
+}
+
+unsafe fn distance() -> usize {
+    // The high half is read first, the distance only changing by whole bursts.
+    let high = CP::fifo_distance_read(1) as usize;
+    let low = CP::fifo_distance_read(0) as usize;
+    high << 16 | low
+}
+
+/// Points the command processor and the processor interface at the FIFO, and enables
+/// the write-gather pipe.
+///
+/// # Safety
+///
+/// Resets the FIFO, discarding the commands the command processor has not read.
+pub(super) unsafe fn init() {
+    let base = (&raw mut BUFFER).cast::<u8>();
+    let start = base as u32 & 0x3fff_ffff;
+    let end = start + SIZE as u32 - 4;
+
+    CP::cr_write(0);
+    CP::clear_write(
+        CpClear::default()
+            .with_overflow(true)
+            .with_underflow(true)
+            .0,
+    );
+    write_halves(CP::fifo_base_write, start);
+    write_halves(CP::fifo_end_write, end);
+    write_halves(CP::fifo_high_watermark_write, HIGH_WATERMARK as u32);
+    write_halves(CP::fifo_low_watermark_write, LOW_WATERMARK as u32);
+    write_halves(CP::fifo_distance_write, 0);
+    write_halves(CP::fifo_write_write, start);
+    write_halves(CP::fifo_read_write, start);
+
+    PI::fifo_base_write(start);
+    PI::fifo_end_write(end);
+    PI::fifo_write_write(start);
+
+    // The pipe's physical address, and the pipe enable of HID2.
+    #[cfg(target_arch = "powerpc")]
+    core::arch::asm!(
+        "mtspr 921, {0}",
+        "mfspr {1}, 920",
+        "oris {1}, {1}, 0x4000",
+        "mtspr 920, {1}",
+        "isync",
+        in(reg) PIPE as u32 & 0x3fff_ffff,
+        out(reg) _,
+    );
+
+    CP::cr_write(
+        CpCr::default()
+            .with_read_enable(true)
+            .with_link_enable(true)
+            .0,
+    );
+}
+
+/// Counts `len` bytes written, waiting for the command processor to read the FIFO down
+/// to its low watermark if it is past the high one.
+#[inline]
+fn count(len: usize) {
+    if UNCHECKED.fetch_add(len, Ordering::Relaxed) + len < CHECK_INTERVAL {
+        return;
+    }
+    UNCHECKED.store(0, Ordering::Relaxed);
+    if unsafe { distance() } > HIGH_WATERMARK {
+        while unsafe { distance() } > LOW_WATERMARK {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+#[inline]
+pub(super) fn write_u8(value: u8) {
+    unsafe { (PIPE as *mut u8).write_volatile(value) };
+    count(1);
+}
+
+#[inline]
+pub(super) fn write_u16(value: u16) {
+    unsafe { (PIPE as *mut u16).write_volatile(value) };
+    count(2);
+}
+
+#[inline]
+pub(super) fn write_u32(value: u32) {
+    unsafe { (PIPE as *mut u32).write_volatile(value) };
+    count(4);
+}
+
+#[inline]
+pub(super) fn write_f32(value: f32) {
+    unsafe { (PIPE as *mut f32).write_volatile(value) };
+    count(4);
+}
+
+/// Pads the pipe with no-op commands until the commands written so far reach the FIFO.
+pub(super) fn flush() {
+    for _ in 0..8 {
+        write_u32(0);
+    }
+}
+
+/// Loads `value` into the command processor's register `reg`.
+pub(super) fn load_cp(reg: u8, value: u32) {
+    write_u8(0x08);
+    write_u8(reg);
+    write_u32(value);
+}
+
+/// Loads `values` into the transform unit's registers or memory from `address`.
+pub(super) fn load_xf(address: u16, values: &[u32]) {
+    debug_assert!(!values.is_empty());
+    write_u8(0x10);
+    write_u32(((values.len() as u32 - 1) << 16) | address as u32);
+    for &value in values {
+        write_u32(value);
+    }
+}
+
+/// Loads the 24 bits of `value` into the register `reg` of the raster pipeline.
+pub(super) fn load_bp(reg: u8, value: u32) {
+    debug_assert!(value < 1 << 24);
+    write_u8(0x61);
+    write_u32((reg as u32) << 24 | value);
+}
@@ -0,0 +1,409 @@
+//! Matrices laid out as GX's matrix memory is, and the transforms programs build from
+//! them.
+//!
+//! Matrices are rows of columns, transforming column vectors: `a * b` transforms by `b`,
+//! then by `a`. A model's position matrix is the camera's times its own:
+//!
+//! ```ignore
+//! use rbrew_gc::gfx::gx::matrix::{Mtx34, Mtx44, Vec3};
+//!
+//! let camera = Mtx34::look_at(Vec3::new(0.0, 0.0, 5.0), Vec3::ZERO, Vec3::new(0.0, 1.0, 0.0));
+//! let model = Mtx34::rotation_y(angle) * Mtx34::translation(0.0, 1.0, 0.0);
+//! gx.load_position_matrix(0, &(camera * model));
+//! gx.set_projection(&Mtx44::perspective(1.0, 4.0 / 3.0, 0.1, 100.0));
+//! ```
+//!
+//! Cameras look down -z, the y axis up, and projections map the depths from `near` to
+//! `far` to -1 to 0, as GX's clip space has them.
+
+use super::{fifo, GxContext};
+use core::ops::{Add, Mul, Neg, Sub};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vec3 {
+    pub const ZERO: Vec3 = Vec3::new(0.0, 0.0, 0.0);
+
+    #[inline]
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+
+    #[inline]
+    pub fn dot(self, other: Vec3) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    #[inline]
+    pub fn cross(self, other: Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    #[inline]
+    pub fn length(self) -> f32 {
+        libm::sqrtf(self.dot(self))
+    }
+
+    /// The vector of length 1 in the same direction, or zero if it is zero.
+    pub fn normalize(self) -> Vec3 {
+        let length = self.length();
+        if length == 0.0 {
+            self
+        } else {
+            self * (1.0 / length)
+        }
+    }
+}
+
+impl Add for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn add(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl Sub for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn sub(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl Neg for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn neg(self) -> Vec3 {
+        Vec3::new(-self.x, -self.y, -self.z)
+    }
+}
+
+impl Mul<f32> for Vec3 {
+    type Output = Vec3;
+
+    #[inline]
+    fn mul(self, scale: f32) -> Vec3 {
+        Vec3::new(self.x * scale, self.y * scale, self.z * scale)
+    }
+}
+
+/// A 3x4 affine transform, a 3x3 matrix and a translation in its last column, as position,
+/// normal and texture matrices are loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mtx34(pub [[f32; 4]; 3]);
+
+impl Default for Mtx34 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Mtx34 {
+    pub const IDENTITY: Mtx34 = Mtx34([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+    ]);
+
+    pub const fn translation(x: f32, y: f32, z: f32) -> Self {
+        Mtx34([[1.0, 0.0, 0.0, x], [0.0, 1.0, 0.0, y], [0.0, 0.0, 1.0, z]])
+    }
+
+    pub const fn scaling(x: f32, y: f32, z: f32) -> Self {
+        Mtx34([[x, 0.0, 0.0, 0.0], [0.0, y, 0.0, 0.0], [0.0, 0.0, z, 0.0]])
+    }
+
+    /// A rotation by `angle` radians about the x axis, counterclockwise looking down it.
+    pub fn rotation_x(angle: f32) -> Self {
+        let (sin, cos) = libm::sincosf(angle);
+        Mtx34([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, cos, -sin, 0.0],
+            [0.0, sin, cos, 0.0],
+        ])
+    }
+
+    pub fn rotation_y(angle: f32) -> Self {
+        let (sin, cos) = libm::sincosf(angle);
+        Mtx34([
+            [cos, 0.0, sin, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [-sin, 0.0, cos, 0.0],
+        ])
+    }
+
+    pub fn rotation_z(angle: f32) -> Self {
+        let (sin, cos) = libm::sincosf(angle);
+        Mtx34([
+            [cos, -sin, 0.0, 0.0],
+            [sin, cos, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+        ])
+    }
+
+    /// A rotation by `angle` radians about `axis`, which need not be normalized.
+    pub fn rotation(axis: Vec3, angle: f32) -> Self {
+        let Vec3 { x, y, z } = axis.normalize();
+        let (sin, cos) = libm::sincosf(angle);
+        let t = 1.0 - cos;
+        Mtx34([
+            [
+                t * x * x + cos,
+                t * x * y - sin * z,
+                t * x * z + sin * y,
+                0.0,
+            ],
+            [
+                t * x * y + sin * z,
+                t * y * y + cos,
+                t * y * z - sin * x,
+                0.0,
+            ],
+            [
+                t * x * z - sin * y,
+                t * y * z + sin * x,
+                t * z * z + cos,
+                0.0,
+            ],
+        ])
+    }
+
+    /// The camera at `eye` looking at `target`, `up` pointing up from it.
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        let back = (eye - target).normalize();
+        let right = up.cross(back).normalize();
+        let up = back.cross(right);
+        let row = |axis: Vec3| [axis.x, axis.y, axis.z, -axis.dot(eye)];
+        Mtx34([row(right), row(up), row(back)])
+    }
+
+    /// Transforms the point `point`, translating it.
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        let row = |[a, b, c, d]: [f32; 4]| a * point.x + b * point.y + c * point.z + d;
+        let [x, y, z] = self.0;
+        Vec3::new(row(x), row(y), row(z))
+    }
+
+    /// Transforms the direction `vector`, without translating it.
+    pub fn transform_vector(&self, vector: Vec3) -> Vec3 {
+        let row = |[a, b, c, _]: [f32; 4]| a * vector.x + b * vector.y + c * vector.z;
+        let [x, y, z] = self.0;
+        Vec3::new(row(x), row(y), row(z))
+    }
+
+    /// The transform undoing this one, if there is one.
+    pub fn inverse(&self) -> Option<Self> {
+        let [[a, b, c, x], [d, e, f, y], [g, h, i, z]] = self.0;
+        // The 3x3 matrix's cofactors, transposed.
+        let adjugate = [
+            [e * i - f * h, c * h - b * i, b * f - c * e],
+            [f * g - d * i, a * i - c * g, c * d - a * f],
+            [d * h - e * g, b * g - a * h, a * e - b * d],
+        ];
+        let determinant = a * adjugate[0][0] + b * adjugate[1][0] + c * adjugate[2][0];
+        if determinant == 0.0 {
+            return None;
+        }
+        let scale = 1.0 / determinant;
+        let mut inverse = [[0.0; 4]; 3];
+        for (row, cofactors) in inverse.iter_mut().zip(adjugate) {
+            let [p, q, r] = cofactors.map(|cofactor| cofactor * scale);
+            *row = [p, q, r, -(p * x + q * y + r * z)];
+        }
+        Some(Mtx34(inverse))
+    }
+
+    /// The matrix normals are transformed by for this one to keep them perpendicular to
+    /// surfaces: the inverse of its 3x3 matrix, transposed. A rotation's is itself.
+    pub fn normal_matrix(&self) -> Option<Self> {
+        let Mtx34(inverse) = self.inverse()?;
+        let mut normal = [[0.0; 4]; 3];
+        for (row, normal) in normal.iter_mut().enumerate() {
+            for (column, value) in normal.iter_mut().take(3).enumerate() {
+                *value = inverse[column][row];
+            }
+        }
+        Some(Mtx34(normal))
+    }
+}
+
+impl Mul for Mtx34 {
+    type Output = Mtx34;
+
+    fn mul(self, other: Mtx34) -> Mtx34 {
+        // The rows of `other` as a 4x4 matrix, the last one implied.
+        let other_row = |k: usize| other.0.get(k).copied().unwrap_or([0.0, 0.0, 0.0, 1.0]);
+        let mut product = [[0.0; 4]; 3];
+        for (row, lhs) in product.iter_mut().zip(self.0) {
+            for (column, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| lhs[k] * other_row(k)[column]).sum::<f32>();
+            }
+        }
+        Mtx34(product)
+    }
+}
+
+impl Mul<Vec3> for Mtx34 {
+    type Output = Vec3;
+
+    #[inline]
+    fn mul(self, point: Vec3) -> Vec3 {
+        self.transform_point(point)
+    }
+}
+
+/// A 4x4 projection, perspective if its last row is `[0, 0, -1, 0]` and orthographic if it
+/// is `[0, 0, 0, 1]`, the only ones GX has.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mtx44(pub [[f32; 4]; 4]);
+
+impl Default for Mtx44 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Mtx44 {
+    /// The orthographic projection of clip space as it is.
+    pub const IDENTITY: Mtx44 = Mtx44([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+
+    /// A perspective projection of the frustum from `left` to `right` and `bottom` to `top`
+    /// at the depth `near`, to `far`.
+    pub fn frustum(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Self {
+        let (width, height, depth) = (right - left, top - bottom, far - near);
+        Mtx44([
+            [2.0 * near / width, 0.0, (right + left) / width, 0.0],
+            [0.0, 2.0 * near / height, (top + bottom) / height, 0.0],
+            [0.0, 0.0, -near / depth, -far * near / depth],
+            [0.0, 0.0, -1.0, 0.0],
+        ])
+    }
+
+    /// A perspective projection of `fov_y` radians from bottom to top, `aspect` times as
+    /// wide as it is high, from `near` to `far`.
+    pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Self {
+        let top = near * libm::tanf(fov_y / 2.0);
+        let right = top * aspect;
+        Self::frustum(-right, right, -top, top, near, far)
+    }
+
+    /// An orthographic projection of the box from `left` to `right`, `bottom` to `top` and
+    /// `near` to `far`.
+    pub fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Self {
+        let (width, height, depth) = (right - left, top - bottom, far - near);
+        Mtx44([
+            [2.0 / width, 0.0, 0.0, -(right + left) / width],
+            [0.0, 2.0 / height, 0.0, -(top + bottom) / height],
+            [0.0, 0.0, -1.0 / depth, -far / depth],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Whether it is orthographic rather than perspective.
+    #[inline]
+    fn is_orthographic(&self) -> bool {
+        self.0[3][3] == 1.0
+    }
+}
+
+/// The position and normal matrices, from GX's matrix memory's start.
+const POSITION_MATRICES: u8 = 10;
+/// The matrix index of the first texture matrix, after the position matrices.
+const FIRST_TEX_MATRIX: u8 = 30;
+
+/// The matrix index vertices select the position matrix `index` with, the row of matrix
+/// memory it starts at.
+#[inline]
+pub const fn position_matrix_index(index: u8) -> u8 {
+    index * 3
+}
+
+/// Loads the rows of `matrix` into matrix memory from the row `row`.
+pub(super) fn load_rows(row: u8, matrix: &Mtx34) {
+    let rows = matrix.0.map(|row| row.map(f32::to_bits));
+    fifo::load_xf(row as u16 * 4, rows.as_flattened());
+}
+
+impl GxContext {
+    /// Loads `matrix` as the position matrix `index`, of the ten.
+    ///
+    /// Panics if `index` is past 9.
+    pub fn load_position_matrix(self, index: u8, matrix: &Mtx34) {
+        assert!(index < POSITION_MATRICES, "there are ten position matrices");
+        load_rows(position_matrix_index(index), matrix);
+    }
+
+    /// Loads the 3x3 matrix of `matrix` as the normal matrix `index`, which goes with the
+    /// position matrix `index`. It is the position matrix's
+    /// [normal matrix](Mtx34::normal_matrix), or the position matrix itself if it only
+    /// rotates and translates.
+    ///
+    /// Panics if `index` is past 9.
+    pub fn load_normal_matrix(self, index: u8, matrix: &Mtx34) {
+        assert!(index < POSITION_MATRICES, "there are ten normal matrices");
+        let rows = matrix.0.map(|[a, b, c, _]| [a, b, c].map(f32::to_bits));
+        // Normal matrices are rows of three, at the position matrices' indices.
+        let address = 0x400 + position_matrix_index(index) as u16 * 3;
+        fifo::load_xf(address, rows.as_flattened());
+    }
+
+    /// Loads `matrix` as the texture matrix `index`, of the ten.
+    ///
+    /// Panics if `index` is past 9.
+    pub fn load_tex_matrix(self, index: u8, matrix: &Mtx34) {
+        assert!(index < 10, "there are ten texture matrices");
+        load_rows(FIRST_TEX_MATRIX + index * 3, matrix);
+    }
+
+    /// Transforms the positions and normals of vertices without a position matrix index by
+    /// the position matrix `index`.
+    ///
+    /// Panics if `index` is past 9.
+    pub fn set_position_matrix(self, index: u8) {
+        assert!(index < POSITION_MATRICES, "there are ten position matrices");
+        super::load_matrix_index(position_matrix_index(index));
+    }
+
+    /// Sets the projection of the transformed positions into clip space.
+    ///
+    /// Panics if it is neither perspective nor orthographic.
+    pub fn set_projection(self, projection: &Mtx44) {
+        let [[p0, _, p02, p03], [_, p2, p12, p13], [_, _, p4, p5], last] = projection.0;
+        let parameters = if projection.is_orthographic() {
+            assert!(
+                last == [0.0, 0.0, 0.0, 1.0],
+                "the projection is not orthographic"
+            );
+            [p0, p03, p2, p13, p4, p5]
+        } else {
+            assert!(
+                last == [0.0, 0.0, -1.0, 0.0],
+                "the projection is not perspective"
+            );
+            [p0, p02, p2, p12, p4, p5]
+        };
+        let [a, b, c, d, e, f] = parameters.map(f32::to_bits);
+        fifo::load_xf(
+            0x1020,
+            &[a, b, c, d, e, f, projection.is_orthographic() as u32],
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! The depth test and blending of the pixels drawn into the embedded framebuffer.
+
+use super::{fifo, GxContext, STATE};
+
+/// How a pixel's depth compares to the one drawn before it for it to be drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Compare {
+    Never = 0,
+    Less = 1,
+    Equal = 2,
+    #[default]
+    LessEqual = 3,
+    Greater = 4,
+    NotEqual = 5,
+    GreaterEqual = 6,
+    Always = 7,
+}
+
+/// The factors of the colors blended, the one drawn and the one in the framebuffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum BlendFactor {
+    Zero = 0,
+    One = 1,
+    /// The framebuffer's color for the color drawn's factor, and the other way around.
+    OtherColor = 2,
+    InverseOtherColor = 3,
+    SourceAlpha = 4,
+    InverseSourceAlpha = 5,
+    DestAlpha = 6,
+    InverseDestAlpha = 7,
+}
+
+/// How the colors drawn are blended with the framebuffer's.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// The color drawn replaces the framebuffer's.
+    #[default]
+    None,
+    /// The sum of the colors by their factors.
+    Blend {
+        source: BlendFactor,
+        dest: BlendFactor,
+    },
+    /// The framebuffer's color minus the one drawn.
+    Subtract,
+}
+
+impl BlendMode {
+    /// Blends by the alpha of the color drawn, as a translucent color over the
+    /// framebuffer's.
+    pub const ALPHA: BlendMode = BlendMode::Blend {
+        source: BlendFactor::SourceAlpha,
+        dest: BlendFactor::InverseSourceAlpha,
+    };
+}
+
+// The bits of the blend mode's register.
+const BLEND_ENABLE: u32 = 1;
+const COLOR_UPDATE: u32 = 1 << 3;
+const ALPHA_UPDATE: u32 = 1 << 4;
+const SUBTRACT: u32 = 1 << 11;
+
+impl GxContext {
+    /// Tests the depth of the pixels of the next draws by `test`, drawing every one if it is
+    /// `None`, and updates the framebuffer's depth with the depths drawn if `update` is set.
+    pub fn set_z_mode(self, test: Option<Compare>, update: bool) {
+        let mode = match test {
+            Some(compare) => 1 | (compare as u32) << 1,
+            None => 0,
+        } | (update as u32) << 4;
+        let mut state = STATE.lock();
+        state.z_mode = mode;
+        fifo::load_bp(0x40, mode);
+    }
+
+    /// Blends the colors of the next draws with the framebuffer's by `mode`.
+    pub fn set_blend_mode(self, mode: BlendMode) {
+        let mut state = STATE.lock();
+        let updates = state.blend_mode & (COLOR_UPDATE | ALPHA_UPDATE);
+        let blend = match mode {
+            BlendMode::None => 0,
+            BlendMode::Blend { source, dest } => {
+                BLEND_ENABLE | (dest as u32) << 5 | (source as u32) << 8
+            }
+            BlendMode::Subtract => BLEND_ENABLE | SUBTRACT,
+        };
+        state.blend_mode = blend | updates;
+        fifo::load_bp(0x41, state.blend_mode);
+    }
+
+    /// Sets whether the next draws write the framebuffer's colors and alphas.
+    pub fn set_color_update(self, color: bool, alpha: bool) {
+        let mut state = STATE.lock();
+        let blend = state.blend_mode & !(COLOR_UPDATE | ALPHA_UPDATE);
+        state.blend_mode =
+            blend | if color { COLOR_UPDATE } else { 0 } | if alpha { ALPHA_UPDATE } else { 0 };
+        fifo::load_bp(0x41, state.blend_mode);
+    }
+}
@@ -0,0 +1,459 @@
+//! The TEV stages, which combine the rasterized colors, textures and constants into the
+//! pixels drawn, each stage from the results of the one before.
+//!
+//! A stage computes `d ± ((1 - c) * a + c * b) + bias`, scaled, for its color and its
+//! alpha, from the inputs of its [`Combiner`]s:
+//!
+//! ```ignore
+//! use rbrew_gc::gfx::gx::tev::TevStage;
+//!
+//! // The texture of the first map at the first coordinates, tinted by the vertex colors.
+//! gx.set_tev_stages(&[TevStage::modulate(0, 0)]);
+//! ```
+
+use super::{fifo, GxContext, STATE};
+use crate::gfx::color::Rgb;
+
+/// An input of a [`Combiner`].
+pub trait TevInput: Copy {
+    #[doc(hidden)]
+    const ZERO: Self;
+    #[doc(hidden)]
+    fn bits(self) -> u32;
+}
+
+/// The color inputs of a stage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ColorInput {
+    /// The color of the previous stage, or of [`TevRegister::Prev`] in the first.
+    Prev = 0x0,
+    PrevAlpha = 0x1,
+    Reg0 = 0x2,
+    Reg0Alpha = 0x3,
+    Reg1 = 0x4,
+    Reg1Alpha = 0x5,
+    Reg2 = 0x6,
+    Reg2Alpha = 0x7,
+    Texture = 0x8,
+    TextureAlpha = 0x9,
+    /// The color channel of the stage, interpolated between the vertices.
+    Raster = 0xa,
+    RasterAlpha = 0xb,
+    One = 0xc,
+    Half = 0xd,
+    /// The constant the stage selects.
+    Konst = 0xe,
+    Zero = 0xf,
+}
+
+impl TevInput for ColorInput {
+    const ZERO: Self = ColorInput::Zero;
+
+    #[inline]
+    fn bits(self) -> u32 {
+        self as u32
+    }
+}
+
+/// The alpha inputs of a stage.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum AlphaInput {
+    Prev = 0,
+    Reg0 = 1,
+    Reg1 = 2,
+    Reg2 = 3,
+    Texture = 4,
+    Raster = 5,
+    Konst = 6,
+    Zero = 7,
+}
+
+impl TevInput for AlphaInput {
+    const ZERO: Self = AlphaInput::Zero;
+
+    #[inline]
+    fn bits(self) -> u32 {
+        self as u32
+    }
+}
+
+/// Whether the blend of `a` and `b` is added to `d` or subtracted from it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TevOp {
+    #[default]
+    Add = 0,
+    Subtract = 1,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TevBias {
+    #[default]
+    Zero = 0,
+    AddHalf = 1,
+    SubtractHalf = 2,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TevScale {
+    #[default]
+    One = 0,
+    Two = 1,
+    Four = 2,
+    Half = 3,
+}
+
+/// The registers stages read and write, the last stage's result being the one drawn out
+/// of [`TevRegister::Prev`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TevRegister {
+    #[default]
+    Prev = 0,
+    Reg0 = 1,
+    Reg1 = 2,
+    Reg2 = 3,
+}
+
+/// What a stage computes for its color or its alpha, from [`ColorInput`]s or
+/// [`AlphaInput`]s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Combiner<I> {
+    pub a: I,
+    pub b: I,
+    pub c: I,
+    pub d: I,
+    pub op: TevOp,
+    pub bias: TevBias,
+    pub scale: TevScale,
+    /// Clamps the result between 0 and 1, rather than let it wrap past -4 to 4.
+    pub clamp: bool,
+    pub dest: TevRegister,
+}
+
+impl<I: TevInput> Combiner<I> {
+    /// Computes `d + ((1 - c) * a + c * b)`, clamped, into [`TevRegister::Prev`].
+    pub const fn new(a: I, b: I, c: I, d: I) -> Self {
+        Self {
+            a,
+            b,
+            c,
+            d,
+            op: TevOp::Add,
+            bias: TevBias::Zero,
+            scale: TevScale::One,
+            clamp: true,
+            dest: TevRegister::Prev,
+        }
+    }
+
+    /// Passes `input` as it is.
+    pub const fn pass(input: I) -> Self {
+        Self::new(I::ZERO, I::ZERO, I::ZERO, input)
+    }
+
+    /// Multiplies `x` by `y`.
+    pub const fn modulate(x: I, y: I) -> Self {
+        Self::new(I::ZERO, x, y, I::ZERO)
+    }
+
+    /// Adds `x` to `y`.
+    pub const fn add(x: I, y: I) -> Self {
+        Self::new(x, I::ZERO, I::ZERO, y)
+    }
+
+    /// Blends from `x` to `y` by `t`.
+    pub const fn blend(x: I, y: I, t: I) -> Self {
+        Self::new(x, y, t, I::ZERO)
+    }
+
+    #[inline]
+    pub const fn with_op(mut self, op: TevOp) -> Self {
+        self.op = op;
+        self
+    }
+
+    #[inline]
+    pub const fn with_bias(mut self, bias: TevBias) -> Self {
+        self.bias = bias;
+        self
+    }
+
+    #[inline]
+    pub const fn with_scale(mut self, scale: TevScale) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    #[inline]
+    pub const fn with_clamp(mut self, clamp: bool) -> Self {
+        self.clamp = clamp;
+        self
+    }
+
+    #[inline]
+    pub const fn with_dest(mut self, dest: TevRegister) -> Self {
+        self.dest = dest;
+        self
+    }
+
+    /// The bits after the inputs, shared by the color and alpha registers.
+    fn operation(&self) -> u32 {
+        (self.bias as u32) << 16
+            | (self.op as u32) << 18
+            | (self.clamp as u32) << 19
+            | (self.scale as u32) << 20
+            | (self.dest as u32) << 22
+    }
+}
+
+impl Combiner<ColorInput> {
+    fn register(&self) -> u32 {
+        self.d.bits()
+            | self.c.bits() << 4
+            | self.b.bits() << 8
+            | self.a.bits() << 12
+            | self.operation()
+    }
+}
+
+impl Combiner<AlphaInput> {
+    /// The register, with the identity swap tables for the raster and texture colors.
+    fn register(&self) -> u32 {
+        self.d.bits() << 4
+            | self.c.bits() << 7
+            | self.b.bits() << 10
+            | self.a.bits() << 13
+            | self.operation()
+    }
+}
+
+/// The constant a stage's [`ColorInput::Konst`] or [`AlphaInput::Konst`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Konst {
+    /// A fraction from 1/8 to 8/8, in all components.
+    Eighths(u8),
+    /// One of the four constant colors, its alpha if it is the alpha's constant.
+    Color(u8),
+    Red(u8),
+    Green(u8),
+    Blue(u8),
+    Alpha(u8),
+}
+
+impl Konst {
+    fn bits(self, alpha: bool) -> u32 {
+        let register = |index: u8, first: u32| {
+            assert!(index < 4, "there are four constant colors");
+            first + index as u32
+        };
+        match self {
+            Konst::Eighths(eighths) => {
+                assert!((1..=8).contains(&eighths), "fractions are 1/8 to 8/8");
+                8 - eighths as u32
+            }
+            Konst::Color(index) if alpha => register(index, 0x1c),
+            Konst::Color(index) => register(index, 0x0c),
+            Konst::Red(index) => register(index, 0x10),
+            Konst::Green(index) => register(index, 0x14),
+            Konst::Blue(index) => register(index, 0x18),
+            Konst::Alpha(index) => register(index, 0x1c),
+        }
+    }
+}
+
+/// The color channel a stage's [`ColorInput::Raster`] is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Channel {
+    #[default]
+    Color0 = 0,
+    Color1 = 1,
+    Zero = 7,
+}
+
+/// A TEV stage, set with [`GxContext::set_tev_stages`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TevStage {
+    pub color: Combiner<ColorInput>,
+    pub alpha: Combiner<AlphaInput>,
+    /// The texture coordinates and texture map [`ColorInput::Texture`] samples.
+    pub texture: Option<(u8, u8)>,
+    pub channel: Channel,
+    pub konst_color: Konst,
+    pub konst_alpha: Konst,
+}
+
+impl Default for TevStage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TevStage {
+    /// A stage passing the first color channel, without a texture.
+    pub const fn new() -> Self {
+        Self {
+            color: Combiner::pass(ColorInput::Raster),
+            alpha: Combiner::pass(AlphaInput::Raster),
+            texture: None,
+            channel: Channel::Color0,
+            konst_color: Konst::Eighths(8),
+            konst_alpha: Konst::Eighths(8),
+        }
+    }
+
+    /// A stage passing the texture of `map` at the coordinates `coord`.
+    pub const fn replace(coord: u8, map: u8) -> Self {
+        Self::new()
+            .with_texture(coord, map)
+            .with_color(Combiner::pass(ColorInput::Texture))
+            .with_alpha(Combiner::pass(AlphaInput::Texture))
+    }
+
+    /// A stage multiplying the texture of `map` at the coordinates `coord` by the first
+    /// color channel.
+    pub const fn modulate(coord: u8, map: u8) -> Self {
+        Self::new()
+            .with_texture(coord, map)
+            .with_color(Combiner::modulate(ColorInput::Texture, ColorInput::Raster))
+            .with_alpha(Combiner::modulate(AlphaInput::Texture, AlphaInput::Raster))
+    }
+
+    #[inline]
+    pub const fn with_color(mut self, color: Combiner<ColorInput>) -> Self {
+        self.color = color;
+        self
+    }
+
+    #[inline]
+    pub const fn with_alpha(mut self, alpha: Combiner<AlphaInput>) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    #[inline]
+    pub const fn with_texture(mut self, coord: u8, map: u8) -> Self {
+        self.texture = Some((coord, map));
+        self
+    }
+
+    #[inline]
+    pub const fn with_channel(mut self, channel: Channel) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    #[inline]
+    pub const fn with_konst(mut self, color: Konst, alpha: Konst) -> Self {
+        self.konst_color = color;
+        self.konst_alpha = alpha;
+        self
+    }
+
+    /// The stage's half of its pair's texture and channel register.
+    fn order(&self) -> u32 {
+        let texture = match self.texture {
+            Some((coord, map)) => {
+                assert!(coord < 8 && map < 8, "there are eight coordinates and maps");
+                map as u32 | (coord as u32) << 3 | 1 << 6
+            }
+            None => 0,
+        };
+        texture | (self.channel as u32) << 7
+    }
+
+    /// The stage's half of its pair's constant selection.
+    fn konst(&self) -> u32 {
+        self.konst_color.bits(false) | self.konst_alpha.bits(true) << 5
+    }
+}
+
+/// Loads `[r, g, b, a]` into the color register `register`, or the constant one if `konst`.
+fn load_color(register: u8, [r, g, b, a]: [i16; 4], konst: bool) {
+    let bits = |low: i16, high: i16| {
+        (low as u32 & 0x7ff) | (high as u32 & 0x7ff) << 12 | (konst as u32) << 23
+    };
+    fifo::load_bp(0xe0 + 2 * register, bits(r, a));
+    // The colors' second half is written thrice for the processor to take it.
+    for _ in 0..if konst { 1 } else { 3 } {
+        fifo::load_bp(0xe1 + 2 * register, bits(b, g));
+    }
+}
+
+impl GxContext {
+    /// Sets the TEV stages of the next draws, from the first to the last.
+    ///
+    /// Panics if there are none or more than 16, or a stage's inputs are out of range.
+    pub fn set_tev_stages(self, stages: &[TevStage]) {
+        assert!(
+            (1..=16).contains(&stages.len()),
+            "there are one to sixteen TEV stages"
+        );
+        for (index, stage) in stages.iter().enumerate() {
+            let index = index as u8;
+            fifo::load_bp(0xc0 + 2 * index, stage.color.register());
+            fifo::load_bp(0xc1 + 2 * index, stage.alpha.register());
+            // Without indirect texturing.
+            fifo::load_bp(0x10 + index, 0);
+        }
+        for (pair, stages) in stages.chunks(2).enumerate() {
+            let (order, konst) =
+                stages
+                    .iter()
+                    .enumerate()
+                    .fold((0, 0), |(order, konst), (i, stage)| {
+                        (
+                            order | stage.order() << (12 * i),
+                            konst | stage.konst() << (4 + 10 * i),
+                        )
+                    });
+            let pair = pair as u8;
+            fifo::load_bp(0x28 + pair, order);
+            // The identity swap tables, their entries split over the pairs' registers.
+            let swap = if pair.is_multiple_of(2) {
+                1 << 2
+            } else {
+                2 | 3 << 2
+            };
+            fifo::load_bp(0xf6 + pair, konst | swap);
+        }
+
+        let mut state = STATE.lock();
+        state.coord_maps = [None; 8];
+        for &(coord, map) in stages.iter().filter_map(|stage| stage.texture.as_ref()) {
+            state.coord_maps[coord as usize].get_or_insert(map);
+        }
+        state.tex_sizes_dirty = true;
+        state.tev_stages = stages.len() as u8;
+        state.load_gen_mode();
+    }
+
+    /// Sets the color register `register` to `color` and `alpha`.
+    pub fn set_tev_color(self, register: TevRegister, color: Rgb, alpha: u8) {
+        let components = [color.r, color.g, color.b, alpha].map(i16::from);
+        load_color(register as u8, components, false);
+    }
+
+    /// Sets the color register `register` to components from -1024 to 1023, 255 being 1.
+    pub fn set_tev_color_signed(self, register: TevRegister, components: [i16; 4]) {
+        assert!(
+            components.iter().all(|c| (-1024..1024).contains(c)),
+            "components are from -1024 to 1023"
+        );
+        load_color(register as u8, components, false);
+    }
+
+    /// Sets the constant color `index` to `color` and `alpha`.
+    ///
+    /// Panics if `index` is past 3.
+    pub fn set_tev_konst(self, index: u8, color: Rgb, alpha: u8) {
+        assert!(index < 4, "there are four constant colors");
+        let components = [color.r, color.g, color.b, alpha].map(i16::from);
+        load_color(index, components, true);
+    }
+}
@@ -0,0 +1,467 @@
+//! Textures, loaded into the eight texture maps the TEV stages sample, and TPL files of
+//! them.
+//!
+//! Texture data is 32-byte aligned, in the 32-byte tiles of its format. A TPL file is
+//! included aligned and its textures loaded as they are:
+//!
+//! ```ignore
+//! use rbrew_gc::gfx::gx::texture::{Aligned, Tpl};
+//!
+//! static TEXTURES: &Aligned<[u8]> = &Aligned(*include_bytes!("textures.tpl"));
+//!
+//! let tpl = Tpl::parse(&TEXTURES.0).unwrap();
+//! gx.load_texture(0, &tpl.texture(0).unwrap());
+//! ```
+
+use super::{fifo, GxContext, STATE};
+use crate::cache;
+
+/// A value aligned to 32 bytes, as texture data is.
+#[repr(C, align(32))]
+pub struct Aligned<T: ?Sized>(pub T);
+
+/// The formats of texture data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TextureFormat {
+    I4 = 0,
+    I8 = 1,
+    Ia4 = 2,
+    Ia8 = 3,
+    Rgb565 = 4,
+    Rgb5a3 = 5,
+    Rgba8 = 6,
+    /// S3TC compressed 4x4 blocks, four to a tile.
+    Cmpr = 0xe,
+}
+
+impl TextureFormat {
+    /// The pixels of its 32-byte tiles, and how many planes of them there are.
+    pub(super) fn tile(self) -> (usize, usize, usize) {
+        match self {
+            TextureFormat::I4 | TextureFormat::Cmpr => (8, 8, 1),
+            TextureFormat::I8 | TextureFormat::Ia4 => (8, 4, 1),
+            TextureFormat::Ia8 | TextureFormat::Rgb565 | TextureFormat::Rgb5a3 => (4, 4, 1),
+            // The alpha and red tiles, followed by the green and blue.
+            TextureFormat::Rgba8 => (4, 4, 2),
+        }
+    }
+
+    /// The bytes of a texture of `width` by `height` pixels, in whole tiles.
+    pub fn size(self, width: usize, height: usize) -> usize {
+        let (tile_width, tile_height, planes) = self.tile();
+        width.div_ceil(tile_width) * height.div_ceil(tile_height) * planes * 32
+    }
+
+    /// The bytes of a texture of `width` by `height` pixels with `levels` levels of
+    /// mipmaps, each level following the one twice its size.
+    pub fn mipmap_size(self, width: usize, height: usize, levels: usize) -> usize {
+        (0..levels)
+            .map(|level| self.size((width >> level).max(1), (height >> level).max(1)))
+            .sum()
+    }
+
+    fn from_tpl(format: u32) -> Result<Self, TplError> {
+        Ok(match format {
+            0 => TextureFormat::I4,
+            1 => TextureFormat::I8,
+            2 => TextureFormat::Ia4,
+            3 => TextureFormat::Ia8,
+            4 => TextureFormat::Rgb565,
+            5 => TextureFormat::Rgb5a3,
+            6 => TextureFormat::Rgba8,
+            0xe => TextureFormat::Cmpr,
+            format => return Err(TplError::UnsupportedFormat(format)),
+        })
+    }
+}
+
+/// How texture coordinates past 0 to 1 sample the texture.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum WrapMode {
+    /// The edge is repeated.
+    #[default]
+    Clamp = 0,
+    Repeat = 1,
+    Mirror = 2,
+}
+
+impl WrapMode {
+    fn from_tpl(wrap: u32) -> Self {
+        match wrap {
+            1 => WrapMode::Repeat,
+            2 => WrapMode::Mirror,
+            _ => WrapMode::Clamp,
+        }
+    }
+}
+
+/// How texels are sampled where a pixel covers more than one, between mipmap levels too.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum MinFilter {
+    Near = 0,
+    #[default]
+    Linear = 4,
+    NearMipNear = 1,
+    LinearMipNear = 5,
+    NearMipLinear = 2,
+    LinearMipLinear = 6,
+}
+
+impl MinFilter {
+    fn from_tpl(filter: u32) -> Self {
+        match filter {
+            0 => MinFilter::Near,
+            2 => MinFilter::NearMipNear,
+            3 => MinFilter::LinearMipNear,
+            4 => MinFilter::NearMipLinear,
+            5 => MinFilter::LinearMipLinear,
+            _ => MinFilter::Linear,
+        }
+    }
+}
+
+/// How texels are sampled where they cover more than a pixel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum MagFilter {
+    Near = 0,
+    #[default]
+    Linear = 1,
+}
+
+/// Texture data and how it is sampled, loaded with [`GxContext::load_texture`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Texture {
+    data: &'static [u8],
+    width: u16,
+    height: u16,
+    format: TextureFormat,
+    levels: u8,
+    wrap: [WrapMode; 2],
+    min_filter: MinFilter,
+    mag_filter: MagFilter,
+    min_lod: f32,
+    max_lod: f32,
+    lod_bias: f32,
+}
+
+impl Texture {
+    /// A texture of `width` by `height` pixels in `format`, clamped and sampled linearly,
+    /// writing its data back from the data cache for the graphics processor. Data the
+    /// processor writes later is written back before the texture is loaded again.
+    ///
+    /// Panics if `data` is not aligned, too small, or the size is past 1024 pixels.
+    pub fn new(data: &'static [u8], width: u16, height: u16, format: TextureFormat) -> Self {
+        assert!(
+            (data.as_ptr() as usize).is_multiple_of(32),
+            "textures are 32-byte aligned"
+        );
+        assert!(
+            (1..=1024).contains(&width) && (1..=1024).contains(&height),
+            "textures are 1 to 1024 pixels wide and high"
+        );
+        assert!(
+            data.len() >= format.size(width as usize, height as usize),
+            "the texture data is too small"
+        );
+        cache::store_data_range(data.as_ptr(), data.len());
+        Self {
+            data,
+            width,
+            height,
+            format,
+            levels: 1,
+            wrap: [WrapMode::Clamp; 2],
+            min_filter: MinFilter::Linear,
+            mag_filter: MagFilter::Linear,
+            min_lod: 0.0,
+            max_lod: 0.0,
+            lod_bias: 0.0,
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    #[inline]
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    /// The levels of its mipmaps, the texture itself included.
+    #[inline]
+    pub fn levels(&self) -> u8 {
+        self.levels
+    }
+
+    #[inline]
+    pub fn with_wrap(mut self, s: WrapMode, t: WrapMode) -> Self {
+        self.wrap = [s, t];
+        self
+    }
+
+    #[inline]
+    pub fn with_filter(mut self, min: MinFilter, mag: MagFilter) -> Self {
+        self.min_filter = min;
+        self.mag_filter = mag;
+        self
+    }
+
+    /// Samples `levels` levels of mipmaps following the texture in its data, all of
+    /// them, sampled between the nearest two.
+    ///
+    /// Panics if the data is too small for them, or there are more levels than halvings
+    /// of the texture to a pixel.
+    pub fn with_mipmaps(mut self, levels: u8) -> Self {
+        let largest = self.width.max(self.height);
+        assert!(
+            levels >= 1 && levels as u32 <= largest.ilog2() + 1,
+            "there are up to as many levels as halvings of the texture to a pixel"
+        );
+        assert!(
+            self.data.len()
+                >= self.format.mipmap_size(
+                    self.width as usize,
+                    self.height as usize,
+                    levels as usize
+                ),
+            "the texture data is too small for its mipmaps"
+        );
+        self.levels = levels;
+        self.min_filter = MinFilter::LinearMipLinear;
+        self.max_lod = (levels - 1) as f32;
+        self
+    }
+
+    /// Limits the mipmap levels sampled to `min` to `max`, after biasing them by `bias`.
+    #[inline]
+    pub fn with_lod(mut self, min: f32, max: f32, bias: f32) -> Self {
+        self.min_lod = min;
+        self.max_lod = max;
+        self.lod_bias = bias;
+        self
+    }
+}
+
+/// The tiles of the texture cache each texture map caches its texture in, at
+/// libogc's: 32 KB of even and odd tiles for each.
+fn cache_region(map: u8) -> (u32, u32) {
+    let even = map as u32 * 0x10000;
+    let size = 3 << 15 | 3 << 18;
+    (even >> 5 | size, (even + 0x8000) >> 5 | size)
+}
+
+/// The register of the texture map `map` from the one of the first, the last four maps'
+/// following a gap.
+fn map_register(first: u8, map: u8) -> u8 {
+    first + (map & 3) + if map < 4 { 0 } else { 0x20 }
+}
+
+impl GxContext {
+    /// Loads `texture` into the texture map `map`, for the TEV stages sampling it.
+    ///
+    /// Panics if `map` is past 7.
+    pub fn load_texture(self, map: u8, texture: &Texture) {
+        assert!(map < 8, "there are eight texture maps");
+        // Levels in fixed point, with four fractional bits, the bias with five.
+        fn lod(lod: f32) -> u32 {
+            (lod * 16.0).clamp(0.0, 10.0 * 16.0) as u32
+        }
+        let bias = (texture.lod_bias * 32.0).clamp(-128.0, 127.0) as i8 as u8 as u32;
+        let [wrap_s, wrap_t] = texture.wrap;
+        // Diagonal LOD, as edge LOD is off, and the bias clamped.
+        let mode0 = wrap_s as u32
+            | (wrap_t as u32) << 2
+            | (texture.mag_filter as u32) << 4
+            | (texture.min_filter as u32) << 5
+            | 1 << 8
+            | bias << 9
+            | 1 << 21;
+        fifo::load_bp(map_register(0x80, map), mode0);
+        fifo::load_bp(
+            map_register(0x84, map),
+            lod(texture.min_lod) | lod(texture.max_lod) << 8,
+        );
+        let image0 = (texture.width as u32 - 1)
+            | (texture.height as u32 - 1) << 10
+            | (texture.format as u32) << 20;
+        fifo::load_bp(map_register(0x88, map), image0);
+        let (even, odd) = cache_region(map);
+        fifo::load_bp(map_register(0x8c, map), even);
+        fifo::load_bp(map_register(0x90, map), odd);
+        fifo::load_bp(
+            map_register(0x94, map),
+            (texture.data.as_ptr() as u32 & 0x3fff_ffff) >> 5,
+        );
+
+        let mut state = STATE.lock();
+        state.texture_sizes[map as usize] = [texture.width, texture.height];
+        state.tex_sizes_dirty = true;
+    }
+
+    /// Sets what the texture coordinates of the next draws are generated from, the
+    /// first coordinates' and on.
+    ///
+    /// Panics if there are more than eight.
+    pub fn set_tex_coord_gens(self, sources: &[TexGenSource]) {
+        assert!(sources.len() <= 8, "there are eight texture coordinates");
+        for (index, source) in sources.iter().enumerate() {
+            fifo::load_xf(0x1040 + index as u16, &[source.register()]);
+        }
+        fifo::load_xf(0x103f, &[sources.len() as u32]);
+        let mut state = STATE.lock();
+        state.tex_gens = sources.len() as u8;
+        state.load_gen_mode();
+    }
+
+    /// Drops what the texture cache holds, for textures whose data changed since they
+    /// were last sampled.
+    pub fn invalidate_textures(self) {
+        fifo::load_bp(0x66, 0x1000);
+        fifo::load_bp(0x66, 0x1100);
+    }
+}
+
+/// What texture coordinates are generated from, by the identity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TexGenSource {
+    Position,
+    Normal,
+    /// The texture coordinates of the vertices.
+    TexCoord(u8),
+}
+
+impl TexGenSource {
+    /// The generation's register in the transform unit, from the row of its input.
+    fn register(self) -> u32 {
+        // Inputs of three components other than texture coordinates.
+        let abc1 = 1 << 2;
+        match self {
+            TexGenSource::Position => abc1,
+            TexGenSource::Normal => abc1 | 1 << 7,
+            TexGenSource::TexCoord(index) => {
+                assert!(index < 8, "there are eight texture coordinates");
+                (5 + index as u32) << 7
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TplError {
+    /// The file doesn't start with TPL's magic.
+    BadMagic,
+    /// An offset or size is past the end of the file.
+    Truncated,
+    /// A texture is in a format there is no support for, such as a paletted one.
+    UnsupportedFormat(u32),
+    /// Texture data is not 32-byte aligned, say as the file is not.
+    Misaligned,
+    /// A texture is empty or past 1024 pixels.
+    BadSize,
+    /// The texture index is past the textures of the file.
+    NoSuchTexture,
+}
+
+/// A TPL file, of textures and how they are sampled.
+#[derive(Clone, Copy, Debug)]
+pub struct Tpl {
+    data: &'static [u8],
+    textures: usize,
+    table: usize,
+}
+
+const TPL_MAGIC: u32 = 0x0020_af30;
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, TplError> {
+    let bytes = data.get(offset..offset + 4).ok_or(TplError::Truncated)?;
+    Ok(u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+impl Tpl {
+    /// Parses the header of the TPL file `data`, which is 32-byte aligned for its
+    /// textures to be.
+    pub fn parse(data: &'static [u8]) -> Result<Self, TplError> {
+        if read_u32(data, 0)? != TPL_MAGIC {
+            return Err(TplError::BadMagic);
+        }
+        let textures = read_u32(data, 4)? as usize;
+        let table = read_u32(data, 8)? as usize;
+        if table + textures * 8 > data.len() {
+            return Err(TplError::Truncated);
+        }
+        Ok(Self {
+            data,
+            textures,
+            table,
+        })
+    }
+
+    /// The textures of the file.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.textures
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.textures == 0
+    }
+
+    /// The texture `index` of the file, sampled as the file says.
+    pub fn texture(&self, index: usize) -> Result<Texture, TplError> {
+        if index >= self.textures {
+            return Err(TplError::NoSuchTexture);
+        }
+        let data = self.data;
+        let header = read_u32(data, self.table + index * 8)? as usize;
+        let size = read_u32(data, header)?;
+        let (height, width) = ((size >> 16) as u16, size as u16);
+        let format = TextureFormat::from_tpl(read_u32(data, header + 4)?)?;
+        let offset = read_u32(data, header + 8)? as usize;
+        let wrap_s = WrapMode::from_tpl(read_u32(data, header + 0xc)?);
+        let wrap_t = WrapMode::from_tpl(read_u32(data, header + 0x10)?);
+        let min_filter = MinFilter::from_tpl(read_u32(data, header + 0x14)?);
+        let mag_filter = match read_u32(data, header + 0x18)? {
+            0 => MagFilter::Near,
+            _ => MagFilter::Linear,
+        };
+        let lod_bias = f32::from_bits(read_u32(data, header + 0x1c)?);
+        let lods = data
+            .get(header + 0x21..header + 0x23)
+            .ok_or(TplError::Truncated)?;
+        let (min_lod, max_lod) = (lods[0], lods[1]);
+
+        if !(1..=1024).contains(&width) || !(1..=1024).contains(&height) {
+            return Err(TplError::BadSize);
+        }
+        let levels = max_lod as usize + 1;
+        if levels > width.max(height).ilog2() as usize + 1 {
+            return Err(TplError::BadSize);
+        }
+        let len = format.mipmap_size(width as usize, height as usize, levels);
+        let image = data.get(offset..offset + len).ok_or(TplError::Truncated)?;
+        if !(image.as_ptr() as usize).is_multiple_of(32) {
+            return Err(TplError::Misaligned);
+        }
+
+        let mut texture = Texture::new(image, width, height, format);
+        if levels > 1 {
+            texture = texture.with_mipmaps(levels as u8);
+        }
+        Ok(texture
+            .with_wrap(wrap_s, wrap_t)
+            .with_filter(min_filter, mag_filter)
+            .with_lod(min_lod as f32, max_lod as f32, lod_bias))
+    }
+}
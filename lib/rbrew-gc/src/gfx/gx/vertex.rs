@@ -0,0 +1,494 @@
+//! The attributes of vertices, and how they are laid out in draws.
+//!
+//! A [`VertexDescriptor`] tells the command processor which attributes a vertex has and
+//! whether they are sent directly or as indices into arrays. A [`VertexFormat`], one of
+//! eight selected by each draw, tells it how the components of each attribute are encoded.
+
+/// How an attribute of a vertex is sent in draws.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum AttributeType {
+    /// The vertices don't have the attribute.
+    #[default]
+    None = 0,
+    /// The components are sent in the draw.
+    Direct = 1,
+    /// An 8-bit index into the attribute's array.
+    Index8 = 2,
+    /// A 16-bit index into the attribute's array.
+    Index16 = 3,
+}
+
+impl AttributeType {
+    /// The bytes of the attribute in a vertex, given the bytes of its components.
+    fn size(self, direct: usize) -> usize {
+        match self {
+            AttributeType::None => 0,
+            AttributeType::Direct => direct,
+            AttributeType::Index8 => 1,
+            AttributeType::Index16 => 2,
+        }
+    }
+}
+
+/// The attributes of the vertices of draws, set with
+/// [`GxContext::set_vertex_descriptor`](super::GxContext::set_vertex_descriptor).
+///
+/// Attributes are sent in the order of the fields. Vertices need a position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexDescriptor {
+    /// An 8-bit index of the matrix positions and normals are transformed by, rather than
+    /// the current one.
+    pub position_matrix_index: bool,
+    /// 8-bit indices of the matrices texture coordinates are generated with.
+    pub tex_matrix_indices: [bool; 8],
+    pub position: AttributeType,
+    pub normal: AttributeType,
+    pub colors: [AttributeType; 2],
+    pub tex_coords: [AttributeType; 8],
+}
+
+impl Default for VertexDescriptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VertexDescriptor {
+    /// Vertices of a direct position and nothing else.
+    pub const fn new() -> Self {
+        Self {
+            position_matrix_index: false,
+            tex_matrix_indices: [false; 8],
+            position: AttributeType::Direct,
+            normal: AttributeType::None,
+            colors: [AttributeType::None; 2],
+            tex_coords: [AttributeType::None; 8],
+        }
+    }
+
+    #[inline]
+    pub const fn with_position(mut self, position: AttributeType) -> Self {
+        self.position = position;
+        self
+    }
+
+    #[inline]
+    pub const fn with_normal(mut self, normal: AttributeType) -> Self {
+        self.normal = normal;
+        self
+    }
+
+    #[inline]
+    pub const fn with_color(mut self, channel: usize, color: AttributeType) -> Self {
+        self.colors[channel] = color;
+        self
+    }
+
+    #[inline]
+    pub const fn with_tex_coord(mut self, index: usize, tex_coord: AttributeType) -> Self {
+        self.tex_coords[index] = tex_coord;
+        self
+    }
+
+    /// The low and high registers of the descriptor, in the command processor.
+    pub(super) fn registers(&self) -> (u32, u32) {
+        let mut low = self.position_matrix_index as u32;
+        for (index, &present) in self.tex_matrix_indices.iter().enumerate() {
+            low |= (present as u32) << (1 + index);
+        }
+        low |= (self.position as u32) << 9
+            | (self.normal as u32) << 11
+            | (self.colors[0] as u32) << 13
+            | (self.colors[1] as u32) << 15;
+        let high = self
+            .tex_coords
+            .iter()
+            .enumerate()
+            .fold(0, |high, (index, &ty)| high | (ty as u32) << (2 * index));
+        (low, high)
+    }
+
+    /// The bytes of a vertex encoded in `format`.
+    pub(super) fn vertex_size(&self, format: &VertexFormat) -> usize {
+        let matrix_indices = self.position_matrix_index as usize
+            + self
+                .tex_matrix_indices
+                .iter()
+                .filter(|&&present| present)
+                .count();
+        let position = self.position.size(format.position.size());
+        let normal = match (self.normal, format.normal.components) {
+            // The normal, binormal and tangent can each be indexed.
+            (
+                AttributeType::Index8 | AttributeType::Index16,
+                NormalComponents::NormalBinormalTangent,
+            ) if format.normal.index3 => 3 * self.normal.size(0),
+            _ => self.normal.size(format.normal.size()),
+        };
+        let colors: usize = (self.colors.iter().zip(&format.colors))
+            .map(|(ty, color)| ty.size(color.size()))
+            .sum();
+        let tex_coords: usize = (self.tex_coords.iter().zip(&format.tex_coords))
+            .map(|(ty, tex_coord)| ty.size(tex_coord.size()))
+            .sum();
+        matrix_indices + position + normal + colors + tex_coords
+    }
+
+    /// The colors, normals and texture coordinates of the vertices, as the transform unit
+    /// expects them.
+    pub(super) fn xf_spec(&self, format: &VertexFormat) -> u32 {
+        let present = |ty: &&AttributeType| **ty != AttributeType::None;
+        let colors = self.colors.iter().filter(present).count() as u32;
+        let normals = match (self.normal, format.normal.components) {
+            (AttributeType::None, _) => 0,
+            (_, NormalComponents::Normal) => 1,
+            (_, NormalComponents::NormalBinormalTangent) => 2,
+        };
+        let tex_coords = self.tex_coords.iter().filter(present).count() as u32;
+        colors | normals << 2 | tex_coords << 4
+    }
+}
+
+/// The encoding of the components of positions, normals and texture coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ComponentType {
+    U8 = 0,
+    I8 = 1,
+    U16 = 2,
+    I16 = 3,
+    F32 = 4,
+}
+
+impl ComponentType {
+    fn size(self) -> usize {
+        match self {
+            ComponentType::U8 | ComponentType::I8 => 1,
+            ComponentType::U16 | ComponentType::I16 => 2,
+            ComponentType::F32 => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PositionComponents {
+    Xy = 0,
+    Xyz = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum NormalComponents {
+    Normal = 0,
+    /// A normal, a binormal and a tangent, for bump mapping.
+    NormalBinormalTangent = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TexCoordComponents {
+    S = 0,
+    St = 1,
+}
+
+/// The encoding of colors, those without alpha having it opaque.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ColorFormat {
+    Rgb565 = 0,
+    Rgb8 = 1,
+    /// An RGB8 color padded to 32 bits.
+    Rgbx8 = 2,
+    Rgba4 = 3,
+    Rgba6 = 4,
+    Rgba8 = 5,
+}
+
+impl ColorFormat {
+    fn size(self) -> usize {
+        match self {
+            ColorFormat::Rgb565 | ColorFormat::Rgba4 => 2,
+            ColorFormat::Rgb8 | ColorFormat::Rgba6 => 3,
+            ColorFormat::Rgbx8 | ColorFormat::Rgba8 => 4,
+        }
+    }
+
+    fn has_alpha(self) -> bool {
+        matches!(
+            self,
+            ColorFormat::Rgba4 | ColorFormat::Rgba6 | ColorFormat::Rgba8
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PositionFormat {
+    pub components: PositionComponents,
+    pub ty: ComponentType,
+    /// The fractional bits of integer components.
+    pub frac: u8,
+}
+
+impl PositionFormat {
+    fn size(&self) -> usize {
+        let components = match self.components {
+            PositionComponents::Xy => 2,
+            PositionComponents::Xyz => 3,
+        };
+        components * self.ty.size()
+    }
+}
+
+/// The encoding of normals, whose integer components have 6 fractional bits if they are
+/// 8-bit and 14 if they are 16-bit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NormalFormat {
+    pub components: NormalComponents,
+    /// One of [`ComponentType::I8`], [`ComponentType::I16`] or [`ComponentType::F32`].
+    pub ty: ComponentType,
+    /// Whether the binormal and tangent of indexed normals have indices of their own,
+    /// rather than following the normal in its array.
+    pub index3: bool,
+}
+
+impl NormalFormat {
+    fn size(&self) -> usize {
+        let components = match self.components {
+            NormalComponents::Normal => 3,
+            NormalComponents::NormalBinormalTangent => 9,
+        };
+        components * self.ty.size()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TexCoordFormat {
+    pub components: TexCoordComponents,
+    pub ty: ComponentType,
+    /// The fractional bits of integer components.
+    pub frac: u8,
+}
+
+impl TexCoordFormat {
+    fn size(&self) -> usize {
+        let components = match self.components {
+            TexCoordComponents::S => 1,
+            TexCoordComponents::St => 2,
+        };
+        components * self.ty.size()
+    }
+
+    /// The 9 bits of the format in the attribute tables.
+    fn bits(&self) -> u32 {
+        self.components as u32 | (self.ty as u32) << 1 | (self.frac as u32) << 4
+    }
+}
+
+/// The encoding of the attributes in the draws using the format, set with
+/// [`GxContext::set_vertex_format`](super::GxContext::set_vertex_format).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexFormat {
+    pub position: PositionFormat,
+    pub normal: NormalFormat,
+    pub colors: [ColorFormat; 2],
+    pub tex_coords: [TexCoordFormat; 8],
+}
+
+impl Default for VertexFormat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VertexFormat {
+    /// Every attribute in 32-bit floats, with three dimensional positions and RGBA8 colors.
+    pub const fn new() -> Self {
+        Self {
+            position: PositionFormat {
+                components: PositionComponents::Xyz,
+                ty: ComponentType::F32,
+                frac: 0,
+            },
+            normal: NormalFormat {
+                components: NormalComponents::Normal,
+                ty: ComponentType::F32,
+                index3: false,
+            },
+            colors: [ColorFormat::Rgba8; 2],
+            tex_coords: [TexCoordFormat {
+                components: TexCoordComponents::St,
+                ty: ComponentType::F32,
+                frac: 0,
+            }; 8],
+        }
+    }
+
+    #[inline]
+    pub const fn with_position(
+        mut self,
+        components: PositionComponents,
+        ty: ComponentType,
+        frac: u8,
+    ) -> Self {
+        self.position = PositionFormat {
+            components,
+            ty,
+            frac,
+        };
+        self
+    }
+
+    #[inline]
+    pub const fn with_normal(mut self, components: NormalComponents, ty: ComponentType) -> Self {
+        self.normal.components = components;
+        self.normal.ty = ty;
+        self
+    }
+
+    #[inline]
+    pub const fn with_color(mut self, channel: usize, format: ColorFormat) -> Self {
+        self.colors[channel] = format;
+        self
+    }
+
+    #[inline]
+    pub const fn with_tex_coord(
+        mut self,
+        index: usize,
+        components: TexCoordComponents,
+        ty: ComponentType,
+        frac: u8,
+    ) -> Self {
+        self.tex_coords[index] = TexCoordFormat {
+            components,
+            ty,
+            frac,
+        };
+        self
+    }
+
+    /// The format's three registers of the attribute tables, in the command processor.
+    pub(super) fn registers(&self) -> [u32; 3] {
+        let position = &self.position;
+        let normal = &self.normal;
+        let [color0, color1] = self.colors;
+        let tex = self.tex_coords.map(|tex_coord| tex_coord.bits());
+        let a = position.components as u32
+            | (position.ty as u32) << 1
+            | (position.frac as u32) << 4
+            | (normal.components as u32) << 9
+            | (normal.ty as u32) << 10
+            | (color0.has_alpha() as u32) << 13
+            | (color0 as u32) << 14
+            | (color1.has_alpha() as u32) << 17
+            | (color1 as u32) << 18
+            | tex[0] << 21
+            // Dequantizes 8-bit components by their fractional bits, like the others.
+            | 1 << 30
+            | (normal.index3 as u32) << 31;
+        // The fifth coordinate's format spans B and C. The vertex cache enhancement of B's
+        // top bit is always set.
+        let b = tex[1] | tex[2] << 9 | tex[3] << 18 | (tex[4] & 0xf) << 27 | 1 << 31;
+        let c = tex[4] >> 4 | tex[5] << 5 | tex[6] << 14 | tex[7] << 23;
+        [a, b, c]
+    }
+}
+
+/// The eight vertex formats, selected by each draw.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum VertexFormatIndex {
+    #[default]
+    Format0 = 0,
+    Format1 = 1,
+    Format2 = 2,
+    Format3 = 3,
+    Format4 = 4,
+    Format5 = 5,
+    Format6 = 6,
+    Format7 = 7,
+}
+
+/// The primitives vertices are drawn as, by their command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Primitive {
+    Quads = 0x80,
+    Triangles = 0x90,
+    TriangleStrip = 0x98,
+    TriangleFan = 0xa0,
+    Lines = 0xa8,
+    LineStrip = 0xb0,
+    Points = 0xb8,
+}
+
+/// The attributes indexed into arrays, set with
+/// [`GxContext::set_array`](super::GxContext::set_array).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArrayAttribute {
+    Position,
+    Normal,
+    Color(u8),
+    TexCoord(u8),
+}
+
+impl ArrayAttribute {
+    /// The array's register in the command processor, after the attribute tables.
+    pub(super) fn index(self) -> u8 {
+        match self {
+            ArrayAttribute::Position => 0,
+            ArrayAttribute::Normal => 1,
+            ArrayAttribute::Color(channel) => {
+                assert!(channel < 2, "there are two color channels");
+                2 + channel
+            }
+            ArrayAttribute::TexCoord(index) => {
+                assert!(index < 8, "there are eight texture coordinates");
+                4 + index
+            }
+        }
+    }
+}
+
+/// A component of a vertex written to a draw, as its bits.
+pub trait Component: Copy {
+    #[doc(hidden)]
+    fn write(self);
+    #[doc(hidden)]
+    const SIZE: usize;
+}
+
+macro_rules! impl_component {
+    ($($ty:ty => $write:ident as $as:ty),* $(,)?) => {
+        $(
+            impl Component for $ty {
+                #[inline]
+                fn write(self) {
+                    super::fifo::$write(self as $as);
+                }
+                const SIZE: usize = size_of::<$ty>();
+            }
+        )*
+    };
+}
+
+impl_component! {
+    u8 => write_u8 as u8,
+    i8 => write_u8 as u8,
+    u16 => write_u16 as u16,
+    i16 => write_u16 as u16,
+    u32 => write_u32 as u32,
+    f32 => write_f32 as f32,
+}
+
+impl<T: Component, const N: usize> Component for [T; N] {
+    #[inline]
+    fn write(self) {
+        for component in self {
+            component.write();
+        }
+    }
+    const SIZE: usize = N * T::SIZE;
+}
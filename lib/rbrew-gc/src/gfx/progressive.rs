@@ -0,0 +1,123 @@
+//! The prompt programs show when the component cable is attached, to display in
+//! progressive scan.
+//!
+//! The answer comes from the program's input, polled once a frame:
+//!
+//! ```ignore
+//! use rbrew_gc::gfx::progressive::Prompt;
+//!
+//! let mode = Prompt::new().run(|| {
+//!     // Some(true) for yes, Some(false) for no, None while unanswered.
+//!     None
+//! })?;
+//! ```
+
+use super::{
+    console,
+    video::{self, VideoContext, VideoInitError, VideoMode},
+};
+use crate::{print, rtc::SystemSettings};
+
+/// The question to display in progressive scan, and how long the program waits for an
+/// answer.
+#[derive(Clone, Copy, Debug)]
+pub struct Prompt {
+    message: &'static str,
+    timeout: Option<u32>,
+    forced: bool,
+}
+
+impl Prompt {
+    /// Asks in English, waiting 10 seconds.
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            message: "Display in progressive scan mode?\n\n  A: Yes    B: No",
+            timeout: Some(10 * 60),
+            forced: false,
+        }
+    }
+
+    /// Asks `message` instead, which should name the buttons answering it.
+    #[inline]
+    pub const fn with_message(self, message: &'static str) -> Self {
+        Self { message, ..self }
+    }
+
+    /// Waits `retraces` for an answer, or as long as it takes if it is `None`. The
+    /// display may not show progressive scan, so no answer is a no.
+    #[inline]
+    pub const fn with_timeout(self, retraces: Option<u32>) -> Self {
+        Self {
+            timeout: retraces,
+            ..self
+        }
+    }
+
+    /// Asks even when progressive scan is disabled in the console's settings, as programs
+    /// do when a button is held at boot.
+    #[inline]
+    pub const fn with_forced(self, forced: bool) -> Self {
+        Self { forced, ..self }
+    }
+
+    /// Initializes video in the mode the console is set up for, asking whether to display
+    /// in progressive scan when the component cable is attached and progressive scan is
+    /// enabled, or the prompt is forced. The question is displayed in progressive scan
+    /// through the global console, which is cleared after, and the answer is saved to the
+    /// console's settings.
+    ///
+    /// `answer` is polled once a retrace, `Some(true)` being a yes and `Some(false)` a no.
+    /// Returns the mode the VI is left in.
+    pub fn run(
+        self,
+        mut answer: impl FnMut() -> Option<bool>,
+    ) -> Result<VideoMode, VideoInitError> {
+        let detected = VideoMode::detect();
+        let progressive = detected.is_progressive();
+        // Detected before the VI displays in progressive scan, which is in NTSC.
+        let interlaced = VideoMode::detect_interlaced();
+        if !video::has_component_cable() || !(progressive || self.forced) {
+            VideoContext::init(detected)?;
+            return Ok(detected);
+        }
+        VideoContext::init(VideoMode::Ntsc480p)?;
+        let video = VideoContext::global();
+
+        console::with_global(|console| console.clear());
+        print!("{}", self.message);
+        let start = video.retrace_count();
+        let accepted = loop {
+            if let Some(accepted) = answer() {
+                break accepted;
+            }
+            if self
+                .timeout
+                .is_some_and(|timeout| video.retrace_count().wrapping_sub(start) >= timeout)
+            {
+                break false;
+            }
+            video.wait_for_retrace();
+        };
+        console::with_global(|console| console.clear());
+        print!("");
+
+        if !accepted {
+            video.set_mode(interlaced);
+        }
+        if accepted != progressive {
+            if let Some(mut settings) = SystemSettings::read() {
+                settings.set_progressive(accepted);
+                settings.write();
+            }
+        }
+        Ok(video.mode())
+    }
+}
+
+impl Default for Prompt {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
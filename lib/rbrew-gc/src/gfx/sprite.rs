@@ -0,0 +1,421 @@
+//! A 2D layer on GX: sprites from regions of texture atlases, and text from font atlases,
+//! in pixels from the top left of the 640x480 embedded framebuffer.
+//!
+//! ```ignore
+//! use rbrew_gc::gfx::sprite::{Atlas, Font, Renderer, Sprite};
+//!
+//! let atlas = Atlas::new(tpl.texture(0).unwrap());
+//! let ship = Sprite::new(atlas.region(0, 0, 32, 32)).with_position(320.0, 240.0);
+//! loop {
+//!     let mut renderer = Renderer::begin(gx);
+//!     renderer.draw(&atlas, &ship.with_rotation(angle));
+//!     renderer.draw_text(Font::builtin(), "Score: 100", 8.0, 8.0, 2.0, Rgb::WHITE);
+//!     gx.present(framebuffer);
+//! }
+//! ```
+//!
+//! [`Renderer::begin`] sets the GX state it draws with, which is set again before drawing
+//! in 3D after it.
+
+use super::{
+    color::Rgb,
+    console,
+    gx::{
+        matrix::{Mtx34, Mtx44},
+        pixel::BlendMode,
+        tev::TevStage,
+        texture::{Aligned, TexGenSource, Texture, TextureFormat},
+        vertex::{
+            AttributeType, ColorFormat, ComponentType, PositionComponents, Primitive,
+            TexCoordComponents, VertexDescriptor, VertexFormat, VertexFormatIndex,
+        },
+        Draw, GxContext,
+    },
+};
+use spin::Once;
+
+/// The width and height drawn to.
+const WIDTH: f32 = 640.0;
+const HEIGHT: f32 = 480.0;
+/// The vertex format sprites are drawn in, the last one.
+const FORMAT: VertexFormatIndex = VertexFormatIndex::Format7;
+/// The quads of a draw, within its 16-bit count of vertices.
+const MAX_QUADS: usize = u16::MAX as usize / 4;
+
+/// A rectangle of an atlas, in pixels.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Region {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// A texture sprites are regions of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Atlas {
+    texture: Texture,
+}
+
+impl Atlas {
+    #[inline]
+    pub fn new(texture: Texture) -> Self {
+        Self { texture }
+    }
+
+    #[inline]
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// The region from `x`, `y` of `width` by `height` pixels.
+    ///
+    /// Panics if it is past the atlas.
+    pub fn region(&self, x: u16, y: u16, width: u16, height: u16) -> Region {
+        assert!(
+            x as u32 + width as u32 <= self.texture.width() as u32
+                && y as u32 + height as u32 <= self.texture.height() as u32,
+            "the region is past the atlas"
+        );
+        Region {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// The texture coordinates of the left, top, right and bottom of `region`.
+    fn tex_coords(&self, region: &Region) -> [f32; 4] {
+        let (width, height) = (self.texture.width() as f32, self.texture.height() as f32);
+        [
+            region.x as f32 / width,
+            region.y as f32 / height,
+            (region.x + region.width) as f32 / width,
+            (region.y + region.height) as f32 / height,
+        ]
+    }
+}
+
+/// A region of an atlas drawn at a position, scaled, rotated and tinted.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sprite {
+    pub region: Region,
+    /// The position of the origin, in pixels.
+    pub x: f32,
+    pub y: f32,
+    /// The point of the region at the position, which it is scaled and rotated about, from
+    /// 0 at its top left to 1 at its bottom right.
+    pub origin: [f32; 2],
+    pub scale: [f32; 2],
+    /// The rotation in radians, clockwise as y goes down.
+    pub rotation: f32,
+    /// The color the texture is multiplied by.
+    pub tint: Rgb,
+    pub alpha: u8,
+}
+
+impl Sprite {
+    /// The region at the top left, of its size, untinted, its origin its top left.
+    pub const fn new(region: Region) -> Self {
+        Self {
+            region,
+            x: 0.0,
+            y: 0.0,
+            origin: [0.0; 2],
+            scale: [1.0; 2],
+            rotation: 0.0,
+            tint: Rgb::WHITE,
+            alpha: 0xff,
+        }
+    }
+
+    #[inline]
+    pub const fn with_position(mut self, x: f32, y: f32) -> Self {
+        self.x = x;
+        self.y = y;
+        self
+    }
+
+    #[inline]
+    pub const fn with_origin(mut self, x: f32, y: f32) -> Self {
+        self.origin = [x, y];
+        self
+    }
+
+    /// Sets the origin to the center, to rotate about it.
+    #[inline]
+    pub const fn centered(self) -> Self {
+        self.with_origin(0.5, 0.5)
+    }
+
+    #[inline]
+    pub const fn with_scale(mut self, x: f32, y: f32) -> Self {
+        self.scale = [x, y];
+        self
+    }
+
+    #[inline]
+    pub const fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    #[inline]
+    pub const fn with_tint(mut self, tint: Rgb, alpha: u8) -> Self {
+        self.tint = tint;
+        self.alpha = alpha;
+        self
+    }
+
+    /// The corners of the sprite from its top left, clockwise.
+    fn corners(&self) -> [[f32; 2]; 4] {
+        let width = self.region.width as f32 * self.scale[0];
+        let height = self.region.height as f32 * self.scale[1];
+        let (left, top) = (-self.origin[0] * width, -self.origin[1] * height);
+        let (right, bottom) = (left + width, top + height);
+        let (sin, cos) = if self.rotation == 0.0 {
+            (0.0, 1.0)
+        } else {
+            libm::sincosf(self.rotation)
+        };
+        [(left, top), (right, top), (right, bottom), (left, bottom)]
+            .map(|(x, y)| [self.x + x * cos - y * sin, self.y + x * sin + y * cos])
+    }
+}
+
+/// Glyphs in a grid of an atlas, left to right and top to bottom from the first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Font {
+    atlas: Atlas,
+    first: char,
+    glyph_width: u16,
+    glyph_height: u16,
+}
+
+/// The console's font in a 128x48 IA4 atlas of 8x8 glyphs, white where they are drawn.
+static BUILTIN_DATA: Aligned<[u8; BUILTIN_SIZE]> = Aligned(builtin_data());
+const BUILTIN_WIDTH: usize = 128;
+const BUILTIN_HEIGHT: usize = 48;
+const BUILTIN_SIZE: usize = BUILTIN_WIDTH * BUILTIN_HEIGHT;
+
+/// Tiles the console's glyphs, sixteen to a row, into 8x4 tiles of IA4 texels.
+const fn builtin_data() -> [u8; BUILTIN_SIZE] {
+    let mut data = [0; BUILTIN_SIZE];
+    let mut glyph = 0;
+    while glyph < console::font::GLYPHS.len() {
+        let mut line = 0;
+        while line < 8 {
+            let bits = console::font::GLYPHS[glyph][line];
+            let mut column = 0;
+            while column < 8 {
+                if bits >> (7 - column) & 1 != 0 {
+                    let x = glyph % 16 * 8 + column;
+                    let y = glyph / 16 * 8 + line;
+                    let tile = y / 4 * (BUILTIN_WIDTH / 8) + x / 8;
+                    data[tile * 32 + y % 4 * 8 + x % 8] = 0xff;
+                }
+                column += 1;
+            }
+            line += 1;
+        }
+        glyph += 1;
+    }
+    data
+}
+
+impl Font {
+    /// Glyphs of `glyph_width` by `glyph_height` pixels from `first`, as many as fit in
+    /// `atlas`.
+    pub fn new(atlas: Atlas, glyph_width: u16, glyph_height: u16, first: char) -> Self {
+        assert!(
+            glyph_width > 0 && glyph_height > 0,
+            "glyphs are at least a pixel"
+        );
+        Self {
+            atlas,
+            first,
+            glyph_width,
+            glyph_height,
+        }
+    }
+
+    /// The console's font, of 8x8 glyphs from `' '` to `'~'`.
+    pub fn builtin() -> &'static Font {
+        static BUILTIN: Once<Font> = Once::new();
+        BUILTIN.call_once(|| {
+            let texture = Texture::new(
+                &BUILTIN_DATA.0,
+                BUILTIN_WIDTH as u16,
+                BUILTIN_HEIGHT as u16,
+                TextureFormat::Ia4,
+            );
+            Font::new(Atlas::new(texture), 8, 8, ' ')
+        })
+    }
+
+    #[inline]
+    pub fn atlas(&self) -> &Atlas {
+        &self.atlas
+    }
+
+    #[inline]
+    pub fn glyph_width(&self) -> u16 {
+        self.glyph_width
+    }
+
+    #[inline]
+    pub fn glyph_height(&self) -> u16 {
+        self.glyph_height
+    }
+
+    /// The region of the glyph of `c`, if the font has one.
+    pub fn glyph(&self, c: char) -> Option<Region> {
+        let texture = self.atlas.texture();
+        let columns = (texture.width() / self.glyph_width) as u32;
+        let rows = (texture.height() / self.glyph_height) as u32;
+        let index = (c as u32).checked_sub(self.first as u32)?;
+        (index < columns * rows).then(|| Region {
+            x: (index % columns) as u16 * self.glyph_width,
+            y: (index / columns) as u16 * self.glyph_height,
+            width: self.glyph_width,
+            height: self.glyph_height,
+        })
+    }
+}
+
+/// Draws sprites and text, with the GX state set by [`Renderer::begin`].
+pub struct Renderer {
+    gx: GxContext,
+    /// The texture loaded in the first texture map.
+    texture: Option<Texture>,
+}
+
+impl Renderer {
+    /// Sets GX up to draw sprites: an orthographic projection of the embedded framebuffer,
+    /// the identity as the first position matrix, the last vertex format, a TEV stage
+    /// of the texture tinted by the vertex colors, blended by their alphas, and no depth
+    /// test.
+    pub fn begin(gx: GxContext) -> Self {
+        gx.set_projection(&Mtx44::orthographic(0.0, WIDTH, HEIGHT, 0.0, -1.0, 1.0));
+        gx.load_position_matrix(0, &Mtx34::IDENTITY);
+        gx.set_position_matrix(0);
+        gx.set_vertex_descriptor(
+            &VertexDescriptor::new()
+                .with_color(0, AttributeType::Direct)
+                .with_tex_coord(0, AttributeType::Direct),
+        );
+        gx.set_vertex_format(
+            FORMAT,
+            &VertexFormat::new()
+                .with_position(PositionComponents::Xy, ComponentType::F32, 0)
+                .with_color(0, ColorFormat::Rgba8)
+                .with_tex_coord(0, TexCoordComponents::St, ComponentType::F32, 0),
+        );
+        gx.set_tex_coord_gens(&[TexGenSource::TexCoord(0)]);
+        gx.set_tev_stages(&[TevStage::modulate(0, 0)]);
+        gx.set_blend_mode(BlendMode::ALPHA);
+        gx.set_z_mode(None, false);
+        Self { gx, texture: None }
+    }
+
+    /// Loads `texture` unless it is the one loaded.
+    fn bind(&mut self, texture: &Texture) {
+        if self.texture.as_ref() != Some(texture) {
+            self.gx.load_texture(0, texture);
+            self.texture = Some(*texture);
+        }
+    }
+
+    #[inline]
+    pub fn draw(&mut self, atlas: &Atlas, sprite: &Sprite) {
+        self.draw_batch(atlas, core::slice::from_ref(sprite));
+    }
+
+    /// Draws `sprites` of `atlas`, in as few draws as there can be.
+    pub fn draw_batch(&mut self, atlas: &Atlas, sprites: &[Sprite]) {
+        self.bind(atlas.texture());
+        for sprites in sprites.chunks(MAX_QUADS) {
+            let mut draw = self.begin_quads(sprites.len());
+            for sprite in sprites {
+                let color = rgba(sprite.tint, sprite.alpha);
+                let tex_coords = atlas.tex_coords(&sprite.region);
+                write_quad(&mut draw, sprite.corners(), tex_coords, color);
+            }
+            draw.end();
+        }
+    }
+
+    /// Draws `text` in `font` from `x`, `y`, its glyphs scaled by `scale`, breaking lines
+    /// at `\n`. Characters the font has no glyph of are drawn as `?`.
+    pub fn draw_text(&mut self, font: &Font, text: &str, x: f32, y: f32, scale: f32, tint: Rgb) {
+        let glyph = |c: char| font.glyph(c).or_else(|| font.glyph('?'));
+        let drawn = |c: char| !c.is_whitespace() && glyph(c).is_some();
+        self.bind(font.atlas.texture());
+
+        let (advance, line_height) = (
+            font.glyph_width as f32 * scale,
+            font.glyph_height as f32 * scale,
+        );
+        let color = rgba(tint, 0xff);
+        let mut glyphs = text.chars().peekable();
+        let (mut pen_x, mut pen_y) = (x, y);
+        while glyphs.peek().is_some() {
+            // As many glyphs as a draw has, then those after them.
+            let mut remaining = glyphs.clone().filter(|&c| drawn(c)).count().min(MAX_QUADS);
+            if remaining == 0 {
+                return;
+            }
+            let mut draw = self.begin_quads(remaining);
+            while remaining > 0 {
+                let Some(c) = glyphs.next() else { break };
+                match c {
+                    '\n' => {
+                        pen_x = x;
+                        pen_y += line_height;
+                    }
+                    c if c.is_whitespace() => pen_x += advance,
+                    c => {
+                        if let Some(region) = glyph(c) {
+                            let sprite = Sprite::new(region)
+                                .with_position(pen_x, pen_y)
+                                .with_scale(scale, scale);
+                            write_quad(
+                                &mut draw,
+                                sprite.corners(),
+                                font.atlas.tex_coords(&region),
+                                color,
+                            );
+                            remaining -= 1;
+                        }
+                        pen_x += advance;
+                    }
+                }
+            }
+            draw.end();
+        }
+    }
+
+    fn begin_quads(&self, quads: usize) -> Draw {
+        self.gx.begin(Primitive::Quads, FORMAT, (quads * 4) as u16)
+    }
+}
+
+/// The RGBA8 vertex color of `tint` and `alpha`.
+fn rgba(tint: Rgb, alpha: u8) -> u32 {
+    u32::from_be_bytes([tint.r, tint.g, tint.b, alpha])
+}
+
+/// Writes the vertices of a quad of `corners`, from its top left clockwise, and its
+/// texture coordinates' left, top, right and bottom.
+fn write_quad(
+    draw: &mut Draw,
+    corners: [[f32; 2]; 4],
+    [left, top, right, bottom]: [f32; 4],
+    color: u32,
+) {
+    let tex_coords = [[left, top], [right, top], [right, bottom], [left, bottom]];
+    for (corner, tex_coord) in corners.into_iter().zip(tex_coords) {
+        draw.write(corner).write(color).write(tex_coord);
+    }
+}
@@ -1,39 +1,597 @@
+use super::color::{Rgb, Yuv};
+use crate::{
+    cache,
+    interrupt::{self, Source},
+    rtc::SystemSettings,
+};
 use core::{
     marker::PhantomData,
-    sync::atomic::{AtomicBool, Ordering},
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU8, Ordering},
+};
+use rbrew_shared::{interrupt, iotype_from_file, IoEnum};
+use spin::Mutex;
+
+iotype_from_file!("src/gfx/vi.regs.toml");
+
+/// The video format the VI generates timings for, as set in its display configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, IoEnum)]
+#[repr(u16)]
+pub enum VideoFormat {
+    Ntsc = 0,
+    Pal = 1,
+    Mpal = 2,
+    Debug = 3,
+}
+
+/// The modes the VI can display a 640 pixel wide framebuffer in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum VideoMode {
+    /// 480 lines interlaced at 60 Hz.
+    #[default]
+    Ntsc480i,
+    /// 576 lines interlaced at 50 Hz.
+    Pal576i,
+    /// PAL-M, 480 lines interlaced at 60 Hz with PAL colors.
+    PalM480i,
+    /// 480 lines progressive at 60 Hz, through the component cable.
+    Ntsc480p,
+}
+
+/// The gamma GX's copies to the external framebuffer correct its colors by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Gamma {
+    #[default]
+    One = 0,
+    OnePointSeven = 1,
+    TwoPointTwo = 2,
+}
+
+/// The shape of the display, which stretches the 640 pixels of a line to its width.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Aspect {
+    /// 4:3.
+    #[default]
+    Standard = 0,
+    /// 16:9, the framebuffer's pixels displayed wider than high.
+    Widescreen = 1,
+}
+
+impl Aspect {
+    /// The width of the display over its height, for projections.
+    #[inline]
+    pub fn ratio(self) -> f32 {
+        match self {
+            Aspect::Standard => 4.0 / 3.0,
+            Aspect::Widescreen => 16.0 / 9.0,
+        }
+    }
+}
+
+/// Where the VI displays the framebuffer's picture, for displays that cut its edges off or
+/// show it off center. Televisions hide the picture's edges behind their bezel, capture
+/// cards show them all.
+///
+/// Interlaced modes display every other line of the framebuffer in each field, so they
+/// round the lines down to even.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Overscan {
+    /// The pixels the picture is moved right by, left if negative.
+    pub x: i16,
+    /// The lines the picture is moved down by, up if negative. The lines moved past the
+    /// display's are not displayed.
+    pub y: i16,
+    /// The pixels of the picture's left and right edges left black.
+    pub crop_x: u16,
+    /// The lines of the picture's top and bottom edges left black.
+    pub crop_y: u16,
+}
+
+impl Overscan {
+    /// The most the picture moves sideways, within the blanking of a line.
+    pub const MAX_X: i16 = 40;
+    /// The most the picture moves up or down.
+    pub const MAX_Y: i16 = 64;
+    /// The most pixels cropped at each side, a quarter of the picture's width.
+    pub const MAX_CROP_X: u16 = 160;
+    /// The most lines cropped at the top and the bottom, a quarter of the picture's height.
+    pub const MAX_CROP_Y: u16 = 120;
+
+    #[inline]
+    pub const fn with_position(self, x: i16, y: i16) -> Self {
+        Self { x, y, ..self }
+    }
+
+    #[inline]
+    pub const fn with_crop(self, crop_x: u16, crop_y: u16) -> Self {
+        Self {
+            crop_x,
+            crop_y,
+            ..self
+        }
+    }
+}
+
+/// The timings of a mode, in the units of the registers they are written to.
+struct Timing {
+    equ: u16,
+    acv: u16,
+    prb_odd: u32,
+    prb_even: u32,
+    psb_odd: u32,
+    psb_even: u32,
+    bs: [u32; 4],
+    be: [u32; 4],
+    hlw: u32,
+    hsy: u32,
+    hcs: u32,
+    hce: u32,
+    hbe: u32,
+    hbs: u32,
+}
+
+const NTSC_480I: Timing = Timing {
+    equ: 6,
+    acv: 240,
+    prb_odd: 24,
+    prb_even: 25,
+    psb_odd: 3,
+    psb_even: 2,
+    bs: [12, 13, 12, 13],
+    be: [520, 519, 520, 519],
+    hlw: 429,
+    hsy: 64,
+    hcs: 71,
+    hce: 105,
+    hbe: 162,
+    hbs: 373,
+};
+
+const PAL_576I: Timing = Timing {
+    equ: 5,
+    acv: 288,
+    prb_odd: 33,
+    prb_even: 34,
+    psb_odd: 1,
+    psb_even: 0,
+    bs: [13, 12, 11, 10],
+    be: [619, 618, 617, 620],
+    hlw: 432,
+    hsy: 64,
+    hcs: 75,
+    hce: 106,
+    hbe: 172,
+    hbs: 380,
+};
+
+const PAL_M_480I: Timing = Timing {
+    equ: 6,
+    acv: 240,
+    prb_odd: 24,
+    prb_even: 25,
+    psb_odd: 3,
+    psb_even: 2,
+    bs: [16, 15, 14, 13],
+    be: [518, 517, 516, 519],
+    hlw: 429,
+    hsy: 64,
+    hcs: 78,
+    hce: 112,
+    hbe: 162,
+    hbs: 373,
+};
+
+const NTSC_480P: Timing = Timing {
+    equ: 12,
+    acv: 480,
+    prb_odd: 48,
+    prb_even: 48,
+    psb_odd: 6,
+    psb_even: 6,
+    bs: [24; 4],
+    be: [1038; 4],
+    hlw: 429,
+    hsy: 64,
+    hcs: 71,
+    hce: 105,
+    hbe: 162,
+    hbs: 373,
 };
-use rbrew_shared::iotype;
-
-iotype! {
-    pub type VI: 0xcc002000, 0x100 {
-        vtr: mut u16 = 0x00,
-        dcr: mut u16 = 0x02,
-        htro: mut u32 = 0x04,
-        htr1: mut u32 = 0x08,
-        vto: mut u32 = 0x0c,
-        vte: mut u32 = 0x10,
-        bbei: mut u32 = 0x14,
-        bboi: mut u32 = 0x18,
-        tfbl: mut u32 = 0x1c,
-        tfbr: mut u32 = 0x20,
-        bfbl: mut u32 = 0x24,
-        bfbr: mut u32 = 0x28,
-        dpv: const u16 = 0x2c,
-        dph: const u16 = 0x2e,
-        di0: mut u32 = 0x30,
-        di1: mut u32 = 0x34,
-        di2: mut u32 = 0x38,
-        di3: mut u32 = 0x3c,
-        dl0: mut u32 = 0x40,
-        dl1: mut u32 = 0x44,
-        hsw: mut u16 = 0x48,
-        hsr: mut u16 = 0x4a,
-        fct0: mut u32 = 0x4c,
-        fct1: mut u32 = 0x50,
+
+/// Whether the component cable is attached, which displays progressive scan.
+#[inline]
+pub fn has_component_cable() -> bool {
+    unsafe { VI::visel_component_read() }
+}
+
+/// The coefficients of the anti-aliasing filter the system software programs.
+const FILTER: [u32; 7] = [
+    0x1ae7_71f0,
+    0x0db4_a574,
+    0x00c1_188e,
+    0xc4c0_cbe2,
+    0xfcec_decf,
+    0x1313_0f08,
+    0x0008_0c0f,
+];
+
+/// Where the VI displays a mode's picture once moved and cropped by an [`Overscan`], in the
+/// units of the registers it is written to.
+struct Placement {
+    hbe: u32,
+    hbs: u32,
+    acv: u16,
+    prb_odd: u32,
+    prb_even: u32,
+    psb_odd: u32,
+    psb_even: u32,
+    /// The 32-byte units read of each line.
+    width: u16,
+    /// The bytes from the start of the framebuffer to the first unit read.
+    offset: u32,
+    /// The pixels of the first unit read that are not displayed.
+    x_offset: u32,
+}
+
+impl VideoMode {
+    const ALL: [VideoMode; 4] = [
+        VideoMode::Ntsc480i,
+        VideoMode::Pal576i,
+        VideoMode::PalM480i,
+        VideoMode::Ntsc480p,
+    ];
+
+    #[inline]
+    pub const fn width(self) -> usize {
+        640
+    }
+
+    #[inline]
+    pub const fn height(self) -> usize {
+        match self {
+            VideoMode::Pal576i => 576,
+            _ => 480,
+        }
+    }
+
+    /// The format of the timings, which progressive scan generates as NTSC.
+    #[inline]
+    pub fn format(self) -> VideoFormat {
+        match self {
+            VideoMode::Ntsc480i | VideoMode::Ntsc480p => VideoFormat::Ntsc,
+            VideoMode::Pal576i => VideoFormat::Pal,
+            VideoMode::PalM480i => VideoFormat::Mpal,
+        }
+    }
+
+    #[inline]
+    pub fn is_progressive(self) -> bool {
+        self == VideoMode::Ntsc480p
+    }
+
+    /// The mode the console is set up for: progressive scan if it is enabled in its
+    /// settings and the component cable is attached, and [`Self::detect_interlaced`]
+    /// otherwise.
+    pub fn detect() -> VideoMode {
+        let progressive = SystemSettings::read().is_some_and(|settings| settings.progressive());
+        if progressive && has_component_cable() {
+            VideoMode::Ntsc480p
+        } else {
+            Self::detect_interlaced()
+        }
+    }
+
+    /// The interlaced mode of the console's format: the one the loader left the VI
+    /// displaying in, or the one in SRAM if the VI is not enabled. Once the VI is
+    /// programmed, this detects the format it was programmed in.
+    pub fn detect_interlaced() -> VideoMode {
+        let format = if unsafe { VI::dcr_enable_read() } {
+            unsafe { VI::dcr_format_read() }.unwrap_or(VideoFormat::Ntsc)
+        } else {
+            match SystemSettings::read().map(|settings| settings.video_format()) {
+                Some(format @ (VideoFormat::Pal | VideoFormat::Mpal)) => format,
+                _ => VideoFormat::Ntsc,
+            }
+        };
+        match format {
+            VideoFormat::Pal => VideoMode::Pal576i,
+            VideoFormat::Mpal => VideoMode::PalM480i,
+            VideoFormat::Ntsc | VideoFormat::Debug => VideoMode::Ntsc480i,
+        }
+    }
+
+    /// Places the mode's picture as `overscan` moves and crops it.
+    fn place(self, overscan: Overscan) -> Placement {
+        let timing = self.timing();
+        // The lines of the framebuffer in each field.
+        let step = if self.is_progressive() { 1 } else { 2 };
+        let lines = i32::from(timing.acv);
+        let y = i32::from(overscan.y) / step;
+        let crop_y = i32::from(overscan.crop_y) / step;
+        // The lines of the field the picture is displayed on, cut to the mode's.
+        let start = (crop_y + y).max(0);
+        let end = (lines - crop_y + y).min(lines);
+
+        let x = i32::from(overscan.x);
+        let crop_x = i32::from(overscan.crop_x);
+        let line_bytes = self.width() as u32 * 2;
+        // The line is read from the unit of 16 pixels the crop starts in.
+        let x_offset = overscan.crop_x as u32 % 16;
+        let pixels = self.width() as u32 - 2 * overscan.crop_x as u32;
+        Placement {
+            hbe: (timing.hbe as i32 + x + crop_x) as u32,
+            hbs: (timing.hbs as i32 + x - crop_x) as u32,
+            acv: (end - start) as u16,
+            prb_odd: timing.prb_odd + 2 * start as u32,
+            prb_even: timing.prb_even + 2 * start as u32,
+            psb_odd: timing.psb_odd + 2 * (lines - end) as u32,
+            psb_even: timing.psb_even + 2 * (lines - end) as u32,
+            width: (x_offset + pixels).div_ceil(16) as u16,
+            offset: (start - y) as u32 * step as u32 * line_bytes
+                + overscan.crop_x as u32 / 16 * 32,
+            x_offset,
+        }
+    }
+
+    fn timing(self) -> &'static Timing {
+        match self {
+            VideoMode::Ntsc480i => &NTSC_480I,
+            VideoMode::Pal576i => &PAL_576I,
+            VideoMode::PalM480i => &PAL_M_480I,
+            VideoMode::Ntsc480p => &NTSC_480P,
+        }
+    }
+
+    /// Programs the VI for the mode, displaying the framebuffer at the physical address
+    /// `xfb`.
+    ///
+    /// # Safety
+    ///
+    /// `xfb` must be 32-byte aligned and hold a framebuffer of the mode, in MEM1.
+    unsafe fn program(self, xfb: u32) {
+        let timing = self.timing();
+        let progressive = self.is_progressive();
+        let placement = self.place(*OVERSCAN.lock());
+
+        VI::dcr_write(ViDcr::default().with_reset(true).0);
+        for _ in 0..1000 {
+            core::hint::spin_loop();
+        }
+        VI::dcr_write(ViDcr::default().0);
+
+        VI::htr0_write(
+            ViHtr0::default()
+                .with_hlw(timing.hlw)
+                .with_hce(timing.hce)
+                .with_hcs(timing.hcs)
+                .0,
+        );
+        self.position(&placement);
+        VI::bbei_write(
+            ViBbei::default()
+                .with_bs1(timing.bs[0])
+                .with_be1(timing.be[0])
+                .with_bs3(timing.bs[2])
+                .with_be3(timing.be[2])
+                .0,
+        );
+        VI::bboi_write(
+            ViBboi::default()
+                .with_bs2(timing.bs[1])
+                .with_be2(timing.be[1])
+                .with_bs4(timing.bs[3])
+                .with_be4(timing.be[3])
+                .0,
+        );
+        for (index, coefficients) in FILTER.into_iter().enumerate() {
+            VI::fct_write(index, coefficients);
+        }
+
+        VI::hsr_write(ViHsr::default().with_step(256).0);
+
+        self.show(xfb);
+
+        // The first display interrupt marks the start of each field, a retrace. The others
+        // the loader left enabled are disabled.
+        VI::di_write(
+            0,
+            ViDi::default().with_hct(1).with_vct(1).with_enable(true).0,
+        );
+        for index in 1..4 {
+            VI::di_write(index, 0);
+        }
+
+        VI::clk_write(ViClk::default().with_double(progressive).0);
+        VI::dcr_write(
+            ViDcr::default()
+                .with_enable(true)
+                .with_non_interlaced(progressive)
+                .with_format(self.format())
+                .0,
+        );
+    }
+
+    /// Writes the timings and the line width `placement` moves and crops the picture by.
+    ///
+    /// # Safety
+    ///
+    /// The VI must be programmed for the mode, or be being so.
+    unsafe fn position(self, placement: &Placement) {
+        let timing = self.timing();
+        VI::htr1_write(
+            ViHtr1::default()
+                .with_hsy(timing.hsy)
+                .with_hbe(placement.hbe)
+                .with_hbs(placement.hbs)
+                .0,
+        );
+        VI::vtr_write(
+            ViVtr::default()
+                .with_equ(timing.equ)
+                .with_acv(placement.acv)
+                .0,
+        );
+        VI::vto_write(
+            ViVto::default()
+                .with_prb(placement.prb_odd)
+                .with_psb(placement.psb_odd)
+                .0,
+        );
+        VI::vte_write(
+            ViVte::default()
+                .with_prb(placement.prb_even)
+                .with_psb(placement.psb_even)
+                .0,
+        );
+        // The lines are read in 32-byte units, interlaced fields skipping every other.
+        let units = (self.width() * 2 / 32) as u16;
+        let stride = if self.is_progressive() {
+            units
+        } else {
+            units * 2
+        };
+        VI::hsw_write(
+            ViHsw::default()
+                .with_width(placement.width)
+                .with_stride(stride)
+                .0,
+        );
+    }
+
+    /// Displays the framebuffer at the physical address `xfb` from the next frame, the
+    /// VI latching the addresses when it starts one.
+    ///
+    /// # Safety
+    ///
+    /// Like [`Self::program`].
+    unsafe fn show(self, xfb: u32) {
+        let placement = self.place(*OVERSCAN.lock());
+        let top = xfb + placement.offset;
+        // Interlaced fields start one line apart.
+        let bottom = if self.is_progressive() {
+            top
+        } else {
+            top + self.width() as u32 * 2
+        };
+        VI::tfbl_write(
+            ViTfbl::default()
+                .with_base(top >> 5)
+                .with_x_offset(placement.x_offset)
+                .with_page_offset(true)
+                .0,
+        );
+        VI::bfbl_write(
+            ViBfbl::default()
+                .with_base(bottom >> 5)
+                .with_page_offset(true)
+                .0,
+        );
+    }
+}
+
+/// The retraces counted since the VI was programmed.
+static RETRACES: AtomicU32 = AtomicU32::new(0);
+/// The `fn()` called at each retrace, null if there is none.
+static RETRACE_CALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Counts a retrace if the first display interrupt marks one, acknowledging it.
+fn poll_retrace() {
+    if !unsafe { VI::di_status_read(0) } {
+        return;
+    }
+    unsafe { VI::di_status_write(0, false) };
+    RETRACES.fetch_add(1, Ordering::Release);
+    let callback = RETRACE_CALLBACK.load(Ordering::Acquire);
+    if !callback.is_null() {
+        unsafe { core::mem::transmute::<*mut (), fn()>(callback)() };
+    }
+}
+
+#[interrupt(Source::Vi)]
+fn retrace_interrupt() {
+    poll_retrace();
+}
+
+/// Waits for a retrace counted after `count`, returning at once if there was one.
+fn wait_for_retrace_after(count: u32) {
+    while RETRACES.load(Ordering::Acquire) == count {
+        // Without the interrupt, before `interrupt::enable` or with it disabled, the
+        // retrace is polled.
+        interrupt::free(poll_retrace);
+        core::hint::spin_loop();
     }
 }
 
+/// The words of an external framebuffer large enough for every mode, each holding two
+/// pixels in YUYV.
+const XFB_WORDS: usize = 640 * 576 / 2;
+
+/// An external framebuffer the VI displays. The VI reads it in 32-byte units, from MEM1.
+#[repr(C, align(32))]
+struct Xfb([u32; XFB_WORDS]);
+
+/// The number of external framebuffers, three with the `triple-buffering` feature.
+const BUFFERS: usize = if cfg!(feature = "triple-buffering") {
+    3
+} else {
+    2
+};
+
+static mut XFBS: [Xfb; BUFFERS] = [const { Xfb([0; XFB_WORDS]) }; BUFFERS];
+
+/// Two black pixels in YUYV.
+const BLACK: u32 = Yuv::pair(Rgb::BLACK.to_yuv(), Rgb::BLACK.to_yuv());
+
+/// The buffer being displayed, the one drawn to and, with triple buffering, the one
+/// displayed from the next frame, `NONE` if there is none, by their index in `XFBS`.
+static FRONT: AtomicU8 = AtomicU8::new(0);
+static BACK: AtomicU8 = AtomicU8::new(1);
+static PENDING: AtomicU8 = AtomicU8::new(NONE);
+const NONE: u8 = u8::MAX;
+/// The retrace count when the pending buffer was shown.
+static SHOWN_AT: AtomicU32 = AtomicU32::new(0);
+
+/// Whether a [`BackBuffer`] is alive.
+static BORROWED: AtomicBool = AtomicBool::new(false);
+
 static IS_INIT: AtomicBool = AtomicBool::new(false);
+static MODE: AtomicU8 = AtomicU8::new(0);
+
+/// The display's tuning: the gamma and brightness GX applies to the frames it copies, and
+/// the aspect programs draw for.
+static GAMMA: AtomicU8 = AtomicU8::new(Gamma::One as u8);
+static BRIGHTNESS: AtomicU32 = AtomicU32::new(1.0f32.to_bits());
+static ASPECT: AtomicU8 = AtomicU8::new(Aspect::Standard as u8);
+static OVERSCAN: Mutex<Overscan> = Mutex::new(Overscan {
+    x: 0,
+    y: 0,
+    crop_x: 0,
+    crop_y: 0,
+});
+
+/// The framebuffer `index` of `XFBS`.
+fn xfb(index: u8) -> *mut [u32; XFB_WORDS] {
+    unsafe { &raw mut XFBS[index as usize].0 }
+}
+
+/// The framebuffer displayed from the next frame, the one swapped in last.
+fn displayed() -> u8 {
+    let pending = PENDING.load(Ordering::Relaxed);
+    if pending != NONE {
+        pending
+    } else {
+        FRONT.load(Ordering::Relaxed)
+    }
+}
+
+/// The physical address of the framebuffer `index`, which the cached mirror at
+/// 0x8000_0000 maps from.
+fn xfb_address(index: u8) -> u32 {
+    xfb(index) as u32 & 0x3fff_ffff
+}
 
 #[derive(Debug)]
 pub enum VideoInitError {
@@ -47,23 +605,34 @@ pub struct VideoContext {
 }
 
 impl VideoContext {
-    fn init_video() -> Result<(), VideoInitError> {
+    fn init_video(mode: VideoMode) -> Result<(), VideoInitError> {
+        MODE.store(mode as u8, Ordering::Relaxed);
         Framebuffer::init()?;
+        unsafe { mode.program(xfb_address(FRONT.load(Ordering::Relaxed))) };
         Ok(())
     }
 
-    pub fn init() -> Result<(), VideoInitError> {
-        if IS_INIT.load(Ordering::Acquire) {
-            IS_INIT.store(true, Ordering::Release);
-            Self::init_video()
-        } else {
+    /// Programs the VI for `mode`, displaying a black framebuffer.
+    pub fn init(mode: VideoMode) -> Result<(), VideoInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
             Err(VideoInitError::AlreadyInitialized)
+        } else {
+            Self::init_video(mode)
         }
     }
 
+    /// Programs the VI for the mode the console is set up for, [`VideoMode::detect`].
+    pub fn init_auto() -> Result<(), VideoInitError> {
+        if IS_INIT.load(Ordering::Acquire) {
+            return Err(VideoInitError::AlreadyInitialized);
+        }
+        Self::init(VideoMode::detect())
+    }
+
+    /// The global video context, initialized in the default mode if it was not.
     pub fn global() -> Self {
         #[allow(unreachable_patterns)]
-        match Self::init() {
+        match Self::init(VideoMode::default()) {
             Err(VideoInitError::AlreadyInitialized) | Ok(_) => {}
             Err(e) => panic!("video failed to initialize: {e:?}"),
         }
@@ -77,12 +646,130 @@ impl VideoContext {
         Self { _mark: PhantomData }
     }
 
+    /// Programs the VI for `mode`, displaying the framebuffer swapped in last. Programs
+    /// switch modes to fall back from progressive scan, say.
+    ///
+    /// Panics if the back buffer is borrowed, its mode changing.
+    pub fn set_mode(self, mode: VideoMode) {
+        if BORROWED.load(Ordering::Acquire) {
+            panic!("cannot set the mode while the back buffer is borrowed");
+        }
+        MODE.store(mode as u8, Ordering::Relaxed);
+        unsafe { mode.program(xfb_address(displayed())) };
+    }
+
+    /// The mode the VI is programmed in.
+    #[inline]
+    pub fn mode(self) -> VideoMode {
+        VideoMode::ALL[MODE.load(Ordering::Relaxed) as usize]
+    }
+
+    /// Waits for the VI to start displaying a field, 50 or 60 times a second.
+    #[inline]
+    pub fn wait_for_retrace(self) {
+        wait_for_retrace_after(RETRACES.load(Ordering::Acquire));
+    }
+
+    /// The retraces since the VI was initialized, wrapping around.
+    #[inline]
+    pub fn retrace_count(self) -> u32 {
+        RETRACES.load(Ordering::Acquire)
+    }
+
+    /// Sets the function called at each retrace, returning the one set before.
+    ///
+    /// It is called by the VI's interrupt handler, with interrupts disabled, once
+    /// [`interrupt::init`](crate::interrupt::init) installed it and interrupts are
+    /// enabled. Until then it is only called from [`Self::wait_for_retrace`] and
+    /// [`Framebuffer::swap`].
+    pub fn set_retrace_callback(self, callback: Option<fn()>) -> Option<fn()> {
+        let new = callback.map_or(ptr::null_mut(), |callback| callback as *mut ());
+        let old = RETRACE_CALLBACK.swap(new, Ordering::AcqRel);
+        (!old.is_null()).then(|| unsafe { core::mem::transmute::<*mut (), fn()>(old) })
+    }
+
+    /// Sets the gamma of the frames GX copies to the framebuffer from now on. What the
+    /// processor draws is displayed as it is.
+    #[inline]
+    pub fn set_gamma(self, gamma: Gamma) {
+        GAMMA.store(gamma as u8, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn gamma(self) -> Gamma {
+        match GAMMA.load(Ordering::Relaxed) {
+            1 => Gamma::OnePointSeven,
+            2 => Gamma::TwoPointTwo,
+            _ => Gamma::One,
+        }
+    }
+
+    /// Scales the colors of the frames GX copies to the framebuffer from now on by
+    /// `brightness`, black staying black. It weighs the lines of the copy filter, which
+    /// are up to 63/64 each, so it goes up to about 3 for the default filter.
+    ///
+    /// Panics if `brightness` is negative or not a number.
+    pub fn set_brightness(self, brightness: f32) {
+        assert!(brightness >= 0.0, "brightness is not negative");
+        BRIGHTNESS.store(brightness.to_bits(), Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn brightness(self) -> f32 {
+        f32::from_bits(BRIGHTNESS.load(Ordering::Relaxed))
+    }
+
+    /// Sets the shape of the display, which [`Aspect::ratio`] has projections and layouts
+    /// follow. The VI displays the same lines whatever it is.
+    #[inline]
+    pub fn set_aspect(self, aspect: Aspect) {
+        ASPECT.store(aspect as u8, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn aspect(self) -> Aspect {
+        match ASPECT.load(Ordering::Relaxed) {
+            1 => Aspect::Widescreen,
+            _ => Aspect::Standard,
+        }
+    }
+
+    /// Moves and crops the picture the VI displays by `overscan` from the next frame.
+    ///
+    /// Panics if `overscan` is beyond the bounds of [`Overscan`].
+    pub fn set_overscan(self, overscan: Overscan) {
+        assert!(
+            overscan.x.abs() <= Overscan::MAX_X && overscan.y.abs() <= Overscan::MAX_Y,
+            "the overscan moves the picture too far"
+        );
+        assert!(
+            overscan.crop_x <= Overscan::MAX_CROP_X && overscan.crop_y <= Overscan::MAX_CROP_Y,
+            "the overscan crops too much of the picture"
+        );
+        let mode = self.mode();
+        *OVERSCAN.lock() = overscan;
+        unsafe {
+            mode.position(&mode.place(overscan));
+            mode.show(xfb_address(displayed()));
+        }
+    }
+
+    #[inline]
+    pub fn overscan(self) -> Overscan {
+        *OVERSCAN.lock()
+    }
+
     #[inline]
     pub fn frambuffer(self) -> Framebuffer {
         Framebuffer { _mark: PhantomData }
     }
 }
 
+/// The external framebuffers the VI displays, drawn to through the [`BackBuffer`] and
+/// displayed by [`Framebuffer::swap`].
+///
+/// Two buffers are reserved, or three with the `triple-buffering` feature, each of
+/// 640x576 pixels whatever the mode.
 #[derive(Clone, Copy)]
 pub struct Framebuffer {
     // Makes the type non-trivially constructible.
@@ -90,7 +777,13 @@ pub struct Framebuffer {
 }
 
 impl Framebuffer {
+    /// Clears the framebuffers to black, writing them back for the VI.
     fn init() -> Result<(), VideoInitError> {
+        for index in 0..BUFFERS as u8 {
+            let xfb = unsafe { &mut *xfb(index) };
+            xfb.fill(BLACK);
+            cache::flush_data_range(xfb.as_ptr().cast(), size_of_val(xfb));
+        }
         Ok(())
     }
 
@@ -98,4 +791,223 @@ impl Framebuffer {
     pub fn global() -> Self {
         VideoContext::global().frambuffer()
     }
+
+    #[inline]
+    pub fn mode(self) -> VideoMode {
+        VideoContext::global().mode()
+    }
+
+    /// Borrows the buffer drawn to, which is not displayed until [`Self::swap`].
+    ///
+    /// Panics if it is already borrowed.
+    pub fn back(self) -> BackBuffer {
+        if BORROWED.swap(true, Ordering::Acquire) {
+            panic!("the back buffer is already borrowed");
+        }
+        BackBuffer {
+            index: BACK.load(Ordering::Relaxed),
+            mode: self.mode(),
+        }
+    }
+
+    /// Displays the back buffer from the next frame, and makes a buffer the VI is done
+    /// with the back buffer.
+    ///
+    /// With double buffering this waits for the VI to start displaying it. With triple
+    /// buffering it only waits for the buffer of the previous swap, if the VI has not
+    /// started a frame since; drawing can go on meanwhile in the third buffer.
+    ///
+    /// Panics if the back buffer is borrowed.
+    pub fn swap(self) {
+        if BORROWED.load(Ordering::Acquire) {
+            panic!("cannot swap while the back buffer is borrowed");
+        }
+        let mode = self.mode();
+        let back = BACK.load(Ordering::Relaxed);
+        let xfb = unsafe { &*xfb(back) };
+        cache::store_data_range(xfb.as_ptr().cast(), size_of_val(xfb));
+
+        let pending = PENDING.load(Ordering::Relaxed);
+        if pending != NONE {
+            // A retrace counted after the pending buffer was shown displays it.
+            wait_for_retrace_after(SHOWN_AT.load(Ordering::Relaxed));
+            FRONT.store(pending, Ordering::Relaxed);
+        }
+        let front = FRONT.load(Ordering::Relaxed);
+        unsafe { mode.show(xfb_address(back)) };
+        // Counted after the addresses are written, a retrace between the two only makes
+        // this wait a field longer.
+        let shown_at = RETRACES.load(Ordering::Acquire);
+        if BUFFERS == 3 {
+            PENDING.store(back, Ordering::Relaxed);
+            SHOWN_AT.store(shown_at, Ordering::Relaxed);
+            BACK.store(3 - front - back, Ordering::Relaxed);
+        } else {
+            wait_for_retrace_after(shown_at);
+            FRONT.store(back, Ordering::Relaxed);
+            BACK.store(front, Ordering::Relaxed);
+        }
+    }
+
+    /// The bytes of a [capture](Self::capture) of the mode's frames.
+    #[inline]
+    pub fn capture_size(self) -> usize {
+        let mode = self.mode();
+        mode.width() * mode.height() * 3
+    }
+
+    /// Captures the frame swapped in last, displayed or about to be, into `buf` as RGB888
+    /// lines of the mode's width from the top, returning its width and height.
+    ///
+    /// Panics if `buf` is smaller than [`Self::capture_size`].
+    pub fn capture(self, buf: &mut [u8]) -> (usize, usize) {
+        let mode = self.mode();
+        let (width, height) = (mode.width(), mode.height());
+        assert!(
+            buf.len() >= self.capture_size(),
+            "the buffer is too small for the capture"
+        );
+        let xfb = unsafe { &*xfb(displayed()) };
+        let words = &xfb[..width * height / 2];
+        // GX writes framebuffers behind the data cache.
+        cache::flush_data_range(words.as_ptr().cast(), size_of_val(words));
+        for (&word, pixels) in words.iter().zip(buf.chunks_exact_mut(6)) {
+            let (left, right) = Yuv::unpair(word);
+            let (left, right) = (left.to_rgb(), right.to_rgb());
+            pixels.copy_from_slice(&[left.r, left.g, left.b, right.r, right.g, right.b]);
+        }
+        (width, height)
+    }
+}
+
+/// The buffer drawn to, as words of two pixels in YUYV, a line of the mode's width
+/// following another.
+///
+/// Its methods draw [`Rgb`] colors, converting them to YUYV.
+pub struct BackBuffer {
+    index: u8,
+    mode: VideoMode,
+}
+
+impl BackBuffer {
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.mode.width()
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.mode.height()
+    }
+
+    /// Sets the pixel at `x`, `y`, if it is in the buffer.
+    ///
+    /// The pixel shares its chroma with its neighbour in the word, which both get the
+    /// average of theirs.
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Rgb) {
+        if x < self.width() && y < self.height() {
+            self.put(x, y, color.to_yuv());
+        }
+    }
+
+    /// The pixel at `x`, `y`, with the chroma it shares with its neighbour in the word.
+    ///
+    /// Panics if it is not in the buffer.
+    pub fn pixel(&self, x: usize, y: usize) -> Rgb {
+        assert!(
+            x < self.width() && y < self.height(),
+            "the pixel is not in the buffer"
+        );
+        let (left, right) = Yuv::unpair(self[(y * self.width() + x) / 2]);
+        if x.is_multiple_of(2) { left } else { right }.to_rgb()
+    }
+
+    /// Fills the buffer with `color`.
+    pub fn clear(&mut self, color: Rgb) {
+        let yuv = color.to_yuv();
+        self.fill(Yuv::pair(yuv, yuv));
+    }
+
+    /// Fills the rectangle of `width` by `height` pixels from `x`, `y` with `color`,
+    /// clipped to the buffer.
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: Rgb) {
+        let yuv = color.to_yuv();
+        let width = width.min(self.width().saturating_sub(x));
+        for y in y..y.saturating_add(height).min(self.height()) {
+            self.draw_line(x, y, core::iter::repeat_n(yuv, width));
+        }
+    }
+
+    /// Draws the image of `width` pixels a line from `x`, `y`, clipped to the buffer.
+    ///
+    /// `pixels` holds the lines one after another, each pixel as three bytes of red,
+    /// green and blue. A last line shorter than `width` is not drawn.
+    pub fn blit(&mut self, x: usize, y: usize, width: usize, pixels: &[u8]) {
+        if width == 0 {
+            return;
+        }
+        let visible = width.min(self.width().saturating_sub(x));
+        let lines = pixels.chunks_exact(width * 3);
+        for (y, line) in (y..self.height()).zip(lines) {
+            let line = line.chunks_exact(3).take(visible);
+            self.draw_line(
+                x,
+                y,
+                line.map(|rgb| Rgb::new(rgb[0], rgb[1], rgb[2]).to_yuv()),
+            );
+        }
+    }
+
+    /// Sets the pixel at `x`, `y` in the buffer.
+    fn put(&mut self, x: usize, y: usize, yuv: Yuv) {
+        let index = (y * self.width() + x) / 2;
+        let word = &mut self[index];
+        let (left, right) = Yuv::unpair(*word);
+        *word = if x.is_multiple_of(2) {
+            Yuv::pair(yuv, right)
+        } else {
+            Yuv::pair(left, yuv)
+        };
+    }
+
+    /// Sets the pixels from `x`, `y` to `pixels`, which ends in the buffer, writing whole
+    /// words where it covers both of their pixels.
+    fn draw_line(&mut self, mut x: usize, y: usize, mut pixels: impl Iterator<Item = Yuv>) {
+        let line = y * self.width();
+        if x % 2 == 1 {
+            let Some(yuv) = pixels.next() else { return };
+            self.put(x, y, yuv);
+            x += 1;
+        }
+        loop {
+            match (pixels.next(), pixels.next()) {
+                (Some(left), Some(right)) => self[(line + x) / 2] = Yuv::pair(left, right),
+                (Some(left), None) => return self.put(x, y, left),
+                _ => return,
+            }
+            x += 2;
+        }
+    }
+}
+
+impl core::ops::Deref for BackBuffer {
+    type Target = [u32];
+
+    fn deref(&self) -> &[u32] {
+        let len = self.width() * self.height() / 2;
+        unsafe { core::slice::from_raw_parts(xfb(self.index).cast(), len) }
+    }
+}
+
+impl core::ops::DerefMut for BackBuffer {
+    fn deref_mut(&mut self) -> &mut [u32] {
+        let len = self.width() * self.height() / 2;
+        unsafe { core::slice::from_raw_parts_mut(xfb(self.index).cast(), len) }
+    }
+}
+
+impl Drop for BackBuffer {
+    fn drop(&mut self) {
+        BORROWED.store(false, Ordering::Release);
+    }
 }
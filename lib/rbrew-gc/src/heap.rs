@@ -0,0 +1,298 @@
+//! The heap in MEM1 backing `alloc`, with the `heap` feature.
+//!
+//! Programs linked with `targets/gamecube.ld` allocate between `__heap_start`, past the
+//! bss, and `__heap_end`, below the stack, which leaves `__stack_size` bytes to the
+//! stack. Each is overridden with `--defsym`. The romfs image is never handed out:
+//!
+//! ```ignore
+//! extern crate alloc;
+//! use alloc::{boxed::Box, vec::Vec};
+//!
+//! let mut levels = Vec::new();
+//! levels.push(Box::new(Level::load("levels/1.bin")));
+//! ```
+//!
+//! The heap is placed on the first allocation, unless [`init`] places it elsewhere. The
+//! free memory is kept as a list of holes sorted by address, which are merged as memory
+//! is freed, and allocations take the first hole they fit in.
+
+use crate::{interrupt, romfs};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ops::Range,
+    ptr::{self, null_mut},
+};
+use spin::Mutex;
+
+/// A free block of memory, whose header is stored at its start.
+struct Hole {
+    size: usize,
+    next: *mut Hole,
+}
+
+/// The smallest block handed out or kept free, which fits a [`Hole`].
+const MIN_SIZE: usize = size_of::<Hole>();
+const MIN_ALIGN: usize = align_of::<Hole>();
+
+struct Heap {
+    /// Whether the holes were set up, by [`init`] or on the first allocation.
+    is_init: bool,
+    /// The first hole, or null.
+    first: *mut Hole,
+    memory: Range<usize>,
+    used: usize,
+}
+
+// The holes are only reached through the lock.
+unsafe impl Send for Heap {}
+
+static HEAP: Mutex<Heap> = Mutex::new(Heap {
+    is_init: false,
+    first: null_mut(),
+    memory: 0..0,
+    used: 0,
+});
+
+/// Called with the layout of an allocation that does not fit, returning whether to retry
+/// it.
+pub type OutOfMemoryHook = fn(Layout) -> bool;
+
+static OOM_HOOK: Mutex<Option<OutOfMemoryHook>> = Mutex::new(None);
+
+/// The size and alignment of the block backing `layout`, such that the memory around it
+/// can always hold a hole.
+fn block(layout: Layout) -> (usize, usize) {
+    let size = layout.size().max(MIN_SIZE).next_multiple_of(MIN_ALIGN);
+    (size, layout.align().max(MIN_ALIGN))
+}
+
+impl Heap {
+    /// Makes `memory` a single hole.
+    unsafe fn place(&mut self, memory: Range<usize>) {
+        let start = memory.start.next_multiple_of(MIN_ALIGN);
+        let end = memory.end & !(MIN_ALIGN - 1);
+        self.is_init = true;
+        self.memory = start..end.max(start);
+        self.used = 0;
+        self.first = null_mut();
+        if end - start.min(end) >= MIN_SIZE {
+            let hole = start as *mut Hole;
+            hole.write(Hole {
+                size: end - start,
+                next: null_mut(),
+            });
+            self.first = hole;
+        }
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        if !self.is_init {
+            self.place(default_memory());
+        }
+        let (size, align) = block(layout);
+        let mut link: *mut *mut Hole = &raw mut self.first;
+        while !(*link).is_null() {
+            let hole = *link;
+            let Hole {
+                size: hole_size,
+                next,
+            } = hole.read();
+            let hole_start = hole as usize;
+            let hole_end = hole_start + hole_size;
+            let mut start = hole_start.next_multiple_of(align);
+            // The memory skipped for the alignment stays a hole, so it must fit one.
+            if start != hole_start && start - hole_start < MIN_SIZE {
+                start = (hole_start + MIN_SIZE).next_multiple_of(align);
+            }
+            let fits = start
+                .checked_add(size)
+                .is_some_and(|end| end == hole_end || end + MIN_SIZE <= hole_end);
+            if !fits {
+                link = &raw mut (*hole).next;
+                continue;
+            }
+            let end = start + size;
+            let mut rest = next;
+            if end != hole_end {
+                rest = end as *mut Hole;
+                rest.write(Hole {
+                    size: hole_end - end,
+                    next,
+                });
+            }
+            if start == hole_start {
+                *link = rest;
+            } else {
+                (*hole).size = start - hole_start;
+                (*hole).next = rest;
+            }
+            self.used += size;
+            return start as *mut u8;
+        }
+        null_mut()
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = block(layout);
+        let start = ptr as usize;
+        self.used -= size;
+        // The hole before the block, which it is merged into if they touch.
+        let mut prev: *mut Hole = null_mut();
+        let mut next = self.first;
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+        let hole = start as *mut Hole;
+        hole.write(Hole { size, next });
+        if !next.is_null() && start + size == next as usize {
+            (*hole).size += (*next).size;
+            (*hole).next = (*next).next;
+        }
+        if prev.is_null() {
+            self.first = hole;
+        } else if prev as usize + (*prev).size == start {
+            (*prev).size += (*hole).size;
+            (*prev).next = (*hole).next;
+        } else {
+            (*prev).next = hole;
+        }
+    }
+
+    /// The largest block that could be allocated.
+    fn largest(&self) -> usize {
+        let mut largest = 0;
+        let mut hole = self.first;
+        while !hole.is_null() {
+            unsafe {
+                largest = largest.max((*hole).size);
+                hole = (*hole).next;
+            }
+        }
+        largest
+    }
+}
+
+/// The memory the linker script leaves to the heap, past the romfs image if it lies in
+/// it.
+fn default_memory() -> Range<usize> {
+    #[cfg(target_arch = "powerpc")]
+    let (mut start, end) = {
+        extern "C" {
+            static __heap_start: u8;
+            static __heap_end: u8;
+        }
+        (
+            &raw const __heap_start as usize,
+            &raw const __heap_end as usize,
+        )
+    };
+    #[cfg(not(target_arch = "powerpc"))]
+    let (mut start, end) = (0, 0);
+    if let Some(image) = romfs::range() {
+        let image = image.start as usize..image.end as usize;
+        if image.start < end && image.end > start {
+            start = start.max(image.end);
+        }
+    }
+    start..end.max(start)
+}
+
+#[derive(Debug)]
+pub enum HeapInitError {
+    AlreadyInitialized,
+}
+
+/// Places the heap in `memory` instead of between `__heap_start` and `__heap_end`.
+///
+/// # Safety
+///
+/// `memory` must be mapped, and not used by anything else for the rest of the program,
+/// like the romfs image or the stack.
+pub unsafe fn init(memory: Range<u32>) -> Result<(), HeapInitError> {
+    interrupt::free(|| {
+        let mut heap = HEAP.lock();
+        if heap.is_init {
+            return Err(HeapInitError::AlreadyInitialized);
+        }
+        heap.place(memory.start as usize..memory.end as usize);
+        Ok(())
+    })
+}
+
+/// Calls `hook` with the layout of each allocation that does not fit, before it fails.
+/// It can free memory, like caches, and return `true` to retry the allocation, which
+/// otherwise aborts the program.
+///
+/// The hook runs with the external interrupt disabled, and must not allocate.
+pub fn set_out_of_memory_hook(hook: Option<OutOfMemoryHook>) {
+    interrupt::free(|| *OOM_HOOK.lock() = hook);
+}
+
+/// The memory of the heap, or `None` if it was not placed yet.
+pub fn memory() -> Option<Range<u32>> {
+    interrupt::free(|| {
+        let heap = HEAP.lock();
+        heap.is_init
+            .then(|| heap.memory.start as u32..heap.memory.end as u32)
+    })
+}
+
+/// The bytes allocated, counting what allocations are rounded up to.
+pub fn used() -> usize {
+    interrupt::free(|| HEAP.lock().used)
+}
+
+/// The bytes not allocated, which may be split between holes.
+pub fn available() -> usize {
+    interrupt::free(|| {
+        let heap = HEAP.lock();
+        heap.memory.len() - heap.used
+    })
+}
+
+/// The largest allocation that would currently succeed, ignoring alignment.
+pub fn largest_available() -> usize {
+    interrupt::free(|| HEAP.lock().largest())
+}
+
+/// The allocator of the heap, registered as the global allocator on the console.
+pub struct Allocator;
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        interrupt::free(|| loop {
+            let ptr = HEAP.lock().alloc(layout);
+            if !ptr.is_null() {
+                return ptr;
+            }
+            let hook = *OOM_HOOK.lock();
+            if !hook.is_some_and(|hook| hook(layout)) {
+                return null_mut();
+            }
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        interrupt::free(|| HEAP.lock().dealloc(ptr, layout));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        // Blocks are rounded up, so small changes keep theirs.
+        if block(new_layout).0 == block(layout).0 {
+            return ptr;
+        }
+        let new = self.alloc(new_layout);
+        if !new.is_null() {
+            ptr::copy_nonoverlapping(ptr, new, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new
+    }
+}
+
+// The host's allocator serves tests.
+#[cfg(target_arch = "powerpc")]
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator;
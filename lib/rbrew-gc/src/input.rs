@@ -0,0 +1,174 @@
+//! The devices of the controller ports, which the serial interface polls.
+//!
+//! The devices are read through their own states, or through [`InputSource`], in the
+//! controls they have in common, for code that takes whichever the player uses:
+//!
+//! ```ignore
+//! use rbrew_gc::input::{Axis, Controls, InputSource};
+//!
+//! fn update(player: &mut Player, input: &impl InputSource) {
+//!     if !input.is_connected() {
+//!         // Pause until it is back.
+//!     }
+//!     player.walk(input.axis(Axis::LeftX));
+//!     if input.pressed(Controls::A) {
+//!         player.jump();
+//!     }
+//! }
+//! ```
+
+use rbrew_shared::{io::IoFlags, ioflags};
+
+pub mod keyboard;
+pub mod pad;
+pub mod si;
+
+/// The four controller ports, from the left.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Port {
+    One = 0,
+    Two = 1,
+    Three = 2,
+    Four = 3,
+}
+
+impl Port {
+    pub const ALL: [Port; 4] = [Port::One, Port::Two, Port::Three, Port::Four];
+}
+
+ioflags! {
+    /// The buttons the devices have in common, which each maps its own to.
+    pub struct Controls: u16 {
+        const A = 1 << 0;
+        const B = 1 << 1;
+        const X = 1 << 2;
+        const Y = 1 << 3;
+        const START = 1 << 4;
+        const SELECT = 1 << 5;
+        const HOME = 1 << 6;
+        const UP = 1 << 7;
+        const DOWN = 1 << 8;
+        const LEFT = 1 << 9;
+        const RIGHT = 1 << 10;
+        const L = 1 << 11;
+        const R = 1 << 12;
+        const Z = 1 << 13;
+    }
+}
+
+/// The analog controls the devices have in common. The sticks and pointers read from -1 to
+/// 1, right and up positive for the sticks, right and down for the pointers as the
+/// screen's, and the triggers from 0 released to 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    TriggerL,
+    TriggerR,
+    PointerX,
+    PointerY,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 8] = [
+        Axis::LeftX,
+        Axis::LeftY,
+        Axis::RightX,
+        Axis::RightY,
+        Axis::TriggerL,
+        Axis::TriggerR,
+        Axis::PointerX,
+        Axis::PointerY,
+    ];
+}
+
+/// A device read in the controls the devices have in common, as it was at its last scan.
+pub trait InputSource {
+    /// Whether the device answered its last scan. Its controls read as released otherwise.
+    fn is_connected(&self) -> bool;
+
+    fn controls(&self) -> Controls;
+
+    /// The controls held at the scan before.
+    fn previous_controls(&self) -> Controls;
+
+    /// The position of `axis`, 0 for the axes the device does not have.
+    fn axis(&self, axis: Axis) -> f32;
+
+    /// Whether any of `controls` is held down.
+    #[inline]
+    fn held(&self, controls: Controls) -> bool {
+        self.controls().intersects(controls)
+    }
+
+    /// Whether any of `controls` went down since the scan before.
+    #[inline]
+    fn pressed(&self, controls: Controls) -> bool {
+        (self.controls() - self.previous_controls()).intersects(controls)
+    }
+
+    /// Whether any of `controls` went up since the scan before.
+    #[inline]
+    fn released(&self, controls: Controls) -> bool {
+        (self.previous_controls() - self.controls()).intersects(controls)
+    }
+}
+
+/// The controls of a device at a scan, as recorded to be played back.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub connected: bool,
+    pub controls: Controls,
+    pub previous: Controls,
+    /// The axes, in the order of [`Axis::ALL`].
+    pub axes: [f32; 8],
+}
+
+impl Snapshot {
+    /// The controls `source` reads.
+    pub fn of(source: &impl InputSource) -> Self {
+        Self {
+            connected: source.is_connected(),
+            controls: source.controls(),
+            previous: source.previous_controls(),
+            axes: Axis::ALL.map(|axis| source.axis(axis)),
+        }
+    }
+}
+
+impl InputSource for Snapshot {
+    #[inline]
+    fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    #[inline]
+    fn controls(&self) -> Controls {
+        self.controls
+    }
+
+    #[inline]
+    fn previous_controls(&self) -> Controls {
+        self.previous
+    }
+
+    #[inline]
+    fn axis(&self, axis: Axis) -> f32 {
+        self.axes[axis as usize]
+    }
+}
+
+/// The controls of a device's `buttons`, each of the buttons of `mapping` held setting
+/// its control.
+pub fn map_controls<B: IoFlags>(buttons: B, mapping: &[(B, Controls)]) -> Controls {
+    let bits = buttons.into_bits();
+    mapping
+        .iter()
+        .filter(|(button, _)| bits & button.into_bits() != 0)
+        .fold(Controls::empty(), |controls, &(_, control)| {
+            controls | control
+        })
+}
@@ -0,0 +1,313 @@
+//! The ASCII keyboard controller, which reports up to three keys held at each poll.
+//!
+//! Keys are scancodes named after the keys of a US keyboard at their position, and a
+//! [`Layout`] maps them to characters:
+//!
+//! ```ignore
+//! use rbrew_gc::input::{
+//!     keyboard::{KeyEvent, Keyboard, Layout},
+//!     Port,
+//! };
+//!
+//! let mut keyboard = Keyboard::detect(Port::One).expect("no keyboard in port 1");
+//! loop {
+//!     video.wait_for_retrace();
+//!     for event in keyboard.scan() {
+//!         if let KeyEvent::Pressed(key) = event {
+//!             if let Some(c) = keyboard.char(key, &Layout::US) {
+//!                 print!("{c}");
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+
+use super::{si, Axis, Controls, InputSource, Port};
+
+/// The command polling a keyboard.
+const POLL_COMMAND: u32 = 0x54_0000;
+
+/// The scancodes the keyboard reports the keys held with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Key(pub u8);
+
+impl Key {
+    pub const HOME: Key = Key(0x06);
+    pub const END: Key = Key(0x07);
+    pub const PAGE_UP: Key = Key(0x08);
+    pub const PAGE_DOWN: Key = Key(0x09);
+    pub const SCROLL_LOCK: Key = Key(0x0a);
+    /// The letters, `A` to `Z` in order.
+    pub const A: Key = Key(0x10);
+    pub const Z: Key = Key(0x29);
+    /// The digits, `1` to `9` then `0`.
+    pub const DIGIT_1: Key = Key(0x2a);
+    pub const DIGIT_0: Key = Key(0x33);
+    pub const MINUS: Key = Key(0x34);
+    pub const PLUS: Key = Key(0x35);
+    pub const PRINT_SCREEN: Key = Key(0x36);
+    pub const BRACE_OPEN: Key = Key(0x37);
+    pub const BRACE_CLOSE: Key = Key(0x38);
+    pub const COLON: Key = Key(0x39);
+    pub const QUOTE: Key = Key(0x3a);
+    pub const HASH: Key = Key(0x3b);
+    pub const COMMA: Key = Key(0x3c);
+    pub const PERIOD: Key = Key(0x3d);
+    pub const QUESTION_MARK: Key = Key(0x3e);
+    pub const INTERNATIONAL_1: Key = Key(0x3f);
+    /// The function keys, `F1` to `F12` in order.
+    pub const F1: Key = Key(0x40);
+    pub const F12: Key = Key(0x4b);
+    pub const ESCAPE: Key = Key(0x4c);
+    pub const INSERT: Key = Key(0x4d);
+    pub const DELETE: Key = Key(0x4e);
+    pub const TILDE: Key = Key(0x4f);
+    pub const BACKSPACE: Key = Key(0x50);
+    pub const TAB: Key = Key(0x51);
+    pub const CAPS_LOCK: Key = Key(0x53);
+    pub const LEFT_SHIFT: Key = Key(0x54);
+    pub const RIGHT_SHIFT: Key = Key(0x55);
+    pub const LEFT_CONTROL: Key = Key(0x56);
+    pub const RIGHT_ALT: Key = Key(0x57);
+    pub const LEFT_WINDOWS: Key = Key(0x58);
+    pub const SPACE: Key = Key(0x59);
+    pub const RIGHT_WINDOWS: Key = Key(0x5a);
+    pub const MENU: Key = Key(0x5b);
+    pub const LEFT: Key = Key(0x5c);
+    pub const DOWN: Key = Key(0x5d);
+    pub const UP: Key = Key(0x5e);
+    pub const RIGHT: Key = Key(0x5f);
+    pub const ENTER: Key = Key(0x61);
+
+    #[inline]
+    pub fn is_shift(self) -> bool {
+        self == Key::LEFT_SHIFT || self == Key::RIGHT_SHIFT
+    }
+
+    #[inline]
+    pub fn is_letter(self) -> bool {
+        (Key::A.0..=Key::Z.0).contains(&self.0)
+    }
+}
+
+/// A key going down or up between two scans.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyEvent {
+    Pressed(Key),
+    Released(Key),
+}
+
+/// The scancodes of a layout, those up to 0x61.
+const KEYS: usize = 0x62;
+
+/// The characters of the keys, unshifted and shifted, 0 for keys without one.
+#[derive(Clone, Debug)]
+pub struct Layout {
+    normal: [u8; KEYS],
+    shifted: [u8; KEYS],
+}
+
+impl Layout {
+    /// The characters of the keys by their scancode, unshifted and shifted, 0 for keys
+    /// without one.
+    #[inline]
+    pub const fn new(normal: [u8; KEYS], shifted: [u8; KEYS]) -> Self {
+        Self { normal, shifted }
+    }
+
+    /// The keys mapped to the characters at their position on a US keyboard.
+    pub const US: Layout = Layout::new(
+        Self::table(b"1234567890", b"-=\0[];'\\,./", b'`'),
+        Self::table(b"!@#$%^&*()", b"_+\0{}:\"|<>?", b'~'),
+    );
+
+    /// The table of the letters, in upper case if `digits` are shifted, `digits` from
+    /// [`Key::DIGIT_1`], `symbols` from [`Key::MINUS`], `tilde`, and the control
+    /// characters of the space, tab, backspace and enter keys.
+    const fn table(digits: &[u8; 10], symbols: &[u8; 11], tilde: u8) -> [u8; KEYS] {
+        let mut table = [0; KEYS];
+        let first = if digits[0] == b'1' { b'a' } else { b'A' };
+        let mut i = 0;
+        while i < 26 {
+            table[Key::A.0 as usize + i] = first + i as u8;
+            i += 1;
+        }
+        let mut i = 0;
+        while i < digits.len() {
+            table[Key::DIGIT_1.0 as usize + i] = digits[i];
+            i += 1;
+        }
+        let mut i = 0;
+        while i < symbols.len() {
+            table[Key::MINUS.0 as usize + i] = symbols[i];
+            i += 1;
+        }
+        table[Key::TILDE.0 as usize] = tilde;
+        table[Key::SPACE.0 as usize] = b' ';
+        table[Key::TAB.0 as usize] = b'\t';
+        table[Key::BACKSPACE.0 as usize] = 0x08;
+        table[Key::ENTER.0 as usize] = b'\n';
+        table
+    }
+
+    /// The character of `key`, shifted if `shift`.
+    #[inline]
+    pub fn char(&self, key: Key, shift: bool) -> Option<char> {
+        let table = if shift { &self.shifted } else { &self.normal };
+        table
+            .get(key.0 as usize)
+            .filter(|&&c| c != 0)
+            .map(|&c| c as char)
+    }
+}
+
+/// A keyboard in a controller port, polled as a keyboard from its detection.
+pub struct Keyboard {
+    port: Port,
+    keys: [Key; 3],
+    previous: [Key; 3],
+    connected: bool,
+    caps_lock: bool,
+}
+
+/// The key reported in the slots of the keys not held.
+const NONE: Key = Key(0);
+
+impl Keyboard {
+    /// Detects a keyboard in `port`, polling it as a keyboard from the next retrace if
+    /// there is one. Its port no longer reads as a controller.
+    pub fn detect(port: Port) -> Option<Self> {
+        let device = si::device_type(port)?;
+        if device & si::DEVICE_TYPE_MASK != si::DEVICE_KEYBOARD {
+            return None;
+        }
+        si::set_command(port, POLL_COMMAND);
+        Some(Self {
+            port,
+            keys: [NONE; 3],
+            previous: [NONE; 3],
+            connected: true,
+            caps_lock: false,
+        })
+    }
+
+    #[inline]
+    pub fn port(&self) -> Port {
+        self.port
+    }
+
+    /// Reads the last poll of the keyboard, returning the keys that went up and down
+    /// since the scan before. No keys are held if it did not respond.
+    pub fn scan(&mut self) -> impl Iterator<Item = KeyEvent> {
+        let previous = self.keys;
+        let response = si::response(self.port);
+        self.connected = response.is_some();
+        self.previous = previous;
+        self.keys = match response {
+            // The keys are the first three bytes of the low word, then a checksum.
+            Some((_, low)) => {
+                let [a, b, c, _] = low.to_be_bytes();
+                [Key(a), Key(b), Key(c)]
+            }
+            None => [NONE; 3],
+        };
+        let keys = self.keys;
+        if keys.contains(&Key::CAPS_LOCK) && !previous.contains(&Key::CAPS_LOCK) {
+            self.caps_lock = !self.caps_lock;
+        }
+        let released = previous
+            .into_iter()
+            .filter(move |&key| key != NONE && !keys.contains(&key))
+            .map(KeyEvent::Released);
+        let pressed = keys
+            .into_iter()
+            .filter(move |&key| key != NONE && !previous.contains(&key))
+            .map(KeyEvent::Pressed);
+        released.chain(pressed)
+    }
+
+    /// The keys held at the last scan.
+    #[inline]
+    pub fn keys(&self) -> impl Iterator<Item = Key> {
+        self.keys.into_iter().filter(|&key| key != NONE)
+    }
+
+    #[inline]
+    pub fn is_held(&self, key: Key) -> bool {
+        key != NONE && self.keys.contains(&key)
+    }
+
+    #[inline]
+    pub fn is_shifted(&self) -> bool {
+        self.keys.iter().any(|key| key.is_shift())
+    }
+
+    #[inline]
+    pub fn caps_lock(&self) -> bool {
+        self.caps_lock
+    }
+
+    /// Whether the keyboard responded to the last poll.
+    #[inline]
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// The character `key` types in `layout` with the shift keys held and the caps lock,
+    /// which shifts the letters.
+    pub fn char(&self, key: Key, layout: &Layout) -> Option<char> {
+        let shift = self.is_shifted() ^ (self.caps_lock && key.is_letter());
+        layout.char(key, shift)
+    }
+}
+
+/// The controls of the keys: the arrows, and the keys confirming and cancelling.
+const CONTROLS: [(Key, Controls); 8] = [
+    (Key::UP, Controls::UP),
+    (Key::DOWN, Controls::DOWN),
+    (Key::LEFT, Controls::LEFT),
+    (Key::RIGHT, Controls::RIGHT),
+    (Key::SPACE, Controls::A),
+    (Key::BACKSPACE, Controls::B),
+    (Key::ENTER, Controls::START),
+    (Key::ESCAPE, Controls::SELECT),
+];
+
+/// The controls of the keys held in `keys`.
+fn controls(keys: [Key; 3]) -> Controls {
+    CONTROLS
+        .iter()
+        .filter(|(key, _)| keys.contains(key))
+        .fold(Controls::empty(), |controls, &(_, control)| {
+            controls | control
+        })
+}
+
+impl InputSource for Keyboard {
+    #[inline]
+    fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    #[inline]
+    fn controls(&self) -> Controls {
+        controls(self.keys)
+    }
+
+    #[inline]
+    fn previous_controls(&self) -> Controls {
+        controls(self.previous)
+    }
+
+    /// The arrows as the left stick, fully tilted while held.
+    fn axis(&self, axis: Axis) -> f32 {
+        let direction = |negative: Key, positive: Key| {
+            self.is_held(positive) as i8 as f32 - self.is_held(negative) as i8 as f32
+        };
+        match axis {
+            Axis::LeftX => direction(Key::LEFT, Key::RIGHT),
+            Axis::LeftY => direction(Key::DOWN, Key::UP),
+            _ => 0.0,
+        }
+    }
+}
@@ -0,0 +1,464 @@
+//! The GameCube controllers, which the serial interface polls in the four ports.
+//!
+//! The serial interface polls the controllers a few times a frame on its own, and
+//! [`PadContext::scan`] reads the last responses, once a frame:
+//!
+//! ```ignore
+//! use rbrew_gc::input::{
+//!     pad::{Buttons, PadContext},
+//!     Port,
+//! };
+//!
+//! let pad = PadContext::global();
+//! loop {
+//!     video.wait_for_retrace();
+//!     pad.scan();
+//!     if pad.state(Port::One).pressed(Buttons::A) {
+//!         // Jump.
+//!     }
+//! }
+//! ```
+
+use super::{map_controls, si, Axis, Controls, InputSource, Port};
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+use rbrew_shared::ioflags;
+use spin::Mutex;
+
+pub mod rumble;
+
+/// The command polling a controller, with the analog mode sending both sticks and
+/// triggers in full. Its low byte sets the motor.
+const POLL_COMMAND: u32 = 0x40_0300;
+
+/// Whether `port` is polled as a controller, rather than as another device.
+fn is_controller(port: Port) -> bool {
+    si::command(port) & !0xff == POLL_COMMAND
+}
+
+ioflags! {
+    /// The buttons of a controller, in the bits the controller reports them in.
+    pub struct Buttons: u16 {
+        const LEFT = 1 << 0;
+        const RIGHT = 1 << 1;
+        const DOWN = 1 << 2;
+        const UP = 1 << 3;
+        const Z = 1 << 4;
+        /// The digital buttons under the triggers, pressed at the end of their travel.
+        const R = 1 << 5;
+        const L = 1 << 6;
+        const A = 1 << 8;
+        const B = 1 << 9;
+        const X = 1 << 10;
+        const Y = 1 << 11;
+        const START = 1 << 12;
+    }
+}
+
+/// The position of a stick from its origin, from -128 at the left and bottom to 127.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stick {
+    pub x: i8,
+    pub y: i8,
+}
+
+impl Stick {
+    /// The stick at the raw position `x`, `y` from its raw origin.
+    #[inline]
+    fn from_raw([x, y]: [u8; 2], [origin_x, origin_y]: [u8; 2]) -> Self {
+        let axis = |raw: u8, origin: u8| (raw as i16 - origin as i16).clamp(-128, 127) as i8;
+        Self {
+            x: axis(x, origin_x),
+            y: axis(y, origin_y),
+        }
+    }
+
+    /// The position of the stick through `deadzone`, each axis from -1 to 1, within the
+    /// unit circle.
+    pub fn axes(self, deadzone: Deadzone) -> [f32; 2] {
+        let (x, y) = (self.x as f32, self.y as f32);
+        let length = libm::sqrtf(x * x + y * y);
+        if length <= deadzone.inner {
+            return [0.0; 2];
+        }
+        let scaled = ((length - deadzone.inner) / (deadzone.outer - deadzone.inner)).min(1.0);
+        [x * scaled / length, y * scaled / length]
+    }
+}
+
+/// A dead zone of a stick or a trigger, in the units they read in: the positions nearer
+/// than `inner` to the center read as centered, and those past `outer` as fully tilted,
+/// the ones in between scaled from the edge of the dead zone.
+///
+/// Sticks are dead zoned radially, by their distance to the center, so they keep their
+/// direction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Deadzone {
+    pub inner: f32,
+    pub outer: f32,
+}
+
+impl Deadzone {
+    /// The control stick, whose gate stops it about 100 from its center along the axes.
+    pub const STICK: Deadzone = Deadzone::new(16.0, 100.0);
+    /// The C stick, with a smaller gate.
+    pub const SUBSTICK: Deadzone = Deadzone::new(16.0, 80.0);
+    /// The triggers, whose digital buttons click about 200 deep.
+    pub const TRIGGER: Deadzone = Deadzone::new(24.0, 200.0);
+
+    /// Panics if `inner` is not below `outer`.
+    #[inline]
+    pub const fn new(inner: f32, outer: f32) -> Self {
+        assert!(inner < outer, "the dead zone ends before the full tilt");
+        Self { inner, outer }
+    }
+
+    /// The travel of a trigger through the dead zone, from 0 to 1.
+    pub fn trigger(self, value: u8) -> f32 {
+        ((value as f32 - self.inner) / (self.outer - self.inner)).clamp(0.0, 1.0)
+    }
+}
+
+/// The raw positions the sticks and triggers of a controller read from when centered and
+/// released, which its analog values are relative to.
+#[derive(Clone, Copy, Debug)]
+struct Origin {
+    stick: [u8; 2],
+    substick: [u8; 2],
+    triggers: [u8; 2],
+}
+
+impl Origin {
+    /// The origin assumed until a controller's is read.
+    const CENTER: Origin = Origin {
+        stick: [128; 2],
+        substick: [128; 2],
+        triggers: [0; 2],
+    };
+
+    /// Sends `command`, reading or recalibrating a controller's origin, to `port`.
+    fn read(port: Port, command: &[u8]) -> Option<Origin> {
+        // The buttons, the sticks and the triggers, then two bytes of nothing.
+        let mut response = [0; 10];
+        si::transfer(port, command, &mut response).then_some(Origin {
+            stick: [response[2], response[3]],
+            substick: [response[4], response[5]],
+            triggers: [response[6], response[7]],
+        })
+    }
+}
+
+/// The commands reading a controller's origin and recalibrating it, setting its origin to
+/// where its sticks and triggers are.
+const ORIGIN_COMMAND: [u8; 1] = [0x41];
+const RECALIBRATE_COMMAND: [u8; 3] = [0x42, 0, 0];
+
+/// A controller's state at the last [`PadContext::scan`], and its buttons and connection at
+/// the one before, to tell what changed in between.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PadState {
+    pub buttons: Buttons,
+    pub previous: Buttons,
+    /// The control stick, on the left.
+    pub stick: Stick,
+    /// The C stick, on the right.
+    pub substick: Stick,
+    /// The travel of the triggers from their origin, 0 released.
+    pub trigger_l: u8,
+    pub trigger_r: u8,
+    /// Whether a controller responded to the last poll. The state is empty otherwise.
+    pub connected: bool,
+    /// Whether a controller responded to the poll before.
+    pub was_connected: bool,
+}
+
+impl PadState {
+    /// The state of the response to a controller's poll, `high` and `low`.
+    fn from_response(high: u32, low: u32, previous: &PadState, origin: &Origin) -> Self {
+        let [_, _, x, y] = high.to_be_bytes();
+        let [sub_x, sub_y, trigger_l, trigger_r] = low.to_be_bytes();
+        Self {
+            buttons: Buttons::from_bits_truncate((high >> 16) as u16),
+            previous: previous.buttons,
+            stick: Stick::from_raw([x, y], origin.stick),
+            substick: Stick::from_raw([sub_x, sub_y], origin.substick),
+            trigger_l: trigger_l.saturating_sub(origin.triggers[0]),
+            trigger_r: trigger_r.saturating_sub(origin.triggers[1]),
+            connected: true,
+            was_connected: previous.connected,
+        }
+    }
+
+    /// Whether a controller was plugged in since the scan before.
+    #[inline]
+    pub fn plugged(self) -> bool {
+        self.connected && !self.was_connected
+    }
+
+    /// Whether the controller was unplugged since the scan before.
+    #[inline]
+    pub fn unplugged(self) -> bool {
+        !self.connected && self.was_connected
+    }
+
+    /// The control stick through `deadzone`, say [`Deadzone::STICK`].
+    #[inline]
+    pub fn stick_axes(self, deadzone: Deadzone) -> [f32; 2] {
+        self.stick.axes(deadzone)
+    }
+
+    /// The C stick through `deadzone`, say [`Deadzone::SUBSTICK`].
+    #[inline]
+    pub fn substick_axes(self, deadzone: Deadzone) -> [f32; 2] {
+        self.substick.axes(deadzone)
+    }
+
+    /// The travel of the left and right triggers through `deadzone`, say
+    /// [`Deadzone::TRIGGER`].
+    #[inline]
+    pub fn triggers(self, deadzone: Deadzone) -> [f32; 2] {
+        [
+            deadzone.trigger(self.trigger_l),
+            deadzone.trigger(self.trigger_r),
+        ]
+    }
+
+    /// Whether any of `buttons` is held down.
+    #[inline]
+    pub fn held(self, buttons: Buttons) -> bool {
+        self.buttons.intersects(buttons)
+    }
+
+    /// Whether any of `buttons` went down since the scan before.
+    #[inline]
+    pub fn pressed(self, buttons: Buttons) -> bool {
+        (self.buttons - self.previous).intersects(buttons)
+    }
+
+    /// Whether any of `buttons` went up since the scan before.
+    #[inline]
+    pub fn released(self, buttons: Buttons) -> bool {
+        (self.previous - self.buttons).intersects(buttons)
+    }
+}
+
+/// The controls of the buttons, as the controller's are printed.
+const CONTROLS: [(Buttons, Controls); 12] = [
+    (Buttons::A, Controls::A),
+    (Buttons::B, Controls::B),
+    (Buttons::X, Controls::X),
+    (Buttons::Y, Controls::Y),
+    (Buttons::START, Controls::START),
+    (Buttons::UP, Controls::UP),
+    (Buttons::DOWN, Controls::DOWN),
+    (Buttons::LEFT, Controls::LEFT),
+    (Buttons::RIGHT, Controls::RIGHT),
+    (Buttons::L, Controls::L),
+    (Buttons::R, Controls::R),
+    (Buttons::Z, Controls::Z),
+];
+
+impl InputSource for PadState {
+    #[inline]
+    fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    #[inline]
+    fn controls(&self) -> Controls {
+        map_controls(self.buttons, &CONTROLS)
+    }
+
+    #[inline]
+    fn previous_controls(&self) -> Controls {
+        map_controls(self.previous, &CONTROLS)
+    }
+
+    /// The sticks and triggers through their default dead zones. A controller has no
+    /// pointer.
+    fn axis(&self, axis: Axis) -> f32 {
+        match axis {
+            Axis::LeftX => self.stick_axes(Deadzone::STICK)[0],
+            Axis::LeftY => self.stick_axes(Deadzone::STICK)[1],
+            Axis::RightX => self.substick_axes(Deadzone::SUBSTICK)[0],
+            Axis::RightY => self.substick_axes(Deadzone::SUBSTICK)[1],
+            Axis::TriggerL => self.triggers(Deadzone::TRIGGER)[0],
+            Axis::TriggerR => self.triggers(Deadzone::TRIGGER)[1],
+            Axis::PointerX | Axis::PointerY => 0.0,
+        }
+    }
+}
+
+/// The state of the ports at the last scan.
+static STATES: Mutex<[PadState; 4]> = Mutex::new(
+    [PadState {
+        buttons: Buttons::empty(),
+        previous: Buttons::empty(),
+        stick: Stick { x: 0, y: 0 },
+        substick: Stick { x: 0, y: 0 },
+        trigger_l: 0,
+        trigger_r: 0,
+        connected: false,
+        was_connected: false,
+    }; 4],
+);
+
+/// A controller plugged in or unplugged, between two scans.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortEvent {
+    Plugged(Port),
+    Unplugged(Port),
+}
+
+/// The `fn(PortEvent)` called by [`PadContext::scan`] for each event, null if there is none.
+static EVENT_CALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// The origins of the controllers, read when they are first scanned and when they report
+/// them changed, `None` until then.
+static ORIGINS: Mutex<[Option<Origin>; 4]> = Mutex::new([None; 4]);
+
+static IS_INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum PadInitError {
+    AlreadyInitialized,
+}
+
+#[derive(Clone, Copy)]
+pub struct PadContext {
+    // Makes the type non-trivially constructible.
+    _mark: PhantomData<()>,
+}
+
+impl PadContext {
+    fn init_pad() -> Result<(), PadInitError> {
+        for port in Port::ALL.into_iter().filter(|&port| si::command(port) == 0) {
+            si::set_command(port, POLL_COMMAND);
+        }
+        Ok(())
+    }
+
+    /// Polls the controllers of the four ports, but the ones polled as other devices.
+    pub fn init() -> Result<(), PadInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
+            Err(PadInitError::AlreadyInitialized)
+        } else {
+            Self::init_pad()
+        }
+    }
+
+    /// The global controller context, initialized if it was not.
+    pub fn global() -> Self {
+        #[allow(unreachable_patterns)]
+        match Self::init() {
+            Err(PadInitError::AlreadyInitialized) | Ok(_) => {}
+            Err(e) => panic!("the controllers failed to initialize: {e:?}"),
+        }
+        unsafe { Self::global_unchecked() }
+    }
+
+    /// # Safety
+    /// Requires that the global controller context has been initialized.
+    /// This is ensured by [`Self::global`] or [`Self::init`].
+    pub unsafe fn global_unchecked() -> Self {
+        Self { _mark: PhantomData }
+    }
+
+    /// Sets how often the controllers are polled: `per_frame` times a frame, `lines`
+    /// apart. Programs reading them more than once a frame poll them more often.
+    ///
+    /// Panics if `lines` is over 1023 or `per_frame` is 0.
+    pub fn set_polling(self, lines: u16, per_frame: u8) {
+        assert!(lines < 1 << 10, "the lines between polls are at most 1023");
+        assert!(
+            per_frame != 0,
+            "the controllers are polled at least once a frame"
+        );
+        si::set_polling(lines as u32, per_frame as u32);
+    }
+
+    /// Reads the last polls of the four ports, the buttons held until now becoming the
+    /// previous ones.
+    ///
+    /// The origins of the controllers are read the first time they respond and whenever
+    /// they report them changed, which they do when their X, Y and Start buttons are held
+    /// for a few seconds.
+    pub fn scan(self) {
+        let mut states = STATES.lock();
+        let mut origins = ORIGINS.lock();
+        for port in Port::ALL {
+            let state = &mut states[port as usize];
+            let origin = &mut origins[port as usize];
+            let response = is_controller(port).then(|| si::response(port)).flatten();
+            *state = match response {
+                Some((high, low)) => {
+                    if origin.is_none() || si::SiInHigh(high).origin_changed() {
+                        *origin = Origin::read(port, &ORIGIN_COMMAND);
+                    }
+                    let origin = origin.as_ref().unwrap_or(&Origin::CENTER);
+                    PadState::from_response(high, low, state, origin)
+                }
+                None => {
+                    // A controller plugged in again has an origin of its own.
+                    *origin = None;
+                    PadState {
+                        previous: state.buttons,
+                        was_connected: state.connected,
+                        ..PadState::default()
+                    }
+                }
+            };
+        }
+        drop((states, origins));
+
+        let callback = EVENT_CALLBACK.load(Ordering::Acquire);
+        if !callback.is_null() {
+            let callback = unsafe { core::mem::transmute::<*mut (), fn(PortEvent)>(callback) };
+            self.events().for_each(callback);
+        }
+    }
+
+    /// The controllers plugged in and unplugged between the last two scans.
+    pub fn events(self) -> impl Iterator<Item = PortEvent> {
+        let states = *STATES.lock();
+        Port::ALL.into_iter().filter_map(move |port| {
+            let state = states[port as usize];
+            if state.plugged() {
+                Some(PortEvent::Plugged(port))
+            } else if state.unplugged() {
+                Some(PortEvent::Unplugged(port))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Sets the function [`Self::scan`] calls for each controller plugged in or unplugged,
+    /// returning the one set before.
+    pub fn set_event_callback(self, callback: Option<fn(PortEvent)>) -> Option<fn(PortEvent)> {
+        let new = callback.map_or(ptr::null_mut(), |callback| callback as *mut ());
+        let old = EVENT_CALLBACK.swap(new, Ordering::AcqRel);
+        (!old.is_null()).then(|| unsafe { core::mem::transmute::<*mut (), fn(PortEvent)>(old) })
+    }
+
+    /// Recalibrates the controller in `port`, its sticks and triggers reading as centered
+    /// and released where they are. Returns whether it responded.
+    pub fn recalibrate(self, port: Port) -> bool {
+        let origin = is_controller(port)
+            .then(|| Origin::read(port, &RECALIBRATE_COMMAND))
+            .flatten();
+        if origin.is_some() {
+            ORIGINS.lock()[port as usize] = origin;
+        }
+        origin.is_some()
+    }
+
+    /// The state of the controller in `port` at the last [`Self::scan`].
+    #[inline]
+    pub fn state(self, port: Port) -> PadState {
+        STATES.lock()[port as usize]
+    }
+}
@@ -0,0 +1,125 @@
+//! The motors of the controllers, set by the low byte of their poll and scheduled at each
+//! retrace.
+
+use super::{is_controller, si, PadContext, Port, POLL_COMMAND};
+use crate::{gfx::video::VideoContext, interrupt};
+use spin::{Mutex, Once};
+
+/// What a controller's motor does from its next poll.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Rumble {
+    /// Lets the motor spin down.
+    Stop = 0,
+    Start = 1,
+    /// Stops the motor at once.
+    Brake = 2,
+}
+
+/// Rumbling for `on` frames then resting for `off`, `repeats` times or until stopped if it
+/// is 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RumblePattern {
+    pub on: u16,
+    pub off: u16,
+    pub repeats: u16,
+}
+
+impl RumblePattern {
+    #[inline]
+    pub const fn new(on: u16, off: u16, repeats: u16) -> Self {
+        Self { on, off, repeats }
+    }
+
+    /// Rumbling once for `frames`.
+    #[inline]
+    pub const fn pulse(frames: u16) -> Self {
+        Self::new(frames, 0, 1)
+    }
+}
+
+/// A pattern being played: the frame of its period and the periods left, 0 for ever.
+#[derive(Clone, Copy)]
+struct Playing {
+    pattern: RumblePattern,
+    frame: u16,
+    left: u16,
+}
+
+/// The patterns of the ports. Locked with interrupts disabled, as the retrace callback
+/// locks it too.
+static PATTERNS: Mutex<[Option<Playing>; 4]> = Mutex::new([None; 4]);
+/// The retrace callback set before the scheduler's, which it calls.
+static CHAINED: Once<Option<fn()>> = Once::new();
+
+/// Sets the motor of `port` from its next poll, if it is polled as a controller.
+fn send(port: Port, rumble: Rumble) {
+    if is_controller(port) {
+        si::set_command(port, POLL_COMMAND | rumble as u32);
+    }
+}
+
+/// Advances the patterns by a frame, called at each retrace.
+fn tick() {
+    let mut patterns = PATTERNS.lock();
+    for port in Port::ALL {
+        let slot = &mut patterns[port as usize];
+        let Some(playing) = slot else {
+            continue;
+        };
+        let pattern = playing.pattern;
+        playing.frame += 1;
+        if playing.frame == pattern.on {
+            send(port, Rumble::Stop);
+        }
+        if playing.frame >= pattern.on + pattern.off {
+            if playing.left == 1 {
+                *slot = None;
+                continue;
+            }
+            playing.left = playing.left.saturating_sub(1);
+            playing.frame = 0;
+            send(port, Rumble::Start);
+        }
+    }
+    drop(patterns);
+    if let Some(Some(chained)) = CHAINED.get() {
+        chained();
+    }
+}
+
+impl PadContext {
+    /// Sets the motor of the controller in `port`, cancelling its pattern.
+    pub fn set_rumble(self, port: Port, rumble: Rumble) {
+        interrupt::free(|| {
+            PATTERNS.lock()[port as usize] = None;
+            send(port, rumble);
+        });
+    }
+
+    /// Plays `pattern` on the controller in `port` from the next poll, replacing the one
+    /// it was playing.
+    ///
+    /// The patterns advance at each retrace, by a retrace callback set the first time
+    /// which calls the one set before. Programs setting a retrace callback of their own
+    /// afterwards call the one it replaces, which
+    /// [`VideoContext::set_retrace_callback`] returns.
+    pub fn rumble(self, port: Port, pattern: RumblePattern) {
+        CHAINED.call_once(|| VideoContext::global().set_retrace_callback(Some(tick)));
+        interrupt::free(|| {
+            PATTERNS.lock()[port as usize] = (pattern.on != 0).then_some(Playing {
+                pattern,
+                frame: 0,
+                left: pattern.repeats,
+            });
+            send(
+                port,
+                if pattern.on != 0 {
+                    Rumble::Start
+                } else {
+                    Rumble::Stop
+                },
+            );
+        });
+    }
+}
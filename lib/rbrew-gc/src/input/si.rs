@@ -0,0 +1,167 @@
+//! The serial interface, which polls the devices of the four controller ports with the
+//! command each is sent, and runs single transfers to identify them.
+
+use super::Port;
+use crate::interrupt;
+use core::sync::atomic::{AtomicU32, Ordering};
+use rbrew_shared::iotype;
+
+iotype! {
+    /// The serial interface, the controller ports.
+    pub type SI: 0x0c006400, 0x100, cached 0x8000_0000, uncached 0xc000_0000 {
+        /// The command each port polls its device with, its three bytes from bit 23.
+        out: mut [u32; 4] = 0x00 stride 12,
+        /// The first four bytes of the responses, from the most significant.
+        in_high: const [u32; 4] = 0x04 stride 12 {
+            /// Set when the device did not respond.
+            error_status: 31,
+            /// Set when a response had an error since the register was last read.
+            error_latch: 30,
+            /// Set by controllers when their origin changed, until it is read.
+            origin_changed: 29,
+        },
+        /// The last four bytes of the responses.
+        in_low: const [u32; 4] = 0x08 stride 12,
+        /// How often the ports are polled.
+        poll: mut u32 {
+            /// Copies the commands to the ports at the next retrace rather than at once, a
+            /// bit each from port 4.
+            vblank_copy: 0..=3,
+            /// The ports polled, a bit each from port 4.
+            enable: 4..=7,
+            /// The polls in each frame.
+            y: 8..=15,
+            /// The lines between them.
+            x: 16..=25,
+        },
+        /// A transfer through the buffer, to a single port.
+        comcs: mut u32 {
+            start: 0,
+            channel: 1..=2,
+            /// The bytes read, 0 being 128.
+            in_length: 8..=14,
+            /// The bytes written, 0 being 128.
+            out_length: 16..=22,
+            read_status_int_mask: 27,
+            read_status_int: 28,
+            /// Set when the transfer had an error.
+            error: 29,
+            /// The transfer complete interrupt.
+            tc_int_mask: 30,
+            tc_int: 31,
+        },
+        /// The errors of the ports, eight bits each from port 1 in the high byte.
+        sr: mut u32 {
+            /// Copies the commands to the ports, cleared once they are.
+            write: 31,
+        },
+        exilk: mut u32,
+        reserved 0x40..0x80,
+        /// The bytes of the transfers of `comcs`.
+        buffer: mut [u32; 32],
+    }
+}
+
+/// The device types a device responds to the identify command with, in its three bytes
+/// from bit 31.
+pub(crate) const DEVICE_TYPE_MASK: u32 = 0xffff_0000;
+pub(crate) const DEVICE_KEYBOARD: u32 = 0x0820_0000;
+
+/// The lines between two polls and the polls in each frame, twice a field of every mode.
+const POLL_LINES: u32 = 120;
+const POLLS: u32 = 2;
+
+/// The command each port is polled with, 0 if it is not polled.
+static COMMANDS: [AtomicU32; 4] = [const { AtomicU32::new(0) }; 4];
+
+/// The bits of the ports in `poll`'s fields, port 1 the most significant.
+const fn port_bit(port: Port) -> u32 {
+    1 << (3 - port as u32)
+}
+
+/// Polls `port` with `command` from the next retrace, setting the default polling if no
+/// port was polled.
+pub(crate) fn set_command(port: Port, command: u32) {
+    // The rumble patterns set commands at each retrace.
+    interrupt::free(|| unsafe {
+        COMMANDS[port as usize].store(command, Ordering::Relaxed);
+        SI::out_write(port as usize, command);
+        SI::sr_write(SiSr::default().with_write(true).0);
+        let mut poll = SiPoll(SI::poll_read());
+        if poll.enable() == 0 {
+            poll = poll.with_x(POLL_LINES).with_y(POLLS);
+        }
+        let ports = poll.enable() | port_bit(port);
+        SI::poll_write(poll.with_enable(ports).with_vblank_copy(ports).0);
+    });
+}
+
+/// The command `port` is polled with, 0 if it is not polled.
+#[inline]
+pub(crate) fn command(port: Port) -> u32 {
+    COMMANDS[port as usize].load(Ordering::Relaxed)
+}
+
+/// Sets how often the polled ports are polled: `per_frame` times a frame, `lines` apart.
+pub(crate) fn set_polling(lines: u32, per_frame: u32) {
+    unsafe {
+        let poll = SiPoll(SI::poll_read()).with_x(lines).with_y(per_frame);
+        SI::poll_write(poll.0);
+    }
+}
+
+/// The last response to the poll of `port`, its high and low words, or `None` if its
+/// device did not respond.
+pub(crate) fn response(port: Port) -> Option<(u32, u32)> {
+    let (high, low) = unsafe {
+        (
+            SI::in_high_read(port as usize),
+            SI::in_low_read(port as usize),
+        )
+    };
+    (!SiInHigh(high).error_status()).then_some((high, low))
+}
+
+/// Sends `out` to the device of `port` and reads its response into `input`, returning
+/// whether it responded in full.
+pub(crate) fn transfer(port: Port, out: &[u8], input: &mut [u8]) -> bool {
+    assert!(
+        (1..=128).contains(&out.len()) && (1..=128).contains(&input.len()),
+        "transfers are of 1 to 128 bytes"
+    );
+    let mut words = [0u32; 32];
+    for (i, &byte) in out.iter().enumerate() {
+        words[i / 4] |= (byte as u32) << (24 - 8 * (i % 4));
+    }
+    unsafe {
+        for (index, &word) in words[..out.len().div_ceil(4)].iter().enumerate() {
+            SI::buffer_write(index, word);
+        }
+        SI::comcs_write(
+            SiComcs::default()
+                .with_start(true)
+                .with_channel(port as u32)
+                .with_in_length(input.len() as u32 % 128)
+                .with_out_length(out.len() as u32 % 128)
+                .with_tc_int(true)
+                .0,
+        );
+        while SI::comcs_start_read() {
+            core::hint::spin_loop();
+        }
+        if SI::comcs_error_read() {
+            return false;
+        }
+        for (i, byte) in input.iter_mut().enumerate() {
+            *byte = (SI::buffer_read(i / 4) >> (24 - 8 * (i % 4))) as u8;
+        }
+    }
+    true
+}
+
+/// The type of the device in `port`, in its three bytes from bit 31, or `None` if there
+/// is none.
+pub(crate) fn device_type(port: Port) -> Option<u32> {
+    let mut id = [0; 3];
+    transfer(port, &[0x00], &mut id).then(|| u32::from_be_bytes([id[0], id[1], id[2], 0]))
+}
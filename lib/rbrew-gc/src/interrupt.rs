@@ -0,0 +1,323 @@
+//! External interrupts, which the devices raise through the processor interface.
+//!
+//! Handlers are `fn()`s registered with `#[interrupt]`, which programs using it depend on
+//! `rbrew-shared` for:
+//!
+//! ```ignore
+//! use rbrew_gc::interrupt::{self, Source};
+//! use rbrew_shared::interrupt;
+//!
+//! #[interrupt(Source::Vi)]
+//! fn retrace() {
+//!     // Acknowledge the interrupt at the VI.
+//! }
+//!
+//! unsafe { interrupt::init() };
+//! interrupt::enable();
+//! ```
+//!
+//! The linker script collects the handlers in the `.rbrew.interrupts` section, between
+//! `__interrupts_start` and `__interrupts_end`. When an interrupt is taken, the handlers
+//! of every pending source run in the order they were linked, with interrupts disabled and
+//! without the floating point unit. A handler must acknowledge the interrupt at its
+//! device, or it is raised again as soon as the handler returns. Sources that are pending
+//! without a handler are masked.
+//!
+//! rbrew-gc also provides the `critical_section` implementation of the console, which
+//! disables the external interrupt.
+
+use rbrew_shared::{iotype, irq::Handler};
+
+iotype! {
+    /// The processor interface: its interrupt registers, and the FIFO of the graphics
+    /// processor, which `gfx::gx` writes.
+    pub type PI: 0x0c003000, 0x100, cached 0x8000_0000, uncached 0xc000_0000 {
+        /// The pending interrupts, a bit for each [`Source`] raising its line.
+        intsr: mut u32,
+        /// The sources that raise the external interrupt, updated by handlers too.
+        #[critical]
+        intmr: mut u32,
+        reserved 0x08..0x0c,
+        /// The physical addresses of the FIFO the write-gather pipe writes to.
+        fifo_base: mut u32,
+        fifo_end: mut u32,
+        fifo_write: mut u32,
+        reserved 0x18..0x24,
+        /// Holds the system and its devices in reset while their bits are clear.
+        reset: mut u32 {
+            system: 0,
+            memory: 1,
+            di: 2,
+        },
+    }
+}
+
+/// The devices raising interrupts, numbered by their bit in [`PI`]'s registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Source {
+    /// An error of the graphics processor.
+    GpError = 0,
+    /// The reset button.
+    ResetSwitch = 1,
+    /// The disc interface.
+    Di = 2,
+    /// The serial interface, the controller ports.
+    Si = 3,
+    /// The external interface, memory cards and other EXI devices.
+    Exi = 4,
+    /// The audio interface's streaming.
+    Ai = 5,
+    /// The DSP, also raising the interrupts of the audio DMA and ARAM.
+    Dsp = 6,
+    /// The memory interface.
+    Mem = 7,
+    /// The video interface's display interrupts.
+    Vi = 8,
+    /// A token of the pixel engine.
+    PeToken = 9,
+    /// The pixel engine finishing a draw.
+    PeFinish = 10,
+    /// The command processor's FIFO.
+    Cp = 11,
+    Debug = 12,
+    /// The high speed port.
+    Hsp = 13,
+    /// Hollywood's own interrupts, on the Wii.
+    Hollywood = 14,
+}
+
+/// The bits of every [`Source`].
+const SOURCES: u32 = (1 << 15) - 1;
+
+// The vector is copied to 0x500 and runs untranslated, without a usable stack: it keeps r3,
+// SRR0 and SRR1 in the SPRGs and returns from the exception into the entry, with
+// translation on. The entry saves the registers calls don't preserve on the interrupted
+// stack, which the EABI keeps no red zone below, and dispatches.
+#[cfg(target_arch = "powerpc")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.interrupt, "ax"
+    .global __rbrew_interrupt_vector
+    .global __rbrew_interrupt_vector_end
+__rbrew_interrupt_vector:
+    mtsprg 0, 3
+    mfsrr0 3
+    mtsprg 1, 3
+    mfsrr1 3
+    mtsprg 2, 3
+    lis 3, __rbrew_interrupt_entry@ha
+    addi 3, 3, __rbrew_interrupt_entry@l
+    mtsrr0 3
+    mfmsr 3
+    ori 3, 3, 0x30
+    mtsrr1 3
+    rfi
+__rbrew_interrupt_vector_end:
+
+__rbrew_interrupt_entry:
+    stwu 1, -80(1)
+    stw 0, 8(1)
+    mfsprg 0, 0
+    stw 0, 12(1)
+    stw 4, 16(1)
+    stw 5, 20(1)
+    stw 6, 24(1)
+    stw 7, 28(1)
+    stw 8, 32(1)
+    stw 9, 36(1)
+    stw 10, 40(1)
+    stw 11, 44(1)
+    stw 12, 48(1)
+    mfsprg 0, 1
+    stw 0, 52(1)
+    mfsprg 0, 2
+    stw 0, 56(1)
+    mfcr 0
+    stw 0, 60(1)
+    mflr 0
+    stw 0, 64(1)
+    mfctr 0
+    stw 0, 68(1)
+    mfxer 0
+    stw 0, 72(1)
+
+    bl __rbrew_dispatch_interrupts
+
+    lwz 0, 72(1)
+    mtxer 0
+    lwz 0, 68(1)
+    mtctr 0
+    lwz 0, 64(1)
+    mtlr 0
+    lwz 0, 60(1)
+    mtcr 0
+    lwz 0, 52(1)
+    mtsrr0 0
+    lwz 0, 56(1)
+    mtsrr1 0
+    lwz 3, 12(1)
+    lwz 4, 16(1)
+    lwz 5, 20(1)
+    lwz 6, 24(1)
+    lwz 7, 28(1)
+    lwz 8, 32(1)
+    lwz 9, 36(1)
+    lwz 10, 40(1)
+    lwz 11, 44(1)
+    lwz 12, 48(1)
+    lwz 0, 8(1)
+    addi 1, 1, 80
+    rfi
+"#
+);
+
+/// The handlers registered with `#[interrupt]`.
+fn handlers() -> &'static [Handler] {
+    #[cfg(target_arch = "powerpc")]
+    unsafe {
+        extern "C" {
+            static __interrupts_start: u8;
+            static __interrupts_end: u8;
+        }
+        let start = (&raw const __interrupts_start).cast::<Handler>();
+        let end = (&raw const __interrupts_end).cast::<Handler>();
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+    #[cfg(not(target_arch = "powerpc"))]
+    &[]
+}
+
+/// Called by the entry of the vector with interrupts disabled.
+#[no_mangle]
+extern "C" fn __rbrew_dispatch_interrupts() {
+    let pending = unsafe { PI::intsr_read() & PI::intmr_read() } & SOURCES;
+    for source in (0..15).filter(|source| pending & 1 << source != 0) {
+        let mut handled = false;
+        for handler in handlers().iter().filter(|handler| handler.source == source) {
+            (handler.handler)();
+            handled = true;
+        }
+        if !handled {
+            unsafe { PI::intmr_clear_bits(1 << source) };
+        }
+    }
+}
+
+/// Installs the exception vector and unmasks the sources with handlers, masking the
+/// others. Interrupts stay disabled until [`enable`].
+///
+/// # Safety
+///
+/// Replaces the exception vector of whatever installed it before, like the loader.
+pub unsafe fn init() {
+    disable();
+    #[cfg(target_arch = "powerpc")]
+    {
+        // The external interrupt exception vector, in the cached mirror.
+        const VECTOR: usize = 0x8000_0500;
+        extern "C" {
+            static __rbrew_interrupt_vector: u8;
+            static __rbrew_interrupt_vector_end: u8;
+        }
+        let start = &raw const __rbrew_interrupt_vector;
+        let len = (&raw const __rbrew_interrupt_vector_end).offset_from(start) as usize;
+        core::ptr::copy_nonoverlapping(start, VECTOR as *mut u8, len);
+        crate::cache::sync_code_range(VECTOR as *const u8, len);
+    }
+    let mask = handlers()
+        .iter()
+        .filter(|handler| handler.source < 15)
+        .fold(0, |mask, handler| mask | 1 << handler.source);
+    PI::intmr_write(mask);
+}
+
+/// Unmasks `source`, masked by [`init`] if it has no handler.
+pub fn unmask(source: Source) {
+    unsafe { PI::intmr_set_bits(1 << source as u32) };
+}
+
+/// Masks `source`, so it no longer raises the interrupt.
+pub fn mask(source: Source) {
+    unsafe { PI::intmr_clear_bits(1 << source as u32) };
+}
+
+/// Allows the external interrupt to be taken.
+pub fn enable() {
+    #[cfg(target_arch = "powerpc")]
+    unsafe {
+        core::arch::asm!(
+            "mfmsr {0}",
+            "ori {0}, {0}, 0x8000",
+            "mtmsr {0}",
+            out(reg) _,
+        )
+    };
+}
+
+/// Keeps the external interrupt from being taken, returning whether it was allowed, for
+/// [`restore`].
+pub fn disable() -> bool {
+    #[cfg(target_arch = "powerpc")]
+    unsafe {
+        let msr: u32;
+        core::arch::asm!(
+            "mfmsr {0}",
+            "rlwinm {1}, {0}, 0, 17, 15",
+            "mtmsr {1}",
+            out(reg) msr,
+            out(reg) _,
+        );
+        msr & 0x8000 != 0
+    }
+    #[cfg(not(target_arch = "powerpc"))]
+    false
+}
+
+/// Allows the external interrupt again if `enabled`, as returned by [`disable`].
+pub fn restore(enabled: bool) {
+    if enabled {
+        enable();
+    }
+}
+
+/// Runs `f` with the external interrupt disabled.
+pub fn free<R>(f: impl FnOnce() -> R) -> R {
+    let enabled = disable();
+    let result = f();
+    restore(enabled);
+    result
+}
+
+/// The critical sections of `critical_section`, which the read-modify-write functions of
+/// `#[critical]` registers run in, disable the external interrupt like [`free`].
+struct CriticalSection;
+
+critical_section::set_impl!(CriticalSection);
+
+unsafe impl critical_section::Impl for CriticalSection {
+    unsafe fn acquire() -> critical_section::RawRestoreState {
+        disable()
+    }
+
+    unsafe fn release(enabled: critical_section::RawRestoreState) {
+        restore(enabled)
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use rbrew_shared::io::mock;
+
+    #[test]
+    fn masking_keeps_the_other_sources() {
+        mock::reset();
+        unsafe { PI::intmr_write(1 << Source::Si as u32) };
+        unmask(Source::Vi);
+        unmask(Source::Exi);
+        mask(Source::Si);
+        let mask = unsafe { PI::intmr_read() };
+        assert_eq!(mask, 1 << Source::Vi as u32 | 1 << Source::Exi as u32);
+    }
+}
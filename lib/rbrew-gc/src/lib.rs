@@ -1,7 +1,48 @@
 /*!
 rbrew-gc is a library for writing homebrew gc programs in Rust.
+
+Programs are linked with `targets/gamecube.ld`. With the `runtime` feature, rbrew-gc
+provides their entry point, which calls the function marked with
+[`#[entry]`](runtime::entry) once `.bss` has been cleared and interrupts are set up.
+With the `heap` feature, `alloc` allocates from the rest of MEM1, see `heap`.
 */
 
 #![no_std]
 
+pub mod aram;
+pub mod audio;
+pub mod cache;
+pub mod dol;
+pub mod exi;
+pub mod gecko;
 pub mod gfx;
+#[cfg(feature = "heap")]
+pub mod heap;
+pub mod input;
+pub mod interrupt;
+pub mod net;
+pub mod rel;
+pub mod romfs;
+pub mod rtc;
+#[cfg(feature = "runtime")]
+pub mod runtime;
+pub mod storage;
+
+/// Waits at least `micros` microseconds, by the time base, which ticks at a quarter of the
+/// 162 MHz bus clock.
+pub(crate) fn delay(micros: u32) {
+    #[cfg(target_arch = "powerpc")]
+    {
+        let ticks = || {
+            let ticks: u32;
+            unsafe { core::arch::asm!("mftb {0}", out(reg) ticks) };
+            ticks
+        };
+        let start = ticks();
+        while ticks().wrapping_sub(start) < micros * 41 {
+            core::hint::spin_loop();
+        }
+    }
+    #[cfg(not(target_arch = "powerpc"))]
+    let _ = micros;
+}
@@ -0,0 +1,27 @@
+//! Networking: the broadband adapter, and the Ethernet frames network stacks exchange
+//! through it.
+
+pub mod bba;
+
+/// The longest Ethernet frame sent, from its destination address to the end of its
+/// payload. The adapter adds the checksum.
+pub const MAX_FRAME_LEN: usize = 1514;
+
+/// The shortest Ethernet frame sent, which shorter frames are padded to.
+pub const MIN_FRAME_LEN: usize = 60;
+
+/// A device sending and receiving Ethernet frames, which network stacks sit on.
+pub trait EthernetDevice {
+    type Error;
+
+    /// The device's MAC address, the source address of the frames it sends.
+    fn mac_address(&self) -> [u8; 6];
+
+    /// Sends `frame`, from its destination address to the end of its payload, of up to
+    /// [`MAX_FRAME_LEN`] bytes.
+    fn send(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+
+    /// Receives the next frame into `buffer`, cutting it to the buffer's length. Returns
+    /// the bytes received, or `None` if no frame was.
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, Self::Error>;
+}
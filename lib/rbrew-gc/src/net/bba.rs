@@ -0,0 +1,556 @@
+//! The broadband adapter, an Ethernet controller in the serial port 1, device 2 of the
+//! EXI's channel 0.
+//!
+//! Received frames are queued by the EXI interrupt, or by [`BbaContext::poll`] in
+//! programs that do not enable interrupts, until they are taken:
+//!
+//! ```ignore
+//! let bba = BbaContext::global();
+//! bba.send(&frame)?;
+//!
+//! let mut buffer = [0; net::MAX_FRAME_LEN];
+//! while let Some(len) = bba.receive(&mut buffer) {
+//!     stack.input(&buffer[..len]);
+//! }
+//! ```
+//!
+//! The adapter raises its interrupt while channel 0 may be locked, by slot A's memory card
+//! or SRAM. The frames it received are then taken by the next poll, send or receive.
+
+use super::{EthernetDevice, MAX_FRAME_LEN, MIN_FRAME_LEN};
+use crate::{
+    exi::{self, Bus, Clock},
+    interrupt::{self, Source},
+};
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicU8, Ordering},
+};
+use rbrew_shared::interrupt;
+use spin::Mutex;
+
+/// The device of the adapter on channel 0, and the channel it raises its interrupt on.
+const DEVICE: u32 = 2;
+const INT_CHANNEL: usize = 2;
+
+/// The identity the adapter replies with.
+const ID: u32 = 0x0402_0200;
+
+/// The registers of the adapter's EXI interface, with their own commands.
+const CMD_REVISION: u8 = 0x01;
+const CMD_INT_MASK: u8 = 0x02;
+const CMD_INT: u8 = 0x03;
+const CMD_DEVICE_ID: u8 = 0x04;
+const CMD_ACSTART: u8 = 0x05;
+const CMD_CHALLENGE: u8 = 0x08;
+const CMD_RESPONSE: u8 = 0x09;
+const CMD_AUTH_STATUS: u8 = 0x0b;
+const CMD_RESET: u8 = 0x0f;
+
+/// The masks of [`CMD_INT_MASK`], and the interrupts of [`CMD_INT`]: the controller's,
+/// a reset, an unknown one, the challenge of the console's authentication, and its end.
+const INT_MASK_ALL: u8 = 0x00;
+const INT_MASK_NONE: u8 = 0xf8;
+const INT_CONTROLLER: u8 = 0x80;
+const INT_RESET: u8 = 0x40;
+const INT_UNKNOWN: u8 = 0x20;
+const INT_CHALLENGE: u8 = 0x10;
+const INT_AUTH: u8 = 0x08;
+
+/// The identity and the start of the authentication the console gives the adapter.
+const DEVICE_ID: u16 = 0xd107;
+const ACSTART: u8 = 0x4e;
+
+/// The registers of the Ethernet controller.
+const NCRA: u16 = 0x00;
+const NCRB: u16 = 0x01;
+const IMR: u16 = 0x08;
+const IR: u16 = 0x09;
+const BP: u16 = 0x0a;
+const TLBP: u16 = 0x0c;
+const RXINTT: u16 = 0x14;
+const RWP: u16 = 0x16;
+const RRP: u16 = 0x18;
+const RHBP: u16 = 0x1a;
+const PAR: u16 = 0x20;
+const NWAYS: u16 = 0x31;
+const GCA: u16 = 0x32;
+const WRTXFIFOD: u16 = 0x48;
+const MISC2: u16 = 0x50;
+const SI_ACTRL2: u16 = 0x60;
+
+const NCRA_RESET: u8 = 1 << 0;
+const NCRA_ST0: u8 = 1 << 1;
+const NCRA_ST1: u8 = 1 << 2;
+const NCRA_SR: u8 = 1 << 3;
+const NCRB_PR: u8 = 1 << 0;
+const NCRB_CA: u8 = 1 << 1;
+const NCRB_PM: u8 = 1 << 2;
+const NCRB_AB: u8 = 1 << 4;
+const IR_RI: u8 = 1 << 1;
+const IR_FIFOEI: u8 = 1 << 5;
+const IR_RBFI: u8 = 1 << 7;
+const NWAYS_LS10: u8 = 1 << 0;
+const NWAYS_LS100: u8 = 1 << 1;
+const GCA_ARXERRB: u8 = 1 << 3;
+const MISC2_AUTORCVR: u8 = 1 << 7;
+
+/// The pages of 256 bytes of the controller's memory the frames are received in, which
+/// wrap from the last to the first.
+const RX_FIRST_PAGE: u16 = 0x01;
+const RX_LAST_PAGE: u16 = 0x0f;
+/// The descriptor each received frame starts with: the page of the next frame, the length
+/// of the frame with its descriptor, and its status.
+const DESCRIPTOR_LEN: usize = 4;
+/// The longest frame received, with its checksum.
+const MAX_RX_LEN: usize = 1536;
+
+/// The frames queued until they are taken.
+pub const RX_QUEUE_LEN: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BbaError {
+    /// The frame is longer than [`MAX_FRAME_LEN`].
+    TooLong,
+}
+
+/// Selects the adapter, sends the command `request` of `len` bytes, runs `f`, and
+/// deselects it, which ends the command.
+fn run<R>(bus: &mut Bus, request: u32, len: usize, f: impl FnOnce(&mut Bus) -> R) -> R {
+    bus.select(DEVICE, Clock::Mhz32);
+    bus.write_word(request, len);
+    let result = f(bus);
+    bus.deselect();
+    result
+}
+
+fn cmd_read8(bus: &mut Bus, register: u8) -> u8 {
+    run(bus, (register as u32) << 24, 2, |bus| {
+        bus.read_word(1) as u8
+    })
+}
+
+fn cmd_write(bus: &mut Bus, register: u8, data: &[u8]) {
+    let request = 0x4000_0000 | (register as u32) << 24;
+    run(bus, request, 2, |bus| bus.write(data));
+}
+
+fn read(bus: &mut Bus, address: u16, data: &mut [u8]) {
+    let request = 0x8000_0000 | (address as u32) << 8;
+    run(bus, request, 4, |bus| bus.read(data));
+}
+
+fn write(bus: &mut Bus, address: u16, data: &[u8]) {
+    let request = 0xc000_0000 | (address as u32) << 8;
+    run(bus, request, 4, |bus| bus.write(data));
+}
+
+fn read8(bus: &mut Bus, address: u16) -> u8 {
+    let mut byte = [0];
+    read(bus, address, &mut byte);
+    byte[0]
+}
+
+fn write8(bus: &mut Bus, address: u16, byte: u8) {
+    write(bus, address, &[byte]);
+}
+
+/// Reads a page pointer, 12 bits in little-endian.
+fn read12(bus: &mut Bus, address: u16) -> u16 {
+    let mut bytes = [0; 2];
+    read(bus, address, &mut bytes);
+    u16::from_le_bytes(bytes) & 0xfff
+}
+
+fn write12(bus: &mut Bus, address: u16, page: u16) {
+    write(bus, address, &(page & 0xfff).to_le_bytes());
+}
+
+/// The response to the authentication's `challenge`, from the adapter's revision and the
+/// console's identity.
+fn response(challenge: u32, revision: u8) -> u32 {
+    let [i0, i1, i2, i3] = challenge.to_be_bytes();
+    let [id0, id1] = DEVICE_ID.to_be_bytes();
+    let c0 = (i0
+        .wrapping_add(i1.wrapping_mul(0xc1))
+        .wrapping_add(0x18)
+        .wrapping_add(revision))
+        ^ i3.wrapping_mul(i2).wrapping_add(0x90);
+    let c1 = i1.wrapping_add(i2).wrapping_add(0x90) ^ c0.wrapping_add(i0).wrapping_sub(0xc1);
+    let c2 = i2.wrapping_add(0xc8)
+        ^ c0.wrapping_add(id0.wrapping_add(revision.wrapping_mul(0x23)) ^ 0x19);
+    let c3 = i0.wrapping_add(0xc1) ^ i3.wrapping_add(id1.wrapping_add(0xc8) ^ 0x90);
+    u32::from_be_bytes([c0, c1, c2, c3])
+}
+
+/// Resets the controller, as the adapter needs before it is set up.
+fn reset(bus: &mut Bus) {
+    write8(bus, SI_ACTRL2, 0);
+    crate::delay(10_000);
+    // Reading the reset register resets the adapter, once it had the time to settle.
+    run(bus, (CMD_RESET as u32) << 24, 2, |bus| {
+        crate::delay(200);
+        bus.read_word(1)
+    });
+    crate::delay(10_000);
+    write8(bus, NCRA, NCRA_RESET);
+    write8(bus, NCRA, 0);
+}
+
+/// Sets up the adapter after a reset, receiving in [`RX_FIRST_PAGE`] to [`RX_LAST_PAGE`]
+/// and raising the interrupts but the FIFO errors'.
+fn setup(bus: &mut Bus) {
+    let revision = cmd_read8(bus, CMD_REVISION);
+    REVISION.store(revision, Ordering::Relaxed);
+    cmd_write(bus, CMD_DEVICE_ID, &DEVICE_ID.to_be_bytes());
+    cmd_write(bus, CMD_ACSTART, &[ACSTART]);
+
+    // The registers of the serial interface to the PHY, as the IPL sets them.
+    let actrl = read8(bus, 0x5b);
+    write8(bus, 0x5b, actrl & !0x80);
+    write8(bus, 0x5e, 0x01);
+    let actrl = read8(bus, 0x5c);
+    write8(bus, 0x5c, actrl | 0x04);
+
+    write8(bus, NCRB, NCRB_AB | NCRB_CA);
+    write8(bus, SI_ACTRL2, 0x74);
+    write(bus, RXINTT, &[0x00, 0x06]);
+    write8(bus, MISC2, MISC2_AUTORCVR);
+    write12(bus, TLBP, 0);
+    write12(bus, BP, RX_FIRST_PAGE);
+    write12(bus, RHBP, RX_LAST_PAGE);
+    write12(bus, RWP, RX_FIRST_PAGE);
+    write12(bus, RRP, RX_FIRST_PAGE);
+    write8(bus, GCA, GCA_ARXERRB);
+    write8(bus, NCRA, NCRA_SR);
+
+    write8(bus, IR, 0xff);
+    write8(bus, IMR, !IR_FIFOEI);
+    cmd_write(bus, CMD_INT_MASK, &[INT_MASK_NONE]);
+}
+
+struct Received {
+    frames: [[u8; MAX_RX_LEN]; RX_QUEUE_LEN],
+    lens: [usize; RX_QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+static RECEIVED: Mutex<Received> = Mutex::new(Received {
+    frames: [[0; MAX_RX_LEN]; RX_QUEUE_LEN],
+    lens: [0; RX_QUEUE_LEN],
+    head: 0,
+    len: 0,
+});
+/// The frames dropped because the queue was full, or they were too long.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+static REVISION: AtomicU8 = AtomicU8::new(0);
+static AUTHENTICATED: AtomicBool = AtomicBool::new(false);
+/// Set while the adapter's interrupt waits for channel 0 to be unlocked.
+static PENDING: AtomicBool = AtomicBool::new(false);
+static RECEIVE_CALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+/// Takes the frames the controller received into the queue, returning how many.
+fn take_frames(bus: &mut Bus) -> usize {
+    let mut taken = 0;
+    let mut page = read12(bus, RRP);
+    loop {
+        let written = read12(bus, RWP);
+        if page == written {
+            break;
+        }
+        let mut descriptor = [0; DESCRIPTOR_LEN];
+        read(bus, page << 8, &mut descriptor);
+        let descriptor = u32::from_le_bytes(descriptor);
+        let next = descriptor as u16 & 0xfff;
+        let len = (descriptor >> 12 & 0xfff) as usize;
+        let len = len.saturating_sub(DESCRIPTOR_LEN);
+        if !(RX_FIRST_PAGE..=RX_LAST_PAGE).contains(&next) {
+            // A corrupt descriptor drops the frames after it.
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+            write12(bus, RRP, written);
+            break;
+        }
+
+        let mut received = RECEIVED.lock();
+        if received.len == RX_QUEUE_LEN || len > MAX_RX_LEN {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let tail = (received.head + received.len) % RX_QUEUE_LEN;
+            let frame = &mut received.frames[tail][..len];
+            // The frame wraps from the last page to the first.
+            let start = (page << 8) as usize + DESCRIPTOR_LEN;
+            let end = ((RX_LAST_PAGE + 1) << 8) as usize;
+            let (first, rest) = frame.split_at_mut(len.min(end.saturating_sub(start)));
+            read(bus, start as u16, first);
+            if !rest.is_empty() {
+                read(bus, RX_FIRST_PAGE << 8, rest);
+            }
+            received.lens[tail] = len;
+            received.len += 1;
+            taken += 1;
+        }
+        drop(received);
+
+        page = next;
+        write12(bus, RRP, page);
+    }
+    taken
+}
+
+/// Handles the interrupts the adapter raised, returning the frames received.
+fn service(bus: &mut Bus) -> usize {
+    let cause = cmd_read8(bus, CMD_INT);
+    cmd_write(bus, CMD_INT_MASK, &[INT_MASK_ALL]);
+    let mut taken = 0;
+    if cause & INT_CONTROLLER != 0 {
+        let status = read8(bus, IR) & read8(bus, IMR);
+        write8(bus, IR, status);
+        if status & (IR_RI | IR_RBFI) != 0 {
+            taken = take_frames(bus);
+        }
+    }
+    if cause & INT_RESET != 0 {
+        setup(bus);
+    }
+    if cause & INT_CHALLENGE != 0 {
+        cmd_write(bus, CMD_ACSTART, &[ACSTART]);
+        let mut challenge = [0; 4];
+        run(bus, (CMD_CHALLENGE as u32) << 24, 2, |bus| {
+            bus.read(&mut challenge)
+        });
+        let response = response(
+            u32::from_be_bytes(challenge),
+            REVISION.load(Ordering::Relaxed),
+        );
+        cmd_write(bus, CMD_RESPONSE, &response.to_be_bytes());
+    }
+    if cause & INT_AUTH != 0 {
+        let status = cmd_read8(bus, CMD_AUTH_STATUS);
+        AUTHENTICATED.store(status == 1, Ordering::Relaxed);
+    }
+    let handled = INT_CONTROLLER | INT_RESET | INT_UNKNOWN | INT_CHALLENGE | INT_AUTH;
+    cmd_write(bus, CMD_INT, &[cause & handled]);
+    cmd_write(bus, CMD_INT_MASK, &[INT_MASK_NONE]);
+    taken
+}
+
+/// Services the adapter if channel 0 is free, or leaves its interrupt pending, masked so
+/// that it stops being raised.
+fn try_service() {
+    let Some(mut bus) = Bus::try_lock(0) else {
+        PENDING.store(true, Ordering::Relaxed);
+        exi::set_int_mask_on(INT_CHANNEL, false);
+        return;
+    };
+    PENDING.store(false, Ordering::Relaxed);
+    let taken = service(&mut bus);
+    drop(bus);
+    exi::set_int_mask_on(INT_CHANNEL, true);
+    let callback = RECEIVE_CALLBACK.load(Ordering::Acquire);
+    if taken > 0 && !callback.is_null() {
+        let callback = unsafe { core::mem::transmute::<*mut (), fn()>(callback) };
+        callback();
+    }
+}
+
+#[interrupt(Source::Exi)]
+fn exi_interrupt() {
+    if IS_INIT.load(Ordering::Acquire) && exi::take_int_on(INT_CHANNEL) {
+        try_service();
+    }
+}
+
+static IS_INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum BbaInitError {
+    AlreadyInitialized,
+    /// There is no broadband adapter in the serial port 1.
+    NotPresent,
+}
+
+#[derive(Clone, Copy)]
+pub struct BbaContext {
+    // Makes the type non-trivially constructible.
+    _mark: PhantomData<()>,
+}
+
+impl BbaContext {
+    fn init_bba() -> Result<(), BbaInitError> {
+        interrupt::free(|| {
+            let mut bus = Bus::lock(0);
+            if run(&mut bus, 0, 2, |bus| bus.read_word(4)) != ID {
+                return Err(BbaInitError::NotPresent);
+            }
+            reset(&mut bus);
+            setup(&mut bus);
+            Ok(())
+        })?;
+        exi::set_int_mask_on(INT_CHANNEL, true);
+        Ok(())
+    }
+
+    /// Resets the adapter and sets it up to receive the frames to its MAC address and
+    /// broadcast, raising the EXI interrupt as it receives them.
+    pub fn init() -> Result<(), BbaInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
+            return Err(BbaInitError::AlreadyInitialized);
+        }
+        Self::init_bba().inspect_err(|_| IS_INIT.store(false, Ordering::Release))
+    }
+
+    /// The global BBA context, initialized if it was not.
+    pub fn global() -> Self {
+        #[allow(unreachable_patterns)]
+        match Self::init() {
+            Err(BbaInitError::AlreadyInitialized) | Ok(_) => {}
+            Err(e) => panic!("the bba failed to initialize: {e:?}"),
+        }
+        unsafe { Self::global_unchecked() }
+    }
+
+    /// # Safety
+    /// Requires that the global BBA context has been initialized.
+    /// This is ensured by [`Self::global`] or [`Self::init`].
+    pub unsafe fn global_unchecked() -> Self {
+        Self { _mark: PhantomData }
+    }
+
+    /// Takes the frames received and handles the adapter's interrupt, for programs that
+    /// do not enable interrupts or while it was left pending.
+    pub fn poll(self) {
+        interrupt::free(|| {
+            if exi::take_int_on(INT_CHANNEL) || PENDING.load(Ordering::Relaxed) {
+                try_service();
+            }
+        });
+    }
+
+    /// The adapter's MAC address, which it is set up with at the factory.
+    pub fn mac_address(self) -> [u8; 6] {
+        let mut mac = [0; 6];
+        interrupt::free(|| read(&mut Bus::lock(0), PAR, &mut mac));
+        mac
+    }
+
+    /// Changes the MAC address the adapter receives the frames to, until it is reset.
+    pub fn set_mac_address(self, mac: [u8; 6]) {
+        interrupt::free(|| write(&mut Bus::lock(0), PAR, &mac));
+    }
+
+    /// Receives all frames, whatever their destination, or only those to the adapter and
+    /// broadcast.
+    pub fn set_promiscuous(self, promiscuous: bool) {
+        self.set_ncrb(NCRB_PR, promiscuous);
+    }
+
+    /// Receives the multicast frames too.
+    pub fn set_multicast(self, multicast: bool) {
+        self.set_ncrb(NCRB_PM, multicast);
+    }
+
+    fn set_ncrb(self, flag: u8, value: bool) {
+        interrupt::free(|| {
+            let mut bus = Bus::lock(0);
+            let ncrb = read8(&mut bus, NCRB);
+            write8(
+                &mut bus,
+                NCRB,
+                if value { ncrb | flag } else { ncrb & !flag },
+            );
+        });
+    }
+
+    /// Whether the link is up, at 10 or 100 Mbit/s.
+    pub fn is_link_up(self) -> bool {
+        let nways = interrupt::free(|| read8(&mut Bus::lock(0), NWAYS));
+        nways & (NWAYS_LS10 | NWAYS_LS100) != 0
+    }
+
+    /// Whether the adapter accepted the console's authentication, which it asks for once
+    /// it is set up.
+    pub fn is_authenticated(self) -> bool {
+        AUTHENTICATED.load(Ordering::Relaxed)
+    }
+
+    /// The frames dropped since the start, as the queue was full.
+    pub fn dropped(self) -> u32 {
+        DROPPED.load(Ordering::Relaxed)
+    }
+
+    /// Sends `frame`, from its destination address to the end of its payload, padding it
+    /// to [`MIN_FRAME_LEN`]. Waits for the frame sent before to be.
+    pub fn send(self, frame: &[u8]) -> Result<(), BbaError> {
+        if frame.len() > MAX_FRAME_LEN {
+            return Err(BbaError::TooLong);
+        }
+        self.poll();
+        loop {
+            let sent = interrupt::free(|| {
+                let mut bus = Bus::lock(0);
+                let ncra = read8(&mut bus, NCRA);
+                if ncra & (NCRA_ST0 | NCRA_ST1) != 0 {
+                    return false;
+                }
+                let request = 0xc000_0000 | (WRTXFIFOD as u32) << 8;
+                run(&mut bus, request, 4, |bus| {
+                    bus.write(frame);
+                    bus.write(&[0; MIN_FRAME_LEN][..MIN_FRAME_LEN.saturating_sub(frame.len())]);
+                });
+                write8(&mut bus, NCRA, ncra | NCRA_ST1);
+                true
+            });
+            if sent {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Takes the next frame received into `buffer`, cutting it to the buffer's length.
+    /// Returns the bytes taken, or `None` if no frame was received.
+    pub fn receive(self, buffer: &mut [u8]) -> Option<usize> {
+        self.poll();
+        interrupt::free(|| {
+            let mut received = RECEIVED.lock();
+            if received.len == 0 {
+                return None;
+            }
+            let head = received.head;
+            let len = received.lens[head].min(buffer.len());
+            buffer[..len].copy_from_slice(&received.frames[head][..len]);
+            received.head = (head + 1) % RX_QUEUE_LEN;
+            received.len -= 1;
+            Some(len)
+        })
+    }
+
+    /// Sets the function called by the EXI interrupt handler as frames are received, with
+    /// interrupts disabled. Returns the previous one.
+    pub fn set_receive_callback(self, callback: Option<fn()>) -> Option<fn()> {
+        let new = callback.map_or(ptr::null_mut(), |callback| callback as *mut ());
+        let old = RECEIVE_CALLBACK.swap(new, Ordering::AcqRel);
+        (!old.is_null()).then(|| unsafe { core::mem::transmute::<*mut (), fn()>(old) })
+    }
+}
+
+impl EthernetDevice for BbaContext {
+    type Error = BbaError;
+
+    #[inline]
+    fn mac_address(&self) -> [u8; 6] {
+        BbaContext::mac_address(*self)
+    }
+
+    #[inline]
+    fn send(&mut self, frame: &[u8]) -> Result<(), BbaError> {
+        BbaContext::send(*self, frame)
+    }
+
+    #[inline]
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<Option<usize>, BbaError> {
+        Ok(BbaContext::receive(*self, buffer))
+    }
+}
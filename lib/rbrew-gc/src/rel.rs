@@ -0,0 +1,522 @@
+//! Linking of REL modules built with `rbrew build --output-type rel`.
+//!
+//! Modules are linked in place, like the SDK's `OSLink`: the image is patched so that its
+//! section table, import table and entry points hold absolute addresses, and it is then
+//! added to the global chain of linked modules. Imports between modules are resolved in
+//! both directions, so modules may be linked in any order.
+
+use crate::cache;
+use core::ptr::NonNull;
+use spin::Mutex;
+
+const R_PPC_NONE: u8 = 0;
+const R_PPC_ADDR32: u8 = 1;
+const R_PPC_ADDR24: u8 = 2;
+const R_PPC_ADDR16: u8 = 3;
+const R_PPC_ADDR16_LO: u8 = 4;
+const R_PPC_ADDR16_HI: u8 = 5;
+const R_PPC_ADDR16_HA: u8 = 6;
+const R_PPC_ADDR14: u8 = 7;
+const R_PPC_ADDR14_BRTAKEN: u8 = 8;
+const R_PPC_ADDR14_BRNTAKEN: u8 = 9;
+const R_PPC_REL24: u8 = 10;
+const R_PPC_REL14: u8 = 11;
+const R_PPC_REL14_BRTAKEN: u8 = 12;
+const R_PPC_REL14_BRNTAKEN: u8 = 13;
+const R_PPC_REL32: u8 = 26;
+const R_DOLPHIN_NOP: u8 = 201;
+const R_DOLPHIN_SECTION: u8 = 202;
+const R_DOLPHIN_END: u8 = 203;
+
+/// The header at the start of every REL image.
+#[repr(C)]
+pub struct ModuleHeader {
+    pub id: u32,
+    pub next: u32,
+    pub prev: u32,
+    pub num_sections: u32,
+    pub section_info_offset: u32,
+    pub name_offset: u32,
+    pub name_size: u32,
+    pub version: u32,
+    pub bss_size: u32,
+    pub rel_offset: u32,
+    pub imp_offset: u32,
+    pub imp_size: u32,
+    pub prolog_section: u8,
+    pub epilog_section: u8,
+    pub unresolved_section: u8,
+    pub bss_section: u8,
+    pub prolog: u32,
+    pub epilog: u32,
+    pub unresolved: u32,
+    pub align: u32,
+    pub bss_align: u32,
+    pub fix_size: u32,
+}
+
+#[repr(C)]
+struct SectionInfo {
+    /// The lowest bit marks executable sections.
+    offset: u32,
+    size: u32,
+}
+
+impl SectionInfo {
+    fn base(&self) -> usize {
+        (self.offset & !1) as usize
+    }
+}
+
+#[repr(C)]
+struct ImportInfo {
+    id: u32,
+    offset: u32,
+}
+
+#[repr(C)]
+struct RelocationEntry {
+    offset: u16,
+    kind: u8,
+    section: u8,
+    addend: u32,
+}
+
+#[derive(Debug)]
+pub enum RelLinkError {
+    UnsupportedVersion(u32),
+    Misaligned,
+    AlreadyLinked(u32),
+    UnsupportedRelocation(u8),
+    NotLinked,
+}
+
+/// The global chain of linked modules, threaded through the module headers.
+struct Chain {
+    head: usize,
+    tail: usize,
+}
+
+static LINKED: Mutex<Chain> = Mutex::new(Chain { head: 0, tail: 0 });
+
+impl Chain {
+    fn iter(&self) -> impl Iterator<Item = &'static mut ModuleHeader> {
+        let mut next = self.head;
+        core::iter::from_fn(move || {
+            let header = unsafe { (next as *mut ModuleHeader).as_mut()? };
+            next = header.next as usize;
+            Some(header)
+        })
+    }
+
+    fn find(&self, id: u32) -> Option<&'static mut ModuleHeader> {
+        self.iter().find(|header| header.id == id)
+    }
+}
+
+/// What the entries of an import resolve against.
+#[derive(Clone, Copy)]
+enum Target<'a> {
+    /// The main executable, whose addresses are absolute.
+    Main,
+    Module(&'a ModuleHeader),
+    /// A module that has been unlinked, every reference is redirected here.
+    Unresolved(usize),
+}
+
+unsafe fn sections<'a>(header: &ModuleHeader) -> &'a mut [SectionInfo] {
+    core::slice::from_raw_parts_mut(
+        header.section_info_offset as usize as *mut SectionInfo,
+        header.num_sections as usize,
+    )
+}
+
+unsafe fn imports<'a>(header: &ModuleHeader) -> &'a mut [ImportInfo] {
+    core::slice::from_raw_parts_mut(
+        header.imp_offset as usize as *mut ImportInfo,
+        header.imp_size as usize / core::mem::size_of::<ImportInfo>(),
+    )
+}
+
+unsafe fn section_base(header: &ModuleHeader, section: u8) -> usize {
+    sections(header)[section as usize].base()
+}
+
+unsafe fn sync_sections(header: &ModuleHeader) {
+    for section in sections(header).iter() {
+        if section.offset != 0 {
+            cache::sync_code_range(section.base() as *const u8, section.size as usize);
+        }
+    }
+}
+
+unsafe fn relocate(
+    module: &ModuleHeader,
+    import: &ImportInfo,
+    target: Target,
+) -> Result<(), RelLinkError> {
+    let mut entry = import.offset as usize as *const RelocationEntry;
+    let mut pos = 0;
+    loop {
+        let RelocationEntry {
+            offset,
+            kind,
+            section,
+            addend,
+        } = entry.read();
+        entry = entry.add(1);
+        pos += offset as usize;
+
+        let word = pos as *mut u32;
+        let half = pos as *mut u16;
+        match kind {
+            R_PPC_NONE | R_DOLPHIN_NOP => {}
+            R_DOLPHIN_SECTION => pos = section_base(module, section),
+            R_DOLPHIN_END => break,
+            // The entries above name sections of the module, only relocations name
+            // sections of the target.
+            kind => {
+                let value = match target {
+                    Target::Main => addend,
+                    Target::Module(header) => {
+                        (section_base(header, section) as u32).wrapping_add(addend)
+                    }
+                    Target::Unresolved(addr) => addr as u32,
+                };
+                let rel = value.wrapping_sub(pos as u32);
+                match kind {
+                    R_PPC_ADDR32 => word.write(value),
+                    R_PPC_ADDR24 => {
+                        word.write((word.read() & !0x03ff_fffc) | (value & 0x03ff_fffc))
+                    }
+                    R_PPC_ADDR16 | R_PPC_ADDR16_LO => half.write(value as u16),
+                    R_PPC_ADDR16_HI => half.write((value >> 16) as u16),
+                    R_PPC_ADDR16_HA => half.write((value.wrapping_add(0x8000) >> 16) as u16),
+                    R_PPC_ADDR14 | R_PPC_ADDR14_BRTAKEN | R_PPC_ADDR14_BRNTAKEN => {
+                        word.write((word.read() & !0xfffc) | (value & 0xfffc))
+                    }
+                    R_PPC_REL24 => word.write((word.read() & !0x03ff_fffc) | (rel & 0x03ff_fffc)),
+                    R_PPC_REL14 | R_PPC_REL14_BRTAKEN | R_PPC_REL14_BRNTAKEN => {
+                        word.write((word.read() & !0xfffc) | (rel & 0xfffc))
+                    }
+                    R_PPC_REL32 => word.write(rel),
+                    kind => return Err(RelLinkError::UnsupportedRelocation(kind)),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A linked REL module.
+#[derive(Clone, Copy)]
+pub struct Module {
+    header: NonNull<ModuleHeader>,
+}
+
+impl Module {
+    pub fn header(&self) -> &ModuleHeader {
+        unsafe { self.header.as_ref() }
+    }
+
+    #[inline]
+    pub fn id(self) -> u32 {
+        self.header().id
+    }
+
+    unsafe fn call(addr: u32) {
+        if addr != 0 {
+            let entry: extern "C" fn() = core::mem::transmute(addr as usize);
+            entry()
+        }
+    }
+
+    /// Calls the module's `_prolog` function, if it exports one.
+    ///
+    /// # Safety
+    /// The module must still be linked and its prolog must be sound to call right now.
+    pub unsafe fn prolog(self) {
+        Self::call(self.header().prolog)
+    }
+
+    /// Calls the module's `_epilog` function, if it exports one.
+    ///
+    /// # Safety
+    /// The module must still be linked and its epilog must be sound to call right now.
+    pub unsafe fn epilog(self) {
+        Self::call(self.header().epilog)
+    }
+}
+
+/// Returns the size and alignment of the bss memory a REL image requires.
+///
+/// # Safety
+/// `image` must point to a complete REL image.
+pub unsafe fn bss_layout(image: *const u8) -> (usize, usize) {
+    let header = &*(image as *const ModuleHeader);
+    let align = if header.version >= 2 {
+        header.bss_align as usize
+    } else {
+        32
+    };
+    (header.bss_size as usize, align.max(1))
+}
+
+/// Returns the linked module with the given id.
+pub fn find(id: u32) -> Option<Module> {
+    LINKED.lock().find(id).map(|header| Module {
+        header: NonNull::from(header),
+    })
+}
+
+/// Links a REL image in place.
+///
+/// The module's imports are resolved against the main executable and every module already
+/// linked, and the imports of linked modules against this one are resolved as well. The
+/// module's prolog is not called.
+///
+/// # Safety
+/// `image` must point to a writable, unlinked REL image aligned to its header's `align`,
+/// and `bss` to writable memory matching [`bss_layout`]. Both must stay valid until the
+/// module is unlinked.
+pub unsafe fn link(image: *mut u8, bss: *mut u8) -> Result<Module, RelLinkError> {
+    let header = &mut *(image as *mut ModuleHeader);
+    if header.version > 3 {
+        return Err(RelLinkError::UnsupportedVersion(header.version));
+    }
+    let (bss_size, bss_align) = bss_layout(image);
+    if header.version >= 2
+        && (!(image as usize).is_multiple_of(header.align.max(1) as usize)
+            || !(bss as usize).is_multiple_of(bss_align))
+    {
+        return Err(RelLinkError::Misaligned);
+    }
+
+    let mut chain = LINKED.lock();
+    if header.id == 0 || chain.find(header.id).is_some() {
+        return Err(RelLinkError::AlreadyLinked(header.id));
+    }
+
+    let base = image as u32;
+    header.section_info_offset += base;
+    for (i, section) in sections(header).iter_mut().enumerate() {
+        if section.offset != 0 {
+            section.offset += base;
+        } else if section.size != 0 {
+            section.offset = bss as u32;
+            header.bss_section = i as u8;
+            core::ptr::write_bytes(bss, 0, bss_size);
+        }
+    }
+    header.rel_offset += base;
+    header.imp_offset += base;
+    for import in imports(header).iter_mut() {
+        import.offset += base;
+    }
+    if header.prolog_section != 0 {
+        header.prolog += section_base(header, header.prolog_section) as u32;
+    }
+    if header.epilog_section != 0 {
+        header.epilog += section_base(header, header.epilog_section) as u32;
+    }
+    if header.unresolved_section != 0 {
+        header.unresolved += section_base(header, header.unresolved_section) as u32;
+    }
+
+    for import in imports(header).iter() {
+        let target = if import.id == 0 {
+            Target::Main
+        } else if import.id == header.id {
+            Target::Module(header)
+        } else if let Some(other) = chain.find(import.id) {
+            Target::Module(other)
+        } else {
+            continue;
+        };
+        relocate(header, import, target)?;
+    }
+    for other in chain.iter() {
+        let mut patched = false;
+        for import in imports(other).iter().filter(|i| i.id == header.id) {
+            relocate(other, import, Target::Module(header))?;
+            patched = true;
+        }
+        if patched {
+            sync_sections(other);
+        }
+    }
+    sync_sections(header);
+
+    header.next = 0;
+    header.prev = chain.tail as u32;
+    if let Some(tail) = (chain.tail as *mut ModuleHeader).as_mut() {
+        tail.next = image as u32;
+    } else {
+        chain.head = image as usize;
+    }
+    chain.tail = image as usize;
+
+    Ok(Module {
+        header: NonNull::from(header),
+    })
+}
+
+/// Removes a module from the chain of linked modules.
+///
+/// References to the module from other linked modules are redirected to their
+/// `_unresolved` function. The module's epilog is not called.
+///
+/// # Safety
+/// No code or data of the module may be in use, including by other modules.
+pub unsafe fn unlink(module: Module) -> Result<(), RelLinkError> {
+    let mut chain = LINKED.lock();
+    let id = module.id();
+    let header = chain.find(id).ok_or(RelLinkError::NotLinked)?;
+
+    if let Some(prev) = (header.prev as usize as *mut ModuleHeader).as_mut() {
+        prev.next = header.next;
+    } else {
+        chain.head = header.next as usize;
+    }
+    if let Some(next) = (header.next as usize as *mut ModuleHeader).as_mut() {
+        next.prev = header.prev;
+    } else {
+        chain.tail = header.prev as usize;
+    }
+
+    for other in chain.iter() {
+        let mut patched = false;
+        for import in imports(other).iter().filter(|i| i.id == id) {
+            relocate(other, import, Target::Unresolved(other.unresolved as usize))?;
+            patched = true;
+        }
+        if patched {
+            sync_sections(other);
+        }
+    }
+
+    Ok(())
+}
+
+// The headers hold 32-bit addresses, so the images are placed below 4 GiB.
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    const SECTIONS: usize = 0x50;
+    const IMPORTS: usize = 0xc0;
+    const RELOCATIONS: usize = 0xc8;
+    const IMAGE_SIZE: usize = 0x100;
+
+    /// Zeroed memory the 32-bit addresses of the images can point to.
+    fn low_memory(len: usize) -> *mut u8 {
+        extern "C" {
+            fn mmap(addr: *mut u8, len: usize, prot: i32, flags: i32, fd: i32, off: i64)
+                -> *mut u8;
+        }
+        const PROT_READ_WRITE: i32 = 0x3;
+        const MAP_PRIVATE_ANONYMOUS_32BIT: i32 = 0x02 | 0x20 | 0x40;
+        let memory = unsafe {
+            mmap(
+                core::ptr::null_mut(),
+                len,
+                PROT_READ_WRITE,
+                MAP_PRIVATE_ANONYMOUS_32BIT,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(memory as isize, -1, "failed to map memory below 4 GiB");
+        memory
+    }
+
+    /// Writes a version 1 image whose sections after the null one have `sizes` and lie
+    /// every 0x20 bytes from 0x80, importing `imports` from the module `import_id`.
+    unsafe fn write_image(
+        image: *mut u8,
+        id: u32,
+        sizes: &[u32],
+        import_id: Option<u32>,
+        imports: &[RelocationEntry],
+    ) {
+        assert!(core::mem::size_of::<ModuleHeader>() <= SECTIONS);
+        image.cast::<ModuleHeader>().write(ModuleHeader {
+            id,
+            next: 0,
+            prev: 0,
+            num_sections: sizes.len() as u32 + 1,
+            section_info_offset: SECTIONS as u32,
+            name_offset: 0,
+            name_size: 0,
+            version: 1,
+            bss_size: 0,
+            rel_offset: RELOCATIONS as u32,
+            imp_offset: IMPORTS as u32,
+            imp_size: import_id.map_or(0, |_| 8),
+            prolog_section: 0,
+            epilog_section: 0,
+            unresolved_section: 0,
+            bss_section: 0,
+            prolog: 0,
+            epilog: 0,
+            unresolved: 0,
+            align: 0,
+            bss_align: 0,
+            fix_size: 0,
+        });
+        let sections = image.add(SECTIONS).cast::<SectionInfo>();
+        for (index, &size) in sizes.iter().enumerate() {
+            sections.add(index + 1).write(SectionInfo {
+                offset: 0x80 + index as u32 * 0x20,
+                size,
+            });
+        }
+        if let Some(import_id) = import_id {
+            image.add(IMPORTS).cast::<ImportInfo>().write(ImportInfo {
+                id: import_id,
+                offset: RELOCATIONS as u32,
+            });
+        }
+        let entries = image.add(RELOCATIONS).cast::<RelocationEntry>();
+        core::ptr::copy_nonoverlapping(imports.as_ptr(), entries, imports.len());
+    }
+
+    fn entry(offset: u16, kind: u8, section: u8, addend: u32) -> RelocationEntry {
+        RelocationEntry {
+            offset,
+            kind,
+            section,
+            addend,
+        }
+    }
+
+    #[test]
+    fn imports_from_modules_with_fewer_sections() {
+        let memory = low_memory(2 * IMAGE_SIZE);
+        let (target, module) = unsafe { (memory, memory.add(IMAGE_SIZE)) };
+        unsafe {
+            write_image(target, 1, &[0x20], None, &[]);
+            // The module's second section lies past the sections of the target.
+            write_image(
+                module,
+                2,
+                &[0x20, 0x20],
+                Some(1),
+                &[
+                    entry(0, R_DOLPHIN_SECTION, 2, 0),
+                    entry(4, R_PPC_ADDR32, 1, 8),
+                    entry(0, R_DOLPHIN_NOP, 2, 0),
+                    entry(0, R_DOLPHIN_END, 0, 0),
+                ],
+            );
+
+            let target_module = link(target, core::ptr::null_mut()).unwrap();
+            let linked = link(module, core::ptr::null_mut()).unwrap();
+            let patched = module.add(0xa0 + 4).cast::<u32>().read();
+            assert_eq!(patched, target as u32 + 0x80 + 8);
+
+            unlink(linked).unwrap();
+            unlink(target_module).unwrap();
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! The read-only filesystem packed by `rbrew build --romfs <dir>`, which the DOL loads
+//! after the program, with the rest of it.
+//!
+//! Its files are in memory from the start, so opening one is all reading it takes:
+//!
+//! ```ignore
+//! let romfs = rbrew_gc::romfs::get().expect("built without --romfs");
+//! let font = romfs.open("fonts/title.bin").unwrap();
+//! for (path, data) in romfs.files_in("levels") {
+//!     // ...
+//! }
+//! ```
+//!
+//! The image lies past the end of the program's bss, 32-byte aligned, where it wants its
+//! memory left alone; [`range`] gives where.
+
+pub use rbrew_shared::types::romfs::RomFs;
+use rbrew_shared::types::romfs::{empty_slot, parse_slot, SLOT_SIZE};
+
+// The contents are replaced after conversion, so the slot must never be read directly or
+// the compiler may fold in the unfilled value.
+#[used]
+#[link_section = ".rbrew.romfs"]
+static SLOT: [u8; SLOT_SIZE] = empty_slot();
+
+/// The memory of the packed image, or `None` if the program was built without
+/// `--romfs`.
+///
+/// The slot is only kept in the final binary when this function or [`get`] is referenced.
+pub fn range() -> Option<core::ops::Range<u32>> {
+    let slot: &'static [u8; SLOT_SIZE] = core::hint::black_box(&SLOT);
+    let (address, len) = parse_slot(slot)?;
+    Some(address..address + len)
+}
+
+/// The packed filesystem, or `None` if the program was built without `--romfs`.
+pub fn get() -> Option<RomFs<'static>> {
+    let range = range()?;
+    let image = unsafe { core::slice::from_raw_parts(range.start as *const u8, range.len()) };
+    RomFs::parse(image)
+}
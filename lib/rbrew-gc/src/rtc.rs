@@ -0,0 +1,265 @@
+//! The real-time clock and SRAM, the settings the console keeps powered by its battery,
+//! device 1 of the EXI's channel 0.
+//!
+//! The clock counts seconds, and the IPL sets the time by offsetting it with a bias it
+//! keeps in SRAM, which [`now`] and [`set_now`] account for:
+//!
+//! ```ignore
+//! let now = rtc::now();
+//! println!("{:04}-{:02}-{:02}", now.year, now.month, now.day);
+//!
+//! if let Some(settings) = rtc::SystemSettings::read() {
+//!     let language = settings.language();
+//! }
+//! ```
+
+use crate::{
+    exi::{self, Bus, Clock},
+    gfx::video::VideoFormat,
+    interrupt,
+};
+
+/// The commands the counter is read and written at, in the RTC's address space.
+const COUNTER_READ: u32 = 0x2000_0000;
+const COUNTER_WRITE: u32 = 0xa000_0000;
+
+/// The bytes of SRAM the settings are at.
+const SRAM_BIAS: usize = 0x0c;
+const SRAM_DISPLAY_OFFSET: usize = 0x10;
+const SRAM_VIDEO: usize = 0x11;
+const SRAM_LANGUAGE: usize = 0x12;
+const SRAM_FLAGS: usize = 0x13;
+
+/// The bits of the video byte: whether PAL consoles display at 60 Hz.
+const VIDEO_EURGB60: u8 = 0x40;
+/// The bits of the flags: the format of the TV, stereo sound, and progressive scan.
+const FLAGS_FORMAT: u8 = 0x03;
+const FLAGS_STEREO: u8 = 0x04;
+const FLAGS_PROGRESSIVE: u8 = 0x80;
+
+/// The seconds from the Unix epoch to 2000-01-01, the epoch of the clock.
+const EPOCH: i64 = 946_684_800;
+
+/// Reads the clock's counter, the seconds since 2000-01-01 minus the bias in SRAM.
+pub fn read_counter() -> u32 {
+    interrupt::free(|| {
+        let mut bus = Bus::lock(0);
+        bus.select(1, Clock::Mhz8);
+        bus.write_word(COUNTER_READ, 4);
+        bus.read_word(4)
+    })
+}
+
+/// Writes the clock's counter, which counts on from `counter`.
+pub fn write_counter(counter: u32) {
+    interrupt::free(|| {
+        let mut bus = Bus::lock(0);
+        bus.select(1, Clock::Mhz8);
+        bus.write_word(COUNTER_WRITE, 4);
+        bus.write_word(counter, 4);
+    })
+}
+
+/// The time the console is set to, in its time zone.
+pub fn now() -> DateTime {
+    let bias = SystemSettings::read().map_or(0, |settings| settings.counter_bias());
+    DateTime::from_seconds(read_counter().wrapping_add(bias))
+}
+
+/// Sets the console's time to `time` as the IPL does, by changing the bias in SRAM.
+///
+/// Returns `false` without setting it if `time` is invalid or not from 2000 to 2135, or
+/// SRAM is, as without a battery.
+pub fn set_now(time: DateTime) -> bool {
+    let (Some(seconds), Some(mut settings)) = (time.to_seconds(), SystemSettings::read()) else {
+        return false;
+    };
+    settings.set_counter_bias(seconds.wrapping_sub(read_counter()));
+    settings.write();
+    true
+}
+
+/// A date and a time of the Gregorian calendar.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    /// From 1 to 12.
+    pub month: u8,
+    /// From 1.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// The time `seconds` after 2000-01-01 00:00:00.
+    pub fn from_seconds(seconds: u32) -> Self {
+        let days = (seconds / 86400) as i64 + EPOCH / 86400;
+        let time = seconds % 86400;
+
+        // The days since 0000-03-01, so that leap days end years.
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// The seconds since 2000-01-01 00:00:00, or `None` if the time is invalid or does
+    /// not fit.
+    pub fn to_seconds(self) -> Option<u32> {
+        let year = self.year as i64 - (self.month <= 2) as i64;
+        let month = self.month as i64;
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year =
+            (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        let seconds = days * 86400 - EPOCH
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64;
+        // Days past the end of their month, and the like, come back as another time.
+        let seconds = u32::try_from(seconds).ok()?;
+        (Self::from_seconds(seconds) == self).then_some(seconds)
+    }
+}
+
+/// The languages the IPL can be set to. Japanese consoles use English.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+    French,
+    Spanish,
+    Italian,
+    Dutch,
+}
+
+/// The settings of the console, as the IPL sets them up, in a copy of SRAM.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SystemSettings {
+    sram: [u8; exi::SRAM_SIZE],
+}
+
+impl SystemSettings {
+    /// Reads the settings. Returns `None` if the checksums of SRAM do not match, as
+    /// without a battery.
+    pub fn read() -> Option<Self> {
+        exi::read_sram().map(|sram| Self { sram })
+    }
+
+    /// Writes the settings back, with the rest of SRAM as it was read.
+    pub fn write(&self) {
+        exi::write_sram(&mut self.sram.clone());
+    }
+
+    /// The language of the IPL, English if it is unknown.
+    pub fn language(&self) -> Language {
+        match self.sram[SRAM_LANGUAGE] {
+            1 => Language::German,
+            2 => Language::French,
+            3 => Language::Spanish,
+            4 => Language::Italian,
+            5 => Language::Dutch,
+            _ => Language::English,
+        }
+    }
+
+    #[inline]
+    pub fn set_language(&mut self, language: Language) {
+        self.sram[SRAM_LANGUAGE] = language as u8;
+    }
+
+    /// The format of the TV.
+    pub fn video_format(&self) -> VideoFormat {
+        match self.sram[SRAM_FLAGS] & FLAGS_FORMAT {
+            0 => VideoFormat::Ntsc,
+            1 => VideoFormat::Pal,
+            2 => VideoFormat::Mpal,
+            _ => VideoFormat::Debug,
+        }
+    }
+
+    pub fn set_video_format(&mut self, format: VideoFormat) {
+        self.sram[SRAM_FLAGS] = self.sram[SRAM_FLAGS] & !FLAGS_FORMAT | format as u8;
+    }
+
+    /// Whether progressive scan is enabled, for when the component cable is attached.
+    #[inline]
+    pub fn progressive(&self) -> bool {
+        self.sram[SRAM_FLAGS] & FLAGS_PROGRESSIVE != 0
+    }
+
+    pub fn set_progressive(&mut self, progressive: bool) {
+        self.set_flag(SRAM_FLAGS, FLAGS_PROGRESSIVE, progressive);
+    }
+
+    /// Whether PAL consoles display at 60 Hz.
+    #[inline]
+    pub fn eurgb60(&self) -> bool {
+        self.sram[SRAM_VIDEO] & VIDEO_EURGB60 != 0
+    }
+
+    pub fn set_eurgb60(&mut self, eurgb60: bool) {
+        self.set_flag(SRAM_VIDEO, VIDEO_EURGB60, eurgb60);
+    }
+
+    /// Whether the sound is stereo rather than mono.
+    #[inline]
+    pub fn stereo(&self) -> bool {
+        self.sram[SRAM_FLAGS] & FLAGS_STEREO != 0
+    }
+
+    pub fn set_stereo(&mut self, stereo: bool) {
+        self.set_flag(SRAM_FLAGS, FLAGS_STEREO, stereo);
+    }
+
+    /// The pixels the picture is moved right by, or left if negative.
+    #[inline]
+    pub fn display_offset(&self) -> i8 {
+        self.sram[SRAM_DISPLAY_OFFSET] as i8
+    }
+
+    #[inline]
+    pub fn set_display_offset(&mut self, offset: i8) {
+        self.sram[SRAM_DISPLAY_OFFSET] = offset as u8;
+    }
+
+    /// The seconds added to the clock's counter to give the time.
+    #[inline]
+    pub fn counter_bias(&self) -> u32 {
+        u32::from_be_bytes(self.sram[SRAM_BIAS..SRAM_BIAS + 4].try_into().unwrap())
+    }
+
+    #[inline]
+    pub fn set_counter_bias(&mut self, bias: u32) {
+        self.sram[SRAM_BIAS..SRAM_BIAS + 4].copy_from_slice(&bias.to_be_bytes());
+    }
+
+    fn set_flag(&mut self, byte: usize, flag: u8, value: bool) {
+        if value {
+            self.sram[byte] |= flag;
+        } else {
+            self.sram[byte] &= !flag;
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! The entry point of programs linked with `targets/gamecube.ld`, with the `runtime`
+//! feature.
+//!
+//! The loader jumps to `__start` with the MMU's BATs mapping MEM1. The runtime enables
+//! the floating point unit, sets up the stack at the top of MEM1 and the small data
+//! bases, and clears the bss. It then installs the interrupt vector, enables interrupts,
+//! and calls the function marked with [`entry`]:
+//!
+//! ```ignore
+//! use rbrew_gc::runtime::entry;
+//!
+//! #[entry]
+//! fn main() -> ! {
+//!     let video = VideoContext::global();
+//!     loop {
+//!         video.wait_for_retrace();
+//!     }
+//! }
+//! ```
+//!
+//! If it returns, the runtime waits forever.
+
+pub use rbrew_shared::entry;
+
+#[cfg(target_arch = "powerpc")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.start, "ax"
+    .global __start
+__start:
+    # Floating point instructions fault until MSR[FP] is set.
+    mfmsr 3
+    ori 3, 3, 0x2000
+    mtmsr 3
+    isync
+
+    lis 1, __stack_top@ha
+    addi 1, 1, __stack_top@l
+    lis 2, _SDA2_BASE_@ha
+    addi 2, 2, _SDA2_BASE_@l
+    lis 13, _SDA_BASE_@ha
+    addi 13, 13, _SDA_BASE_@l
+
+    # The loader zeroes the DOL's bss, but not every loader reads DOLs.
+    lis 3, __bss_start@ha
+    addi 3, 3, __bss_start@l
+    lis 4, __bss_end@ha
+    addi 4, 4, __bss_end@l
+    li 0, 0
+1:
+    cmplw 3, 4
+    bge 2f
+    stw 0, 0(3)
+    addi 3, 3, 4
+    b 1b
+2:
+    # Terminate the back chain of the stack frames.
+    stwu 0, -16(1)
+    b __rbrew_runtime_start
+"#
+);
+
+/// Initializes the hardware the drivers rely on, then calls the program's entry point.
+#[cfg(target_arch = "powerpc")]
+#[no_mangle]
+extern "C" fn __rbrew_runtime_start() -> ! {
+    extern "C" {
+        fn __rbrew_entry();
+    }
+    unsafe {
+        crate::interrupt::init();
+    }
+    crate::interrupt::enable();
+    unsafe { __rbrew_entry() };
+    loop {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,127 @@
+//! Storage: the disc drive, the memory cards, SD cards, and the files on them.
+
+pub mod card;
+pub mod dvd;
+pub mod fat;
+pub mod sd;
+
+/// The bytes of a block of a [`BlockDevice`].
+pub const BLOCK_LEN: usize = 0x200;
+
+/// A device read and written in blocks of [`BLOCK_LEN`] bytes, which filesystems sit on.
+pub trait BlockDevice {
+    type Error;
+
+    /// The blocks of the device.
+    fn blocks(&self) -> u64;
+
+    /// Reads the blocks from `block` into `buffer`, whose length is a multiple of
+    /// [`BLOCK_LEN`].
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Writes `data`, whose length is a multiple of [`BLOCK_LEN`], to the blocks from
+    /// `block`.
+    fn write_blocks(&mut self, block: u64, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// The longest name of a [`DirEntry`], in bytes: 255 UTF-16 units of up to 3 bytes each.
+pub const MAX_NAME_LEN: usize = 255 * 3;
+
+/// A file or directory in a directory of a [`FileSystem`].
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    name: [u8; MAX_NAME_LEN],
+    name_len: usize,
+    is_dir: bool,
+    len: u32,
+}
+
+impl DirEntry {
+    pub(crate) const fn new() -> Self {
+        Self {
+            name: [0; MAX_NAME_LEN],
+            name_len: 0,
+            is_dir: false,
+            len: 0,
+        }
+    }
+
+    /// Appends `c` to the name, returning whether it fit.
+    pub(crate) fn push(&mut self, c: char) -> bool {
+        let len = c.len_utf8();
+        if self.name_len + len > MAX_NAME_LEN {
+            return false;
+        }
+        c.encode_utf8(&mut self.name[self.name_len..]);
+        self.name_len += len;
+        true
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        // Only whole characters are pushed.
+        unsafe { core::str::from_utf8_unchecked(&self.name[..self.name_len]) }
+    }
+
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        self.is_dir
+    }
+
+    /// The bytes of the file, 0 for a directory.
+    #[inline]
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub(crate) fn set_kind(&mut self, is_dir: bool, len: u32) {
+        self.is_dir = is_dir;
+        self.len = len;
+    }
+}
+
+/// A filesystem of directories and files, at paths from its root whose components are
+/// separated by `/`.
+///
+/// Files are handles that the filesystem reads and writes, so that several can be open
+/// at once.
+pub trait FileSystem {
+    type Error;
+    type File;
+    /// The entries of a directory, in the order it holds them.
+    type ReadDir<'a>: Iterator<Item = Result<DirEntry, Self::Error>>
+    where
+        Self: 'a;
+
+    /// Opens the file at `path`.
+    fn open(&mut self, path: &str) -> Result<Self::File, Self::Error>;
+
+    /// Creates the file at `path` in a directory that exists, emptying it if it exists.
+    fn create(&mut self, path: &str) -> Result<Self::File, Self::Error>;
+
+    /// Creates the directory at `path` in a directory that exists.
+    fn create_dir(&mut self, path: &str) -> Result<(), Self::Error>;
+
+    /// The entries of the directory at `path`.
+    fn read_dir(&mut self, path: &str) -> Result<Self::ReadDir<'_>, Self::Error>;
+
+    /// The bytes of `file`.
+    fn file_len(&self, file: &Self::File) -> u32;
+
+    /// Reads `file` from `offset` into `buffer`, up to its end, returning the bytes read.
+    fn read_at(
+        &mut self,
+        file: &Self::File,
+        offset: u32,
+        buffer: &mut [u8],
+    ) -> Result<usize, Self::Error>;
+
+    /// Writes `data` at the end of `file`.
+    fn append(&mut self, file: &mut Self::File, data: &[u8]) -> Result<(), Self::Error>;
+}
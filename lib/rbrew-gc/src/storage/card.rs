@@ -0,0 +1,952 @@
+//! Memory cards in slot A and B, reached through the EXI, and the files saved on them.
+//!
+//! A card is erased and programmed in blocks of 8 KiB. The first five hold its header,
+//! then its directory and its block allocation table, twice each: a change is written to
+//! the older copy, which becomes the current one, so that a card pulled out halfway keeps
+//! the files it had. Mounting a card reads the current copies, which every change to its
+//! files rewrites:
+//!
+//! ```ignore
+//! use rbrew_gc::storage::card::{CardContext, NewFile, Owner, Slot};
+//!
+//! let card = CardContext::global().mount(Slot::A)?;
+//! let file = match card.open(Owner::current(), "save") {
+//!     Ok(file) => file,
+//!     Err(_) => card.create(&NewFile::new(Owner::current(), "save", 1).with_comment(0))?,
+//! };
+//! file.write_at(0, b"My Game\0")?;
+//! ```
+//!
+//! A file's [`Entry`] is the header of the GCI files saves are exported as, followed by
+//! the file's blocks, which [`Card::import`] writes back.
+
+use crate::{exi, interrupt};
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use spin::Mutex;
+
+/// The bytes of a block, the unit files are allocated and cards erased in.
+pub const BLOCK_LEN: usize = 0x2000;
+/// The files a directory holds.
+pub const MAX_FILES: usize = 127;
+/// The bytes of a directory entry, and of the header of a GCI file.
+pub const ENTRY_LEN: usize = 0x40;
+
+/// The formats of the banner, in [`Entry::banner_format`]: none, 8-bit indices into the
+/// RGB5A3 palette that follows it, or RGB5A3.
+pub const BANNER_NONE: u8 = 0;
+pub const BANNER_CI8: u8 = 1;
+pub const BANNER_RGB5A3: u8 = 2;
+/// The formats of the icons, 2 bits for each of the 8 in [`Entry::icon_formats`]: none,
+/// 8-bit indices into the palette that follows the last icon, RGB5A3, or 8-bit indices
+/// into a palette of its own.
+pub const ICON_NONE: u16 = 0;
+pub const ICON_CI8_SHARED: u16 = 1;
+pub const ICON_RGB5A3: u16 = 2;
+pub const ICON_CI8: u16 = 3;
+
+/// The bytes programmed at once, and read at once.
+const PAGE_LEN: usize = 0x80;
+const SECTOR_LEN: usize = 0x200;
+/// The blocks of the header, the directories and the allocation tables.
+const SYSTEM_BLOCKS: u16 = 5;
+const DIRECTORY_BLOCK: u16 = 1;
+const BAT_BLOCK: u16 = 3;
+
+/// The allocation table's marks of a free block and of the last block of a file.
+const BAT_FREE: u16 = 0x0000;
+const BAT_LAST: u16 = 0xffff;
+
+const CMD_ID: u8 = 0x00;
+const CMD_READ_ARRAY: u8 = 0x52;
+const CMD_STATUS: u8 = 0x83;
+const CMD_CLEAR_STATUS: u8 = 0x89;
+const CMD_ERASE_SECTOR: u8 = 0xf1;
+const CMD_PROGRAM_PAGE: u8 = 0xf2;
+
+const STATUS_BUSY: u8 = 0x80;
+const STATUS_UNLOCKED: u8 = 0x40;
+const STATUS_ERASE_ERROR: u8 = 0x10;
+const STATUS_PROGRAM_ERROR: u8 = 0x08;
+
+/// The bytes a read waits for before its data, by the bits 8 to 10 of the card's ID.
+const LATENCIES: [usize; 8] = [4, 8, 16, 32, 64, 128, 256, 512];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    #[inline]
+    fn channel(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CardError {
+    /// There is no card in the slot, or it was pulled out.
+    NoCard,
+    /// The device in the slot is not a memory card.
+    NotACard,
+    /// The card has not been unlocked, which the IPL does as it manages cards.
+    Locked,
+    /// Neither copy of the directory or of the allocation table is valid.
+    Unformatted,
+    /// The card failed to erase or program a block, with its status.
+    Device(u8),
+    /// The card was unmounted, or mounted again.
+    NotMounted,
+    NotFound,
+    Exists,
+    /// The directory is full, or there are not enough free blocks.
+    NoSpace,
+    /// The name does not fit in 32 bytes, or is empty.
+    InvalidName,
+    /// The GCI file is shorter or longer than its blocks.
+    InvalidGci,
+}
+
+/// The card in a slot, which is device 0 of the slot's channel.
+#[derive(Clone, Copy)]
+struct Device {
+    channel: usize,
+    latency: usize,
+}
+
+impl Device {
+    /// Sends `command`, then runs `f` with the card still selected.
+    fn run<R>(self, command: &[u8], f: impl FnOnce() -> R) -> R {
+        interrupt::free(|| {
+            let mut bus = exi::Bus::lock(self.channel);
+            bus.select(0, exi::Clock::Mhz16);
+            bus.write(command);
+            f()
+        })
+    }
+
+    fn send(self, bytes: &[u8]) {
+        for chunk in bytes.chunks(4) {
+            let mut word = [0; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            exi::write_on(self.channel, u32::from_be_bytes(word), chunk.len());
+        }
+    }
+
+    fn receive(self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(4) {
+            let word = exi::read_on(self.channel, chunk.len()).to_be_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+    }
+
+    fn id(self) -> u32 {
+        self.run(&[CMD_ID, 0], || exi::read_on(self.channel, 4))
+    }
+
+    fn status(self) -> u8 {
+        self.run(&[CMD_STATUS, 0], || exi::read_on(self.channel, 1) as u8)
+    }
+
+    /// Waits while the card is busy, clearing the status if it failed.
+    fn wait(self) -> Result<(), CardError> {
+        loop {
+            if !exi::is_attached_on(self.channel) {
+                return Err(CardError::NoCard);
+            }
+            let status = self.status();
+            if status & STATUS_BUSY != 0 {
+                core::hint::spin_loop();
+                continue;
+            }
+            if status & (STATUS_ERASE_ERROR | STATUS_PROGRAM_ERROR) != 0 {
+                self.run(&[CMD_CLEAR_STATUS], || {});
+                return Err(CardError::Device(status));
+            }
+            return Ok(());
+        }
+    }
+
+    /// The command on the byte `address`, which it takes in 4 bytes.
+    fn addressed(command: u8, address: u32) -> [u8; 5] {
+        [
+            command,
+            (address >> 17) as u8 & 0x7f,
+            (address >> 9) as u8,
+            (address >> 7) as u8 & 0x03,
+            address as u8 & 0x7f,
+        ]
+    }
+
+    /// Reads `buffer` from the byte `address`, a sector at a time.
+    fn read(self, address: u32, buffer: &mut [u8]) {
+        for (i, sector) in buffer.chunks_mut(SECTOR_LEN).enumerate() {
+            let address = address + (i * SECTOR_LEN) as u32;
+            self.run(&Self::addressed(CMD_READ_ARRAY, address), || {
+                for _ in 0..self.latency / 4 {
+                    exi::write_on(self.channel, 0, 4);
+                }
+                self.receive(sector);
+            });
+        }
+    }
+
+    /// Erases `block`, then programs it with `data` a page at a time.
+    fn write_block(self, block: u16, data: &[u8; BLOCK_LEN]) -> Result<(), CardError> {
+        let address = block as u32 * BLOCK_LEN as u32;
+        let [command, high, low, ..] = Self::addressed(CMD_ERASE_SECTOR, address);
+        self.run(&[command, high, low], || {});
+        self.wait()?;
+        for (i, page) in data.chunks_exact(PAGE_LEN).enumerate() {
+            let address = address + (i * PAGE_LEN) as u32;
+            self.run(&Self::addressed(CMD_PROGRAM_PAGE, address), || {
+                self.send(page)
+            });
+            self.wait()?;
+        }
+        Ok(())
+    }
+}
+
+fn be16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+fn be32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn set_be16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// The checksums of the directory and of the allocation table, the sum of the half-words
+/// and of their complements, where a sum of `0xffff` is written as 0.
+fn checksums(data: &[u8]) -> (u16, u16) {
+    let (sum, inverse) = data
+        .chunks_exact(2)
+        .map(|half| u16::from_be_bytes([half[0], half[1]]))
+        .fold((0u16, 0u16), |(sum, inverse), half| {
+            (sum.wrapping_add(half), inverse.wrapping_add(!half))
+        });
+    let fix = |sum: u16| if sum == 0xffff { 0 } else { sum };
+    (fix(sum), fix(inverse))
+}
+
+struct Block([u8; BLOCK_LEN]);
+
+impl Block {
+    /// The directory holds the entries, then its update counter and its checksums.
+    const DIRECTORY_COUNTER: usize = 0x1ffa;
+    const DIRECTORY_CHECKSUMS: usize = 0x1ffc;
+    /// The allocation table holds its checksums, its update counter, the free blocks, the
+    /// block allocated last, then the next block of each block's file.
+    const BAT_CHECKSUMS: usize = 0x00;
+    const BAT_COUNTER: usize = 0x04;
+    const BAT_FREE_BLOCKS: usize = 0x06;
+    const BAT_LAST_ALLOCATED: usize = 0x08;
+    const BAT_MAP: usize = 0x0a;
+
+    /// The update counter of the directory or allocation table, if its checksums match.
+    fn counter(&self, is_directory: bool) -> Option<u16> {
+        let (summed, sums, counter) = if is_directory {
+            (
+                &self.0[..Self::DIRECTORY_CHECKSUMS],
+                Self::DIRECTORY_CHECKSUMS,
+                Self::DIRECTORY_COUNTER,
+            )
+        } else {
+            (
+                &self.0[Self::BAT_COUNTER..],
+                Self::BAT_CHECKSUMS,
+                Self::BAT_COUNTER,
+            )
+        };
+        (checksums(summed) == (be16(&self.0, sums), be16(&self.0, sums + 2)))
+            .then(|| be16(&self.0, counter))
+    }
+
+    /// Increments the update counter and updates the checksums, before it is written.
+    fn seal(&mut self, is_directory: bool) {
+        let data = &mut self.0;
+        let (sums, counter) = if is_directory {
+            (Self::DIRECTORY_CHECKSUMS, Self::DIRECTORY_COUNTER)
+        } else {
+            (Self::BAT_CHECKSUMS, Self::BAT_COUNTER)
+        };
+        let updates = be16(data, counter).wrapping_add(1);
+        set_be16(data, counter, updates);
+        let (sum, inverse) = if is_directory {
+            checksums(&data[..Self::DIRECTORY_CHECKSUMS])
+        } else {
+            checksums(&data[Self::BAT_COUNTER..])
+        };
+        set_be16(data, sums, sum);
+        set_be16(data, sums + 2, inverse);
+    }
+
+    fn entry(&self, index: usize) -> Entry {
+        let mut entry = [0; ENTRY_LEN];
+        entry.copy_from_slice(&self.0[index * ENTRY_LEN..][..ENTRY_LEN]);
+        Entry(entry)
+    }
+
+    fn set_entry(&mut self, index: usize, entry: &Entry) {
+        self.0[index * ENTRY_LEN..][..ENTRY_LEN].copy_from_slice(&entry.0);
+    }
+
+    fn next(&self, block: u16) -> u16 {
+        be16(
+            &self.0,
+            Self::BAT_MAP + (block - SYSTEM_BLOCKS) as usize * 2,
+        )
+    }
+
+    fn set_next(&mut self, block: u16, next: u16) {
+        set_be16(
+            &mut self.0,
+            Self::BAT_MAP + (block - SYSTEM_BLOCKS) as usize * 2,
+            next,
+        );
+    }
+}
+
+/// A mounted card, with the current copies of its directory and allocation table.
+struct Mounted {
+    is_mounted: bool,
+    device: Device,
+    blocks: u16,
+    directory: Block,
+    bat: Block,
+    /// Which copies are current, 0 or 1.
+    directory_copy: u16,
+    bat_copy: u16,
+}
+
+impl Mounted {
+    const UNMOUNTED: Self = Self {
+        is_mounted: false,
+        device: Device {
+            channel: 0,
+            latency: 4,
+        },
+        blocks: 0,
+        directory: Block([0; BLOCK_LEN]),
+        bat: Block([0; BLOCK_LEN]),
+        directory_copy: 0,
+        bat_copy: 0,
+    };
+
+    /// The `n`th block of the file that starts at `first`.
+    fn nth_block(&self, first: u16, n: usize) -> u16 {
+        (0..n).fold(first, |block, _| self.bat.next(block))
+    }
+
+    /// The index of the live entry that matches `entry`, or whose game, maker and name do.
+    fn find(&self, entry: &Entry, exact: bool) -> Option<usize> {
+        (0..MAX_FILES).find(|&i| {
+            let other = self.directory.entry(i);
+            other.is_used()
+                && if exact {
+                    other == *entry
+                } else {
+                    other.0[..0x28] == entry.0[..0x28]
+                }
+        })
+    }
+
+    /// Writes the allocation table then the directory over their older copies.
+    fn commit(&mut self) -> Result<(), CardError> {
+        self.bat.seal(false);
+        self.device
+            .write_block(BAT_BLOCK + (1 - self.bat_copy), &self.bat.0)?;
+        self.bat_copy = 1 - self.bat_copy;
+        self.directory.seal(true);
+        self.device.write_block(
+            DIRECTORY_BLOCK + (1 - self.directory_copy),
+            &self.directory.0,
+        )?;
+        self.directory_copy = 1 - self.directory_copy;
+        Ok(())
+    }
+}
+
+/// The mounted cards of slot A and B.
+static MOUNTED: [Mutex<Mounted>; 2] = [
+    Mutex::new(Mounted::UNMOUNTED),
+    Mutex::new(Mounted::UNMOUNTED),
+];
+/// The buffer of the blocks rewritten in part, and of the older copies while mounting.
+static SCRATCH: Mutex<Block> = Mutex::new(Block([0; BLOCK_LEN]));
+
+/// Runs `f` on the card mounted in `slot`. No interrupt handler takes the cards, which
+/// are left with interrupts enabled while they erase and program.
+fn with_mounted<R>(
+    slot: Slot,
+    f: impl FnOnce(&mut Mounted) -> Result<R, CardError>,
+) -> Result<R, CardError> {
+    let mut card = MOUNTED[slot.channel()].lock();
+    if !card.is_mounted {
+        return Err(CardError::NotMounted);
+    }
+    if !exi::is_attached_on(slot.channel()) {
+        card.is_mounted = false;
+        return Err(CardError::NoCard);
+    }
+    f(&mut card)
+}
+
+/// The game and maker a file belongs to, which its name is unique for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Owner {
+    pub game: [u8; 4],
+    pub maker: [u8; 2],
+}
+
+impl Owner {
+    /// The game and maker of the disc booted, whose identity the loader left at the start
+    /// of memory.
+    pub fn current() -> Self {
+        let id = unsafe { core::ptr::read_volatile(0x8000_0000 as *const [u8; 6]) };
+        Self {
+            game: [id[0], id[1], id[2], id[3]],
+            maker: [id[4], id[5]],
+        }
+    }
+}
+
+/// A directory entry, which is the header of the file's GCI export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Entry([u8; ENTRY_LEN]);
+
+impl Entry {
+    /// The entry of a GCI file, its first bytes.
+    pub fn from_gci(gci: &[u8]) -> Option<Self> {
+        gci.get(..ENTRY_LEN)
+            .map(|header| Self(header.try_into().unwrap()))
+    }
+
+    #[inline]
+    fn is_used(&self) -> bool {
+        self.0[..4] != [0xff; 4]
+    }
+
+    #[inline]
+    pub fn owner(&self) -> Owner {
+        Owner {
+            game: [self.0[0], self.0[1], self.0[2], self.0[3]],
+            maker: [self.0[4], self.0[5]],
+        }
+    }
+
+    /// The name, without the padding of the 32 bytes it is stored in.
+    pub fn name(&self) -> &[u8] {
+        let name = &self.0[0x08..0x28];
+        &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())]
+    }
+
+    /// When the file was last changed, in seconds since 2000.
+    #[inline]
+    pub fn modified(&self) -> u32 {
+        be32(&self.0, 0x28)
+    }
+
+    /// The offset in the file of the banner, then of the icons.
+    #[inline]
+    pub fn image_offset(&self) -> u32 {
+        be32(&self.0, 0x2c)
+    }
+
+    /// One of the `BANNER_*` formats, in the bits 0 and 1.
+    #[inline]
+    pub fn banner_format(&self) -> u8 {
+        self.0[0x07]
+    }
+
+    /// The `ICON_*` format of each icon, 2 bits each from the least significant.
+    #[inline]
+    pub fn icon_formats(&self) -> u16 {
+        be16(&self.0, 0x30)
+    }
+
+    /// How long each icon is shown, 2 bits each in units of 4 frames.
+    #[inline]
+    pub fn animation_speeds(&self) -> u16 {
+        be16(&self.0, 0x32)
+    }
+
+    #[inline]
+    pub fn permissions(&self) -> u8 {
+        self.0[0x34]
+    }
+
+    /// How many times the file was copied.
+    #[inline]
+    pub fn copies(&self) -> u8 {
+        self.0[0x35]
+    }
+
+    #[inline]
+    fn first_block(&self) -> u16 {
+        be16(&self.0, 0x36)
+    }
+
+    #[inline]
+    pub fn blocks(&self) -> u16 {
+        be16(&self.0, 0x38)
+    }
+
+    /// The offset in the file of the comment, two lines of 32 bytes.
+    #[inline]
+    pub fn comment_offset(&self) -> u32 {
+        be32(&self.0, 0x3c)
+    }
+
+    /// The bytes of the file, its whole blocks.
+    #[inline]
+    pub fn len(&self) -> u32 {
+        self.blocks() as u32 * BLOCK_LEN as u32
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.blocks() == 0
+    }
+
+    /// The entry as stored, and as the header of a GCI file.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8; ENTRY_LEN] {
+        &self.0
+    }
+}
+
+/// A file to create, by its owner, its name and its blocks.
+#[derive(Clone, Copy, Debug)]
+pub struct NewFile<'a> {
+    owner: Owner,
+    name: &'a str,
+    blocks: u16,
+    modified: u32,
+    image_offset: u32,
+    banner_format: u8,
+    icon_formats: u16,
+    animation_speeds: u16,
+    permissions: u8,
+    comment_offset: u32,
+}
+
+impl<'a> NewFile<'a> {
+    /// A file of `blocks` blocks, without a comment or images, which is public.
+    #[inline]
+    pub const fn new(owner: Owner, name: &'a str, blocks: u16) -> Self {
+        Self {
+            owner,
+            name,
+            blocks,
+            modified: 0,
+            image_offset: 0xffff_ffff,
+            banner_format: BANNER_NONE,
+            icon_formats: ICON_NONE,
+            animation_speeds: 0,
+            permissions: 0x04,
+            comment_offset: 0xffff_ffff,
+        }
+    }
+
+    /// Sets when the file was last changed, in seconds since 2000.
+    #[inline]
+    pub const fn with_modified(self, modified: u32) -> Self {
+        Self { modified, ..self }
+    }
+
+    /// Shows the comment that the file holds at `offset`.
+    #[inline]
+    pub const fn with_comment(self, offset: u32) -> Self {
+        Self {
+            comment_offset: offset,
+            ..self
+        }
+    }
+
+    /// Shows the banner and icons that the file holds from `offset`, in the formats and
+    /// with the speeds of [`Entry`].
+    #[inline]
+    pub const fn with_images(
+        self,
+        offset: u32,
+        banner_format: u8,
+        icon_formats: u16,
+        animation_speeds: u16,
+    ) -> Self {
+        Self {
+            image_offset: offset,
+            banner_format,
+            icon_formats,
+            animation_speeds,
+            ..self
+        }
+    }
+
+    #[inline]
+    pub const fn with_permissions(self, permissions: u8) -> Self {
+        Self {
+            permissions,
+            ..self
+        }
+    }
+
+    fn entry(&self) -> Result<Entry, CardError> {
+        let name = self.name.as_bytes();
+        if name.is_empty() || name.len() > 32 {
+            return Err(CardError::InvalidName);
+        }
+        let mut entry = [0; ENTRY_LEN];
+        entry[..4].copy_from_slice(&self.owner.game);
+        entry[4..6].copy_from_slice(&self.owner.maker);
+        entry[0x06] = 0xff;
+        entry[0x07] = self.banner_format;
+        entry[0x08..0x08 + name.len()].copy_from_slice(name);
+        entry[0x28..0x2c].copy_from_slice(&self.modified.to_be_bytes());
+        entry[0x2c..0x30].copy_from_slice(&self.image_offset.to_be_bytes());
+        entry[0x30..0x32].copy_from_slice(&self.icon_formats.to_be_bytes());
+        entry[0x32..0x34].copy_from_slice(&self.animation_speeds.to_be_bytes());
+        entry[0x34] = self.permissions;
+        entry[0x38..0x3a].copy_from_slice(&self.blocks.to_be_bytes());
+        entry[0x3a..0x3c].copy_from_slice(&[0xff; 2]);
+        entry[0x3c..0x40].copy_from_slice(&self.comment_offset.to_be_bytes());
+        Ok(Entry(entry))
+    }
+}
+
+/// A file on a mounted card, while its entry is unchanged.
+#[derive(Clone, Copy, Debug)]
+pub struct CardFile {
+    slot: Slot,
+    entry: Entry,
+}
+
+impl CardFile {
+    #[inline]
+    pub fn entry(&self) -> Entry {
+        self.entry
+    }
+
+    #[inline]
+    pub fn len(&self) -> u32 {
+        self.entry.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entry.is_empty()
+    }
+
+    /// Runs `f` for the parts of `offset..offset + len` in each block, with the block, the
+    /// offset in it and the range of the parts so far.
+    fn each_block(
+        &self,
+        offset: u32,
+        len: usize,
+        mut f: impl FnMut(&Mounted, u16, usize, core::ops::Range<usize>) -> Result<(), CardError>,
+    ) -> Result<usize, CardError> {
+        let len = len.min(self.len().saturating_sub(offset) as usize);
+        with_mounted(self.slot, |card| {
+            card.find(&self.entry, true).ok_or(CardError::NotFound)?;
+            let mut done = 0;
+            while done < len {
+                let position = offset as usize + done;
+                let block = card.nth_block(self.entry.first_block(), position / BLOCK_LEN);
+                let start = position % BLOCK_LEN;
+                let part = (BLOCK_LEN - start).min(len - done);
+                f(card, block, start, done..done + part)?;
+                done += part;
+            }
+            Ok(len)
+        })
+    }
+
+    /// Reads from the byte `offset` of the file into `buffer`, returning the bytes read,
+    /// fewer at its end.
+    pub fn read_at(&self, offset: u32, buffer: &mut [u8]) -> Result<usize, CardError> {
+        self.each_block(offset, buffer.len(), |card, block, start, range| {
+            let address = block as u32 * BLOCK_LEN as u32;
+            let mut sector = [0; SECTOR_LEN];
+            let (mut position, end) = (start, start + range.len());
+            let mut out = &mut buffer[range];
+            while position < end {
+                let aligned = position - position % SECTOR_LEN;
+                card.device.read(address + aligned as u32, &mut sector);
+                let part = (aligned + SECTOR_LEN).min(end) - position;
+                out[..part].copy_from_slice(&sector[position - aligned..][..part]);
+                out = &mut out[part..];
+                position += part;
+            }
+            Ok(())
+        })
+    }
+
+    /// Writes `data` from the byte `offset` of the file, erasing and programming each block
+    /// it changes, returning the bytes written, fewer at its end.
+    pub fn write_at(&self, offset: u32, data: &[u8]) -> Result<usize, CardError> {
+        self.each_block(offset, data.len(), |card, block, start, range| {
+            let mut scratch = SCRATCH.lock();
+            let address = block as u32 * BLOCK_LEN as u32;
+            if range.len() < BLOCK_LEN {
+                card.device.read(address, &mut scratch.0);
+            }
+            scratch.0[start..start + range.len()].copy_from_slice(&data[range]);
+            card.device.write_block(block, &scratch.0)
+        })
+    }
+
+    /// The two lines of the comment, if the file has one.
+    pub fn comment(&self) -> Result<Option<[u8; 64]>, CardError> {
+        let offset = self.entry.comment_offset();
+        if offset == 0xffff_ffff {
+            return Ok(None);
+        }
+        let mut comment = [0; 64];
+        self.read_at(offset, &mut comment)?;
+        Ok(Some(comment))
+    }
+}
+
+/// A mounted card.
+#[derive(Debug)]
+pub struct Card {
+    slot: Slot,
+}
+
+impl Card {
+    #[inline]
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+
+    /// Forgets the card, which can be pulled out once no write is running.
+    pub fn unmount(self) {
+        MOUNTED[self.slot.channel()].lock().is_mounted = false;
+    }
+
+    /// The blocks of the card, including the five of its header, directory and
+    /// allocation table.
+    pub fn blocks(&self) -> Result<u16, CardError> {
+        with_mounted(self.slot, |card| Ok(card.blocks))
+    }
+
+    pub fn free_blocks(&self) -> Result<u16, CardError> {
+        with_mounted(self.slot, |card| {
+            Ok(be16(&card.bat.0, Block::BAT_FREE_BLOCKS))
+        })
+    }
+
+    /// The file at `index` of the directory, if it holds one.
+    pub fn entry(&self, index: usize) -> Result<Option<Entry>, CardError> {
+        with_mounted(self.slot, |card| {
+            Ok(Some(card.directory.entry(index)).filter(Entry::is_used))
+        })
+    }
+
+    /// The files on the card, in the order of the directory.
+    pub fn entries(&self) -> impl Iterator<Item = Entry> + '_ {
+        (0..MAX_FILES).filter_map(|index| self.entry(index).ok().flatten())
+    }
+
+    /// Opens the file of `owner` named `name`.
+    pub fn open(&self, owner: Owner, name: &str) -> Result<CardFile, CardError> {
+        let entry = NewFile::new(owner, name, 0).entry()?;
+        with_mounted(self.slot, |card| {
+            let index = card.find(&entry, false).ok_or(CardError::NotFound)?;
+            Ok(CardFile {
+                slot: self.slot,
+                entry: card.directory.entry(index),
+            })
+        })
+    }
+
+    /// Creates `file`, allocating its blocks, which keep what they held.
+    pub fn create(&self, file: &NewFile) -> Result<CardFile, CardError> {
+        self.create_entry(file.entry()?)
+    }
+
+    fn create_entry(&self, mut entry: Entry) -> Result<CardFile, CardError> {
+        with_mounted(self.slot, |card| {
+            if card.find(&entry, false).is_some() {
+                return Err(CardError::Exists);
+            }
+            let index = (0..MAX_FILES)
+                .find(|&i| !card.directory.entry(i).is_used())
+                .ok_or(CardError::NoSpace)?;
+            let blocks = entry.blocks();
+            if blocks == 0 || be16(&card.bat.0, Block::BAT_FREE_BLOCKS) < blocks {
+                return Err(CardError::NoSpace);
+            }
+
+            // The blocks are chained from the one after the block allocated last.
+            let usable = card.blocks - SYSTEM_BLOCKS;
+            let mut last = be16(&card.bat.0, Block::BAT_LAST_ALLOCATED);
+            let (mut first, mut previous) = (None, None);
+            for _ in 0..blocks {
+                let after = last.checked_sub(SYSTEM_BLOCKS).map_or(0, |last| last + 1);
+                let block = (0..usable)
+                    .map(|n| SYSTEM_BLOCKS + (after + n) % usable)
+                    .find(|&block| card.bat.next(block) == BAT_FREE)
+                    .ok_or(CardError::Unformatted)?;
+                card.bat.set_next(block, BAT_LAST);
+                if let Some(previous) = previous {
+                    card.bat.set_next(previous, block);
+                }
+                first.get_or_insert(block);
+                (previous, last) = (Some(block), block);
+            }
+            let free = be16(&card.bat.0, Block::BAT_FREE_BLOCKS) - blocks;
+            set_be16(&mut card.bat.0, Block::BAT_FREE_BLOCKS, free);
+            set_be16(&mut card.bat.0, Block::BAT_LAST_ALLOCATED, last);
+
+            set_be16(&mut entry.0, 0x36, first.unwrap());
+            card.directory.set_entry(index, &entry);
+            card.commit()?;
+            Ok(CardFile {
+                slot: self.slot,
+                entry,
+            })
+        })
+    }
+
+    /// Deletes `file`, freeing its blocks.
+    pub fn delete(&self, file: CardFile) -> Result<(), CardError> {
+        with_mounted(self.slot, |card| {
+            let index = card.find(&file.entry, true).ok_or(CardError::NotFound)?;
+            let mut block = file.entry.first_block();
+            for _ in 0..file.entry.blocks() {
+                let next = card.bat.next(block);
+                card.bat.set_next(block, BAT_FREE);
+                block = next;
+            }
+            let free = be16(&card.bat.0, Block::BAT_FREE_BLOCKS) + file.entry.blocks();
+            set_be16(&mut card.bat.0, Block::BAT_FREE_BLOCKS, free);
+            card.directory.set_entry(index, &Entry([0xff; ENTRY_LEN]));
+            card.commit()
+        })
+    }
+
+    /// Creates the file exported as `gci`, its entry followed by its blocks.
+    pub fn import(&self, gci: &[u8]) -> Result<CardFile, CardError> {
+        let entry = Entry::from_gci(gci).ok_or(CardError::InvalidGci)?;
+        if gci.len() != ENTRY_LEN + entry.len() as usize {
+            return Err(CardError::InvalidGci);
+        }
+        let file = self.create_entry(entry)?;
+        file.write_at(0, &gci[ENTRY_LEN..])?;
+        Ok(file)
+    }
+}
+
+static IS_INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum CardInitError {
+    AlreadyInitialized,
+}
+
+#[derive(Clone, Copy)]
+pub struct CardContext {
+    // Makes the type non-trivially constructible.
+    _mark: PhantomData<()>,
+}
+
+impl CardContext {
+    fn init_card() -> Result<(), CardInitError> {
+        Ok(())
+    }
+
+    /// No cards are mounted until [`Self::mount`] mounts them.
+    pub fn init() -> Result<(), CardInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
+            Err(CardInitError::AlreadyInitialized)
+        } else {
+            Self::init_card()
+        }
+    }
+
+    /// The global card context, initialized if it was not.
+    pub fn global() -> Self {
+        #[allow(unreachable_patterns)]
+        match Self::init() {
+            Err(CardInitError::AlreadyInitialized) | Ok(_) => {}
+            Err(e) => panic!("the memory cards failed to initialize: {e:?}"),
+        }
+        unsafe { Self::global_unchecked() }
+    }
+
+    /// # Safety
+    /// Requires that the global card context has been initialized.
+    /// This is ensured by [`Self::global`] or [`Self::init`].
+    pub unsafe fn global_unchecked() -> Self {
+        Self { _mark: PhantomData }
+    }
+
+    /// Mounts the card in `slot`, reading its directory and allocation table. Mounting a
+    /// card again reads them again, and the card mounted before is no longer.
+    pub fn mount(self, slot: Slot) -> Result<Card, CardError> {
+        let channel = slot.channel();
+        if !exi::is_attached_on(channel) {
+            return Err(CardError::NoCard);
+        }
+        let mut device = Device {
+            channel,
+            latency: 4,
+        };
+        // Cards have an ID of their size in megabits, and the latency of their reads.
+        let id = device.id();
+        if id & 0xffff_0003 != 0 || id & 0xfc == 0 {
+            return Err(CardError::NotACard);
+        }
+        device.latency = LATENCIES[(id >> 8) as usize & 7];
+        if device.status() & STATUS_UNLOCKED == 0 {
+            return Err(CardError::Locked);
+        }
+
+        // The blocks are read in place, as they are too large for the stack.
+        let mut card = MOUNTED[channel].lock();
+        card.is_mounted = false;
+        card.device = device;
+        card.blocks = (id & 0xfc) as u16 * 16;
+        let mut scratch = SCRATCH.lock();
+        for (first, is_directory) in [(DIRECTORY_BLOCK, true), (BAT_BLOCK, false)] {
+            let current = if is_directory {
+                &mut card.directory
+            } else {
+                &mut card.bat
+            };
+            let address = |copy: u16| (first + copy) as u32 * BLOCK_LEN as u32;
+            device.read(address(0), &mut current.0);
+            device.read(address(1), &mut scratch.0);
+            let copy = match (current.counter(is_directory), scratch.counter(is_directory)) {
+                (Some(a), Some(b)) if (b.wrapping_sub(a) as i16) > 0 => 1,
+                (None, Some(_)) => 1,
+                (Some(_), _) => 0,
+                (None, None) => return Err(CardError::Unformatted),
+            };
+            if copy == 1 {
+                current.0.copy_from_slice(&scratch.0);
+            }
+            if is_directory {
+                card.directory_copy = copy;
+            } else {
+                card.bat_copy = copy;
+            }
+        }
+        card.is_mounted = true;
+        Ok(Card { slot })
+    }
+}
@@ -0,0 +1,692 @@
+//! The disc drive, reached through the disc interface, and the files of the disc's FST.
+//!
+//! The drive DMAs what it reads into memory, 32 bytes at a time from offsets of 4 bytes.
+//! [`DvdContext::read`] reads as the drive does, and [`File::read_at`] any range of a file,
+//! through a buffer of its own where it has to:
+//!
+//! ```ignore
+//! use rbrew_gc::storage::dvd::DvdContext;
+//!
+//! static mut FST: [u8; 0x4000] = [0; 0x4000];
+//!
+//! let dvd = DvdContext::global();
+//! dvd.reset()?;
+//! let fst = dvd.read_fst(unsafe { &mut *&raw mut FST })?;
+//! let file = fst.open("/sound/music.dsp").ok_or(NotFound)?;
+//! let mut header = [0; 0x60];
+//! file.read_at(0, &mut header)?;
+//! ```
+//!
+//! Commands are queued and run one after the other. [`DvdContext::read_async`] returns
+//! as soon as the read is queued, so that it overlaps with the rest of the frame, and the
+//! disc interface's interrupt completes it and starts the next:
+//!
+//! ```ignore
+//! #[repr(C, align(32))]
+//! struct Level([u8; 0x10_0000]);
+//!
+//! let read = unsafe { dvd.read_async(file.offset(), &mut level.0, None)? };
+//! while !read.is_done() {
+//!     // render a frame
+//! }
+//! read.wait()?;
+//! ```
+//!
+//! Once the cover opens, reads fail until it closes and the drive is [reset] again.
+//!
+//! [reset]: DvdContext::reset
+
+use crate::{
+    cache,
+    interrupt::{self, PiReset, Source, PI},
+};
+use core::{
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, Ordering},
+};
+use rbrew_shared::{interrupt, iotype};
+use spin::Mutex;
+
+iotype! {
+    /// The disc interface, which sends the drive commands and DMAs what it reads.
+    pub type DI: 0x0c006000, 0x28, cached 0x8000_0000, uncached 0xc000_0000 {
+        /// The interrupts of commands, acknowledged by writing them set.
+        sr: mut u32 {
+            /// Aborts the command running.
+            break_request: 0,
+            error_int_mask: 1,
+            /// The drive failed the command.
+            error_int: 2,
+            transfer_int_mask: 3,
+            /// The command completed.
+            transfer_int: 4,
+            break_int_mask: 5,
+            break_int: 6,
+        },
+        cvr: mut u32 {
+            /// The cover is open.
+            open: 0,
+            int_mask: 1,
+            /// The cover opened or closed.
+            int: 2,
+        },
+        /// The command and its arguments.
+        cmd0: mut u32,
+        cmd1: mut u32,
+        cmd2: mut u32,
+        /// The physical address the DMA writes to, 32-byte aligned.
+        mar: mut u32,
+        /// The bytes of the DMA, a multiple of 32.
+        length: mut u32,
+        cr: mut u32 {
+            /// Starts the command, clear once it completed.
+            start: 0,
+            dma: 1,
+            write: 2,
+        },
+        /// The reply of immediate commands.
+        immbuf: mut u32,
+        cfg: const u32,
+    }
+}
+
+const SR_INTERRUPTS: u32 = 1 << 2 | 1 << 4 | 1 << 6;
+/// The masks of the error and transfer interrupts, set while commands are queued.
+const SR_MASKS: u32 = 1 << 1 | 1 << 3;
+
+const READ: u32 = 0xa800_0000;
+const READ_ID: u32 = 0xa800_0040;
+const REQUEST_ERROR: u32 = 0xe000_0000;
+const STOP_MOTOR: u32 = 0xe300_0000;
+
+/// The bytes of a sector of the disc.
+pub const SECTOR_LEN: usize = 0x800;
+
+/// Where the disc's header gives the offset and the length of the FST.
+const FST_LOCATION: u32 = 0x424;
+
+/// The commands that can be queued at once, and whose results are kept once they
+/// completed.
+pub const QUEUE_LEN: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DvdError {
+    /// The cover is open.
+    CoverOpen,
+    /// The drive failed the command, with its error code.
+    Drive(u32),
+    /// The FST is not one, or does not fit in the buffer.
+    InvalidFst,
+    /// The command completed before the last [`QUEUE_LEN`] did, and its result was not
+    /// kept.
+    Forgotten,
+}
+
+/// The identity of the disc, the first bytes of its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DiscId {
+    pub game: [u8; 4],
+    pub maker: [u8; 2],
+    /// The number of the disc in a set, counting from zero.
+    pub disc: u8,
+    pub version: u8,
+}
+
+/// A buffer the drive DMAs into.
+#[repr(C, align(32))]
+struct Sector([u8; SECTOR_LEN]);
+
+/// The buffer of the reads that are not aligned.
+static BOUNCE: Mutex<Sector> = Mutex::new(Sector([0; SECTOR_LEN]));
+
+#[derive(Clone, Copy)]
+struct Request {
+    command: [u32; 3],
+    /// The physical address and the length of the DMA, if the command has one.
+    dma: Option<(u32, u32)>,
+    callback: Option<fn(Result<(), DvdError>)>,
+}
+
+/// The commands queued, the first one running, and the results of those that completed
+/// last, by their tickets.
+struct Queue {
+    requests: [Option<Request>; QUEUE_LEN],
+    head: usize,
+    len: usize,
+    results: [(u32, Result<u32, DvdError>); QUEUE_LEN],
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    requests: [None; QUEUE_LEN],
+    head: 0,
+    len: 0,
+    results: [(0, Err(DvdError::Forgotten)); QUEUE_LEN],
+});
+/// The commands queued and completed since the start, which tickets are numbered by.
+static QUEUED: AtomicU32 = AtomicU32::new(0);
+static COMPLETED: AtomicU32 = AtomicU32::new(0);
+
+/// Starts `request`, unmasking the interrupts that complete it.
+fn start(request: &Request) {
+    unsafe {
+        DI::sr_write(DI::sr_read() & !SR_INTERRUPTS | SR_INTERRUPTS | SR_MASKS);
+        DI::cmd0_write(request.command[0]);
+        DI::cmd1_write(request.command[1]);
+        DI::cmd2_write(request.command[2]);
+        if let Some((address, len)) = request.dma {
+            DI::mar_write(address);
+            DI::length_write(len);
+        }
+        DI::cr_write(
+            DiCr::default()
+                .with_start(true)
+                .with_dma(request.dma.is_some())
+                .0,
+        );
+    }
+}
+
+/// The error the drive failed the last command with, asked for at once.
+fn request_error() -> DvdError {
+    unsafe {
+        DI::cmd0_write(REQUEST_ERROR);
+        DI::cr_write(DiCr::default().with_start(true).0);
+        loop {
+            let sr = DiSr(DI::sr_read());
+            if sr.transfer_int() || sr.error_int() {
+                DI::sr_write(sr.0 & !SR_INTERRUPTS | 1 << 2 | 1 << 4);
+                return DvdError::Drive(DI::immbuf_read());
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Takes the command that completed, keeping its result and starting the next one, and
+/// calls its callback, acknowledging the interrupt.
+fn complete() {
+    let sr = DiSr(unsafe { DI::sr_read() });
+    if !sr.transfer_int() && !sr.error_int() {
+        return;
+    }
+    let result = if sr.error_int() {
+        unsafe { DI::sr_write(sr.0 & !SR_INTERRUPTS | 1 << 2) };
+        // A command fails as the cover opens, which the drive reports as any other error.
+        Err(if unsafe { DI::cvr_open_read() } {
+            DvdError::CoverOpen
+        } else {
+            request_error()
+        })
+    } else {
+        unsafe { DI::sr_write(sr.0 & !SR_INTERRUPTS | 1 << 4) };
+        Ok(unsafe { DI::immbuf_read() })
+    };
+    let callback = {
+        let mut queue = QUEUE.lock();
+        if queue.len == 0 {
+            return;
+        }
+        let head = queue.head;
+        let done = queue.requests[head].take();
+        queue.head = (head + 1) % QUEUE_LEN;
+        queue.len -= 1;
+        let ticket = COMPLETED.load(Ordering::Acquire).wrapping_add(1);
+        queue.results[ticket as usize % QUEUE_LEN] = (ticket, result);
+        COMPLETED.store(ticket, Ordering::Release);
+        match queue.requests[queue.head].filter(|_| queue.len > 0) {
+            Some(next) => start(&next),
+            None => unsafe { DI::sr_write(DI::sr_read() & !SR_INTERRUPTS & !SR_MASKS) },
+        }
+        done.and_then(|request| request.callback)
+    };
+    if let Some(callback) = callback {
+        callback(result.map(drop));
+    }
+}
+
+/// Queues `request`, waiting for a free slot if the queue is full. Fails at once if the
+/// cover is open.
+fn queue(request: Request) -> Result<Command, DvdError> {
+    loop {
+        if unsafe { DI::cvr_open_read() } {
+            return Err(DvdError::CoverOpen);
+        }
+        let ticket = interrupt::free(|| {
+            let mut queue = QUEUE.lock();
+            if queue.len == QUEUE_LEN {
+                return None;
+            }
+            let tail = (queue.head + queue.len) % QUEUE_LEN;
+            queue.requests[tail] = Some(request);
+            queue.len += 1;
+            if queue.len == 1 {
+                start(&request);
+            }
+            Some(QUEUED.fetch_add(1, Ordering::AcqRel) + 1)
+        });
+        match ticket {
+            Some(ticket) => return Ok(Command(ticket)),
+            None => poll(),
+        }
+    }
+}
+
+/// Takes the commands that completed, for programs that do not enable interrupts.
+#[inline]
+pub fn poll() {
+    interrupt::free(complete);
+}
+
+/// A command queued, which completes in the order it was queued in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[must_use]
+pub struct Command(u32);
+
+impl Command {
+    /// Whether the command completed, polling the disc interface first.
+    pub fn is_done(self) -> bool {
+        poll();
+        COMPLETED.load(Ordering::Acquire).wrapping_sub(self.0) as i32 >= 0
+    }
+
+    /// The reply of the command, once it completed.
+    fn reply(self) -> Option<Result<u32, DvdError>> {
+        if !self.is_done() {
+            return None;
+        }
+        let (ticket, result) =
+            interrupt::free(|| QUEUE.lock().results[self.0 as usize % QUEUE_LEN]);
+        Some(if ticket == self.0 {
+            result
+        } else {
+            Err(DvdError::Forgotten)
+        })
+    }
+
+    /// The result of the command, once it completed. It is kept until [`QUEUE_LEN`] more
+    /// commands complete.
+    #[inline]
+    pub fn result(self) -> Option<Result<(), DvdError>> {
+        self.reply().map(|result| result.map(drop))
+    }
+
+    /// Waits for the command, returning its result.
+    pub fn wait(self) -> Result<(), DvdError> {
+        self.wait_reply().map(drop)
+    }
+
+    fn wait_reply(self) -> Result<u32, DvdError> {
+        loop {
+            if let Some(result) = self.reply() {
+                return result;
+            }
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Queues `command`, DMAing into `buffer` if there is one, and waits for it, returning the
+/// reply.
+fn run(command: [u32; 3], buffer: Option<&mut [u8]>) -> Result<u32, DvdError> {
+    let dma = buffer.as_ref().map(|buffer| {
+        cache::flush_data_range(buffer.as_ptr(), buffer.len());
+        (buffer.as_ptr() as u32 & 0x3fff_ffff, buffer.len() as u32)
+    });
+    let reply = queue(Request {
+        command,
+        dma,
+        callback: None,
+    })?
+    .wait_reply();
+    if let Some(buffer) = buffer {
+        cache::flush_data_range(buffer.as_ptr(), buffer.len());
+    }
+    reply
+}
+
+/// Reads `buffer` from `offset` on the disc, as the drive does.
+fn read(offset: u32, buffer: &mut [u8]) -> Result<(), DvdError> {
+    let len = buffer.len() as u32;
+    run([READ, offset >> 2, len], Some(buffer)).map(drop)
+}
+
+/// Reads `buffer` from `offset` on the disc, through the bounce buffer where it is not
+/// aligned.
+fn read_unaligned(mut offset: u32, mut buffer: &mut [u8]) -> Result<(), DvdError> {
+    while !buffer.is_empty() {
+        let aligned = (buffer.as_ptr() as usize).is_multiple_of(32) && offset.is_multiple_of(4);
+        if aligned && buffer.len() >= 32 {
+            let len = buffer.len() & !31;
+            let (now, rest) = buffer.split_at_mut(len);
+            read(offset, now)?;
+            (offset, buffer) = (offset + len as u32, rest);
+        } else {
+            let mut bounce = BOUNCE.lock();
+            let start = offset & !31;
+            read(start, &mut bounce.0)?;
+            let skip = (offset - start) as usize;
+            let len = buffer.len().min(SECTOR_LEN - skip);
+            let (now, rest) = buffer.split_at_mut(len);
+            now.copy_from_slice(&bounce.0[skip..skip + len]);
+            (offset, buffer) = (offset + len as u32, rest);
+        }
+    }
+    Ok(())
+}
+
+/// The `fn(bool)` called when the cover opens or closes, null if there is none.
+static COVER_CALLBACK: AtomicPtr<()> = AtomicPtr::new(ptr::null_mut());
+
+#[interrupt(Source::Di)]
+fn di_interrupt() {
+    complete();
+    if !unsafe { DI::cvr_int_read() } {
+        return;
+    }
+    unsafe { DI::cvr_int_write(true) };
+    let callback = COVER_CALLBACK.load(Ordering::Acquire);
+    if !callback.is_null() {
+        let callback = unsafe { core::mem::transmute::<*mut (), fn(bool)>(callback) };
+        callback(unsafe { DI::cvr_open_read() });
+    }
+}
+
+/// A file on the disc.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct File {
+    offset: u32,
+    len: u32,
+}
+
+impl File {
+    /// Where the file starts on the disc.
+    #[inline]
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    #[inline]
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads the file from `offset` into `buffer`, up to its end, returning the bytes read.
+    ///
+    /// The whole 32-byte units of reads from offsets of 4 bytes into 32-byte aligned
+    /// buffers are DMAed in place, and the rest goes through a buffer of the driver.
+    pub fn read_at(&self, offset: u32, buffer: &mut [u8]) -> Result<usize, DvdError> {
+        let len = buffer.len().min(self.len.saturating_sub(offset) as usize);
+        read_unaligned(self.offset + offset, &mut buffer[..len])?;
+        Ok(len)
+    }
+}
+
+/// The file system table of the disc, its directories and files.
+///
+/// It starts with the entries, 12 bytes each: the root directory first, and every
+/// directory followed by its children. The names they point to follow.
+#[derive(Clone, Copy, Debug)]
+pub struct Fst<'a> {
+    entries: &'a [u8],
+    names: &'a [u8],
+}
+
+impl<'a> Fst<'a> {
+    /// The table in `fst`, or `None` if it is not one.
+    pub fn parse(fst: &'a [u8]) -> Option<Self> {
+        let root = fst.get(..12)?;
+        let count = u32::from_be_bytes(root[8..12].try_into().unwrap()) as usize;
+        if root[0] != 1 || count == 0 {
+            return None;
+        }
+        let (entries, names) = fst.split_at_checked(count * 12)?;
+        Some(Self { entries, names })
+    }
+
+    fn word(&self, index: usize, word: usize) -> u32 {
+        let at = index * 12 + word * 4;
+        u32::from_be_bytes(self.entries[at..at + 4].try_into().unwrap())
+    }
+
+    fn is_dir(&self, index: usize) -> bool {
+        self.entries[index * 12] != 0
+    }
+
+    fn name(&self, index: usize) -> &'a [u8] {
+        let start = (self.word(index, 0) & 0xff_ffff) as usize;
+        let name = self.names.get(start..).unwrap_or_default();
+        name.split(|&byte| byte == 0).next().unwrap_or_default()
+    }
+
+    /// The entry at `path`, from the root, its components separated by `/`.
+    fn find(&self, path: &str) -> Option<usize> {
+        let mut dir = 0;
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            if !self.is_dir(dir) {
+                return None;
+            }
+            let end = self.word(dir, 2) as usize;
+            let mut index = dir + 1;
+            loop {
+                if index >= end || index * 12 >= self.entries.len() {
+                    return None;
+                }
+                if self.name(index).eq_ignore_ascii_case(component.as_bytes()) {
+                    break;
+                }
+                // Skipping the children of directories.
+                index = if self.is_dir(index) {
+                    self.word(index, 2) as usize
+                } else {
+                    index + 1
+                };
+            }
+            dir = index;
+        }
+        Some(dir)
+    }
+
+    /// The file at `path`, from the root, its components separated by `/` and matched
+    /// regardless of case. `None` if there is none, or it is a directory.
+    pub fn open(&self, path: &str) -> Option<File> {
+        let index = self.find(path)?;
+        (!self.is_dir(index)).then(|| File {
+            offset: self.word(index, 1),
+            len: self.word(index, 2),
+        })
+    }
+
+    /// Whether there is a directory at `path`.
+    pub fn is_dir_at(&self, path: &str) -> bool {
+        self.find(path).is_some_and(|index| self.is_dir(index))
+    }
+}
+
+static IS_INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum DvdInitError {
+    AlreadyInitialized,
+}
+
+#[derive(Clone, Copy)]
+pub struct DvdContext {
+    // Makes the type non-trivially constructible.
+    _mark: PhantomData<()>,
+}
+
+impl DvdContext {
+    fn init_dvd() -> Result<(), DvdInitError> {
+        unsafe {
+            DI::sr_write(SR_INTERRUPTS);
+            DI::cvr_write(DiCvr::default().with_int(true).0);
+        }
+        Ok(())
+    }
+
+    /// Acknowledges the interrupts of the disc interface, masking them. The drive is left
+    /// as the loader left it.
+    pub fn init() -> Result<(), DvdInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
+            Err(DvdInitError::AlreadyInitialized)
+        } else {
+            Self::init_dvd()
+        }
+    }
+
+    /// The global DVD context, initialized if it was not.
+    pub fn global() -> Self {
+        #[allow(unreachable_patterns)]
+        match Self::init() {
+            Err(DvdInitError::AlreadyInitialized) | Ok(_) => {}
+            Err(e) => panic!("the dvd failed to initialize: {e:?}"),
+        }
+        unsafe { Self::global_unchecked() }
+    }
+
+    /// # Safety
+    /// Requires that the global DVD context has been initialized.
+    /// This is ensured by [`Self::global`] or [`Self::init`].
+    pub unsafe fn global_unchecked() -> Self {
+        Self { _mark: PhantomData }
+    }
+
+    /// Waits for the commands queued, resets the drive, then reads the disc's identity,
+    /// which the drive requires before it reads the disc.
+    pub fn reset(self) -> Result<DiscId, DvdError> {
+        self.wait_idle();
+        interrupt::free(|| unsafe {
+            let reset = PiReset(PI::reset_read());
+            PI::reset_write(reset.with_di(false).with_system(true).0);
+            crate::delay(12);
+            PI::reset_write(reset.with_di(true).with_system(true).0);
+        });
+        self.read_disc_id()
+    }
+
+    /// Reads the disc's identity, which the drive requires after a reset or once a disc
+    /// is inserted.
+    pub fn read_disc_id(self) -> Result<DiscId, DvdError> {
+        let mut bounce = BOUNCE.lock();
+        run([READ_ID, 0, 32], Some(&mut bounce.0[..32]))?;
+        let id = &bounce.0;
+        Ok(DiscId {
+            game: id[0..4].try_into().unwrap(),
+            maker: id[4..6].try_into().unwrap(),
+            disc: id[6],
+            version: id[7],
+        })
+    }
+
+    /// Stops the disc spinning, until the next read.
+    pub fn stop_motor(self) -> Result<(), DvdError> {
+        run([STOP_MOTOR, 0, 0], None).map(drop)
+    }
+
+    #[inline]
+    pub fn is_cover_open(self) -> bool {
+        unsafe { DI::cvr_open_read() }
+    }
+
+    /// Reads `buffer` from `offset` on the disc.
+    ///
+    /// Panics if `offset` is not a multiple of 4, or `buffer` is not 32-byte aligned and a
+    /// multiple of 32 bytes.
+    pub fn read(self, offset: u32, buffer: &mut [u8]) -> Result<(), DvdError> {
+        assert!(
+            offset.is_multiple_of(4),
+            "the drive reads from offsets of 4 bytes"
+        );
+        assert!(
+            (buffer.as_ptr() as usize).is_multiple_of(32) && buffer.len().is_multiple_of(32),
+            "the drive reads 32-byte aligned buffers of 32-byte units"
+        );
+        read(offset, buffer)
+    }
+
+    /// Queues reading `buffer` from `offset` on the disc, returning once it is queued, or
+    /// waiting for a free slot if [`QUEUE_LEN`] commands are. `callback` is called with the
+    /// result as the read completes, by the disc interface's interrupt handler with
+    /// interrupts disabled, or from [`poll`].
+    ///
+    /// Panics if `offset` is not a multiple of 4, or `buffer` is not 32-byte aligned and a
+    /// multiple of 32 bytes.
+    ///
+    /// # Safety
+    /// `buffer` must stay in memory, and be left alone, until the read completes.
+    pub unsafe fn read_async(
+        self,
+        offset: u32,
+        buffer: &mut [u8],
+        callback: Option<fn(Result<(), DvdError>)>,
+    ) -> Result<Command, DvdError> {
+        assert!(
+            offset.is_multiple_of(4),
+            "the drive reads from offsets of 4 bytes"
+        );
+        assert!(
+            (buffer.as_ptr() as usize).is_multiple_of(32) && buffer.len().is_multiple_of(32),
+            "the drive reads 32-byte aligned buffers of 32-byte units"
+        );
+        cache::flush_data_range(buffer.as_ptr(), buffer.len());
+        queue(Request {
+            command: [READ, offset >> 2, buffer.len() as u32],
+            dma: Some((buffer.as_ptr() as u32 & 0x3fff_ffff, buffer.len() as u32)),
+            callback,
+        })
+    }
+
+    /// Whether no command is queued.
+    pub fn is_idle(self) -> bool {
+        poll();
+        QUEUED.load(Ordering::Acquire) == COMPLETED.load(Ordering::Acquire)
+    }
+
+    /// Waits for the commands queued.
+    pub fn wait_idle(self) {
+        while !self.is_idle() {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Reads `buffer` from the sector `sector`, whole sectors.
+    ///
+    /// Panics if `buffer` is not 32-byte aligned and a multiple of [`SECTOR_LEN`].
+    pub fn read_sectors(self, sector: u32, buffer: &mut [u8]) -> Result<(), DvdError> {
+        assert!(
+            buffer.len().is_multiple_of(SECTOR_LEN),
+            "sectors are read whole"
+        );
+        self.read(sector * SECTOR_LEN as u32, buffer)
+    }
+
+    /// Reads the disc's FST into `buffer`, which it has to fit in.
+    pub fn read_fst(self, buffer: &mut [u8]) -> Result<Fst<'_>, DvdError> {
+        let mut location = [0; 8];
+        read_unaligned(FST_LOCATION, &mut location)?;
+        let offset = u32::from_be_bytes(location[..4].try_into().unwrap());
+        let len = u32::from_be_bytes(location[4..].try_into().unwrap()) as usize;
+        let fst = buffer.get_mut(..len).ok_or(DvdError::InvalidFst)?;
+        read_unaligned(offset, fst)?;
+        Fst::parse(fst).ok_or(DvdError::InvalidFst)
+    }
+
+    /// Sets the function called when the cover opens or closes, with whether it is open,
+    /// returning the one set before. `None` masks the interrupt.
+    ///
+    /// It is called by the disc interface's interrupt handler, with interrupts disabled,
+    /// once [`interrupt::init`](crate::interrupt::init) installed it and interrupts are
+    /// enabled.
+    pub fn set_cover_callback(self, callback: Option<fn(bool)>) -> Option<fn(bool)> {
+        let new = callback.map_or(ptr::null_mut(), |callback| callback as *mut ());
+        let old = COVER_CALLBACK.swap(new, Ordering::AcqRel);
+        interrupt::free(|| unsafe { DI::cvr_int_mask_write(callback.is_some()) });
+        (!old.is_null()).then(|| unsafe { core::mem::transmute::<*mut (), fn(bool)>(old) })
+    }
+}
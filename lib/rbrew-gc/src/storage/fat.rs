@@ -0,0 +1,1203 @@
+//! FAT16 and FAT32 filesystems on [`BlockDevice`]s, with long file names, as SD cards are
+//! formatted.
+//!
+//! The filesystem is found at the start of the device, or in the first FAT partition of
+//! its MBR. It goes through a buffer of a block, which writes go through to the device at
+//! once, so that a card can be pulled out whenever no call is running:
+//!
+//! ```ignore
+//! use rbrew_gc::storage::{fat::Fat, sd::{SdContext, SdPort}, FileSystem};
+//!
+//! let mut fs = Fat::mount(SdContext::global().open(SdPort::Sp2)?)?;
+//! for entry in fs.read_dir("/apps")? {
+//!     let entry = entry?;
+//!     // ...
+//! }
+//! let mut log = fs.create("/apps/game/log.txt")?;
+//! fs.append(&mut log, b"started\n")?;
+//! ```
+//!
+//! Files are created with the long name given, and a short one made up from it where the
+//! name is not one already. Their times are left at the start of 1980.
+
+use super::{BlockDevice, DirEntry, FileSystem, BLOCK_LEN};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatError<E> {
+    /// The device failed, with its error.
+    Device(E),
+    /// There is no FAT filesystem at the start of the device or in its partitions.
+    NotFat,
+    /// The filesystem is FAT12, or its sectors are not blocks of the device.
+    Unsupported,
+    /// A cluster chain leads out of the filesystem.
+    Corrupt,
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    Exists,
+    /// There are no free clusters, the FAT16 root directory is full, or the file would
+    /// pass 4 GiB.
+    NoSpace,
+    /// The name is empty, too long, or has a character FAT forbids.
+    InvalidName,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatKind {
+    Fat16,
+    Fat32,
+}
+
+/// The bytes of a directory entry.
+const ENTRY_LEN: usize = 0x20;
+const ENTRIES_PER_BLOCK: u32 = (BLOCK_LEN / ENTRY_LEN) as u32;
+
+/// The attributes of an entry.
+const ATTR_VOLUME: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+/// The attributes of the entries holding parts of a long name.
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+/// The first byte of an entry deleted, and of the entries after the last.
+const DELETED: u8 = 0xe5;
+const END: u8 = 0x00;
+
+/// The bits of an entry's case flags, for a short name shown in lower case.
+const LOWER_BASE: u8 = 0x08;
+const LOWER_EXT: u8 = 0x10;
+
+/// The characters of a long name in each of its entries, and the longest name.
+const LONG_NAME_UNITS: usize = 13;
+const MAX_LONG_NAME: usize = 255;
+/// Where the characters of a long name are in its entries.
+const LONG_NAME_OFFSETS: [usize; LONG_NAME_UNITS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/// The date of the files created, the first of January 1980.
+const DATE_1980: u16 = 1 << 5 | 1;
+
+/// The partition types of FAT16 and FAT32 in an MBR.
+const PARTITION_TYPES: [u8; 6] = [0x04, 0x06, 0x0b, 0x0c, 0x0e, 0x0f];
+
+/// The punctuation short names may hold, besides letters and digits.
+const SHORT_PUNCTUATION: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// The buffered block that is no block.
+const NO_BLOCK: u64 = u64::MAX;
+
+fn le16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn le32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn set_le16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn set_le32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The checksum of a short name, which the entries of its long name hold.
+fn short_checksum(short: &[u8; 11]) -> u8 {
+    short
+        .iter()
+        .fold(0u8, |sum, &byte| sum.rotate_right(1).wrapping_add(byte))
+}
+
+/// Whether `block` is a FAT boot sector rather than an MBR: it starts with a jump, and
+/// its sectors are of a power of two bytes.
+fn is_boot_sector(block: &[u8]) -> bool {
+    matches!(block[0], 0xe9 | 0xeb)
+        && le16(block, 0x0b).is_power_of_two()
+        && le16(block, 0x0b) >= 0x200
+        && (1..=2).contains(&block[0x10])
+}
+
+/// Splits `path` into its directory and its last component.
+fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    path.rsplit_once('/').unwrap_or(("", path))
+}
+
+/// Whether `name` can be a long name: not empty nor `.` or `..`, within 255 UTF-16 units,
+/// without the characters FAT forbids, and not ending in a dot or a space.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && name.encode_utf16().count() <= MAX_LONG_NAME
+        && !name.ends_with(['.', ' '])
+        && !name
+            .chars()
+            .any(|c| c < ' ' || matches!(c, '"' | '*' | '/' | ':' | '<' | '>' | '?' | '\\' | '|'))
+}
+
+fn is_short_char(byte: u8) -> bool {
+    byte.is_ascii_uppercase() || byte.is_ascii_digit() || SHORT_PUNCTUATION.contains(&byte)
+}
+
+/// The short name of `name` and its case flags, if it is one, in upper or lower case.
+fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || ext.contains('.') {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    let mut flags = 0;
+    for (part, field, lower) in [(base, 0..8, LOWER_BASE), (ext, 8..11, LOWER_EXT)] {
+        let has_upper = part.bytes().any(|byte| byte.is_ascii_uppercase());
+        let has_lower = part.bytes().any(|byte| byte.is_ascii_lowercase());
+        if has_upper && has_lower {
+            return None;
+        }
+        if has_lower {
+            flags |= lower;
+        }
+        for (slot, byte) in short[field].iter_mut().zip(part.bytes()) {
+            *slot = byte.to_ascii_uppercase();
+            if !is_short_char(*slot) {
+                return None;
+            }
+        }
+    }
+    Some((short, flags))
+}
+
+/// The short name made up from `name` with the numeric tail `~tail`: its characters in
+/// upper case, those short names cannot hold replaced by `_`.
+fn tailed_short_name(name: &str, tail: u32) -> [u8; 11] {
+    let name = name.trim_start_matches('.');
+    let (base, ext) = name.rsplit_once('.').unwrap_or((name, ""));
+    let shorten = |c: char| match u8::try_from(c.to_ascii_uppercase()) {
+        Ok(byte) if is_short_char(byte) => byte,
+        _ => b'_',
+    };
+    let mut short = [b' '; 11];
+    let mut digits = [0; 10];
+    let mut len = 0;
+    let mut n = tail;
+    while n > 0 || len == 0 {
+        digits[len] = b'0' + (n % 10) as u8;
+        n /= 10;
+        len += 1;
+    }
+    let kept = 8 - 1 - len;
+    let mut at = 0;
+    for c in base.chars().filter(|&c| c != ' ').take(kept) {
+        short[at] = shorten(c);
+        at += 1;
+    }
+    short[at] = b'~';
+    for (i, &digit) in digits[..len].iter().rev().enumerate() {
+        short[at + 1 + i] = digit;
+    }
+    for (slot, c) in short[8..].iter_mut().zip(ext.chars().filter(|&c| c != ' ')) {
+        *slot = shorten(c);
+    }
+    short
+}
+
+/// Whether `name` is the short name `short` shown, regardless of case.
+fn matches_short(short: &[u8; 11], name: &str) -> bool {
+    let mut shown = [0; 12];
+    let mut len = 0;
+    let base = short[..8]
+        .iter()
+        .rposition(|&b| b != b' ')
+        .map_or(0, |end| end + 1);
+    let ext = short[8..]
+        .iter()
+        .rposition(|&b| b != b' ')
+        .map_or(0, |end| end + 1);
+    for &byte in &short[..base] {
+        shown[len] = byte;
+        len += 1;
+    }
+    if ext > 0 {
+        shown[len] = b'.';
+        len += 1;
+        for &byte in &short[8..8 + ext] {
+            shown[len] = byte;
+            len += 1;
+        }
+    }
+    shown[..len].eq_ignore_ascii_case(name.as_bytes())
+}
+
+/// An entry of a directory, the byte `offset` of the block `block`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Slot {
+    block: u64,
+    offset: usize,
+}
+
+/// A position in a directory: the FAT16 root directory, as cluster 0, or a cluster of a
+/// directory's chain.
+#[derive(Clone, Copy)]
+struct Cursor {
+    cluster: u32,
+    index: u32,
+    done: bool,
+}
+
+impl Cursor {
+    #[inline]
+    fn new(cluster: u32) -> Self {
+        Self {
+            cluster,
+            index: 0,
+            done: false,
+        }
+    }
+
+    /// The entry at the position, moving past it. `None` past the directory's last.
+    fn next<D: BlockDevice>(
+        &mut self,
+        fat: &mut Fat<D>,
+    ) -> Result<Option<Slot>, FatError<D::Error>> {
+        if self.done {
+            return Ok(None);
+        }
+        let per_cluster = if self.cluster == 0 {
+            fat.root_entries
+        } else {
+            fat.sectors_per_cluster * ENTRIES_PER_BLOCK
+        };
+        if self.index == per_cluster {
+            match (self.cluster != 0).then(|| fat.next_cluster(self.cluster)) {
+                Some(Ok(Some(next))) => (self.cluster, self.index) = (next, 0),
+                Some(Err(e)) => return Err(e),
+                _ => {
+                    self.done = true;
+                    return Ok(None);
+                }
+            }
+        }
+        let start = if self.cluster == 0 {
+            fat.root_start
+        } else {
+            fat.cluster_block(self.cluster)
+        };
+        let slot = Slot {
+            block: start + (self.index / ENTRIES_PER_BLOCK) as u64,
+            offset: (self.index % ENTRIES_PER_BLOCK) as usize * ENTRY_LEN,
+        };
+        self.index += 1;
+        Ok(Some(slot))
+    }
+}
+
+/// The parts of a long name read so far, from its last.
+struct LongName {
+    units: [u16; MAX_LONG_NAME + LONG_NAME_UNITS],
+    checksum: u8,
+    /// The sequence number of the part expected next, 0 once the first was read.
+    expected: u8,
+    is_valid: bool,
+}
+
+impl LongName {
+    const fn new() -> Self {
+        Self {
+            units: [0xffff; MAX_LONG_NAME + LONG_NAME_UNITS],
+            checksum: 0,
+            expected: 0,
+            is_valid: false,
+        }
+    }
+
+    fn add(&mut self, entry: &[u8]) {
+        let sequence = entry[0] & 0x1f;
+        if entry[0] & 0x40 != 0 {
+            self.units.fill(0xffff);
+            (self.checksum, self.expected, self.is_valid) = (entry[13], sequence, true);
+        }
+        let is_in_range = (1..=MAX_LONG_NAME.div_ceil(LONG_NAME_UNITS) as u8).contains(&sequence);
+        if !is_in_range || sequence != self.expected || entry[13] != self.checksum {
+            self.is_valid = false;
+            return;
+        }
+        let start = (sequence - 1) as usize * LONG_NAME_UNITS;
+        for (i, &offset) in LONG_NAME_OFFSETS.iter().enumerate() {
+            self.units[start + i] = le16(entry, offset);
+        }
+        self.expected -= 1;
+    }
+
+    /// Whether the name is whole and belongs to the short name `short`.
+    fn is_of(&self, short: &[u8; 11]) -> bool {
+        self.is_valid && self.expected == 0 && self.checksum == short_checksum(short)
+    }
+}
+
+/// An entry found in a directory.
+struct Found {
+    entry: DirEntry,
+    short: [u8; 11],
+    cluster: u32,
+    slot: Slot,
+}
+
+impl Found {
+    fn matches(&self, name: &str) -> bool {
+        self.entry
+            .name()
+            .as_bytes()
+            .eq_ignore_ascii_case(name.as_bytes())
+            || matches_short(&self.short, name)
+    }
+
+    fn is_dot(&self) -> bool {
+        self.short[0] == b'.'
+    }
+}
+
+/// A file or directory, the root directory being at no slot.
+struct Node {
+    cluster: u32,
+    is_dir: bool,
+    len: u32,
+    slot: Option<Slot>,
+}
+
+/// A file of a [`Fat`] filesystem.
+#[derive(Clone, Debug)]
+pub struct FatFile {
+    /// The first cluster of the file, 0 while it is empty.
+    cluster: u32,
+    len: u32,
+    /// The file's entry in its directory.
+    slot: Slot,
+}
+
+impl FatFile {
+    #[inline]
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// A block, the unit the filesystem reads and writes its tables in.
+#[repr(C, align(32))]
+struct Block([u8; BLOCK_LEN]);
+
+/// A FAT filesystem mounted on `D`.
+pub struct Fat<D> {
+    device: D,
+    kind: FatKind,
+    sectors_per_cluster: u32,
+    /// The first block of the first FAT, the blocks of each, and their copies.
+    fat_start: u64,
+    fat_blocks: u32,
+    fats: u32,
+    /// The FAT16 root directory, its first block and its entries.
+    root_start: u64,
+    root_entries: u32,
+    /// The first cluster of the FAT32 root directory, 0 on FAT16.
+    root_cluster: u32,
+    /// The block of cluster 2, the first.
+    data_start: u64,
+    clusters: u32,
+    /// The block of the FAT32 information sector, whose free cluster count is cleared
+    /// once a cluster is allocated.
+    info_block: Option<u64>,
+    /// Where the search for a free cluster starts.
+    next_free: u32,
+    buffer: Block,
+    buffered: u64,
+}
+
+impl<D: BlockDevice> Fat<D> {
+    /// Mounts the filesystem at the start of `device`, or in the first FAT partition of
+    /// its MBR.
+    pub fn mount(device: D) -> Result<Self, FatError<D::Error>> {
+        let mut fat = Self {
+            device,
+            kind: FatKind::Fat16,
+            sectors_per_cluster: 1,
+            fat_start: 0,
+            fat_blocks: 0,
+            fats: 0,
+            root_start: 0,
+            root_entries: 0,
+            root_cluster: 0,
+            data_start: 0,
+            clusters: 0,
+            info_block: None,
+            next_free: 2,
+            buffer: Block([0; BLOCK_LEN]),
+            buffered: NO_BLOCK,
+        };
+        fat.load(0)?;
+        let boot = &fat.buffer.0;
+        if le16(boot, 0x1fe) != 0xaa55 {
+            return Err(FatError::NotFat);
+        }
+        let mut start = 0;
+        if !is_boot_sector(boot) {
+            start = (0..4)
+                .map(|i| &boot[0x1be + i * 16..][..16])
+                .find(|partition| PARTITION_TYPES.contains(&partition[4]))
+                .map(|partition| le32(partition, 8) as u64)
+                .ok_or(FatError::NotFat)?;
+            fat.load(start)?;
+            if !is_boot_sector(&fat.buffer.0) {
+                return Err(FatError::NotFat);
+            }
+        }
+        fat.parse_boot(start)?;
+        Ok(fat)
+    }
+
+    /// Reads the layout from the boot sector in the buffer, the block `start`.
+    fn parse_boot(&mut self, start: u64) -> Result<(), FatError<D::Error>> {
+        let boot = &self.buffer.0;
+        if le16(boot, 0x0b) as usize != BLOCK_LEN {
+            return Err(FatError::Unsupported);
+        }
+        let sectors_per_cluster = boot[0x0d] as u32;
+        let reserved = le16(boot, 0x0e) as u32;
+        let fats = boot[0x10] as u32;
+        let root_entries = le16(boot, 0x11) as u32;
+        let total = match le16(boot, 0x13) {
+            0 => le32(boot, 0x20),
+            total => total as u32,
+        };
+        let fat_blocks = match le16(boot, 0x16) {
+            0 => le32(boot, 0x24),
+            blocks => blocks as u32,
+        };
+        if !sectors_per_cluster.is_power_of_two() || fats == 0 || fat_blocks == 0 {
+            return Err(FatError::NotFat);
+        }
+        let root_blocks = root_entries.div_ceil(ENTRIES_PER_BLOCK);
+        let system = reserved + fats * fat_blocks + root_blocks;
+        let clusters = total.checked_sub(system).ok_or(FatError::NotFat)? / sectors_per_cluster;
+        let kind = match clusters {
+            0..4085 => return Err(FatError::Unsupported),
+            4085..65525 => FatKind::Fat16,
+            _ => FatKind::Fat32,
+        };
+
+        self.kind = kind;
+        self.sectors_per_cluster = sectors_per_cluster;
+        self.fat_start = start + reserved as u64;
+        self.fat_blocks = fat_blocks;
+        self.fats = fats;
+        self.root_start = self.fat_start + (fats * fat_blocks) as u64;
+        self.root_entries = root_entries;
+        self.data_start = self.root_start + root_blocks as u64;
+        // The clusters the FAT has entries for, which can be fewer.
+        let entries = fat_blocks as u64 * BLOCK_LEN as u64 / self.entry_len() as u64;
+        self.clusters = clusters.min(entries.saturating_sub(2) as u32);
+        if kind == FatKind::Fat32 {
+            self.root_cluster = le32(boot, 0x2c);
+            let info = le16(boot, 0x30) as u64;
+            if info != 0 && info != 0xffff {
+                let info = start + info;
+                self.load(info)?;
+                let next = le32(&self.buffer.0, 0x1ec);
+                if (2..self.clusters + 2).contains(&next) {
+                    self.next_free = next;
+                }
+                self.info_block = Some(info);
+            }
+            if !self.is_cluster(self.root_cluster) {
+                return Err(FatError::Corrupt);
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn kind(&self) -> FatKind {
+        self.kind
+    }
+
+    /// The bytes of a cluster, the unit files are allocated in.
+    #[inline]
+    pub fn cluster_len(&self) -> u32 {
+        self.sectors_per_cluster * BLOCK_LEN as u32
+    }
+
+    /// Unmounts the filesystem, returning its device.
+    #[inline]
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Reads `block` into the buffer, unless it is there.
+    fn load(&mut self, block: u64) -> Result<(), FatError<D::Error>> {
+        if self.buffered != block {
+            self.buffered = NO_BLOCK;
+            self.device
+                .read_blocks(block, &mut self.buffer.0)
+                .map_err(FatError::Device)?;
+            self.buffered = block;
+        }
+        Ok(())
+    }
+
+    /// Writes the buffer to its block.
+    fn store(&mut self) -> Result<(), FatError<D::Error>> {
+        self.device
+            .write_blocks(self.buffered, &self.buffer.0)
+            .map_err(FatError::Device)
+    }
+
+    /// Forgets the buffer if it holds one of the `count` blocks from `block`, which are
+    /// read or written around it.
+    fn bypass(&mut self, block: u64, count: u64) {
+        if (block..block + count).contains(&self.buffered) {
+            self.buffered = NO_BLOCK;
+        }
+    }
+
+    #[inline]
+    fn entry_len(&self) -> u32 {
+        match self.kind {
+            FatKind::Fat16 => 2,
+            FatKind::Fat32 => 4,
+        }
+    }
+
+    #[inline]
+    fn is_cluster(&self, cluster: u32) -> bool {
+        (2..self.clusters + 2).contains(&cluster)
+    }
+
+    #[inline]
+    fn cluster_block(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster - 2) as u64 * self.sectors_per_cluster as u64
+    }
+
+    /// The entry of `cluster` in the first FAT: the cluster after it, 0 if it is free, or
+    /// an end mark.
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32, FatError<D::Error>> {
+        let offset = cluster * self.entry_len();
+        self.load(self.fat_start + (offset as usize / BLOCK_LEN) as u64)?;
+        let at = offset as usize % BLOCK_LEN;
+        Ok(match self.kind {
+            FatKind::Fat16 => le16(&self.buffer.0, at) as u32,
+            FatKind::Fat32 => le32(&self.buffer.0, at) & 0x0fff_ffff,
+        })
+    }
+
+    /// Sets the entry of `cluster` in every FAT.
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), FatError<D::Error>> {
+        let offset = cluster * self.entry_len();
+        for copy in 0..self.fats {
+            let block =
+                self.fat_start + (copy * self.fat_blocks + offset / BLOCK_LEN as u32) as u64;
+            self.load(block)?;
+            let at = offset as usize % BLOCK_LEN;
+            match self.kind {
+                FatKind::Fat16 => set_le16(&mut self.buffer.0, at, value as u16),
+                FatKind::Fat32 => {
+                    let reserved = le32(&self.buffer.0, at) & 0xf000_0000;
+                    set_le32(&mut self.buffer.0, at, reserved | value & 0x0fff_ffff);
+                }
+            }
+            self.store()?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn end_mark(&self) -> u32 {
+        match self.kind {
+            FatKind::Fat16 => 0xffff,
+            FatKind::Fat32 => 0x0fff_ffff,
+        }
+    }
+
+    /// The cluster after `cluster` in its chain, `None` at its end.
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, FatError<D::Error>> {
+        let next = self.fat_entry(cluster)?;
+        let end = match self.kind {
+            FatKind::Fat16 => 0xfff8,
+            FatKind::Fat32 => 0x0fff_fff8,
+        };
+        if next >= end {
+            Ok(None)
+        } else if self.is_cluster(next) {
+            Ok(Some(next))
+        } else {
+            Err(FatError::Corrupt)
+        }
+    }
+
+    /// The `n`th cluster of the chain from `first`.
+    fn nth_cluster(&mut self, first: u32, n: u32) -> Result<u32, FatError<D::Error>> {
+        (0..n).try_fold(first, |cluster, _| {
+            self.next_cluster(cluster)?.ok_or(FatError::Corrupt)
+        })
+    }
+
+    /// Allocates a free cluster at the end of a chain, after `previous` if it is given.
+    fn allocate(&mut self, previous: Option<u32>) -> Result<u32, FatError<D::Error>> {
+        let start = self.next_free;
+        let mut cluster = start;
+        while self.fat_entry(cluster)? != 0 {
+            cluster = if cluster + 1 == self.clusters + 2 {
+                2
+            } else {
+                cluster + 1
+            };
+            if cluster == start {
+                return Err(FatError::NoSpace);
+            }
+        }
+        self.set_fat_entry(cluster, self.end_mark())?;
+        if let Some(previous) = previous {
+            self.set_fat_entry(previous, cluster)?;
+        }
+        self.next_free = cluster;
+        // The free cluster count is only a hint, which is left unknown rather than kept.
+        if let Some(info) = self.info_block.take() {
+            self.load(info)?;
+            set_le32(&mut self.buffer.0, 0x1e8, u32::MAX);
+            self.store()?;
+        }
+        Ok(cluster)
+    }
+
+    /// Frees the chain from `first`.
+    fn free_chain(&mut self, first: u32) -> Result<(), FatError<D::Error>> {
+        let mut cluster = Some(first);
+        while let Some(current) = cluster {
+            cluster = self.next_cluster(current)?;
+            self.set_fat_entry(current, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Fills `cluster` with zeros.
+    fn zero_cluster(&mut self, cluster: u32) -> Result<(), FatError<D::Error>> {
+        let start = self.cluster_block(cluster);
+        self.buffer.0.fill(0);
+        for block in start..start + self.sectors_per_cluster as u64 {
+            self.buffered = block;
+            self.store()?;
+        }
+        Ok(())
+    }
+
+    fn read_slot(&mut self, slot: Slot) -> Result<[u8; ENTRY_LEN], FatError<D::Error>> {
+        self.load(slot.block)?;
+        Ok(self.buffer.0[slot.offset..][..ENTRY_LEN]
+            .try_into()
+            .unwrap())
+    }
+
+    fn write_slot(
+        &mut self,
+        slot: Slot,
+        entry: &[u8; ENTRY_LEN],
+    ) -> Result<(), FatError<D::Error>> {
+        self.load(slot.block)?;
+        self.buffer.0[slot.offset..][..ENTRY_LEN].copy_from_slice(entry);
+        self.store()
+    }
+
+    /// The next file or directory of the directory at `cursor`, with its long name if it
+    /// has one.
+    fn scan(
+        &mut self,
+        cursor: &mut Cursor,
+        long: &mut LongName,
+    ) -> Result<Option<Found>, FatError<D::Error>> {
+        while let Some(slot) = cursor.next(self)? {
+            let raw = self.read_slot(slot)?;
+            match raw[0] {
+                END => {
+                    cursor.done = true;
+                    return Ok(None);
+                }
+                DELETED => {
+                    long.is_valid = false;
+                    continue;
+                }
+                _ => {}
+            }
+            let attributes = raw[0x0b];
+            if attributes & 0x3f == ATTR_LONG_NAME {
+                long.add(&raw);
+                continue;
+            }
+            if attributes & ATTR_VOLUME != 0 {
+                long.is_valid = false;
+                continue;
+            }
+
+            let mut short: [u8; 11] = raw[..11].try_into().unwrap();
+            if short[0] == 0x05 {
+                short[0] = DELETED;
+            }
+            let mut entry = DirEntry::new();
+            if long.is_of(&short) {
+                let units = long
+                    .units
+                    .iter()
+                    .copied()
+                    .take_while(|&unit| unit != 0 && unit != 0xffff);
+                for c in char::decode_utf16(units) {
+                    entry.push(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+            } else {
+                let flags = raw[0x0c];
+                let lower = |byte: u8, flag: u8| {
+                    char::from(if flags & flag != 0 {
+                        byte.to_ascii_lowercase()
+                    } else {
+                        byte
+                    })
+                };
+                for &byte in short[..8].iter().take_while(|&&byte| byte != b' ') {
+                    entry.push(lower(byte, LOWER_BASE));
+                }
+                if short[8] != b' ' {
+                    entry.push('.');
+                    for &byte in short[8..].iter().take_while(|&&byte| byte != b' ') {
+                        entry.push(lower(byte, LOWER_EXT));
+                    }
+                }
+            }
+            long.is_valid = false;
+
+            let is_dir = attributes & ATTR_DIRECTORY != 0;
+            let high = match self.kind {
+                FatKind::Fat16 => 0,
+                FatKind::Fat32 => le16(&raw, 0x14) as u32,
+            };
+            let len = if is_dir { 0 } else { le32(&raw, 0x1c) };
+            entry.set_kind(is_dir, len);
+            return Ok(Some(Found {
+                entry,
+                short,
+                cluster: high << 16 | le16(&raw, 0x1a) as u32,
+                slot,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// The entry named `name` in the directory at `cluster`.
+    fn find_in(&mut self, cluster: u32, name: &str) -> Result<Option<Found>, FatError<D::Error>> {
+        let (mut cursor, mut long) = (Cursor::new(cluster), LongName::new());
+        while let Some(found) = self.scan(&mut cursor, &mut long)? {
+            if found.matches(name) {
+                return Ok(Some(found));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether a file or directory of the directory at `cluster` has the short name
+    /// `short`.
+    fn has_short(&mut self, cluster: u32, short: &[u8; 11]) -> Result<bool, FatError<D::Error>> {
+        let (mut cursor, mut long) = (Cursor::new(cluster), LongName::new());
+        while let Some(found) = self.scan(&mut cursor, &mut long)? {
+            if found.short == *short {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    #[inline]
+    fn root(&self) -> Node {
+        Node {
+            cluster: self.root_cluster,
+            is_dir: true,
+            len: 0,
+            slot: None,
+        }
+    }
+
+    /// The file or directory at `path`.
+    fn lookup(&mut self, path: &str) -> Result<Node, FatError<D::Error>> {
+        let mut node = self.root();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if !node.is_dir {
+                return Err(FatError::NotADirectory);
+            }
+            let found = self
+                .find_in(node.cluster, name)?
+                .ok_or(FatError::NotFound)?;
+            node = Node {
+                // The `..` of the root's children points to cluster 0, even on FAT32.
+                cluster: match found.cluster {
+                    0 if found.entry.is_dir() => self.root_cluster,
+                    cluster => cluster,
+                },
+                is_dir: found.entry.is_dir(),
+                len: found.entry.len(),
+                slot: Some(found.slot),
+            };
+        }
+        Ok(node)
+    }
+
+    /// The directory `path` is in, and its last component.
+    fn parent<'p>(&mut self, path: &'p str) -> Result<(u32, &'p str), FatError<D::Error>> {
+        let (parent, name) = split_path(path);
+        if !is_valid_name(name) {
+            return Err(FatError::InvalidName);
+        }
+        let parent = self.lookup(parent)?;
+        if !parent.is_dir {
+            return Err(FatError::NotADirectory);
+        }
+        Ok((parent.cluster, name))
+    }
+
+    /// Adds the entry of `name` to the directory at `dir`, with its long name if it needs
+    /// one, growing the directory if it is full. `name` is not in the directory.
+    fn add_entry(
+        &mut self,
+        dir: u32,
+        name: &str,
+        attributes: u8,
+        cluster: u32,
+    ) -> Result<Slot, FatError<D::Error>> {
+        let (short, flags, long_parts) = match exact_short_name(name) {
+            Some((short, flags)) => (short, flags, 0),
+            None => {
+                let mut tail = 1;
+                let short = loop {
+                    let short = tailed_short_name(name, tail);
+                    if !self.has_short(dir, &short)? {
+                        break short;
+                    }
+                    tail += 1;
+                    if tail == 1_000_000 {
+                        return Err(FatError::NoSpace);
+                    }
+                };
+                let units = name.encode_utf16().count();
+                (short, 0, units.div_ceil(LONG_NAME_UNITS))
+            }
+        };
+
+        // A run of free entries for the parts of the long name then the short name.
+        let needed = long_parts + 1;
+        let mut run = [Slot {
+            block: 0,
+            offset: 0,
+        }; MAX_LONG_NAME / LONG_NAME_UNITS + 2];
+        let mut len = 0;
+        let mut cursor = Cursor::new(dir);
+        while len < needed {
+            let slot = match cursor.next(self)? {
+                Some(slot) => slot,
+                None if dir == 0 => return Err(FatError::NoSpace),
+                None => {
+                    let cluster = self.allocate(Some(cursor.cluster))?;
+                    self.zero_cluster(cluster)?;
+                    cursor = Cursor::new(cluster);
+                    continue;
+                }
+            };
+            if matches!(self.read_slot(slot)?[0], END | DELETED) {
+                run[len] = slot;
+                len += 1;
+            } else {
+                len = 0;
+            }
+        }
+
+        let checksum = short_checksum(&short);
+        let mut units = name
+            .encode_utf16()
+            .chain([0])
+            .chain(core::iter::repeat(0xffff));
+        let mut parts = [[0; ENTRY_LEN]; MAX_LONG_NAME / LONG_NAME_UNITS + 1];
+        for (sequence, part) in parts[..long_parts].iter_mut().enumerate() {
+            part[0] = sequence as u8 + 1;
+            part[0x0b] = ATTR_LONG_NAME;
+            part[13] = checksum;
+            for &offset in &LONG_NAME_OFFSETS {
+                set_le16(part, offset, units.next().unwrap());
+            }
+        }
+        for (i, slot) in run[..long_parts].iter().enumerate() {
+            let mut part = parts[long_parts - 1 - i];
+            if i == 0 {
+                part[0] |= 0x40;
+            }
+            self.write_slot(*slot, &part)?;
+        }
+
+        let mut entry = [0; ENTRY_LEN];
+        entry[..11].copy_from_slice(&short);
+        if entry[0] == DELETED {
+            entry[0] = 0x05;
+        }
+        entry[0x0b] = attributes;
+        entry[0x0c] = flags;
+        for offset in [0x10, 0x12, 0x18] {
+            set_le16(&mut entry, offset, DATE_1980);
+        }
+        set_le16(&mut entry, 0x14, (cluster >> 16) as u16);
+        set_le16(&mut entry, 0x1a, cluster as u16);
+        let slot = run[long_parts];
+        self.write_slot(slot, &entry)?;
+        Ok(slot)
+    }
+
+    /// Sets the first cluster and the length in the entry at `slot`.
+    fn update_entry(
+        &mut self,
+        slot: Slot,
+        cluster: u32,
+        len: u32,
+    ) -> Result<(), FatError<D::Error>> {
+        let mut entry = self.read_slot(slot)?;
+        set_le16(&mut entry, 0x14, (cluster >> 16) as u16);
+        set_le16(&mut entry, 0x1a, cluster as u16);
+        set_le32(&mut entry, 0x1c, len);
+        self.write_slot(slot, &entry)
+    }
+
+    /// The block of the byte `offset` of `cluster`, and the offset in it.
+    fn span(&self, cluster: u32, offset: u32) -> (u64, usize) {
+        let block = self.cluster_block(cluster) + (offset as usize / BLOCK_LEN) as u64;
+        (block, offset as usize % BLOCK_LEN)
+    }
+}
+
+/// The entries of a directory of a [`Fat`] filesystem, without `.` and `..`.
+pub struct ReadDir<'a, D> {
+    fat: &'a mut Fat<D>,
+    cursor: Cursor,
+    long: LongName,
+}
+
+impl<D: BlockDevice> Iterator for ReadDir<'_, D> {
+    type Item = Result<DirEntry, FatError<D::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.fat.scan(&mut self.cursor, &mut self.long) {
+                Ok(Some(found)) if found.is_dot() => continue,
+                Ok(found) => return found.map(|found| Ok(found.entry)),
+                Err(e) => {
+                    self.cursor.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl<D: BlockDevice> FileSystem for Fat<D> {
+    type Error = FatError<D::Error>;
+    type File = FatFile;
+    type ReadDir<'a>
+        = ReadDir<'a, D>
+    where
+        Self: 'a;
+
+    fn open(&mut self, path: &str) -> Result<FatFile, Self::Error> {
+        let node = self.lookup(path)?;
+        match node.slot {
+            Some(slot) if !node.is_dir => Ok(FatFile {
+                cluster: node.cluster,
+                len: node.len,
+                slot,
+            }),
+            _ => Err(FatError::IsADirectory),
+        }
+    }
+
+    /// Creates the file at `path`, or frees the clusters of the file there.
+    fn create(&mut self, path: &str) -> Result<FatFile, Self::Error> {
+        let (dir, name) = self.parent(path)?;
+        if let Some(found) = self.find_in(dir, name)? {
+            if found.entry.is_dir() {
+                return Err(FatError::IsADirectory);
+            }
+            self.update_entry(found.slot, 0, 0)?;
+            if self.is_cluster(found.cluster) {
+                self.free_chain(found.cluster)?;
+            }
+            return Ok(FatFile {
+                cluster: 0,
+                len: 0,
+                slot: found.slot,
+            });
+        }
+        let slot = self.add_entry(dir, name, ATTR_ARCHIVE, 0)?;
+        Ok(FatFile {
+            cluster: 0,
+            len: 0,
+            slot,
+        })
+    }
+
+    /// Creates the directory at `path`, a cluster holding its `.` and `..`.
+    fn create_dir(&mut self, path: &str) -> Result<(), Self::Error> {
+        let (dir, name) = self.parent(path)?;
+        if self.find_in(dir, name)?.is_some() {
+            return Err(FatError::Exists);
+        }
+        let cluster = self.allocate(None)?;
+        self.zero_cluster(cluster)?;
+        let parent = if dir == self.root_cluster { 0 } else { dir };
+        for (i, (dots, target)) in [(&b"."[..], cluster), (&b".."[..], parent)]
+            .into_iter()
+            .enumerate()
+        {
+            let mut entry = [0; ENTRY_LEN];
+            entry[..11].fill(b' ');
+            entry[..dots.len()].copy_from_slice(dots);
+            entry[0x0b] = ATTR_DIRECTORY;
+            for offset in [0x10, 0x12, 0x18] {
+                set_le16(&mut entry, offset, DATE_1980);
+            }
+            set_le16(&mut entry, 0x14, (target >> 16) as u16);
+            set_le16(&mut entry, 0x1a, target as u16);
+            let slot = Slot {
+                block: self.cluster_block(cluster),
+                offset: i * ENTRY_LEN,
+            };
+            self.write_slot(slot, &entry)?;
+        }
+        self.add_entry(dir, name, ATTR_DIRECTORY, cluster).map(drop)
+    }
+
+    fn read_dir(&mut self, path: &str) -> Result<ReadDir<'_, D>, Self::Error> {
+        let node = self.lookup(path)?;
+        if !node.is_dir {
+            return Err(FatError::NotADirectory);
+        }
+        Ok(ReadDir {
+            fat: self,
+            cursor: Cursor::new(node.cluster),
+            long: LongName::new(),
+        })
+    }
+
+    #[inline]
+    fn file_len(&self, file: &FatFile) -> u32 {
+        file.len
+    }
+
+    /// Reads the whole blocks of each cluster straight into `buffer`, and the rest through
+    /// the filesystem's buffer.
+    fn read_at(
+        &mut self,
+        file: &FatFile,
+        offset: u32,
+        buffer: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        let len = buffer.len().min(file.len.saturating_sub(offset) as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+        if !self.is_cluster(file.cluster) {
+            return Err(FatError::Corrupt);
+        }
+        let cluster_len = self.cluster_len();
+        let mut cluster = self.nth_cluster(file.cluster, offset / cluster_len)?;
+        let mut position = offset % cluster_len;
+        let mut out = &mut buffer[..len];
+        while !out.is_empty() {
+            if position == cluster_len {
+                cluster = self.next_cluster(cluster)?.ok_or(FatError::Corrupt)?;
+                position = 0;
+            }
+            let (block, skip) = self.span(cluster, position);
+            let in_cluster = (cluster_len - position) as usize;
+            let part = if skip == 0 && out.len() >= BLOCK_LEN {
+                let part = out.len().min(in_cluster) / BLOCK_LEN * BLOCK_LEN;
+                self.bypass(block, (part / BLOCK_LEN) as u64);
+                self.device
+                    .read_blocks(block, &mut out[..part])
+                    .map_err(FatError::Device)?;
+                part
+            } else {
+                let part = out.len().min(BLOCK_LEN - skip);
+                self.load(block)?;
+                out[..part].copy_from_slice(&self.buffer.0[skip..skip + part]);
+                part
+            };
+            out = &mut out[part..];
+            position += part as u32;
+        }
+        Ok(len)
+    }
+
+    /// Fills the last cluster of `file`, then allocates more. The file's entry is updated
+    /// once the data is written.
+    fn append(&mut self, file: &mut FatFile, mut data: &[u8]) -> Result<(), Self::Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let new_len = file
+            .len
+            .checked_add(u32::try_from(data.len()).map_err(|_| FatError::NoSpace)?)
+            .ok_or(FatError::NoSpace)?;
+        let cluster_len = self.cluster_len();
+        let (mut cluster, mut position) = if self.is_cluster(file.cluster) {
+            let last = file.len.saturating_sub(1) / cluster_len;
+            let cluster = self.nth_cluster(file.cluster, last)?;
+            (cluster, file.len - last * cluster_len)
+        } else {
+            let cluster = self.allocate(None)?;
+            file.cluster = cluster;
+            (cluster, 0)
+        };
+        while !data.is_empty() {
+            if position == cluster_len {
+                cluster = match self.next_cluster(cluster)? {
+                    Some(next) => next,
+                    None => self.allocate(Some(cluster))?,
+                };
+                position = 0;
+            }
+            let (block, skip) = self.span(cluster, position);
+            let in_cluster = (cluster_len - position) as usize;
+            let part = if skip == 0 && data.len() >= BLOCK_LEN {
+                let part = data.len().min(in_cluster) / BLOCK_LEN * BLOCK_LEN;
+                self.bypass(block, (part / BLOCK_LEN) as u64);
+                self.device
+                    .write_blocks(block, &data[..part])
+                    .map_err(FatError::Device)?;
+                part
+            } else {
+                let part = data.len().min(BLOCK_LEN - skip);
+                if skip == 0 {
+                    // Past the end of the file, whose block holds nothing to keep.
+                    self.buffer.0.fill(0);
+                    self.buffered = block;
+                } else {
+                    self.load(block)?;
+                }
+                self.buffer.0[skip..skip + part].copy_from_slice(&data[..part]);
+                self.store()?;
+                part
+            };
+            data = &data[part..];
+            position += part as u32;
+        }
+        file.len = new_len;
+        self.update_entry(file.slot, file.cluster, file.len)
+    }
+}
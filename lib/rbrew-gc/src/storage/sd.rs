@@ -0,0 +1,461 @@
+//! SD and SDHC cards in SPI mode, through adapters in the memory card slots, as the SD
+//! Gecko, or in serial port 2 under the console, as the SD2SP2.
+//!
+//! The adapter wires the card's SPI bus to device 0 of its EXI channel. The card starts at
+//! 1 MHz, then runs at 16 MHz once it is ready:
+//!
+//! ```ignore
+//! use rbrew_gc::storage::{sd::{SdContext, SdPort}, BlockDevice};
+//!
+//! let mut card = SdContext::global().open(SdPort::Sp2)?;
+//! let mut mbr = [0; 0x200];
+//! card.read_blocks(0, &mut mbr)?;
+//! ```
+//!
+//! No interrupt handler uses the EXI, so transfers run with interrupts enabled.
+
+use super::{BlockDevice, BLOCK_LEN};
+use crate::exi;
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdPort {
+    SlotA,
+    SlotB,
+    /// Serial port 2, under the console.
+    Sp2,
+}
+
+impl SdPort {
+    #[inline]
+    fn channel(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdError {
+    /// There is no adapter in the port, or no card in the adapter.
+    NoCard,
+    /// The card does not reply, or stays busy.
+    Timeout,
+    /// The card is neither SD version 1 nor version 2.
+    Unsupported,
+    /// The card failed the command, with its R1 reply.
+    Command(u8, u8),
+    /// The card failed a read, with its error token.
+    Read(u8),
+    /// The card rejected a write, with its data reply.
+    Write(u8),
+    /// The buffer is not a whole number of blocks.
+    NotBlocks,
+    /// The blocks go past the end of the card.
+    OutOfRange,
+}
+
+const CMD_GO_IDLE: u8 = 0;
+const CMD_SEND_IF_COND: u8 = 8;
+const CMD_SEND_CSD: u8 = 9;
+const CMD_STOP_TRANSMISSION: u8 = 12;
+const CMD_SET_BLOCKLEN: u8 = 16;
+const CMD_READ_SINGLE: u8 = 17;
+const CMD_READ_MULTIPLE: u8 = 18;
+const CMD_WRITE_SINGLE: u8 = 24;
+const CMD_WRITE_MULTIPLE: u8 = 25;
+const CMD_APP: u8 = 55;
+const CMD_READ_OCR: u8 = 58;
+const ACMD_SEND_OP_COND: u8 = 41;
+
+/// The bits of the R1 reply: the card is initializing, or did not know the command.
+const R1_IDLE: u8 = 0x01;
+const R1_ILLEGAL: u8 = 0x04;
+
+/// The argument of CMD8, 2.7 to 3.6 V and a pattern the card echoes.
+const IF_COND: u32 = 0x1aa;
+/// The bit of ACMD41 and of the OCR for high capacity cards.
+const HIGH_CAPACITY: u32 = 1 << 30;
+
+/// The tokens starting data read or written, ending a multiple-block write, and the reply
+/// of a write accepted.
+const TOKEN_START: u8 = 0xfe;
+const TOKEN_START_MULTIPLE: u8 = 0xfc;
+const TOKEN_STOP: u8 = 0xfd;
+const DATA_ACCEPTED: u8 = 0x05;
+
+/// The bytes waited for a reply, for data, and while the card is busy, and the tries of
+/// ACMD41 while it leaves the idle state.
+const REPLY_BYTES: usize = 16;
+const DATA_BYTES: usize = 0x10_0000;
+const BUSY_BYTES: usize = 0x40_0000;
+const INIT_TRIES: usize = 0x1000;
+
+/// The CRC7 of a command, shifted to the command's last byte with its end bit.
+fn crc7(bytes: &[u8]) -> u8 {
+    let crc = bytes.iter().fold(0u8, |crc, &byte| {
+        (0..8).fold(crc, |crc, bit| {
+            let feedback = (crc >> 6 ^ byte >> (7 - bit)) & 1;
+            (crc << 1 & 0x7f) ^ if feedback != 0 { 0x09 } else { 0 }
+        })
+    });
+    crc << 1 | 1
+}
+
+/// The card's SPI bus, device 0 of the port's channel.
+#[derive(Clone, Copy)]
+struct Spi {
+    channel: usize,
+}
+
+impl Spi {
+    fn deselect(self) {
+        // The card releases its output only on the clock after it is deselected.
+        exi::select_none_on(self.channel, exi::Clock::Mhz16);
+        self.byte();
+        exi::deselect_on(self.channel);
+    }
+
+    fn send(self, bytes: &[u8]) {
+        for chunk in bytes.chunks(4) {
+            let mut word = [0xff; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            exi::exchange_on(self.channel, u32::from_be_bytes(word), chunk.len());
+        }
+    }
+
+    fn receive(self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(4) {
+            let word = exi::exchange_on(self.channel, u32::MAX, chunk.len()).to_be_bytes();
+            chunk.copy_from_slice(&word[..chunk.len()]);
+        }
+    }
+
+    /// Clocks a byte out of the card, sending `0xff`.
+    fn byte(self) -> u8 {
+        exi::exchange_on(self.channel, u32::MAX, 1) as u8
+    }
+
+    /// Sends `command` with `argument`, returning the R1 reply. The card stays selected
+    /// for the rest of the reply and the data.
+    fn command(self, command: u8, argument: u32) -> Result<u8, SdError> {
+        let [a0, a1, a2, a3] = argument.to_be_bytes();
+        let mut bytes = [0x40 | command, a0, a1, a2, a3, 0];
+        bytes[5] = crc7(&bytes[..5]);
+        self.byte();
+        self.send(&bytes);
+        if command == CMD_STOP_TRANSMISSION {
+            // The byte after the command is stuffed.
+            self.byte();
+        }
+        (0..REPLY_BYTES)
+            .map(|_| self.byte())
+            .find(|reply| reply & 0x80 == 0)
+            .ok_or(SdError::Timeout)
+    }
+
+    /// Sends `command`, failing unless the card replies with `expected`.
+    fn expect(self, command: u8, argument: u32, expected: u8) -> Result<(), SdError> {
+        match self.command(command, argument)? {
+            reply if reply == expected => Ok(()),
+            reply => Err(SdError::Command(command, reply)),
+        }
+    }
+
+    /// Sends the application command `command`.
+    fn app_command(self, command: u8, argument: u32) -> Result<u8, SdError> {
+        self.command(CMD_APP, 0)?;
+        self.command(command, argument)
+    }
+
+    /// Waits for the card to stop holding its output low while it is busy.
+    fn wait_busy(self) -> Result<(), SdError> {
+        (0..BUSY_BYTES)
+            .any(|_| self.byte() == 0xff)
+            .then_some(())
+            .ok_or(SdError::Timeout)
+    }
+
+    /// Reads a block of data into `block`, after its start token.
+    fn read_data(self, block: &mut [u8]) -> Result<(), SdError> {
+        match (0..DATA_BYTES).map(|_| self.byte()).find(|&b| b != 0xff) {
+            Some(TOKEN_START) => {}
+            Some(token) => return Err(SdError::Read(token)),
+            None => return Err(SdError::Timeout),
+        }
+        self.receive(block);
+        // The CRC, which SPI mode does not check.
+        self.byte();
+        self.byte();
+        Ok(())
+    }
+
+    /// Writes the block `block` after `token`, waiting for the card to program it.
+    fn write_data(self, token: u8, block: &[u8]) -> Result<(), SdError> {
+        self.send(&[0xff, token]);
+        self.send(block);
+        self.send(&[0xff, 0xff]);
+        let reply = self.byte() & 0x1f;
+        if reply != DATA_ACCEPTED {
+            return Err(SdError::Write(reply));
+        }
+        self.wait_busy()
+    }
+
+    /// Runs `f` with the card selected, deselecting it whatever `f` returns.
+    fn selected<R>(
+        self,
+        clock: exi::Clock,
+        f: impl FnOnce() -> Result<R, SdError>,
+    ) -> Result<R, SdError> {
+        let mut bus = exi::Bus::lock(self.channel);
+        bus.select(0, clock);
+        let result = f();
+        self.deselect();
+        result
+    }
+}
+
+/// An SD card set up in SPI mode.
+#[derive(Debug)]
+pub struct SdCard {
+    port: SdPort,
+    /// Whether the card is addressed by block rather than by byte.
+    high_capacity: bool,
+    csd: [u8; 16],
+    blocks: u64,
+}
+
+impl SdCard {
+    #[inline]
+    pub fn port(&self) -> SdPort {
+        self.port
+    }
+
+    /// Whether the card is SDHC or SDXC, rather than standard capacity.
+    #[inline]
+    pub fn is_high_capacity(&self) -> bool {
+        self.high_capacity
+    }
+
+    /// The card's specific data register, which gives its size and timings.
+    #[inline]
+    pub fn csd(&self) -> &[u8; 16] {
+        &self.csd
+    }
+
+    fn spi(&self) -> Spi {
+        Spi {
+            channel: self.port.channel(),
+        }
+    }
+
+    /// The argument of the commands on `block`.
+    fn address(&self, block: u64) -> u32 {
+        if self.high_capacity {
+            block as u32
+        } else {
+            (block * BLOCK_LEN as u64) as u32
+        }
+    }
+
+    /// Checks that `len` bytes from `block` are whole blocks on the card.
+    fn check(&self, block: u64, len: usize) -> Result<usize, SdError> {
+        if !len.is_multiple_of(BLOCK_LEN) {
+            return Err(SdError::NotBlocks);
+        }
+        let count = len / BLOCK_LEN;
+        if block + count as u64 > self.blocks {
+            return Err(SdError::OutOfRange);
+        }
+        if !exi::is_attached_on(self.port.channel()) && self.port != SdPort::Sp2 {
+            return Err(SdError::NoCard);
+        }
+        Ok(count)
+    }
+}
+
+/// The blocks of the card whose specific data register is `csd`.
+fn csd_blocks(csd: &[u8; 16]) -> Option<u64> {
+    match csd[0] >> 6 {
+        // Version 1: (C_SIZE + 1) << (C_SIZE_MULT + 2) blocks of 2 ** READ_BL_LEN bytes.
+        0 => {
+            let size = ((csd[6] as u64 & 0x03) << 10) | (csd[7] as u64) << 2 | csd[8] as u64 >> 6;
+            let mult = ((csd[9] & 0x03) << 1) | csd[10] >> 7;
+            let block_len = csd[5] & 0x0f;
+            Some(((size + 1) << (mult + 2 + block_len)) / BLOCK_LEN as u64)
+        }
+        // Version 2: C_SIZE + 1 units of 512 KiB.
+        1 => {
+            let size = ((csd[7] as u64 & 0x3f) << 16) | (csd[8] as u64) << 8 | csd[9] as u64;
+            Some((size + 1) * 1024)
+        }
+        _ => None,
+    }
+}
+
+impl BlockDevice for SdCard {
+    type Error = SdError;
+
+    #[inline]
+    fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    /// Reads one block with CMD17, or more with CMD18 until CMD12 stops it.
+    fn read_blocks(&mut self, block: u64, buffer: &mut [u8]) -> Result<(), SdError> {
+        let count = self.check(block, buffer.len())?;
+        let (spi, address) = (self.spi(), self.address(block));
+        spi.selected(exi::Clock::Mhz16, || {
+            if count == 1 {
+                spi.expect(CMD_READ_SINGLE, address, 0)?;
+                return spi.read_data(buffer);
+            }
+            spi.expect(CMD_READ_MULTIPLE, address, 0)?;
+            let read = buffer
+                .chunks_exact_mut(BLOCK_LEN)
+                .try_for_each(|block| spi.read_data(block));
+            spi.command(CMD_STOP_TRANSMISSION, 0)?;
+            spi.wait_busy()?;
+            read
+        })
+    }
+
+    /// Writes one block with CMD24, or more with CMD25 until its stop token.
+    fn write_blocks(&mut self, block: u64, data: &[u8]) -> Result<(), SdError> {
+        let count = self.check(block, data.len())?;
+        let (spi, address) = (self.spi(), self.address(block));
+        spi.selected(exi::Clock::Mhz16, || {
+            if count == 1 {
+                spi.expect(CMD_WRITE_SINGLE, address, 0)?;
+                return spi.write_data(TOKEN_START, data);
+            }
+            spi.expect(CMD_WRITE_MULTIPLE, address, 0)?;
+            let written = data
+                .chunks_exact(BLOCK_LEN)
+                .try_for_each(|block| spi.write_data(TOKEN_START_MULTIPLE, block));
+            spi.send(&[TOKEN_STOP]);
+            spi.byte();
+            spi.wait_busy()?;
+            written
+        })
+    }
+}
+
+static IS_INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum SdInitError {
+    AlreadyInitialized,
+}
+
+#[derive(Clone, Copy)]
+pub struct SdContext {
+    // Makes the type non-trivially constructible.
+    _mark: PhantomData<()>,
+}
+
+impl SdContext {
+    fn init_sd() -> Result<(), SdInitError> {
+        Ok(())
+    }
+
+    /// No card is set up until [`Self::open`] sets it up.
+    pub fn init() -> Result<(), SdInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
+            Err(SdInitError::AlreadyInitialized)
+        } else {
+            Self::init_sd()
+        }
+    }
+
+    /// The global SD context, initialized if it was not.
+    pub fn global() -> Self {
+        #[allow(unreachable_patterns)]
+        match Self::init() {
+            Err(SdInitError::AlreadyInitialized) | Ok(_) => {}
+            Err(e) => panic!("the sd cards failed to initialize: {e:?}"),
+        }
+        unsafe { Self::global_unchecked() }
+    }
+
+    /// # Safety
+    /// Requires that the global SD context has been initialized.
+    /// This is ensured by [`Self::global`] or [`Self::init`].
+    pub unsafe fn global_unchecked() -> Self {
+        Self { _mark: PhantomData }
+    }
+
+    /// Sets up the card in `port` in SPI mode and reads its size. Opening a card again
+    /// sets it up again.
+    ///
+    /// Serial port 2 has no detection of the adapter, whose absence fails as a timeout.
+    pub fn open(self, port: SdPort) -> Result<SdCard, SdError> {
+        let channel = port.channel();
+        if port != SdPort::Sp2 && !exi::is_attached_on(channel) {
+            return Err(SdError::NoCard);
+        }
+        let spi = Spi { channel };
+
+        // At least 74 clocks with the card deselected, then CMD0 selected enters SPI mode.
+        {
+            let mut bus = exi::Bus::lock(channel);
+            bus.select_none(exi::Clock::Mhz1);
+            spi.send(&[0xff; 10]);
+        }
+        let high_capacity = spi.selected(exi::Clock::Mhz1, || {
+            spi.expect(CMD_GO_IDLE, 0, R1_IDLE)?;
+            let version_2 = match spi.command(CMD_SEND_IF_COND, IF_COND)? {
+                reply if reply & R1_ILLEGAL != 0 => false,
+                R1_IDLE => {
+                    let mut echo = [0; 4];
+                    spi.receive(&mut echo);
+                    if u32::from_be_bytes(echo) & 0xfff != IF_COND {
+                        return Err(SdError::Unsupported);
+                    }
+                    true
+                }
+                reply => return Err(SdError::Command(CMD_SEND_IF_COND, reply)),
+            };
+
+            let argument = if version_2 { HIGH_CAPACITY } else { 0 };
+            let mut ready = false;
+            for _ in 0..INIT_TRIES {
+                match spi.app_command(ACMD_SEND_OP_COND, argument)? {
+                    0 => {
+                        ready = true;
+                        break;
+                    }
+                    R1_IDLE => spi.byte(),
+                    reply => return Err(SdError::Command(ACMD_SEND_OP_COND, reply)),
+                };
+            }
+            if !ready {
+                return Err(SdError::Timeout);
+            }
+
+            if !version_2 {
+                spi.expect(CMD_SET_BLOCKLEN, BLOCK_LEN as u32, 0)?;
+                return Ok(false);
+            }
+            spi.expect(CMD_READ_OCR, 0, 0)?;
+            let mut ocr = [0; 4];
+            spi.receive(&mut ocr);
+            Ok(u32::from_be_bytes(ocr) & HIGH_CAPACITY != 0)
+        })?;
+
+        let mut csd = [0; 16];
+        spi.selected(exi::Clock::Mhz16, || {
+            spi.expect(CMD_SEND_CSD, 0, 0)?;
+            spi.read_data(&mut csd)
+        })?;
+        let blocks = csd_blocks(&csd).ok_or(SdError::Unsupported)?;
+        Ok(SdCard {
+            port,
+            high_capacity,
+            csd,
+            blocks,
+        })
+    }
+}
@@ -0,0 +1,59 @@
+//! The vector table, cartridge header and entry point.
+//!
+//! The 68000 reads the initial stack pointer and program counter from the first two
+//! vectors. The header after the vector table is reserved here and filled in by rbrew
+//! when converting to `.md`.
+
+#[cfg(target_arch = "m68k")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.header, "ax"
+    .global __start
+    .long __stack_top
+    .long __start
+    .rept 62
+    .long __rbrew_exception
+    .endr
+    .space 0x100
+
+__start:
+    ; move.w #0x2700, %sr masks interrupts, which LLVM's assembler encodes as a move to
+    ; %d0.
+    .short 0x46fc, 0x2700
+
+    ; From the second hardware revision on, the VDP stays locked until the TMSS sees
+    ; "SEGA" written to it.
+    lea 0xa10001, %a0
+    move.b (%a0), %d0
+    and.b #0x0f, %d0
+    beq 1f
+    move.l #0x53454741, %d0
+    move.l %d0, 0xa14000
+1:
+    lea __data_load, %a0
+    lea __data_start, %a1
+    lea __data_end, %a2
+2:
+    move.l %a1, %d0
+    cmp.l %a2, %d0
+    bcc 3f
+    move.w (%a0)+, (%a1)+
+    bra 2b
+3:
+    lea __bss_start, %a1
+    lea __bss_end, %a2
+    moveq #0, %d1
+4:
+    move.l %a1, %d0
+    cmp.l %a2, %d0
+    bcc 5f
+    move.w %d1, (%a1)+
+    bra 4b
+5:
+    lea main, %a0
+    jmp (%a0)
+
+__rbrew_exception:
+    rte
+"#
+);
@@ -0,0 +1,15 @@
+/*!
+rbrew-genesis is a library for writing homebrew genesis programs in Rust.
+
+Programs are linked with `targets/genesis.ld` and provide their entry point as
+`#[no_mangle] extern "C" fn main() -> !`, which crt0 calls with interrupts masked once
+`.data` has been copied to RAM and `.bss` has been cleared.
+
+lld cannot link M68k code, so programs are linked with binutils' `m68k-elf-ld`, which has to
+be on the `PATH`.
+*/
+
+#![no_std]
+#![cfg_attr(target_arch = "m68k", feature(asm_experimental_arch))]
+
+mod crt0;
@@ -0,0 +1,29 @@
+//! The entry point, which IPL3 jumps to after copying the program to RDRAM.
+
+#[cfg(target_arch = "mips")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.start, "ax"
+    .set noreorder
+    .global __start
+__start:
+    # IPL3 stores the size of RDRAM at 0x80000318, the stack starts at its top.
+    lui $t0, 0x8000
+    lw $sp, 0x318($t0)
+    addu $sp, $sp, $t0
+    addiu $sp, $sp, -0x10
+
+    la $t0, __bss_start
+    la $t1, __bss_end
+1:
+    beq $t0, $t1, 2f
+    nop
+    sw $zero, 0($t0)
+    b 1b
+    addiu $t0, $t0, 4
+2:
+    la $t0, main
+    jr $t0
+    nop
+"#
+);
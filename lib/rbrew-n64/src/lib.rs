@@ -0,0 +1,12 @@
+/*!
+rbrew-n64 is a library for writing homebrew n64 programs in Rust.
+
+Programs are linked with `targets/n64.ld` and provide their entry point as
+`#[no_mangle] extern "C" fn main() -> !`, which crt0 calls once the stack is set up at
+the top of RDRAM and `.bss` has been cleared.
+*/
+
+#![no_std]
+#![cfg_attr(target_arch = "mips", feature(asm_experimental_arch))]
+
+mod crt0;
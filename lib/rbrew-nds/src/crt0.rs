@@ -0,0 +1,55 @@
+//! The entry points of both CPUs.
+//!
+//! The loader copies each binary to its load address before starting it in ARM mode, so
+//! only the stacks and `.bss` need to be set up.
+
+/// Sets up the IRQ and System mode stacks below the given tops, clears `.bss` and calls
+/// `main`.
+#[cfg(target_arch = "arm")]
+macro_rules! crt0 {
+    ($irq_stack:literal, $sys_stack:literal) => {
+        core::arch::global_asm!(
+            r#"
+    .section .text.rbrew.start, "ax"
+    .arm
+    .global __start
+__start:
+    @ Disable interrupts through REG_IME.
+    mov r0, #0x04000000
+    str r0, [r0, #0x208]
+
+    mov r0, #0x12
+    msr cpsr_c, r0
+    ldr sp, ={irq_stack}
+    mov r0, #0x1f
+    msr cpsr_c, r0
+    ldr sp, ={sys_stack}
+
+    ldr r1, =__bss_start
+    ldr r2, =__bss_end
+    mov r3, #0
+1:
+    cmp r1, r2
+    strlo r3, [r1], #4
+    blo 1b
+
+    ldr r0, =main
+    mov lr, pc
+    bx r0
+2:
+    b 2b
+    .ltorg
+"#,
+            irq_stack = const $irq_stack,
+            sys_stack = const $sys_stack,
+        );
+    };
+}
+
+// The ARM9 keeps its stacks at the top of its part of main RAM.
+#[cfg(all(target_arch = "arm", target_feature = "v5te"))]
+crt0!(0x0238_0000, 0x0237_f000);
+
+// The ARM7 keeps its stacks at the top of its WRAM, like the GBA BIOS.
+#[cfg(all(target_arch = "arm", not(target_feature = "v5te")))]
+crt0!(0x0380_ffa0, 0x0380_ff00);
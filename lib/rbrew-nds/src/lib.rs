@@ -0,0 +1,12 @@
+/*!
+rbrew-nds is a library for writing homebrew nds programs in Rust.
+
+A DS program consists of an ARM9 binary, linked with `targets/nds-arm9.ld`, and
+optionally an ARM7 binary, linked with `targets/nds-arm7.ld`. Both provide their entry
+point as `#[no_mangle] extern "C" fn main() -> !`, which crt0 calls in System mode once
+`.bss` has been cleared. The crate selects the crt0 of the CPU it is built for.
+*/
+
+#![no_std]
+
+mod crt0;
@@ -0,0 +1,15 @@
+//! The entry point, which the BIOS jumps to with `$sp` and `$gp` set from the PS-EXE
+//! header.
+
+#[cfg(target_arch = "mips")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.start, "ax"
+    .set noreorder
+    .global __start
+__start:
+    la $t0, main
+    jr $t0
+    nop
+"#
+);
@@ -0,0 +1,12 @@
+/*!
+rbrew-ps1 is a library for writing homebrew ps1 programs in Rust.
+
+Programs are linked with `targets/ps1.ld` and provide their entry point as
+`#[no_mangle] extern "C" fn main() -> !`, which crt0 calls on the stack the BIOS set up.
+The BIOS loads the program and zeroes `.bss` itself.
+*/
+
+#![no_std]
+#![cfg_attr(target_arch = "mips", feature(asm_experimental_arch))]
+
+mod crt0;
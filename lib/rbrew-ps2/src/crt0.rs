@@ -0,0 +1,61 @@
+//! The entry point, which the kernel's ELF loader jumps to.
+
+#[cfg(target_arch = "mips")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.start, "ax"
+    .set noreorder
+    .global __start
+__start:
+    la $t0, __bss_start
+    la $t1, __bss_end
+1:
+    beq $t0, $t1, 2f
+    nop
+    sw $zero, 0($t0)
+    b 1b
+    addiu $t0, $t0, 4
+2:
+    # SetupThread(gp, stack, stack size, args, exit handler) places a 128 KiB stack at
+    # the top of memory, fills in the arguments and returns the stack pointer.
+    la $gp, _gp
+    move $a0, $gp
+    li $a1, -1
+    li $a2, 0x20000
+    la $a3, __rbrew_args
+    la $t0, __rbrew_exit
+    li $v1, 60
+    syscall
+    move $sp, $v0
+
+    # SetupHeap(start, size) gives the memory between the bss and the stack to the heap.
+    la $a0, __bss_end
+    li $a1, -1
+    li $v1, 61
+    syscall
+
+    # FlushCache(0) writes back the data cache.
+    move $a0, $zero
+    li $v1, 100
+    syscall
+
+    la $t0, __rbrew_args
+    lw $a0, 0($t0)
+    addiu $a1, $t0, 4
+    la $t0, main
+    jr $t0
+    nop
+
+__rbrew_exit:
+    # Exit(0), once the main thread exits.
+    move $a0, $zero
+    li $v1, 4
+    syscall
+
+    # The arguments: their count, up to 16 pointers and the strings they point to.
+    .section .bss.rbrew.args, "aw", @nobits
+    .align 2
+__rbrew_args:
+    .space 4 + 16 * 4 + 256
+"#
+);
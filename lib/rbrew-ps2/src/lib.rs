@@ -0,0 +1,16 @@
+/*!
+rbrew-ps2 is a library for writing homebrew ps2 programs in Rust.
+
+Programs are linked with `targets/ps2.ld` and provide their entry point as
+`#[no_mangle] extern "C" fn main(argc: i32, argv: *const *const u8) -> !`, which crt0
+calls once `.bss` has been cleared and the kernel has set up the main thread's stack and
+heap.
+
+LLVM has no R5900 CPU, so the target generates MIPS II code restricted to what the
+Emotion Engine implements: single precision floats and no atomics.
+*/
+
+#![no_std]
+#![cfg_attr(target_arch = "mips", feature(asm_experimental_arch))]
+
+mod crt0;
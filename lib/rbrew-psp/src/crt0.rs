@@ -0,0 +1,49 @@
+//! The entry point, along with the module info and the exports the kernel requires of
+//! every module.
+
+#[cfg(target_arch = "mips")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.start, "ax"
+    .set noreorder
+    .global module_start
+module_start:
+    # The argument size and pointer are passed through to main.
+    la $t0, main
+    jr $t0
+    nop
+
+    .section .rodata.sceModuleInfo, "a"
+    .align 4
+__rbrew_module_info:
+    # A user mode module, version 1.1.
+    .hword 0
+    .byte 1, 1
+    # The module name, padded to 27 bytes, and its terminator.
+    .ascii "rbrew"
+    .space 27 - 5 + 1
+    .word _gp
+    .word __lib_ent_top
+    .word __lib_ent_bottom
+    .word __lib_stub_top
+    .word __lib_stub_bottom
+
+    # The system library every module exports, holding module_start and the module info.
+    .section .lib.ent, "a"
+    .align 2
+    .word 0
+    .byte 1, 1
+    .hword 0x8000
+    .byte 4, 1
+    .hword 1
+    .word __rbrew_module_exports
+
+    .section .rodata.sceResident, "a"
+    .align 2
+__rbrew_module_exports:
+    .word 0xd632acdb
+    .word 0xf01d73a7
+    .word module_start
+    .word __rbrew_module_info
+"#
+);
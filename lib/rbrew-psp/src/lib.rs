@@ -0,0 +1,12 @@
+/*!
+rbrew-psp is a library for writing homebrew psp programs in Rust.
+
+Programs are linked with `targets/psp.ld` and provide their entry point as
+`#[no_mangle] extern "C" fn main() -> !`, which crt0 calls in the thread the kernel starts
+the module in. The kernel relocates the module and zeroes `.bss` itself.
+*/
+
+#![no_std]
+#![cfg_attr(target_arch = "mips", feature(asm_experimental_arch))]
+
+mod crt0;
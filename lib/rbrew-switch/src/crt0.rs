@@ -0,0 +1,87 @@
+//! The entry point, at the very start of the image.
+//!
+//! The code begins with the NRO start and header, which rbrew fills in, followed by the
+//! MOD0 header the loader uses to find the dynamic section and the bss.
+
+#[cfg(target_arch = "aarch64")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.start, "ax"
+    .global __start
+__start:
+    b 1f
+    .word __rbrew_mod0 - __start
+    .ascii "HOMEBREW"
+    // The NRO header.
+    .space 0x70
+
+__rbrew_mod0:
+    .ascii "MOD0"
+    .word _DYNAMIC - __rbrew_mod0
+    .word __bss_start - __rbrew_mod0
+    .word __bss_end - __rbrew_mod0
+    .word __eh_frame_hdr_start - __rbrew_mod0
+    .word __eh_frame_hdr_end - __rbrew_mod0
+    // The module object, which nothing uses.
+    .word 0
+
+1:
+    // Keep the loader's arguments for main.
+    mov x19, x0
+    mov x20, x1
+
+    adr x0, __start
+    adrp x1, _DYNAMIC
+    add x1, x1, :lo12:_DYNAMIC
+    bl __rbrew_relocate
+
+    adrp x0, __bss_start
+    add x0, x0, :lo12:__bss_start
+    adrp x1, __bss_end
+    add x1, x1, :lo12:__bss_end
+2:
+    cmp x0, x1
+    b.hs 3f
+    str xzr, [x0], #8
+    b 2b
+3:
+    mov x0, x19
+    mov x1, x20
+    bl main
+4:
+    b 4b
+"#
+);
+
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const R_AARCH64_RELATIVE: u64 = 1027;
+
+/// Applies the relative relocations of the program loaded at `base`.
+///
+/// # Safety
+/// Must only be called once by crt0, before anything uses a relocated pointer.
+#[no_mangle]
+unsafe extern "C" fn __rbrew_relocate(base: *mut u8, mut dynamic: *const [u64; 2]) {
+    let (mut rela, mut size) = (0, 0);
+    loop {
+        let [tag, value] = *dynamic;
+        match tag {
+            DT_NULL => break,
+            DT_RELA => rela = value,
+            DT_RELASZ => size = value,
+            _ => {}
+        }
+        dynamic = dynamic.add(1);
+    }
+
+    let entries = base.add(rela as usize) as *const [u64; 3];
+    for i in 0..size as usize / 24 {
+        let [offset, info, addend] = *entries.add(i);
+        if info & 0xffff_ffff == R_AARCH64_RELATIVE {
+            let target = base.add(offset as usize) as *mut u64;
+            *target = (base as u64).wrapping_add(addend);
+        }
+    }
+}
@@ -0,0 +1,12 @@
+/*!
+rbrew-switch is a library for writing homebrew switch programs in Rust.
+
+Programs are linked with `targets/switch.ld` as position independent executables and
+provide their entry point as `#[no_mangle] extern "C" fn main(context: usize, thread:
+usize) -> !`. crt0 applies the program's relocations, clears `.bss` and calls `main`
+with the loader's arguments on the stack the loader set up.
+*/
+
+#![no_std]
+
+mod crt0;
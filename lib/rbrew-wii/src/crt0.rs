@@ -0,0 +1,33 @@
+//! The entry point, which the loader jumps to with the MMU's BATs mapping MEM1 and MEM2.
+
+#[cfg(target_arch = "powerpc")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.start, "ax"
+    .global __start
+__start:
+    lis 1, __stack_top@ha
+    addi 1, 1, __stack_top@l
+    lis 2, _SDA2_BASE_@ha
+    addi 2, 2, _SDA2_BASE_@l
+    lis 13, _SDA_BASE_@ha
+    addi 13, 13, _SDA_BASE_@l
+
+    # The loader zeroes the DOL's bss, but not every loader reads DOLs.
+    lis 3, __bss_start@ha
+    addi 3, 3, __bss_start@l
+    lis 4, __bss_end@ha
+    addi 4, 4, __bss_end@l
+    li 0, 0
+1:
+    cmplw 3, 4
+    bge 2f
+    stw 0, 0(3)
+    addi 3, 3, 4
+    b 1b
+2:
+    # Terminate the back chain of the stack frames.
+    stwu 0, -16(1)
+    b main
+"#
+);
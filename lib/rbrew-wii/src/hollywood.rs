@@ -0,0 +1,43 @@
+//! Registers of Hollywood, the Wii's system chip, that the GameCube has no counterpart of.
+//!
+//! Hollywood's own registers are only accessible from Broadway when IOS leaves them open,
+//! as the loaders homebrew is started from do.
+
+use rbrew_shared::iotype;
+
+iotype! {
+    /// The mailbox to the Starlet, the ARM core running IOS.
+    pub type IPC: 0x0d000000, 0x10, cached 0x8000_0000, uncached 0xc000_0000 {
+        ppcmsg: mut u32 = 0x00,
+        ppcctrl: mut u32 = 0x04,
+        armmsg: const u32 = 0x08,
+        armctrl: mut u32 = 0x0c,
+    }
+}
+
+iotype! {
+    pub type HW: 0x0d800000, 0x400, cached 0x8000_0000, uncached 0xc000_0000 {
+        timer: const u32 = 0x010,
+        alarm: mut u32 = 0x014,
+        ppcirqflag: mut u32 = 0x030,
+        ppcirqmask: mut u32 = 0x034,
+        gpiob_out: mut u32 = 0x0c0,
+        gpiob_dir: mut u32 = 0x0c4,
+        gpiob_in: const u32 = 0x0c8,
+        version: const u32 = 0x214,
+    }
+}
+
+/// The GPIO driving the blue light of the disc slot.
+const GPIO_SLOT_LED: u32 = 1 << 5;
+
+/// Turns the disc slot's light on or off.
+pub fn set_slot_led(on: bool) {
+    unsafe {
+        if on {
+            HW::gpiob_out_set_bits(GPIO_SLOT_LED);
+        } else {
+            HW::gpiob_out_clear_bits(GPIO_SLOT_LED);
+        }
+    }
+}
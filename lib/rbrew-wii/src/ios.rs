@@ -0,0 +1,265 @@
+//! Requests to IOS, the system running on the Starlet, which owns the Wii's devices.
+//!
+//! Requests are passed through the [`IPC`] mailbox by their physical address. IOS
+//! acknowledges them at once and replies when they complete, writing their result in
+//! place. Their buffers are read and written by IOS directly, so they are flushed from the
+//! cache before a request and invalidated after, and are best aligned to cache lines.
+
+use crate::{cache, hollywood::IPC, interrupt};
+use core::{
+    ffi::CStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// The bits of the control registers: a request sent, its acknowledgement, a reply, and
+/// the mailbox freed for the next reply.
+const X1: u32 = 1 << 0;
+const Y2: u32 = 1 << 1;
+const Y1: u32 = 1 << 2;
+const X2: u32 = 1 << 3;
+/// The interrupt enables, kept by the writes.
+const INTERRUPTS: u32 = 0x30;
+
+const OPEN: u32 = 1;
+const CLOSE: u32 = 2;
+const IOCTL: u32 = 6;
+const IOCTLV: u32 = 7;
+
+/// The most buffers of an ioctlv.
+pub const MAX_VECTORS: usize = 8;
+
+/// The error IOS replied with, a negative code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IosError(pub i32);
+
+impl IosError {
+    /// Permission denied.
+    pub const ACCESS: IosError = IosError(-1);
+    pub const EXISTS: IosError = IosError(-2);
+    pub const INVALID: IosError = IosError(-4);
+    /// The device does not exist, or was not opened.
+    pub const NOT_FOUND: IosError = IosError(-6);
+    pub const BUSY: IosError = IosError(-8);
+}
+
+/// The result of a request, the error if it is negative.
+fn result(result: i32) -> Result<u32, IosError> {
+    if result < 0 {
+        Err(IosError(result))
+    } else {
+        Ok(result as u32)
+    }
+}
+
+/// The physical address of `ptr`, in MEM1 or MEM2 through either of their mappings.
+#[inline]
+pub fn physical<T>(ptr: *const T) -> u32 {
+    ptr as u32 & 0x1fff_ffff
+}
+
+/// A buffer of an ioctlv, as IOS reads it.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct Vector {
+    address: u32,
+    len: u32,
+}
+
+/// A request to IOS, kept in place until it completes.
+///
+/// The first line is the one IOS reads and writes. The second is the processor's.
+#[repr(C, align(32))]
+pub struct Request {
+    command: u32,
+    result: i32,
+    fd: i32,
+    args: [u32; 5],
+    done: AtomicBool,
+    vectors: [Vector; MAX_VECTORS],
+}
+
+impl Request {
+    pub const fn new() -> Self {
+        Self {
+            command: 0,
+            result: 0,
+            fd: 0,
+            args: [0; 5],
+            done: AtomicBool::new(true),
+            vectors: [Vector { address: 0, len: 0 }; MAX_VECTORS],
+        }
+    }
+
+    /// Whether IOS replied to the request, polling the mailbox for replies first.
+    pub fn is_done(&self) -> bool {
+        poll();
+        self.done.load(Ordering::Acquire)
+    }
+
+    /// The result of the request, once done.
+    pub fn result(&self) -> Result<u32, IosError> {
+        cache::flush_data_range((self as *const Self).cast(), 32);
+        result(unsafe { (&raw const self.result).read_volatile() })
+    }
+
+    /// Waits for the reply, returning the result.
+    pub fn wait(&self) -> Result<u32, IosError> {
+        while !self.is_done() {
+            core::hint::spin_loop();
+        }
+        self.result()
+    }
+
+    /// Sends the request, which completes when [`Self::is_done`].
+    ///
+    /// # Safety
+    /// The request and the buffers it refers to must stay in place, and be left alone by
+    /// the processor, until it is done.
+    unsafe fn send(&mut self, command: u32, fd: i32, args: [u32; 5]) {
+        assert!(
+            self.done.load(Ordering::Acquire),
+            "the request is still pending"
+        );
+        self.command = command;
+        self.result = 0;
+        self.fd = fd;
+        self.args = args;
+        self.done.store(false, Ordering::Release);
+        cache::flush_data_range((self as *const Self).cast(), size_of::<Self>());
+        interrupt::free(|| unsafe {
+            IPC::ppcmsg_write(physical(self));
+            IPC::ppcctrl_write(IPC::ppcctrl_read() & INTERRUPTS | X1);
+            while IPC::ppcctrl_read() & Y2 == 0 {
+                // The replies come in while IOS takes the request.
+                reply();
+            }
+            IPC::ppcctrl_write(IPC::ppcctrl_read() & INTERRUPTS | Y2);
+        });
+    }
+
+    /// Sends an ioctlv to `fd`, with the buffers `input` read by IOS and `output` written,
+    /// flushing them from the cache.
+    ///
+    /// Panics if there are more than [`MAX_VECTORS`] buffers.
+    ///
+    /// # Safety
+    /// The request and its buffers must stay in place, and be left alone by the
+    /// processor, until it is done. The buffers written are invalidated from the cache by
+    /// [`invalidate`] once it is.
+    pub unsafe fn ioctlv(&mut self, fd: Fd, ioctl: u32, input: &[&[u8]], output: &mut [&mut [u8]]) {
+        assert!(
+            input.len() + output.len() <= MAX_VECTORS,
+            "an ioctlv has at most 8 buffers"
+        );
+        let buffers = input
+            .iter()
+            .map(|b| &**b)
+            .chain(output.iter().map(|b| &**b));
+        for (vector, buffer) in self.vectors.iter_mut().zip(buffers) {
+            cache::flush_data_range(buffer.as_ptr(), buffer.len());
+            *vector = Vector {
+                address: physical(buffer.as_ptr()),
+                len: buffer.len() as u32,
+            };
+        }
+        let vectors = physical(self.vectors.as_ptr());
+        let args = [ioctl, input.len() as u32, output.len() as u32, vectors, 0];
+        unsafe { self.send(IOCTLV, fd.0, args) };
+    }
+}
+
+impl Default for Request {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Invalidates `buffer` from the cache, which IOS wrote.
+#[inline]
+pub fn invalidate(buffer: &[u8]) {
+    cache::flush_data_range(buffer.as_ptr(), buffer.len());
+}
+
+/// Takes the reply in the mailbox, if there is one, marking its request done.
+fn reply() {
+    unsafe {
+        if IPC::ppcctrl_read() & Y1 == 0 {
+            return;
+        }
+        let request = (IPC::armmsg_read() | 0x8000_0000) as *const Request;
+        IPC::ppcctrl_write(IPC::ppcctrl_read() & INTERRUPTS | Y1);
+        IPC::ppcctrl_write(IPC::ppcctrl_read() & INTERRUPTS | X2);
+        (*request).done.store(true, Ordering::Release);
+    }
+}
+
+/// Takes the replies IOS sent, marking their requests done.
+pub fn poll() {
+    interrupt::free(reply);
+}
+
+/// A device opened in IOS.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fd(i32);
+
+/// How a device is opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Mode {
+    None = 0,
+    Read = 1,
+    Write = 2,
+    ReadWrite = 3,
+}
+
+/// Opens the device or file at `path`.
+pub fn open(path: &CStr, mode: Mode) -> Result<Fd, IosError> {
+    let bytes = path.to_bytes_with_nul();
+    cache::flush_data_range(bytes.as_ptr(), bytes.len());
+    let mut request = Request::new();
+    unsafe { request.send(OPEN, 0, [physical(bytes.as_ptr()), mode as u32, 0, 0, 0]) };
+    request.wait().map(|fd| Fd(fd as i32))
+}
+
+impl Fd {
+    pub fn close(self) -> Result<(), IosError> {
+        let mut request = Request::new();
+        unsafe { request.send(CLOSE, self.0, [0; 5]) };
+        request.wait().map(drop)
+    }
+
+    /// Sends `ioctl` with `input` and `output`, waiting for the result.
+    pub fn ioctl(self, ioctl: u32, input: &[u8], output: &mut [u8]) -> Result<u32, IosError> {
+        cache::flush_data_range(input.as_ptr(), input.len());
+        cache::flush_data_range(output.as_ptr(), output.len());
+        let mut request = Request::new();
+        let args = [
+            ioctl,
+            physical(input.as_ptr()),
+            input.len() as u32,
+            physical(output.as_ptr()),
+            output.len() as u32,
+        ];
+        unsafe { request.send(IOCTL, self.0, args) };
+        let result = request.wait();
+        invalidate(output);
+        result
+    }
+
+    /// Sends an ioctlv with the buffers `input` and `output`, waiting for the result.
+    ///
+    /// Panics if there are more than [`MAX_VECTORS`] buffers.
+    pub fn ioctlv(
+        self,
+        ioctl: u32,
+        input: &[&[u8]],
+        output: &mut [&mut [u8]],
+    ) -> Result<u32, IosError> {
+        let mut request = Request::new();
+        unsafe { request.ioctlv(self, ioctl, input, output) };
+        let result = request.wait();
+        output.iter().for_each(|buffer| invalidate(buffer));
+        result
+    }
+}
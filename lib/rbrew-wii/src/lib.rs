@@ -0,0 +1,20 @@
+/*!
+rbrew-wii is a library for writing homebrew wii programs in Rust.
+
+Programs are linked with `targets/wii.ld` and provide their entry point as
+`#[no_mangle] extern "C" fn main() -> !`, which crt0 calls once `.bss` has been cleared.
+
+Broadway and the hardware the Wii inherited from the GameCube are driven by rbrew-gc's
+modules, which are re-exported here. The Wii-only peripherals live in [`hollywood`], and the
+devices IOS owns, like the Bluetooth module of the Wii Remotes, are reached through [`ios`].
+*/
+
+#![no_std]
+
+pub use rbrew_gc::{audio, cache, dol, gfx, include_sample, input, interrupt, print, println};
+
+mod crt0;
+pub mod hollywood;
+pub mod ios;
+pub mod mem2;
+pub mod wpad;
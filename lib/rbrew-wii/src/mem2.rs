@@ -0,0 +1,16 @@
+//! The 64 MiB of MEM2 the Wii adds to the GameCube's 24 MiB of MEM1.
+//!
+//! `targets/wii.ld` places the sections named `.mem2` at its start. What is left up to the
+//! memory IOS reserves at its top is free for the program to use.
+
+use core::ops::Range;
+
+extern "C" {
+    static mut __mem2_start: u8;
+    static mut __mem2_end: u8;
+}
+
+/// The free memory in MEM2.
+pub fn arena() -> Range<*mut u8> {
+    &raw mut __mem2_start..&raw mut __mem2_end
+}
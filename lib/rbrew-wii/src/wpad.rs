@@ -0,0 +1,578 @@
+//! The Wii Remotes, connected through the Bluetooth module and read in the slots of the
+//! four players.
+//!
+//! The remotes paired with the console connect when one of their buttons is pressed, and
+//! are set up to report their buttons, accelerometer and IR camera. [`WpadContext::scan`]
+//! reads what they reported, once a frame:
+//!
+//! ```ignore
+//! use rbrew_wii::{
+//!     input::Port,
+//!     wpad::{Buttons, WpadContext},
+//! };
+//!
+//! let wpad = WpadContext::global();
+//! loop {
+//!     video.wait_for_retrace();
+//!     wpad.scan();
+//!     let state = wpad.state(Port::One);
+//!     if state.pressed(Buttons::A) {
+//!         if let Some([x, y]) = state.pointer() {
+//!             // Shoot at x, y.
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Remotes are paired by the system menu, or by the program that last synced them.
+
+use crate::{
+    input::{map_controls, pad::PortEvent, Axis, Controls, InputSource, Port},
+    ios::IosError,
+};
+use bluetooth::{Bluetooth, LinkEvent};
+use core::{
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use rbrew_shared::ioflags;
+use spin::Mutex;
+
+mod bluetooth;
+
+ioflags! {
+    /// The buttons of a remote, in the bits it reports them in. The directions are those of
+    /// the remote held upright.
+    pub struct Buttons: u16 {
+        const TWO = 1 << 0;
+        const ONE = 1 << 1;
+        const B = 1 << 2;
+        const A = 1 << 3;
+        const MINUS = 1 << 4;
+        const HOME = 1 << 7;
+        const LEFT = 1 << 8;
+        const RIGHT = 1 << 9;
+        const DOWN = 1 << 10;
+        const UP = 1 << 11;
+        const PLUS = 1 << 12;
+    }
+}
+
+/// A dot the IR camera sees, from 0 to 1023 left to right and 0 to 767, as the camera sees
+/// it, and its size from 0 to 15.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IrDot {
+    pub x: u16,
+    pub y: u16,
+    pub size: u8,
+}
+
+impl IrDot {
+    /// The dot in the three bytes of the extended mode, `None` if there is none.
+    fn from_extended([x, y, high]: [u8; 3]) -> Option<Self> {
+        if [x, y, high] == [0xff; 3] {
+            return None;
+        }
+        Some(Self {
+            x: x as u16 | ((high as u16 >> 4) & 3) << 8,
+            y: y as u16 | ((high as u16 >> 6) & 3) << 8,
+            size: high & 0xf,
+        })
+    }
+}
+
+/// The accelerometer's readings at rest and under 1 g, from the remote's memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Calibration {
+    zero: [u16; 3],
+    gravity: [u16; 3],
+}
+
+impl Calibration {
+    /// The readings of most remotes, until its own are read.
+    const NOMINAL: Calibration = Calibration {
+        zero: [512; 3],
+        gravity: [616; 3],
+    };
+
+    /// The calibration in the 8 bytes at 0x16 of the remote's memory: the upper bits of
+    /// the zero point, their lower bits, then the same for 1 g.
+    fn from_memory(data: &[u8]) -> Option<Self> {
+        let axes = |bytes: &[u8]| {
+            let low = bytes[3];
+            [
+                (bytes[0] as u16) << 2 | (low as u16 >> 4) & 3,
+                (bytes[1] as u16) << 2 | (low as u16 >> 2) & 3,
+                (bytes[2] as u16) << 2 | low as u16 & 3,
+            ]
+        };
+        let calibration = Calibration {
+            zero: axes(data.get(..4)?),
+            gravity: axes(data.get(4..8)?),
+        };
+        // Erased memory reads all ones, over which no axis reads 1 g.
+        (calibration
+            .zero
+            .iter()
+            .zip(calibration.gravity)
+            .all(|(&zero, one)| one > zero))
+        .then_some(calibration)
+    }
+}
+
+/// A remote's state at the last [`WpadContext::scan`], and its buttons and connection at the
+/// one before, to tell what changed in between.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WpadState {
+    pub buttons: Buttons,
+    pub previous: Buttons,
+    /// The raw readings of the accelerometer, X to the left, Y forward and Z up, 10 bits
+    /// each.
+    pub accel: [u16; 3],
+    /// The dots the IR camera sees, the two of the sensor bar's among them.
+    pub ir: [Option<IrDot>; 4],
+    /// Whether the remote is connected and set up. The state is empty otherwise.
+    pub connected: bool,
+    /// Whether it was at the scan before.
+    pub was_connected: bool,
+    calibration: Calibration,
+}
+
+impl WpadState {
+    const EMPTY: WpadState = WpadState {
+        buttons: Buttons::empty(),
+        previous: Buttons::empty(),
+        accel: [0; 3],
+        ir: [None; 4],
+        connected: false,
+        was_connected: false,
+        calibration: Calibration::NOMINAL,
+    };
+
+    /// Whether a remote connected since the scan before.
+    #[inline]
+    pub fn plugged(self) -> bool {
+        self.connected && !self.was_connected
+    }
+
+    /// Whether the remote disconnected since the scan before.
+    #[inline]
+    pub fn unplugged(self) -> bool {
+        !self.connected && self.was_connected
+    }
+
+    /// The acceleration along the axes of [`Self::accel`] in g, 1 g up at rest.
+    pub fn acceleration(self) -> [f32; 3] {
+        let Calibration { zero, gravity } = self.calibration;
+        core::array::from_fn(|axis| {
+            (self.accel[axis] as f32 - zero[axis] as f32)
+                / (gravity[axis] as f32 - zero[axis] as f32)
+        })
+    }
+
+    /// Where the remote points, between the first two dots the camera sees, from -1 to 1
+    /// left to right and top to bottom as it points at the screen. `None` while it sees
+    /// fewer than two.
+    pub fn pointer(self) -> Option<[f32; 2]> {
+        let mut dots = self.ir.iter().flatten();
+        let (a, b) = (dots.next()?, dots.next()?);
+        let (x, y) = ((a.x + b.x) as f32 / 2.0, (a.y + b.y) as f32 / 2.0);
+        // The camera sees the sensor bar move the opposite way the remote turns.
+        Some([(512.0 - x) / 512.0, (384.0 - y) / 384.0])
+    }
+
+    /// Whether any of `buttons` is held down.
+    #[inline]
+    pub fn held(self, buttons: Buttons) -> bool {
+        self.buttons.intersects(buttons)
+    }
+
+    /// Whether any of `buttons` went down since the scan before.
+    #[inline]
+    pub fn pressed(self, buttons: Buttons) -> bool {
+        (self.buttons - self.previous).intersects(buttons)
+    }
+
+    /// Whether any of `buttons` went up since the scan before.
+    #[inline]
+    pub fn released(self, buttons: Buttons) -> bool {
+        (self.previous - self.buttons).intersects(buttons)
+    }
+}
+
+impl Default for WpadState {
+    #[inline]
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+/// The controls of the buttons, the 1 and 2 buttons being the X and Y of a remote held
+/// upright.
+const CONTROLS: [(Buttons, Controls); 11] = [
+    (Buttons::A, Controls::A),
+    (Buttons::B, Controls::B),
+    (Buttons::ONE, Controls::X),
+    (Buttons::TWO, Controls::Y),
+    (Buttons::PLUS, Controls::START),
+    (Buttons::MINUS, Controls::SELECT),
+    (Buttons::HOME, Controls::HOME),
+    (Buttons::UP, Controls::UP),
+    (Buttons::DOWN, Controls::DOWN),
+    (Buttons::LEFT, Controls::LEFT),
+    (Buttons::RIGHT, Controls::RIGHT),
+];
+
+impl InputSource for WpadState {
+    #[inline]
+    fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    #[inline]
+    fn controls(&self) -> Controls {
+        map_controls(self.buttons, &CONTROLS)
+    }
+
+    #[inline]
+    fn previous_controls(&self) -> Controls {
+        map_controls(self.previous, &CONTROLS)
+    }
+
+    /// The pointer, centered while the camera does not see the sensor bar. A remote has no
+    /// sticks or triggers.
+    fn axis(&self, axis: Axis) -> f32 {
+        let pointer = self.pointer().unwrap_or_default();
+        match axis {
+            Axis::PointerX => pointer[0],
+            Axis::PointerY => pointer[1],
+            _ => 0.0,
+        }
+    }
+}
+
+/// The output reports: the motor, the lights, the data reported, the IR camera's clock and
+/// logic, and the remote's memory and registers written and read.
+const RUMBLE: u8 = 0x10;
+const LEDS: u8 = 0x11;
+const REPORTING_MODE: u8 = 0x12;
+const IR_CLOCK: u8 = 0x13;
+const WRITE_MEMORY: u8 = 0x16;
+const READ_MEMORY: u8 = 0x17;
+const IR_LOGIC: u8 = 0x1a;
+
+/// The input reports: the status, sent when an extension is plugged in and after which the
+/// data is reported again, the memory read, the acknowledgement of an output report, and
+/// the buttons, accelerometer and IR camera.
+const STATUS: u8 = 0x20;
+const MEMORY_DATA: u8 = 0x21;
+const ACKNOWLEDGE: u8 = 0x22;
+const BUTTONS_ACCEL_IR: u8 = 0x33;
+
+/// The address space of the registers of the remote's peripherals, and those of the IR
+/// camera.
+const REGISTERS: u8 = 0x04;
+const IR_ENABLE: u32 = 0xb0_0030;
+const IR_SENSITIVITY_1: u32 = 0xb0_0000;
+const IR_SENSITIVITY_2: u32 = 0xb0_001a;
+const IR_MODE: u32 = 0xb0_0033;
+/// The camera's mode reporting the position and size of four dots.
+const IR_EXTENDED: u8 = 3;
+
+/// The address of the accelerometer's calibration in the remote's memory.
+const CALIBRATION: u16 = 0x16;
+
+/// A step of setting up a remote once connected.
+#[derive(Clone, Copy)]
+enum Step {
+    Leds,
+    ReadCalibration,
+    IrClock,
+    IrLogic,
+    /// Writes `data` to the register at `address`.
+    Write(u32, &'static [u8]),
+    ReportingMode,
+}
+
+/// The steps setting up a remote, the camera's writes in the order it takes them. The
+/// sensitivity is the middle of the system menu's.
+const SETUP: [Step; 10] = [
+    Step::Leds,
+    Step::ReadCalibration,
+    Step::IrClock,
+    Step::IrLogic,
+    Step::Write(IR_ENABLE, &[0x08]),
+    Step::Write(IR_SENSITIVITY_1, &[0, 0, 0, 0, 0, 0, 0x90, 0, 0xc0]),
+    Step::Write(IR_SENSITIVITY_2, &[0x40, 0]),
+    Step::Write(IR_MODE, &[IR_EXTENDED]),
+    Step::Write(IR_ENABLE, &[0x08]),
+    Step::ReportingMode,
+];
+
+/// A remote as it last reported, and how far it was set up.
+#[derive(Clone, Copy)]
+struct Remote {
+    connected: bool,
+    /// The next step of [`SETUP`], whose steps are sent once the one before was answered.
+    step: usize,
+    waiting: bool,
+    rumble: bool,
+    buttons: Buttons,
+    accel: [u16; 3],
+    ir: [Option<IrDot>; 4],
+    calibration: Calibration,
+}
+
+impl Remote {
+    const DISCONNECTED: Remote = Remote {
+        connected: false,
+        step: 0,
+        waiting: false,
+        rumble: false,
+        buttons: Buttons::empty(),
+        accel: [0; 3],
+        ir: [None; 4],
+        calibration: Calibration::NOMINAL,
+    };
+
+    fn is_set_up(&self) -> bool {
+        self.connected && self.step == SETUP.len()
+    }
+
+    /// Reads the report `data`, its ID first.
+    fn read(&mut self, data: &[u8]) {
+        let &[id, high, low, ref data @ ..] = data else {
+            return;
+        };
+        self.buttons = Buttons::from_bits_truncate(u16::from_be_bytes([high, low]));
+        match (id, data) {
+            (BUTTONS_ACCEL_IR, &[x, y, z, ref ir @ ..]) if ir.len() >= 12 => {
+                // The low bits of the axes are in the unused bits of the buttons.
+                self.accel = [
+                    (x as u16) << 2 | (high as u16 >> 5) & 3,
+                    (y as u16) << 2 | (low as u16 >> 4) & 2,
+                    (z as u16) << 2 | (low as u16 >> 5) & 2,
+                ];
+                for (dot, bytes) in self.ir.iter_mut().zip(ir.chunks_exact(3)) {
+                    *dot = IrDot::from_extended([bytes[0], bytes[1], bytes[2]]);
+                }
+            }
+            (STATUS, _) => {
+                // The remote stops reporting until its mode is set again.
+                if self.is_set_up() {
+                    self.step = SETUP.len() - 1;
+                }
+            }
+            (MEMORY_DATA, &[size_error, _, address, ref memory @ ..]) => {
+                if address as u16 == CALIBRATION && size_error & 0xf == 0 {
+                    self.calibration = Calibration::from_memory(memory).unwrap_or(self.calibration);
+                }
+                self.answered(Step::ReadCalibration);
+            }
+            (ACKNOWLEDGE, &[WRITE_MEMORY, ..]) => {
+                self.answered(Step::Write(0, &[]));
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves to the next step if the one waited for is of the kind of `step`.
+    fn answered(&mut self, step: Step) {
+        let waited = SETUP.get(self.step.wrapping_sub(1)).copied();
+        if self.waiting
+            && waited.is_some_and(|waited| {
+                core::mem::discriminant(&waited) == core::mem::discriminant(&step)
+            })
+        {
+            self.waiting = false;
+        }
+    }
+
+    /// Sends the next step of the setup to the remote in `port`, unless it waits for the
+    /// answer to the last.
+    fn set_up(&mut self, port: Port, bluetooth: &mut Bluetooth) {
+        if !self.connected || self.waiting || self.step == SETUP.len() {
+            return;
+        }
+        let rumble = self.rumble as u8;
+        let mut report = [0; 22];
+        let len = match SETUP[self.step] {
+            Step::Leds => {
+                report[..2].copy_from_slice(&[LEDS, 0x10 << port as u8 | rumble]);
+                2
+            }
+            Step::ReadCalibration => {
+                let [high, low] = CALIBRATION.to_be_bytes();
+                report[..7].copy_from_slice(&[READ_MEMORY, rumble, 0, high, low, 0, 8]);
+                self.waiting = true;
+                7
+            }
+            Step::IrClock | Step::IrLogic => {
+                let id = if let Step::IrClock = SETUP[self.step] {
+                    IR_CLOCK
+                } else {
+                    IR_LOGIC
+                };
+                report[..2].copy_from_slice(&[id, 0x04 | rumble]);
+                2
+            }
+            Step::Write(address, data) => {
+                let [_, a, b, c] = address.to_be_bytes();
+                report[..6].copy_from_slice(&[
+                    WRITE_MEMORY,
+                    REGISTERS | rumble,
+                    a,
+                    b,
+                    c,
+                    data.len() as u8,
+                ]);
+                report[6..6 + data.len()].copy_from_slice(data);
+                self.waiting = true;
+                22
+            }
+            Step::ReportingMode => {
+                report[..3].copy_from_slice(&[REPORTING_MODE, rumble, BUTTONS_ACCEL_IR]);
+                3
+            }
+        };
+        bluetooth.send_report(port, &report[..len]);
+        self.step += 1;
+    }
+}
+
+static BLUETOOTH: Mutex<Bluetooth> = Mutex::new(Bluetooth::new());
+static REMOTES: Mutex<[Remote; 4]> = Mutex::new([Remote::DISCONNECTED; 4]);
+/// The state of the remotes at the last scan.
+static STATES: Mutex<[WpadState; 4]> = Mutex::new([WpadState::EMPTY; 4]);
+
+static IS_INIT: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum WpadInitError {
+    AlreadyInitialized,
+    /// The Bluetooth module could not be opened or transferred to.
+    Ios(IosError),
+    /// The Bluetooth module failed a command with this HCI error.
+    Bluetooth(u8),
+}
+
+#[derive(Clone, Copy)]
+pub struct WpadContext {
+    // Makes the type non-trivially constructible.
+    _mark: PhantomData<()>,
+}
+
+impl WpadContext {
+    fn init_wpad() -> Result<(), WpadInitError> {
+        BLUETOOTH.lock().start()
+    }
+
+    /// Opens the Bluetooth module, letting the paired remotes connect.
+    pub fn init() -> Result<(), WpadInitError> {
+        if IS_INIT.swap(true, Ordering::AcqRel) {
+            Err(WpadInitError::AlreadyInitialized)
+        } else {
+            Self::init_wpad()
+        }
+    }
+
+    /// The global remote context, initialized if it was not.
+    pub fn global() -> Self {
+        #[allow(unreachable_patterns)]
+        match Self::init() {
+            Err(WpadInitError::AlreadyInitialized) | Ok(_) => {}
+            Err(e) => panic!("the wii remotes failed to initialize: {e:?}"),
+        }
+        unsafe { Self::global_unchecked() }
+    }
+
+    /// # Safety
+    /// Requires that the global remote context has been initialized.
+    /// This is ensured by [`Self::global`] or [`Self::init`].
+    pub unsafe fn global_unchecked() -> Self {
+        Self { _mark: PhantomData }
+    }
+
+    /// Reads what the remotes reported since the scan before, connecting and setting up
+    /// the remotes that came in, the buttons held until now becoming the previous ones.
+    ///
+    /// The Bluetooth module keeps a few reports of each remote, so programs scan them at
+    /// least once a frame.
+    pub fn scan(self) {
+        let mut bluetooth = BLUETOOTH.lock();
+        let mut remotes = REMOTES.lock();
+        while let Some(event) = bluetooth.poll() {
+            match event {
+                LinkEvent::Connected(port) => {
+                    remotes[port as usize] = Remote {
+                        connected: true,
+                        ..Remote::DISCONNECTED
+                    };
+                }
+                LinkEvent::Disconnected(port) => remotes[port as usize] = Remote::DISCONNECTED,
+                LinkEvent::Report(port, data, len) => remotes[port as usize].read(&data[..len]),
+            }
+        }
+        for port in Port::ALL {
+            remotes[port as usize].set_up(port, &mut bluetooth);
+        }
+        drop(bluetooth);
+
+        let mut states = STATES.lock();
+        for (state, remote) in states.iter_mut().zip(remotes.iter()) {
+            *state = if remote.is_set_up() {
+                WpadState {
+                    buttons: remote.buttons,
+                    previous: state.buttons,
+                    accel: remote.accel,
+                    ir: remote.ir,
+                    connected: true,
+                    was_connected: state.connected,
+                    calibration: remote.calibration,
+                }
+            } else {
+                WpadState {
+                    previous: state.buttons,
+                    was_connected: state.connected,
+                    ..WpadState::EMPTY
+                }
+            };
+        }
+    }
+
+    /// The remotes connected and disconnected between the last two scans.
+    pub fn events(self) -> impl Iterator<Item = PortEvent> {
+        let states = *STATES.lock();
+        Port::ALL.into_iter().filter_map(move |port| {
+            let state = states[port as usize];
+            if state.plugged() {
+                Some(PortEvent::Plugged(port))
+            } else if state.unplugged() {
+                Some(PortEvent::Unplugged(port))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// The state of the remote in `port` at the last [`Self::scan`].
+    #[inline]
+    pub fn state(self, port: Port) -> WpadState {
+        STATES.lock()[port as usize]
+    }
+
+    /// Starts or stops the motor of the remote in `port`.
+    pub fn set_rumble(self, port: Port, rumble: bool) {
+        let mut bluetooth = BLUETOOTH.lock();
+        let mut remotes = REMOTES.lock();
+        remotes[port as usize].rumble = rumble;
+        if remotes[port as usize].connected {
+            bluetooth.send_report(port, &[RUMBLE, rumble as u8]);
+        }
+    }
+
+    /// Disconnects the remote in `port`, turning it off.
+    pub fn disconnect(self, port: Port) {
+        BLUETOOTH.lock().disconnect(port);
+    }
+}
@@ -0,0 +1,605 @@
+//! The Bluetooth module, a USB device IOS passes the transfers of through, and the HCI
+//! and L2CAP the Wii Remotes are reached through.
+//!
+//! Only the remotes paired with the console are accepted: they connect when a button is
+//! pressed, with the link key the module stored when they were paired, and open the HID
+//! channels themselves.
+
+use super::WpadInitError;
+use crate::{
+    input::Port,
+    ios::{self, Fd, Mode, Request},
+};
+
+/// The transfers of the USB device: control, bulk and interrupt.
+const CONTROL_MESSAGE: u32 = 0;
+const BULK_MESSAGE: u32 = 1;
+const INTERRUPT_MESSAGE: u32 = 2;
+
+/// The endpoints of the HCI events, and of the ACL data from and to the module.
+const EVENT_ENDPOINT: u8 = 0x81;
+const ACL_IN_ENDPOINT: u8 = 0x82;
+const ACL_OUT_ENDPOINT: u8 = 0x02;
+
+/// The largest event, and the ACL packets the module sends in.
+const EVENT_SIZE: usize = 0x120;
+const ACL_SIZE: usize = 0x180;
+/// The ACL reads kept pending, for the packets coming in between two polls.
+const ACL_READS: usize = 4;
+
+const RESET: u16 = 0x0c03;
+const READ_STORED_LINK_KEY: u16 = 0x0c0d;
+const WRITE_SCAN_ENABLE: u16 = 0x0c1a;
+const ACCEPT_CONNECTION: u16 = 0x0409;
+const REJECT_CONNECTION: u16 = 0x040a;
+const LINK_KEY_REPLY: u16 = 0x040b;
+const LINK_KEY_NEGATIVE_REPLY: u16 = 0x040c;
+const DISCONNECT: u16 = 0x0406;
+
+const CONNECTION_COMPLETE: u8 = 0x03;
+const CONNECTION_REQUEST: u8 = 0x04;
+const DISCONNECTION_COMPLETE: u8 = 0x05;
+const COMMAND_COMPLETE: u8 = 0x0e;
+const COMMAND_STATUS: u8 = 0x0f;
+const RETURN_LINK_KEYS: u8 = 0x15;
+const LINK_KEY_REQUEST: u8 = 0x17;
+
+/// The HCI error rejecting connections when the four slots are taken.
+const LIMITED_RESOURCES: u8 = 0x0d;
+/// The HCI error disconnecting a remote on the program's request.
+const USER_TERMINATED: u8 = 0x13;
+
+/// The channel of the L2CAP signalling, and its commands.
+const SIGNALLING: u16 = 0x0001;
+const COMMAND_REJECT: u8 = 0x01;
+const CONNECTION_REQUEST_SIGNAL: u8 = 0x02;
+const CONNECTION_RESPONSE: u8 = 0x03;
+const CONFIGURE_REQUEST: u8 = 0x04;
+const CONFIGURE_RESPONSE: u8 = 0x05;
+const DISCONNECTION_REQUEST: u8 = 0x06;
+const DISCONNECTION_RESPONSE: u8 = 0x07;
+const INFORMATION_REQUEST: u8 = 0x0a;
+const INFORMATION_RESPONSE: u8 = 0x0b;
+
+/// The PSMs of the HID control and interrupt channels.
+const HID_CONTROL: u16 = 0x11;
+const HID_INTERRUPT: u16 = 0x13;
+/// The HID headers of the reports sent and received on the interrupt channel.
+const OUTPUT_REPORT: u8 = 0xa2;
+const INPUT_REPORT: u8 = 0xa1;
+
+/// The most link keys read from the module.
+const MAX_KEYS: usize = 16;
+
+type Address = [u8; 6];
+
+#[derive(Clone, Copy)]
+#[repr(C, align(32))]
+struct Line([u8; 32]);
+
+#[repr(C, align(32))]
+struct Buffer<const N: usize>([u8; N]);
+
+/// A USB transfer, with its request and the buffers IOS reads the setup from, each
+/// aligned to a cache line.
+struct Transfer<const N: usize> {
+    request: Request,
+    setup: [Line; 6],
+    data: Buffer<N>,
+}
+
+impl<const N: usize> Transfer<N> {
+    const fn new() -> Self {
+        Self {
+            request: Request::new(),
+            setup: [Line([0; 32]); 6],
+            data: Buffer([0; N]),
+        }
+    }
+
+    /// Starts a bulk or interrupt transfer of `len` bytes of the data on `endpoint`.
+    ///
+    /// # Safety
+    /// The transfer must stay in place until it is done.
+    unsafe fn start(&mut self, fd: Fd, ioctl: u32, endpoint: u8, len: usize) {
+        self.setup[0].0[0] = endpoint;
+        self.setup[1].0[..2].copy_from_slice(&(len as u16).to_le_bytes());
+        let [endpoint, len_le, ..] = &self.setup;
+        unsafe {
+            self.request.ioctlv(
+                fd,
+                ioctl,
+                &[&endpoint.0[..1], &len_le.0[..2]],
+                &mut [&mut self.data.0[..len]],
+            );
+        }
+    }
+
+    /// The data the transfer read, once done.
+    fn read(&self) -> Option<&[u8]> {
+        let len = self.request.result().ok()? as usize;
+        let data = &self.data.0[..len.min(N)];
+        ios::invalidate(data);
+        Some(data)
+    }
+
+    /// Sends `len` bytes of the data with a class request to the device, waiting for it.
+    fn control(&mut self, fd: Fd, len: usize) -> Result<u32, ios::IosError> {
+        let setup = [
+            &[0x20][..],
+            &[0],
+            &[0, 0],
+            &[0, 0],
+            &(len as u16).to_le_bytes(),
+            &[0],
+        ];
+        for (line, field) in self.setup.iter_mut().zip(setup) {
+            line.0[..field.len()].copy_from_slice(field);
+        }
+        let [a, b, c, d, e, f] = &self.setup;
+        unsafe {
+            self.request.ioctlv(
+                fd,
+                CONTROL_MESSAGE,
+                &[
+                    &a.0[..1],
+                    &b.0[..1],
+                    &c.0[..2],
+                    &d.0[..2],
+                    &e.0[..2],
+                    &f.0[..1],
+                ],
+                &mut [&mut self.data.0[..len]],
+            );
+        }
+        self.request.wait()
+    }
+}
+
+/// A channel of a remote's, by the CID the remote gave it. Configured when both sides
+/// accepted the other's configuration.
+#[derive(Clone, Copy, Default)]
+struct Channel {
+    remote: u16,
+    configured: bool,
+    accepted: bool,
+}
+
+impl Channel {
+    fn is_open(self) -> bool {
+        self.remote != 0 && self.configured && self.accepted
+    }
+}
+
+/// A remote connecting or connected, in the slot of its player.
+#[derive(Clone, Copy)]
+struct Link {
+    address: Address,
+    /// The connection handle, once connected.
+    handle: Option<u16>,
+    /// The HID control and interrupt channels.
+    channels: [Channel; 2],
+    open: bool,
+}
+
+impl Link {
+    /// The CID of a channel of the link in `slot`.
+    fn cid(slot: usize, channel: usize) -> u16 {
+        0x40 + (slot * 2 + channel) as u16
+    }
+}
+
+/// What happened to the remotes since the last poll.
+pub(super) enum LinkEvent {
+    Connected(Port),
+    Disconnected(Port),
+    /// The report the remote sent, its ID then its data.
+    Report(Port, [u8; 22], usize),
+}
+
+pub(super) struct Bluetooth {
+    fd: Option<Fd>,
+    event: Transfer<EVENT_SIZE>,
+    acl: [Transfer<ACL_SIZE>; ACL_READS],
+    /// The ACL read completing first.
+    next_acl: usize,
+    /// The transfer of the commands and the ACL data sent.
+    out: Transfer<ACL_SIZE>,
+    keys: [(Address, [u8; 16]); MAX_KEYS],
+    key_count: usize,
+    links: [Option<Link>; 4],
+    /// The identifier of the last L2CAP request sent.
+    ident: u8,
+}
+
+impl Bluetooth {
+    pub(super) const fn new() -> Self {
+        Self {
+            fd: None,
+            event: Transfer::new(),
+            acl: [const { Transfer::new() }; ACL_READS],
+            next_acl: 0,
+            out: Transfer::new(),
+            keys: [([0; 6], [0; 16]); MAX_KEYS],
+            key_count: 0,
+            links: [None; 4],
+            ident: 0,
+        }
+    }
+
+    /// Opens the module, resets it, reads the link keys of the paired remotes, and lets
+    /// them connect.
+    ///
+    /// The transfers are started in place, which they stay in.
+    pub(super) fn start(&mut self) -> Result<(), WpadInitError> {
+        let fd = ios::open(c"/dev/usb/oh1/57e/305", Mode::None).map_err(WpadInitError::Ios)?;
+        self.fd = Some(fd);
+        self.command(RESET, &[])?;
+        self.wait_for(RESET)?;
+        self.command(READ_STORED_LINK_KEY, &[0, 0, 0, 0, 0, 0, 1])?;
+        self.wait_for(READ_STORED_LINK_KEY)?;
+        // Page scan only: the paired remotes connect, the others are not looking for it.
+        self.command(WRITE_SCAN_ENABLE, &[0x02])?;
+        self.wait_for(WRITE_SCAN_ENABLE)?;
+
+        unsafe {
+            self.event
+                .start(fd, INTERRUPT_MESSAGE, EVENT_ENDPOINT, EVENT_SIZE);
+            for acl in &mut self.acl {
+                acl.start(fd, BULK_MESSAGE, ACL_IN_ENDPOINT, ACL_SIZE);
+            }
+        }
+        Ok(())
+    }
+
+    fn fd(&self) -> Fd {
+        self.fd.expect("the bluetooth module is not open")
+    }
+
+    /// Sends the HCI command `opcode` with `params`.
+    fn command(&mut self, opcode: u16, params: &[u8]) -> Result<(), WpadInitError> {
+        let [low, high] = opcode.to_le_bytes();
+        let data = &mut self.out.data.0;
+        data[..3].copy_from_slice(&[low, high, params.len() as u8]);
+        data[3..3 + params.len()].copy_from_slice(params);
+        let fd = self.fd();
+        self.out
+            .control(fd, 3 + params.len())
+            .map(drop)
+            .map_err(WpadInitError::Ios)
+    }
+
+    /// Reads the events until the one completing the command `opcode`, keeping the link
+    /// keys returned in between.
+    fn wait_for(&mut self, opcode: u16) -> Result<(), WpadInitError> {
+        let fd = self.fd();
+        loop {
+            unsafe {
+                self.event
+                    .start(fd, INTERRUPT_MESSAGE, EVENT_ENDPOINT, EVENT_SIZE)
+            };
+            self.event.request.wait().map_err(WpadInitError::Ios)?;
+            let Some(&[code, _, ref params @ ..]) = self.event.read() else {
+                continue;
+            };
+            let (status, completed) = match (code, params) {
+                (COMMAND_COMPLETE, &[_, low, high, status, ..]) => (status, [low, high]),
+                (COMMAND_STATUS, &[status, _, low, high, ..]) if status != 0 => {
+                    (status, [low, high])
+                }
+                (RETURN_LINK_KEYS, &[count, ref keys @ ..]) => {
+                    let keys = keys.chunks_exact(22).take(count as usize);
+                    for key in keys {
+                        if self.key_count < MAX_KEYS {
+                            let (address, key) = key.split_at(6);
+                            self.keys[self.key_count] =
+                                (address.try_into().unwrap(), key.try_into().unwrap());
+                            self.key_count += 1;
+                        }
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            if u16::from_le_bytes(completed) == opcode {
+                return match status {
+                    0 => Ok(()),
+                    status => Err(WpadInitError::Bluetooth(status)),
+                };
+            }
+        }
+    }
+
+    /// Handles the events and the ACL data that came in, returning the first that changed
+    /// a remote.
+    pub(super) fn poll(&mut self) -> Option<LinkEvent> {
+        let fd = self.fd?;
+        loop {
+            if self.event.request.is_done() {
+                let mut event = [0; EVENT_SIZE];
+                let len = self.event.read().map_or(0, |data| {
+                    event[..data.len()].copy_from_slice(data);
+                    data.len()
+                });
+                unsafe {
+                    self.event
+                        .start(fd, INTERRUPT_MESSAGE, EVENT_ENDPOINT, EVENT_SIZE)
+                };
+                if let Some(event) = self.handle_event(&event[..len]) {
+                    return Some(event);
+                }
+            } else if self.acl[self.next_acl].request.is_done() {
+                let acl = &mut self.acl[self.next_acl];
+                let mut packet = [0; ACL_SIZE];
+                let len = acl.read().map_or(0, |data| {
+                    packet[..data.len()].copy_from_slice(data);
+                    data.len()
+                });
+                unsafe { acl.start(fd, BULK_MESSAGE, ACL_IN_ENDPOINT, ACL_SIZE) };
+                self.next_acl = (self.next_acl + 1) % ACL_READS;
+                if let Some(event) = self.handle_acl(&packet[..len]) {
+                    return Some(event);
+                }
+            } else {
+                return None;
+            }
+        }
+    }
+
+    fn slot_of(&self, matches: impl Fn(&Link) -> bool) -> Option<usize> {
+        self.links
+            .iter()
+            .position(|link| link.as_ref().is_some_and(&matches))
+    }
+
+    fn handle_event(&mut self, event: &[u8]) -> Option<LinkEvent> {
+        let &[code, _, ref params @ ..] = event else {
+            return None;
+        };
+        // The commands answering events are sent while the module is up, so their errors
+        // are those of the connection, reported by its own events.
+        match (code, params) {
+            (CONNECTION_REQUEST, &[ref address @ .., _, _, _, _]) if address.len() == 6 => {
+                let address: Address = address.try_into().unwrap();
+                match self.links.iter().position(Option::is_none) {
+                    Some(slot) => {
+                        self.links[slot] = Some(Link {
+                            address,
+                            handle: None,
+                            channels: [Channel::default(); 2],
+                            open: false,
+                        });
+                        // Taking the master role, to connect the remotes together.
+                        let _ = self.command(ACCEPT_CONNECTION, &with_byte(address, 0));
+                    }
+                    None => {
+                        let params = with_byte(address, LIMITED_RESOURCES);
+                        let _ = self.command(REJECT_CONNECTION, &params);
+                    }
+                }
+                None
+            }
+            (LINK_KEY_REQUEST, address) if address.len() >= 6 => {
+                let address = &address[..6];
+                let key = self.keys[..self.key_count]
+                    .iter()
+                    .find(|(paired, _)| paired == address);
+                let _ = match key {
+                    Some(&(address, key)) => {
+                        let mut params = [0; 22];
+                        params[..6].copy_from_slice(&address);
+                        params[6..].copy_from_slice(&key);
+                        self.command(LINK_KEY_REPLY, &params)
+                    }
+                    None => {
+                        let address: Address = address.try_into().unwrap();
+                        self.command(LINK_KEY_NEGATIVE_REPLY, &address)
+                    }
+                };
+                None
+            }
+            (CONNECTION_COMPLETE, &[status, low, high, ref address @ .., _, _])
+                if address.len() == 6 =>
+            {
+                let address: Address = address.try_into().unwrap();
+                let slot = self.slot_of(|link| link.address == address)?;
+                if status == 0 {
+                    let link = self.links[slot].as_mut()?;
+                    link.handle = Some(u16::from_le_bytes([low, high]) & 0x0fff);
+                } else {
+                    self.links[slot] = None;
+                }
+                None
+            }
+            (DISCONNECTION_COMPLETE, &[0, low, high, ..]) => {
+                let handle = u16::from_le_bytes([low, high]) & 0x0fff;
+                let slot = self.slot_of(|link| link.handle == Some(handle))?;
+                let link = self.links[slot].take()?;
+                link.open
+                    .then_some(LinkEvent::Disconnected(Port::ALL[slot]))
+            }
+            _ => None,
+        }
+    }
+
+    fn handle_acl(&mut self, packet: &[u8]) -> Option<LinkEvent> {
+        let &[low, high, _, _, len_low, len_high, cid_low, cid_high, ref payload @ ..] = packet
+        else {
+            return None;
+        };
+        // Continuations of a frame never come from the remotes, whose reports are small.
+        if high & 0x30 != 0x20 {
+            return None;
+        }
+        let handle = u16::from_le_bytes([low, high]) & 0x0fff;
+        let slot = self.slot_of(|link| link.handle == Some(handle))?;
+        let payload = payload.get(..u16::from_le_bytes([len_low, len_high]) as usize)?;
+        match u16::from_le_bytes([cid_low, cid_high]) {
+            SIGNALLING => {
+                let mut commands = payload;
+                let mut event = None;
+                while let &[code, ident, len_low, len_high, ref rest @ ..] = commands {
+                    let len = (u16::from_le_bytes([len_low, len_high]) as usize).min(rest.len());
+                    let (data, next) = rest.split_at(len);
+                    event = event.or(self.signal(slot, code, ident, data));
+                    commands = next;
+                }
+                event
+            }
+            cid if cid == Link::cid(slot, 1) => match payload {
+                &[INPUT_REPORT, ref report @ ..] if report.len() <= 22 => {
+                    let mut data = [0; 22];
+                    data[..report.len()].copy_from_slice(report);
+                    Some(LinkEvent::Report(Port::ALL[slot], data, report.len()))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Handles the signalling command `code` of the link in `slot`.
+    fn signal(&mut self, slot: usize, code: u8, ident: u8, data: &[u8]) -> Option<LinkEvent> {
+        let link = self.links[slot].as_mut()?;
+        let u16_at = |at: usize| {
+            data.get(at..at + 2)
+                .map_or(0, |bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        };
+        let channel_of = |cid: u16| (0..2).find(|&channel| Link::cid(slot, channel) == cid);
+        match code {
+            CONNECTION_REQUEST_SIGNAL => {
+                let (psm, remote) = (u16_at(0), u16_at(2));
+                let channel = match psm {
+                    HID_CONTROL => Some(0),
+                    HID_INTERRUPT => Some(1),
+                    _ => None,
+                };
+                let Some(channel) = channel else {
+                    // The PSM is not supported.
+                    self.send_signal(slot, CONNECTION_RESPONSE, ident, &[0, remote, 0x0002, 0]);
+                    return None;
+                };
+                link.channels[channel] = Channel {
+                    remote,
+                    ..Channel::default()
+                };
+                let local = Link::cid(slot, channel);
+                self.send_signal(slot, CONNECTION_RESPONSE, ident, &[local, remote, 0, 0]);
+                // Configured with the defaults, asking for no options.
+                self.ident = self.ident.wrapping_add(1).max(1);
+                let ident = self.ident;
+                self.send_signal(slot, CONFIGURE_REQUEST, ident, &[remote, 0]);
+                None
+            }
+            CONFIGURE_REQUEST => {
+                let channel = channel_of(u16_at(0))?;
+                let channel = &mut link.channels[channel];
+                channel.accepted = true;
+                let remote = channel.remote;
+                self.send_signal(slot, CONFIGURE_RESPONSE, ident, &[remote, 0, 0]);
+                self.update_open(slot)
+            }
+            CONFIGURE_RESPONSE => {
+                let channel = channel_of(u16_at(0))?;
+                link.channels[channel].configured = u16_at(4) == 0;
+                self.update_open(slot)
+            }
+            DISCONNECTION_REQUEST => {
+                let (local, remote) = (u16_at(0), u16_at(2));
+                let channel = channel_of(local)?;
+                link.channels[channel] = Channel::default();
+                self.send_signal(slot, DISCONNECTION_RESPONSE, ident, &[local, remote]);
+                self.update_open(slot)
+            }
+            INFORMATION_REQUEST => {
+                // None of the information is supported.
+                let kind = u16_at(0);
+                self.send_signal(slot, INFORMATION_RESPONSE, ident, &[kind, 0x0001]);
+                None
+            }
+            COMMAND_REJECT | CONNECTION_RESPONSE | DISCONNECTION_RESPONSE => None,
+            _ => {
+                // The command is not understood.
+                self.send_signal(slot, COMMAND_REJECT, ident, &[0]);
+                None
+            }
+        }
+    }
+
+    /// Marks the link in `slot` open once both its channels are, or closed.
+    fn update_open(&mut self, slot: usize) -> Option<LinkEvent> {
+        let link = self.links[slot].as_mut()?;
+        let open = link.channels.iter().all(|channel| channel.is_open());
+        if open == link.open {
+            return None;
+        }
+        link.open = open;
+        let port = Port::ALL[slot];
+        Some(if open {
+            LinkEvent::Connected(port)
+        } else {
+            LinkEvent::Disconnected(port)
+        })
+    }
+
+    /// Sends the signalling command `code` with the parameters `halves`, all of which are
+    /// 16-bit.
+    fn send_signal(&mut self, slot: usize, code: u8, ident: u8, halves: &[u16]) {
+        let mut command = [0; 16];
+        let len = halves.len() * 2;
+        command[..4].copy_from_slice(&[code, ident, len as u8, 0]);
+        for (bytes, half) in command[4..].chunks_exact_mut(2).zip(halves) {
+            bytes.copy_from_slice(&half.to_le_bytes());
+        }
+        self.send_acl(slot, SIGNALLING, &command[..4 + len]);
+    }
+
+    /// Sends `payload` on the channel `cid` of the remote in `slot`.
+    fn send_acl(&mut self, slot: usize, cid: u16, payload: &[u8]) {
+        let Some(handle) = self.links[slot].and_then(|link| link.handle) else {
+            return;
+        };
+        let len = payload.len() as u16;
+        let data = &mut self.out.data.0;
+        // The start of a frame, flushed automatically.
+        data[..2].copy_from_slice(&(handle | 0x2000).to_le_bytes());
+        data[2..4].copy_from_slice(&(len + 4).to_le_bytes());
+        data[4..6].copy_from_slice(&len.to_le_bytes());
+        data[6..8].copy_from_slice(&cid.to_le_bytes());
+        data[8..8 + payload.len()].copy_from_slice(payload);
+        let fd = self.fd();
+        unsafe {
+            self.out
+                .start(fd, BULK_MESSAGE, ACL_OUT_ENDPOINT, 8 + payload.len())
+        };
+        let _ = self.out.request.wait();
+    }
+
+    /// Sends the output `report`, its ID then its data, to the remote in `port` if it is
+    /// connected.
+    pub(super) fn send_report(&mut self, port: Port, report: &[u8]) {
+        let slot = port as usize;
+        let Some(link) = self.links[slot].filter(|link| link.open) else {
+            return;
+        };
+        let mut payload = [0; 23];
+        payload[0] = OUTPUT_REPORT;
+        payload[1..1 + report.len()].copy_from_slice(report);
+        self.send_acl(slot, link.channels[1].remote, &payload[..1 + report.len()]);
+    }
+
+    /// Disconnects the remote in `port`, which turns it off.
+    pub(super) fn disconnect(&mut self, port: Port) {
+        if let Some(handle) = self.links[port as usize].and_then(|link| link.handle) {
+            let [low, high] = handle.to_le_bytes();
+            let _ = self.command(DISCONNECT, &[low, high, USER_TERMINATED]);
+        }
+    }
+}
+
+/// The parameters of a command taking `address` then `byte`.
+fn with_byte(address: Address, byte: u8) -> [u8; 7] {
+    let mut params = [byte; 7];
+    params[..6].copy_from_slice(&address);
+    params
+}
@@ -0,0 +1,11 @@
+//! The entry point, which the loader calls with the program's arguments.
+
+#[cfg(target_arch = "powerpc")]
+core::arch::global_asm!(
+    r#"
+    .section .text.rbrew.start, "ax"
+    .global __rpx_start
+__rpx_start:
+    b main
+"#
+);
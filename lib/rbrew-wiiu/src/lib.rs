@@ -0,0 +1,12 @@
+/*!
+rbrew-wiiu is a library for writing homebrew wiiu programs in Rust.
+
+Programs are linked with `targets/wiiu.ld`, converted with `--output-type rpx` and provide
+their entry point as `#[no_mangle] extern "C" fn main(argc: i32, argv: *const *const u8)
+-> i32`. The loader sets up the stack and the small data registers and clears `.bss`
+itself, and the program exits when `main` returns.
+*/
+
+#![no_std]
+
+mod crt0;
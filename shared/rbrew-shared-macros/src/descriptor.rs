@@ -0,0 +1,204 @@
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields};
+
+/// The `#[descriptor(...)]` options of a structure.
+#[derive(Default)]
+struct Options {
+    size: Option<syn::LitInt>,
+    align: Option<syn::LitInt>,
+    /// Whether the hardware reads the structure as big-endian, the target's byte order
+    /// if not given.
+    big_endian: Option<bool>,
+}
+
+fn parse_options(input: &DeriveInput) -> syn::Result<Options> {
+    let mut options = Options::default();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("descriptor"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("size") {
+                options.size = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("align") {
+                options.align = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("endian") {
+                let endian: syn::LitStr = meta.value()?.parse()?;
+                options.big_endian = Some(match endian.value().as_str() {
+                    "big" => true,
+                    "little" => false,
+                    _ => {
+                        return Err(syn::Error::new(
+                            endian.span(),
+                            "the endian must be \"big\" or \"little\"",
+                        ))
+                    }
+                });
+            } else {
+                return Err(meta.error("expected `size`, `align` or `endian`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(options)
+}
+
+/// Whether the structure is `#[repr(C)]`, which its layout must be to be read by the
+/// hardware.
+fn is_repr_c(input: &DeriveInput) -> bool {
+    input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("repr"))
+        .any(|attr| {
+            let mut c = false;
+            let _ = attr.parse_nested_meta(|meta| {
+                c |= meta.path.is_ident("C");
+                // Skips the arguments of `align(..)` and `packed(..)`.
+                if meta.input.peek(syn::token::Paren) {
+                    let content;
+                    syn::parenthesized!(content in meta.input);
+                    content.parse::<proc_macro2::TokenStream>()?;
+                }
+                Ok(())
+            });
+            c
+        })
+}
+
+fn derive(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &input.ident;
+    let name = ident.to_string();
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            ident.span(),
+            "Descriptor can only be derived for structures",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new(
+            ident.span(),
+            "Descriptor can only be derived for structures with named fields",
+        ));
+    };
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "Descriptor cannot be derived for generic structures",
+        ));
+    }
+    if !is_repr_c(&input) {
+        return Err(syn::Error::new(
+            ident.span(),
+            "Descriptor needs a `#[repr(C)]` structure, whose layout the hardware can read",
+        ));
+    }
+    let options = parse_options(&input)?;
+
+    let size_assert = options.size.as_ref().map(|size| {
+        let msg = format!("`{name}` is not {} bytes", size.base10_digits());
+        quote!(::core::assert!(::core::mem::size_of::<#ident>() == #size, #msg);)
+    });
+    let align_assert = options.align.as_ref().map(|align| {
+        let msg = format!("`{name}` is not aligned to {} bytes", align.base10_digits());
+        quote!(::core::assert!(::core::mem::align_of::<#ident>() == #align, #msg);)
+    });
+
+    // Fields starting with an underscore are padding, without accessors.
+    let accessors = fields
+        .named
+        .iter()
+        .filter(|field| !field.ident.as_ref().unwrap().to_string().starts_with('_'))
+        .map(|field| {
+            let field_ident = field.ident.as_ref().unwrap();
+            let (vis, ty) = (&field.vis, &field.ty);
+            let docs = field
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("doc"));
+            let read_ident = format_ident!("{}_read", field_ident);
+            let write_ident = format_ident!("{}_write", field_ident);
+            quote! {
+                #(#docs)*
+                #[inline(always)]
+                #vis fn #read_ident(&self) -> #ty {
+                    unsafe { ::core::ptr::addr_of!(self.#field_ident).read_volatile() }
+                }
+
+                #[inline(always)]
+                #vis fn #write_ident(&mut self, value: #ty) {
+                    unsafe { ::core::ptr::addr_of_mut!(self.#field_ident).write_volatile(value) }
+                }
+            }
+        });
+
+    let field_idents: Vec<_> = fields.named.iter().map(|field| &field.ident).collect();
+    let big_endian = match options.big_endian {
+        Some(big_endian) => quote!(#big_endian),
+        None => quote!(big_endian),
+    };
+    let native = match options.big_endian {
+        Some(big_endian) => quote!(#big_endian),
+        None => quote!(::core::cfg!(target_endian = "big")),
+    };
+
+    Ok(quote! {
+        const _: () = {
+            #size_assert
+            #align_assert
+        };
+
+        impl #ident {
+            #(#accessors)*
+
+            /// The bytes of the structure as the hardware reads them, with zeroed padding.
+            pub fn to_bytes(&self) -> [u8; ::core::mem::size_of::<#ident>()] {
+                let mut out = [0; ::core::mem::size_of::<#ident>()];
+                ::rbrew_shared::io::IoValue::store(*self, &mut out, #native);
+                out
+            }
+
+            /// Reads the structure from the bytes the hardware wrote.
+            pub fn from_bytes(bytes: &[u8; ::core::mem::size_of::<#ident>()]) -> Self {
+                ::rbrew_shared::io::IoValue::load(bytes, #native)
+            }
+        }
+
+        impl ::rbrew_shared::io::IoValue for #ident {
+            #[allow(unused_variables)]
+            fn store(self, out: &mut [u8], big_endian: bool) {
+                #(
+                    ::rbrew_shared::io::IoValue::store(
+                        self.#field_idents,
+                        &mut out[::core::mem::offset_of!(Self, #field_idents)..],
+                        #big_endian,
+                    );
+                )*
+            }
+
+            #[allow(unused_variables)]
+            fn load(bytes: &[u8], big_endian: bool) -> Self {
+                Self {
+                    #(
+                        #field_idents: ::rbrew_shared::io::IoValue::load(
+                            &bytes[::core::mem::offset_of!(Self, #field_idents)..],
+                            #big_endian,
+                        ),
+                    )*
+                }
+            }
+        }
+    })
+}
+
+pub fn derive_descriptor2(ts: TokenStream) -> TokenStream {
+    let input: DeriveInput = match syn::parse(ts) {
+        Ok(input) => input,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    derive(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
@@ -0,0 +1,66 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{ItemFn, ReturnType};
+
+/// Checks that `item` can be called by the runtime, as a `fn()` or a `fn() -> !`.
+fn check_signature(item: &ItemFn) -> syn::Result<()> {
+    let sig = &item.sig;
+    let error = |msg: &str| Err(syn::Error::new(sig.ident.span(), msg));
+    if !sig.inputs.is_empty() || sig.variadic.is_some() {
+        return error("the entry point takes no arguments");
+    }
+    let returns = match &sig.output {
+        ReturnType::Default => true,
+        ReturnType::Type(_, ty) => match &**ty {
+            syn::Type::Tuple(tuple) => tuple.elems.is_empty(),
+            syn::Type::Never(_) => true,
+            _ => false,
+        },
+    };
+    if !returns {
+        return error("the entry point returns nothing, or never returns");
+    }
+    if !sig.generics.params.is_empty() {
+        return error("the entry point cannot be generic");
+    }
+    if sig.asyncness.is_some() || sig.constness.is_some() {
+        return error("the entry point cannot be `async` or `const`");
+    }
+    if sig.unsafety.is_some() {
+        return error("the entry point cannot be `unsafe`");
+    }
+    if sig.abi.is_some() {
+        return error(
+            "the entry point uses the Rust ABI, the runtime calls it through a trampoline",
+        );
+    }
+    Ok(())
+}
+
+fn expand(attr: TokenStream, item: TokenStream) -> syn::Result<proc_macro2::TokenStream> {
+    if !attr.is_empty() {
+        return Err(syn::Error::new(
+            proc_macro2::Span::call_site(),
+            "`#[entry]` takes no arguments",
+        ));
+    }
+    let item: ItemFn = syn::parse(item)?;
+    check_signature(&item)?;
+    let ident = &item.sig.ident;
+    Ok(quote! {
+        #item
+
+        const _: () = {
+            #[export_name = "__rbrew_entry"]
+            extern "C" fn entry() {
+                #ident();
+            }
+        };
+    })
+}
+
+pub fn entry2(attr: TokenStream, item: TokenStream) -> TokenStream {
+    expand(attr, item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
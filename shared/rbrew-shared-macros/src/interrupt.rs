@@ -0,0 +1,60 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{ItemFn, ReturnType};
+
+/// Checks that `item` can be called by the dispatcher, as a `fn()`.
+fn check_signature(item: &ItemFn) -> syn::Result<()> {
+    let sig = &item.sig;
+    let error = |msg: &str| Err(syn::Error::new(sig.ident.span(), msg));
+    if !sig.inputs.is_empty() || sig.variadic.is_some() {
+        return error("interrupt handlers take no arguments");
+    }
+    let returns_unit = match &sig.output {
+        ReturnType::Default => true,
+        ReturnType::Type(_, ty) => {
+            matches!(&**ty, syn::Type::Tuple(tuple) if tuple.elems.is_empty())
+        }
+    };
+    if !returns_unit {
+        return error("interrupt handlers return nothing");
+    }
+    if !sig.generics.params.is_empty() {
+        return error("interrupt handlers cannot be generic");
+    }
+    if sig.asyncness.is_some() || sig.constness.is_some() {
+        return error("interrupt handlers cannot be `async` or `const`");
+    }
+    if sig.unsafety.is_some() {
+        return error("interrupt handlers cannot be `unsafe`, the dispatcher calls them as `fn()`");
+    }
+    if sig.abi.is_some() {
+        return error("interrupt handlers use the Rust ABI, the dispatcher saves the registers");
+    }
+    Ok(())
+}
+
+fn expand(attr: TokenStream, item: TokenStream) -> syn::Result<proc_macro2::TokenStream> {
+    let source: syn::Expr = syn::parse(attr)?;
+    let item: ItemFn = syn::parse(item)?;
+    check_signature(&item)?;
+    let ident = &item.sig.ident;
+    Ok(quote! {
+        #item
+
+        const _: () = {
+            #[link_section = ".rbrew.interrupts"]
+            #[used]
+            static HANDLER: ::rbrew_shared::irq::Handler =
+                ::rbrew_shared::irq::Handler {
+                    source: #source as u32,
+                    handler: #ident,
+                };
+        };
+    })
+}
+
+pub fn interrupt2(attr: TokenStream, item: TokenStream) -> TokenStream {
+    expand(attr, item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
@@ -0,0 +1,49 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields};
+
+pub fn derive_io_enum2(ts: TokenStream) -> TokenStream {
+    let input: DeriveInput = match syn::parse(ts) {
+        Ok(input) => input,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let ident = &input.ident;
+    let Data::Enum(data) = &input.data else {
+        return syn::Error::new(ident.span(), "IoEnum can only be derived for enums")
+            .to_compile_error()
+            .into();
+    };
+    if let Some(variant) = data
+        .variants
+        .iter()
+        .find(|variant| !matches!(variant.fields, Fields::Unit))
+    {
+        return syn::Error::new(
+            variant.ident.span(),
+            "IoEnum can only be derived for enums without fields",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let arms = data.variants.iter().map(|variant| {
+        let variant = &variant.ident;
+        quote!(bits if bits == Self::#variant as u64 => Ok(Self::#variant),)
+    });
+
+    quote! {
+        impl ::rbrew_shared::io::IoEnum for #ident {
+            fn from_bits(bits: u64) -> ::core::result::Result<Self, ::rbrew_shared::io::InvalidValue> {
+                match bits {
+                    #(#arms)*
+                    bits => Err(::rbrew_shared::io::InvalidValue(bits)),
+                }
+            }
+
+            fn into_bits(self) -> u64 {
+                self as u64
+            }
+        }
+    }
+    .into()
+}
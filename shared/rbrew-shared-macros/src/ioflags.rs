@@ -0,0 +1,284 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse::Parse, Attribute, Ident, Visibility};
+
+/// A flag of the type, `const NAME = bits;`.
+struct Flag {
+    attrs: Vec<Attribute>,
+    ident: Ident,
+    value: syn::Expr,
+}
+
+struct IoFlagsItem {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    ident: Ident,
+    bits_ty: Ident,
+    flags: Vec<Flag>,
+}
+
+impl Parse for IoFlagsItem {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let attrs = Attribute::parse_outer(input)?;
+        let vis = input.parse()?;
+        input.parse::<syn::Token![struct]>()?;
+        let ident = input.parse()?;
+        input.parse::<syn::Token![:]>()?;
+        let bits_ty: Ident = input.parse()?;
+        if !["u8", "u16", "u32", "u64"].contains(&bits_ty.to_string().as_str()) {
+            return Err(syn::Error::new(
+                bits_ty.span(),
+                "flags are held in a `u8`, `u16`, `u32` or `u64`",
+            ));
+        }
+        let content;
+        syn::braced!(content in input);
+        let mut flags = vec![];
+        while !content.is_empty() {
+            let attrs = Attribute::parse_outer(&content)?;
+            content.parse::<syn::Token![const]>()?;
+            let ident = content.parse()?;
+            content.parse::<syn::Token![=]>()?;
+            let value = content.parse()?;
+            content.parse::<syn::Token![;]>()?;
+            flags.push(Flag {
+                attrs,
+                ident,
+                value,
+            });
+        }
+        Ok(Self {
+            attrs,
+            vis,
+            ident,
+            bits_ty,
+            flags,
+        })
+    }
+}
+
+pub fn ioflags2(ts: TokenStream) -> TokenStream {
+    let IoFlagsItem {
+        attrs,
+        vis,
+        ident,
+        bits_ty,
+        flags,
+    } = match syn::parse(ts) {
+        Ok(item) => item,
+        Err(err) => return err.to_compile_error().into(),
+    };
+
+    let consts = flags.iter().map(
+        |Flag {
+             attrs,
+             ident,
+             value,
+         }| {
+            quote! {
+                #(#attrs)*
+                pub const #ident: Self = Self(#value);
+            }
+        },
+    );
+    let flag_idents: Vec<_> = flags.iter().map(|flag| &flag.ident).collect();
+    let flag_names = flag_idents.iter().map(|ident| ident.to_string());
+    let name = ident.to_string();
+
+    quote! {
+        #(#attrs)*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+        #[repr(transparent)]
+        #vis struct #ident(#bits_ty);
+
+        impl #ident {
+            #(#consts)*
+
+            /// No flags set.
+            #[inline(always)]
+            pub const fn empty() -> Self {
+                Self(0)
+            }
+
+            /// Every named flag set.
+            #[inline(always)]
+            pub const fn all() -> Self {
+                Self(0 #(| Self::#flag_idents.0)*)
+            }
+
+            /// The flags set in `bits`, keeping the bits no flag names.
+            #[inline(always)]
+            pub const fn from_bits_retain(bits: #bits_ty) -> Self {
+                Self(bits)
+            }
+
+            /// The flags set in `bits`, dropping the bits no flag names.
+            #[inline(always)]
+            pub const fn from_bits_truncate(bits: #bits_ty) -> Self {
+                Self(bits & Self::all().0)
+            }
+
+            #[inline(always)]
+            pub const fn bits(self) -> #bits_ty {
+                self.0
+            }
+
+            #[inline(always)]
+            pub const fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+
+            /// Whether all flags of `other` are set.
+            #[inline(always)]
+            pub const fn contains(self, other: Self) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            /// Whether any flag of `other` is set.
+            #[inline(always)]
+            pub const fn intersects(self, other: Self) -> bool {
+                self.0 & other.0 != 0
+            }
+
+            #[inline(always)]
+            pub fn insert(&mut self, other: Self) {
+                self.0 |= other.0;
+            }
+
+            #[inline(always)]
+            pub fn remove(&mut self, other: Self) {
+                self.0 &= !other.0;
+            }
+
+            #[inline(always)]
+            pub fn toggle(&mut self, other: Self) {
+                self.0 ^= other.0;
+            }
+
+            /// Inserts the flags of `other` if `value` is set, removes them otherwise.
+            #[inline(always)]
+            pub fn set(&mut self, other: Self, value: bool) {
+                if value {
+                    self.insert(other);
+                } else {
+                    self.remove(other);
+                }
+            }
+        }
+
+        impl ::core::ops::BitOr for #ident {
+            type Output = Self;
+
+            #[inline(always)]
+            fn bitor(self, other: Self) -> Self {
+                Self(self.0 | other.0)
+            }
+        }
+
+        impl ::core::ops::BitAnd for #ident {
+            type Output = Self;
+
+            #[inline(always)]
+            fn bitand(self, other: Self) -> Self {
+                Self(self.0 & other.0)
+            }
+        }
+
+        impl ::core::ops::BitXor for #ident {
+            type Output = Self;
+
+            #[inline(always)]
+            fn bitxor(self, other: Self) -> Self {
+                Self(self.0 ^ other.0)
+            }
+        }
+
+        impl ::core::ops::Sub for #ident {
+            type Output = Self;
+
+            #[inline(always)]
+            fn sub(self, other: Self) -> Self {
+                Self(self.0 & !other.0)
+            }
+        }
+
+        /// The named flags not set.
+        impl ::core::ops::Not for #ident {
+            type Output = Self;
+
+            #[inline(always)]
+            fn not(self) -> Self {
+                Self(!self.0 & Self::all().0)
+            }
+        }
+
+        impl ::core::ops::BitOrAssign for #ident {
+            #[inline(always)]
+            fn bitor_assign(&mut self, other: Self) {
+                self.0 |= other.0;
+            }
+        }
+
+        impl ::core::ops::BitAndAssign for #ident {
+            #[inline(always)]
+            fn bitand_assign(&mut self, other: Self) {
+                self.0 &= other.0;
+            }
+        }
+
+        impl ::core::ops::BitXorAssign for #ident {
+            #[inline(always)]
+            fn bitxor_assign(&mut self, other: Self) {
+                self.0 ^= other.0;
+            }
+        }
+
+        impl ::core::ops::SubAssign for #ident {
+            #[inline(always)]
+            fn sub_assign(&mut self, other: Self) {
+                self.0 &= !other.0;
+            }
+        }
+
+        /// Prints the names of the flags set, and the bits no flag names in hex.
+        impl ::core::fmt::Debug for #ident {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                f.write_str(#name)?;
+                f.write_str("(")?;
+                let mut first = true;
+                let mut rest = self.0;
+                #(
+                    // Flags whose bits were printed with others are skipped.
+                    if rest & Self::#flag_idents.0 != 0 && self.contains(Self::#flag_idents) {
+                        if !first {
+                            f.write_str(" | ")?;
+                        }
+                        f.write_str(#flag_names)?;
+                        first = false;
+                        rest &= !Self::#flag_idents.0;
+                    }
+                )*
+                if rest != 0 || first {
+                    if !first {
+                        f.write_str(" | ")?;
+                    }
+                    ::core::write!(f, "{:#x}", rest)?;
+                }
+                f.write_str(")")
+            }
+        }
+
+        impl ::rbrew_shared::io::IoFlags for #ident {
+            #[inline(always)]
+            fn from_bits(bits: u64) -> Self {
+                Self(bits as #bits_ty)
+            }
+
+            #[inline(always)]
+            fn into_bits(self) -> u64 {
+                self.0 as u64
+            }
+        }
+    }
+    .into()
+}
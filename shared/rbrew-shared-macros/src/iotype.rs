@@ -1,201 +1,2260 @@
 use proc_macro::TokenStream;
 use proc_macro2::Span;
 use quote::{format_ident, quote};
-use syn::{parse::Parse, punctuated::Punctuated, Attribute, Ident, Visibility};
+use std::ops::Range;
+use syn::{parse::Parse, Attribute, Ident, Visibility};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum Primitive {
     U8,
     U16,
     U32,
     U64,
+    U128,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    F32,
+    F64,
 }
 
 impl Primitive {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "u8" => Primitive::U8,
+            "u16" => Primitive::U16,
+            "u32" => Primitive::U32,
+            "u64" => Primitive::U64,
+            "u128" => Primitive::U128,
+            "i8" => Primitive::I8,
+            "i16" => Primitive::I16,
+            "i32" => Primitive::I32,
+            "i64" => Primitive::I64,
+            "i128" => Primitive::I128,
+            "f32" => Primitive::F32,
+            "f64" => Primitive::F64,
+            _ => return None,
+        })
+    }
+
     fn as_ty(self) -> proc_macro2::TokenStream {
         match self {
             Primitive::U8 => quote!(u8),
             Primitive::U16 => quote!(u16),
             Primitive::U32 => quote!(u32),
             Primitive::U64 => quote!(u64),
+            Primitive::U128 => quote!(u128),
+            Primitive::I8 => quote!(i8),
+            Primitive::I16 => quote!(i16),
+            Primitive::I32 => quote!(i32),
+            Primitive::I64 => quote!(i64),
+            Primitive::I128 => quote!(i128),
+            Primitive::F32 => quote!(f32),
+            Primitive::F64 => quote!(f64),
         }
     }
 
+    /// Converts `raw` of the type to the unsigned integer with the same bits.
+    fn to_bits(self, raw: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        match self {
+            Primitive::I8 => quote!(#raw as u8),
+            Primitive::I16 => quote!(#raw as u16),
+            Primitive::I32 => quote!(#raw as u32),
+            Primitive::I64 => quote!(#raw as u64),
+            Primitive::I128 => quote!(#raw as u128),
+            Primitive::F32 | Primitive::F64 => quote!(#raw.to_bits()),
+            _ => raw,
+        }
+    }
+
+    /// The size of the type, which registers of it must be aligned to.
     fn align(self) -> u64 {
         match self {
-            Primitive::U8 => 1,
-            Primitive::U16 => 2,
-            Primitive::U32 => 4,
-            Primitive::U64 => 8,
+            Primitive::U8 | Primitive::I8 => 1,
+            Primitive::U16 | Primitive::I16 => 2,
+            Primitive::U32 | Primitive::I32 | Primitive::F32 => 4,
+            Primitive::U64 | Primitive::I64 | Primitive::F64 => 8,
+            Primitive::U128 | Primitive::I128 => 16,
         }
     }
+
+    fn bits(self) -> u32 {
+        self.align() as u32 * 8
+    }
+
+    /// Whether the type can be accessed as two 32-bit halves with `#[split]`.
+    fn is_split(self) -> bool {
+        matches!(self, Primitive::U64 | Primitive::I64)
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, Primitive::F32 | Primitive::F64)
+    }
+
+    /// Whether the type can hold bit ranges and reset values, which are limited to
+    /// unsigned integers of up to 64 bits.
+    fn has_bits(self) -> bool {
+        matches!(
+            self,
+            Primitive::U8 | Primitive::U16 | Primitive::U32 | Primitive::U64
+        )
+    }
+}
+
+/// Removes the attribute `#[name]` from `attrs`, returning it if it was there.
+fn take_flag(attrs: &mut Vec<Attribute>, name: &str) -> Option<Attribute> {
+    let index = attrs
+        .iter()
+        .position(|attr| matches!(&attr.meta, syn::Meta::Path(path) if path.is_ident(name)))?;
+    Some(attrs.remove(index))
+}
+
+/// The byte order of registers not in the target's, `#[le]` or `#[be]`.
+#[derive(Clone, Copy)]
+enum Endian {
+    Little,
+    Big,
+}
+
+/// Removes `#[le]` or `#[be]` from `attrs`, returning the byte order and the attribute.
+fn take_endian(attrs: &mut Vec<Attribute>) -> syn::Result<Option<(Endian, Attribute)>> {
+    match (take_flag(attrs, "le"), take_flag(attrs, "be")) {
+        (Some(_), Some(be)) => Err(syn::Error::new_spanned(
+            be,
+            "registers are either `#[le]` or `#[be]`",
+        )),
+        (Some(le), None) => Ok(Some((Endian::Little, le))),
+        (None, Some(be)) => Ok(Some((Endian::Big, be))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// An address or offset, a literal or a constant expression such as `IO_BASE + 0x2000`.
+enum Number {
+    /// Evaluated by the macro, being arithmetic on literals.
+    Known(u64),
+    /// Naming constants, left to const evaluation and checked by assertions rather than
+    /// by the macro.
+    Deferred(Box<syn::Expr>),
+}
+
+impl Number {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        // Without eager braces, the bit ranges following an offset are not taken for a
+        // struct expression.
+        let expr = syn::Expr::parse_without_eager_brace(input)?;
+        Ok(match eval(&expr) {
+            Some(value) => Number::Known(value),
+            None => Number::Deferred(Box::new(expr)),
+        })
+    }
+
+    fn known(&self) -> Option<u64> {
+        match self {
+            Number::Known(value) => Some(*value),
+            Number::Deferred(_) => None,
+        }
+    }
+
+    fn to_tokens(&self) -> proc_macro2::TokenStream {
+        match self {
+            Number::Known(value) => {
+                let lit = syn::LitInt::new(&format!("{value:#x}"), Span::mixed_site());
+                quote!(#lit)
+            }
+            Number::Deferred(expr) => quote!(#expr),
+        }
+    }
+}
+
+/// Evaluates arithmetic on integer literals, failing on anything else. Overflows fail
+/// too, and are reported by const evaluation.
+fn eval(expr: &syn::Expr) -> Option<u64> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Int(lit),
+            ..
+        }) => lit.base10_parse().ok(),
+        syn::Expr::Paren(expr) => eval(&expr.expr),
+        syn::Expr::Group(expr) => eval(&expr.expr),
+        syn::Expr::Binary(expr) => {
+            let (left, right) = (eval(&expr.left)?, eval(&expr.right)?);
+            match expr.op {
+                syn::BinOp::Add(_) => left.checked_add(right),
+                syn::BinOp::Sub(_) => left.checked_sub(right),
+                syn::BinOp::Mul(_) => left.checked_mul(right),
+                syn::BinOp::Div(_) => left.checked_div(right),
+                syn::BinOp::Rem(_) => left.checked_rem(right),
+                syn::BinOp::Shl(_) => left.checked_shl(right.try_into().ok()?),
+                syn::BinOp::Shr(_) => left.checked_shr(right.try_into().ok()?),
+                syn::BinOp::BitAnd(_) => Some(left & right),
+                syn::BinOp::BitOr(_) => Some(left | right),
+                syn::BinOp::BitXor(_) => Some(left ^ right),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Parses the `= offset` of a field or sub-block if it comes next.
+fn parse_offset(input: syn::parse::ParseStream) -> syn::Result<Option<Number>> {
+    if input.parse::<Option<syn::Token![=]>>()?.is_some() {
+        Ok(Some(Number::parse(input)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The error of an item without an offset following a register whose offset is deferred.
+fn no_offset_error(ident: &Ident) -> syn::Error {
+    syn::Error::new(
+        ident.span(),
+        format!(
+            "'{ident}' follows a register whose offset names constants, and needs an offset \
+             of its own"
+        ),
+    )
+}
+
+/// Parses the contextual keyword `name` if it comes next.
+fn parse_keyword(input: syn::parse::ParseStream, name: &str) -> syn::Result<bool> {
+    if input.peek(Ident) && input.fork().parse::<Ident>()? == name {
+        input.parse::<Ident>()?;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Converts a `snake_case` or `UPPERCASE` name to `PascalCase`, leaving `PascalCase`
+/// names as they are.
+fn pascal_case(name: &str) -> String {
+    name.split('_')
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let upper = !word.chars().any(|c| c.is_ascii_lowercase());
+            let mut chars = word.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            core::iter::once(first)
+                .chain(chars.map(|c| if upper { c.to_ascii_lowercase() } else { c }))
+                .collect::<String>()
+        })
+        .collect()
+}
+
+/// The type a field or bit range is read and written as instead of its bits, `as Enum`
+/// or `flags Flags`.
+enum ValueType {
+    /// An `IoEnum`, whose reads fail for bits matching no variant.
+    Enum(syn::Path),
+    /// An `IoFlags`, declared once with `ioflags!` for every field holding the flags.
+    Flags(syn::Path),
+}
+
+impl ValueType {
+    fn path(&self) -> &syn::Path {
+        match self {
+            ValueType::Enum(path) | ValueType::Flags(path) => path,
+        }
+    }
+}
+
+/// Parses the `as Enum` or `flags Flags` following a field or bit range, naming the type
+/// its values are read as.
+fn parse_value_type(input: syn::parse::ParseStream) -> syn::Result<Option<ValueType>> {
+    if input.parse::<syn::Token![as]>().is_ok() {
+        Ok(Some(ValueType::Enum(input.parse()?)))
+    } else if parse_keyword(input, "flags")? {
+        Ok(Some(ValueType::Flags(input.parse()?)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// How a field's values are read and written.
+struct ValueConversion {
+    read_ty: proc_macro2::TokenStream,
+    write_ty: proc_macro2::TokenStream,
+    /// Converts `raw`, the field's bits shifted down to bit 0, to the read type.
+    from_raw: proc_macro2::TokenStream,
+    /// Converts `value` of the write type to the field's bits.
+    to_raw: proc_macro2::TokenStream,
+}
+
+fn value_conversion(
+    ty: &proc_macro2::TokenStream,
+    value_ty: Option<&ValueType>,
+    flag: bool,
+) -> ValueConversion {
+    match value_ty {
+        Some(ValueType::Enum(enum_ty)) => ValueConversion {
+            read_ty: quote!(::core::result::Result<#enum_ty, ::rbrew_shared::io::InvalidValue>),
+            write_ty: quote!(#enum_ty),
+            from_raw: quote!(<#enum_ty as ::rbrew_shared::io::IoEnum>::from_bits(raw as u64)),
+            to_raw: quote!(::rbrew_shared::io::IoEnum::into_bits(value) as #ty),
+        },
+        Some(ValueType::Flags(flags_ty)) => ValueConversion {
+            read_ty: quote!(#flags_ty),
+            write_ty: quote!(#flags_ty),
+            from_raw: quote!(<#flags_ty as ::rbrew_shared::io::IoFlags>::from_bits(raw as u64)),
+            to_raw: quote!(::rbrew_shared::io::IoFlags::into_bits(value) as #ty),
+        },
+        // Single bits are flags, wider ranges values shifted down to bit 0.
+        None if flag => ValueConversion {
+            read_ty: quote!(bool),
+            write_ty: quote!(bool),
+            from_raw: quote!(raw != 0),
+            to_raw: quote!(value as #ty),
+        },
+        None => ValueConversion {
+            read_ty: quote!(#ty),
+            write_ty: quote!(#ty),
+            from_raw: quote!(raw),
+            to_raw: quote!(value),
+        },
+    }
+}
+
+/// A named range of bits within a register, `low..=high`.
+struct BitField {
+    attrs: Vec<Attribute>,
+    ident: Ident,
+    low: u32,
+    high: u32,
+    value_ty: Option<ValueType>,
+}
+
+impl BitField {
+    fn parse(input: syn::parse::ParseStream, primitive: Primitive) -> syn::Result<Self> {
+        let attrs = Attribute::parse_outer(input)?;
+        let ident: Ident = input.parse()?;
+        input.parse::<syn::Token![:]>()?;
+        let low: syn::LitInt = input.parse()?;
+        let low: u32 = low.base10_parse()?;
+        let high = if input.parse::<syn::Token![..=]>().is_ok() {
+            input.parse::<syn::LitInt>()?.base10_parse()?
+        } else if input.parse::<syn::Token![..]>().is_ok() {
+            let end: syn::LitInt = input.parse()?;
+            match end.base10_parse::<u32>()?.checked_sub(1) {
+                Some(high) => high,
+                None => return Err(syn::Error::new(end.span(), "empty bit range")),
+            }
+        } else {
+            low
+        };
+        if high < low {
+            return Err(syn::Error::new(ident.span(), "empty bit range"));
+        }
+        if high >= primitive.bits() {
+            return Err(syn::Error::new(
+                ident.span(),
+                format!(
+                    "bit {high} is outside of a {}-bit register",
+                    primitive.bits()
+                ),
+            ));
+        }
+        let value_ty = parse_value_type(input)?;
+        Ok(Self {
+            attrs,
+            ident,
+            low,
+            high,
+            value_ty,
+        })
+    }
+
+    fn mask(&self) -> u64 {
+        (u64::MAX >> (63 - (self.high - self.low))) << self.low
+    }
+}
+
+/// How a register may be accessed, `const`, `mut` or `wo`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    ReadOnly,
+    ReadWrite,
+    WriteOnly,
+}
+
+impl Access {
+    fn readable(self) -> bool {
+        self != Access::WriteOnly
+    }
+
+    fn writable(self) -> bool {
+        self != Access::ReadOnly
+    }
+}
+
+/// The registers of an array field, `[ty; count]` at `offset + index * stride`.
+struct IoArray {
+    count: u64,
+    stride: u64,
 }
 
 struct IoField {
+    /// Doc comments and attributes, applied to every function generated for the field.
+    attrs: Vec<Attribute>,
+    /// The visibility of the functions generated for the field, `pub` unless given.
+    vis: Visibility,
     ident: Ident,
-    writable: bool,
+    access: Access,
     primitive: Primitive,
-    offset: u64,
+    value_ty: Option<ValueType>,
+    /// Without `= offset`, the field follows the previous one at its alignment.
+    /// Offsets naming constants are only allowed for fields of the top-level block.
+    offset: Number,
+    array: Option<IoArray>,
+    /// The value after reset, `reset 0x...` following the offset.
+    reset: Option<u64>,
+    bits: Vec<BitField>,
+    /// Whether the read-modify-write functions run in a critical section, `#[critical]`
+    /// on the field or the block declaring it.
+    critical: bool,
+    /// Whether the 64-bit register is accessed as two 32-bit halves, `#[split]` on the
+    /// field or the block declaring it.
+    split: bool,
+    /// The byte order of the register, `#[le]` or `#[be]` on the field or the block
+    /// declaring it, the target's otherwise. Accessors swap the bytes, bit ranges being
+    /// numbered in the swapped value.
+    endian: Option<Endian>,
 }
 
-impl Parse for IoField {
-    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let ident = input.parse()?;
-        input.parse::<syn::Token![:]>()?;
-        let writable = if input.parse::<syn::Token![mut]>().is_ok() {
-            true
+impl IoField {
+    /// Parses the rest of a field after its name and colon, `next` being the end of the
+    /// previous one if it is known.
+    fn parse(
+        input: syn::parse::ParseStream,
+        mut attrs: Vec<Attribute>,
+        vis: Visibility,
+        ident: Ident,
+        next: Option<u64>,
+    ) -> syn::Result<Self> {
+        let critical = take_flag(&mut attrs, "critical").is_some();
+        let split = take_flag(&mut attrs, "split");
+        let endian = take_endian(&mut attrs)?;
+        let access = if input.parse::<syn::Token![mut]>().is_ok() {
+            Access::ReadWrite
         } else if input.parse::<syn::Token![const]>().is_ok() {
-            false
+            Access::ReadOnly
+        } else if parse_keyword(input, "wo")? {
+            Access::WriteOnly
         } else {
-            return Err(input.error("expected either 'mut' or 'const' before a field type."));
+            return Err(input.error("expected either 'mut', 'const' or 'wo' before a field type."));
         };
-        let primitive = match input.parse::<Ident>()?.to_string().as_str() {
-            "u8" => Primitive::U8,
-            "u16" => Primitive::U16,
-            "u32" => Primitive::U32,
-            "u64" => Primitive::U64,
-            ty => {
-                return Err(input.error(format!(
-                    "expected either 'u8', 'u16', 'u32' or 'u64'. Got '{ty}'"
-                )))
+        let parse_primitive = |input: syn::parse::ParseStream| {
+            let ty: Ident = input.parse()?;
+            Primitive::from_name(&ty.to_string()).ok_or_else(|| {
+                syn::Error::new(
+                    ty.span(),
+                    format!("expected an integer or float type such as 'u32'. Got '{ty}'"),
+                )
+            })
+        };
+        let (primitive, count) = if input.peek(syn::token::Bracket) {
+            let content;
+            syn::bracketed!(content in input);
+            let primitive = parse_primitive(&content)?;
+            content.parse::<syn::Token![;]>()?;
+            let count: syn::LitInt = content.parse()?;
+            let count: u64 = count.base10_parse()?;
+            if count == 0 {
+                return Err(content.error("register arrays cannot be empty"));
             }
+            (primitive, Some(count))
+        } else {
+            (parse_primitive(input)?, None)
         };
-        input.parse::<syn::Token![=]>()?;
-        let offset: syn::LitInt = input.parse()?;
+        let value_ty = parse_value_type(input)?;
+        if let (Some(value_ty), true) = (&value_ty, primitive.is_float()) {
+            return Err(syn::Error::new_spanned(
+                value_ty.path(),
+                "float registers cannot be read as enums or flags",
+            ));
+        }
+        if let Some((_, attr)) = &endian {
+            if primitive.align() == 1 {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "single byte registers have no byte order",
+                ));
+            }
+        }
+        if let Some(split) = &split {
+            if !primitive.is_split() {
+                return Err(syn::Error::new_spanned(
+                    split,
+                    "only `u64` and `i64` registers can be split",
+                ));
+            }
+        }
+        let offset = match (parse_offset(input)?, next) {
+            (Some(offset), _) => offset,
+            (None, Some(next)) => Number::Known(next.next_multiple_of(primitive.align())),
+            (None, None) => return Err(no_offset_error(&ident)),
+        };
+
+        // `stride` is optional, and only allowed for arrays, whose registers are
+        // otherwise packed.
+        let array = match count {
+            Some(count) => {
+                let stride = if parse_keyword(input, "stride")? {
+                    let stride: syn::LitInt = input.parse()?;
+                    let value: u64 = stride.base10_parse()?;
+                    if value < primitive.align() || !value.is_multiple_of(primitive.align()) {
+                        return Err(syn::Error::new(
+                            stride.span(),
+                            format!(
+                                "the stride must be a multiple of the register size, {}",
+                                primitive.align()
+                            ),
+                        ));
+                    }
+                    value
+                } else {
+                    primitive.align()
+                };
+                Some(IoArray { count, stride })
+            }
+            None => None,
+        };
+
+        let reset = if parse_keyword(input, "reset")? {
+            let reset: syn::LitInt = input.parse()?;
+            if !primitive.has_bits() {
+                return Err(syn::Error::new(
+                    reset.span(),
+                    "reset values need an unsigned register of up to 64 bits",
+                ));
+            }
+            let value: u64 = reset.base10_parse()?;
+            if primitive.bits() < 64 && value >> primitive.bits() != 0 {
+                return Err(syn::Error::new(
+                    reset.span(),
+                    format!("the reset value does not fit {} bits", primitive.bits()),
+                ));
+            }
+            Some(value)
+        } else {
+            None
+        };
+
+        let mut bits: Vec<BitField> = vec![];
+        if input.peek(syn::token::Brace) {
+            let content;
+            syn::braced!(content in input);
+            if !primitive.has_bits() {
+                return Err(content.error("bit ranges need an unsigned register of up to 64 bits"));
+            }
+            while !content.is_empty() {
+                let field = BitField::parse(&content, primitive)?;
+                if let Some(other) = bits.iter().find(|other| other.mask() & field.mask() != 0) {
+                    return Err(syn::Error::new(
+                        field.ident.span(),
+                        format!("bits overlap with '{}'", other.ident),
+                    ));
+                }
+                bits.push(field);
+                if content.is_empty() {
+                    break;
+                }
+                content.parse::<syn::Token![,]>()?;
+            }
+        }
+
         Ok(Self {
+            attrs,
+            vis,
             ident,
-            writable,
+            access,
             primitive,
-            offset: offset
-                .base10_parse()
-                .expect("unable to cast offset to a u64"),
+            value_ty,
+            offset,
+            array,
+            reset,
+            bits,
+            critical,
+            split: split.is_some(),
+            endian: endian.map(|(endian, _)| endian),
         })
     }
 }
 
-struct IoTypeItem {
+impl IoField {
+    /// The unsigned integer the bytes of a float register are swapped in.
+    fn float_bits(&self) -> proc_macro2::TokenStream {
+        match self.primitive {
+            Primitive::F32 => quote!(u32),
+            _ => quote!(u64),
+        }
+    }
+
+    /// Reads the register `ptr` points to, in two halves if it is split, swapping its
+    /// bytes if it is not in the target's byte order.
+    fn load(&self, ptr: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+        let from = match self.endian {
+            Some(Endian::Little) => quote!(from_le),
+            Some(Endian::Big) => quote!(from_be),
+            None => quote!(),
+        };
+        let ty = self.primitive.as_ty();
+        let raw = match (self.split, self.primitive) {
+            (false, _) => quote!(#ptr.read_volatile()),
+            (true, Primitive::I64) => {
+                quote!(::rbrew_shared::io::read_split(#ptr.cast::<u64>()) as i64)
+            }
+            (true, _) => quote!(::rbrew_shared::io::read_split(#ptr)),
+        };
+        match (self.endian, self.primitive.is_float()) {
+            (None, _) => raw,
+            (Some(_), false) => quote!(#ty::#from(#raw)),
+            // Floats are swapped as integers, keeping the bits of NaNs.
+            (Some(_), true) => {
+                let bits = self.float_bits();
+                quote!(#ty::from_bits(#bits::#from(#ptr.cast::<#bits>().read_volatile())))
+            }
+        }
+    }
+
+    /// Writes `value` to the register `ptr` points to, in two halves if it is split,
+    /// swapping its bytes if it is not in the target's byte order.
+    fn store(
+        &self,
+        ptr: proc_macro2::TokenStream,
+        value: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        let to = match self.endian {
+            Some(Endian::Little) => quote!(to_le),
+            Some(Endian::Big) => quote!(to_be),
+            None => quote!(),
+        };
+        let value = match (self.endian, self.primitive.is_float()) {
+            (None, _) => value,
+            (Some(_), false) => quote!((#value).#to()),
+            (Some(_), true) => {
+                let bits = self.float_bits();
+                return quote!(#ptr.cast::<#bits>().write_volatile((#value).to_bits().#to()));
+            }
+        };
+        match (self.split, self.primitive) {
+            (false, _) => quote!(#ptr.write_volatile(#value)),
+            (true, Primitive::I64) => {
+                quote!(::rbrew_shared::io::write_split(#ptr.cast::<u64>(), (#value) as u64))
+            }
+            (true, _) => quote!(::rbrew_shared::io::write_split(#ptr, #value)),
+        }
+    }
+
+    /// Takes the byte order of the block declaring the field, unless it has its own or is
+    /// a single byte.
+    fn inherit_endian(&mut self, endian: Option<Endian>) {
+        if self.endian.is_none() && self.primitive.align() > 1 {
+            self.endian = endian;
+        }
+    }
+
+    /// The bytes from the field's first register to past its last one.
+    fn extent(&self) -> u64 {
+        let size = self.primitive.align();
+        match &self.array {
+            Some(IoArray { count, stride }) => (count - 1) * stride + size,
+            None => size,
+        }
+    }
+
+    /// The offset of the byte after the field's last register, if its offset is known.
+    fn end(&self) -> Option<u64> {
+        Some(self.offset.known()? + self.extent())
+    }
+
+    /// The bytes of each of the field's registers, relative to its block, none if its
+    /// offset is deferred.
+    fn ranges(&self) -> Vec<Range<u64>> {
+        let Some(offset) = self.offset.known() else {
+            return vec![];
+        };
+        let size = self.primitive.align();
+        let (count, stride) = match &self.array {
+            Some(IoArray { count, stride }) => (*count, *stride),
+            None => (1, 0),
+        };
+        (0..count)
+            .map(|index| offset + index * stride..offset + index * stride + size)
+            .collect()
+    }
+}
+
+/// A group of registers at an offset from its block, `name: block = offset { fields }`,
+/// with a type of its own named after the block and the group, such as `ExiChannel`.
+///
+/// Repeated groups, `name: [block; count] = offset [stride N] { fields }`, take the
+/// index of the group as a const parameter.
+///
+/// Like fields, sub-blocks without an offset follow the previous field at their
+/// alignment.
+struct IoBlock {
     attrs: Vec<Attribute>,
+    /// The visibility of the sub-block's type, the block's unless given.
     vis: Visibility,
     ident: Ident,
-    base_adr: u64,
+    offset: u64,
+    /// The stride defaults to the size of the group.
+    array: Option<IoArray>,
+    fields: Vec<IoField>,
+    /// Relative to the sub-block.
+    reserved: Vec<Reserved>,
+}
+
+/// Whether a sub-block, `block` or `[block; count]`, comes next.
+fn peek_block(input: syn::parse::ParseStream) -> bool {
+    let peek = |input: syn::parse::ParseStream| {
+        if input.peek(syn::token::Bracket) {
+            let content;
+            syn::bracketed!(content in input);
+            parse_keyword(&content, "block")
+        } else {
+            parse_keyword(input, "block")
+        }
+    };
+    peek(&input.fork()).unwrap_or(false)
+}
+
+impl IoBlock {
+    /// Parses the rest of a sub-block after its name and colon, `next` being the end of
+    /// the previous field if it is known.
+    fn parse(
+        input: syn::parse::ParseStream,
+        mut attrs: Vec<Attribute>,
+        vis: Visibility,
+        ident: Ident,
+        next: Option<u64>,
+    ) -> syn::Result<Self> {
+        let critical = take_flag(&mut attrs, "critical").is_some();
+        let split = take_flag(&mut attrs, "split").is_some();
+        let endian = take_endian(&mut attrs)?.map(|(endian, _)| endian);
+        let count = if input.peek(syn::token::Bracket) {
+            let content;
+            syn::bracketed!(content in input);
+            parse_keyword(&content, "block")?;
+            content.parse::<syn::Token![;]>()?;
+            let count: syn::LitInt = content.parse()?;
+            let count: u64 = count.base10_parse()?;
+            if count == 0 {
+                return Err(content.error("sub-block arrays cannot be empty"));
+            }
+            Some(count)
+        } else {
+            parse_keyword(input, "block")?;
+            None
+        };
+        // The layout of the sub-block's registers needs its offset.
+        let offset = match parse_offset(input)? {
+            Some(Number::Deferred(expr)) => {
+                return Err(syn::Error::new_spanned(
+                    expr,
+                    "sub-blocks need offsets of literals, not naming constants",
+                ))
+            }
+            offset => offset.and_then(|offset| offset.known()),
+        };
+        let stride = match count {
+            Some(_) if parse_keyword(input, "stride")? => Some(input.parse::<syn::LitInt>()?),
+            _ => None,
+        };
+
+        let content;
+        syn::braced!(content in input);
+        let mut fields = vec![];
+        let mut reserved = vec![];
+        for item in parse_items(&content, None)? {
+            match item {
+                IoItem::Field(mut field) => {
+                    if let Number::Deferred(expr) = &field.offset {
+                        return Err(syn::Error::new_spanned(
+                            expr,
+                            "registers of sub-blocks need offsets of literals, not naming \
+                             constants",
+                        ));
+                    }
+                    field.critical |= critical;
+                    field.split |= split && field.primitive.is_split();
+                    field.inherit_endian(endian);
+                    fields.push(field);
+                }
+                IoItem::Reserved(range) => reserved.push(range),
+                IoItem::Block(block) => {
+                    return Err(syn::Error::new(
+                        block.ident.span(),
+                        "sub-blocks cannot contain sub-blocks",
+                    ))
+                }
+            }
+        }
+        let ends = reserved.iter().map(|reserved| reserved.range.end);
+        let (Some(extent), Some(align)) = (
+            fields.iter().filter_map(IoField::end).chain(ends).max(),
+            fields.iter().map(|field| field.primitive.align()).max(),
+        ) else {
+            return Err(syn::Error::new(
+                ident.span(),
+                "sub-blocks need at least one register",
+            ));
+        };
+
+        let offset = match (offset, next) {
+            (Some(offset), _) => offset,
+            (None, Some(next)) => next.next_multiple_of(align),
+            (None, None) => return Err(no_offset_error(&ident)),
+        };
+        let array = match count {
+            Some(count) => {
+                let stride = match stride {
+                    Some(stride) => {
+                        let value: u64 = stride.base10_parse()?;
+                        if value < extent || !value.is_multiple_of(align) {
+                            return Err(syn::Error::new(
+                                stride.span(),
+                                format!(
+                                    "the stride must be at least the size of the sub-block, \
+                                     {extent}, and a multiple of its alignment, {align}"
+                                ),
+                            ));
+                        }
+                        value
+                    }
+                    None => extent.next_multiple_of(align),
+                };
+                Some(IoArray { count, stride })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            attrs,
+            vis,
+            ident,
+            offset,
+            array,
+            fields,
+            reserved,
+        })
+    }
+
+    /// The offset of the byte after the sub-block.
+    fn end(&self) -> u64 {
+        match &self.array {
+            Some(IoArray { count, stride }) => self.offset + count * stride,
+            None => self.offset + self.len(),
+        }
+    }
+
+    /// The size of the group, or of one of its instances.
+    fn len(&self) -> u64 {
+        match &self.array {
+            Some(IoArray { stride, .. }) => *stride,
+            None => {
+                let ends = self.reserved.iter().map(|reserved| reserved.range.end);
+                self.fields
+                    .iter()
+                    .filter_map(IoField::end)
+                    .chain(ends)
+                    .max()
+                    .unwrap_or(0)
+            }
+        }
+    }
+}
+
+enum IoItem {
+    Field(IoField),
+    Block(IoBlock),
+    Reserved(Reserved),
+}
+
+/// A range of a block no register may lie in, `reserved start..end` or `start..=end`.
+/// Fields without an offset following it are placed after it.
+struct Reserved {
+    range: Range<u64>,
+    span: Span,
+}
+
+impl Reserved {
+    /// Parses the range after `reserved`. Open ranges, `start..`, reach the end of the
+    /// block and are only allowed where its `len` is known.
+    fn parse(input: syn::parse::ParseStream, span: Span, len: Option<u64>) -> syn::Result<Self> {
+        let start: syn::LitInt = input.parse()?;
+        let start = start.base10_parse()?;
+        let end = if input.parse::<Option<syn::Token![..=]>>()?.is_some() {
+            let end: syn::LitInt = input.parse()?;
+            end.base10_parse::<u64>()? + 1
+        } else {
+            input.parse::<syn::Token![..]>()?;
+            if input.peek(syn::LitInt) {
+                let end: syn::LitInt = input.parse()?;
+                end.base10_parse()?
+            } else {
+                len.ok_or_else(|| {
+                    syn::Error::new(span, "reserved ranges of sub-blocks need an end")
+                })?
+            }
+        };
+        if end <= start {
+            return Err(syn::Error::new(span, "the reserved range is empty"));
+        }
+        Ok(Self {
+            range: start..end,
+            span,
+        })
+    }
+}
+
+impl IoItem {
+    /// Parses an item, `next` being the end of the previous one if it is known and `len`
+    /// the size of the block, unless it is a sub-block.
+    fn parse(
+        input: syn::parse::ParseStream,
+        next: Option<u64>,
+        len: Option<u64>,
+    ) -> syn::Result<Self> {
+        // Reserved ranges take doc comments, explaining the hole.
+        let attrs = Attribute::parse_outer(input)?;
+        let vis: Visibility = input.parse()?;
+        if input.peek(Ident) && !input.peek2(syn::Token![:]) {
+            let keyword: Ident = input.parse()?;
+            if keyword != "reserved" {
+                return Err(syn::Error::new(
+                    keyword.span(),
+                    "expected a field, a sub-block or a reserved range",
+                ));
+            }
+            if !matches!(vis, Visibility::Inherited) {
+                return Err(syn::Error::new(
+                    keyword.span(),
+                    "reserved ranges have no visibility",
+                ));
+            }
+            return Reserved::parse(input, keyword.span(), len).map(IoItem::Reserved);
+        }
+        let ident = input.parse()?;
+        input.parse::<syn::Token![:]>()?;
+        if peek_block(input) {
+            IoBlock::parse(input, attrs, vis, ident, next).map(IoItem::Block)
+        } else {
+            IoField::parse(input, attrs, vis, ident, next).map(IoItem::Field)
+        }
+    }
+
+    fn end(&self) -> Option<u64> {
+        match self {
+            IoItem::Field(field) => field.end(),
+            IoItem::Block(block) => Some(block.end()),
+            IoItem::Reserved(reserved) => Some(reserved.range.end),
+        }
+    }
+}
+
+/// Parses the comma separated fields, sub-blocks and reserved ranges of a block, whose
+/// size is `len` unless it is a sub-block.
+fn parse_items(input: syn::parse::ParseStream, len: Option<u64>) -> syn::Result<Vec<IoItem>> {
+    let mut items: Vec<IoItem> = vec![];
+    while !input.is_empty() {
+        let next = items.last().map_or(Some(0), IoItem::end);
+        items.push(IoItem::parse(input, next, len)?);
+        if input.is_empty() {
+            break;
+        }
+        input.parse::<syn::Token![,]>()?;
+    }
+    Ok(items)
+}
+
+/// The offsets of a block's cached and uncached mirrors from its physical address,
+/// `, cached 0x8000_0000, uncached 0xc000_0000` following its length.
+#[derive(Clone, Copy, Default)]
+struct Mirrors {
+    cached: Option<u64>,
+    /// Registers are accessed through the uncached mirror.
+    uncached: Option<u64>,
+}
+
+impl Mirrors {
+    fn is_empty(self) -> bool {
+        self.cached.is_none() && self.uncached.is_none()
+    }
+}
+
+pub(crate) struct IoTypeItem {
+    attrs: Vec<Attribute>,
+    /// Whether the block is marked `#[peripheral]`, moving its access functions to an
+    /// ownership token and making its pointer functions private to the module.
+    peripheral: bool,
+    /// Whether the block is marked `#[view]`, making it a unit struct with access methods.
+    view: bool,
+    vis: Visibility,
+    ident: Ident,
+    /// The physical address of the block with mirrors.
+    base_adr: Number,
     len: u64,
-    body: Punctuated<IoField, syn::Token![,]>,
+    mirrors: Mirrors,
+    body: Vec<IoItem>,
 }
 
 impl Parse for IoTypeItem {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let attrs = Attribute::parse_outer(input)?;
+        let mut attrs = Attribute::parse_outer(input)?;
+        let peripheral = take_flag(&mut attrs, "peripheral");
+        let view = take_flag(&mut attrs, "view");
+        let critical = take_flag(&mut attrs, "critical").is_some();
+        let split = take_flag(&mut attrs, "split").is_some();
+        let endian = take_endian(&mut attrs)?.map(|(endian, _)| endian);
+        if let (Some(_), Some(view)) = (&peripheral, &view) {
+            return Err(syn::Error::new_spanned(
+                view,
+                "a block cannot be both a `#[view]` and a `#[peripheral]`",
+            ));
+        }
         let vis = input.parse()?;
         input.parse::<syn::Token![type]>()?;
         let ident = input.parse()?;
         input.parse::<syn::Token![:]>()?;
-        let base_adr: syn::LitInt = input.parse()?;
+        let base_adr = Number::parse(input)?;
         input.parse::<syn::Token![,]>()?;
         let len: syn::LitInt = input.parse()?;
+        let mut mirrors = Mirrors::default();
+        while input.parse::<Option<syn::Token![,]>>()?.is_some() {
+            let mirror = if parse_keyword(input, "cached")? {
+                &mut mirrors.cached
+            } else if parse_keyword(input, "uncached")? {
+                &mut mirrors.uncached
+            } else {
+                return Err(input.error("expected 'cached' or 'uncached' and a mirror offset"));
+            };
+            let offset: syn::LitInt = input.parse()?;
+            if mirror.replace(offset.base10_parse()?).is_some() {
+                return Err(syn::Error::new(
+                    offset.span(),
+                    "the mirror is already declared",
+                ));
+            }
+        }
+        let len = len.base10_parse()?;
         let content;
         syn::braced!(content in input);
-        let body = Punctuated::parse_terminated(&content)?;
+        let mut body = parse_items(&content, Some(len))?;
+        // `#[critical]`, `#[split]`, `#[le]` and `#[be]` on the block apply to all of its
+        // fields.
+        let fields = body.iter_mut().flat_map(|item| match item {
+            IoItem::Field(field) => std::slice::from_mut(field),
+            IoItem::Block(block) => &mut block.fields[..],
+            IoItem::Reserved(_) => &mut [],
+        });
+        for field in fields {
+            field.critical |= critical;
+            field.split |= split && field.primitive.is_split();
+            field.inherit_endian(endian);
+        }
+        let base = base_adr.known();
+        check_layout(
+            &body,
+            base.map(|base| base + mirrors.uncached.unwrap_or(0)),
+            len,
+        )?;
         Ok(Self {
             attrs,
+            peripheral: peripheral.is_some(),
+            view: view.is_some(),
             vis,
             ident,
-            base_adr: base_adr
-                .base10_parse()
-                .expect("failed to convert base address to u64"),
-            len: len.base10_parse().expect("failed to convert length to u64"),
+            base_adr,
+            len,
+            mirrors,
             body,
         })
     }
 }
 
-pub fn iotype2(ts: TokenStream) -> TokenStream {
-    let IoTypeItem {
+/// Checks that the registers of the block at `base_adr` are aligned, that no two of
+/// them overlap or lie in a reserved range, and that they all fit in its `len` bytes.
+/// Without a known address, registers are checked to be aligned in the block, and the
+/// block to be aligned to its largest register by [`deferred_asserts`]. Registers at
+/// deferred offsets are only checked by those assertions, and not for overlaps.
+fn check_layout(body: &[IoItem], base_adr: Option<u64>, len: u64) -> syn::Result<()> {
+    // The reserved ranges, checked first so registers are reported as lying in them.
+    let mut reserved: Vec<(Range<u64>, Span)> = vec![];
+    for item in body {
+        match item {
+            IoItem::Reserved(range) => reserved.push((range.range.clone(), range.span)),
+            IoItem::Block(block) => {
+                for index in 0..block.array.as_ref().map_or(1, |array| array.count) {
+                    let start =
+                        block.offset + block.array.as_ref().map_or(0, |array| index * array.stride);
+                    reserved.extend(block.reserved.iter().map(|range| {
+                        (
+                            range.range.start + start..range.range.end + start,
+                            range.span,
+                        )
+                    }));
+                }
+            }
+            IoItem::Field(_) => {}
+        }
+    }
+    for (index, (range, span)) in reserved.iter().enumerate() {
+        if range.end > len {
+            return Err(syn::Error::new(
+                *span,
+                format!(
+                    "the reserved range ends at {:#x}, past the {len:#x} bytes of the block",
+                    range.end
+                ),
+            ));
+        }
+        if reserved[..index]
+            .iter()
+            .any(|(other, _)| other.start < range.end && range.start < other.end)
+        {
+            return Err(syn::Error::new(*span, "reserved ranges overlap"));
+        }
+    }
+
+    // Every register, with the name of its field.
+    let mut registers: Vec<(Range<u64>, String, &Ident)> = vec![];
+    for item in body {
+        let fields: Vec<_> = match item {
+            IoItem::Reserved(_) => vec![],
+            IoItem::Field(field) => field
+                .ranges()
+                .into_iter()
+                .map(|range| (range, field.ident.to_string(), &field.ident))
+                .collect(),
+            IoItem::Block(block) => {
+                let instances = match &block.array {
+                    Some(IoArray { count, stride }) => {
+                        (0..*count).map(|index| index * stride).collect()
+                    }
+                    None => vec![0],
+                };
+                instances
+                    .into_iter()
+                    .flat_map(|start| {
+                        block.fields.iter().flat_map(move |field| {
+                            field.ranges().into_iter().map(move |range| {
+                                let offset = block.offset + start;
+                                (
+                                    range.start + offset..range.end + offset,
+                                    format!("{}.{}", block.ident, field.ident),
+                                    &field.ident,
+                                )
+                            })
+                        })
+                    })
+                    .collect()
+            }
+        };
+        for (range, name, ident) in fields {
+            let size = range.end - range.start;
+            let (address, at) = match base_adr {
+                Some(base_adr) => (base_adr + range.start, ""),
+                None => (range.start, "offset "),
+            };
+            if !address.is_multiple_of(size) {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    format!("'{name}' at {at}{address:#x} is not aligned to its size, {size}"),
+                ));
+            }
+            if range.end > len {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    format!(
+                        "'{name}' ends at {:#x}, past the {len:#x} bytes of the block",
+                        range.end
+                    ),
+                ));
+            }
+            if let Some((hole, _)) = reserved
+                .iter()
+                .find(|(hole, _)| hole.start < range.end && range.start < hole.end)
+            {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    format!(
+                        "'{name}' lies in the reserved range {:#x}..{:#x}",
+                        hole.start, hole.end
+                    ),
+                ));
+            }
+            if let Some((_, other, _)) = registers
+                .iter()
+                .find(|(other, ..)| other.start < range.end && range.start < other.end)
+            {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    format!("'{name}' overlaps with '{other}'"),
+                ));
+            }
+            registers.push((range, name, ident));
+        }
+    }
+    Ok(())
+}
+
+/// The newtype holding values of a register, named after the block and the field, such
+/// as `ViDcr`, with accessors for its bit ranges. `outer` are the attributes of the
+/// sub-block declaring the field.
+fn value_type(
+    vis: &Visibility,
+    block: &Ident,
+    outer: &[&Attribute],
+    field: &IoField,
+) -> proc_macro2::TokenStream {
+    let ty = field.primitive.as_ty();
+    let name = format_ident!(
+        "{}{}",
+        pascal_case(&block.to_string()),
+        pascal_case(&field.ident.to_string())
+    );
+    let doc = format!("A value of the `{}` register of [`{block}`].", field.ident);
+    let reset = syn::LitInt::new(
+        &format!("{:#x}", field.reset.unwrap_or(0)),
+        Span::mixed_site(),
+    );
+    let reset_doc = match field.reset {
+        Some(_) => "The value of the register after reset.",
+        None => "All bits cleared.",
+    };
+    let cfgs: Vec<_> = outer
+        .iter()
+        .copied()
+        .chain(
+            field
+                .attrs
+                .iter()
+                .filter(|attr| !attr.path().is_ident("doc")),
+        )
+        .collect();
+
+    let accessors = field.bits.iter().map(|bit| {
+        let attrs = &bit.attrs;
+        let get_ident = &bit.ident;
+        let set_ident = format_ident!("set_{}", bit.ident);
+        let with_ident = format_ident!("with_{}", bit.ident);
+        let mask = syn::LitInt::new(&format!("{:#x}", bit.mask()), Span::mixed_site());
+        let shift = syn::LitInt::new(&bit.low.to_string(), Span::mixed_site());
+        let ValueConversion {
+            read_ty,
+            write_ty,
+            from_raw,
+            to_raw,
+        } = value_conversion(&ty, bit.value_ty.as_ref(), bit.low == bit.high);
+        quote! {
+            #(#attrs)*
+            #[inline(always)]
+            pub fn #get_ident(self) -> #read_ty {
+                let raw = (self.0 & #mask) >> #shift;
+                #from_raw
+            }
+
+            #(#attrs)*
+            /// Bits of `value` that do not fit the field are ignored.
+            #[inline(always)]
+            pub fn #set_ident(&mut self, value: #write_ty) {
+                let raw = #to_raw;
+                self.0 = self.0 & !#mask | (raw << #shift) & #mask;
+            }
+
+            #(#attrs)*
+            /// Bits of `value` that do not fit the field are ignored.
+            #[inline(always)]
+            #[must_use]
+            pub fn #with_ident(mut self, value: #write_ty) -> Self {
+                self.#set_ident(value);
+                self
+            }
+        }
+    });
+
+    quote! {
+        #(#cfgs)*
+        #[doc = #doc]
+        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+        #[repr(transparent)]
+        #vis struct #name(pub #ty);
+
+        #(#cfgs)*
+        #[allow(deprecated)]
+        impl #name {
+            #(#accessors)*
+        }
+
+        #(#cfgs)*
+        #[allow(deprecated)]
+        impl ::core::default::Default for #name {
+            #[doc = #reset_doc]
+            fn default() -> Self {
+                Self(#reset)
+            }
+        }
+
+        #(#cfgs)*
+        #[allow(deprecated)]
+        impl ::core::convert::From<#ty> for #name {
+            fn from(value: #ty) -> Self {
+                Self(value)
+            }
+        }
+
+        #(#cfgs)*
+        #[allow(deprecated)]
+        impl ::core::convert::From<#name> for #ty {
+            fn from(value: #name) -> Self {
+                value.0
+            }
+        }
+    }
+}
+
+/// The pointer function of a field, `ptr_vis` coming before it, and the functions
+/// accessing it through the pointer function of `ptrs`.
+fn field_fns(
+    field: &IoField,
+    ptrs: &proc_macro2::TokenStream,
+    receivers: &Receivers,
+    mirrors: Mirrors,
+    ptr_vis: &proc_macro2::TokenStream,
+) -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    let IoField {
         attrs,
         vis,
         ident,
-        base_adr,
-        len,
-        body,
-    }: IoTypeItem = syn::parse(ts).expect("failed to parse IO item");
+        access,
+        offset,
+        primitive,
+        value_ty,
+        array,
+        bits,
+        critical,
+        ..
+    } = field;
+    let Receivers { read, write } = receivers;
+    let vis = match vis {
+        Visibility::Inherited => quote!(pub),
+        vis => quote!(#vis),
+    };
+    let upper = ident.to_string().trim_start_matches("r#").to_uppercase();
+    let (offset_ident, addr_ident) = (
+        format_ident!("{upper}_OFFSET"),
+        format_ident!("{upper}_ADDR"),
+    );
+    // Deferred offsets are written once, in the offset const.
+    let offset_lit = match offset {
+        Number::Known(_) => offset.to_tokens(),
+        Number::Deferred(_) => quote!(Self::#offset_ident),
+    };
+    let offset_value = offset.to_tokens();
+    let ty = primitive.as_ty();
 
-    let base_adr_lit = syn::LitInt::new(&base_adr.to_string(), Span::mixed_site());
-    let len_lit = syn::LitInt::new(&len.to_string(), Span::mixed_site());
+    let ptr_ident = format_ident!("{}_ptr", ident);
+    let (readable, writable) = (access.readable(), access.writable());
+    let write_only_doc = if readable {
+        quote!()
+    } else {
+        quote! {
+            ///
+            /// The register is write-only, reading it is undefined or has side
+            /// effects.
+        }
+    };
+    // The access functions swap the bytes, the pointers give the register as it is.
+    let endian_doc = match field.endian {
+        Some(Endian::Little) => quote! {
+            ///
+            /// The register is little-endian, and read through the pointer with its bytes
+            /// swapped on big-endian targets.
+        },
+        Some(Endian::Big) => quote! {
+            ///
+            /// The register is big-endian, and read through the pointer with its bytes
+            /// swapped on little-endian targets.
+        },
+        None => quote!(),
+    };
+    // Registers of arrays are accessed by index.
+    let (index_param, index_arg) = match array {
+        Some(_) => (quote!(index: usize,), quote!(index)),
+        None => (quote!(), quote!()),
+    };
+    // Read-modify-write functions of `#[critical]` fields run in a critical section.
+    let critical_doc = if *critical {
+        quote! {
+            ///
+            /// Runs in a critical section, so interrupts cannot tear the update.
+        }
+    } else {
+        quote!()
+    };
+    let rmw = |body: proc_macro2::TokenStream| {
+        if *critical {
+            quote!(::rbrew_shared::critical_section::with(|_| { #body }))
+        } else {
+            body
+        }
+    };
 
-    let fns = body.iter().map(
-        |IoField {
-             ident,
-             writable,
-             offset,
-             primitive,
-         }| {
-            let offset_lit = syn::LitInt::new(&offset.to_string(), Span::mixed_site());
-            let ty = primitive.as_ty();
-
-            let align = primitive.align();
-            assert!(
-                (base_adr + offset) & (align - 1) == 0,
-                "unaligned IO register"
-            );
+    let ValueConversion {
+        read_ty,
+        write_ty,
+        from_raw,
+        to_raw,
+    } = value_conversion(&ty, value_ty.as_ref(), false);
 
-            let write_fn = if *writable {
-                let write_ident = format_ident!("{}_write", ident);
-                quote! {
-                    #[inline(always)]
-                    pub unsafe fn #write_ident(value: #ty) {
-                        Self::#ident().write_volatile(value)
-                    }
+    let write_fn = if writable {
+        let write_ident = format_ident!("{}_write", ident);
+        let store = field.store(quote!(#ptrs::#ptr_ident(#index_arg)), to_raw.clone());
+        quote! {
+            #(#attrs)*
+            #write_only_doc
+            #[inline(always)]
+            #vis unsafe fn #write_ident(#write #index_param value: #write_ty) {
+                #store
+            }
+        }
+    } else {
+        quote!()
+    };
+
+    let modify_fn = if readable && writable {
+        let modify_ident = format_ident!("{}_modify", ident);
+        let load = field.load(quote!(ptr));
+        let store = field.store(quote!(ptr), to_raw.clone());
+        let body = rmw(quote! {
+            let ptr = #ptrs::#ptr_ident(#index_arg);
+            let raw = #load;
+            let value = f(#from_raw);
+            #store
+        });
+        quote! {
+            #(#attrs)*
+            /// Reads the register, passes its value to `f` and writes back what `f`
+            /// returns.
+            #critical_doc
+            #[inline(always)]
+            #vis unsafe fn #modify_ident(
+                #write
+                #index_param
+                f: impl ::core::ops::FnOnce(#read_ty) -> #write_ty,
+            ) {
+                #body
+            }
+        }
+    } else {
+        quote!()
+    };
+
+    // Masked updates of integer registers.
+    let mask_fns = if readable
+        && writable
+        && !matches!(value_ty, Some(ValueType::Enum(_)))
+        && !primitive.is_float()
+    {
+        let ops = [
+            ("set", quote!(raw | mask), "Sets"),
+            ("clear", quote!(raw & !mask), "Clears"),
+            ("toggle", quote!(raw ^ mask), "Toggles"),
+        ];
+        let fns = ops.into_iter().map(|(name, op, verb)| {
+            let fn_ident = format_ident!("{}_{}_bits", ident, name);
+            let doc = format!(" {verb} the bits of `mask`, leaving the others unchanged.");
+            let (load, store) = (field.load(quote!(ptr)), field.store(quote!(ptr), op));
+            let body = rmw(quote! {
+                let ptr = #ptrs::#ptr_ident(#index_arg);
+                let raw = #load;
+                #store
+            });
+            quote! {
+                #(#attrs)*
+                #[doc = #doc]
+                #critical_doc
+                #[inline(always)]
+                #vis unsafe fn #fn_ident(#write #index_param mask: #ty) {
+                    #body
                 }
-            } else {
-                quote!()
-            };
+            }
+        });
+        quote!(#(#fns)*)
+    } else {
+        quote!()
+    };
+
+    let read_fn = if readable {
+        let read_ident = format_ident!("{}_read", ident);
+        let load = field.load(quote!(#ptrs::#ptr_ident(#index_arg)));
+        quote! {
+            #(#attrs)*
+            #[inline(always)]
+            #vis unsafe fn #read_ident(#read #index_param) -> #read_ty {
+                let raw = #load;
+                #from_raw
+            }
+        }
+    } else {
+        quote!()
+    };
 
-            let read_fn = {
-                let read_ident = format_ident!("{}_read", ident);
+    let ptr_ty = if writable {
+        quote!(*mut #ty)
+    } else {
+        quote!(*const #ty)
+    };
+    // The pointer function through `BASE`, and the ones through the mirrors.
+    let ptr_fns = [
+        (ptr_ident.clone(), quote!(BASE), quote!()),
+        (
+            format_ident!("{}_ptr_cached", ident),
+            quote!(CACHED_BASE),
+            quote! {
+                ///
+                /// The register in the cached mirror.
+            },
+        ),
+        (
+            format_ident!("{}_ptr_uncached", ident),
+            quote!(BASE),
+            quote! {
+                ///
+                /// The register in the uncached mirror, where it is accessed.
+            },
+        ),
+    ];
+    let mirrored = [true, mirrors.cached.is_some(), mirrors.uncached.is_some()];
+    let ptr_fns = ptr_fns
+        .into_iter()
+        .zip(mirrored)
+        .filter(|(_, mirrored)| *mirrored);
+    let ptr_fn = ptr_fns.map(|((ptr_ident, base, mirror_doc), _)| {
+        // The offset of the mirror from the physical address, for the mock registers.
+        let mirror = if mirrors.is_empty() {
+            quote!(0)
+        } else {
+            quote!(Self::#base - Self::PHYS_BASE)
+        };
+        match array {
+            Some(IoArray { count, stride }) => {
+                let count = syn::LitInt::new(&count.to_string(), Span::mixed_site());
+                let stride = syn::LitInt::new(&stride.to_string(), Span::mixed_site());
                 quote! {
+                    #(#attrs)*
+                    #write_only_doc
+                    #endian_doc
+                    #mirror_doc
+                    ///
+                    /// Panics if `index` is out of bounds.
                     #[inline(always)]
-                    pub unsafe fn #read_ident() -> #ty {
-                        Self::#ident().read_volatile()
+                    #ptr_vis fn #ptr_ident(index: usize) -> #ptr_ty {
+                        assert!(index < #count, "register index out of bounds");
+                        let address = Self::#base + #offset_lit + index * #stride;
+                        ::rbrew_shared::io::mmio(address, #mirror) as *mut _
                     }
                 }
-            };
+            }
+            None => quote! {
+                #(#attrs)*
+                #write_only_doc
+                #endian_doc
+                #mirror_doc
+                #[inline(always)]
+                #ptr_vis fn #ptr_ident() -> #ptr_ty {
+                    ::rbrew_shared::io::mmio(Self::#base + #offset_lit, #mirror) as *mut _
+                }
+            },
+        }
+    });
+    let ptr_fn = quote!(#(#ptr_fn)*);
 
-            let ptr_fn = {
-                let ptr_ty = if *writable {
-                    quote!(*mut #ty)
-                } else {
-                    quote!(*const #ty)
-                };
-                let ptr_ident = format_ident!("{}_ptr", ident);
-                quote! {
-                    #[inline(always)]
-                    pub fn #ptr_ident() -> #ptr_ty {
-                        (#base_adr_lit + #offset_lit) as *mut _
+    // The bit ranges also get the field's attributes, except for its docs.
+    let field_attrs: Vec<_> = attrs
+        .iter()
+        .filter(|attr| !attr.path().is_ident("doc"))
+        .collect();
+
+    // The offset and address, for code that cannot call the pointer functions.
+    let first = if array.is_some() {
+        " of the first register"
+    } else {
+        ""
+    };
+    let offset_doc = format!(" The offset of `{ident}`{first} in the block.");
+    let mirror = if mirrors.is_empty() {
+        ""
+    } else {
+        " in the uncached mirror"
+    };
+    let addr_doc = format!(" The address of `{ident}`{first}{mirror}.");
+    let ptr_fn = quote! {
+        #ptr_fn
+
+        #(#field_attrs)*
+        #[doc = #offset_doc]
+        #vis const #offset_ident: usize = #offset_value;
+        #(#field_attrs)*
+        #[doc = #addr_doc]
+        #vis const #addr_ident: usize = Self::BASE + #offset_lit;
+    };
+    // Bit ranges are read from the register, and written by writing it back.
+    let bit_fns = bits.iter().filter(|_| readable).map(|bit| {
+        let bit_attrs: Vec<_> = field_attrs.iter().copied().chain(&bit.attrs).collect();
+        let mask = syn::LitInt::new(&format!("{:#x}", bit.mask()), Span::mixed_site());
+        let shift = syn::LitInt::new(&bit.low.to_string(), Span::mixed_site());
+        let read_ident = format_ident!("{}_{}_read", ident, bit.ident);
+        let write_ident = format_ident!("{}_{}_write", ident, bit.ident);
+        let ValueConversion {
+            read_ty,
+            write_ty,
+            from_raw,
+            to_raw,
+        } = value_conversion(&ty, bit.value_ty.as_ref(), bit.low == bit.high);
+        let load_reg = field.load(quote!(#ptrs::#ptr_ident(#index_arg)));
+        let read_fn = quote! {
+            #(#bit_attrs)*
+            #[inline(always)]
+            #vis unsafe fn #read_ident(#read #index_param) -> #read_ty {
+                let raw = (#load_reg & #mask) >> #shift;
+                #from_raw
+            }
+        };
+        let load = field.load(quote!(ptr));
+        let write_fn = if writable {
+            let store = field.store(
+                quote!(ptr),
+                quote!(#load & !#mask | (raw << #shift) & #mask),
+            );
+            let body = rmw(quote! {
+                let ptr = #ptrs::#ptr_ident(#index_arg);
+                let raw = #to_raw;
+                #store
+            });
+            quote! {
+                #(#bit_attrs)*
+                /// Bits of `value` that do not fit the field are ignored, the
+                /// rest of the register is read and written back unchanged.
+                #critical_doc
+                #[inline(always)]
+                #vis unsafe fn #write_ident(#write #index_param value: #write_ty) {
+                    #body
+                }
+            }
+        } else {
+            quote!()
+        };
+        let modify_fn = if writable {
+            let modify_ident = format_ident!("{}_{}_modify", ident, bit.ident);
+            let store = field.store(quote!(ptr), quote!(reg & !#mask | (raw << #shift) & #mask));
+            let body = rmw(quote! {
+                let ptr = #ptrs::#ptr_ident(#index_arg);
+                let reg = #load;
+                let raw = (reg & #mask) >> #shift;
+                let value = f(#from_raw);
+                let raw = #to_raw;
+                #store
+            });
+            quote! {
+                #(#bit_attrs)*
+                /// Reads the register, passes the field's value to `f` and writes
+                /// back the register with the field set to what `f` returns.
+                #critical_doc
+                #[inline(always)]
+                #vis unsafe fn #modify_ident(
+                    #write
+                    #index_param
+                    f: impl ::core::ops::FnOnce(#read_ty) -> #write_ty,
+                ) {
+                    #body
+                }
+            }
+        } else {
+            quote!()
+        };
+        quote!(
+            #read_fn
+            #write_fn
+            #modify_fn
+        )
+    });
+
+    (
+        ptr_fn,
+        quote!(
+            #read_fn
+            #write_fn
+            #modify_fn
+            #mask_fns
+            #(#bit_fns)*
+        ),
+    )
+}
+
+/// The statements writing the value of every readable register of `fields` to `out`,
+/// accessed through `block` and named with `prefix` before the name of the field.
+fn dump_statements(
+    fields: &[IoField],
+    block: &proc_macro2::TokenStream,
+    prefix: &str,
+) -> Vec<proc_macro2::TokenStream> {
+    fields
+        .iter()
+        .filter(|field| field.access.readable())
+        .map(|field| {
+            let ptr_ident = format_ident!("{}_ptr", field.ident);
+            let cfgs = field
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("cfg"));
+            let width = field.primitive.align() as usize * 2 + 2;
+            let bits = field.primitive.to_bits(quote!(raw));
+            match &field.array {
+                Some(IoArray { count, .. }) => {
+                    let format = format!("{prefix}{}[{{}}] = {{:#0{width}x}}", field.ident);
+                    let count = syn::LitInt::new(&count.to_string(), Span::mixed_site());
+                    let load = field.load(quote!(#block::#ptr_ident(index)));
+                    quote! {
+                        #(#cfgs)*
+                        for index in 0..#count {
+                            let raw = #load;
+                            ::core::writeln!(out, #format, index, #bits)?;
+                        }
                     }
                 }
-            };
+                None => {
+                    let format = format!("{prefix}{} = {{:#0{width}x}}", field.ident);
+                    let load = field.load(quote!(#block::#ptr_ident()));
+                    quote! {
+                        #(#cfgs)*
+                        {
+                            let raw = #load;
+                            ::core::writeln!(out, #format, #bits)?;
+                        }
+                    }
+                }
+            }
+        })
+        .collect()
+}
+
+/// The receivers of the access functions, empty unless they are methods of a view or a
+/// peripheral.
+struct Receivers {
+    read: proc_macro2::TokenStream,
+    write: proc_macro2::TokenStream,
+}
+
+/// Where the access functions of a `#[peripheral]` block are moved to.
+enum Peripheral {
+    /// A singleton taken once.
+    Owned,
+    /// A view of a sub-block borrowed from the peripheral of its parent.
+    Borrowed { parent: Ident },
+}
+
+/// A block of registers to generate the type of.
+struct BlockType<'a> {
+    attrs: &'a [Attribute],
+    /// The `cfg`s of a sub-block, for the items besides its type.
+    outer: &'a [&'a Attribute],
+    vis: &'a Visibility,
+    ident: &'a Ident,
+    array: Option<&'a IoArray>,
+    /// The expression of `BASE`, or of `PHYS_BASE` with mirrors.
+    base: proc_macro2::TokenStream,
+    mirrors: Mirrors,
+    len: u64,
+    fields: &'a [IoField],
+    /// The statements of the `dump` function.
+    dumps: Vec<proc_macro2::TokenStream>,
+    peripheral: Option<Peripheral>,
+    /// Whether the type is a unit struct with access methods rather than an empty enum.
+    view: bool,
+    /// The functions returning its sub-blocks, next to the access functions.
+    accessors: Vec<proc_macro2::TokenStream>,
+}
+
+/// The type of a block with its functions, the value types of its fields and its
+/// peripheral.
+fn block_type(
+    BlockType {
+        attrs,
+        outer,
+        vis,
+        ident,
+        array,
+        base,
+        mirrors,
+        len,
+        fields,
+        dumps,
+        peripheral,
+        view,
+        accessors,
+    }: BlockType,
+) -> proc_macro2::TokenStream {
+    let len_lit = syn::LitInt::new(&len.to_string(), Span::mixed_site());
+    let value_types = fields
+        .iter()
+        .filter(|field| field.value_ty.is_none() && !field.primitive.is_float())
+        .map(|field| value_type(vis, ident, outer, field));
 
-            quote!(
-                #ptr_fn
-                #read_fn
-                #write_fn
+    // Views are unit structs, copied around like references to the registers.
+    let (item, body) = if view {
+        (quote!(#[derive(Clone, Copy)] #vis struct), quote!(;))
+    } else {
+        (quote!(#vis enum), quote!({}))
+    };
+    let (declaration, header, ptrs, base) = match array {
+        Some(IoArray { count, stride }) => {
+            let doc = format!(
+                "`N` selects one of the {count} instances of the block, in order of address; \
+                 other values fail to compile."
+            );
+            let count = syn::LitInt::new(&count.to_string(), Span::mixed_site());
+            let stride = syn::LitInt::new(&stride.to_string(), Span::mixed_site());
+            (
+                quote! {
+                    ///
+                    #[doc = #doc]
+                    #item #ident<const N: usize> #body
+                },
+                quote!(impl<const N: usize> #ident<N>),
+                quote!(#ident::<N>),
+                quote! {{
+                    assert!(N < #count, "block index out of bounds");
+                    #base + N * #stride
+                }},
             )
+        }
+        None => (
+            quote!(#item #ident #body),
+            quote!(impl #ident),
+            quote!(#ident),
+            base,
+        ),
+    };
+
+    let bases = if mirrors.is_empty() {
+        quote!(pub const BASE: usize = #base;)
+    } else {
+        let lit = |offset: u64| syn::LitInt::new(&format!("{offset:#x}"), Span::mixed_site());
+        let uncached = lit(mirrors.uncached.unwrap_or(0));
+        let cached = mirrors.cached.map(lit).map(|cached| {
+            quote! {
+                /// The address of the block in the cached mirror.
+                pub const CACHED_BASE: usize = Self::PHYS_BASE + #cached;
+            }
+        });
+        quote! {
+            /// The physical address of the block.
+            pub const PHYS_BASE: usize = #base;
+            /// The address of the block in the uncached mirror, where it is accessed.
+            pub const BASE: usize = Self::PHYS_BASE + #uncached;
+            #cached
+        }
+    };
+    let mirror = if mirrors.is_empty() {
+        quote!(0)
+    } else {
+        quote!(Self::BASE - Self::PHYS_BASE)
+    };
+
+    let receivers = match (&peripheral, view) {
+        (Some(_), _) => Receivers {
+            read: quote!(&self,),
+            write: quote!(&mut self,),
         },
-    );
+        (None, true) => Receivers {
+            read: quote!(&self,),
+            write: quote!(&self,),
+        },
+        (None, false) => Receivers {
+            read: quote!(),
+            write: quote!(),
+        },
+    };
+    // The pointers of a peripheral are private to the module declaring it, so the
+    // registers are only accessed outside of it through the peripheral that owns them.
+    let private = quote!(#[allow(dead_code)]);
+    let ptr_vis = if peripheral.is_some() {
+        private.clone()
+    } else {
+        quote!(pub)
+    };
+    let (ptr_fns, access_fns): (Vec<_>, Vec<_>) = fields
+        .iter()
+        .map(|field| {
+            let field_ptr_vis = match (&field.vis, peripheral.is_some()) {
+                (_, true) => private.clone(),
+                (Visibility::Inherited, false) => quote!(pub),
+                (vis, false) => quote!(#vis),
+            };
+            field_fns(field, &ptrs, &receivers, mirrors, &field_ptr_vis)
+        })
+        .unzip();
+    let read = &receivers.read;
+    let dump_fn = quote! {
+        /// Writes the value of every readable register to `out`, one `name = value`
+        /// line each.
+        ///
+        /// # Safety
+        /// Reads every register, so reading them must not have side effects.
+        pub unsafe fn dump(#read out: &mut impl ::core::fmt::Write) -> ::core::fmt::Result {
+            #(#dumps)*
+            Ok(())
+        }
+    };
+
+    let (fns, peripheral) = match peripheral {
+        None => (
+            quote! {
+                #dump_fn
+                #(#accessors)*
+                #(#ptr_fns)*
+                #(#access_fns)*
+            },
+            quote!(),
+        ),
+        Some(peripheral) => {
+            let name = format_ident!("{}Peripheral", pascal_case(&ident.to_string()));
+            let peripheral = match peripheral {
+                Peripheral::Owned => {
+                    let doc = format!(
+                        "The registers of [`{ident}`], owned by a single driver at a time."
+                    );
+                    quote! {
+                        #[doc = #doc]
+                        ///
+                        /// The pointer functions of the block are private to the module
+                        /// declaring it, so other modules only access the registers
+                        /// through the peripheral.
+                        #vis struct #name {
+                            _mark: ::core::marker::PhantomData<()>,
+                        }
+
+                        #[allow(deprecated)]
+                        impl #name {
+                            /// Returns the peripheral the first time it is called, and `None`
+                            /// after.
+                            #[cfg(target_has_atomic = "8")]
+                            pub fn take() -> Option<Self> {
+                                static TAKEN: ::core::sync::atomic::AtomicBool =
+                                    ::core::sync::atomic::AtomicBool::new(false);
+                                if TAKEN.swap(true, ::core::sync::atomic::Ordering::AcqRel) {
+                                    None
+                                } else {
+                                    Some(Self { _mark: ::core::marker::PhantomData })
+                                }
+                            }
+
+                            /// Returns the peripheral, taken or not. On targets without
+                            /// atomics, this is the only way to get it.
+                            ///
+                            /// # Safety
+                            /// The registers must not be accessed through another
+                            /// instance of the peripheral at the same time.
+                            pub unsafe fn steal() -> Self {
+                                Self { _mark: ::core::marker::PhantomData }
+                            }
+
+                            #dump_fn
+                            #(#accessors)*
+                            #(#access_fns)*
+                        }
+                    }
+                }
+                Peripheral::Borrowed { parent } => {
+                    let doc = format!("The registers of [`{ident}`], borrowed from [`{parent}`].");
+                    let (params, args) = match array {
+                        Some(_) => (quote!(<'a, const N: usize>), quote!(<'a, N>)),
+                        None => (quote!(<'a>), quote!(<'a>)),
+                    };
+                    quote! {
+                        #(#outer)*
+                        #[doc = #doc]
+                        #vis struct #name #params {
+                            _mark: ::core::marker::PhantomData<&'a mut #parent>,
+                        }
+
+                        #(#outer)*
+                        #[allow(deprecated)]
+                        impl #params #name #args {
+                            #dump_fn
+                            #(#access_fns)*
+                        }
+                    }
+                }
+            };
+            (quote!(#(#ptr_fns)*), peripheral)
+        }
+    };
 
     quote! {
         #(#attrs)*
-        #vis enum #ident {}
+        #declaration
+
+        #(#value_types)*
 
-        impl #ident {
-            pub const BASE: usize = #base_adr_lit;
+        #(#outer)*
+        // The generated functions call each other, deprecated fields or not.
+        #[allow(deprecated)]
+        #header {
+            #bases
             pub const LEN: usize = #len_lit;
 
-            pub fn ptr() -> *mut () {
-                Self::BASE as *mut _
+            #ptr_vis fn ptr() -> *mut () {
+                ::rbrew_shared::io::mmio(Self::BASE, #mirror) as *mut _
+            }
+
+            #fns
+        }
+
+        #peripheral
+    }
+}
+
+pub fn iotype2(ts: TokenStream) -> TokenStream {
+    match syn::parse(ts) {
+        Ok(item) => expand(item).into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// The assertions checking the layout of a block the macro could not, for a base address
+/// or offsets naming constants.
+fn deferred_asserts(
+    ident: &Ident,
+    base_adr: &Number,
+    len: u64,
+    body: &[IoItem],
+) -> proc_macro2::TokenStream {
+    let name = ident.to_string();
+    let lit = |value: u64| syn::LitInt::new(&format!("{value:#x}"), Span::mixed_site());
+    let fields = body.iter().flat_map(|item| match item {
+        IoItem::Field(field) => std::slice::from_ref(field),
+        IoItem::Block(block) => &block.fields[..],
+        IoItem::Reserved(_) => &[],
+    });
+    // The registers were checked to be aligned in the block.
+    let base_assert = match base_adr {
+        Number::Known(_) => None,
+        Number::Deferred(_) => fields
+            .map(|field| field.primitive.align())
+            .max()
+            .map(|align| {
+                let msg = format!("`{name}` is not aligned to its largest register, {align}");
+                let align = lit(align);
+                quote! {
+                    const _: () = ::core::assert!(#ident::BASE % #align == 0, #msg);
+                }
+            }),
+    };
+    let field_asserts = body.iter().filter_map(|item| match item {
+        IoItem::Field(
+            field @ IoField {
+                offset: Number::Deferred(_),
+                ..
+            },
+        ) => Some(field),
+        _ => None,
+    });
+    let field_asserts = field_asserts.map(|field| {
+        let upper = field
+            .ident
+            .to_string()
+            .trim_start_matches("r#")
+            .to_uppercase();
+        let offset_ident = format_ident!("{upper}_OFFSET");
+        let cfgs = field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("cfg"));
+        let size = field.primitive.align();
+        let align_msg = format!("'{}' is not aligned to its size, {size}", field.ident);
+        let len_msg = format!(
+            "'{}' ends past the {len:#x} bytes of the block",
+            field.ident
+        );
+        let (size, extent, len) = (lit(size), lit(field.extent()), lit(len));
+        quote! {
+            #(#cfgs)*
+            const _: () = {
+                let offset = #ident::#offset_ident;
+                ::core::assert!((#ident::BASE + offset) % #size == 0, #align_msg);
+                ::core::assert!(offset + #extent <= #len, #len_msg);
+            };
+        }
+    });
+    quote! {
+        #base_assert
+        #(#field_asserts)*
+    }
+}
+
+/// Generates the types of a block.
+pub(crate) fn expand(
+    IoTypeItem {
+        attrs,
+        peripheral,
+        view,
+        vis,
+        ident,
+        base_adr,
+        len,
+        mirrors,
+        body,
+    }: IoTypeItem,
+) -> proc_macro2::TokenStream {
+    let peripheral_ident = format_ident!("{}Peripheral", pascal_case(&ident.to_string()));
+    let asserts = deferred_asserts(&ident, &base_adr, len, &body);
+
+    let mut fields = vec![];
+    let mut blocks = vec![];
+    // The block also dumps its sub-blocks, in the order they are declared.
+    let mut dumps = vec![];
+    let mut accessors = vec![];
+    for item in body {
+        match item {
+            IoItem::Field(field) => {
+                dumps.extend(dump_statements(
+                    std::slice::from_ref(&field),
+                    &quote!(#ident),
+                    "",
+                ));
+                fields.push(field);
             }
+            IoItem::Reserved(_) => {}
+            IoItem::Block(block) => {
+                let block_ident = format_ident!(
+                    "{}{}",
+                    pascal_case(&ident.to_string()),
+                    pascal_case(&block.ident.to_string())
+                );
+                let cfgs: Vec<_> = block
+                    .attrs
+                    .iter()
+                    .filter(|attr| attr.path().is_ident("cfg"))
+                    .collect();
+                let instances: Vec<_> = match &block.array {
+                    Some(IoArray { count, .. }) => (0..*count)
+                        .map(|index| {
+                            let index = syn::LitInt::new(&index.to_string(), Span::mixed_site());
+                            let prefix = format!("{}[{index}].", block.ident);
+                            (quote!(#block_ident::<#index>), prefix)
+                        })
+                        .collect(),
+                    None => vec![(quote!(#block_ident), format!("{}.", block.ident))],
+                };
+                for (path, prefix) in instances {
+                    let statements = dump_statements(&block.fields, &path, &prefix);
+                    dumps.push(quote! {
+                        #(#cfgs)*
+                        {
+                            #(#statements)*
+                        }
+                    });
+                }
+
+                let accessor = &block.ident;
+                let (block_vis, accessor_vis) = match &block.vis {
+                    Visibility::Inherited => (&vis, quote!(pub)),
+                    block_vis => (block_vis, quote!(#block_vis)),
+                };
+                if peripheral {
+                    let borrowed = format_ident!("{block_ident}Peripheral");
+                    let doc = format!("Borrows the registers of [`{block_ident}`].");
+                    accessors.push(match block.array {
+                        Some(_) => quote! {
+                            #(#cfgs)*
+                            #[doc = #doc]
+                            #accessor_vis fn #accessor<const N: usize>(&mut self) -> #borrowed<'_, N> {
+                                #borrowed { _mark: ::core::marker::PhantomData }
+                            }
+                        },
+                        None => quote! {
+                            #(#cfgs)*
+                            #[doc = #doc]
+                            #accessor_vis fn #accessor(&mut self) -> #borrowed<'_> {
+                                #borrowed { _mark: ::core::marker::PhantomData }
+                            }
+                        },
+                    });
+                } else if view {
+                    let doc = format!("The view of [`{block_ident}`].");
+                    accessors.push(match block.array {
+                        Some(_) => quote! {
+                            #(#cfgs)*
+                            #[doc = #doc]
+                            #accessor_vis fn #accessor<const N: usize>(&self) -> #block_ident<N> {
+                                #block_ident
+                            }
+                        },
+                        None => quote! {
+                            #(#cfgs)*
+                            #[doc = #doc]
+                            #accessor_vis fn #accessor(&self) -> #block_ident {
+                                #block_ident
+                            }
+                        },
+                    });
+                }
 
-            #(#fns)*
+                let offset = syn::LitInt::new(&block.offset.to_string(), Span::mixed_site());
+                let own_path = match block.array {
+                    Some(_) => quote!(#block_ident::<N>),
+                    None => quote!(#block_ident),
+                };
+                blocks.push(block_type(BlockType {
+                    attrs: &block.attrs,
+                    outer: &cfgs,
+                    vis: block_vis,
+                    ident: &block_ident,
+                    array: block.array.as_ref(),
+                    base: if mirrors.is_empty() {
+                        quote!(#ident::BASE + #offset)
+                    } else {
+                        quote!(#ident::PHYS_BASE + #offset)
+                    },
+                    mirrors,
+                    len: block.len(),
+                    fields: &block.fields,
+                    dumps: dump_statements(&block.fields, &own_path, ""),
+                    peripheral: peripheral.then(|| Peripheral::Borrowed {
+                        parent: peripheral_ident.clone(),
+                    }),
+                    view,
+                    accessors: vec![],
+                }));
+            }
         }
     }
-    .into()
+
+    let block = block_type(BlockType {
+        attrs: &attrs,
+        outer: &[],
+        vis: &vis,
+        ident: &ident,
+        array: None,
+        base: base_adr.to_tokens(),
+        mirrors,
+        len,
+        fields: &fields,
+        dumps,
+        peripheral: peripheral.then_some(Peripheral::Owned),
+        view,
+        accessors,
+    });
+    quote! {
+        #block
+        #(#blocks)*
+        #asserts
+    }
 }
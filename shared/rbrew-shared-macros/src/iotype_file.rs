@@ -0,0 +1,342 @@
+//! `iotype_from_file!`, reading a block from a TOML description rather than the inline
+//! syntax. The description is turned into the inline syntax, so it goes through the same
+//! validation and code generation.
+
+use crate::iotype::{expand, IoTypeItem};
+use proc_macro::TokenStream;
+use quote::quote;
+use std::{fmt::Write, path::PathBuf};
+
+type Table = toml::Table;
+
+/// Reads an optional key of `table`, which `as_ty` converts to an `expected` value.
+fn get<'a, T>(
+    table: &'a Table,
+    key: &str,
+    what: &str,
+    expected: &str,
+    as_ty: impl FnOnce(&'a toml::Value) -> Option<T>,
+) -> Result<Option<T>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(value) => as_ty(value)
+            .map(Some)
+            .ok_or_else(|| format!("'{key}' of {what} must be {expected}")),
+    }
+}
+
+/// A number as a literal of the inline syntax, from an integer or a string such as
+/// `"0xffff_ffff_ffff_ffff"` for values TOML integers do not hold, or `"IO_BASE + 0x2000"`
+/// for addresses and offsets naming constants.
+fn number(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::Integer(value) if *value >= 0 => Some(format!("{value:#x}")),
+        toml::Value::String(value) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+/// Fails on keys of `table` other than `keys`, catching their misspellings.
+fn check_keys(table: &Table, keys: &[&str], what: &str) -> Result<(), String> {
+    match table.keys().find(|key| !keys.contains(&key.as_str())) {
+        Some(key) => Err(format!("unknown key '{key}' in {what}")),
+        None => Ok(()),
+    }
+}
+
+fn name<'a>(table: &'a Table, what: &str) -> Result<&'a str, String> {
+    get(table, "name", what, "a string", toml::Value::as_str)?
+        .ok_or_else(|| format!("{what} has no 'name'"))
+}
+
+fn write_doc(out: &mut String, table: &Table, what: &str) -> Result<(), String> {
+    if let Some(doc) = get(table, "doc", what, "a string", toml::Value::as_str)? {
+        for line in doc.lines() {
+            write!(out, "#[doc = {:?}] ", format!(" {line}")).unwrap();
+        }
+    }
+    Ok(())
+}
+
+/// Writes `#[critical]` and `#[split]` for `critical = true` and `split = true` in `table`,
+/// and `#[le]` or `#[be]` for `endian = "little"` or `"big"`.
+fn write_flags(out: &mut String, table: &Table, what: &str) -> Result<(), String> {
+    for flag in ["critical", "split"] {
+        if get(table, flag, what, "a boolean", toml::Value::as_bool)? == Some(true) {
+            write!(out, "#[{flag}] ").unwrap();
+        }
+    }
+    match get(table, "endian", what, "a string", toml::Value::as_str)? {
+        Some("little") => out.push_str("#[le] "),
+        Some("big") => out.push_str("#[be] "),
+        Some(_) => return Err(format!("'endian' of {what} must be \"little\" or \"big\"")),
+        None => {}
+    }
+    Ok(())
+}
+
+/// Writes the enumeration of `as` or the flags type of `flags` in `table` the value is read
+/// as.
+fn write_value_type(out: &mut String, table: &Table, what: &str) -> Result<(), String> {
+    let enum_ty = get(table, "as", what, "a string", toml::Value::as_str)?;
+    let flags_ty = get(table, "flags", what, "a string", toml::Value::as_str)?;
+    match (enum_ty, flags_ty) {
+        (Some(_), Some(_)) => return Err(format!("{what} has both 'as' and 'flags'")),
+        (Some(enum_ty), None) => write!(out, " as {enum_ty}").unwrap(),
+        (None, Some(flags_ty)) => write!(out, " flags {flags_ty}").unwrap(),
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+fn write_bits(out: &mut String, bits: &Table, register: &str) -> Result<(), String> {
+    let what = format!("a bit range of '{register}'");
+    check_keys(bits, &["name", "doc", "bits", "as", "flags"], &what)?;
+    write_doc(out, bits, &what)?;
+    let range = get(
+        bits,
+        "bits",
+        &what,
+        "a bit or a range",
+        |value| match value {
+            toml::Value::Integer(bit) => Some(bit.to_string()),
+            toml::Value::String(range) => Some(range.clone()),
+            _ => None,
+        },
+    )?
+    .ok_or_else(|| format!("{what} has no 'bits'"))?;
+    write!(out, "{}: {range}", name(bits, &what)?).unwrap();
+    write_value_type(out, bits, &what)?;
+    out.push_str(", ");
+    Ok(())
+}
+
+/// Writes the registers and sub-blocks of `registers`, those with registers of their own
+/// being sub-blocks.
+fn write_registers(out: &mut String, registers: &[toml::Value]) -> Result<(), String> {
+    for register in registers {
+        let register = register
+            .as_table()
+            .ok_or_else(|| "registers must be tables".to_string())?;
+        if let Some(range) = get(
+            register,
+            "reserved",
+            "a register",
+            "a range",
+            toml::Value::as_str,
+        )? {
+            check_keys(register, &["reserved", "doc"], "a reserved range")?;
+            write_doc(out, register, "a reserved range")?;
+            write!(out, "reserved {range}, ").unwrap();
+            continue;
+        }
+        let what = format!("'{}'", name(register, "a register")?);
+        write_doc(out, register, &what)?;
+        write_flags(out, register, &what)?;
+        let count = get(register, "count", &what, "a number", number)?;
+        let offset = get(register, "offset", &what, "a number", number)?;
+        let stride = get(register, "stride", &what, "a number", number)?;
+        if let Some(vis) = get(
+            register,
+            "visibility",
+            &what,
+            "a string",
+            toml::Value::as_str,
+        )? {
+            write!(out, "{vis} ").unwrap();
+        }
+        write!(out, "{}: ", name(register, &what)?).unwrap();
+
+        if let Some(fields) = get(
+            register,
+            "registers",
+            &what,
+            "a list",
+            toml::Value::as_array,
+        )? {
+            check_keys(
+                register,
+                &[
+                    "name",
+                    "doc",
+                    "visibility",
+                    "critical",
+                    "split",
+                    "endian",
+                    "count",
+                    "offset",
+                    "stride",
+                    "registers",
+                ],
+                &what,
+            )?;
+            match count {
+                Some(count) => write!(out, "[block; {count}]").unwrap(),
+                None => out.push_str("block"),
+            }
+            if let Some(offset) = offset {
+                write!(out, " = {offset}").unwrap();
+            }
+            if let Some(stride) = stride {
+                write!(out, " stride {stride}").unwrap();
+            }
+            out.push_str(" { ");
+            write_registers(out, fields)?;
+            out.push_str("}, ");
+            continue;
+        }
+
+        check_keys(
+            register,
+            &[
+                "name",
+                "doc",
+                "visibility",
+                "critical",
+                "split",
+                "endian",
+                "access",
+                "type",
+                "count",
+                "offset",
+                "stride",
+                "reset",
+                "as",
+                "flags",
+                "bits",
+            ],
+            &what,
+        )?;
+        let access = match get(register, "access", &what, "a string", toml::Value::as_str)? {
+            Some("read-write") => "mut",
+            Some("read-only") => "const",
+            Some("write-only") => "wo",
+            _ => {
+                return Err(format!(
+                    "'access' of {what} must be \"read-write\", \"read-only\" or \"write-only\""
+                ))
+            }
+        };
+        let ty = get(register, "type", &what, "a string", toml::Value::as_str)?
+            .ok_or_else(|| format!("{what} has no 'type'"))?;
+        match count {
+            Some(count) => write!(out, "{access} [{ty}; {count}]").unwrap(),
+            None => write!(out, "{access} {ty}").unwrap(),
+        }
+        write_value_type(out, register, &what)?;
+        if let Some(offset) = offset {
+            write!(out, " = {offset}").unwrap();
+        }
+        if let Some(stride) = stride {
+            write!(out, " stride {stride}").unwrap();
+        }
+        if let Some(reset) = get(register, "reset", &what, "a number", number)? {
+            write!(out, " reset {reset}").unwrap();
+        }
+        if let Some(bits) = get(register, "bits", &what, "a list", toml::Value::as_array)? {
+            out.push_str(" { ");
+            for bits in bits {
+                let bits = bits
+                    .as_table()
+                    .ok_or_else(|| format!("the bit ranges of {what} must be tables"))?;
+                write_bits(out, bits, name(register, &what)?)?;
+            }
+            out.push('}');
+        }
+        out.push_str(", ");
+    }
+    Ok(())
+}
+
+/// Turns the description of a block into the inline syntax.
+fn inline_syntax(text: &str) -> Result<String, String> {
+    let block: Table = text
+        .parse()
+        .map_err(|err| format!("not valid TOML: {err}"))?;
+    let what = "the block";
+    check_keys(
+        &block,
+        &[
+            "name",
+            "doc",
+            "visibility",
+            "address",
+            "len",
+            "cached",
+            "uncached",
+            "peripheral",
+            "view",
+            "critical",
+            "split",
+            "endian",
+            "registers",
+        ],
+        what,
+    )?;
+    let mut out = String::new();
+    write_doc(&mut out, &block, what)?;
+    if get(
+        &block,
+        "peripheral",
+        what,
+        "a boolean",
+        toml::Value::as_bool,
+    )? == Some(true)
+    {
+        out.push_str("#[peripheral] ");
+    }
+    if get(&block, "view", what, "a boolean", toml::Value::as_bool)? == Some(true) {
+        out.push_str("#[view] ");
+    }
+    write_flags(&mut out, &block, what)?;
+    let vis = get(&block, "visibility", what, "a string", toml::Value::as_str)?.unwrap_or("pub");
+    let address =
+        get(&block, "address", what, "a number", number)?.ok_or("the block has no 'address'")?;
+    let len = get(&block, "len", what, "a number", number)?.ok_or("the block has no 'len'")?;
+    write!(out, "{vis} type {}: {address}, {len}", name(&block, what)?).unwrap();
+    for mirror in ["cached", "uncached"] {
+        if let Some(offset) = get(&block, mirror, what, "a number", number)? {
+            write!(out, ", {mirror} {offset}").unwrap();
+        }
+    }
+    out.push_str(" { ");
+    if let Some(registers) = get(&block, "registers", what, "a list", toml::Value::as_array)? {
+        write_registers(&mut out, registers)?;
+    }
+    out.push('}');
+    Ok(out)
+}
+
+pub fn iotype_from_file2(ts: TokenStream) -> TokenStream {
+    let lit: syn::LitStr = match syn::parse(ts) {
+        Ok(lit) => lit,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let error = |msg: String| {
+        syn::Error::new(lit.span(), format!("{}: {msg}", lit.value()))
+            .to_compile_error()
+            .into()
+    };
+
+    let dir = std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default();
+    let path = PathBuf::from(dir).join(lit.value());
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) => return error(err.to_string()),
+    };
+    let item: IoTypeItem = match inline_syntax(&text)
+        .and_then(|syntax| syn::parse_str(&syntax).map_err(|err| err.to_string()))
+    {
+        Ok(item) => item,
+        Err(err) => return error(err),
+    };
+
+    let block = expand(item);
+    let path = path.to_string_lossy();
+    quote! {
+        #block
+        // Rebuilds the crate when the description changes.
+        const _: &[u8] = include_bytes!(#path);
+    }
+    .into()
+}
@@ -1,8 +1,152 @@
 use proc_macro::TokenStream;
 
+mod descriptor;
+mod entry;
+mod interrupt;
+mod io_enum;
+mod ioflags;
 mod iotype;
+mod iotype_file;
 
 #[proc_macro]
 pub fn iotype(ts: TokenStream) -> TokenStream {
     iotype::iotype2(ts)
 }
+
+/// Declares a set of flags held in an unsigned integer, which `iotype!` fields and bit
+/// ranges are read and written as with `flags Type` in place of `as Enum`, so registers
+/// holding the same flags share them:
+///
+/// ```ignore
+/// ioflags! {
+///     /// The enable and status of a display interrupt.
+///     pub struct DisplayInterrupt: u32 {
+///         const ENABLE = 1 << 28;
+///         const STATUS = 1 << 31;
+///     }
+/// }
+///
+/// iotype! {
+///     pub type VI: 0x0c002000, 0x100 {
+///         di0: mut u32 flags DisplayInterrupt = 0x30,
+///         di1: mut u32 flags DisplayInterrupt,
+///     }
+/// }
+/// ```
+///
+/// The type has the operators and methods of a bitflags type, and keeps the bits no flag
+/// names, so writing back what was read leaves the rest of the register unchanged.
+#[proc_macro]
+pub fn ioflags(ts: TokenStream) -> TokenStream {
+    ioflags::ioflags2(ts)
+}
+
+/// Declares a block like `iotype!`, reading it from a TOML file relative to the crate's
+/// manifest, so register maps can be shared between crates:
+///
+/// ```toml
+/// name = "VI"
+/// doc = "The video interface."
+/// # Addresses and offsets may be strings of constant expressions, "IO_BASE + 0x2000".
+/// address = 0x0c002000
+/// len = 0x100
+/// # Optional, like `peripheral = true` and `view = true`, and `visibility = "pub(crate)"`,
+/// # `critical = true`, `split = true` and `endian = "little"` or `"big"`, which registers
+/// # and sub-blocks take too.
+/// cached = 0x8000_0000
+/// uncached = 0xc000_0000
+///
+/// [[registers]]
+/// name = "dcr"
+/// access = "read-write" # or "read-only", "write-only"
+/// type = "u16"
+/// # Optional, like `count`, `stride`, `reset`, and `as` or `flags`, which bit ranges
+/// # take too; without an offset the register follows the previous one.
+/// offset = 0x02
+/// bits = [
+///     { name = "enable", bits = 0 },
+///     { name = "format", bits = "8..=9", as = "VideoFormat" },
+/// ]
+///
+/// [[registers]]
+/// reserved = "0x04..0x10"
+///
+/// # Entries with registers of their own are sub-blocks.
+/// [[registers]]
+/// name = "channel"
+/// count = 3
+/// registers = [{ name = "csr", access = "read-write", type = "u32" }]
+/// ```
+#[proc_macro]
+pub fn iotype_from_file(ts: TokenStream) -> TokenStream {
+    iotype_file::iotype_from_file2(ts)
+}
+
+/// Registers a `fn()` as the handler of an interrupt source, given as its number or
+/// the platform's enum of sources. The handler is placed in the table the platform's
+/// dispatcher reads, so it is called whenever the source raises the interrupt:
+///
+/// ```ignore
+/// use rbrew_gc::interrupt::Source;
+///
+/// #[interrupt(Source::Vi)]
+/// fn retrace() {
+///     // Acknowledge the interrupt at the device.
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn interrupt(attr: TokenStream, item: TokenStream) -> TokenStream {
+    interrupt::interrupt2(attr, item)
+}
+
+/// Marks the function the platform's runtime calls once it has set up the stack, cleared
+/// the bss and initialized the hardware, in place of `main`. It takes no arguments and
+/// returns nothing, or never returns:
+///
+/// ```ignore
+/// use rbrew_gc::runtime::entry;
+///
+/// #[entry]
+/// fn main() -> ! {
+///     loop {}
+/// }
+/// ```
+///
+/// A program has a single entry point, which is exported as `__rbrew_entry`.
+#[proc_macro_attribute]
+pub fn entry(attr: TokenStream, item: TokenStream) -> TokenStream {
+    entry::entry2(attr, item)
+}
+
+/// Implements `rbrew_shared::io::IoEnum` for a fieldless enum, so `iotype!` fields can be
+/// read and written as it.
+#[proc_macro_derive(IoEnum)]
+pub fn derive_io_enum(ts: TokenStream) -> TokenStream {
+    io_enum::derive_io_enum2(ts)
+}
+
+/// Generates volatile accessors and byte order aware conversions for a `#[repr(C)]`
+/// structure the hardware reads or writes, like a DMA descriptor, and implements
+/// `rbrew_shared::io::IoValue` for it so descriptors can contain descriptors.
+///
+/// Each field `x` gets `x_read` and `x_write` with the field's visibility, except fields
+/// starting with an underscore, which are padding. `to_bytes` and `from_bytes` convert the
+/// structure from and to the bytes the hardware sees, in the byte order given by
+/// `endian`, the target's by default. `size` and `align` are checked at compile time:
+///
+/// ```ignore
+/// #[derive(Clone, Copy, Descriptor)]
+/// #[repr(C, align(32))]
+/// #[descriptor(size = 32, align = 32, endian = "big")]
+/// struct DmaBlock {
+///     address: u32,
+///     len: u32,
+///     _pad: [u8; 24],
+/// }
+/// ```
+///
+/// Every field must implement `IoValue`, which integers, floats and arrays of them do.
+#[proc_macro_derive(Descriptor, attributes(descriptor))]
+pub fn derive_descriptor(ts: TokenStream) -> TokenStream {
+    descriptor::derive_descriptor2(ts)
+}
@@ -0,0 +1,79 @@
+//! The layout of the build information slot filled in by `rbrew build --build-info`.
+//!
+//! The slot is a fixed-size blob starting with [`MAGIC`], followed by NUL-terminated
+//! `key=value` strings and zero padding. Being plain text, it reads the same on every
+//! platform regardless of byte order.
+
+/// The section the runtime places the slot in.
+pub const SECTION: &str = ".rbrew.build_info";
+pub const MAGIC: [u8; 8] = *b"RBREWBI\0";
+pub const SLOT_SIZE: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BuildInfo<'a> {
+    pub git_hash: &'a str,
+    pub timestamp: &'a str,
+    pub profile: &'a str,
+    pub rbrew_version: &'a str,
+}
+
+impl<'a> BuildInfo<'a> {
+    /// An unfilled slot, as the runtime reserves it.
+    pub const fn empty_slot() -> [u8; SLOT_SIZE] {
+        let mut slot = [0; SLOT_SIZE];
+        let mut i = 0;
+        while i < MAGIC.len() {
+            slot[i] = MAGIC[i];
+            i += 1;
+        }
+        slot
+    }
+
+    /// Parses a slot starting at its magic. Returns `None` if the slot was never filled.
+    pub fn parse(slot: &'a [u8]) -> Option<Self> {
+        let body = slot.strip_prefix(&MAGIC)?;
+        let mut info = BuildInfo {
+            git_hash: "",
+            timestamp: "",
+            profile: "",
+            rbrew_version: "",
+        };
+        let mut any = false;
+        for entry in body.split(|&b| b == 0).take_while(|e| !e.is_empty()) {
+            let entry = core::str::from_utf8(entry).ok()?;
+            let (key, value) = entry.split_once('=')?;
+            match key {
+                "git" => info.git_hash = value,
+                "time" => info.timestamp = value,
+                "profile" => info.profile = value,
+                "version" => info.rbrew_version = value,
+                _ => continue,
+            }
+            any = true;
+        }
+        any.then_some(info)
+    }
+
+    /// Encodes the information into a slot. Returns `false` if it does not fit.
+    pub fn encode(&self, slot: &mut [u8; SLOT_SIZE]) -> bool {
+        *slot = Self::empty_slot();
+        let mut pos = MAGIC.len();
+        for (key, value) in [
+            ("git", self.git_hash),
+            ("time", self.timestamp),
+            ("profile", self.profile),
+            ("version", self.rbrew_version),
+        ] {
+            let len = key.len() + 1 + value.len() + 1;
+            // Keep a final NUL so parsing always terminates inside the slot.
+            if pos + len >= SLOT_SIZE {
+                return false;
+            }
+            slot[pos..pos + key.len()].copy_from_slice(key.as_bytes());
+            slot[pos + key.len()] = b'=';
+            slot[pos + key.len() + 1..pos + len - 1].copy_from_slice(value.as_bytes());
+            pos += len;
+        }
+        true
+    }
+}
@@ -1 +1,4 @@
 #![no_std]
+
+pub mod build_info;
+pub mod romfs;
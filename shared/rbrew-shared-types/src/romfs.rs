@@ -0,0 +1,159 @@
+//! The layout of the read-only filesystem packed by `rbrew build --romfs`, and of the slot
+//! the program finds it through.
+//!
+//! The image starts with [`MAGIC`] and the number of files, then an entry of
+//! [`ENTRY_SIZE`] bytes for each file, sorted by path: the offsets in the image of its path
+//! and of its data, and their lengths. Paths are relative to the packed directory, with
+//! `/` separators. The data of each file is [`DATA_ALIGN`]-byte aligned. All integers are
+//! big-endian.
+//!
+//! The slot starts with [`SLOT_MAGIC`], followed by the address and the length of the
+//! image once it is loaded, which stay zero if the program was built without one.
+
+/// The section the runtime places the slot in.
+pub const SECTION: &str = ".rbrew.romfs";
+pub const SLOT_MAGIC: [u8; 8] = *b"RBREWRS\0";
+pub const SLOT_SIZE: usize = 16;
+
+pub const MAGIC: [u8; 8] = *b"RBREWFS\0";
+pub const HEADER_SIZE: usize = 16;
+pub const ENTRY_SIZE: usize = 16;
+/// The alignment of the data of each file in the image, and of the image in memory.
+pub const DATA_ALIGN: usize = 32;
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// An unfilled slot, as the runtime reserves it.
+pub const fn empty_slot() -> [u8; SLOT_SIZE] {
+    let mut slot = [0; SLOT_SIZE];
+    let mut i = 0;
+    while i < SLOT_MAGIC.len() {
+        slot[i] = SLOT_MAGIC[i];
+        i += 1;
+    }
+    slot
+}
+
+/// A slot holding the image at `address` of `len` bytes.
+pub fn encode_slot(address: u32, len: u32) -> [u8; SLOT_SIZE] {
+    let mut slot = empty_slot();
+    slot[8..12].copy_from_slice(&address.to_be_bytes());
+    slot[12..16].copy_from_slice(&len.to_be_bytes());
+    slot
+}
+
+/// The address and the length of the image in a slot starting at its magic. Returns
+/// `None` if the slot was never filled.
+pub fn parse_slot(slot: &[u8]) -> Option<(u32, u32)> {
+    if slot.len() < SLOT_SIZE || !slot.starts_with(&SLOT_MAGIC) {
+        return None;
+    }
+    let len = be_u32(slot, 12);
+    (len != 0).then(|| (be_u32(slot, 8), len))
+}
+
+/// A packed image, whose entries were checked to lie within it.
+#[derive(Clone, Copy, Debug)]
+pub struct RomFs<'a> {
+    image: &'a [u8],
+    count: usize,
+}
+
+impl<'a> RomFs<'a> {
+    /// Parses an image starting at its magic. Returns `None` if it is not one, or any
+    /// path or data lies outside it.
+    pub fn parse(image: &'a [u8]) -> Option<Self> {
+        if image.len() < HEADER_SIZE || !image.starts_with(&MAGIC) {
+            return None;
+        }
+        let count = be_u32(image, 8) as usize;
+        let entries_end = count.checked_mul(ENTRY_SIZE)?.checked_add(HEADER_SIZE)?;
+        if entries_end > image.len() {
+            return None;
+        }
+        let fs = Self { image, count };
+        for index in 0..count {
+            let [path, data] = fs.ranges(index);
+            if path.end > image.len() || data.end > image.len() {
+                return None;
+            }
+            core::str::from_utf8(&image[path]).ok()?;
+        }
+        Some(fs)
+    }
+
+    /// The ranges of the path and of the data of the file at `index`.
+    fn ranges(&self, index: usize) -> [core::ops::Range<usize>; 2] {
+        let entry = HEADER_SIZE + index * ENTRY_SIZE;
+        [0, 8].map(|field| {
+            let start = be_u32(self.image, entry + field) as usize;
+            start..start.saturating_add(be_u32(self.image, entry + field + 4) as usize)
+        })
+    }
+
+    /// The number of files.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// The path and the data of the file at `index`, in the order of their paths.
+    pub fn get(&self, index: usize) -> Option<(&'a str, &'a [u8])> {
+        if index >= self.count {
+            return None;
+        }
+        let [path, data] = self.ranges(index);
+        let path = core::str::from_utf8(&self.image[path]).unwrap_or_default();
+        Some((path, &self.image[data]))
+    }
+
+    /// The data of the file at `path`, with or without a leading `/`.
+    pub fn open(&self, path: &str) -> Option<&'a [u8]> {
+        let path = path.trim_start_matches('/');
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let (other, data) = self.get(middle)?;
+            match other.as_bytes().cmp(path.as_bytes()) {
+                core::cmp::Ordering::Less => low = middle + 1,
+                core::cmp::Ordering::Greater => high = middle,
+                core::cmp::Ordering::Equal => return Some(data),
+            }
+        }
+        None
+    }
+
+    /// The paths and the data of the files, in the order of their paths.
+    pub fn files(&self) -> impl Iterator<Item = (&'a str, &'a [u8])> + 'a {
+        let fs = *self;
+        (0..fs.count).filter_map(move |index| fs.get(index))
+    }
+
+    /// The files in the directory `dir` and the directories within it, with their paths
+    /// relative to it.
+    pub fn files_in<'d>(&self, dir: &'d str) -> impl Iterator<Item = (&'a str, &'a [u8])> + 'd
+    where
+        'a: 'd,
+    {
+        let dir = dir.trim_matches('/');
+        self.files().filter_map(move |(path, data)| {
+            if dir.is_empty() {
+                return Some((path, data));
+            }
+            let rest = path.strip_prefix(dir)?.strip_prefix('/')?;
+            Some((rest, data))
+        })
+    }
+}
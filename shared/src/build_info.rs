@@ -0,0 +1,19 @@
+//! Access to the build information embedded by `rbrew build --build-info`.
+
+pub use crate::types::build_info::BuildInfo;
+use crate::types::build_info::SLOT_SIZE;
+
+// The contents are replaced after linking, so the slot must never be read directly or the
+// compiler may fold in the unfilled value.
+#[used]
+#[link_section = ".rbrew.build_info"]
+static SLOT: [u8; SLOT_SIZE] = BuildInfo::empty_slot();
+
+/// Returns the embedded build information, or `None` if the program was built without
+/// `--build-info`.
+///
+/// The slot is only kept in the final binary when this function is referenced.
+pub fn get() -> Option<BuildInfo<'static>> {
+    let slot: &'static [u8; SLOT_SIZE] = core::hint::black_box(&SLOT);
+    BuildInfo::parse(slot)
+}
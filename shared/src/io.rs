@@ -0,0 +1,252 @@
+//! Support for the register blocks declared with [`iotype!`](crate::iotype).
+
+/// The bits read from a register field that match no variant of its enum.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidValue(pub u64);
+
+/// An enum register fields can be read and written as, usually implemented with
+/// `#[derive(IoEnum)]` on a fieldless `#[repr(uN)]` enum.
+pub trait IoEnum: Sized {
+    /// Returns the variant with the discriminant `bits`.
+    fn from_bits(bits: u64) -> Result<Self, InvalidValue>;
+
+    /// Returns the discriminant of the variant.
+    fn into_bits(self) -> u64;
+}
+
+/// A set of flags register fields can be read and written as, declared with
+/// [`ioflags!`](crate::ioflags) so registers holding the same flags share them.
+pub trait IoFlags: Copy {
+    /// Returns the flags set in `bits`, keeping the bits no flag names.
+    fn from_bits(bits: u64) -> Self;
+
+    /// Returns the bits of the flags.
+    fn into_bits(self) -> u64;
+}
+
+/// The address the register at `address` is accessed through, `mirror` being the offset
+/// of its mirror from its physical address. Called by the pointer functions of `iotype!`
+/// blocks.
+#[doc(hidden)]
+#[inline(always)]
+pub fn mmio(address: usize, mirror: usize) -> usize {
+    #[cfg(feature = "mock")]
+    return mock::address(address - mirror);
+    #[cfg(not(feature = "mock"))]
+    {
+        let _ = mirror;
+        address
+    }
+}
+
+/// A mock of the registers, enabled by the `mock` feature, so drivers can be tested on
+/// the host.
+///
+/// Every physical address `iotype!` blocks access is backed by zeroed host memory, the
+/// mirrors of a block sharing it. Each thread has its own memory, so tests running in
+/// parallel do not see each other's writes. The registers are plain memory: tests set the
+/// values the hardware would, and reading back gives the last value written. The halves
+/// `#[split]` registers are accessed as are also recorded, so tests can check their order.
+#[cfg(feature = "mock")]
+pub mod mock {
+    extern crate std;
+
+    use core::cell::RefCell;
+    use std::{boxed::Box, collections::BTreeMap, vec::Vec};
+
+    const PAGE_SIZE: usize = 0x1000;
+
+    std::thread_local! {
+        static PAGES: RefCell<BTreeMap<usize, Box<[u64; PAGE_SIZE / 8]>>> =
+            const { RefCell::new(BTreeMap::new()) };
+        static SPLIT_ACCESSES: RefCell<Vec<SplitAccess>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// A 32-bit access to half of a `#[split]` register, at the address backing the half.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum SplitAccess {
+        Read(usize),
+        Write(usize, u32),
+    }
+
+    /// Returns the address backing the physical address `address` on the current thread.
+    pub fn address(address: usize) -> usize {
+        PAGES.with_borrow_mut(|pages| {
+            let page = pages
+                .entry(address / PAGE_SIZE)
+                .or_insert_with(|| Box::new([0; PAGE_SIZE / 8]));
+            page.as_mut_ptr() as usize + address % PAGE_SIZE
+        })
+    }
+
+    /// Returns the accesses to the halves of `#[split]` registers the current thread made
+    /// since the last call, in order.
+    pub fn take_split_accesses() -> Vec<SplitAccess> {
+        SPLIT_ACCESSES.take()
+    }
+
+    pub(super) fn record(access: SplitAccess) {
+        SPLIT_ACCESSES.with_borrow_mut(|accesses| accesses.push(access));
+    }
+
+    /// Zeroes the registers of the current thread and forgets its accesses.
+    pub fn reset() {
+        PAGES.with_borrow_mut(|pages| pages.values_mut().for_each(|page| page.fill(0)));
+        SPLIT_ACCESSES.take();
+    }
+}
+
+/// Reads the 64-bit register at `ptr` as two 32-bit accesses, for buses without 64-bit
+/// accesses. The low half is read first, then the high half, so registers latching their
+/// high half when the low half is read are read consistently.
+///
+/// # Safety
+///
+/// `ptr` must point to a readable 64-bit register.
+#[inline(always)]
+pub unsafe fn read_split(ptr: *const u64) -> u64 {
+    let (lo, hi) = halves(ptr.cast::<u32>());
+    #[cfg(feature = "mock")]
+    mock::record(mock::SplitAccess::Read(lo as usize));
+    let lo = lo.read_volatile() as u64;
+    #[cfg(feature = "mock")]
+    mock::record(mock::SplitAccess::Read(hi as usize));
+    let hi = hi.read_volatile() as u64;
+    hi << 32 | lo
+}
+
+/// Writes `value` to the 64-bit register at `ptr` as two 32-bit accesses, for buses
+/// without 64-bit accesses. The high half is written first, then the low half, so
+/// registers taking the value when the low half is written get all of it.
+///
+/// # Safety
+///
+/// `ptr` must point to a writable 64-bit register.
+#[inline(always)]
+pub unsafe fn write_split(ptr: *mut u64, value: u64) {
+    let (lo, hi) = halves(ptr.cast::<u32>());
+    #[cfg(feature = "mock")]
+    mock::record(mock::SplitAccess::Write(hi as usize, (value >> 32) as u32));
+    hi.cast_mut().write_volatile((value >> 32) as u32);
+    #[cfg(feature = "mock")]
+    mock::record(mock::SplitAccess::Write(lo as usize, value as u32));
+    lo.cast_mut().write_volatile(value as u32);
+}
+
+/// The low and high halves of the 64-bit register at `ptr`, in the target's byte order.
+#[inline(always)]
+unsafe fn halves(ptr: *const u32) -> (*const u32, *const u32) {
+    if cfg!(target_endian = "big") {
+        (ptr.add(1), ptr)
+    } else {
+        (ptr, ptr.add(1))
+    }
+}
+
+/// A value of the hardware structures `#[derive(Descriptor)]` types describe, stored in
+/// the byte order of the hardware rather than of the target.
+pub trait IoValue: Copy {
+    /// Writes the value to the start of `out`, big-endian if `big_endian` is set.
+    fn store(self, out: &mut [u8], big_endian: bool);
+
+    /// Reads a value from the start of `bytes`, big-endian if `big_endian` is set.
+    fn load(bytes: &[u8], big_endian: bool) -> Self;
+}
+
+macro_rules! impl_io_value {
+    ($($ty:ty)*) => {$(
+        impl IoValue for $ty {
+            fn store(self, out: &mut [u8], big_endian: bool) {
+                let bytes = if big_endian {
+                    self.to_be_bytes()
+                } else {
+                    self.to_le_bytes()
+                };
+                out[..bytes.len()].copy_from_slice(&bytes);
+            }
+
+            fn load(bytes: &[u8], big_endian: bool) -> Self {
+                let bytes = bytes[..core::mem::size_of::<Self>()].try_into().unwrap();
+                if big_endian {
+                    Self::from_be_bytes(bytes)
+                } else {
+                    Self::from_le_bytes(bytes)
+                }
+            }
+        }
+    )*};
+}
+
+impl_io_value!(u8 u16 u32 u64 u128 i8 i16 i32 i64 i128 f32 f64);
+
+impl<T: IoValue, const N: usize> IoValue for [T; N] {
+    fn store(self, out: &mut [u8], big_endian: bool) {
+        let size = core::mem::size_of::<T>();
+        for (index, value) in self.into_iter().enumerate() {
+            value.store(&mut out[index * size..], big_endian);
+        }
+    }
+
+    fn load(bytes: &[u8], big_endian: bool) -> Self {
+        let size = core::mem::size_of::<T>();
+        core::array::from_fn(|index| T::load(&bytes[index * size..], big_endian))
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    const PHYS: usize = 0x0c00_2000;
+
+    fn read(address: usize) -> u32 {
+        unsafe { (address as *const u32).read_volatile() }
+    }
+
+    fn write(address: usize, value: u32) {
+        unsafe { (address as *mut u32).write_volatile(value) }
+    }
+
+    #[test]
+    fn registers_never_written_read_zero() {
+        mock::reset();
+        assert_eq!(read(mmio(PHYS + 0xc000_0000, 0xc000_0000)), 0);
+        assert_eq!(read(mmio(0x0cc0_0ffc, 0)), 0);
+    }
+
+    #[test]
+    fn mirrors_share_the_physical_register() {
+        mock::reset();
+        let uncached = mmio(PHYS + 0xc000_0000, 0xc000_0000);
+        let cached = mmio(PHYS + 0x8000_0000, 0x8000_0000);
+        assert_eq!(uncached, cached);
+        assert_eq!(uncached, mmio(PHYS, 0));
+
+        write(uncached, 0x1234_5678);
+        assert_eq!(read(cached), 0x1234_5678);
+        assert_eq!(read(mmio(PHYS + 4, 0)), 0);
+    }
+
+    #[test]
+    fn reset_zeroes_the_registers() {
+        mock::reset();
+        write(mmio(PHYS, 0), 0xffff_ffff);
+        mock::reset();
+        assert_eq!(read(mmio(PHYS, 0)), 0);
+    }
+
+    #[test]
+    fn threads_have_their_own_registers() {
+        mock::reset();
+        write(mmio(PHYS, 0), 1);
+        std::thread::spawn(|| {
+            assert_eq!(read(mmio(PHYS, 0)), 0);
+            write(mmio(PHYS, 0), 2);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(read(mmio(PHYS, 0)), 1);
+    }
+}
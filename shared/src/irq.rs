@@ -0,0 +1,10 @@
+//! Support for the interrupt handlers registered with [`#[interrupt]`](crate::interrupt).
+
+/// A handler registered by `#[interrupt]`, which places it in the `.rbrew.interrupts`
+/// section the linker script collects for the platform's dispatcher.
+#[repr(C)]
+pub struct Handler {
+    /// The number the platform gives the interrupt source.
+    pub source: u32,
+    pub handler: fn(),
+}
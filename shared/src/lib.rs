@@ -2,6 +2,14 @@
 
 pub extern crate rbrew_shared_types as types;
 
+/// Runs the read-modify-write functions of `#[critical]` registers, the platform crate
+/// providing its `Impl`.
+pub extern crate critical_section;
+
 extern crate rbrew_shared_macros as macros;
 
 pub use macros::*;
+
+pub mod build_info;
+pub mod io;
+pub mod irq;
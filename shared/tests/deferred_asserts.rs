@@ -0,0 +1,8 @@
+#[test]
+fn deferred_layouts_are_checked_at_compile_time() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/ui/deferred_offsets.rs");
+    cases.compile_fail("tests/ui/deferred_base_misaligned.rs");
+    cases.compile_fail("tests/ui/deferred_offset_misaligned.rs");
+    cases.compile_fail("tests/ui/deferred_offset_past_len.rs");
+}
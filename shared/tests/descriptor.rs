@@ -0,0 +1,103 @@
+use core::mem::{align_of, offset_of, size_of};
+use rbrew_shared::Descriptor;
+
+#[derive(Clone, Copy, Debug, PartialEq, Descriptor)]
+#[repr(C)]
+#[descriptor(size = 8, endian = "little")]
+struct Extent {
+    start: u32,
+    len: u16,
+    _pad: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Descriptor)]
+#[repr(C, align(32))]
+#[descriptor(size = 32, align = 32, endian = "big")]
+struct DmaBlock {
+    address: u32,
+    flags: u8,
+    _pad0: [u8; 3],
+    extents: [Extent; 2],
+    next: u32,
+    _pad1: [u8; 4],
+}
+
+#[derive(Clone, Copy, Descriptor)]
+#[repr(C)]
+#[descriptor(size = 8, endian = "big")]
+struct Tagged {
+    tag: u8,
+    value: u32,
+}
+
+fn block() -> DmaBlock {
+    DmaBlock {
+        address: 0x0123_4567,
+        flags: 0x80,
+        _pad0: [0; 3],
+        extents: [
+            Extent {
+                start: 0x89ab_cdef,
+                len: 0x0102,
+                _pad: 0,
+            },
+            Extent {
+                start: 0,
+                len: 0,
+                _pad: 0,
+            },
+        ],
+        next: 0x0000_0020,
+        _pad1: [0; 4],
+    }
+}
+
+#[test]
+fn layout_is_the_declared_one() {
+    assert_eq!(size_of::<DmaBlock>(), 32);
+    assert_eq!(align_of::<DmaBlock>(), 32);
+    assert_eq!(offset_of!(DmaBlock, extents), 8);
+    assert_eq!(offset_of!(DmaBlock, next), 24);
+    assert_eq!(size_of::<Extent>(), 8);
+}
+
+#[test]
+fn to_bytes_uses_the_byte_order_of_each_descriptor() {
+    let bytes = block().to_bytes();
+    assert_eq!(bytes[..8], [0x01, 0x23, 0x45, 0x67, 0x80, 0, 0, 0]);
+    // Nested descriptors with an endian keep theirs.
+    assert_eq!(bytes[8..16], [0xef, 0xcd, 0xab, 0x89, 0x02, 0x01, 0, 0]);
+    assert_eq!(bytes[16..24], [0; 8]);
+    assert_eq!(bytes[24..], [0, 0, 0, 0x20, 0, 0, 0, 0]);
+
+    let extent = block().extents[0].to_bytes();
+    assert_eq!(extent, [0xef, 0xcd, 0xab, 0x89, 0x02, 0x01, 0, 0]);
+}
+
+#[test]
+fn to_bytes_zeroes_the_padding_between_fields() {
+    let tagged = Tagged {
+        tag: 0xaa,
+        value: 0x1122_3344,
+    };
+    assert_eq!(tagged.to_bytes(), [0xaa, 0, 0, 0, 0x11, 0x22, 0x33, 0x44]);
+}
+
+#[test]
+fn from_bytes_reads_back_to_bytes() {
+    let bytes = block().to_bytes();
+    let read = DmaBlock::from_bytes(&bytes);
+    assert_eq!(read.address_read(), 0x0123_4567);
+    assert_eq!(read.extents_read()[0].start_read(), 0x89ab_cdef);
+    assert_eq!(read.to_bytes(), bytes);
+}
+
+#[test]
+fn accessors_read_and_write_the_fields() {
+    let mut block = block();
+    block.next_write(0x40);
+    block.flags_write(0);
+    assert_eq!(block.next_read(), 0x40);
+    assert_eq!(block.flags_read(), 0);
+    assert_eq!(block.address_read(), 0x0123_4567);
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "mock")]
+
+use rbrew_shared::io::mock;
+use rbrew_shared::iotype;
+
+iotype! {
+    pub type DSP: 0x0c00_5000, 0x10 {
+        #[le]
+        little: mut u32 {
+            low: 0..=7,
+        },
+        #[be]
+        big: mut u32 {
+            low: 0..=7,
+        },
+    }
+}
+
+/// The bytes backing the register `ptr` points to, in address order.
+fn bytes(ptr: *mut u32) -> [u8; 4] {
+    unsafe { ptr.cast::<[u8; 4]>().read_volatile() }
+}
+
+#[test]
+fn little_endian_registers_store_the_low_byte_first() {
+    mock::reset();
+    unsafe { DSP::little_write(0x1122_3344) };
+    assert_eq!(bytes(DSP::little_ptr()), [0x44, 0x33, 0x22, 0x11]);
+    assert_eq!(unsafe { DSP::little_read() }, 0x1122_3344);
+}
+
+#[test]
+fn big_endian_registers_store_the_high_byte_first() {
+    mock::reset();
+    unsafe { DSP::big_write(0x1122_3344) };
+    assert_eq!(bytes(DSP::big_ptr()), [0x11, 0x22, 0x33, 0x44]);
+    assert_eq!(unsafe { DSP::big_read() }, 0x1122_3344);
+}
+
+#[test]
+fn bit_ranges_are_numbered_in_the_swapped_value() {
+    mock::reset();
+    unsafe {
+        DSP::little_low_write(0xaa);
+        DSP::big_low_write(0xaa);
+    }
+    assert_eq!(bytes(DSP::little_ptr()), [0xaa, 0, 0, 0]);
+    assert_eq!(bytes(DSP::big_ptr()), [0, 0, 0, 0xaa]);
+}
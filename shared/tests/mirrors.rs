@@ -0,0 +1,50 @@
+#![cfg(feature = "mock")]
+
+use rbrew_shared::io::mock;
+use rbrew_shared::iotype;
+
+iotype! {
+    pub type AI: 0x0c00_6c00, 0x20, cached 0x8000_0000, uncached 0xc000_0000 {
+        control: mut u32,
+        volume: mut u32,
+        channel: [block; 2] = 0x10 stride 0x08 {
+            count: const u32,
+        },
+    }
+}
+
+iotype! {
+    pub type SRAM: 0x0400_0000, 0x10, uncached 0xa000_0000 {
+        data: mut u32,
+    }
+}
+
+#[test]
+fn bases_are_offset_by_the_mirrors() {
+    assert_eq!(AI::PHYS_BASE, 0x0c00_6c00);
+    assert_eq!(AI::BASE, 0xcc00_6c00);
+    assert_eq!(AI::CACHED_BASE, 0x8c00_6c00);
+    assert_eq!(AiChannel::<1>::PHYS_BASE, 0x0c00_6c18);
+    assert_eq!(AiChannel::<1>::BASE, 0xcc00_6c18);
+
+    assert_eq!(SRAM::PHYS_BASE, 0x0400_0000);
+    assert_eq!(SRAM::BASE, 0xa400_0000);
+}
+
+#[test]
+fn registers_are_addressed_in_the_uncached_mirror() {
+    assert_eq!(AI::VOLUME_OFFSET, 0x04);
+    assert_eq!(AI::VOLUME_ADDR, 0xcc00_6c04);
+    assert_eq!(AiChannel::<1>::COUNT_OFFSET, 0x00);
+    assert_eq!(AiChannel::<1>::COUNT_ADDR, 0xcc00_6c18);
+    assert_eq!(SRAM::DATA_ADDR, 0xa400_0000);
+}
+
+#[test]
+fn mirrors_reach_the_same_register() {
+    mock::reset();
+    unsafe { AI::volume_ptr_uncached().write_volatile(0xff) };
+    assert_eq!(unsafe { AI::volume_ptr_cached().read_volatile() }, 0xff);
+    assert_eq!(unsafe { AI::volume_read() }, 0xff);
+    assert_eq!(AI::volume_ptr(), AI::volume_ptr_uncached());
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "mock")]
+
+use rbrew_shared::io::mock;
+
+mod regs {
+    use rbrew_shared::iotype;
+
+    iotype! {
+        #[peripheral]
+        pub type UART: 0x0d00_6800, 0x40 {
+            data: mut u32,
+            channel: [block; 2] = 0x10 stride 0x10 {
+                csr: mut u32 {
+                    enable: 0,
+                },
+            },
+        }
+    }
+
+    /// The pointer functions are only visible here, in the module declaring the block.
+    pub fn addresses() -> (usize, usize) {
+        (
+            UART::data_ptr() as usize,
+            UartChannel::<1>::csr_ptr() as usize,
+        )
+    }
+}
+
+use regs::UartPeripheral;
+
+#[test]
+fn peripheral_is_taken_once() {
+    assert!(UartPeripheral::take().is_some());
+    assert!(UartPeripheral::take().is_none());
+}
+
+#[test]
+fn registers_are_accessed_through_the_peripheral() {
+    mock::reset();
+    let mut uart = unsafe { UartPeripheral::steal() };
+    unsafe {
+        uart.data_write(0x41);
+        uart.channel::<1>().csr_enable_write(true);
+    }
+    assert_eq!(unsafe { uart.data_read() }, 0x41);
+    assert!(unsafe { uart.channel::<1>().csr_enable_read() });
+    assert!(!unsafe { uart.channel::<0>().csr_enable_read() });
+
+    let (data, csr) = regs::addresses();
+    assert_eq!(unsafe { (data as *const u32).read() }, 0x41);
+    assert_eq!(unsafe { (csr as *const u32).read() }, 1);
+}
+
+#[test]
+fn pointers_are_private_to_the_module() {
+    trybuild::TestCases::new().compile_fail("tests/ui/peripheral_ptr.rs");
+}
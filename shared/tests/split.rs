@@ -0,0 +1,60 @@
+#![cfg(feature = "mock")]
+
+use rbrew_shared::io::mock::{self, SplitAccess};
+use rbrew_shared::iotype;
+
+iotype! {
+    pub type TIMER: 0x0d80_0000, 0x10 {
+        #[split]
+        count: mut u64,
+        compare: mut u64,
+    }
+}
+
+/// The addresses of the low and high halves of `TIMER::count`.
+fn halves() -> (usize, usize) {
+    let ptr = TIMER::count_ptr() as usize;
+    if cfg!(target_endian = "big") {
+        (ptr + 4, ptr)
+    } else {
+        (ptr, ptr + 4)
+    }
+}
+
+#[test]
+fn writes_the_high_half_first() {
+    mock::reset();
+    let (lo, hi) = halves();
+    unsafe { TIMER::count_write(0x1122_3344_5566_7788) };
+    assert_eq!(
+        mock::take_split_accesses(),
+        [
+            SplitAccess::Write(hi, 0x1122_3344),
+            SplitAccess::Write(lo, 0x5566_7788)
+        ]
+    );
+    assert_eq!(
+        unsafe { TIMER::count_ptr().read_volatile() },
+        0x1122_3344_5566_7788
+    );
+}
+
+#[test]
+fn reads_the_low_half_first() {
+    mock::reset();
+    let (lo, hi) = halves();
+    unsafe { TIMER::count_ptr().write_volatile(0x1122_3344_5566_7788) };
+    assert_eq!(unsafe { TIMER::count_read() }, 0x1122_3344_5566_7788);
+    assert_eq!(
+        mock::take_split_accesses(),
+        [SplitAccess::Read(lo), SplitAccess::Read(hi)]
+    );
+}
+
+#[test]
+fn registers_not_split_are_accessed_whole() {
+    mock::reset();
+    unsafe { TIMER::compare_write(1) };
+    assert_eq!(unsafe { TIMER::compare_read() }, 1);
+    assert_eq!(mock::take_split_accesses(), []);
+}
@@ -0,0 +1,9 @@
+const IO_BASE: usize = 0x0c00_0000;
+
+rbrew_shared::iotype! {
+    pub type DSP: IO_BASE + 0x5002, 0x40 {
+        mbox: mut u32,
+    }
+}
+
+fn main() {}
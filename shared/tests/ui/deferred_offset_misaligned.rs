@@ -0,0 +1,9 @@
+const MAILBOX: usize = 0x10;
+
+rbrew_shared::iotype! {
+    pub type DSP: 0x0c00_5000, 0x40 {
+        mbox: mut u32 = MAILBOX + 2,
+    }
+}
+
+fn main() {}
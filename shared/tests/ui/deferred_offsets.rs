@@ -0,0 +1,15 @@
+const IO_BASE: usize = 0x0c00_0000;
+const MAILBOX: usize = 0x10;
+
+rbrew_shared::iotype! {
+    pub type DSP: IO_BASE + 0x5000, 0x40 {
+        mbox: mut u32 = MAILBOX,
+        control: mut u16 = MAILBOX + 0x0a,
+        last: mut u32 = 0x40 - 4,
+    }
+}
+
+fn main() {
+    assert_eq!(DSP::MBOX_ADDR, 0x0c00_5010);
+    assert_eq!(DSP::CONTROL_ADDR, 0x0c00_501a);
+}
@@ -0,0 +1,12 @@
+mod regs {
+    rbrew_shared::iotype! {
+        #[peripheral]
+        pub type UART: 0x0d00_6800, 0x40 {
+            data: mut u32,
+        }
+    }
+}
+
+fn main() {
+    let _ = regs::UART::data_ptr();
+}
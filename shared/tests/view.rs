@@ -0,0 +1,46 @@
+#![cfg(feature = "mock")]
+
+use rbrew_shared::io::mock;
+use rbrew_shared::iotype;
+
+iotype! {
+    #[view]
+    pub type SI: 0x0c00_6400, 0x40 {
+        channel: [block; 4] = 0x00 stride 0x0c {
+            out: mut u32,
+            status: const u32 {
+                ready: 0,
+            },
+        },
+        poll: mut u32 {
+            enable: 4..=7,
+        },
+    }
+}
+
+/// Views are values, passed to the code driving the registers.
+fn enable_all(si: SI) {
+    unsafe { si.poll_enable_write(0xf) };
+}
+
+#[test]
+fn views_are_copied_unit_values() {
+    mock::reset();
+    assert_eq!(core::mem::size_of::<SI>(), 0);
+    let si = SI;
+    let copy = si;
+    enable_all(si);
+    assert_eq!(unsafe { copy.poll_enable_read() }, 0xf);
+}
+
+#[test]
+fn sub_blocks_are_views_too() {
+    mock::reset();
+    let channel = SI.channel::<2>();
+    unsafe { channel.out_write(0x0040_0300) };
+    assert_eq!(unsafe { SI.channel::<2>().out_read() }, 0x0040_0300);
+    assert_eq!(unsafe { SI.channel::<1>().out_read() }, 0);
+
+    unsafe { SiChannel::<3>::status_ptr().cast_mut().write(1) };
+    assert!(unsafe { SI.channel::<3>().status_ready_read() });
+}
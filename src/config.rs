@@ -0,0 +1,49 @@
+//! Reading of the project's `rbrew.toml`.
+
+use std::{io, path::Path};
+
+/// The project configuration file, read from the current directory.
+pub const RBREW_TOML: &str = "rbrew.toml";
+
+pub fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Reads a top-level table of `rbrew.toml`, returning `None` if the file or the table
+/// does not exist.
+pub fn load_table(key: &str) -> io::Result<Option<toml::Table>> {
+    let path = Path::new(RBREW_TOML);
+    let text = match std::fs::read_to_string(path) {
+        Ok(ok) => ok,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    let mut table: toml::Table = text
+        .parse()
+        .map_err(|err| invalid(format!("'{RBREW_TOML}' is not valid TOML: {err}")))?;
+    match table.remove(key) {
+        Some(toml::Value::Table(table)) => Ok(Some(table)),
+        Some(_) => Err(invalid(format!("'{key}' must be a table"))),
+        None => Ok(None),
+    }
+}
+
+/// Parses a size or address given as an integer or as a string such as `"0x80800000"`,
+/// `"512K"` or `"2M"`.
+pub fn parse_size(value: &toml::Value) -> Option<u64> {
+    let s = match value {
+        toml::Value::Integer(int) => return u64::try_from(*int).ok(),
+        toml::Value::String(str) => str.trim(),
+        _ => return None,
+    };
+    let (digits, unit) = match s.as_bytes().last()? {
+        b'K' | b'k' => (&s[..s.len() - 1], 1 << 10),
+        b'M' | b'm' => (&s[..s.len() - 1], 1 << 20),
+        _ => (s, 1),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    value.checked_mul(unit)
+}
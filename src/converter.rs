@@ -0,0 +1,152 @@
+//! Conversion of linked ELFs to the output types selected with `--output-type`.
+//!
+//! Every output type is an [`OutputConverter`] held by a [`Registry`]. rbrew's own are
+//! registered by [`Registry::builtin`]; programs using rbrew as a library can register
+//! further converters, or replace built-in ones by name, and pass the registry to
+//! [`run_with`](crate::run_with).
+
+use std::{
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+};
+
+mod builtin;
+
+pub use crate::{platform::Platform, tools::fixup::Fixup};
+
+/// An executable to convert.
+pub struct ConvertInput<'a> {
+    pub platform: &'a Platform,
+    /// The linked ELF.
+    pub elf: &'a Path,
+    /// Where the output goes, as chosen by [`OutputConverter::output_path`].
+    pub output: &'a Path,
+    /// The name of the output, which converters use as the title where the output type
+    /// has one.
+    pub name: &'a str,
+    /// The position of the executable among those built, counting from zero.
+    pub index: usize,
+}
+
+/// Options of the build shared by every conversion.
+pub struct ConvertOptions {
+    /// The linked ARM7 binary for NDS ROMs.
+    pub arm7: Option<PathBuf>,
+    /// The linked main executable REL modules import symbols from.
+    pub rel_base: Option<PathBuf>,
+    /// Module id of the first REL module.
+    pub rel_module_id: u32,
+    /// Whether converters print what they did beyond writing the output.
+    pub report: bool,
+}
+
+/// A file a converter wrote, which fixups and the size budget are applied to.
+pub struct Artifact {
+    /// The ELF the file was converted from.
+    pub elf: PathBuf,
+    pub path: PathBuf,
+}
+
+/// Converts linked ELFs to one output type.
+///
+/// [`convert`](Self::convert) is called for every executable built in order, and
+/// [`finish`](Self::finish) once after all of them, for output types that are written
+/// or checked together.
+pub trait OutputConverter {
+    /// The name `--output-type` selects the converter by.
+    fn name(&self) -> &str;
+
+    /// What outputs are called in messages, such as "N64 ROM".
+    fn description(&self) -> &str;
+
+    /// The extension of output files, or an empty string for none.
+    fn extension(&self) -> &str;
+
+    /// Whether the converter works for the platform, by default if the platform lists
+    /// it among its outputs.
+    fn supports(&self, platform: &Platform) -> bool {
+        platform.outputs.iter().any(|output| output == self.name())
+    }
+
+    /// Extra rustc flags required to produce an ELF suitable for the output type.
+    fn rustflags(&self) -> &[&str] {
+        &[]
+    }
+
+    /// Whether the output is converted from partially linked objects, which the layout
+    /// validation has to allow for.
+    fn relocatable(&self) -> bool {
+        false
+    }
+
+    /// Where the output named `name` is written, in `directory`.
+    fn output_path(&self, directory: &Path, name: &OsStr) -> PathBuf {
+        directory.join(name).with_extension(self.extension())
+    }
+
+    /// Fixups applied to every artifact after conversion, see [`Fixup`].
+    fn fixups(&self) -> &'static [Fixup] {
+        &[]
+    }
+
+    fn convert(
+        &mut self,
+        input: &ConvertInput,
+        options: &ConvertOptions,
+    ) -> io::Result<Vec<Artifact>>;
+
+    fn finish(&mut self, _options: &ConvertOptions) -> io::Result<Vec<Artifact>> {
+        Ok(vec![])
+    }
+}
+
+/// The output converters available to `rbrew build`.
+#[derive(Default)]
+pub struct Registry {
+    converters: Vec<Box<dyn OutputConverter>>,
+}
+
+impl Registry {
+    /// A registry without any converters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry with every output type rbrew supports.
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        builtin::register(&mut registry);
+        registry
+    }
+
+    /// Adds a converter, replacing the one registered under the same name if any.
+    pub fn register(&mut self, converter: impl OutputConverter + 'static) -> &mut Self {
+        let converter = Box::new(converter);
+        match self.position(converter.name()) {
+            Some(index) => self.converters[index] = converter,
+            None => self.converters.push(converter),
+        }
+        self
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.converters
+            .iter()
+            .position(|converter| converter.name() == name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn OutputConverter> {
+        self.position(name).map(|index| &*self.converters[index])
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut dyn OutputConverter> {
+        let index = self.position(name)?;
+        Some(&mut *self.converters[index])
+    }
+
+    /// The converters in the order they were registered.
+    pub fn iter(&self) -> impl Iterator<Item = &dyn OutputConverter> {
+        self.converters.iter().map(|converter| &**converter)
+    }
+}
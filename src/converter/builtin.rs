@@ -0,0 +1,406 @@
+//! The output types rbrew supports out of the box.
+
+use super::{Artifact, ConvertInput, ConvertOptions, Fixup, OutputConverter, Platform, Registry};
+use crate::tools;
+use std::{
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// 3DSX loaders, the PSP kernel and the Wii U loader relocate the program, using the
+/// relocations kept by this.
+const EMIT_RELOCS: &[&str] = &["-C", "link-arg=--emit-relocs"];
+
+fn context(err: io::Error, what: &str) -> io::Error {
+    io::Error::new(err.kind(), format!("{what}: {err}"))
+}
+
+pub fn register(registry: &mut Registry) {
+    registry
+        .register(Simple {
+            name: "elf",
+            description: "ELF",
+            extension: "",
+            generic: true,
+            rustflags: &[],
+            fixups: &[],
+            convert: |input, _| {
+                // Without an output directory the linked ELF is the output, and copying
+                // it onto itself would truncate it.
+                if input.elf != input.output {
+                    std::fs::copy(input.elf, input.output)?;
+                }
+                Ok(())
+            },
+        })
+        .register(Dol::default())
+        .register(Rel::default())
+        .register(Simple {
+            name: "gba",
+            description: "GBA ROM",
+            extension: "gba",
+            generic: false,
+            rustflags: &[],
+            fixups: &[Fixup {
+                name: "gba header checksum",
+                apply: |rom| tools::gba::fix_header_checksum(rom),
+            }],
+            convert: |input, _| tools::elf2gba(input.elf, input.output, input.name),
+        })
+        .register(Simple {
+            name: "nds",
+            description: "NDS ROM",
+            extension: "nds",
+            generic: false,
+            rustflags: &[],
+            fixups: &[Fixup {
+                name: "nds header checksums",
+                apply: |rom| tools::nds::fix_header_checksums(rom),
+            }],
+            convert: |input, options| {
+                tools::elf2nds(input.elf, options.arm7.as_deref(), input.output, input.name)
+            },
+        })
+        .register(Simple {
+            name: "3dsx",
+            description: "3DSX",
+            extension: "3dsx",
+            generic: false,
+            rustflags: EMIT_RELOCS,
+            fixups: &[],
+            convert: |input, _| {
+                let smdh = tools::threedsx::Smdh::load(input.name)
+                    .map_err(|err| context(err, "failed to read the SMDH"))?;
+                tools::elf2threedsx(input.elf, input.output, &smdh)
+            },
+        })
+        .register(Simple {
+            name: "nro",
+            description: "NRO",
+            extension: "nro",
+            generic: false,
+            rustflags: &[],
+            fixups: &[],
+            convert: |input, _| {
+                let nacp = tools::nro::Nacp::load(input.name)
+                    .map_err(|err| context(err, "failed to read the NACP"))?;
+                tools::elf2nro(input.elf, input.output, &nacp)
+            },
+        })
+        .register(Simple {
+            name: "z64",
+            description: "N64 ROM",
+            extension: "z64",
+            generic: false,
+            rustflags: &[],
+            fixups: &[Fixup {
+                name: "n64 checksum",
+                apply: |rom| tools::n64::fix_checksum(rom),
+            }],
+            convert: |input, _| {
+                let ipl3 =
+                    tools::n64::load_ipl3().map_err(|err| context(err, "failed to read IPL3"))?;
+                tools::elf2z64(input.elf, input.output, input.name, &ipl3)
+            },
+        })
+        .register(Pbp)
+        .register(Simple {
+            name: "psexe",
+            description: "PS-EXE",
+            extension: "exe",
+            generic: false,
+            rustflags: &[],
+            fixups: &[],
+            convert: |input, _| tools::elf2psexe(input.elf, input.output),
+        })
+        .register(Simple {
+            name: "md",
+            description: "Genesis ROM",
+            extension: "md",
+            generic: false,
+            rustflags: &[],
+            fixups: &[Fixup {
+                name: "genesis checksum",
+                apply: |rom| tools::genesis::fix_checksum(rom),
+            }],
+            convert: |input, _| tools::elf2md(input.elf, input.output, input.name),
+        })
+        .register(Simple {
+            name: "rpx",
+            description: "RPX",
+            extension: "rpx",
+            generic: false,
+            rustflags: EMIT_RELOCS,
+            fixups: &[],
+            convert: |input, _| tools::elf2rpx(input.elf, input.output),
+        })
+        .register(Simple {
+            name: "bin",
+            description: "binary",
+            extension: "bin",
+            generic: true,
+            rustflags: &[],
+            fixups: &[],
+            convert: |input, options| {
+                let bin_options = tools::bin::BinOptions::load()
+                    .map_err(|err| context(err, "failed to read the bin options"))?;
+                let base = tools::elf2bin(input.elf, input.output, &bin_options)?;
+                if options.report {
+                    println!("load address: 0x{base:08x}");
+                }
+                Ok(())
+            },
+        })
+        .register(Simple {
+            name: "hex",
+            description: "Intel HEX",
+            extension: "hex",
+            generic: true,
+            rustflags: &[],
+            fixups: &[],
+            convert: |input, _| records(input, tools::records::RecordFormat::IntelHex),
+        })
+        .register(Simple {
+            name: "srec",
+            description: "S-records",
+            extension: "srec",
+            generic: true,
+            rustflags: &[],
+            fixups: &[],
+            convert: |input, _| records(input, tools::records::RecordFormat::Srec),
+        });
+}
+
+fn records(input: &ConvertInput, format: tools::records::RecordFormat) -> io::Result<()> {
+    let options = tools::records::RecordOptions::load(format)
+        .map_err(|err| context(err, "failed to read the record options"))?;
+    tools::elf2records(input.elf, input.output, format, &options, input.name)
+}
+
+/// An output type written by converting each executable on its own.
+struct Simple {
+    name: &'static str,
+    description: &'static str,
+    extension: &'static str,
+    /// Whether the output type works for any platform's ELFs, rather than only for the
+    /// platforms listing it.
+    generic: bool,
+    rustflags: &'static [&'static str],
+    fixups: &'static [Fixup],
+    convert: fn(&ConvertInput, &ConvertOptions) -> io::Result<()>,
+}
+
+impl OutputConverter for Simple {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn description(&self) -> &str {
+        self.description
+    }
+
+    fn extension(&self) -> &str {
+        self.extension
+    }
+
+    fn supports(&self, platform: &Platform) -> bool {
+        self.generic || platform.outputs.iter().any(|output| output == self.name)
+    }
+
+    fn rustflags(&self) -> &[&str] {
+        self.rustflags
+    }
+
+    fn fixups(&self) -> &'static [Fixup] {
+        self.fixups
+    }
+
+    fn convert(
+        &mut self,
+        input: &ConvertInput,
+        options: &ConvertOptions,
+    ) -> io::Result<Vec<Artifact>> {
+        (self.convert)(input, options)?;
+        Ok(vec![Artifact {
+            elf: input.elf.to_path_buf(),
+            path: input.output.to_path_buf(),
+        }])
+    }
+}
+
+/// DOLs, which are checked against the layout of secondary DOLs in `rbrew.toml` once
+/// all are converted.
+#[derive(Default)]
+struct Dol {
+    layout: Option<tools::multi_dol::DolLayout>,
+    primary: Option<tools::Dol>,
+    /// Each secondary DOL with the index of its range in the layout.
+    secondary: Vec<(usize, tools::Dol)>,
+}
+
+impl OutputConverter for Dol {
+    fn name(&self) -> &str {
+        "dol"
+    }
+
+    fn description(&self) -> &str {
+        "DOL"
+    }
+
+    fn extension(&self) -> &str {
+        "dol"
+    }
+
+    fn convert(
+        &mut self,
+        input: &ConvertInput,
+        _options: &ConvertOptions,
+    ) -> io::Result<Vec<Artifact>> {
+        if input.index == 0 {
+            self.layout = tools::multi_dol::DolLayout::load()?;
+        }
+        let dol = tools::elf2dol(input.elf, input.output)?;
+        // Secondary DOLs are named after their packages.
+        let secondary = self.layout.as_ref().and_then(|layout| {
+            layout
+                .secondary
+                .iter()
+                .position(|secondary| secondary.package == input.name)
+        });
+        match secondary {
+            Some(index) => self.secondary.push((index, dol)),
+            None => self.primary = Some(dol),
+        }
+        Ok(vec![Artifact {
+            elf: input.elf.to_path_buf(),
+            path: input.output.to_path_buf(),
+        }])
+    }
+
+    fn finish(&mut self, _options: &ConvertOptions) -> io::Result<Vec<Artifact>> {
+        let (Some(layout), Some(primary)) = (&self.layout, &self.primary) else {
+            return Ok(vec![]);
+        };
+        let secondary: Vec<_> = self
+            .secondary
+            .drain(..)
+            .map(|(index, dol)| (&layout.secondary[index], dol))
+            .collect();
+        let issues = tools::multi_dol::check(primary, &secondary);
+        if !issues.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("the DOL layout is invalid:\n  - {}", issues.join("\n  - ")),
+            ));
+        }
+        Ok(vec![])
+    }
+}
+
+/// REL modules, which are linked against the main executable and each other once all
+/// are built.
+#[derive(Default)]
+struct Rel {
+    modules: Vec<tools::RelModule>,
+}
+
+impl OutputConverter for Rel {
+    fn name(&self) -> &str {
+        "rel"
+    }
+
+    fn description(&self) -> &str {
+        "REL"
+    }
+
+    fn extension(&self) -> &str {
+        "rel"
+    }
+
+    // REL modules are converted from partially linked objects so that their relocations
+    // are still available.
+    fn rustflags(&self) -> &[&str] {
+        &["-C", "link-arg=-r"]
+    }
+
+    fn relocatable(&self) -> bool {
+        true
+    }
+
+    fn convert(
+        &mut self,
+        input: &ConvertInput,
+        options: &ConvertOptions,
+    ) -> io::Result<Vec<Artifact>> {
+        self.modules.push(tools::RelModule {
+            id: options.rel_module_id + input.index as u32,
+            input: input.elf.to_path_buf(),
+            output: input.output.to_path_buf(),
+        });
+        Ok(vec![])
+    }
+
+    fn finish(&mut self, options: &ConvertOptions) -> io::Result<Vec<Artifact>> {
+        if self.modules.is_empty() {
+            return Ok(vec![]);
+        }
+        tools::elf2rel(&self.modules, options.rel_base.as_deref())?;
+        Ok(self
+            .modules
+            .drain(..)
+            .map(|module| Artifact {
+                elf: module.input,
+                path: module.output,
+            })
+            .collect())
+    }
+}
+
+/// EBOOT.PBPs for the PSP.
+struct Pbp;
+
+impl OutputConverter for Pbp {
+    fn name(&self) -> &str {
+        "pbp"
+    }
+
+    fn description(&self) -> &str {
+        "EBOOT.PBP"
+    }
+
+    fn extension(&self) -> &str {
+        "PBP"
+    }
+
+    fn rustflags(&self) -> &[&str] {
+        EMIT_RELOCS
+    }
+
+    // The XMB only launches a PBP named EBOOT.PBP, in a directory of its own. It is laid
+    // out as on the memory stick, ready to be copied over.
+    fn output_path(&self, directory: &Path, name: &OsStr) -> PathBuf {
+        directory
+            .join("PSP/GAME")
+            .join(name)
+            .join(tools::psp::EBOOT_NAME)
+    }
+
+    fn convert(
+        &mut self,
+        input: &ConvertInput,
+        _options: &ConvertOptions,
+    ) -> io::Result<Vec<Artifact>> {
+        let sfo = tools::psp::Sfo::load(input.name)
+            .map_err(|err| context(err, "failed to read the SFO"))?;
+        if let Some(dir) = input.output.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| context(err, &format!("failed to create '{}'", dir.display())))?;
+        }
+        tools::elf2pbp(input.elf, input.output, &sfo)?;
+        Ok(vec![Artifact {
+            elf: input.elf.to_path_buf(),
+            path: input.output.to_path_buf(),
+        }])
+    }
+}
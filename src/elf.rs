@@ -0,0 +1,629 @@
+//! A small, allocation-friendly ELF reader.
+//!
+//! Only the parts of the format the output converters need are parsed: the file header,
+//! section and program headers, symbol tables and relocation tables. Both 32 and 64-bit
+//! files in either byte order are supported.
+
+use std::{io, path::Path};
+
+pub const ET_REL: u16 = 1;
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+
+pub const EM_68K: u16 = 4;
+pub const EM_MIPS: u16 = 8;
+pub const EM_PPC: u16 = 20;
+pub const EM_ARM: u16 = 40;
+pub const EM_AARCH64: u16 = 183;
+
+pub const SHT_NULL: u32 = 0;
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_REL: u32 = 9;
+
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+
+pub const SHN_UNDEF: u16 = 0;
+pub const SHN_ABS: u16 = 0xfff1;
+pub const SHN_COMMON: u16 = 0xfff2;
+
+pub const PT_LOAD: u32 = 1;
+
+pub const PF_X: u32 = 0x1;
+pub const PF_W: u32 = 0x2;
+pub const PF_R: u32 = 0x4;
+
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+pub const STB_WEAK: u8 = 2;
+
+pub const R_PPC_NONE: u32 = 0;
+pub const R_PPC_ADDR32: u32 = 1;
+pub const R_PPC_ADDR24: u32 = 2;
+pub const R_PPC_ADDR16: u32 = 3;
+pub const R_PPC_ADDR16_LO: u32 = 4;
+pub const R_PPC_ADDR16_HI: u32 = 5;
+pub const R_PPC_ADDR16_HA: u32 = 6;
+pub const R_PPC_ADDR14: u32 = 7;
+pub const R_PPC_ADDR14_BRTAKEN: u32 = 8;
+pub const R_PPC_ADDR14_BRNTAKEN: u32 = 9;
+pub const R_PPC_REL24: u32 = 10;
+pub const R_PPC_REL14: u32 = 11;
+pub const R_PPC_REL14_BRTAKEN: u32 = 12;
+pub const R_PPC_REL14_BRNTAKEN: u32 = 13;
+pub const R_PPC_REL32: u32 = 26;
+
+/// The PowerPC relocation types that can be applied without a dynamic linker, which is
+/// also the set REL modules support.
+pub const PPC_STATIC_RELOCATIONS: &[u32] = &[
+    R_PPC_NONE,
+    R_PPC_ADDR32,
+    R_PPC_ADDR24,
+    R_PPC_ADDR16,
+    R_PPC_ADDR16_LO,
+    R_PPC_ADDR16_HI,
+    R_PPC_ADDR16_HA,
+    R_PPC_ADDR14,
+    R_PPC_ADDR14_BRTAKEN,
+    R_PPC_ADDR14_BRNTAKEN,
+    R_PPC_REL24,
+    R_PPC_REL14,
+    R_PPC_REL14_BRTAKEN,
+    R_PPC_REL14_BRNTAKEN,
+    R_PPC_REL32,
+];
+
+pub const R_ARM_NONE: u32 = 0;
+pub const R_ARM_ABS32: u32 = 2;
+pub const R_ARM_REL32: u32 = 3;
+pub const R_ARM_THM_CALL: u32 = 10;
+pub const R_ARM_CALL: u32 = 28;
+pub const R_ARM_JUMP24: u32 = 29;
+pub const R_ARM_THM_JUMP24: u32 = 30;
+/// Used for `.init_array` entries, treated as `R_ARM_ABS32`.
+pub const R_ARM_TARGET1: u32 = 38;
+pub const R_ARM_V4BX: u32 = 40;
+pub const R_ARM_PREL31: u32 = 42;
+pub const R_ARM_THM_JUMP11: u32 = 102;
+pub const R_ARM_THM_JUMP8: u32 = 103;
+
+/// The ARM relocation types that can be applied without a dynamic linker.
+pub const ARM_STATIC_RELOCATIONS: &[u32] = &[
+    R_ARM_NONE,
+    R_ARM_ABS32,
+    R_ARM_REL32,
+    R_ARM_THM_CALL,
+    R_ARM_CALL,
+    R_ARM_JUMP24,
+    R_ARM_THM_JUMP24,
+    R_ARM_TARGET1,
+    R_ARM_V4BX,
+    R_ARM_PREL31,
+    R_ARM_THM_JUMP11,
+    R_ARM_THM_JUMP8,
+];
+
+pub const R_MIPS_NONE: u32 = 0;
+pub const R_MIPS_32: u32 = 2;
+pub const R_MIPS_26: u32 = 4;
+pub const R_MIPS_HI16: u32 = 5;
+pub const R_MIPS_LO16: u32 = 6;
+pub const R_MIPS_GPREL16: u32 = 7;
+pub const R_MIPS_PC16: u32 = 10;
+pub const R_MIPS_GPREL32: u32 = 12;
+
+/// The MIPS relocation types that can be applied without a dynamic linker.
+pub const MIPS_STATIC_RELOCATIONS: &[u32] = &[
+    R_MIPS_NONE,
+    R_MIPS_32,
+    R_MIPS_26,
+    R_MIPS_HI16,
+    R_MIPS_LO16,
+    R_MIPS_GPREL16,
+    R_MIPS_PC16,
+    R_MIPS_GPREL32,
+];
+
+pub const R_68K_NONE: u32 = 0;
+pub const R_68K_32: u32 = 1;
+pub const R_68K_16: u32 = 2;
+pub const R_68K_8: u32 = 3;
+pub const R_68K_PC32: u32 = 4;
+pub const R_68K_PC16: u32 = 5;
+pub const R_68K_PC8: u32 = 6;
+
+/// The M68k relocation types that can be applied without a dynamic linker.
+pub const M68K_STATIC_RELOCATIONS: &[u32] = &[
+    R_68K_NONE, R_68K_32, R_68K_16, R_68K_8, R_68K_PC32, R_68K_PC16, R_68K_PC8,
+];
+
+pub const R_AARCH64_NONE: u32 = 0;
+pub const R_AARCH64_RELATIVE: u32 = 1027;
+
+/// The AArch64 relocation types a self-relocating program applies at startup.
+pub const AARCH64_SELF_RELOCATIONS: &[u32] = &[R_AARCH64_NONE, R_AARCH64_RELATIVE];
+
+pub const STT_OBJECT: u8 = 1;
+pub const STT_FUNC: u8 = 2;
+pub const STT_SECTION: u8 = 3;
+pub const STT_FILE: u8 = 4;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Class {
+    Elf32,
+    Elf64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+#[derive(Clone, Debug)]
+pub struct Section {
+    pub name: String,
+    pub kind: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub info: u32,
+    pub addralign: u64,
+    pub entsize: u64,
+}
+
+impl Section {
+    pub fn is_alloc(&self) -> bool {
+        self.flags & SHF_ALLOC != 0
+    }
+
+    pub fn is_exec(&self) -> bool {
+        self.flags & SHF_EXECINSTR != 0
+    }
+
+    pub fn is_nobits(&self) -> bool {
+        self.kind == SHT_NOBITS
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+    pub info: u8,
+    pub other: u8,
+    pub shndx: u16,
+}
+
+impl Symbol {
+    pub fn binding(&self) -> u8 {
+        self.info >> 4
+    }
+
+    pub fn kind(&self) -> u8 {
+        self.info & 0xf
+    }
+
+    pub fn is_undefined(&self) -> bool {
+        self.shndx == SHN_UNDEF
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Relocation {
+    pub offset: u64,
+    pub sym: u32,
+    pub kind: u32,
+    pub addend: i64,
+}
+
+pub struct Elf {
+    data: Vec<u8>,
+    pub class: Class,
+    pub endian: Endian,
+    pub kind: u16,
+    pub machine: u16,
+    pub entry: u64,
+    pub flags: u32,
+    pub sections: Vec<Section>,
+    pub segments: Vec<Segment>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    endian: Endian,
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| invalid("unexpected end of ELF data"))?;
+        self.pos += N;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.bytes()?;
+        Ok(match self.endian {
+            Endian::Little => u16::from_le_bytes(b),
+            Endian::Big => u16::from_be_bytes(b),
+        })
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.bytes()?;
+        Ok(match self.endian {
+            Endian::Little => u32::from_le_bytes(b),
+            Endian::Big => u32::from_be_bytes(b),
+        })
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let b = self.bytes()?;
+        Ok(match self.endian {
+            Endian::Little => u64::from_le_bytes(b),
+            Endian::Big => u64::from_be_bytes(b),
+        })
+    }
+
+    /// Reads a word whose width depends on the ELF class.
+    fn word(&mut self, class: Class) -> io::Result<u64> {
+        match class {
+            Class::Elf32 => self.u32().map(u64::from),
+            Class::Elf64 => self.u64(),
+        }
+    }
+}
+
+impl Elf {
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(std::fs::read(path)?)
+    }
+
+    pub fn parse(data: Vec<u8>) -> io::Result<Self> {
+        if data.get(0..4) != Some(b"\x7fELF") {
+            return Err(invalid("missing ELF magic"));
+        }
+        let class = match data.get(4) {
+            Some(1) => Class::Elf32,
+            Some(2) => Class::Elf64,
+            _ => return Err(invalid("invalid ELF class")),
+        };
+        let endian = match data.get(5) {
+            Some(1) => Endian::Little,
+            Some(2) => Endian::Big,
+            _ => return Err(invalid("invalid ELF data encoding")),
+        };
+
+        let mut r = Reader {
+            data: &data,
+            endian,
+            pos: 16,
+        };
+        let kind = r.u16()?;
+        let machine = r.u16()?;
+        let _version = r.u32()?;
+        let entry = r.word(class)?;
+        let phoff = r.word(class)?;
+        let shoff = r.word(class)?;
+        let flags = r.u32()?;
+        let _ehsize = r.u16()?;
+        let phentsize = r.u16()? as usize;
+        let phnum = r.u16()? as usize;
+        let shentsize = r.u16()? as usize;
+        let shnum = r.u16()? as usize;
+        let shstrndx = r.u16()? as usize;
+
+        let mut segments = Vec::with_capacity(phnum);
+        for i in 0..phnum {
+            r.pos = phoff as usize + i * phentsize;
+            segments.push(match class {
+                Class::Elf32 => {
+                    let kind = r.u32()?;
+                    let offset = r.u32()? as u64;
+                    let vaddr = r.u32()? as u64;
+                    let paddr = r.u32()? as u64;
+                    let filesz = r.u32()? as u64;
+                    let memsz = r.u32()? as u64;
+                    let flags = r.u32()?;
+                    let align = r.u32()? as u64;
+                    Segment {
+                        kind,
+                        flags,
+                        offset,
+                        vaddr,
+                        paddr,
+                        filesz,
+                        memsz,
+                        align,
+                    }
+                }
+                Class::Elf64 => {
+                    let kind = r.u32()?;
+                    let flags = r.u32()?;
+                    Segment {
+                        kind,
+                        flags,
+                        offset: r.u64()?,
+                        vaddr: r.u64()?,
+                        paddr: r.u64()?,
+                        filesz: r.u64()?,
+                        memsz: r.u64()?,
+                        align: r.u64()?,
+                    }
+                }
+            });
+        }
+
+        let mut raw_sections = Vec::with_capacity(shnum);
+        for i in 0..shnum {
+            r.pos = shoff as usize + i * shentsize;
+            let name = r.u32()?;
+            let kind = r.u32()?;
+            let flags = r.word(class)?;
+            let addr = r.word(class)?;
+            let offset = r.word(class)?;
+            let size = r.word(class)?;
+            let link = r.u32()?;
+            let info = r.u32()?;
+            let addralign = r.word(class)?;
+            let entsize = r.word(class)?;
+            raw_sections.push((
+                name,
+                Section {
+                    name: String::new(),
+                    kind,
+                    flags,
+                    addr,
+                    offset,
+                    size,
+                    link,
+                    info,
+                    addralign,
+                    entsize,
+                },
+            ));
+        }
+
+        let mut elf = Self {
+            data,
+            class,
+            endian,
+            kind,
+            machine,
+            entry,
+            flags,
+            sections: vec![],
+            segments,
+        };
+
+        let shstrtab = raw_sections.get(shstrndx).map(|(_, s)| s.clone());
+        for (name, mut section) in raw_sections {
+            if let Some(shstrtab) = &shstrtab {
+                section.name = elf.string(shstrtab, name)?;
+            }
+            elf.sections.push(section);
+        }
+
+        Ok(elf)
+    }
+
+    /// The raw contents of the whole file.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    fn reader(&self, pos: usize) -> Reader<'_> {
+        Reader {
+            data: &self.data,
+            endian: self.endian,
+            pos,
+        }
+    }
+
+    /// Reads a NUL-terminated string out of a string table section.
+    pub fn string(&self, strtab: &Section, index: u32) -> io::Result<String> {
+        let table = self.section_data(strtab)?;
+        let bytes = table
+            .get(index as usize..)
+            .ok_or_else(|| invalid("string index out of bounds"))?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8(bytes[..end].to_vec()).map_err(|_| invalid("invalid UTF-8 in string"))
+    }
+
+    /// The raw file contents of a section. `SHT_NOBITS` sections have no contents.
+    pub fn section_data(&self, section: &Section) -> io::Result<&[u8]> {
+        if section.is_nobits() {
+            return Ok(&[]);
+        }
+        let start = section.offset as usize;
+        self.data
+            .get(start..start + section.size as usize)
+            .ok_or_else(|| invalid(format!("section '{}' out of bounds", section.name)))
+    }
+
+    /// The raw file contents of a segment, not including any zero-filled tail.
+    pub fn segment_data(&self, segment: &Segment) -> io::Result<&[u8]> {
+        let start = segment.offset as usize;
+        self.data
+            .get(start..start + segment.filesz as usize)
+            .ok_or_else(|| invalid("segment out of bounds"))
+    }
+
+    /// Lays out the contents of all loadable segments at their physical (load) addresses,
+    /// returning the lowest load address along with the image starting at it.
+    ///
+    /// Gaps between segments are zero-filled and zero-initialized tails are left out.
+    pub fn load_image(&self) -> io::Result<(u64, Vec<u8>)> {
+        self.load_image_with_fill(0)
+    }
+
+    /// Like [`Elf::load_image`], with the gaps between segments filled with `fill`.
+    pub fn load_image_with_fill(&self, fill: u8) -> io::Result<(u64, Vec<u8>)> {
+        let segments: Vec<&Segment> = self
+            .segments
+            .iter()
+            .filter(|segment| segment.kind == PT_LOAD && segment.filesz != 0)
+            .collect();
+        let Some(base) = segments.iter().map(|segment| segment.paddr).min() else {
+            return Ok((0, vec![]));
+        };
+        let mut image = vec![];
+        for segment in segments {
+            let start = (segment.paddr - base) as usize;
+            let data = self.segment_data(segment)?;
+            if image.len() < start + data.len() {
+                image.resize(start + data.len(), fill);
+            }
+            image[start..start + data.len()].copy_from_slice(data);
+        }
+        Ok((base, image))
+    }
+
+    /// The range of memory covering the zero-initialized tails of all loadable segments,
+    /// if any of them has one.
+    pub fn zero_fill_range(&self) -> Option<(u64, u64)> {
+        self.segments
+            .iter()
+            .filter(|segment| segment.kind == PT_LOAD && segment.memsz > segment.filesz)
+            .map(|segment| {
+                (
+                    segment.vaddr + segment.filesz,
+                    segment.vaddr + segment.memsz,
+                )
+            })
+            .reduce(|(lo, hi), (start, end)| (lo.min(start), hi.max(end)))
+    }
+
+    pub fn section_by_name(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// All symbols in the static symbol table, indexed by their symbol table index.
+    pub fn symbols(&self) -> io::Result<Vec<Symbol>> {
+        let Some(symtab) = self.sections.iter().find(|s| s.kind == SHT_SYMTAB) else {
+            return Ok(vec![]);
+        };
+        let strtab = self
+            .sections
+            .get(symtab.link as usize)
+            .ok_or_else(|| invalid("symbol table has an invalid string table link"))?;
+
+        let entsize = match self.class {
+            Class::Elf32 => 16,
+            Class::Elf64 => 24,
+        };
+        let count = symtab.size as usize / entsize;
+        let mut symbols = Vec::with_capacity(count);
+        for i in 0..count {
+            let mut r = self.reader(symtab.offset as usize + i * entsize);
+            let (name, value, size, info, other, shndx) = match self.class {
+                Class::Elf32 => {
+                    let name = r.u32()?;
+                    let value = r.u32()? as u64;
+                    let size = r.u32()? as u64;
+                    let info = r.u8()?;
+                    let other = r.u8()?;
+                    let shndx = r.u16()?;
+                    (name, value, size, info, other, shndx)
+                }
+                Class::Elf64 => {
+                    let name = r.u32()?;
+                    let info = r.u8()?;
+                    let other = r.u8()?;
+                    let shndx = r.u16()?;
+                    let value = r.u64()?;
+                    let size = r.u64()?;
+                    (name, value, size, info, other, shndx)
+                }
+            };
+            symbols.push(Symbol {
+                name: self.string(strtab, name)?,
+                value,
+                size,
+                info,
+                other,
+                shndx,
+            });
+        }
+        Ok(symbols)
+    }
+
+    /// Parses the entries of an `SHT_REL` or `SHT_RELA` section.
+    pub fn relocations(&self, section: &Section) -> io::Result<Vec<Relocation>> {
+        let rela = match section.kind {
+            SHT_RELA => true,
+            SHT_REL => false,
+            _ => {
+                return Err(invalid(format!(
+                    "'{}' is not a relocation section",
+                    section.name
+                )))
+            }
+        };
+        let entsize = match (self.class, rela) {
+            (Class::Elf32, false) => 8,
+            (Class::Elf32, true) => 12,
+            (Class::Elf64, false) => 16,
+            (Class::Elf64, true) => 24,
+        };
+        let count = section.size as usize / entsize;
+        let mut relocations = Vec::with_capacity(count);
+        for i in 0..count {
+            let mut r = self.reader(section.offset as usize + i * entsize);
+            let offset = r.word(self.class)?;
+            let info = r.word(self.class)?;
+            let (sym, kind) = match self.class {
+                Class::Elf32 => ((info >> 8) as u32, (info & 0xff) as u32),
+                Class::Elf64 => ((info >> 32) as u32, (info & 0xffff_ffff) as u32),
+            };
+            let addend = match (self.class, rela) {
+                (_, false) => 0,
+                (Class::Elf32, true) => r.u32()? as i32 as i64,
+                (Class::Elf64, true) => r.u64()? as i64,
+            };
+            relocations.push(Relocation {
+                offset,
+                sym,
+                kind,
+                addend,
+            });
+        }
+        Ok(relocations)
+    }
+}
@@ -1,8 +1,3 @@
-// TODOS:
-//
-// - make `--help` output details of all possible platforms.
-// - make `--help` output details of all possible output types.
-
 #![feature(exitcode_exit_method)]
 
 use argp::{FromArgValue, FromArgs};
@@ -13,6 +8,10 @@ use std::{
     process::{Command, ExitCode},
 };
 
+mod config;
+pub mod converter;
+pub mod elf;
+pub mod platform;
 mod tools;
 
 fn graceful_error_exit(msg: impl Display) -> ! {
@@ -23,70 +22,50 @@ fn graceful_error_exit(msg: impl Display) -> ! {
 mod fields {
     use super::*;
 
-    #[derive(Clone, Copy)]
-    pub enum Platform {
-        Gamecube,
-    }
-
-    impl Platform {
-        pub fn target_json_name(self) -> &'static str {
-            match self {
-                Platform::Gamecube => "gamecube.json",
-            }
-        }
-
-        pub fn config_toml_name(self) -> &'static str {
-            match self {
-                Platform::Gamecube => "gamecube.toml",
-            }
-        }
+    #[derive(Default, Clone, Copy)]
+    pub enum PatchFormat {
+        Ips,
+        #[default]
+        Bps,
     }
 
-    impl FromArgValue for Platform {
+    impl FromArgValue for PatchFormat {
         fn from_arg_value(value: &std::ffi::OsStr) -> Result<Self, String> {
             let str = value.to_str().ok_or("invalid UTF-8 string".to_string())?;
             Ok(match str {
-                "gamecube" | "gc" => Self::Gamecube,
-                _ => return Err("expected a valid platform.".to_string()),
+                "ips" => Self::Ips,
+                "bps" => Self::Bps,
+                _ => return Err("expected 'ips' or 'bps'.".to_string()),
             })
         }
     }
 
+    impl PatchFormat {
+        pub fn extension_name(self) -> &'static str {
+            match self {
+                PatchFormat::Ips => "ips",
+                PatchFormat::Bps => "bps",
+            }
+        }
+    }
+
     #[derive(Default, Clone, Copy)]
-    pub enum OutputType {
+    pub enum CheatFormat {
         #[default]
-        Elf,
-        Dol,
+        Gecko,
+        ActionReplay,
     }
 
-    impl FromArgValue for OutputType {
+    impl FromArgValue for CheatFormat {
         fn from_arg_value(value: &std::ffi::OsStr) -> Result<Self, String> {
             let str = value.to_str().ok_or("invalid UTF-8 string".to_string())?;
             Ok(match str {
-                "elf" => Self::Elf,
-                "dol" => Self::Dol,
-                _ => return Err("expected a valid output type.".to_string()),
+                "gecko" => Self::Gecko,
+                "ar" | "action-replay" => Self::ActionReplay,
+                _ => return Err("expected 'gecko' or 'ar'.".to_string()),
             })
         }
     }
-
-    impl OutputType {
-        pub fn supports_platform(self, platform: Platform) -> bool {
-            #[allow(unreachable_patterns)]
-            #[allow(clippy::match_like_matches_macro)]
-            match (self, platform) {
-                (Self::Elf, _) | (Self::Dol, Platform::Gamecube) => true,
-                _ => false,
-            }
-        }
-
-        pub fn extension_name(self) -> &'static str {
-            match self {
-                OutputType::Elf => "",
-                OutputType::Dol => ".dol",
-            }
-        }
-    }
 }
 
 /// The rbrew build subcommand.
@@ -96,10 +75,10 @@ struct RbrewCliSubBuild {
     /// The platform to build for.
     /// See `--help` for more details.
     #[argp(option)]
-    platform: fields::Platform,
-    /// Output file type.
-    #[argp(option, default = "Default::default()")]
-    output_type: fields::OutputType,
+    platform: String,
+    /// Output file type, the name of a registered converter such as `dol`.
+    #[argp(option, default = "String::from(\"elf\")")]
+    output_type: String,
     /// Build all packages in the workspace.
     #[argp(switch)]
     workspace: bool,
@@ -112,23 +91,238 @@ struct RbrewCliSubBuild {
     /// Custom cargo flags.
     #[argp(option)]
     custom_options: Vec<String>,
+    /// The linked main executable REL modules import symbols from.
+    #[argp(option)]
+    rel_base: Option<PathBuf>,
+    /// Module id of the first REL module; each further module counts up from it.
+    #[argp(option, default = "1")]
+    rel_module_id: u32,
+    /// Embed the git hash, build time, profile and rbrew version into the output.
+    /// Requires the program to reference `rbrew_shared::build_info::get`.
+    #[argp(switch)]
+    build_info: bool,
+    /// Also write a Dolphin symbol map next to each output.
+    #[argp(switch)]
+    dolphin_map: bool,
+    /// A directory to pack into a read-only filesystem loaded after each DOL.
+    /// Requires the program to reference `rbrew_gc::romfs::get`.
+    #[argp(option)]
+    romfs: Option<PathBuf>,
+    /// Skip checking the linked ELF's layout against the platform before conversion.
+    #[argp(switch)]
+    no_validate: bool,
+    /// Don't pass the platform's default target CPU, float ABI and link args to rustc.
+    #[argp(switch)]
+    no_default_flags: bool,
+}
+
+/// Creates a patch from a base DOL or ISO to a modified one.
+#[derive(FromArgs)]
+#[argp(subcommand, name = "patch")]
+struct RbrewCliSubToolsPatch {
+    /// The unmodified base image.
+    #[argp(positional)]
+    base: PathBuf,
+    /// The modified image, usually the build output.
+    #[argp(positional)]
+    target: PathBuf,
+    /// Patch format, either 'ips' or 'bps'.
+    #[argp(option, default = "Default::default()")]
+    format: fields::PatchFormat,
+    /// Output file. Defaults to the target path with the patch format's extension.
+    #[argp(option)]
+    output: Option<PathBuf>,
+}
+
+/// Prints the build information embedded into an artifact.
+#[derive(FromArgs)]
+#[argp(subcommand, name = "info")]
+struct RbrewCliSubToolsInfo {
+    /// The ELF, DOL, REL or disc image to inspect.
+    #[argp(positional)]
+    artifact: PathBuf,
+}
+
+/// Creates a Dolphin symbol map from a linked ELF, using DWARF debug info when present.
+#[derive(FromArgs)]
+#[argp(subcommand, name = "map")]
+struct RbrewCliSubToolsMap {
+    /// The linked ELF.
+    #[argp(positional)]
+    input: PathBuf,
+    /// Output file. Defaults to the input path with a `.map` extension.
+    #[argp(option)]
+    output: Option<PathBuf>,
+}
+
+/// Generates Gecko or Action Replay codes writing to a symbol of a linked ELF.
+#[derive(FromArgs)]
+#[argp(subcommand, name = "cheat")]
+struct RbrewCliSubToolsCheat {
+    /// The linked ELF to resolve the symbol in.
+    #[argp(positional)]
+    elf: PathBuf,
+    /// The symbol, either mangled or demangled without its hash.
+    #[argp(positional)]
+    symbol: String,
+    /// Byte offset added to the symbol's address.
+    #[argp(option)]
+    offset: Option<String>,
+    /// Value to write, decimal or `0x`-prefixed hex.
+    #[argp(option)]
+    value: Option<String>,
+    /// Width of `--value` in bytes. Defaults to the symbol size if it is 1, 2 or 4.
+    #[argp(option)]
+    width: Option<u32>,
+    /// Bytes to write instead of a value, as hex digits (e.g. `60000000`).
+    #[argp(option)]
+    bytes: Option<String>,
+    /// Code format, either 'gecko' or 'ar'.
+    #[argp(option, default = "Default::default()")]
+    format: fields::CheatFormat,
+}
+
+/// Generates an apploader that loads a DOL from a disc image.
+#[derive(FromArgs)]
+#[argp(subcommand, name = "apploader")]
+struct RbrewCliSubToolsApploader {
+    /// The DOL to load.
+    #[argp(positional)]
+    dol: PathBuf,
+    /// Offset of the DOL on the disc, decimal or `0x`-prefixed hex.
+    #[argp(option)]
+    dol_offset: String,
+    /// Output file. Defaults to `apploader.img` next to the DOL.
+    #[argp(option)]
+    output: Option<PathBuf>,
+}
+
+/// Prints the entry point, bss and section table of a DOL.
+#[derive(FromArgs)]
+#[argp(subcommand, name = "info")]
+struct RbrewCliSubToolsDolInfo {
+    /// The DOL to inspect.
+    #[argp(positional)]
+    dol: PathBuf,
+}
+
+/// Extracts the contents of one section of a DOL.
+#[derive(FromArgs)]
+#[argp(subcommand, name = "extract")]
+struct RbrewCliSubToolsDolExtract {
+    /// The DOL to read.
+    #[argp(positional)]
+    dol: PathBuf,
+    /// The section, as named by `rbrew tools dol info` (e.g. `text0` or `data2`).
+    #[argp(positional)]
+    section: String,
+    /// Output file. Defaults to the DOL path with the section as its extension.
+    #[argp(option)]
+    output: Option<PathBuf>,
+}
+
+/// Replaces the data loaded at an address within a section of a DOL.
+#[derive(FromArgs)]
+#[argp(subcommand, name = "patch")]
+struct RbrewCliSubToolsDolPatch {
+    /// The DOL to patch.
+    #[argp(positional)]
+    dol: PathBuf,
+    /// Load address of the data, decimal or `0x`-prefixed hex.
+    #[argp(option)]
+    address: String,
+    /// Bytes to write, as hex digits (e.g. `60000000`).
+    #[argp(option)]
+    bytes: Option<String>,
+    /// File whose contents to write instead of `--bytes`.
+    #[argp(option)]
+    file: Option<PathBuf>,
+    /// Output file. Defaults to patching the DOL in place.
+    #[argp(option)]
+    output: Option<PathBuf>,
+}
+
+/// Adds a section loading data at an address to a DOL.
+#[derive(FromArgs)]
+#[argp(subcommand, name = "inject")]
+struct RbrewCliSubToolsDolInject {
+    /// The DOL to add the section to.
+    #[argp(positional)]
+    dol: PathBuf,
+    /// Load address of the section, decimal or `0x`-prefixed hex.
+    #[argp(option)]
+    address: String,
+    /// Bytes of the section, as hex digits (e.g. `60000000`).
+    #[argp(option)]
+    bytes: Option<String>,
+    /// File whose contents to use instead of `--bytes`.
+    #[argp(option)]
+    file: Option<PathBuf>,
+    /// Add a text section instead of a data section.
+    #[argp(switch)]
+    text: bool,
+    /// Output file. Defaults to modifying the DOL in place.
+    #[argp(option)]
+    output: Option<PathBuf>,
+}
+
+#[derive(FromArgs)]
+#[argp(subcommand)]
+enum RbrewCliSubToolsDolSub {
+    Info(RbrewCliSubToolsDolInfo),
+    Extract(RbrewCliSubToolsDolExtract),
+    Patch(RbrewCliSubToolsDolPatch),
+    Inject(RbrewCliSubToolsDolInject),
+}
+
+/// Inspects and patches DOLs.
+#[derive(FromArgs)]
+#[argp(subcommand, name = "dol")]
+struct RbrewCliSubToolsDol {
+    #[argp(subcommand)]
+    subcommand: RbrewCliSubToolsDolSub,
+}
+
+#[derive(FromArgs)]
+#[argp(subcommand)]
+enum RbrewCliSubToolsSub {
+    Patch(RbrewCliSubToolsPatch),
+    Info(RbrewCliSubToolsInfo),
+    Map(RbrewCliSubToolsMap),
+    Cheat(RbrewCliSubToolsCheat),
+    Apploader(RbrewCliSubToolsApploader),
+    Dol(RbrewCliSubToolsDol),
 }
 
 /// The rbrew tools subommand.
 #[derive(FromArgs)]
 #[argp(subcommand, name = "tools")]
-struct RbrewCliSubTools {}
+struct RbrewCliSubTools {
+    #[argp(subcommand)]
+    subcommand: RbrewCliSubToolsSub,
+}
+
+/// Lists the platforms, their output types and what building and running for them needs.
+#[derive(FromArgs)]
+#[argp(subcommand, name = "platforms")]
+struct RbrewCliSubPlatforms {
+    /// Print the listing as JSON.
+    #[argp(switch)]
+    json: bool,
+}
 
 #[derive(FromArgs)]
 #[argp(subcommand)]
 enum RbrewCliSub {
     Build(RbrewCliSubBuild),
+    Platforms(RbrewCliSubPlatforms),
     Tools(RbrewCliSubTools),
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 enum Verbosity {
     Quiet,
+    #[default]
     Normal,
     Verbose,
 }
@@ -139,12 +333,6 @@ impl Verbosity {
     }
 }
 
-impl Default for Verbosity {
-    fn default() -> Self {
-        Self::Normal
-    }
-}
-
 impl FromArgValue for Verbosity {
     fn from_arg_value(value: &std::ffi::OsStr) -> Result<Self, String> {
         Ok(match value.to_str() {
@@ -198,48 +386,726 @@ mod util {
             include_str!(concat!(env!("OUT_DIR"), "/config_path.inc"))
         )))
     }
+
+    /// Finds an executable on the `PATH`.
+    pub fn find_program(name: &str) -> Option<PathBuf> {
+        std::env::split_paths(&std::env::var_os("PATH")?)
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// The sysroot and host triple of the toolchain cargo builds with.
+    pub fn rustc_sysroot() -> Option<(PathBuf, String)> {
+        let run = |arg: &str| {
+            let output = Command::new("rustc").arg(arg).output().ok()?;
+            String::from_utf8(output.stdout).ok()
+        };
+        let sysroot = run("--print=sysroot")?;
+        let host = run("-vV")?
+            .lines()
+            .find_map(|line| line.strip_prefix("host: "))?
+            .to_string();
+        Some((PathBuf::from(sysroot.trim()), host))
+    }
+}
+
+/// Parses the command line like [`argp::parse_args_or_exit`], listing the platforms and
+/// output types after the help of `build`. `cargo` is set when running as `cargo rbrew`,
+/// where the second argument names the subcommand.
+pub fn parse_args_or_exit(cargo: bool) -> RbrewCli {
+    let args: Vec<_> = std::env::args_os().collect();
+    let skip = if cargo { 2 } else { 1 };
+    let command = match args.get(skip - 1) {
+        Some(command) => Path::new(command)
+            .file_name()
+            .unwrap_or(command)
+            .to_string_lossy()
+            .into_owned(),
+        None => graceful_error_exit("no program name, argv is empty."),
+    };
+    let rest: Vec<&str> = args
+        .iter()
+        .skip(skip)
+        .map(|arg| arg.to_str().unwrap_or_default())
+        .collect();
+
+    match RbrewCli::from_args(&[&command], &rest) {
+        Ok(cli) => cli,
+        Err(argp::EarlyExit::Help(help)) => {
+            println!("{}", help.generate_default());
+            // The first argument that is not the verbosity names the subcommand.
+            let mut words = rest.iter().filter(|arg| !arg.starts_with('-'));
+            if rest.first() == Some(&"--verbosity") {
+                words.next();
+            }
+            if words.next() == Some(&"build") {
+                println!("\n{}", build_help_footer(&converter::Registry::builtin()));
+            }
+            ExitCode::SUCCESS.exit_process()
+        }
+        Err(argp::EarlyExit::Err(err)) => {
+            eprintln!("{err}\nRun {command} --help for more information.");
+            ExitCode::FAILURE.exit_process()
+        }
+    }
+}
+
+/// The platforms and output types, for the help of `build`.
+fn build_help_footer(registry: &converter::Registry) -> String {
+    let platforms = bundled_platforms();
+    let mut out = String::from("Platforms:\n");
+    for platform in platforms.iter() {
+        let mut names = platform.name.clone();
+        if !platform.aliases.is_empty() {
+            names = format!("{names} ({})", platform.aliases.join(", "));
+        }
+        out += &format!("  {names:<26} {}\n", platform.description);
+    }
+    let all = platforms.iter().count();
+    out += "\nOutput types:\n";
+    for converter in registry.iter() {
+        let platforms: Vec<_> = platforms
+            .iter()
+            .filter(|platform| converter.supports(platform))
+            .map(|platform| platform.name.as_str())
+            .collect();
+        let supported = if platforms.len() == all {
+            "any platform".to_string()
+        } else {
+            platforms.join(", ")
+        };
+        out += &format!(
+            "  {:<26} {}, for {supported}\n",
+            converter.name(),
+            converter.description(),
+        );
+    }
+    out += "\nSee `rbrew platforms` for what building and running for each platform needs.";
+    out
 }
 
 pub fn run(cli: RbrewCli) {
+    run_with(cli, converter::Registry::builtin())
+}
+
+/// Runs rbrew with the output types of `registry` in place of the built-in ones.
+pub fn run_with(cli: RbrewCli, mut registry: converter::Registry) {
     match cli.subcommand {
-        RbrewCliSub::Build(args) => build(args, cli.verbosity),
+        RbrewCliSub::Build(args) => build(args, &mut registry, cli.verbosity),
+        RbrewCliSub::Platforms(args) => platforms(args, &registry),
         RbrewCliSub::Tools(args) => tools(args, cli.verbosity),
     }
 }
 
-fn build(args: RbrewCliSubBuild, verbosity: Verbosity) {
-    if !args.output_type.supports_platform(args.platform) {
-        graceful_error_exit("output type does not support platform. See `--help`.")
+fn bundled_platforms() -> platform::Registry {
+    match platform::Registry::bundled() {
+        Ok(ok) => ok,
+        Err(err) => graceful_error_exit(format!("failed to read the platforms: {err}")),
     }
+}
 
-    let mut cmd = util::cargo();
-    cmd.arg("build");
-    if let Some(package) = &args.package {
-        cmd.arg("--package").arg(package);
+/// What building for a platform needs besides rbrew: the standard library sources for
+/// `build-std` and the linker its target JSON names.
+fn toolchain_requirements(platform: &platform::Platform) -> Vec<(String, bool)> {
+    let linker = util::rbrew_target_file(&platform.target_json)
+        .and_then(std::fs::read_to_string)
+        .ok()
+        .and_then(|text| json::parse(&text).ok())
+        .and_then(|target| target["linker"].as_str().map(str::to_string));
+    let sysroot = util::rustc_sysroot();
+
+    let mut requirements = vec![(
+        "rust-src".to_string(),
+        sysroot
+            .as_ref()
+            .is_some_and(|(sysroot, _)| sysroot.join("lib/rustlib/src/rust/library").is_dir()),
+    )];
+    if let Some(linker) = linker {
+        // rust-lld ships with the toolchain rather than being on the `PATH`.
+        let bundled = sysroot.as_ref().is_some_and(|(sysroot, host)| {
+            sysroot
+                .join(format!("lib/rustlib/{host}/bin/{linker}"))
+                .is_file()
+        });
+        let found = bundled || util::find_program(&linker).is_some();
+        requirements.push((linker, found));
     }
-    if args.workspace {
-        cmd.arg("--workspace");
+    requirements
+}
+
+fn platforms(args: RbrewCliSubPlatforms, registry: &converter::Registry) {
+    let platforms = bundled_platforms();
+    let mut listing = json::JsonValue::new_array();
+    for platform in platforms.iter() {
+        let outputs: Vec<_> = registry
+            .iter()
+            .filter(|converter| converter.supports(platform))
+            .map(|converter| converter.name().to_string())
+            .collect();
+        let toolchain: Vec<_> = toolchain_requirements(platform)
+            .into_iter()
+            .map(|(name, found)| json::object! { name: name, found: found })
+            .collect();
+        let emulators: Vec<_> = platform
+            .emulators
+            .iter()
+            .map(|name| {
+                json::object! {
+                    name: name.as_str(),
+                    path: util::find_program(name).map(|path| path.display().to_string()),
+                }
+            })
+            .collect();
+        listing
+            .push(json::object! {
+                name: platform.name.as_str(),
+                aliases: platform.aliases.clone(),
+                description: platform.description.as_str(),
+                outputs: outputs,
+                toolchain: toolchain,
+                emulators: emulators,
+            })
+            .unwrap();
+    }
+
+    if args.json {
+        println!("{}", listing.pretty(2));
+        return;
+    }
+    let found = |found: bool| if found { "found" } else { "missing" };
+    for platform in listing.members() {
+        let mut names = platform["name"].to_string();
+        if !platform["aliases"].is_empty() {
+            let aliases: Vec<_> = platform["aliases"]
+                .members()
+                .map(|a| a.to_string())
+                .collect();
+            names = format!("{names} ({})", aliases.join(", "));
+        }
+        println!("{names} - {}", platform["description"]);
+        let outputs: Vec<_> = platform["outputs"]
+            .members()
+            .map(|o| o.to_string())
+            .collect();
+        println!("  outputs:   {}", outputs.join(", "));
+        let toolchain: Vec<_> = platform["toolchain"]
+            .members()
+            .map(|tool| format!("{} ({})", tool["name"], found(tool["found"] == true)))
+            .collect();
+        println!("  toolchain: {}", toolchain.join(", "));
+        let emulators: Vec<_> = platform["emulators"]
+            .members()
+            .map(|emulator| {
+                format!(
+                    "{} ({})",
+                    emulator["name"],
+                    found(!emulator["path"].is_null())
+                )
+            })
+            .collect();
+        if !emulators.is_empty() {
+            println!("  emulators: {}", emulators.join(", "));
+        }
     }
+}
+
+fn build(args: RbrewCliSubBuild, registry: &mut converter::Registry, verbosity: Verbosity) {
+    use tools::pipeline::Stage;
 
-    let target_json_ident = args.platform.target_json_name();
-    let target_json = match util::rbrew_target_file(target_json_ident) {
+    let Some(converter) = registry.get_mut(&args.output_type) else {
+        graceful_error_exit(format!(
+            "unknown output type '{}'. See `--help`.",
+            args.output_type
+        ))
+    };
+    let platforms = bundled_platforms();
+    let Some(platform) = platforms.get(&args.platform) else {
+        let names: Vec<_> = platforms
+            .iter()
+            .map(|platform| platform.name.as_str())
+            .collect();
+        graceful_error_exit(format!(
+            "unknown platform '{}', expected one of: {}.",
+            args.platform,
+            names.join(", ")
+        ))
+    };
+    if !converter.supports(platform) {
+        graceful_error_exit("output type does not support platform. See `--help`.")
+    }
+
+    let romfs = args.romfs.as_deref().map(|dir| {
+        if converter.name() != "dol" {
+            graceful_error_exit("`--romfs` can only be used with the 'dol' output type.")
+        }
+        match tools::romfs::pack(dir) {
+            Ok(ok) => ok,
+            Err(err) => graceful_error_exit(format!(
+                "failed to pack the romfs '{}': {err}",
+                dir.display()
+            )),
+        }
+    });
+
+    let budget = match tools::budget::Budget::load() {
+        Ok(ok) => ok,
+        Err(err) => graceful_error_exit(format!("failed to read the size budget: {err}")),
+    };
+    let dol_layout = match converter.name() {
+        "dol" => match tools::multi_dol::DolLayout::load() {
+            Ok(ok) => ok,
+            Err(err) => graceful_error_exit(format!("failed to read the DOL layout: {err}")),
+        },
+        _ => None,
+    };
+    let nds_layout = match converter.name() {
+        "nds" => match tools::nds::NdsLayout::load() {
+            Ok(ok) => ok,
+            Err(err) => graceful_error_exit(format!("failed to read the NDS packages: {err}")),
+        },
+        _ => None,
+    };
+    if (dol_layout.is_some() || nds_layout.is_some()) && (args.package.is_some() || args.workspace)
+    {
+        graceful_error_exit(
+            "`--package` and `--workspace` cannot be used with the packages declared in rbrew.toml.",
+        )
+    }
+
+    let _target_json = match util::rbrew_target_file(&platform.target_json) {
         Ok(ok) => ok,
         Err(err) => graceful_error_exit(format!(
             "failed to find the target json file for the platform: {err}"
         )),
     };
 
-    let target_config_ident = args.platform.config_toml_name();
-    let target_config = match util::rbrew_config_file(target_config_ident) {
+    let target_config = match util::rbrew_config_file(&platform.config_toml) {
         Ok(ok) => ok,
         Err(err) => graceful_error_exit(format!(
             "failed to find the config toml file for the platform: {err}"
         )),
     };
 
-    // cmd.arg(format!("--target={}", target_json.display()));
+    let linker_script = platform.linker_script.as_deref();
+    let default_flags = if args.no_default_flags {
+        vec![]
+    } else {
+        platform.default_rustflags()
+    };
+
+    // The ARM7 binary is built first, every ARM9 executable is assembled with it.
+    let arm7 = nds_layout
+        .as_ref()
+        .and_then(|layout| layout.arm7.as_deref())
+        .map(|package| {
+            let config = match util::rbrew_config_file(tools::nds::ARM7_CONFIG) {
+                Ok(ok) => ok,
+                Err(err) => graceful_error_exit(format!(
+                    "failed to find the config toml file for the ARM7: {err}"
+                )),
+            };
+            let built = cargo_build(
+                &args,
+                &*converter,
+                &config,
+                Some(tools::nds::ARM7_LINKER_SCRIPT),
+                Some(package),
+                &[],
+                verbosity,
+            );
+            let input = PathBuf::from(single_executable(package, built));
+            if !args.no_validate {
+                validate_layout(&input, &tools::nds::ARM7_LAYOUT, false);
+            }
+            input
+        });
+
+    // Each executable, with the name of its output.
+    let mut executables: Vec<(String, Option<String>)> = vec![];
+    match &dol_layout {
+        None => {
+            let package = match nds_layout
+                .as_ref()
+                .and_then(|layout| layout.arm9.as_deref())
+            {
+                Some(arm9) => Some(arm9),
+                None => args.package.as_deref(),
+            };
+            for executable in cargo_build(
+                &args,
+                &*converter,
+                &target_config,
+                linker_script,
+                package,
+                &default_flags,
+                verbosity,
+            ) {
+                executables.push((executable, None));
+            }
+        }
+        Some(layout) => {
+            let primary = cargo_build(
+                &args,
+                &*converter,
+                &target_config,
+                linker_script,
+                Some(&layout.primary),
+                &default_flags,
+                verbosity,
+            );
+            executables.push((
+                single_executable(&layout.primary, primary),
+                Some(tools::multi_dol::PRIMARY_NAME.to_string()),
+            ));
+            for secondary in &layout.secondary {
+                let built = cargo_build(
+                    &args,
+                    &*converter,
+                    &target_config,
+                    linker_script,
+                    Some(&secondary.package),
+                    &[&default_flags[..], &secondary.rustflags()].concat(),
+                    verbosity,
+                );
+                executables.push((
+                    single_executable(&secondary.package, built),
+                    Some(secondary.package.clone()),
+                ));
+            }
+        }
+    }
+
+    let options = converter::ConvertOptions {
+        arm7,
+        rel_base: args.rel_base.clone(),
+        rel_module_id: args.rel_module_id,
+        report: verbosity.should_output(Verbosity::Normal),
+    };
+    let description = converter.description().to_string();
+    let convert_error =
+        |err| graceful_error_exit(format!("failed to convert to {description}: {err}"));
+
+    // The profile's output directory is named after it, except for the dev profile's.
+    let target_dir = executables
+        .first()
+        .and_then(|(input, _)| Path::new(input).parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    let profile = match target_dir.file_name().and_then(OsStr::to_str) {
+        Some("debug") | None => "dev",
+        Some(profile) => profile,
+    };
+    let pipeline = match tools::pipeline::Pipeline::load(profile) {
+        Ok(ok) => ok,
+        Err(err) => graceful_error_exit(format!("failed to read the pipeline: {err}")),
+    };
+    let cache = tools::pipeline::Cache::new(target_dir.join(tools::pipeline::CACHE_DIR));
+    let mut package = None;
+
+    let mut artifacts = vec![];
+    for (index, (input, name)) in executables.into_iter().enumerate() {
+        let input = Path::new(&input);
+        let output_dir = args
+            .output_directory
+            .clone()
+            .unwrap_or(input.parent().map(Path::to_path_buf).unwrap_or_default());
+        let output_gennerated_name = format!("output{index}");
+        let output_name = match &name {
+            Some(name) => OsStr::new(name),
+            None => input
+                .file_stem()
+                .unwrap_or(OsStr::new(&output_gennerated_name)),
+        };
+
+        let output = converter.output_path(&output_dir, output_name);
+        if pipeline.packages() && package.is_none() {
+            package = Some((
+                output_dir.clone(),
+                output_dir.join(output_name).with_extension("zip"),
+            ));
+        }
+
+        if verbosity.should_output(Verbosity::Normal) {
+            println!("output file: {}", output.display());
+        }
+
+        let with_info;
+        let input = if args.build_info {
+            with_info = build_info_stage(&cache, input);
+            &with_info
+        } else {
+            input
+        };
+
+        if !args.no_validate {
+            validate_layout(input, &platform.layout, converter.relocatable());
+        }
+
+        if args.dolphin_map {
+            write_dolphin_map(input, &output.with_extension("map"), verbosity);
+        }
+
+        let mut elf = input.to_path_buf();
+        for stage in pipeline.before_convert() {
+            match stage {
+                Stage::Strip => elf = strip_stage(&cache, &elf, verbosity),
+                // Checked when the pipeline is read.
+                Stage::Convert | Stage::Compress(_) | Stage::Package => unreachable!(),
+            }
+        }
+
+        let input = converter::ConvertInput {
+            platform,
+            elf: &elf,
+            output: &output,
+            name: &output_name.to_string_lossy(),
+            index,
+        };
+        match converter.convert(&input, &options) {
+            Ok(converted) => artifacts.extend(converted),
+            Err(err) => convert_error(err),
+        }
+    }
+    match converter.finish(&options) {
+        Ok(converted) => artifacts.extend(converted),
+        Err(err) => convert_error(err),
+    }
+
+    let fixups = converter.fixups();
+    for artifact in &artifacts {
+        if let Err(err) = tools::fixup::apply(&artifact.path, fixups) {
+            graceful_error_exit(format!(
+                "failed to fix up '{}': {err}",
+                artifact.path.display()
+            ))
+        }
+    }
+
+    if let Some(image) = &romfs {
+        for artifact in &artifacts {
+            if artifact.path.extension() == Some(OsStr::new("dol")) {
+                romfs_stage(&artifact.path, image, &platform.layout.regions, verbosity);
+            }
+        }
+    }
+
+    if let Some(budget) = budget {
+        let mut exceeded = false;
+        for artifact in &artifacts {
+            exceeded |= check_budget(&budget, &artifact.elf, &artifact.path);
+        }
+        if exceeded && !budget.warn_only {
+            graceful_error_exit("the size budget was exceeded.")
+        }
+    }
+
+    let mut outputs: Vec<PathBuf> = artifacts
+        .into_iter()
+        .map(|artifact| artifact.path)
+        .collect();
+    for stage in pipeline.after_convert() {
+        match stage {
+            Stage::Compress(compression) => {
+                for output in &mut outputs {
+                    *output = compress_stage(&cache, output, *compression, verbosity);
+                }
+            }
+            Stage::Package => {
+                // Set for the first executable when the pipeline packages.
+                let (output_dir, package) = package.as_ref().unwrap();
+                package_stage(output_dir, package, &outputs, verbosity);
+                outputs = vec![package.clone()];
+            }
+            // Checked when the pipeline is read.
+            Stage::Strip | Stage::Convert => unreachable!(),
+        }
+    }
+}
+
+/// Writes the ELF with build information to the cache directory, returning the path of
+/// the copy.
+fn build_info_stage(cache: &tools::pipeline::Cache, input: &Path) -> PathBuf {
+    let output = cache
+        .dir()
+        .join(input.file_name().unwrap_or_default())
+        .with_extension("info");
+    let profile = input
+        .parent()
+        .and_then(Path::file_name)
+        .and_then(OsStr::to_str)
+        .unwrap_or("unknown");
+    let info = tools::build_info::HostBuildInfo::collect(profile);
+    let embedded = std::fs::create_dir_all(cache.dir())
+        .and_then(|()| tools::build_info::embed(input, &output, &info.as_build_info()));
+    if let Err(err) = embedded {
+        graceful_error_exit(format!("failed to embed build info: {err}"))
+    }
+    output
+}
+
+/// Writes the ELF without its debug information to the cache directory, returning the
+/// path of the stripped ELF.
+fn strip_stage(cache: &tools::pipeline::Cache, input: &Path, verbosity: Verbosity) -> PathBuf {
+    let output = cache
+        .dir()
+        .join(input.file_name().unwrap_or_default())
+        .with_extension("stripped");
+    let data = read_input(input);
+    let ran = std::fs::create_dir_all(cache.dir()).and_then(|()| {
+        cache.run("strip", &data, &output, |data| {
+            tools::strip::strip_debug(&elf::Elf::parse(data.to_vec())?)
+        })
+    });
+    match ran {
+        Ok(false) if verbosity.should_output(Verbosity::Verbose) => {
+            println!("stripped file is up to date: {}", output.display())
+        }
+        Ok(_) => {}
+        Err(err) => graceful_error_exit(format!("failed to strip '{}': {err}", input.display())),
+    }
+    output
+}
+
+/// Adds the packed romfs to a converted DOL, in place.
+fn romfs_stage(
+    path: &Path,
+    image: &[u8],
+    regions: &[tools::validate::MemoryRegion],
+    verbosity: Verbosity,
+) {
+    let mut dol = read_dol(path);
+    match tools::romfs::embed(&mut dol, image.to_vec(), regions) {
+        Ok(address) => {
+            if verbosity.should_output(Verbosity::Normal) {
+                println!(
+                    "romfs: 0x{:x} bytes at 0x{address:08x} in {}",
+                    image.len(),
+                    path.display()
+                );
+            }
+        }
+        Err(err) => graceful_error_exit(format!(
+            "failed to add the romfs to '{}': {err}",
+            path.display()
+        )),
+    }
+    if let Err(err) = std::fs::write(path, dol.to_bytes()) {
+        graceful_error_exit(format!("failed to write '{}': {err}", path.display()))
+    }
+}
+
+/// Compresses an output next to it, returning the path of the compressed file.
+fn compress_stage(
+    cache: &tools::pipeline::Cache,
+    input: &Path,
+    compression: tools::compress::Compression,
+    verbosity: Verbosity,
+) -> PathBuf {
+    let mut name = input.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(compression.extension_name());
+    let output = input.with_file_name(name);
+    let data = read_input(input);
+    let stage = format!("compress-{}", compression.name());
+    match cache.run(&stage, &data, &output, |data| compression.compress(data)) {
+        Ok(ran) => {
+            if verbosity.should_output(Verbosity::Normal) {
+                let state = if ran { "" } else { " (up to date)" };
+                println!("compressed file: {}{state}", output.display());
+            }
+        }
+        Err(err) => graceful_error_exit(format!("failed to compress '{}': {err}", input.display())),
+    }
+    output
+}
+
+/// Zips the outputs up, named by their paths in the output directory.
+fn package_stage(output_dir: &Path, package: &Path, outputs: &[PathBuf], verbosity: Verbosity) {
+    let files: Vec<(String, Vec<u8>)> = outputs
+        .iter()
+        .map(|path| {
+            let name = path
+                .strip_prefix(output_dir)
+                .ok()
+                .or_else(|| path.file_name().map(Path::new))
+                .unwrap_or(path);
+            (name.to_string_lossy().into_owned(), read_input(path))
+        })
+        .collect();
+    if let Err(err) = tools::package::write_zip(package, &files) {
+        graceful_error_exit(format!("failed to write '{}': {err}", package.display()))
+    }
+    if verbosity.should_output(Verbosity::Normal) {
+        println!("package: {}", package.display());
+    }
+}
+
+/// Returns the only executable a package declared in rbrew.toml built.
+fn single_executable(package: &str, executables: Vec<String>) -> String {
+    match <[String; 1]>::try_from(executables) {
+        Ok([executable]) => executable,
+        Err(_) => graceful_error_exit(format!(
+            "the package '{package}' must build exactly one executable."
+        )),
+    }
+}
+
+fn validate_layout(input: &Path, rules: &tools::validate::LayoutRules, relocatable: bool) {
+    match tools::validate::validate(input, rules, relocatable) {
+        Ok(issues) if issues.is_empty() => {}
+        Ok(issues) => graceful_error_exit(format!(
+            "'{}' failed layout validation:\n  - {}\nUse `--no-validate` to convert it anyway.",
+            input.display(),
+            issues.join("\n  - ")
+        )),
+        Err(err) => graceful_error_exit(format!("failed to validate '{}': {err}", input.display())),
+    }
+}
+
+/// Runs `cargo build` for the platform and returns the paths of the built executables.
+fn cargo_build(
+    args: &RbrewCliSubBuild,
+    converter: &dyn converter::OutputConverter,
+    target_config: &Path,
+    linker_script: Option<&str>,
+    package: Option<&str>,
+    extra_rustflags: &[String],
+    verbosity: Verbosity,
+) -> Vec<String> {
+    let mut cmd = util::cargo();
+    cmd.arg("build");
+    if let Some(package) = package {
+        cmd.arg("--package").arg(package);
+    }
+    if args.workspace {
+        cmd.arg("--workspace");
+    }
+
+    // cmd.arg(format!("--target={}", _target_json.display()));
     cmd.arg(format!("--config={}", target_config.display()));
 
+    let mut rustflags: Vec<String> = converter
+        .rustflags()
+        .iter()
+        .map(ToString::to_string)
+        .chain(extra_rustflags.iter().cloned())
+        .collect();
+    if let Some(name) = linker_script {
+        match util::rbrew_target_file(name) {
+            Ok(script) => {
+                rustflags.extend(["-C".to_string(), format!("link-arg=-T{}", script.display())])
+            }
+            Err(err) => graceful_error_exit(format!(
+                "failed to find the linker script for the platform: {err}"
+            )),
+        }
+    }
+    if !rustflags.is_empty() {
+        let flags: Vec<String> = rustflags.iter().map(|flag| format!("{flag:?}")).collect();
+        cmd.arg("--config")
+            .arg(format!("build.rustflags=[{}]", flags.join(", ")));
+    }
+
     for option in &args.custom_options {
         cmd.arg(option);
     }
@@ -295,33 +1161,265 @@ fn build(args: RbrewCliSubBuild, verbosity: Verbosity) {
         }
     }
 
-    for (gen, input) in output_executable.into_iter().enumerate() {
-        let input = Path::new(&input);
-        let output_dir = args
-            .output_directory
-            .clone()
-            .unwrap_or(input.parent().map(Path::to_path_buf).unwrap_or_default());
-        let output_gennerated_name = format!("output{gen}");
-        let output_name = input
-            .file_stem()
-            .unwrap_or(OsStr::new(&output_gennerated_name));
+    output_executable
+}
 
-        let mut output = output_dir.join(output_name);
-        output.set_extension(args.output_type.extension_name());
+/// Checks one output against the budget and records its sizes for the next build.
+/// Returns whether the budget was exceeded.
+fn check_budget(budget: &tools::budget::Budget, input: &Path, output: &Path) -> bool {
+    let sizes = match tools::budget::measure(budget, input, output) {
+        Ok(ok) => ok,
+        Err(err) => graceful_error_exit(format!("failed to measure '{}': {err}", output.display())),
+    };
+    let record = output.with_extension("sizes");
+    let previous = tools::budget::read_record(&record);
+    if let Err(err) = tools::budget::write_record(&record, &sizes) {
+        graceful_error_exit(format!("failed to write '{}': {err}", record.display()))
+    }
 
-        if verbosity.should_output(Verbosity::Normal) {
-            println!("output file: {}", output.display());
+    match tools::budget::check(budget, &sizes, &previous) {
+        Some(report) => {
+            let level = if budget.warn_only { "warning" } else { "error" };
+            eprintln!(
+                "{level}: '{}' exceeds its size budget:\n{report}",
+                output.display()
+            );
+            true
+        }
+        None => false,
+    }
+}
+
+fn tools(args: RbrewCliSubTools, verbosity: Verbosity) {
+    match args.subcommand {
+        RbrewCliSubToolsSub::Patch(args) => tools_patch(args, verbosity),
+        RbrewCliSubToolsSub::Info(args) => tools_info(args, verbosity),
+        RbrewCliSubToolsSub::Map(args) => tools_map(args, verbosity),
+        RbrewCliSubToolsSub::Cheat(args) => tools_cheat(args, verbosity),
+        RbrewCliSubToolsSub::Apploader(args) => tools_apploader(args, verbosity),
+        RbrewCliSubToolsSub::Dol(args) => tools_dol(args, verbosity),
+    }
+}
+
+fn read_input(path: &Path) -> Vec<u8> {
+    match std::fs::read(path) {
+        Ok(ok) => ok,
+        Err(err) => graceful_error_exit(format!("failed to read '{}': {err}", path.display())),
+    }
+}
+
+fn tools_patch(args: RbrewCliSubToolsPatch, verbosity: Verbosity) {
+    let base = read_input(&args.base);
+    let target = read_input(&args.target);
+
+    let patch = match args.format {
+        fields::PatchFormat::Ips => match tools::patch::ips(&base, &target) {
+            Ok(ok) => ok,
+            Err(err) => graceful_error_exit(format!("failed to create patch: {err}")),
+        },
+        fields::PatchFormat::Bps => tools::patch::bps(&base, &target),
+    };
+
+    let output = args
+        .output
+        .unwrap_or_else(|| args.target.with_extension(args.format.extension_name()));
+    if let Err(err) = std::fs::write(&output, patch) {
+        graceful_error_exit(format!("failed to write '{}': {err}", output.display()))
+    }
+    if verbosity.should_output(Verbosity::Normal) {
+        println!("output file: {}", output.display());
+    }
+}
+
+fn tools_info(args: RbrewCliSubToolsInfo, _verbosity: Verbosity) {
+    let data = read_input(&args.artifact);
+    match tools::build_info::find(&data) {
+        tools::build_info::Found::Filled(info) => {
+            println!("git hash:      {}", info.git_hash);
+            println!("timestamp:     {}", info.timestamp);
+            println!("profile:       {}", info.profile);
+            println!("rbrew version: {}", info.rbrew_version);
+        }
+        tools::build_info::Found::Unfilled => graceful_error_exit(
+            "the artifact reserves build info but was built without `--build-info`.",
+        ),
+        tools::build_info::Found::Missing => {
+            graceful_error_exit("the artifact contains no build info.")
         }
+    }
+}
+
+fn write_dolphin_map(input: &Path, output: &Path, verbosity: Verbosity) {
+    let map = match tools::elf2map(input) {
+        Ok(ok) => ok,
+        Err(err) => graceful_error_exit(format!("failed to create symbol map: {err}")),
+    };
+    if let Err(err) = std::fs::write(output, map) {
+        graceful_error_exit(format!("failed to write '{}': {err}", output.display()))
+    }
+    if verbosity.should_output(Verbosity::Normal) {
+        println!("symbol map: {}", output.display());
+    }
+}
 
-        match args.output_type {
-            fields::OutputType::Elf => {
-                std::fs::copy(input, output).unwrap();
+fn tools_map(args: RbrewCliSubToolsMap, verbosity: Verbosity) {
+    let output = args
+        .output
+        .unwrap_or_else(|| args.input.with_extension("map"));
+    write_dolphin_map(&args.input, &output, verbosity);
+}
+
+fn tools_cheat(args: RbrewCliSubToolsCheat, verbosity: Verbosity) {
+    use tools::cheat;
+
+    let elf = match elf::Elf::read(&args.elf) {
+        Ok(ok) => ok,
+        Err(err) => graceful_error_exit(format!("failed to read '{}': {err}", args.elf.display())),
+    };
+    let symbol = match cheat::resolve_symbol(&elf, &args.symbol) {
+        Ok(ok) => ok,
+        Err(err) => graceful_error_exit(err),
+    };
+    let offset = match args.offset.as_deref().map(cheat::parse_u32) {
+        Some(Ok(ok)) => ok,
+        Some(Err(err)) => graceful_error_exit(err),
+        None => 0,
+    };
+    let Some(address) = u32::try_from(symbol.value)
+        .ok()
+        .and_then(|value| value.checked_add(offset))
+    else {
+        graceful_error_exit(format!(
+            "'{}' (0x{:x}) plus the offset 0x{offset:x} is not a 32-bit address.",
+            args.symbol, symbol.value
+        ))
+    };
+
+    let patch = match (args.value, args.bytes) {
+        (Some(value), None) => {
+            let value = cheat::parse_u32(&value).unwrap_or_else(|err| graceful_error_exit(err));
+            let width = args.width.unwrap_or(match symbol.size {
+                1 | 2 | 4 => symbol.size as u32,
+                _ => 4,
+            });
+            cheat::Patch::Value { value, width }
+        }
+        (None, Some(bytes)) => cheat::Patch::Bytes(
+            cheat::parse_hex_bytes(&bytes).unwrap_or_else(|err| graceful_error_exit(err)),
+        ),
+        _ => graceful_error_exit("expected exactly one of `--value` or `--bytes`."),
+    };
+
+    let lines = match args.format {
+        fields::CheatFormat::Gecko => cheat::gecko(address, &patch),
+        fields::CheatFormat::ActionReplay => cheat::action_replay(address, &patch),
+    };
+    let lines = lines.unwrap_or_else(|err| graceful_error_exit(err));
+
+    if verbosity.should_output(Verbosity::Verbose) {
+        println!("{} at 0x{address:08x}", symbol.name);
+    }
+    for (a, b) in lines {
+        println!("{a:08X} {b:08X}");
+    }
+}
+
+fn tools_apploader(args: RbrewCliSubToolsApploader, verbosity: Verbosity) {
+    let dol_offset =
+        tools::cheat::parse_u32(&args.dol_offset).unwrap_or_else(|err| graceful_error_exit(err));
+    let dol = read_input(&args.dol);
+    let date = tools::build_info::timestamp()[..10].replace('-', "/");
+    let apploader = match tools::apploader::generate(&dol, dol_offset, &date) {
+        Ok(ok) => ok,
+        Err(err) => graceful_error_exit(format!("failed to generate the apploader: {err}")),
+    };
+
+    let output = args
+        .output
+        .unwrap_or_else(|| args.dol.with_file_name("apploader.img"));
+    if verbosity.should_output(Verbosity::Normal) {
+        println!("output file: {}", output.display());
+    }
+    if let Err(err) = std::fs::write(&output, apploader) {
+        graceful_error_exit(format!("failed to write '{}': {err}", output.display()))
+    }
+}
+
+fn tools_dol(args: RbrewCliSubToolsDol, verbosity: Verbosity) {
+    match args.subcommand {
+        RbrewCliSubToolsDolSub::Info(args) => {
+            let data = read_input(&args.dol);
+            match tools::dol::section_table(&data) {
+                Ok(table) => print!("{table}"),
+                Err(err) => {
+                    graceful_error_exit(format!("failed to read '{}': {err}", args.dol.display()))
+                }
             }
-            fields::OutputType::Dol => {
-                tools::elf2dol(input, output).unwrap();
+        }
+        RbrewCliSubToolsDolSub::Extract(args) => {
+            let data = read_input(&args.dol);
+            let section = match tools::dol::extract(&data, &args.section) {
+                Ok(ok) => ok,
+                Err(err) => {
+                    graceful_error_exit(format!("failed to read '{}': {err}", args.dol.display()))
+                }
+            };
+            let output = args
+                .output
+                .unwrap_or_else(|| args.dol.with_extension(&args.section));
+            write_dol_output(&output, section, verbosity);
+        }
+        RbrewCliSubToolsDolSub::Patch(args) => {
+            let address = tools::cheat::parse_u32(&args.address)
+                .unwrap_or_else(|err| graceful_error_exit(err));
+            let bytes = dol_tool_bytes(args.bytes, args.file.as_deref());
+            let mut dol = read_dol(&args.dol);
+            if let Err(err) = tools::dol::patch(&mut dol, address, &bytes) {
+                graceful_error_exit(format!("failed to patch '{}': {err}", args.dol.display()))
             }
+            let output = args.output.unwrap_or(args.dol);
+            write_dol_output(&output, dol.to_bytes(), verbosity);
+        }
+        RbrewCliSubToolsDolSub::Inject(args) => {
+            let address = tools::cheat::parse_u32(&args.address)
+                .unwrap_or_else(|err| graceful_error_exit(err));
+            let bytes = dol_tool_bytes(args.bytes, args.file.as_deref());
+            let mut dol = read_dol(&args.dol);
+            if let Err(err) = tools::dol::inject(&mut dol, address, bytes, args.text) {
+                graceful_error_exit(format!(
+                    "failed to inject into '{}': {err}",
+                    args.dol.display()
+                ))
+            }
+            let output = args.output.unwrap_or(args.dol);
+            write_dol_output(&output, dol.to_bytes(), verbosity);
         }
     }
 }
 
-fn tools(_args: RbrewCliSubTools, _verbosity: Verbosity) {}
+fn read_dol(path: &Path) -> tools::Dol {
+    match tools::dol::parse(&read_input(path)) {
+        Ok(ok) => ok,
+        Err(err) => graceful_error_exit(format!("failed to read '{}': {err}", path.display())),
+    }
+}
+
+/// The data given to a DOL tool either inline or as a file.
+fn dol_tool_bytes(bytes: Option<String>, file: Option<&Path>) -> Vec<u8> {
+    match (bytes, file) {
+        (Some(bytes), None) => {
+            tools::cheat::parse_hex_bytes(&bytes).unwrap_or_else(|err| graceful_error_exit(err))
+        }
+        (None, Some(file)) => read_input(file),
+        _ => graceful_error_exit("expected exactly one of `--bytes` or `--file`."),
+    }
+}
+
+fn write_dol_output(output: &Path, data: Vec<u8>, verbosity: Verbosity) {
+    if let Err(err) = std::fs::write(output, data) {
+        graceful_error_exit(format!("failed to write '{}': {err}", output.display()))
+    }
+    if verbosity.should_output(Verbosity::Normal) {
+        println!("output file: {}", output.display());
+    }
+}
@@ -0,0 +1,307 @@
+//! The platforms rbrew builds for, described by the TOML files in `configs/platforms`.
+//!
+//! Each file names the platform, the aliases `--platform` also accepts, the target JSON
+//! and cargo config it builds with, and the rules its linked ELFs are validated against:
+//!
+//! ```toml
+//! name = "gamecube"
+//! aliases = ["gc"]
+//! description = "Nintendo GameCube"
+//! target = "gamecube.json"
+//! config = "gamecube.toml"
+//! # Optional, from the targets directory.
+//! linker_script = "gamecube.ld"
+//! outputs = ["dol", "rel"]
+//! # Programs that run the outputs, looked for on the `PATH` by `rbrew platforms`.
+//! emulators = ["dolphin-emu"]
+//!
+//! # Passed to rustc unless building with `--no-default-flags`.
+//! [flags]
+//! target_cpu = "750"
+//! # "soft" or "hard", not available on AArch64 and M68k.
+//! float_abi = "hard"
+//! # Console programs are loaded whole rather than paged in, so the platforms link with
+//! # `--nmagic`, which keeps the linker from page aligning their segments in the file.
+//! link_args = ["--nmagic"]
+//!
+//! [layout]
+//! machine = "ppc"
+//! load_alignment = 32
+//! position_independent = false
+//!
+//! [[layout.regions]]
+//! name = "MEM1"
+//! start = 0x8000_3100
+//! end = 0x8180_0000
+//! ```
+//!
+//! `outputs` lists the platform specific output types; those that work for any ELF, such
+//! as `elf` and `bin`, are supported everywhere. A new platform is a file here, its
+//! target files and, if it has its own output type, a converter.
+
+use crate::{
+    config, elf,
+    tools::validate::{LayoutRules, MemoryRegion},
+};
+use std::{borrow::Cow, io, path::Path};
+
+/// A platform as described by its file.
+pub struct Platform {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub description: String,
+    /// The target JSON in the targets directory.
+    pub target_json: String,
+    /// The cargo config in the configs directory.
+    pub config_toml: String,
+    /// The linker script in the targets directory the platform links with, if any.
+    pub linker_script: Option<String>,
+    /// The platform specific output types.
+    pub outputs: Vec<String>,
+    pub emulators: Vec<String>,
+    pub flags: DefaultFlags,
+    pub layout: LayoutRules,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FloatAbi {
+    Soft,
+    Hard,
+}
+
+/// The codegen and linker flags a platform builds with by default.
+#[derive(Default)]
+pub struct DefaultFlags {
+    pub target_cpu: Option<String>,
+    pub float_abi: Option<FloatAbi>,
+    pub link_args: Vec<String>,
+}
+
+/// The ELF machine and the static relocations the validation allows for each machine
+/// a platform can declare.
+fn machine(name: &str) -> Option<(u16, &'static [u32])> {
+    Some(match name {
+        "arm" => (elf::EM_ARM, elf::ARM_STATIC_RELOCATIONS),
+        // AArch64 platforms load programs anywhere and keep their self relocations.
+        "aarch64" => (elf::EM_AARCH64, elf::AARCH64_SELF_RELOCATIONS),
+        "mips" => (elf::EM_MIPS, elf::MIPS_STATIC_RELOCATIONS),
+        "m68k" => (elf::EM_68K, elf::M68K_STATIC_RELOCATIONS),
+        "ppc" => (elf::EM_PPC, elf::PPC_STATIC_RELOCATIONS),
+        _ => return None,
+    })
+}
+
+/// The target feature selecting a float ABI, which LLVM names differently per machine.
+fn float_feature(machine: u16, abi: FloatAbi) -> Option<&'static str> {
+    Some(match (machine, abi) {
+        (elf::EM_PPC, FloatAbi::Soft) => "-hard-float",
+        (elf::EM_PPC, FloatAbi::Hard) => "+hard-float",
+        (elf::EM_ARM | elf::EM_MIPS, FloatAbi::Soft) => "+soft-float",
+        (elf::EM_ARM | elf::EM_MIPS, FloatAbi::Hard) => "-soft-float",
+        _ => return None,
+    })
+}
+
+impl Platform {
+    /// Parses the description of a platform, `file` naming it in errors.
+    pub fn parse(text: &str, file: &str) -> io::Result<Self> {
+        let invalid = |msg: String| config::invalid(format!("'{file}': {msg}"));
+        let table: toml::Table = text
+            .parse()
+            .map_err(|err| invalid(format!("not valid TOML: {err}")))?;
+        let string = |table: &toml::Table, key: &str| match table.get(key) {
+            Some(toml::Value::String(value)) => Ok(value.clone()),
+            _ => Err(invalid(format!("'{key}' must be a string"))),
+        };
+        let strings = |key: &str| match table.get(key) {
+            None => Ok(vec![]),
+            Some(toml::Value::Array(values)) => values
+                .iter()
+                .map(|value| value.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| invalid(format!("'{key}' must be a list of strings"))),
+            Some(_) => Err(invalid(format!("'{key}' must be a list of strings"))),
+        };
+
+        let flags = match table.get("flags") {
+            None => DefaultFlags::default(),
+            Some(toml::Value::Table(flags)) => DefaultFlags {
+                target_cpu: match flags.get("target_cpu") {
+                    None => None,
+                    Some(_) => Some(string(flags, "target_cpu")?),
+                },
+                float_abi: match flags.get("float_abi").map(toml::Value::as_str) {
+                    None => None,
+                    Some(Some("soft")) => Some(FloatAbi::Soft),
+                    Some(Some("hard")) => Some(FloatAbi::Hard),
+                    Some(_) => {
+                        return Err(invalid(
+                            "'flags.float_abi' must be \"soft\" or \"hard\"".to_string(),
+                        ))
+                    }
+                },
+                link_args: match flags.get("link_args") {
+                    None => vec![],
+                    Some(toml::Value::Array(args)) => args
+                        .iter()
+                        .map(|arg| arg.as_str().map(str::to_string))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| {
+                            invalid("'flags.link_args' must be a list of strings".to_string())
+                        })?,
+                    Some(_) => {
+                        return Err(invalid(
+                            "'flags.link_args' must be a list of strings".to_string(),
+                        ))
+                    }
+                },
+            },
+            Some(_) => return Err(invalid("'flags' must be a table".to_string())),
+        };
+
+        let Some(toml::Value::Table(layout)) = table.get("layout") else {
+            return Err(invalid("'layout' must be a table".to_string()));
+        };
+        let machine_name = string(layout, "machine")?;
+        let (machine, relocations) = machine(&machine_name)
+            .ok_or_else(|| invalid(format!("unknown machine '{machine_name}'")))?;
+        if flags.float_abi.is_some() && float_feature(machine, FloatAbi::Hard).is_none() {
+            return Err(invalid(format!(
+                "'flags.float_abi' cannot be set for the '{machine_name}' machine"
+            )));
+        }
+        let load_alignment = layout
+            .get("load_alignment")
+            .and_then(config::parse_size)
+            .filter(|alignment| alignment.is_power_of_two())
+            .ok_or_else(|| invalid("'layout.load_alignment' must be a power of two".to_string()))?;
+        let position_independent = match layout.get("position_independent") {
+            None => false,
+            Some(toml::Value::Boolean(value)) => *value,
+            Some(_) => {
+                return Err(invalid(
+                    "'layout.position_independent' must be a boolean".to_string(),
+                ))
+            }
+        };
+        let regions = match layout.get("regions") {
+            Some(toml::Value::Array(regions)) => regions
+                .iter()
+                .map(|region| {
+                    let region = region
+                        .as_table()
+                        .ok_or_else(|| invalid("regions must be tables".to_string()))?;
+                    let address = |key: &str| {
+                        region.get(key).and_then(config::parse_size).ok_or_else(|| {
+                            invalid(format!("the region's '{key}' is not a valid address"))
+                        })
+                    };
+                    Ok(MemoryRegion {
+                        name: Cow::Owned(string(region, "name")?),
+                        start: address("start")?,
+                        end: address("end")?,
+                    })
+                })
+                .collect::<io::Result<Vec<_>>>()?,
+            _ => {
+                return Err(invalid(
+                    "'layout.regions' must list the memory regions".to_string(),
+                ))
+            }
+        };
+
+        Ok(Self {
+            name: string(&table, "name")?,
+            aliases: strings("aliases")?,
+            description: string(&table, "description")?,
+            target_json: string(&table, "target")?,
+            config_toml: string(&table, "config")?,
+            linker_script: match table.get("linker_script") {
+                None => None,
+                Some(_) => Some(string(&table, "linker_script")?),
+            },
+            outputs: strings("outputs")?,
+            emulators: strings("emulators")?,
+            flags,
+            layout: LayoutRules {
+                machine,
+                regions: Cow::Owned(regions),
+                load_alignment,
+                relocations,
+                position_independent,
+            },
+        })
+    }
+
+    /// The rustc flags selecting the platform's default CPU, float ABI and link args.
+    pub fn default_rustflags(&self) -> Vec<String> {
+        let mut rustflags = vec![];
+        if let Some(cpu) = &self.flags.target_cpu {
+            rustflags.extend(["-C".to_string(), format!("target-cpu={cpu}")]);
+        }
+        if let Some(feature) = self
+            .flags
+            .float_abi
+            .and_then(|abi| float_feature(self.layout.machine, abi))
+        {
+            rustflags.extend(["-C".to_string(), format!("target-feature={feature}")]);
+        }
+        for arg in &self.flags.link_args {
+            rustflags.extend(["-C".to_string(), format!("link-arg={arg}")]);
+        }
+        rustflags
+    }
+
+    /// Whether `--platform` selects the platform with `name`.
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+}
+
+/// The platforms rbrew knows of.
+#[derive(Default)]
+pub struct Registry {
+    platforms: Vec<Platform>,
+}
+
+impl Registry {
+    /// Reads the platforms bundled with rbrew.
+    pub fn bundled() -> io::Result<Self> {
+        Self::load(&crate::util::rbrew_config_file("platforms")?)
+    }
+
+    /// Reads every platform described in a directory, in the order of their names.
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<_>>()?;
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "toml"));
+        paths.sort();
+
+        let mut registry = Self::default();
+        for path in paths {
+            let text = std::fs::read_to_string(&path)?;
+            let platform = Platform::parse(&text, &path.display().to_string())?;
+            let names = std::iter::once(&platform.name).chain(&platform.aliases);
+            if let Some(name) = names.into_iter().find(|name| registry.get(name).is_some()) {
+                return Err(config::invalid(format!(
+                    "'{}': the platform name '{name}' is already taken",
+                    path.display()
+                )));
+            }
+            registry.platforms.push(platform);
+        }
+        Ok(registry)
+    }
+
+    /// The platform with a name or alias.
+    pub fn get(&self, name: &str) -> Option<&Platform> {
+        self.platforms
+            .iter()
+            .find(|platform| platform.is_named(name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Platform> {
+        self.platforms.iter()
+    }
+}
@@ -1,2 +1,42 @@
+pub mod apploader;
+pub mod bin;
+pub mod budget;
+pub mod build_info;
+pub mod cheat;
+pub mod compress;
+pub mod dol;
+mod dolphin_map;
 mod elf2dol;
-pub use elf2dol::elf2dol;
+mod elf2rel;
+pub mod fixup;
+pub mod gba;
+pub mod genesis;
+pub mod multi_dol;
+pub mod n64;
+pub mod nds;
+pub mod nro;
+pub mod package;
+pub mod patch;
+pub mod pipeline;
+pub mod psexe;
+pub mod psp;
+pub mod records;
+pub mod romfs;
+pub mod rpx;
+pub mod strip;
+pub mod threedsx;
+pub mod validate;
+pub use bin::elf2bin;
+pub use dolphin_map::elf2map;
+pub use elf2dol::{elf2dol, Dol};
+pub use elf2rel::{elf2rel, RelModule};
+pub use gba::elf2gba;
+pub use genesis::elf2md;
+pub use n64::elf2z64;
+pub use nds::elf2nds;
+pub use nro::elf2nro;
+pub use psexe::elf2psexe;
+pub use psp::elf2pbp;
+pub use records::elf2records;
+pub use rpx::elf2rpx;
+pub use threedsx::elf2threedsx;
@@ -0,0 +1,345 @@
+//! Generation of apploaders, which the IPL runs to load the main DOL of a disc.
+//!
+//! The generated apploader is table driven: rbrew reads the DOL's header at generation
+//! time and stores the disc offset, load address and size of every section, so the
+//! PowerPC code only has to clear the bss, hand the entries to the IPL one by one and
+//! return the entry point.
+
+use std::io;
+
+/// Where the IPL loads the apploader's code.
+pub const BASE: u32 = 0x8120_0000;
+const HEADER_SIZE: usize = 0x20;
+/// DVD reads must start at 32-byte aligned addresses and cover whole 32-byte blocks.
+const DVD_ALIGN: u32 = 32;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// A minimal PowerPC assembler for the handful of instructions the apploader needs.
+#[derive(Default)]
+struct Asm {
+    code: Vec<u32>,
+    labels: Vec<Option<usize>>,
+    /// Branches waiting for their label, as `(instruction index, label, mask)`.
+    fixups: Vec<(usize, usize, u32)>,
+}
+
+const R0: u32 = 0;
+const R3: u32 = 3;
+const R4: u32 = 4;
+const R5: u32 = 5;
+const R6: u32 = 6;
+const R7: u32 = 7;
+const R8: u32 = 8;
+const R9: u32 = 9;
+const R10: u32 = 10;
+
+impl Asm {
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn bind(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    fn here(&self) -> u32 {
+        BASE + self.code.len() as u32 * 4
+    }
+
+    fn d_form(&mut self, op: u32, rd: u32, ra: u32, imm: u32) {
+        self.code
+            .push((op << 26) | (rd << 21) | (ra << 16) | (imm & 0xffff));
+    }
+
+    fn x_form(&mut self, rd: u32, ra: u32, rb: u32, xo: u32) {
+        self.code
+            .push((31 << 26) | (rd << 21) | (ra << 16) | (rb << 11) | (xo << 1));
+    }
+
+    fn li(&mut self, rd: u32, value: i16) {
+        self.d_form(14, rd, R0, value as u32);
+    }
+
+    fn addi(&mut self, rd: u32, ra: u32, value: i16) {
+        self.d_form(14, rd, ra, value as u32);
+    }
+
+    /// Loads a 32-bit constant.
+    fn load(&mut self, rd: u32, value: u32) {
+        self.d_form(15, rd, R0, value >> 16);
+        self.d_form(24, rd, rd, value & 0xffff);
+    }
+
+    fn lwz(&mut self, rd: u32, offset: i16, ra: u32) {
+        self.d_form(32, rd, ra, offset as u32);
+    }
+
+    fn stw(&mut self, rs: u32, offset: i16, ra: u32) {
+        self.d_form(36, rs, ra, offset as u32);
+    }
+
+    fn stb(&mut self, rs: u32, offset: i16, ra: u32) {
+        self.d_form(38, rs, ra, offset as u32);
+    }
+
+    fn add(&mut self, rd: u32, ra: u32, rb: u32) {
+        self.x_form(rd, ra, rb, 266);
+    }
+
+    fn cmpwi(&mut self, ra: u32, value: i16) {
+        self.d_form(11, 0, ra, value as u32);
+    }
+
+    fn cmplw(&mut self, ra: u32, rb: u32) {
+        self.x_form(0, ra, rb, 32);
+    }
+
+    /// Clears the low 5 bits, aligning down to a cache line.
+    fn align_line(&mut self, ra: u32, rs: u32) {
+        self.code
+            .push((21 << 26) | (rs << 21) | (ra << 16) | (26 << 1));
+    }
+
+    /// Writes back and invalidates the data cache line at the address in `rb`.
+    fn dcbf(&mut self, rb: u32) {
+        self.x_form(0, 0, rb, 86);
+    }
+
+    fn icbi(&mut self, rb: u32) {
+        self.x_form(0, 0, rb, 982);
+    }
+
+    fn sync(&mut self) {
+        self.code.push(0x7c00_04ac);
+    }
+
+    fn isync(&mut self) {
+        self.code.push(0x4c00_012c);
+    }
+
+    fn blr(&mut self) {
+        self.code.push(0x4e80_0020);
+    }
+
+    fn branch(&mut self, base: u32, label: usize, mask: u32) {
+        self.fixups.push((self.code.len(), label, mask));
+        self.code.push(base);
+    }
+
+    fn b(&mut self, label: usize) {
+        self.branch(18 << 26, label, 0x03ff_fffc);
+    }
+
+    /// Branches if the last comparison was equal.
+    fn beq(&mut self, label: usize) {
+        self.branch((16 << 26) | (12 << 21) | (2 << 16), label, 0xfffc);
+    }
+
+    /// Branches if the last comparison was less than.
+    fn blt(&mut self, label: usize) {
+        self.branch((16 << 26) | (12 << 21), label, 0xfffc);
+    }
+
+    fn finish(mut self) -> Vec<u32> {
+        for (at, label, mask) in self.fixups {
+            let target = self.labels[label].expect("branch to an unbound label");
+            let disp = (target as i64 - at as i64) * 4;
+            self.code[at] |= disp as u32 & mask;
+        }
+        self.code
+    }
+}
+
+/// Offsets of the fields in the apploader's data block.
+const STATE_INDEX: i16 = 0;
+const STATE_ENTRY: i16 = 4;
+const STATE_BSS_ADDRESS: i16 = 8;
+const STATE_BSS_SIZE: i16 = 12;
+const STATE_TABLE: i16 = 16;
+/// Every table entry holds the load address, size and disc offset of one read.
+const ENTRY_SIZE: i16 = 12;
+
+/// Assembles the apploader code for a data block at `state`.
+fn assemble(state: u32) -> Vec<u32> {
+    let mut asm = Asm::default();
+    let (init, main, close) = (asm.label(), asm.label(), asm.label());
+    let mut addresses = [0; 3];
+
+    // The entry point receives pointers to store the three callbacks in. The addresses
+    // are only known after assembling, so a first pass determines them.
+    let entry = |asm: &mut Asm, addresses: &[u32; 3]| {
+        for (reg, address) in [R3, R4, R5].into_iter().zip(addresses) {
+            asm.load(R6, *address);
+            asm.stw(R6, 0, reg);
+        }
+        asm.blr();
+    };
+    entry(&mut asm, &addresses);
+
+    // init(report): clears the bss before any section is read, like rbrew-gc's loader.
+    // The DOL has a single bss range, which may cover data sections lying between the bss
+    // pieces. The cleared lines are flushed, so none is written back over what the reads
+    // store in memory.
+    asm.bind(init);
+    addresses[0] = asm.here();
+    let (clear, flush, cleared) = (asm.label(), asm.label(), asm.label());
+    asm.load(R6, state);
+    asm.lwz(R7, STATE_BSS_ADDRESS, R6);
+    asm.lwz(R8, STATE_BSS_SIZE, R6);
+    asm.cmpwi(R8, 0);
+    asm.beq(cleared);
+    asm.add(R9, R7, R8);
+    asm.li(R10, 0);
+    asm.bind(clear);
+    asm.stb(R10, 0, R7);
+    asm.addi(R7, R7, 1);
+    asm.cmplw(R7, R9);
+    asm.blt(clear);
+    asm.lwz(R7, STATE_BSS_ADDRESS, R6);
+    asm.align_line(R7, R7);
+    asm.bind(flush);
+    asm.dcbf(R7);
+    asm.addi(R7, R7, 32);
+    asm.cmplw(R7, R9);
+    asm.blt(flush);
+    asm.sync();
+    asm.bind(cleared);
+    asm.blr();
+
+    // main(&address, &size, &offset): requests the next read, returning 0 once done.
+    asm.bind(main);
+    addresses[1] = asm.here();
+    let done = asm.label();
+    asm.load(R6, state);
+    asm.lwz(R7, STATE_INDEX, R6);
+    asm.addi(R8, R6, STATE_TABLE);
+    asm.add(R8, R8, R7);
+    asm.lwz(R9, 4, R8);
+    asm.cmpwi(R9, 0);
+    asm.beq(done);
+    asm.lwz(R10, 0, R8);
+    asm.stw(R10, 0, R3);
+    asm.stw(R9, 0, R4);
+    asm.lwz(R10, 8, R8);
+    asm.stw(R10, 0, R5);
+    asm.addi(R7, R7, ENTRY_SIZE);
+    asm.stw(R7, STATE_INDEX, R6);
+    asm.li(R3, 1);
+    asm.blr();
+    asm.bind(done);
+    asm.li(R3, 0);
+    asm.blr();
+
+    // close(): invalidates the loaded code and returns the entry point.
+    asm.bind(close);
+    addresses[2] = asm.here();
+    let (next, lines, synced) = (asm.label(), asm.label(), asm.label());
+    asm.load(R6, state);
+    asm.addi(R8, R6, STATE_TABLE);
+    asm.bind(next);
+    asm.lwz(R9, 4, R8);
+    asm.cmpwi(R9, 0);
+    asm.beq(synced);
+    asm.lwz(R10, 0, R8);
+    asm.add(R9, R10, R9);
+    asm.align_line(R10, R10);
+    asm.bind(lines);
+    asm.icbi(R10);
+    asm.addi(R10, R10, 32);
+    asm.cmplw(R10, R9);
+    asm.blt(lines);
+    asm.addi(R8, R8, ENTRY_SIZE);
+    asm.b(next);
+    asm.bind(synced);
+    asm.sync();
+    asm.isync();
+    asm.lwz(R3, STATE_ENTRY, R6);
+    asm.blr();
+
+    // Every instruction has a fixed size, so re-emitting the entry with the final
+    // addresses keeps all other code in place.
+    let mut fixed = Asm::default();
+    entry(&mut fixed, &addresses);
+    asm.code[..fixed.code.len()].copy_from_slice(&fixed.code);
+    asm.finish()
+}
+
+/// Generates an apploader image loading the DOL stored at `dol_offset` on the disc.
+///
+/// `date` is the build date shown by some loaders, as `YYYY/MM/DD`.
+pub fn generate(dol: &[u8], dol_offset: u32, date: &str) -> io::Result<Vec<u8>> {
+    if dol.len() < 0x100 {
+        return Err(invalid("the DOL is truncated"));
+    }
+    if !dol_offset.is_multiple_of(4) {
+        return Err(invalid(
+            "the DOL must be stored at a 4-byte aligned disc offset",
+        ));
+    }
+
+    let mut table = vec![];
+    for slot in 0..18 {
+        let (offset, address, size) = (
+            be_u32(dol, slot * 4),
+            be_u32(dol, 0x48 + slot * 4),
+            be_u32(dol, 0x90 + slot * 4),
+        );
+        if size == 0 {
+            continue;
+        }
+        if !address.is_multiple_of(DVD_ALIGN) || !offset.is_multiple_of(4) {
+            return Err(invalid(format!(
+                "the DOL section at 0x{address:08x} cannot be read with DVD DMA, it must be 32-byte aligned"
+            )));
+        }
+        table.push((
+            address,
+            size.next_multiple_of(DVD_ALIGN),
+            dol_offset + offset,
+        ));
+    }
+    let (bss_address, bss_size, entry) = (be_u32(dol, 0xd8), be_u32(dol, 0xdc), be_u32(dol, 0xe0));
+
+    // The code does not depend on where the data block is, other than through its
+    // address, so it can be assembled once to learn its size.
+    let code_size = assemble(0).len() as u32 * 4;
+    let state = BASE + code_size;
+    let code = assemble(state);
+
+    let mut body: Vec<u8> = code.iter().flat_map(|word| word.to_be_bytes()).collect();
+    for word in [0, entry, bss_address, bss_size] {
+        body.extend_from_slice(&word.to_be_bytes());
+    }
+    for (address, size, offset) in &table {
+        for word in [*address, *size, *offset] {
+            body.extend_from_slice(&word.to_be_bytes());
+        }
+    }
+    body.extend_from_slice(&[0; ENTRY_SIZE as usize]);
+    body.resize(body.len().next_multiple_of(DVD_ALIGN as usize), 0);
+
+    let end = BASE as u64 + body.len() as u64;
+    for (address, size, _) in &table {
+        if (*address as u64) < end && BASE < address + size {
+            return Err(invalid(format!(
+                "the DOL section at 0x{address:08x} overlaps the apploader at 0x{BASE:08x}"
+            )));
+        }
+    }
+
+    let mut out = vec![0; HEADER_SIZE];
+    let date = date.as_bytes();
+    out[..date.len().min(10)].copy_from_slice(&date[..date.len().min(10)]);
+    out[0x10..0x14].copy_from_slice(&BASE.to_be_bytes());
+    out[0x14..0x18].copy_from_slice(&(body.len() as u32).to_be_bytes());
+    out.extend_from_slice(&body);
+    Ok(out)
+}
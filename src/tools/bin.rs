@@ -0,0 +1,71 @@
+//! Flattening of linked ELFs to raw binaries, as `objcopy -O binary` does.
+//!
+//! The binary is the memory image starting at the lowest load address, for bootloaders
+//! and platforms that load a plain binary to a fixed address. The byte filling the gaps
+//! between segments and the size to pad the binary to are read from `rbrew.toml`:
+//!
+//! ```toml
+//! [bin]
+//! fill = 0xff
+//! pad_to = "64K"
+//! ```
+
+use crate::{config, elf::Elf};
+use std::{io, path::Path};
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// How the binary is laid out.
+#[derive(Default)]
+pub struct BinOptions {
+    /// The byte filling gaps and padding.
+    pub fill: u8,
+    /// The size the binary is padded to.
+    pub pad_to: Option<u64>,
+}
+
+impl BinOptions {
+    /// Reads the options from `rbrew.toml`, with zero fill and no padding by default.
+    pub fn load() -> io::Result<Self> {
+        let Some(bin) = config::load_table("bin")? else {
+            return Ok(Self::default());
+        };
+        let fill = match bin.get("fill") {
+            None => 0,
+            Some(value) => value
+                .as_integer()
+                .and_then(|fill| u8::try_from(fill).ok())
+                .ok_or_else(|| config::invalid("'bin.fill' must be a byte"))?,
+        };
+        let pad_to = match bin.get("pad_to") {
+            None => None,
+            Some(value) => Some(
+                config::parse_size(value)
+                    .ok_or_else(|| config::invalid("'bin.pad_to' is not a valid size"))?,
+            ),
+        };
+        Ok(Self { fill, pad_to })
+    }
+}
+
+/// Flattens a linked ELF to a raw binary, returning the address it has to be loaded at.
+pub fn elf2bin(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    options: &BinOptions,
+) -> io::Result<u64> {
+    let (base, mut image) = Elf::read(input)?.load_image_with_fill(options.fill)?;
+    if let Some(pad_to) = options.pad_to {
+        if image.len() as u64 > pad_to {
+            return Err(invalid(format!(
+                "the image is {} bytes, more than the {pad_to} it is padded to",
+                image.len()
+            )));
+        }
+        image.resize(pad_to as usize, options.fill);
+    }
+    std::fs::write(output, image)?;
+    Ok(base)
+}
@@ -0,0 +1,133 @@
+use crate::{config, elf::Elf};
+use std::{fmt::Write, io, path::Path};
+
+/// The key budgeting the size of the converted output file instead of a section.
+pub const TOTAL: &str = "total";
+
+/// Size limits declared in the `[budget]` table of `rbrew.toml`.
+///
+/// Every key other than [`TOTAL`] names a section without its leading dot and covers the
+/// section along with its `.name.*` subsections, e.g. `text = "2M"` or `bss = "512K"`.
+pub struct Budget {
+    pub limits: Vec<(String, u64)>,
+    /// Only warn instead of failing the build, set by `on_exceed = "warn"`.
+    pub warn_only: bool,
+}
+
+impl Budget {
+    /// Reads the budget from `rbrew.toml`, returning `None` if it declares none.
+    pub fn load() -> io::Result<Option<Self>> {
+        let Some(budget) = config::load_table("budget")? else {
+            return Ok(None);
+        };
+
+        let mut limits = vec![];
+        let mut warn_only = false;
+        for (key, value) in &budget {
+            if key == "on_exceed" {
+                warn_only = match value.as_str() {
+                    Some("fail") => false,
+                    Some("warn") => true,
+                    _ => {
+                        return Err(config::invalid(
+                            "'budget.on_exceed' must be \"fail\" or \"warn\"",
+                        ))
+                    }
+                };
+                continue;
+            }
+            let limit = config::parse_size(value)
+                .ok_or_else(|| config::invalid(format!("'budget.{key}' is not a valid size")))?;
+            limits.push((key.clone(), limit));
+        }
+        Ok(Some(Self { limits, warn_only }))
+    }
+}
+
+/// Measures every budgeted item of a build.
+pub fn measure(
+    budget: &Budget,
+    elf_path: &Path,
+    output_path: &Path,
+) -> io::Result<Vec<(String, u64)>> {
+    let elf = Elf::read(elf_path)?;
+    let output_size = std::fs::metadata(output_path)?.len();
+
+    let sizes = budget
+        .limits
+        .iter()
+        .map(|(key, _)| {
+            let size = if key == TOTAL {
+                output_size
+            } else {
+                let name = format!(".{key}");
+                let prefix = format!(".{key}.");
+                elf.sections
+                    .iter()
+                    .filter(|section| {
+                        section.is_alloc()
+                            && (section.name == name || section.name.starts_with(&prefix))
+                    })
+                    .map(|section| section.size)
+                    .sum()
+            };
+            (key.clone(), size)
+        })
+        .collect();
+    Ok(sizes)
+}
+
+/// Reads the sizes recorded by a previous build, if there was one.
+pub fn read_record(path: &Path) -> Vec<(String, u64)> {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return vec![];
+    };
+    text.lines()
+        .filter_map(|line| {
+            let (key, size) = line.split_once(' ')?;
+            Some((key.to_string(), size.parse().ok()?))
+        })
+        .collect()
+}
+
+/// Records the sizes of this build so that the next one can be compared against it.
+pub fn write_record(path: &Path, sizes: &[(String, u64)]) -> io::Result<()> {
+    let mut text = String::new();
+    for (key, size) in sizes {
+        writeln!(text, "{key} {size}").unwrap();
+    }
+    std::fs::write(path, text)
+}
+
+/// Compares the sizes against the budget, returning a report of every item if any of them
+/// exceeds its limit.
+pub fn check(
+    budget: &Budget,
+    sizes: &[(String, u64)],
+    previous: &[(String, u64)],
+) -> Option<String> {
+    let exceeded = budget
+        .limits
+        .iter()
+        .zip(sizes)
+        .any(|((_, limit), (_, size))| size > limit);
+    if !exceeded {
+        return None;
+    }
+
+    let mut report = format!(
+        "{:<16} {:>10} {:>10} {:>11} {:>10}\n",
+        "budget", "size", "limit", "change", "previous"
+    );
+    for ((key, limit), (_, size)) in budget.limits.iter().zip(sizes) {
+        let marker = if size > limit { "!" } else { " " };
+        write!(report, "{marker}{key:<15} {size:>10} {limit:>10}").unwrap();
+        match previous.iter().find(|(prev_key, _)| prev_key == key) {
+            Some((_, prev)) => {
+                writeln!(report, " {:>+11} {prev:>10}", *size as i64 - *prev as i64).unwrap()
+            }
+            None => writeln!(report, " {:>11} {:>10}", "-", "-").unwrap(),
+        }
+    }
+    Some(report)
+}
@@ -0,0 +1,162 @@
+use crate::elf::Elf;
+use rbrew_shared_types::build_info::{BuildInfo, MAGIC, SECTION, SLOT_SIZE};
+use std::{
+    io,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Owned build information collected on the host.
+pub struct HostBuildInfo {
+    pub git_hash: String,
+    pub timestamp: String,
+    pub profile: String,
+    pub rbrew_version: String,
+}
+
+impl HostBuildInfo {
+    /// Collects the information for the current build.
+    ///
+    /// `SOURCE_DATE_EPOCH` is honored for the timestamp so builds can stay reproducible.
+    pub fn collect(profile: &str) -> Self {
+        Self {
+            git_hash: git_hash().unwrap_or_else(|| "unknown".to_string()),
+            timestamp: timestamp(),
+            profile: profile.to_string(),
+            rbrew_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    pub fn as_build_info(&self) -> BuildInfo<'_> {
+        BuildInfo {
+            git_hash: &self.git_hash,
+            timestamp: &self.timestamp,
+            profile: &self.profile,
+            rbrew_version: &self.rbrew_version,
+        }
+    }
+}
+
+fn git_hash() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let mut hash = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    let clean = Command::new("git")
+        .args(["diff", "--quiet", "HEAD"])
+        .status()
+        .ok()?;
+    if !clean.success() {
+        hash.push_str("-dirty");
+    }
+    Some(hash)
+}
+
+/// The current time as an ISO 8601 UTC timestamp, or `SOURCE_DATE_EPOCH` if it is set.
+pub fn timestamp() -> String {
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+        });
+    format_timestamp(secs)
+}
+
+/// Formats seconds since the Unix epoch as an ISO 8601 UTC timestamp.
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// Writes a copy of a linked ELF to `output` with build information in its reserved slot.
+///
+/// The linked ELF is left alone, cargo does not relink it when nothing changed.
+pub fn embed(elf_path: &Path, output: &Path, info: &BuildInfo) -> io::Result<()> {
+    let elf = Elf::read(elf_path)?;
+    let section = elf.section_by_name(SECTION).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "'{}' has no build info slot, reference `rbrew_shared::build_info::get` to keep it",
+                elf_path.display()
+            ),
+        )
+    })?;
+    if section.is_nobits() || (section.size as usize) < SLOT_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("'{SECTION}' in '{}' is malformed", elf_path.display()),
+        ));
+    }
+
+    let mut slot = [0; SLOT_SIZE];
+    if !info.encode(&mut slot) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "build info does not fit into its slot",
+        ));
+    }
+
+    let offset = section.offset as usize;
+    let mut data = elf.into_data();
+    data[offset..offset + SLOT_SIZE].copy_from_slice(&slot);
+    std::fs::write(output, data)
+}
+
+/// The outcome of searching an artifact for build information.
+pub enum Found<'a> {
+    Filled(BuildInfo<'a>),
+    Unfilled,
+    Missing,
+}
+
+/// Searches any artifact (ELF, DOL, REL, disc image, ...) for the build info slot.
+pub fn find(data: &[u8]) -> Found<'_> {
+    let mut unfilled = false;
+    let mut pos = 0;
+    while let Some(at) = data[pos..]
+        .windows(MAGIC.len())
+        .position(|window| window == MAGIC)
+    {
+        let start = pos + at;
+        let end = (start + SLOT_SIZE).min(data.len());
+        match BuildInfo::parse(&data[start..end]) {
+            Some(info) => return Found::Filled(info),
+            None => unfilled = true,
+        }
+        pos = start + 1;
+    }
+    if unfilled {
+        Found::Unfilled
+    } else {
+        Found::Missing
+    }
+}
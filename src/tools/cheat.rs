@@ -0,0 +1,175 @@
+use crate::elf::{self, Elf, Symbol};
+use std::io;
+
+/// The first address the codes can reach, the start of cached MEM1.
+const BASE: u32 = 0x8000_0000;
+/// Both formats encode 25 address bits relative to [`BASE`].
+const ADDRESS_MASK: u32 = 0x01ff_ffff;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg.into())
+}
+
+/// What a code writes at the target address.
+pub enum Patch {
+    /// A 1, 2 or 4 byte value.
+    Value {
+        value: u32,
+        width: u32,
+    },
+    Bytes(Vec<u8>),
+}
+
+/// One line of a cheat code.
+pub type CodeLine = (u32, u32);
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number.
+pub fn parse_u32(s: &str) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("'{s}' is not a valid number"))
+}
+
+/// Parses a string of hexadecimal digit pairs, ignoring whitespace.
+pub fn parse_hex_bytes(s: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!("'{s}' is not an even number of hex digits"));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).map_err(|_| format!("'{pair}' is not a hex byte"))
+        })
+        .collect()
+}
+
+/// Finds a defined symbol by its mangled name or its demangled name without the hash.
+pub fn resolve_symbol(elf: &Elf, name: &str) -> io::Result<Symbol> {
+    let matches: Vec<Symbol> = elf
+        .symbols()?
+        .into_iter()
+        .filter(|sym| {
+            !sym.is_undefined()
+                && sym.kind() != elf::STT_SECTION
+                && sym.kind() != elf::STT_FILE
+                && (sym.name == name
+                    || format!("{:#}", rustc_demangle::demangle(&sym.name)) == name)
+        })
+        .collect();
+    match matches.len() {
+        0 => Err(invalid(format!("no symbol named '{name}'"))),
+        1 => Ok(matches.into_iter().next().unwrap()),
+        _ => Err(invalid(format!(
+            "'{name}' is ambiguous, use one of: {}",
+            matches
+                .iter()
+                .map(|sym| sym.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+fn check_range(address: u32, len: usize) -> io::Result<()> {
+    let end = address as u64 + len as u64;
+    if address < BASE || end > (BASE | ADDRESS_MASK) as u64 + 1 {
+        return Err(invalid(format!(
+            "address 0x{address:08x} is outside the range codes can write to"
+        )));
+    }
+    Ok(())
+}
+
+/// Encodes the basic 8, 16 or 32-bit write shared by Gecko and Action Replay codes.
+fn write_line(address: u32, value: u32, width: u32) -> io::Result<CodeLine> {
+    let size = match width {
+        1 => 0,
+        2 => 1,
+        4 => 2,
+        _ => return Err(invalid("value width must be 1, 2 or 4 bytes")),
+    };
+    if !address.is_multiple_of(width) {
+        return Err(invalid(format!(
+            "address 0x{address:08x} is not aligned to the {width} byte value"
+        )));
+    }
+    // Values wider than the write would be truncated by the code handler.
+    if width < 4 && value >> (width * 8) != 0 {
+        return Err(invalid(format!(
+            "0x{value:x} does not fit into {width} bytes"
+        )));
+    }
+    Ok(((size << 25) | (address & ADDRESS_MASK), value))
+}
+
+/// Splits a byte patch into the widest aligned writes possible.
+fn split_bytes(address: u32, bytes: &[u8]) -> io::Result<Vec<CodeLine>> {
+    let mut lines = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let addr = address + offset as u32;
+        let rest = &bytes[offset..];
+        let width = if addr.is_multiple_of(4) && rest.len() >= 4 {
+            4
+        } else if addr.is_multiple_of(2) && rest.len() >= 2 {
+            2
+        } else {
+            1
+        };
+        let value = rest[..width]
+            .iter()
+            .fold(0u32, |acc, &b| (acc << 8) | b as u32);
+        lines.push(write_line(addr, value, width as u32)?);
+        offset += width;
+    }
+    Ok(lines)
+}
+
+/// Generates Gecko codes applying the patch.
+///
+/// Byte patches longer than four bytes use a single string write.
+pub fn gecko(address: u32, patch: &Patch) -> io::Result<Vec<CodeLine>> {
+    match patch {
+        Patch::Value { value, width } => {
+            check_range(address, *width as usize)?;
+            Ok(vec![write_line(address, *value, *width)?])
+        }
+        Patch::Bytes(bytes) if bytes.len() <= 4 => {
+            check_range(address, bytes.len())?;
+            split_bytes(address, bytes)
+        }
+        Patch::Bytes(bytes) => {
+            check_range(address, bytes.len())?;
+            let mut lines = vec![(0x0600_0000 | (address & ADDRESS_MASK), bytes.len() as u32)];
+            for chunk in bytes.chunks(8) {
+                let mut line = [0; 8];
+                line[..chunk.len()].copy_from_slice(chunk);
+                lines.push((
+                    u32::from_be_bytes(line[..4].try_into().unwrap()),
+                    u32::from_be_bytes(line[4..].try_into().unwrap()),
+                ));
+            }
+            Ok(lines)
+        }
+    }
+}
+
+/// Generates unencrypted Action Replay codes applying the patch.
+///
+/// Dolphin accepts these directly; a physical Action Replay needs them encrypted first.
+pub fn action_replay(address: u32, patch: &Patch) -> io::Result<Vec<CodeLine>> {
+    match patch {
+        Patch::Value { value, width } => {
+            check_range(address, *width as usize)?;
+            Ok(vec![write_line(address, *value, *width)?])
+        }
+        Patch::Bytes(bytes) => {
+            check_range(address, bytes.len())?;
+            split_bytes(address, bytes)
+        }
+    }
+}
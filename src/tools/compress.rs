@@ -0,0 +1,129 @@
+//! Compression of converted outputs.
+//!
+//! Yaz0 is the format Nintendo's GameCube and Wii libraries decompress, zlib the one
+//! most other loaders can inflate.
+
+use std::io;
+
+/// The compression formats.
+#[derive(Clone, Copy)]
+pub enum Compression {
+    Yaz0,
+    Zlib,
+}
+
+impl Compression {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "yaz0" => Self::Yaz0,
+            "zlib" => Self::Zlib,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Yaz0 => "yaz0",
+            Compression::Zlib => "zlib",
+        }
+    }
+
+    /// The extension appended to the name of compressed files.
+    pub fn extension_name(self) -> &'static str {
+        match self {
+            Compression::Yaz0 => "szs",
+            Compression::Zlib => "zlib",
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::Yaz0 => yaz0(data),
+            Compression::Zlib => Ok(miniz_oxide::deflate::compress_to_vec_zlib(
+                data,
+                COMPRESSION_LEVEL,
+            )),
+        }
+    }
+}
+
+const COMPRESSION_LEVEL: u8 = 9;
+
+const YAZ0_WINDOW: usize = 0x1000;
+const YAZ0_MIN_MATCH: usize = 3;
+const YAZ0_MAX_MATCH: usize = 0x111;
+/// How many earlier positions with the same three bytes are tried for a match.
+const YAZ0_CANDIDATES: usize = 64;
+
+/// Finds the longest match for `data[pos..]` in the window before it.
+fn longest_match(data: &[u8], pos: usize, chains: &[Vec<usize>], key: usize) -> (usize, usize) {
+    let max = (data.len() - pos).min(YAZ0_MAX_MATCH);
+    let mut best = (0, 0);
+    for &start in chains[key].iter().rev().take(YAZ0_CANDIDATES) {
+        if pos - start > YAZ0_WINDOW {
+            break;
+        }
+        let len = data[start..]
+            .iter()
+            .zip(&data[pos..pos + max])
+            .take_while(|(a, b)| a == b)
+            .count();
+        if len > best.0 {
+            best = (len, pos - start);
+            if len == max {
+                break;
+            }
+        }
+    }
+    best
+}
+
+fn yaz0(data: &[u8]) -> io::Result<Vec<u8>> {
+    let size = u32::try_from(data.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too large for Yaz0"))?;
+    let mut out = b"Yaz0".to_vec();
+    out.extend_from_slice(&size.to_be_bytes());
+    out.extend_from_slice(&[0; 8]);
+
+    // Earlier positions by their first three bytes, hashed to 12 bits.
+    let mut chains = vec![vec![]; 0x1000];
+    let hash = |pos: usize| {
+        ((data[pos] as usize) << 4 ^ (data[pos + 1] as usize) << 2 ^ data[pos + 2] as usize) & 0xfff
+    };
+    let mut pos = 0;
+    while pos < data.len() {
+        // Each group of eight chunks is led by a byte flagging its literals.
+        let code_at = out.len();
+        out.push(0);
+        for bit in (0..8).rev() {
+            if pos >= data.len() {
+                break;
+            }
+            let (len, distance) = if pos + YAZ0_MIN_MATCH <= data.len() {
+                longest_match(data, pos, &chains, hash(pos))
+            } else {
+                (0, 0)
+            };
+            let advance = if len >= YAZ0_MIN_MATCH {
+                let back = distance - 1;
+                if len >= 0x12 {
+                    out.extend_from_slice(&[(back >> 8) as u8, back as u8, (len - 0x12) as u8]);
+                } else {
+                    out.extend_from_slice(&[((len - 2) << 4 | back >> 8) as u8, back as u8]);
+                }
+                len
+            } else {
+                out[code_at] |= 1 << bit;
+                out.push(data[pos]);
+                1
+            };
+            for p in pos..pos + advance {
+                if p + YAZ0_MIN_MATCH <= data.len() {
+                    chains[hash(p)].push(p);
+                }
+            }
+            pos += advance;
+        }
+    }
+    Ok(out)
+}
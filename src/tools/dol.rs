@@ -0,0 +1,171 @@
+//! Reading and patching of existing DOLs, for `rbrew tools dol`.
+//!
+//! Sections are named by their slot in the header, `text0` to `text6` and `data0` to
+//! `data10`, as printed by [`section_table`].
+
+use super::{
+    elf2dol::{DolSection, MAX_DATA_SECTIONS, MAX_TEXT_SECTIONS},
+    Dol,
+};
+use std::{fmt::Write, io};
+
+const HEADER_SIZE: usize = 0x100;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// A used section slot of a DOL header.
+pub struct Slot {
+    pub name: String,
+    pub text: bool,
+    pub offset: u32,
+    pub address: u32,
+    pub size: u32,
+}
+
+/// Reads the used section slots of a DOL, checking that they lie within the file.
+pub fn slots(data: &[u8]) -> io::Result<Vec<Slot>> {
+    if data.len() < HEADER_SIZE {
+        return Err(invalid("the file is too small to be a DOL"));
+    }
+    let mut slots = vec![];
+    for slot in 0..MAX_TEXT_SECTIONS + MAX_DATA_SECTIONS {
+        let size = be_u32(data, 0x90 + slot * 4);
+        if size == 0 {
+            continue;
+        }
+        let text = slot < MAX_TEXT_SECTIONS;
+        let name = if text {
+            format!("text{slot}")
+        } else {
+            format!("data{}", slot - MAX_TEXT_SECTIONS)
+        };
+        let offset = be_u32(data, slot * 4);
+        if offset as u64 + size as u64 > data.len() as u64 {
+            return Err(invalid(format!("{name} lies outside the file")));
+        }
+        slots.push(Slot {
+            name,
+            text,
+            offset,
+            address: be_u32(data, 0x48 + slot * 4),
+            size,
+        });
+    }
+    Ok(slots)
+}
+
+/// Reads a DOL.
+pub fn parse(data: &[u8]) -> io::Result<Dol> {
+    let slots = slots(data)?;
+    let mut dol = Dol {
+        text: vec![],
+        data: vec![],
+        bss_address: be_u32(data, 0xd8),
+        bss_size: be_u32(data, 0xdc),
+        entry: be_u32(data, 0xe0),
+    };
+    for slot in slots {
+        let section = DolSection {
+            address: slot.address,
+            data: data[slot.offset as usize..][..slot.size as usize].to_vec(),
+        };
+        if slot.text {
+            dol.text.push(section);
+        } else {
+            dol.data.push(section);
+        }
+    }
+    Ok(dol)
+}
+
+/// Describes the entry point, bss and sections of a DOL.
+pub fn section_table(data: &[u8]) -> io::Result<String> {
+    let slots = slots(data)?;
+    let (bss_address, bss_size) = (be_u32(data, 0xd8), be_u32(data, 0xdc));
+    let mut out = String::new();
+    writeln!(out, "entry point: 0x{:08x}", be_u32(data, 0xe0)).unwrap();
+    writeln!(
+        out,
+        "bss:         0x{bss_address:08x}..0x{:08x} (0x{bss_size:x} bytes)",
+        bss_address as u64 + bss_size as u64
+    )
+    .unwrap();
+    writeln!(out, "section  offset      address     size").unwrap();
+    for slot in slots {
+        writeln!(
+            out,
+            "{:<8} 0x{:08x}  0x{:08x}  0x{:08x}",
+            slot.name, slot.offset, slot.address, slot.size
+        )
+        .unwrap();
+    }
+    Ok(out)
+}
+
+/// Returns the contents of the section named `name`.
+pub fn extract(data: &[u8], name: &str) -> io::Result<Vec<u8>> {
+    let slot = slots(data)?
+        .into_iter()
+        .find(|slot| slot.name == name)
+        .ok_or_else(|| invalid(format!("the DOL has no section '{name}'")))?;
+    Ok(data[slot.offset as usize..][..slot.size as usize].to_vec())
+}
+
+/// Overwrites the bytes loaded at `address` with `bytes`, which must lie within a
+/// single section.
+pub fn patch(dol: &mut Dol, address: u32, bytes: &[u8]) -> io::Result<()> {
+    let end = address as u64 + bytes.len() as u64;
+    let section = dol
+        .text
+        .iter_mut()
+        .chain(&mut dol.data)
+        .find(|section| {
+            section.address <= address && end <= section.address as u64 + section.data.len() as u64
+        })
+        .ok_or_else(|| {
+            invalid(format!(
+                "0x{address:08x}..0x{end:08x} does not lie within a single section, \
+                 use `inject` to add a section"
+            ))
+        })?;
+    let start = (address - section.address) as usize;
+    section.data[start..start + bytes.len()].copy_from_slice(bytes);
+    Ok(())
+}
+
+/// Adds a section loading `bytes` at `address`, as text if `text` is set.
+pub fn inject(dol: &mut Dol, address: u32, bytes: Vec<u8>, text: bool) -> io::Result<()> {
+    let end = address as u64 + bytes.len() as u64;
+    if bytes.is_empty() {
+        return Err(invalid("cannot inject an empty section"));
+    }
+    if let Some(section) = dol.text.iter().chain(&dol.data).find(|section| {
+        (section.address as u64) < end
+            && (address as u64) < section.address as u64 + section.data.len() as u64
+    }) {
+        return Err(invalid(format!(
+            "0x{address:08x}..0x{end:08x} overlaps the section at 0x{:08x}, use `patch` to \
+             replace its data",
+            section.address
+        )));
+    }
+    let (sections, max, kind) = if text {
+        (&mut dol.text, MAX_TEXT_SECTIONS, "text")
+    } else {
+        (&mut dol.data, MAX_DATA_SECTIONS, "data")
+    };
+    if sections.len() == max {
+        return Err(invalid(format!("all {max} {kind} sections are in use")));
+    }
+    sections.push(DolSection {
+        address,
+        data: bytes,
+    });
+    Ok(())
+}
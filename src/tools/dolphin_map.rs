@@ -0,0 +1,478 @@
+use crate::elf::{self, Elf};
+use gimli::{AttributeValue, EndianSlice, RunTimeEndian, UnitOffset};
+use std::{fmt::Write, io, path::Path};
+
+type Slice<'a> = EndianSlice<'a, RunTimeEndian>;
+
+fn invalid(err: gimli::Error) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid DWARF data: {err}"),
+    )
+}
+
+struct LineRow {
+    address: u64,
+    file: String,
+    line: u64,
+}
+
+struct InlineRange {
+    start: u64,
+    end: u64,
+    depth: isize,
+    name: String,
+}
+
+struct MapSymbol {
+    address: u64,
+    size: u64,
+    name: String,
+}
+
+/// Demangles a symbol name into the form used in the map.
+///
+/// Dolphin splits map lines on whitespace, so names must not contain any.
+fn map_name(name: &str) -> String {
+    format!("{:#}", rustc_demangle::demangle(name)).replace(' ', "_")
+}
+
+/// Resolves the name of an inlined subroutine through its abstract origin, preferring
+/// linkage names since they demangle to full paths.
+fn origin_name(unit: gimli::UnitRef<Slice>, offset: UnitOffset) -> Option<String> {
+    let mut offset = offset;
+    let mut short_name = None;
+    // Origins may point at a definition, which in turn points at its declaration.
+    for _ in 0..4 {
+        let entry = unit.entry(offset).ok()?;
+        if let Ok(Some(value)) = entry.attr_value(gimli::DW_AT_linkage_name) {
+            let name = unit.attr_string(value).ok()?;
+            return Some(map_name(&name.to_string_lossy()));
+        }
+        if short_name.is_none() {
+            if let Ok(Some(value)) = entry.attr_value(gimli::DW_AT_name) {
+                short_name = Some(unit.attr_string(value).ok()?.to_string_lossy().into_owned());
+            }
+        }
+        let next = entry
+            .attr_value(gimli::DW_AT_abstract_origin)
+            .ok()
+            .flatten()
+            .or_else(|| entry.attr_value(gimli::DW_AT_specification).ok().flatten());
+        match next {
+            Some(AttributeValue::UnitRef(next)) => offset = next,
+            _ => break,
+        }
+    }
+    short_name
+}
+
+fn file_path<'a>(
+    unit: gimli::UnitRef<Slice<'a>>,
+    header: &gimli::LineProgramHeader<Slice<'a>>,
+    file: &gimli::FileEntry<Slice<'a>>,
+) -> Option<String> {
+    let name = unit.attr_string(file.path_name()).ok()?;
+    let name = name.to_string_lossy();
+    if name.starts_with('/') {
+        return Some(name.into_owned());
+    }
+    match file.directory(header) {
+        Some(dir) => {
+            let dir = unit.attr_string(dir).ok()?;
+            Some(format!("{}/{name}", dir.to_string_lossy()))
+        }
+        None => Some(name.into_owned()),
+    }
+}
+
+fn load_dwarf(elf: &Elf) -> io::Result<(Vec<LineRow>, Vec<InlineRange>)> {
+    let endian = match elf.endian {
+        elf::Endian::Little => RunTimeEndian::Little,
+        elf::Endian::Big => RunTimeEndian::Big,
+    };
+    let dwarf = gimli::Dwarf::load(|id| -> io::Result<Slice> {
+        let data = match elf.section_by_name(id.name()) {
+            Some(section) => elf.section_data(section)?,
+            None => &[],
+        };
+        Ok(EndianSlice::new(data, endian))
+    })?;
+
+    let mut lines = vec![];
+    let mut inlines = vec![];
+    let mut units = dwarf.units();
+    while let Some(header) = units.next().map_err(invalid)? {
+        let unit = dwarf.unit(header).map_err(invalid)?;
+        let unit = unit.unit_ref(&dwarf);
+
+        let mut depth = 0;
+        let mut entries = unit.entries();
+        while let Some((delta, entry)) = entries.next_dfs().map_err(invalid)? {
+            depth += delta;
+            if entry.tag() != gimli::DW_TAG_inlined_subroutine {
+                continue;
+            }
+            let Some(AttributeValue::UnitRef(origin)) = entry
+                .attr_value(gimli::DW_AT_abstract_origin)
+                .map_err(invalid)?
+            else {
+                continue;
+            };
+            let Some(name) = origin_name(unit, origin) else {
+                continue;
+            };
+            let mut ranges = unit.die_ranges(entry).map_err(invalid)?;
+            while let Some(range) = ranges.next().map_err(invalid)? {
+                if range.begin < range.end {
+                    inlines.push(InlineRange {
+                        start: range.begin,
+                        end: range.end,
+                        depth,
+                        name: name.clone(),
+                    });
+                }
+            }
+        }
+
+        if let Some(program) = unit.line_program.clone() {
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row().map_err(invalid)? {
+                if row.end_sequence() {
+                    continue;
+                }
+                let (Some(file), Some(line)) = (row.file(header), row.line()) else {
+                    continue;
+                };
+                if let Some(file) = file_path(unit, header, file) {
+                    lines.push(LineRow {
+                        address: row.address(),
+                        file,
+                        line: line.get(),
+                    });
+                }
+            }
+        }
+    }
+
+    lines.sort_by_key(|row| row.address);
+    inlines.sort_by_key(|range| range.start);
+    Ok((lines, inlines))
+}
+
+fn line_at(lines: &[LineRow], address: u64) -> Option<&LineRow> {
+    let index = lines.partition_point(|row| row.address <= address);
+    index.checked_sub(1).map(|i| &lines[i])
+}
+
+/// Splits a function into consecutive pieces, each attributed either to the function
+/// itself or to the innermost subroutine inlined at that address.
+///
+/// Dolphin looks symbols up by their start address, so overlapping entries would hide the
+/// rest of the enclosing function; splitting keeps every address attributed.
+fn split_function(function: &MapSymbol, inlines: &[InlineRange]) -> Vec<MapSymbol> {
+    let start = function.address;
+    let end = start + function.size;
+    let first = inlines.partition_point(|range| range.start < start);
+    let ranges: Vec<&InlineRange> = inlines[first..]
+        .iter()
+        .take_while(|range| range.start < end)
+        .collect();
+
+    let mut bounds = vec![start, end];
+    for range in &ranges {
+        bounds.push(range.start);
+        bounds.push(range.end.min(end));
+    }
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let mut pieces: Vec<(u64, u64, Option<&InlineRange>)> = vec![];
+    for window in bounds.windows(2) {
+        let (a, b) = (window[0], window[1]);
+        let owner = ranges
+            .iter()
+            .filter(|range| range.start <= a && a < range.end)
+            .max_by_key(|range| range.depth)
+            .copied();
+        match pieces.last_mut() {
+            Some(last) if last.2.map(|r| r as *const _) == owner.map(|r| r as *const _) => {
+                last.1 = b
+            }
+            _ => pieces.push((a, b, owner)),
+        }
+    }
+
+    pieces
+        .into_iter()
+        .map(|(a, b, owner)| MapSymbol {
+            address: a,
+            size: b - a,
+            name: match owner {
+                Some(range) => format!("{}::{{inline:{}}}", function.name, range.name),
+                None => function.name.clone(),
+            },
+        })
+        .collect()
+}
+
+/// Creates a Dolphin symbol map from a linked ELF.
+///
+/// When DWARF debug info is present, every entry is annotated with its source location and
+/// functions are split at the ranges of code inlined into them.
+pub fn elf2map(input: &Path) -> io::Result<String> {
+    let elf = Elf::read(input)?;
+    let (lines, inlines) = load_dwarf(&elf)?;
+
+    let mut by_section: Vec<Vec<MapSymbol>> = elf.sections.iter().map(|_| vec![]).collect();
+    for sym in elf.symbols()? {
+        let kind = sym.kind();
+        if sym.is_undefined()
+            || sym.name.is_empty()
+            || sym.size == 0
+            || kind == elf::STT_SECTION
+            || kind == elf::STT_FILE
+        {
+            continue;
+        }
+        let Some(section) = elf.sections.get(sym.shndx as usize) else {
+            continue;
+        };
+        // Skips symbols whose values are not addresses, such as thread-local ones.
+        if sym.value < section.addr || sym.value + sym.size > section.addr + section.size {
+            continue;
+        }
+        by_section[sym.shndx as usize].push(MapSymbol {
+            address: sym.value,
+            size: sym.size,
+            name: map_name(&sym.name),
+        });
+    }
+
+    let mut map = String::new();
+    for (section, mut symbols) in elf.sections.iter().zip(by_section) {
+        if symbols.is_empty() || !section.is_alloc() {
+            continue;
+        }
+        symbols.sort_by_key(|sym| sym.address);
+        symbols.dedup_by_key(|sym| sym.address);
+
+        writeln!(map, "{} section layout", section.name).unwrap();
+        writeln!(map, "  Starting        Virtual").unwrap();
+        writeln!(map, "  address  Size   address").unwrap();
+        writeln!(map, "  -----------------------").unwrap();
+        for sym in symbols {
+            let pieces = if section.is_exec() {
+                split_function(&sym, &inlines)
+            } else {
+                vec![sym]
+            };
+            for piece in pieces {
+                write!(
+                    map,
+                    "  {:08x} {:06x} {:08x}  4 {}",
+                    piece.address - section.addr,
+                    piece.size,
+                    piece.address,
+                    piece.name
+                )
+                .unwrap();
+                if let Some(row) = line_at(&lines, piece.address).filter(|_| section.is_exec()) {
+                    write!(map, " \t{}:{}", row.file, row.line).unwrap();
+                }
+                map.push('\n');
+            }
+        }
+        map.push('\n');
+    }
+
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inline(start: u64, end: u64, depth: isize, name: &str) -> InlineRange {
+        InlineRange {
+            start,
+            end,
+            depth,
+            name: name.to_string(),
+        }
+    }
+
+    fn pieces(symbols: &[MapSymbol]) -> Vec<(u64, u64, &str)> {
+        symbols
+            .iter()
+            .map(|sym| (sym.address, sym.size, sym.name.as_str()))
+            .collect()
+    }
+
+    fn function(address: u64, size: u64) -> MapSymbol {
+        MapSymbol {
+            address,
+            size,
+            name: "main".to_string(),
+        }
+    }
+
+    #[test]
+    fn functions_without_inlines_stay_whole() {
+        let inlines = [inline(0x100, 0x110, 2, "elsewhere")];
+        assert_eq!(
+            pieces(&split_function(&function(0x0, 0x40), &inlines)),
+            [(0x0, 0x40, "main")]
+        );
+    }
+
+    #[test]
+    fn splits_at_the_innermost_inline() {
+        let inlines = [
+            inline(0x10, 0x30, 2, "outer"),
+            inline(0x18, 0x20, 3, "inner"),
+        ];
+        assert_eq!(
+            pieces(&split_function(&function(0x0, 0x40), &inlines)),
+            [
+                (0x00, 0x10, "main"),
+                (0x10, 0x08, "main::{inline:outer}"),
+                (0x18, 0x08, "main::{inline:inner}"),
+                (0x20, 0x10, "main::{inline:outer}"),
+                (0x30, 0x10, "main"),
+            ]
+        );
+    }
+
+    #[test]
+    fn inlines_are_cut_at_the_function_end() {
+        // Adjacent ranges of the same inline stay separate, being different ranges.
+        let inlines = [
+            inline(0x30, 0x38, 2, "a"),
+            inline(0x38, 0x60, 2, "a"),
+            inline(0x60, 0x70, 2, "past"),
+        ];
+        assert_eq!(
+            pieces(&split_function(&function(0x20, 0x20), &inlines)),
+            [
+                (0x20, 0x10, "main"),
+                (0x30, 0x08, "main::{inline:a}"),
+                (0x38, 0x08, "main::{inline:a}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn names_have_no_whitespace() {
+        assert_eq!(map_name("_ZN4game4main17h0123456789abcdefE"), "game::main");
+        assert_eq!(
+            map_name("_ZN46_$LT$game..Foo$u20$as$u20$core..fmt..Debug$GT$3fmt17h0123456789abcdefE"),
+            "<game::Foo_as_core::fmt::Debug>::fmt"
+        );
+    }
+
+    /// A big-endian ELF32 with `.text`, `.data` and a symbol table, without DWARF.
+    fn test_elf() -> Vec<u8> {
+        let mut strtab = vec![0];
+        let mut name = |name: &str| {
+            let index = strtab.len() as u32;
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            index
+        };
+        // Name, value, size, info, section.
+        let symbols = [
+            (0, 0, 0, 0, 0),
+            (
+                name("_ZN4game4main17h0123456789abcdefE"),
+                0x8000_3100,
+                0x10,
+                0x12,
+                1,
+            ),
+            (name("helper"), 0x8000_3110, 0x10, 0x12, 1),
+            (name("COUNTER"), 0x8000_3200, 4, 0x11, 2),
+            (name("label"), 0x8000_3104, 0, 0x10, 1),
+            (name("extern"), 0, 4, 0x10, 0),
+        ];
+        let mut symtab = vec![];
+        for (name, value, size, info, shndx) in symbols {
+            symtab.extend_from_slice(&u32::to_be_bytes(name));
+            symtab.extend_from_slice(&u32::to_be_bytes(value));
+            symtab.extend_from_slice(&u32::to_be_bytes(size));
+            symtab.extend_from_slice(&[info, 0]);
+            symtab.extend_from_slice(&u16::to_be_bytes(shndx));
+        }
+        let shstrtab = b"\0.text\0.data\0.symtab\0.strtab\0.shstrtab\0".to_vec();
+
+        // Name, kind, flags, address, contents, link, entry size.
+        let sections = [
+            (0, elf::SHT_NULL, 0, 0, vec![], 0, 0),
+            (1, elf::SHT_PROGBITS, 0x6, 0x8000_3100, vec![0; 0x20], 0, 0),
+            (7, elf::SHT_PROGBITS, 0x3, 0x8000_3200, vec![0; 8], 0, 0),
+            (13, elf::SHT_SYMTAB, 0, 0, symtab, 4, 16),
+            (21, elf::SHT_STRTAB, 0, 0, strtab, 0, 0),
+            (29, elf::SHT_STRTAB, 0, 0, shstrtab, 0, 0),
+        ];
+        let mut data = vec![0; 52];
+        let shnum = sections.len() as u16;
+        let mut headers = vec![];
+        for (name, kind, flags, addr, contents, link, entsize) in sections {
+            let offset = data.len() as u32;
+            data.extend_from_slice(&contents);
+            for word in [
+                name,
+                kind,
+                flags,
+                addr,
+                offset,
+                contents.len() as u32,
+                link,
+                0,
+                1,
+                entsize,
+            ] {
+                headers.extend_from_slice(&word.to_be_bytes());
+            }
+        }
+        let shoff = data.len() as u32;
+        data.extend_from_slice(&headers);
+
+        data[..6].copy_from_slice(b"\x7fELF\x01\x02");
+        data[6] = 1;
+        data[16..18].copy_from_slice(&elf::ET_EXEC.to_be_bytes());
+        data[18..20].copy_from_slice(&elf::EM_PPC.to_be_bytes());
+        data[20..24].copy_from_slice(&1u32.to_be_bytes());
+        data[24..28].copy_from_slice(&0x8000_3100u32.to_be_bytes());
+        data[32..36].copy_from_slice(&shoff.to_be_bytes());
+        data[40..42].copy_from_slice(&52u16.to_be_bytes());
+        data[46..48].copy_from_slice(&40u16.to_be_bytes());
+        data[48..50].copy_from_slice(&shnum.to_be_bytes());
+        data[50..52].copy_from_slice(&5u16.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn maps_the_sized_symbols_of_each_section() {
+        let path = std::env::temp_dir().join(format!("rbrew-map-{}.elf", std::process::id()));
+        std::fs::write(&path, test_elf()).unwrap();
+        let map = elf2map(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            map.unwrap(),
+            ".text section layout\n\
+             \x20 Starting        Virtual\n\
+             \x20 address  Size   address\n\
+             \x20 -----------------------\n\
+             \x20 00000000 000010 80003100  4 game::main\n\
+             \x20 00000010 000010 80003110  4 helper\n\
+             \n\
+             .data section layout\n\
+             \x20 Starting        Virtual\n\
+             \x20 address  Size   address\n\
+             \x20 -----------------------\n\
+             \x20 00000000 000004 80003200  4 COUNTER\n\
+             \n"
+        );
+    }
+}
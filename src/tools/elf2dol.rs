@@ -1,5 +1,125 @@
-use std::path::Path;
+use crate::elf::{self, Elf};
+use std::{io, path::Path};
 
-pub fn elf2dol(_input: impl AsRef<Path>, _output: impl AsRef<Path>) -> std::io::Result<()> {
-    todo!()
+pub const MAX_TEXT_SECTIONS: usize = 7;
+pub const MAX_DATA_SECTIONS: usize = 11;
+const HEADER_SIZE: usize = 0x100;
+/// Section data is aligned in the file so that it can be read with DVD DMA.
+const FILE_ALIGN: usize = 32;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+pub struct DolSection {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// The contents of a DOL executable.
+pub struct Dol {
+    pub text: Vec<DolSection>,
+    pub data: Vec<DolSection>,
+    pub bss_address: u32,
+    pub bss_size: u32,
+    pub entry: u32,
+}
+
+impl Dol {
+    /// Builds a DOL from the loadable segments of a linked ELF.
+    ///
+    /// Executable segments become text sections and the rest data sections. The
+    /// zero-initialized tails of all segments are covered by the single bss range.
+    pub fn from_elf(elf: &Elf) -> io::Result<Self> {
+        if elf.class != elf::Class::Elf32 {
+            return Err(invalid("DOLs can only be created from 32-bit ELFs"));
+        }
+
+        let mut dol = Self {
+            text: vec![],
+            data: vec![],
+            bss_address: 0,
+            bss_size: 0,
+            entry: elf.entry as u32,
+        };
+        for segment in &elf.segments {
+            if segment.kind != elf::PT_LOAD || segment.memsz == 0 {
+                continue;
+            }
+            if segment.filesz != 0 {
+                let section = DolSection {
+                    address: segment.vaddr as u32,
+                    data: elf.segment_data(segment)?.to_vec(),
+                };
+                if segment.flags & elf::PF_X != 0 {
+                    dol.text.push(section);
+                } else {
+                    dol.data.push(section);
+                }
+            }
+        }
+        if dol.text.len() > MAX_TEXT_SECTIONS {
+            return Err(invalid(format!(
+                "{} executable segments do not fit into the {MAX_TEXT_SECTIONS} DOL text sections",
+                dol.text.len()
+            )));
+        }
+        if dol.data.len() > MAX_DATA_SECTIONS {
+            return Err(invalid(format!(
+                "{} data segments do not fit into the {MAX_DATA_SECTIONS} DOL data sections",
+                dol.data.len()
+            )));
+        }
+        if let Some((start, end)) = elf.zero_fill_range() {
+            dol.bss_address = start as u32;
+            dol.bss_size = (end - start) as u32;
+        }
+        Ok(dol)
+    }
+
+    /// The range of memory the DOL occupies once loaded, including its bss.
+    pub fn extent(&self) -> (u32, u32) {
+        let mut ranges: Vec<(u32, u32)> = self
+            .text
+            .iter()
+            .chain(&self.data)
+            .map(|section| (section.address, section.address + section.data.len() as u32))
+            .collect();
+        if self.bss_size != 0 {
+            ranges.push((self.bss_address, self.bss_address + self.bss_size));
+        }
+        let start = ranges.iter().map(|range| range.0).min().unwrap_or_default();
+        let end = ranges.iter().map(|range| range.1).max().unwrap_or_default();
+        (start, end)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = vec![0; HEADER_SIZE];
+        let text = self.text.iter().enumerate();
+        let data = self.data.iter().enumerate();
+        let sections = text.chain(data.map(|(i, section)| (MAX_TEXT_SECTIONS + i, section)));
+        for (slot, section) in sections {
+            let offset = out.len() as u32;
+            put_u32(&mut out, slot * 4, offset);
+            put_u32(&mut out, 0x48 + slot * 4, section.address);
+            put_u32(&mut out, 0x90 + slot * 4, section.data.len() as u32);
+            out.extend_from_slice(&section.data);
+            out.resize(out.len().next_multiple_of(FILE_ALIGN), 0);
+        }
+        put_u32(&mut out, 0xd8, self.bss_address);
+        put_u32(&mut out, 0xdc, self.bss_size);
+        put_u32(&mut out, 0xe0, self.entry);
+        out
+    }
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Converts a linked ELF to a DOL, returning its contents.
+pub fn elf2dol(input: impl AsRef<Path>, output: impl AsRef<Path>) -> io::Result<Dol> {
+    let dol = Dol::from_elf(&Elf::read(input)?)?;
+    std::fs::write(output, dol.to_bytes())?;
+    Ok(dol)
 }
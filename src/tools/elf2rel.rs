@@ -0,0 +1,326 @@
+use crate::elf::{self, Elf};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+const R_DOLPHIN_NOP: u8 = 201;
+const R_DOLPHIN_SECTION: u8 = 202;
+const R_DOLPHIN_END: u8 = 203;
+
+const REL_VERSION: u32 = 3;
+const HEADER_SIZE: usize = 0x4c;
+
+/// A module to convert as part of a single REL build.
+///
+/// All modules converted together may import symbols from each other.
+pub struct RelModule {
+    pub id: u32,
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Where an exported symbol can be found once all modules are loaded.
+#[derive(Clone, Copy)]
+struct Export {
+    module: u32,
+    section: u8,
+    offset: u32,
+}
+
+struct RelEntry {
+    section: u8,
+    offset: u32,
+    kind: u8,
+    target_section: u8,
+    addend: u32,
+}
+
+fn check_ppc(elf: &Elf, path: &Path) -> io::Result<()> {
+    if elf.machine != elf::EM_PPC
+        || elf.class != elf::Class::Elf32
+        || elf.endian != elf::Endian::Big
+    {
+        return Err(invalid(format!(
+            "'{}' is not a 32-bit big-endian PowerPC ELF",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+fn section_index(shndx: u16, path: &Path) -> io::Result<u8> {
+    u8::try_from(shndx).map_err(|_| {
+        invalid(format!(
+            "'{}' has more sections than a REL module can address",
+            path.display()
+        ))
+    })
+}
+
+/// Converts a set of partially linked ELF files into REL modules.
+///
+/// Undefined symbols are resolved against the other modules in `modules` first and then
+/// against the symbols of `base`, the linked main executable (module 0).
+pub fn elf2rel(modules: &[RelModule], base: Option<&Path>) -> io::Result<()> {
+    let mut base_symbols = HashMap::new();
+    if let Some(base) = base {
+        let base_elf = Elf::read(base)?;
+        check_ppc(&base_elf, base)?;
+        for sym in base_elf.symbols()? {
+            if !sym.is_undefined() && sym.binding() != elf::STB_LOCAL && !sym.name.is_empty() {
+                base_symbols.insert(sym.name, sym.value as u32);
+            }
+        }
+    }
+
+    let mut elfs = Vec::with_capacity(modules.len());
+    let mut exports = HashMap::new();
+    for module in modules {
+        let elf = Elf::read(&module.input)?;
+        check_ppc(&elf, &module.input)?;
+        for sym in elf.symbols()? {
+            if sym.is_undefined() || sym.binding() == elf::STB_LOCAL || sym.name.is_empty() {
+                continue;
+            }
+            if sym.shndx >= 0xff00 {
+                continue;
+            }
+            exports.insert(
+                sym.name,
+                Export {
+                    module: module.id,
+                    section: section_index(sym.shndx, &module.input)?,
+                    offset: sym.value as u32,
+                },
+            );
+        }
+        elfs.push(elf);
+    }
+
+    for (module, elf) in modules.iter().zip(&elfs) {
+        let rel = build_rel(module, elf, &exports, &base_symbols)?;
+        std::fs::write(&module.output, rel)?;
+    }
+
+    Ok(())
+}
+
+fn align_to(buf: &mut Vec<u8>, align: usize) {
+    while !buf.len().is_multiple_of(align) {
+        buf.push(0);
+    }
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+fn build_rel(
+    module: &RelModule,
+    elf: &Elf,
+    exports: &HashMap<String, Export>,
+    base_symbols: &HashMap<String, u32>,
+) -> io::Result<Vec<u8>> {
+    let path = module.input.as_path();
+    let num_sections = elf.sections.len();
+    section_index(num_sections as u16, path)?;
+
+    // The largest uninitialized section becomes the module bss. REL files only describe a
+    // single bss section, so any others are stored as zero-filled data instead.
+    let bss_index = elf
+        .sections
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| s.is_alloc() && s.is_nobits() && s.size != 0)
+        .max_by_key(|(_, s)| s.size)
+        .map(|(i, _)| i);
+
+    let mut out = vec![0; HEADER_SIZE + num_sections * 8];
+    let mut section_table = vec![(0u32, 0u32); num_sections];
+    let mut align = 1;
+    let mut bss_align = 1;
+    let mut bss_size = 0;
+    for (i, section) in elf.sections.iter().enumerate() {
+        if !section.is_alloc() || section.size == 0 {
+            continue;
+        }
+        let section_align = section.addralign.max(4) as usize;
+        if Some(i) == bss_index {
+            bss_size = section.size as u32;
+            bss_align = section_align;
+            section_table[i] = (0, bss_size);
+            continue;
+        }
+
+        align = align.max(section_align);
+        align_to(&mut out, section_align);
+        let offset = out.len() as u32;
+        if section.is_nobits() {
+            out.resize(out.len() + section.size as usize, 0);
+        } else {
+            out.extend_from_slice(elf.section_data(section)?);
+        }
+        let exec = section.is_exec() as u32;
+        section_table[i] = (offset | exec, section.size as u32);
+    }
+
+    let symbols = elf.symbols()?;
+    let mut relocations: HashMap<u32, Vec<RelEntry>> = HashMap::new();
+    for section in &elf.sections {
+        if section.kind != elf::SHT_RELA && section.kind != elf::SHT_REL {
+            continue;
+        }
+        let target = section.info as usize;
+        if !elf.sections.get(target).is_some_and(|s| s.is_alloc()) {
+            continue;
+        }
+
+        for reloc in elf.relocations(section)? {
+            if reloc.kind == elf::R_PPC_NONE {
+                continue;
+            }
+            if !elf::PPC_STATIC_RELOCATIONS.contains(&reloc.kind) {
+                return Err(invalid(format!(
+                    "'{}' uses relocation type {} which REL modules do not support",
+                    path.display(),
+                    reloc.kind
+                )));
+            }
+
+            let sym = symbols
+                .get(reloc.sym as usize)
+                .ok_or_else(|| invalid("relocation references an invalid symbol"))?;
+            let addend = reloc.addend as u32;
+            let (import, target_section, addend) = if reloc.sym == 0 || sym.shndx == elf::SHN_ABS {
+                (0, 0, (sym.value as u32).wrapping_add(addend))
+            } else if sym.is_undefined() {
+                if let Some(export) = exports.get(&sym.name) {
+                    (
+                        export.module,
+                        export.section,
+                        export.offset.wrapping_add(addend),
+                    )
+                } else if let Some(&addr) = base_symbols.get(&sym.name) {
+                    (0, 0, addr.wrapping_add(addend))
+                } else {
+                    return Err(invalid(format!(
+                        "'{}' references undefined symbol '{}'",
+                        path.display(),
+                        sym.name
+                    )));
+                }
+            } else {
+                (
+                    module.id,
+                    section_index(sym.shndx, path)?,
+                    (sym.value as u32).wrapping_add(addend),
+                )
+            };
+
+            relocations.entry(import).or_default().push(RelEntry {
+                section: target as u8,
+                offset: reloc.offset as u32,
+                kind: reloc.kind as u8,
+                target_section,
+                addend,
+            });
+        }
+    }
+
+    // Imports of other modules come first so that everything after `fix_size` (the
+    // relocations against this module and the main executable) can be released once the
+    // module has been linked.
+    let mut imports: Vec<u32> = relocations.keys().copied().collect();
+    imports.sort_by_key(|&id| (id == module.id || id == 0, id == 0, id));
+
+    align_to(&mut out, 8);
+    let imp_offset = out.len();
+    let imp_size = imports.len() * 8;
+    out.resize(out.len() + imp_size, 0);
+    let rel_offset = out.len();
+    let mut fix_size = None;
+
+    for (i, id) in imports.iter().enumerate() {
+        if (*id == module.id || *id == 0) && fix_size.is_none() {
+            fix_size = Some(out.len());
+        }
+        put_u32(&mut out, imp_offset + i * 8, *id);
+        let list_offset = out.len() as u32;
+        put_u32(&mut out, imp_offset + i * 8 + 4, list_offset);
+
+        let entries = relocations.get_mut(id).unwrap();
+        entries.sort_by_key(|e| (e.section, e.offset));
+        let mut current_section = None;
+        let mut position = 0;
+        for entry in entries.iter() {
+            if current_section != Some(entry.section) {
+                push_entry(&mut out, 0, R_DOLPHIN_SECTION, entry.section, 0);
+                current_section = Some(entry.section);
+                position = 0;
+            }
+            let mut delta = entry.offset - position;
+            while delta > 0xffff {
+                push_entry(&mut out, 0xffff, R_DOLPHIN_NOP, 0, 0);
+                delta -= 0xffff;
+            }
+            push_entry(
+                &mut out,
+                delta as u16,
+                entry.kind,
+                entry.target_section,
+                entry.addend,
+            );
+            position = entry.offset;
+        }
+        push_entry(&mut out, 0, R_DOLPHIN_END, 0, 0);
+    }
+    let fix_size = fix_size.unwrap_or(out.len());
+
+    for (i, (offset, size)) in section_table.iter().enumerate() {
+        put_u32(&mut out, HEADER_SIZE + i * 8, *offset);
+        put_u32(&mut out, HEADER_SIZE + i * 8 + 4, *size);
+    }
+
+    let find_entry = |name: &str| -> io::Result<(u8, u32)> {
+        match symbols.iter().find(|s| s.name == name && !s.is_undefined()) {
+            Some(sym) => Ok((section_index(sym.shndx, path)?, sym.value as u32)),
+            None => Ok((0, 0)),
+        }
+    };
+    let prolog = find_entry("_prolog")?;
+    let epilog = find_entry("_epilog")?;
+    let unresolved = find_entry("_unresolved")?;
+
+    put_u32(&mut out, 0x00, module.id);
+    put_u32(&mut out, 0x0c, num_sections as u32);
+    put_u32(&mut out, 0x10, HEADER_SIZE as u32);
+    put_u32(&mut out, 0x1c, REL_VERSION);
+    put_u32(&mut out, 0x20, bss_size);
+    put_u32(&mut out, 0x24, rel_offset as u32);
+    put_u32(&mut out, 0x28, imp_offset as u32);
+    put_u32(&mut out, 0x2c, imp_size as u32);
+    out[0x30] = prolog.0;
+    out[0x31] = epilog.0;
+    out[0x32] = unresolved.0;
+    put_u32(&mut out, 0x34, prolog.1);
+    put_u32(&mut out, 0x38, epilog.1);
+    put_u32(&mut out, 0x3c, unresolved.1);
+    put_u32(&mut out, 0x40, align as u32);
+    put_u32(&mut out, 0x44, bss_align as u32);
+    put_u32(&mut out, 0x48, fix_size as u32);
+
+    Ok(out)
+}
+
+fn push_entry(out: &mut Vec<u8>, offset: u16, kind: u8, section: u8, addend: u32) {
+    out.extend_from_slice(&offset.to_be_bytes());
+    out.push(kind);
+    out.push(section);
+    out.extend_from_slice(&addend.to_be_bytes());
+}
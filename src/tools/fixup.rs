@@ -0,0 +1,24 @@
+//! Fixups applied to converted outputs, such as header checksums and signatures that
+//! depend on the final bytes of the image.
+
+use std::{io, path::Path};
+
+/// A named step patching a converted output in place.
+pub struct Fixup {
+    pub name: &'static str,
+    pub apply: fn(&mut Vec<u8>) -> io::Result<()>,
+}
+
+/// Runs the fixups over an output file in order, rewriting it if there were any.
+pub fn apply(path: &Path, fixups: &[Fixup]) -> io::Result<()> {
+    if fixups.is_empty() {
+        return Ok(());
+    }
+    let mut data = std::fs::read(path)?;
+    for fixup in fixups {
+        (fixup.apply)(&mut data).map_err(|err| {
+            io::Error::new(err.kind(), format!("fixup '{}' failed: {err}", fixup.name))
+        })?;
+    }
+    std::fs::write(path, data)
+}
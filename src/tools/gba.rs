@@ -0,0 +1,97 @@
+use crate::elf::Elf;
+use std::{io, path::Path};
+
+/// Where the cartridge ROM is mapped.
+pub const ROM_BASE: u64 = 0x0800_0000;
+const ROM_MAX_SIZE: u64 = 32 << 20;
+const HEADER_SIZE: usize = 0xc0;
+
+/// The compressed Nintendo logo the BIOS verifies before booting a cartridge. DS ROM
+/// headers contain it as well.
+pub const LOGO: [u8; 156] = [
+    0x24, 0xff, 0xae, 0x51, 0x69, 0x9a, 0xa2, 0x21, 0x3d, 0x84, 0x82, 0x0a, 0x84, 0xe4, 0x09, 0xad,
+    0x11, 0x24, 0x8b, 0x98, 0xc0, 0x81, 0x7f, 0x21, 0xa3, 0x52, 0xbe, 0x19, 0x93, 0x09, 0xce, 0x20,
+    0x10, 0x46, 0x4a, 0x4a, 0xf8, 0x27, 0x31, 0xec, 0x58, 0xc7, 0xe8, 0x33, 0x82, 0xe3, 0xce, 0xbf,
+    0x85, 0xf4, 0xdf, 0x94, 0xce, 0x4b, 0x09, 0xc1, 0x94, 0x56, 0x8a, 0xc0, 0x13, 0x72, 0xa7, 0xfc,
+    0x9f, 0x84, 0x4d, 0x73, 0xa3, 0xca, 0x9a, 0x61, 0x58, 0x97, 0xa3, 0x27, 0xfc, 0x03, 0x98, 0x76,
+    0x23, 0x1d, 0xc7, 0x61, 0x03, 0x04, 0xae, 0x56, 0xbf, 0x38, 0x84, 0x00, 0x40, 0xa7, 0x0e, 0xfd,
+    0xff, 0x52, 0xfe, 0x03, 0x6f, 0x95, 0x30, 0xf1, 0x97, 0xfb, 0xc0, 0x85, 0x60, 0xd6, 0x80, 0x25,
+    0xa9, 0x63, 0xbe, 0x03, 0x01, 0x4e, 0x38, 0xe2, 0xf9, 0xa2, 0x34, 0xff, 0xbb, 0x3e, 0x03, 0x44,
+    0x78, 0x00, 0x90, 0xcb, 0x88, 0x11, 0x3a, 0x94, 0x65, 0xc0, 0x7c, 0x63, 0x87, 0xf0, 0x3c, 0xaf,
+    0xd6, 0x25, 0xe4, 0x8b, 0x38, 0x0a, 0xac, 0x72, 0x21, 0xd4, 0xf8, 0x07,
+];
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Converts a linked ELF to a `.gba` ROM image and fills in its header.
+///
+/// Segments are placed at their load addresses, so data copied to RAM at startup is
+/// stored in ROM. The header checksum is left to the fixup stage, see
+/// [`fix_header_checksum`].
+pub fn elf2gba(input: impl AsRef<Path>, output: impl AsRef<Path>, title: &str) -> io::Result<()> {
+    let (base, mut rom) = Elf::read(input)?.load_image()?;
+    if base != ROM_BASE || rom.len() as u64 > ROM_MAX_SIZE {
+        return Err(invalid(format!(
+            "the ROM image at 0x{base:08x}..0x{:08x} does not fit the cartridge ROM",
+            base + rom.len() as u64
+        )));
+    }
+
+    if rom.len() < HEADER_SIZE || rom[..4] == [0; 4] {
+        return Err(invalid(
+            "the ROM does not begin with a cartridge header, link it with rbrew-gba",
+        ));
+    }
+    rom[0x04..0xa0].copy_from_slice(&LOGO);
+    let title: Vec<u8> = title
+        .bytes()
+        .filter(u8::is_ascii_graphic)
+        .map(|b| b.to_ascii_uppercase())
+        .take(12)
+        .collect();
+    rom[0xa0..0xac].fill(0);
+    rom[0xa0..0xa0 + title.len()].copy_from_slice(&title);
+    rom[0xb2] = 0x96;
+
+    std::fs::write(output, rom)
+}
+
+/// Sets the header complement checksum, which the BIOS verifies before booting.
+pub fn fix_header_checksum(rom: &mut [u8]) -> io::Result<()> {
+    if rom.len() < HEADER_SIZE {
+        return Err(invalid("the ROM is too small to hold a cartridge header"));
+    }
+    let sum = rom[0xa0..0xbd]
+        .iter()
+        .fold(0u8, |sum, &b| sum.wrapping_add(b));
+    rom[0xbd] = 0u8.wrapping_sub(sum.wrapping_add(0x19));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_checksum_complements_the_header() {
+        let mut rom = vec![0; HEADER_SIZE];
+        rom[0xb2] = 0x96;
+        fix_header_checksum(&mut rom).unwrap();
+        assert_eq!(rom[0xbd], 0x51);
+
+        rom[0xa0..0xac].copy_from_slice(b"RBREW TITLE!");
+        rom[0xac..0xb0].copy_from_slice(b"ABCE");
+        fix_header_checksum(&mut rom).unwrap();
+        let sum = rom[0xa0..=0xbd]
+            .iter()
+            .fold(0x19u8, |sum, &b| sum.wrapping_add(b));
+        assert_eq!(sum, 0);
+    }
+
+    #[test]
+    fn header_checksum_needs_a_header() {
+        assert!(fix_header_checksum(&mut [0; HEADER_SIZE - 1]).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//! Assembly of `.md` ROMs for the Sega Genesis, or Mega Drive.
+//!
+//! The 68000 boots from the vector table at the start of the cartridge, followed by a
+//! header describing the game. crt0 reserves the header, which is filled in here. Its
+//! checksum over the rest of the ROM is left to the fixup stage, see [`fix_checksum`].
+
+use super::build_info;
+use crate::elf::Elf;
+use std::{io, path::Path};
+
+const VECTORS_SIZE: usize = 0x100;
+const HEADER_SIZE: usize = 0x100;
+/// Where the program starts in the ROM, and the checksum with it.
+const PROGRAM_OFFSET: usize = VECTORS_SIZE + HEADER_SIZE;
+const ROM_MAX_SIZE: usize = 4 << 20;
+/// Cartridges are at least 128 KiB.
+const ROM_MIN_SIZE: usize = 128 << 10;
+const RAM_START: u32 = 0xff_0000;
+const RAM_END: u32 = 0xff_ffff;
+const CHECKSUM: usize = 0x18e;
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Writes `text` into a space padded field of the header.
+fn put_field(rom: &mut [u8], offset: usize, len: usize, text: &str) {
+    let field = &mut rom[offset..offset + len];
+    field.fill(b' ');
+    let text = text.bytes().filter(|b| b.is_ascii_graphic() || *b == b' ');
+    for (b, c) in field.iter_mut().zip(text) {
+        *b = c;
+    }
+}
+
+/// Assembles a `.md` ROM from a linked ELF and fills in its header.
+///
+/// Segments are placed at their load addresses, so data copied to RAM at startup is
+/// stored in ROM.
+pub fn elf2md(input: impl AsRef<Path>, output: impl AsRef<Path>, title: &str) -> io::Result<()> {
+    let (base, mut rom) = Elf::read(input)?.load_image()?;
+    if base != 0 || rom.len() > ROM_MAX_SIZE {
+        return Err(invalid(format!(
+            "the ROM image at 0x{base:06x}..0x{:06x} does not fit the cartridge ROM",
+            base + rom.len() as u64
+        )));
+    }
+    if rom.len() < PROGRAM_OFFSET || rom[VECTORS_SIZE..PROGRAM_OFFSET] != [0; HEADER_SIZE] {
+        return Err(invalid(
+            "the ROM does not reserve space for the cartridge header, link it with rbrew-genesis",
+        ));
+    }
+    // Cartridge ROMs come in sizes of powers of two.
+    rom.resize(rom.len().next_power_of_two().max(ROM_MIN_SIZE), 0);
+
+    // The copyright field carries the build date, as "(C)XXXX YYYY.MMM".
+    let timestamp = build_info::timestamp();
+    let year = &timestamp[..4];
+    let month = timestamp[5..7]
+        .parse::<usize>()
+        .ok()
+        .and_then(|month| MONTHS.get(month.wrapping_sub(1)))
+        .unwrap_or(&"JAN");
+    let title = title.to_ascii_uppercase();
+
+    put_field(&mut rom, 0x100, 16, "SEGA MEGA DRIVE");
+    put_field(&mut rom, 0x110, 16, &format!("(C)RBRW {year}.{month}"));
+    put_field(&mut rom, 0x120, 48, &title);
+    put_field(&mut rom, 0x150, 48, &title);
+    // A game, with the serial number homebrew conventionally uses.
+    put_field(&mut rom, 0x180, 14, "GM 00000000-00");
+    // A three button controller.
+    put_field(&mut rom, 0x190, 16, "J");
+    let rom_end = rom.len() as u32 - 1;
+    rom[0x1a0..0x1a4].copy_from_slice(&0u32.to_be_bytes());
+    rom[0x1a4..0x1a8].copy_from_slice(&rom_end.to_be_bytes());
+    rom[0x1a8..0x1ac].copy_from_slice(&RAM_START.to_be_bytes());
+    rom[0x1ac..0x1b0].copy_from_slice(&RAM_END.to_be_bytes());
+    // No save RAM, no modem and no notes.
+    put_field(&mut rom, 0x1b0, 64, "");
+    // Region free.
+    put_field(&mut rom, 0x1f0, 16, "JUE");
+
+    std::fs::write(output, rom)
+}
+
+/// Sets the checksum over the program, which the boot code of many games verifies.
+pub fn fix_checksum(rom: &mut [u8]) -> io::Result<()> {
+    if rom.len() < PROGRAM_OFFSET {
+        return Err(invalid("the ROM is too small to hold a cartridge header"));
+    }
+    let sum = rom[PROGRAM_OFFSET..]
+        .chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]))
+        .fold(0u16, u16::wrapping_add);
+    rom[CHECKSUM..CHECKSUM + 2].copy_from_slice(&sum.to_be_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_sums_the_program_words() {
+        let mut rom = vec![0; PROGRAM_OFFSET];
+        rom.extend_from_slice(&[0x12, 0x34, 0xff, 0xff, 0x00, 0x02, 0xab]);
+        fix_checksum(&mut rom).unwrap();
+        // 0x1234 + 0xffff + 0x0002 + 0xab00, wrapped, with the odd byte padded.
+        assert_eq!(rom[CHECKSUM..CHECKSUM + 2], [0xbd, 0x35]);
+
+        // The header, checksum included, is left out of the sum.
+        rom[0x100] = 0xff;
+        fix_checksum(&mut rom).unwrap();
+        assert_eq!(rom[CHECKSUM..CHECKSUM + 2], [0xbd, 0x35]);
+    }
+
+    #[test]
+    fn checksum_needs_a_header() {
+        assert!(fix_checksum(&mut [0; PROGRAM_OFFSET - 1]).is_err());
+    }
+}
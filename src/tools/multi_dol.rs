@@ -0,0 +1,129 @@
+//! Workspaces producing a primary `boot.dol` and secondary DOLs it loads at runtime.
+//!
+//! The layout is declared in `rbrew.toml`:
+//!
+//! ```toml
+//! [dol]
+//! primary = "game"
+//!
+//! [dol.secondary.minigame]
+//! address = "0x80c00000"
+//! size = "4M"
+//! ```
+//!
+//! Every secondary package is linked at its own address, and after conversion the DOLs are
+//! checked to stay within their ranges and clear of the primary.
+
+use super::Dol;
+use crate::config;
+use std::io;
+
+/// The name the primary DOL is written as.
+pub const PRIMARY_NAME: &str = "boot";
+
+pub struct SecondaryDol {
+    pub package: String,
+    pub address: u32,
+    pub size: u32,
+}
+
+impl SecondaryDol {
+    pub fn end(&self) -> u64 {
+        self.address as u64 + self.size as u64
+    }
+
+    /// Rust flags linking the package at its address. The platform's linker script fixes
+    /// the origin of its memory, which `--image-base` does not move, so it takes the
+    /// address as `__rbrew_origin`.
+    pub fn rustflags(&self) -> Vec<String> {
+        vec![
+            "-C".to_string(),
+            format!("link-arg=--defsym=__rbrew_origin=0x{:08x}", self.address),
+        ]
+    }
+}
+
+pub struct DolLayout {
+    pub primary: String,
+    pub secondary: Vec<SecondaryDol>,
+}
+
+impl DolLayout {
+    /// Reads the layout from `rbrew.toml`, returning `None` if it declares none.
+    pub fn load() -> io::Result<Option<Self>> {
+        let Some(dol) = config::load_table("dol")? else {
+            return Ok(None);
+        };
+        let primary = dol
+            .get("primary")
+            .and_then(toml::Value::as_str)
+            .ok_or_else(|| config::invalid("'dol.primary' must name the primary package"))?
+            .to_string();
+
+        let mut secondary = vec![];
+        if let Some(table) = dol.get("secondary") {
+            let table = table
+                .as_table()
+                .ok_or_else(|| config::invalid("'dol.secondary' must be a table"))?;
+            for (package, entry) in table {
+                let field = |name: &str| {
+                    entry
+                        .get(name)
+                        .and_then(config::parse_size)
+                        .and_then(|value| u32::try_from(value).ok())
+                        .ok_or_else(|| {
+                            config::invalid(format!(
+                                "'dol.secondary.{package}.{name}' must be a 32-bit address or size"
+                            ))
+                        })
+                };
+                secondary.push(SecondaryDol {
+                    package: package.clone(),
+                    address: field("address")?,
+                    size: field("size")?,
+                });
+            }
+        }
+
+        let mut ranges: Vec<&SecondaryDol> = secondary.iter().collect();
+        ranges.sort_by_key(|dol| dol.address);
+        // Compared with the furthest reaching range before it, not only the previous one.
+        let mut furthest: Option<&SecondaryDol> = None;
+        for dol in ranges {
+            if let Some(before) = furthest.filter(|before| (dol.address as u64) < before.end()) {
+                return Err(config::invalid(format!(
+                    "the ranges of the secondary DOLs '{}' and '{}' overlap",
+                    before.package, dol.package
+                )));
+            }
+            furthest = Some(dol);
+        }
+
+        Ok(Some(Self { primary, secondary }))
+    }
+}
+
+/// Checks that every secondary DOL lies within its declared range and does not overlap
+/// the primary, which is still running while they are loaded.
+pub fn check(primary: &Dol, secondary: &[(&SecondaryDol, Dol)]) -> Vec<String> {
+    let mut issues = vec![];
+    let (primary_start, primary_end) = primary.extent();
+    for (layout, dol) in secondary {
+        let (start, end) = dol.extent();
+        if (start as u64) < layout.address as u64 || end as u64 > layout.end() {
+            issues.push(format!(
+                "'{}' occupies 0x{start:08x}..0x{end:08x}, outside its range 0x{:08x}..0x{:08x}",
+                layout.package,
+                layout.address,
+                layout.end()
+            ));
+        }
+        if start < primary_end && primary_start < end {
+            issues.push(format!(
+                "'{}' (0x{start:08x}..0x{end:08x}) overlaps the primary DOL (0x{primary_start:08x}..0x{primary_end:08x})",
+                layout.package
+            ));
+        }
+    }
+    issues
+}
@@ -0,0 +1,240 @@
+//! Assembly of `.z64` ROMs for the Nintendo 64.
+//!
+//! The console's boot code, IPL3, checks the cartridge's CIC security chip and copies the
+//! first megabyte after it to RDRAM. rbrew cannot ship IPL3, so it is read from a file
+//! dumped from a cartridge with the CIC the ROM is meant for, named in `rbrew.toml`:
+//!
+//! ```toml
+//! [n64]
+//! ipl3 = "ipl3.bin"
+//! ```
+
+use super::patch::crc32;
+use crate::{config, elf::Elf};
+use std::{io, path::Path};
+
+const HEADER_SIZE: usize = 0x40;
+const IPL3_SIZE: usize = 0x1000 - HEADER_SIZE;
+/// Where the program starts in the ROM.
+const PROGRAM_OFFSET: usize = 0x1000;
+/// How much of the program IPL3 copies to RDRAM and checksums.
+const PROGRAM_SIZE: usize = 0x10_0000;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn be_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// The CIC chips, which each come with their own IPL3.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Cic {
+    Cic6101,
+    Cic6102,
+    Cic6103,
+    Cic6105,
+    Cic6106,
+    Cic7102,
+}
+
+impl Cic {
+    fn identify(ipl3: &[u8]) -> Option<Self> {
+        Some(match crc32(ipl3) {
+            0x6170_a4a1 => Self::Cic6101,
+            0x90bb_6cb5 => Self::Cic6102,
+            0x0b05_0ee0 => Self::Cic6103,
+            0x98bc_2c86 => Self::Cic6105,
+            0xacc8_580a => Self::Cic6106,
+            0x009e_9ea3 => Self::Cic7102,
+            _ => return None,
+        })
+    }
+
+    fn seed(self) -> u32 {
+        match self {
+            Self::Cic6101 | Self::Cic6102 | Self::Cic7102 => 0xf8ca_4ddc,
+            Self::Cic6103 => 0xa388_6759,
+            Self::Cic6105 => 0xdf26_f436,
+            Self::Cic6106 => 0x1fea_617a,
+        }
+    }
+
+    /// How far below the boot address in the header IPL3 loads the program.
+    fn load_offset(self) -> u32 {
+        match self {
+            Self::Cic6103 => 0x10_0000,
+            Self::Cic6106 => 0x20_0000,
+            _ => 0,
+        }
+    }
+}
+
+/// Reads the IPL3 named in `rbrew.toml`, either on its own or as the first 4 KiB of a
+/// ROM.
+pub fn load_ipl3() -> io::Result<Vec<u8>> {
+    let path = config::load_table("n64")?
+        .and_then(|n64| {
+            n64.get("ipl3")
+                .and_then(toml::Value::as_str)
+                .map(str::to_string)
+        })
+        .ok_or_else(|| config::invalid("'n64.ipl3' must name a file holding the IPL3 boot code"))?;
+    let data = std::fs::read(&path)
+        .map_err(|err| io::Error::new(err.kind(), format!("failed to read '{path}': {err}")))?;
+    match data.len() {
+        IPL3_SIZE => Ok(data),
+        PROGRAM_OFFSET => Ok(data[HEADER_SIZE..].to_vec()),
+        len => Err(invalid(format!(
+            "'{path}' is {len} bytes, but IPL3 is {IPL3_SIZE} bytes"
+        ))),
+    }
+}
+
+/// Assembles a `.z64` ROM from a linked ELF and IPL3.
+///
+/// The checksum is left to the fixup stage, see [`fix_checksum`].
+pub fn elf2z64(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    title: &str,
+    ipl3: &[u8],
+) -> io::Result<()> {
+    let elf = Elf::read(input)?;
+    let (base, image) = elf.load_image()?;
+    if elf.entry != base {
+        return Err(invalid(format!(
+            "the entry point 0x{:08x} must be at the start of the image, 0x{base:08x}",
+            elf.entry
+        )));
+    }
+    if image.len() > PROGRAM_SIZE {
+        return Err(invalid(format!(
+            "the image is {} bytes, but IPL3 only loads the first {PROGRAM_SIZE}",
+            image.len()
+        )));
+    }
+    let cic = Cic::identify(ipl3).ok_or_else(|| invalid("the IPL3 is not one of a known CIC"))?;
+
+    let mut rom = vec![0; PROGRAM_OFFSET];
+    // The PI bus timings, clock rate, boot address and libultra version.
+    rom[0x00..0x04].copy_from_slice(&0x8037_1240_u32.to_be_bytes());
+    rom[0x04..0x08].copy_from_slice(&0x0000_000f_u32.to_be_bytes());
+    rom[0x08..0x0c].copy_from_slice(&(base as u32 + cic.load_offset()).to_be_bytes());
+    rom[0x0c..0x10].copy_from_slice(&0x0000_144c_u32.to_be_bytes());
+    rom[0x20..0x34].fill(b' ');
+    let name: Vec<u8> = title
+        .bytes()
+        .filter(u8::is_ascii_graphic)
+        .map(|b| b.to_ascii_uppercase())
+        .take(20)
+        .collect();
+    rom[0x20..0x20 + name.len()].copy_from_slice(&name);
+    // A cartridge, with the game ID homebrew conventionally uses.
+    rom[0x3b] = b'N';
+    rom[0x3c..0x3e].copy_from_slice(b"ED");
+    rom[0x3e] = b'E';
+    rom[HEADER_SIZE..PROGRAM_OFFSET].copy_from_slice(ipl3);
+
+    rom.extend_from_slice(&image);
+    // IPL3 checksums a full megabyte, and cartridges come in sizes of whole megabytes.
+    rom.resize(
+        rom.len()
+            .max(PROGRAM_OFFSET + PROGRAM_SIZE)
+            .next_multiple_of(1 << 20),
+        0,
+    );
+
+    std::fs::write(output, rom)
+}
+
+/// The checksum IPL3 computes over the first megabyte of the program.
+fn checksum(cic: Cic, ipl3: &[u8], program: &[u8]) -> (u32, u32) {
+    let seed = cic.seed();
+    let (mut t1, mut t2, mut t3, mut t4, mut t5, mut t6) = (seed, seed, seed, seed, seed, seed);
+    for i in (0..PROGRAM_SIZE).step_by(4) {
+        let d = be_u32(program, i);
+        let (sum, carry) = t6.overflowing_add(d);
+        if carry {
+            t4 = t4.wrapping_add(1);
+        }
+        t6 = sum;
+        t3 ^= d;
+        let r = d.rotate_left(d & 0x1f);
+        t5 = t5.wrapping_add(r);
+        if t2 > d {
+            t2 ^= r;
+        } else {
+            t2 ^= t6 ^ d;
+        }
+        t1 = t1.wrapping_add(match cic {
+            // The 6105's IPL3 hides a table it mixes into the checksum.
+            Cic::Cic6105 => be_u32(ipl3, 0x0710 + (i & 0xff)) ^ d,
+            _ => t5 ^ d,
+        });
+    }
+    match cic {
+        Cic::Cic6103 => ((t6 ^ t4).wrapping_add(t3), (t5 ^ t2).wrapping_add(t1)),
+        Cic::Cic6106 => (
+            t6.wrapping_mul(t4).wrapping_add(t3),
+            t5.wrapping_mul(t2).wrapping_add(t1),
+        ),
+        _ => (t6 ^ t4 ^ t3, t5 ^ t2 ^ t1),
+    }
+}
+
+/// Sets the checksum IPL3 verifies over the first megabyte of the program.
+pub fn fix_checksum(rom: &mut [u8]) -> io::Result<()> {
+    if rom.len() < PROGRAM_OFFSET + PROGRAM_SIZE {
+        return Err(invalid("the ROM is too small to hold IPL3 and the program"));
+    }
+    let ipl3 = &rom[HEADER_SIZE..PROGRAM_OFFSET];
+    let cic =
+        Cic::identify(ipl3).ok_or_else(|| invalid("the ROM's IPL3 is not one of a known CIC"))?;
+    let (crc1, crc2) = checksum(
+        cic,
+        ipl3,
+        &rom[PROGRAM_OFFSET..PROGRAM_OFFSET + PROGRAM_SIZE],
+    );
+    rom[0x10..0x14].copy_from_slice(&crc1.to_be_bytes());
+    rom[0x14..0x18].copy_from_slice(&crc2.to_be_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksum_of_an_empty_program() {
+        // Every word being 0 leaves the seed in all but t1, which adds it once per word.
+        let program = vec![0; PROGRAM_SIZE];
+        assert_eq!(
+            checksum(Cic::Cic6102, &[], &program),
+            (0xf8ca_4ddc, 0x303a_4ddc)
+        );
+        assert_eq!(
+            checksum(Cic::Cic6103, &[], &program),
+            (0xa388_6759, 0x40ec_6759)
+        );
+    }
+
+    #[test]
+    fn checksum_covers_only_the_first_megabyte() {
+        let mut program: Vec<u8> = (0..PROGRAM_SIZE as u32 + 4)
+            .map(|i| (i.wrapping_mul(0x9e37_79b9) >> 24) as u8)
+            .collect();
+        let crc = checksum(Cic::Cic6102, &[], &program[..PROGRAM_SIZE]);
+        program[PROGRAM_SIZE] ^= 1;
+        assert_eq!(checksum(Cic::Cic6102, &[], &program[..PROGRAM_SIZE]), crc);
+        program[PROGRAM_SIZE - 1] ^= 1;
+        assert_ne!(checksum(Cic::Cic6102, &[], &program[..PROGRAM_SIZE]), crc);
+    }
+
+    #[test]
+    fn fix_checksum_needs_a_known_ipl3() {
+        assert!(fix_checksum(&mut vec![0; PROGRAM_OFFSET]).is_err());
+        assert!(fix_checksum(&mut vec![0; PROGRAM_OFFSET + PROGRAM_SIZE]).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! Assembly of `.nds` ROMs from the ARM9 and ARM7 binaries.
+//!
+//! The packages for both CPUs are declared in `rbrew.toml`:
+//!
+//! ```toml
+//! [nds]
+//! arm9 = "game"
+//! arm7 = "game-arm7"
+//! ```
+//!
+//! The ARM7 package is optional, without one the ARM7 idles.
+
+use super::validate::{LayoutRules, MemoryRegion};
+use crate::{
+    config,
+    elf::{self, Elf},
+};
+use std::{borrow::Cow, io, path::Path};
+
+pub const ARM7_CONFIG: &str = "nds-arm7.toml";
+pub const ARM7_LINKER_SCRIPT: &str = "nds-arm7.ld";
+
+/// Where the ARM7 binary runs, its private WRAM.
+const ARM7_WRAM: u32 = 0x037f_8000;
+/// An ARM7 binary idling forever (`b .`), used when the project has no ARM7 package.
+const ARM7_IDLE: [u8; 4] = 0xeaff_fffe_u32.to_le_bytes();
+
+pub const ARM7_LAYOUT: LayoutRules = LayoutRules {
+    machine: elf::EM_ARM,
+    regions: Cow::Borrowed(&[
+        MemoryRegion {
+            name: Cow::Borrowed("main RAM"),
+            start: 0x0238_0000,
+            end: 0x023f_f000,
+        },
+        MemoryRegion {
+            name: Cow::Borrowed("ARM7 WRAM"),
+            start: ARM7_WRAM as u64,
+            end: 0x0381_0000,
+        },
+    ]),
+    load_alignment: 4,
+    relocations: elf::ARM_STATIC_RELOCATIONS,
+    position_independent: false,
+};
+
+pub struct NdsLayout {
+    pub arm9: Option<String>,
+    pub arm7: Option<String>,
+}
+
+impl NdsLayout {
+    /// Reads the packages from `rbrew.toml`, returning `None` if it declares none.
+    pub fn load() -> io::Result<Option<Self>> {
+        let Some(nds) = config::load_table("nds")? else {
+            return Ok(None);
+        };
+        let package = |name: &str| match nds.get(name) {
+            None => Ok(None),
+            Some(toml::Value::String(package)) => Ok(Some(package.clone())),
+            Some(_) => Err(config::invalid(format!("'nds.{name}' must name a package"))),
+        };
+        Ok(Some(Self {
+            arm9: package("arm9")?,
+            arm7: package("arm7")?,
+        }))
+    }
+}
+
+const HEADER_SIZE: usize = 0x4000;
+const SECURE_AREA: std::ops::Range<usize> = 0x4000..0x8000;
+const BANNER_SIZE: usize = 0x840;
+const ALIGN: usize = 0x200;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// The CRC-16 used throughout DS headers and banners.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |mut crc, &b| {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+        crc
+    })
+}
+
+fn put_u32(rom: &mut [u8], offset: usize, value: u32) {
+    rom[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u16(rom: &mut [u8], offset: usize, value: u16) {
+    rom[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+struct Binary {
+    address: u32,
+    entry: u32,
+    data: Vec<u8>,
+}
+
+impl Binary {
+    fn from_elf(path: &Path) -> io::Result<Self> {
+        let elf = Elf::read(path)?;
+        let (address, data) = elf.load_image()?;
+        if data.is_empty() {
+            return Err(invalid(format!("'{}' has nothing to load", path.display())));
+        }
+        Ok(Self {
+            address: address as u32,
+            entry: elf.entry as u32,
+            data,
+        })
+    }
+}
+
+fn banner(title: &str) -> Vec<u8> {
+    let mut banner = vec![0; BANNER_SIZE];
+    put_u16(&mut banner, 0, 1);
+    // The icon bitmap and palette stay empty. Every language gets the same title.
+    let title: Vec<u16> = title.encode_utf16().take(127).collect();
+    for language in 0..6 {
+        let start = 0x240 + language * 0x100;
+        for (i, c) in title.iter().enumerate() {
+            put_u16(&mut banner, start + i * 2, *c);
+        }
+    }
+    let crc = crc16(&banner[0x20..]);
+    put_u16(&mut banner, 2, crc);
+    banner
+}
+
+fn pad(rom: &mut Vec<u8>) {
+    rom.resize(rom.len().next_multiple_of(ALIGN), 0);
+}
+
+/// Assembles a `.nds` ROM from the linked ARM9 and ARM7 ELFs.
+///
+/// Without an ARM7 ELF, the ARM7 idles in its WRAM. The header checksums are left to the
+/// fixup stage, see [`fix_header_checksums`].
+pub fn elf2nds(
+    arm9: &Path,
+    arm7: Option<&Path>,
+    output: impl AsRef<Path>,
+    title: &str,
+) -> io::Result<()> {
+    let arm9 = Binary::from_elf(arm9)?;
+    let arm7 = match arm7 {
+        Some(path) => Binary::from_elf(path)?,
+        None => Binary {
+            address: ARM7_WRAM,
+            entry: ARM7_WRAM,
+            data: ARM7_IDLE.to_vec(),
+        },
+    };
+
+    let mut rom = vec![0; HEADER_SIZE];
+    let name: Vec<u8> = title
+        .bytes()
+        .filter(u8::is_ascii_graphic)
+        .map(|b| b.to_ascii_uppercase())
+        .take(12)
+        .collect();
+    rom[..name.len()].copy_from_slice(&name);
+    // The game and maker codes homebrew conventionally uses.
+    rom[0x0c..0x10].copy_from_slice(b"####");
+    rom[0x10..0x12].copy_from_slice(b"00");
+
+    let place = |rom: &mut Vec<u8>, header: usize, binary: &Binary| {
+        let offset = rom.len() as u32;
+        rom.extend_from_slice(&binary.data);
+        pad(rom);
+        put_u32(rom, header, offset);
+        put_u32(rom, header + 4, binary.entry);
+        put_u32(rom, header + 8, binary.address);
+        put_u32(rom, header + 12, binary.data.len() as u32);
+    };
+    place(&mut rom, 0x20, &arm9);
+    place(&mut rom, 0x30, &arm7);
+
+    // An empty file system: the root directory with no entries, and no files.
+    let fnt = rom.len() as u32;
+    rom.extend_from_slice(&[8, 0, 0, 0, 0, 0, 1, 0, 0]);
+    pad(&mut rom);
+    put_u32(&mut rom, 0x40, fnt);
+    put_u32(&mut rom, 0x44, 9);
+    let fat = rom.len() as u32;
+    put_u32(&mut rom, 0x48, fat);
+
+    let banner_offset = rom.len() as u32;
+    rom.extend_from_slice(&banner(title));
+    put_u32(&mut rom, 0x68, banner_offset);
+
+    // The default ROM control settings used by homebrew.
+    put_u32(&mut rom, 0x60, 0x0058_6000);
+    put_u32(&mut rom, 0x64, 0x0018_08f8);
+    put_u16(&mut rom, 0x6e, 0x051e);
+    let used = rom.len() as u32;
+    put_u32(&mut rom, 0x80, used);
+    put_u32(&mut rom, 0x84, HEADER_SIZE as u32);
+    rom[0xc0..0x15c].copy_from_slice(&super::gba::LOGO);
+
+    // The smallest capacity of 128 KiB << n that holds the ROM.
+    let mut capacity = 0;
+    while (0x20000usize << capacity) < rom.len() {
+        capacity += 1;
+    }
+    rom[0x14] = capacity;
+    pad(&mut rom);
+
+    std::fs::write(output, rom)
+}
+
+/// Sets the secure area, logo and header CRCs of a `.nds` ROM.
+pub fn fix_header_checksums(rom: &mut [u8]) -> io::Result<()> {
+    if rom.len() < HEADER_SIZE {
+        return Err(invalid("the ROM is too small to hold a DS header"));
+    }
+    let mut secure_area = [0; SECURE_AREA.end - SECURE_AREA.start];
+    let available = rom
+        .len()
+        .min(SECURE_AREA.end)
+        .saturating_sub(SECURE_AREA.start);
+    secure_area[..available]
+        .copy_from_slice(&rom[SECURE_AREA.start..SECURE_AREA.start + available]);
+    put_u16(rom, 0x6c, crc16(&secure_area));
+    put_u16(rom, 0x15c, crc16(&rom[0xc0..0x15c]));
+    put_u16(rom, 0x15e, crc16(&rom[..0x15e]));
+    Ok(())
+}
@@ -0,0 +1,210 @@
+//! Conversion of Switch executables to the `.nro` format the homebrew loader runs.
+//!
+//! An NRO is the memory image of a position independent program, with its header placed
+//! in space crt0 reserves at the start of the code. The NACP metadata and icon shown in
+//! the homebrew menu are appended as assets and read from `rbrew.toml`:
+//!
+//! ```toml
+//! [nacp]
+//! title = "Game"
+//! author = "Someone"
+//! version = "1.0.0"
+//! icon = "icon.jpg"
+//! ```
+
+use crate::{
+    config,
+    elf::{self, Elf},
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+const PAGE: u64 = 0x1000;
+/// The start of the code holds a branch, the offset of the MOD0 header and the NRO header.
+const START_SIZE: usize = 0x10;
+const HEADER_SIZE: usize = 0x70;
+const ASSET_HEADER_SIZE: usize = 0x38;
+const NACP_SIZE: usize = 0x4000;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// The metadata the homebrew menu displays for an application.
+pub struct Nacp {
+    pub title: String,
+    pub author: String,
+    pub version: String,
+    /// A 256x256 JPEG shown as the icon.
+    pub icon: Option<PathBuf>,
+}
+
+impl Nacp {
+    /// Reads the metadata from `rbrew.toml`, using `title` unless it names one.
+    pub fn load(title: &str) -> io::Result<Self> {
+        let nacp = config::load_table("nacp")?.unwrap_or_default();
+        let field = |name: &str| match nacp.get(name) {
+            None => Ok(None),
+            Some(toml::Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(config::invalid(format!("'nacp.{name}' must be a string"))),
+        };
+        Ok(Self {
+            title: field("title")?.unwrap_or_else(|| title.to_string()),
+            author: field("author")?.unwrap_or_else(|| "Unknown".to_string()),
+            version: field("version")?.unwrap_or_else(|| "1.0.0".to_string()),
+            icon: field("icon")?.map(PathBuf::from),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut nacp = vec![0; NACP_SIZE];
+        let put = |nacp: &mut [u8], offset: usize, len: usize, text: &str| {
+            // Leave room for the terminator without splitting a character.
+            let mut end = text.len().min(len - 1);
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            nacp[offset..offset + end].copy_from_slice(&text.as_bytes()[..end]);
+        };
+        // Every language gets the same title and author.
+        for language in 0..16 {
+            let start = language * 0x300;
+            put(&mut nacp, start, 0x200, &self.title);
+            put(&mut nacp, start + 0x200, 0x100, &self.author);
+        }
+        put(&mut nacp, 0x3060, 0x10, &self.version);
+        nacp
+    }
+}
+
+fn put_u32(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes());
+}
+
+/// Converts a linked ELF to a `.nro` executable with the given metadata.
+pub fn elf2nro(input: impl AsRef<Path>, output: impl AsRef<Path>, nacp: &Nacp) -> io::Result<()> {
+    let elf = Elf::read(input)?;
+    if elf.kind != elf::ET_DYN || elf.entry != 0 {
+        return Err(invalid(
+            "the ELF must be position independent and start at its entry point",
+        ));
+    }
+
+    // The code, rodata and data segments, in that order and each starting on a page.
+    let segments: Vec<&elf::Segment> = elf
+        .segments
+        .iter()
+        .filter(|segment| segment.kind == elf::PT_LOAD && segment.memsz != 0)
+        .collect();
+    let &[text, ro, data] = segments.as_slice() else {
+        return Err(invalid(format!(
+            "the ELF has {} segments instead of code, rodata and data, link it with targets/switch.ld",
+            segments.len()
+        )));
+    };
+    let mut address = 0;
+    for (segment, name) in [(text, "code"), (ro, "rodata"), (data, "data")] {
+        if segment.vaddr != address {
+            return Err(invalid(format!(
+                "the {name} segment is at 0x{:08x} instead of 0x{address:08x}, link it with targets/switch.ld",
+                segment.vaddr
+            )));
+        }
+        address = (segment.vaddr + segment.memsz).next_multiple_of(PAGE);
+    }
+
+    // The image is stored as it is laid out in memory, up to the bss.
+    let data_size = data.filesz.next_multiple_of(PAGE);
+    let bss_size = (data.memsz.next_multiple_of(PAGE)).saturating_sub(data_size);
+    let mut image = vec![0; (data.vaddr + data_size) as usize];
+    for segment in [text, ro, data] {
+        let contents = elf.segment_data(segment)?;
+        let start = segment.vaddr as usize;
+        image[start..start + contents.len()].copy_from_slice(contents);
+    }
+
+    if image.len() < START_SIZE + HEADER_SIZE
+        || image[START_SIZE..START_SIZE + HEADER_SIZE] != [0; HEADER_SIZE]
+    {
+        return Err(invalid(
+            "the code does not reserve space for the NRO header, link it with rbrew-switch",
+        ));
+    }
+    let mod0 = u32::from_le_bytes(image[4..8].try_into().unwrap()) as usize;
+    if image.get(mod0..mod0 + 4) != Some(b"MOD0") {
+        return Err(invalid(
+            "the code does not point to a MOD0 header, link it with rbrew-switch",
+        ));
+    }
+
+    let header = &mut image[START_SIZE..START_SIZE + HEADER_SIZE];
+    header[..4].copy_from_slice(b"NRO0");
+    put_u32(header, 0x08, data.vaddr + data_size);
+    for (i, (offset, size)) in [
+        (text.vaddr, ro.vaddr - text.vaddr),
+        (ro.vaddr, data.vaddr - ro.vaddr),
+        (data.vaddr, data_size),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        put_u32(header, 0x10 + i * 8, offset);
+        put_u32(header, 0x14 + i * 8, size);
+    }
+    put_u32(header, 0x28, bss_size);
+
+    // The build ID identifies the module in crash reports, when the linker emitted one.
+    if let Some(note) = elf.section_by_name(".note.gnu.build-id") {
+        let note = elf.section_data(note)?;
+        if let Some(id) = note.get(16..) {
+            let len = id.len().min(0x20);
+            header[0x30..0x30 + len].copy_from_slice(&id[..len]);
+        }
+    }
+
+    // The dynamic symbols, relative to the rodata segment.
+    for (i, name) in [".dynstr", ".dynsym"].into_iter().enumerate() {
+        if let Some(section) = elf.section_by_name(name) {
+            put_u32(header, 0x60 + i * 8, section.addr - ro.vaddr);
+            put_u32(header, 0x64 + i * 8, section.size);
+        }
+    }
+
+    // The assets: the icon and the NACP. There is no RomFS.
+    let icon = match &nacp.icon {
+        Some(path) => {
+            let icon = std::fs::read(path).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("failed to read the icon '{}': {err}", path.display()),
+                )
+            })?;
+            if !icon.starts_with(&[0xff, 0xd8]) {
+                return Err(invalid(format!(
+                    "the icon '{}' is not a JPEG",
+                    path.display()
+                )));
+            }
+            icon
+        }
+        None => vec![],
+    };
+    let mut assets = vec![0; ASSET_HEADER_SIZE];
+    assets[..4].copy_from_slice(b"ASET");
+    let section = |assets: &mut Vec<u8>, index: usize, contents: &[u8]| {
+        let offset = assets.len() as u64;
+        assets[0x08 + index * 0x10..0x10 + index * 0x10].copy_from_slice(&offset.to_le_bytes());
+        assets[0x10 + index * 0x10..0x18 + index * 0x10]
+            .copy_from_slice(&(contents.len() as u64).to_le_bytes());
+        assets.extend_from_slice(contents);
+    };
+    if !icon.is_empty() {
+        section(&mut assets, 0, &icon);
+    }
+    section(&mut assets, 1, &nacp.to_bytes());
+
+    image.extend_from_slice(&assets);
+    std::fs::write(output, image)
+}
@@ -0,0 +1,83 @@
+//! Packaging of a build's outputs into a zip archive for distribution.
+//!
+//! Entries are named by their paths relative to the output directory, so layouts such as
+//! the PSP's `PSP/GAME/<name>/EBOOT.PBP` are kept when the archive is extracted onto a
+//! memory card. Entries are dated 1980-01-01, the earliest date zip can store, so that
+//! archives of the same outputs are identical.
+
+use super::patch::crc32;
+use std::{io, path::Path};
+
+const DEFLATE_LEVEL: u8 = 9;
+/// Version 2.0, which introduced deflate.
+const ZIP_VERSION: u16 = 20;
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Writes a zip archive of `files`, each the path to store it as and its contents.
+pub fn write_zip(output: impl AsRef<Path>, files: &[(String, Vec<u8>)]) -> io::Result<()> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "too large for a zip archive");
+    let mut out = vec![];
+    let mut central = vec![];
+    for (name, data) in files {
+        let deflated = miniz_oxide::deflate::compress_to_vec(data, DEFLATE_LEVEL);
+        // Data that does not shrink is stored as it is.
+        let (method, contents) = if deflated.len() < data.len() {
+            (8, deflated.as_slice())
+        } else {
+            (0, data.as_slice())
+        };
+        let offset = u32::try_from(out.len()).map_err(|_| too_large())?;
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        let name = name.replace('\\', "/");
+
+        // The fields the local and central headers share.
+        let mut fields = vec![];
+        push_u16(&mut fields, ZIP_VERSION);
+        push_u16(&mut fields, 0);
+        push_u16(&mut fields, method);
+        push_u16(&mut fields, 0);
+        push_u16(&mut fields, DOS_DATE);
+        push_u32(&mut fields, crc32(data));
+        push_u32(&mut fields, contents.len() as u32);
+        push_u32(&mut fields, size);
+        push_u16(&mut fields, name.len() as u16);
+        push_u16(&mut fields, 0);
+
+        push_u32(&mut out, 0x0403_4b50);
+        out.extend_from_slice(&fields);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(contents);
+
+        push_u32(&mut central, 0x0201_4b50);
+        push_u16(&mut central, ZIP_VERSION);
+        central.extend_from_slice(&fields);
+        // No comment, on the first disk, with no attributes.
+        push_u16(&mut central, 0);
+        push_u16(&mut central, 0);
+        push_u16(&mut central, 0);
+        push_u32(&mut central, 0);
+        push_u32(&mut central, offset);
+        central.extend_from_slice(name.as_bytes());
+    }
+
+    let central_offset = u32::try_from(out.len()).map_err(|_| too_large())?;
+    let count = u16::try_from(files.len()).map_err(|_| too_large())?;
+    out.extend_from_slice(&central);
+    push_u32(&mut out, 0x0605_4b50);
+    push_u16(&mut out, 0);
+    push_u16(&mut out, 0);
+    push_u16(&mut out, count);
+    push_u16(&mut out, count);
+    push_u32(&mut out, central.len() as u32);
+    push_u32(&mut out, central_offset);
+    push_u16(&mut out, 0);
+    std::fs::write(output, out)
+}
@@ -0,0 +1,373 @@
+use std::io;
+
+/// The largest file offset an IPS record can address.
+const IPS_MAX_SIZE: usize = 1 << 24;
+const IPS_MAX_RECORD: usize = 0xffff;
+/// The offset `0x454f46` spells `EOF` and would end the patch early.
+const IPS_EOF: usize = 0x454f46;
+
+/// Unchanged gaps shorter than this are folded into the surrounding records, since a new
+/// record costs more than repeating the bytes.
+const MERGE_GAP: usize = 6;
+/// Runs of a single byte at least this long are stored as RLE records.
+const RLE_MIN: usize = 9;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn differs(base: &[u8], target: &[u8], i: usize) -> bool {
+    base.get(i) != Some(&target[i])
+}
+
+/// The ranges of `target` that differ from `base`, with short unchanged gaps merged.
+fn changed_ranges(base: &[u8], target: &[u8]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    let mut i = 0;
+    while i < target.len() {
+        if !differs(base, target, i) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < target.len() && differs(base, target, i) {
+            i += 1;
+        }
+        match ranges.last_mut() {
+            Some(last) if start - last.1 < MERGE_GAP => last.1 = i,
+            _ => ranges.push((start, i)),
+        }
+    }
+    ranges
+}
+
+fn push_ips_record(out: &mut Vec<u8>, offset: usize, data: &[u8]) {
+    out.extend_from_slice(&(offset as u32).to_be_bytes()[1..]);
+    let run = data.iter().take_while(|&&b| b == data[0]).count();
+    if run == data.len() && run >= RLE_MIN {
+        out.extend_from_slice(&[0, 0]);
+        out.extend_from_slice(&(run as u16).to_be_bytes());
+        out.push(data[0]);
+    } else {
+        out.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        out.extend_from_slice(data);
+    }
+}
+
+/// Creates an IPS patch turning `base` into `target`.
+pub fn ips(base: &[u8], target: &[u8]) -> io::Result<Vec<u8>> {
+    if target.len() > IPS_MAX_SIZE {
+        return Err(invalid(
+            "IPS patches cannot address files larger than 16 MiB, use BPS instead",
+        ));
+    }
+
+    let mut out = b"PATCH".to_vec();
+    for (mut start, end) in changed_ranges(base, target) {
+        if start == IPS_EOF {
+            start -= 1;
+        }
+        while start < end {
+            let mut len = (end - start).min(IPS_MAX_RECORD);
+            // Split off single-byte runs so they can be stored as RLE records.
+            let run = target[start..start + len]
+                .iter()
+                .take_while(|&&b| b == target[start])
+                .count();
+            if run >= RLE_MIN {
+                len = run;
+            } else if let Some(next_run) = (start..start + len).find(|&i| {
+                target[i..start + len].len() >= RLE_MIN
+                    && target[i..i + RLE_MIN].iter().all(|&b| b == target[i])
+            }) {
+                len = next_run - start;
+            }
+            // The next record may not begin at the `EOF` offset either.
+            if start + len == IPS_EOF && start + len < end {
+                if len < IPS_MAX_RECORD {
+                    len += 1;
+                } else {
+                    len -= 1;
+                }
+            }
+            push_ips_record(&mut out, start, &target[start..start + len]);
+            start += len;
+        }
+    }
+    out.extend_from_slice(b"EOF");
+    if target.len() < base.len() {
+        out.extend_from_slice(&(target.len() as u32).to_be_bytes()[1..]);
+    }
+    Ok(out)
+}
+
+const BPS_SOURCE_READ: usize = 0;
+const BPS_TARGET_READ: usize = 1;
+
+/// Matching runs shorter than this are stored as literal data instead.
+const BPS_MIN_SOURCE_READ: usize = 4;
+
+fn push_bps_number(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let low = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(0x80 | low);
+            break;
+        }
+        out.push(low);
+        value -= 1;
+    }
+}
+
+fn push_bps_action(out: &mut Vec<u8>, action: usize, len: usize) {
+    push_bps_number(out, ((len - 1) << 2) | action);
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut c = i as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+        }
+        *entry = c;
+    }
+    !data.iter().fold(!0u32, |crc, &b| {
+        table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Creates a BPS patch turning `base` into `target`.
+///
+/// Data is only matched at the same offset in both files, which suits images where code
+/// and files keep their positions between builds.
+pub fn bps(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = b"BPS1".to_vec();
+    push_bps_number(&mut out, base.len());
+    push_bps_number(&mut out, target.len());
+    push_bps_number(&mut out, 0);
+
+    let matches = |i: usize| base.get(i) == Some(&target[i]);
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i < target.len() {
+        let run = (i..target.len()).take_while(|&j| matches(j)).count();
+        if run < BPS_MIN_SOURCE_READ && i + run < target.len() {
+            i += run.max(1);
+            continue;
+        }
+        if literal_start < i {
+            push_bps_action(&mut out, BPS_TARGET_READ, i - literal_start);
+            out.extend_from_slice(&target[literal_start..i]);
+        }
+        if run > 0 {
+            push_bps_action(&mut out, BPS_SOURCE_READ, run);
+        }
+        i += run;
+        literal_start = i;
+    }
+    if literal_start < target.len() {
+        push_bps_action(&mut out, BPS_TARGET_READ, target.len() - literal_start);
+        out.extend_from_slice(&target[literal_start..]);
+    }
+
+    out.extend_from_slice(&crc32(base).to_le_bytes());
+    out.extend_from_slice(&crc32(target).to_le_bytes());
+    let patch_crc = crc32(&out);
+    out.extend_from_slice(&patch_crc.to_le_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn be(bytes: &[u8]) -> usize {
+        bytes.iter().fold(0, |value, &b| value << 8 | b as usize)
+    }
+
+    /// Applies an IPS patch, returning the offsets of its records.
+    fn apply_ips(base: &[u8], patch: &[u8]) -> (Vec<u8>, Vec<usize>) {
+        assert_eq!(&patch[..5], b"PATCH");
+        let mut out = base.to_vec();
+        let mut offsets = vec![];
+        let mut at = 5;
+        while &patch[at..at + 3] != b"EOF" {
+            let offset = be(&patch[at..at + 3]);
+            let len = be(&patch[at + 3..at + 5]);
+            offsets.push(offset);
+            at += 5;
+            let data = if len == 0 {
+                let run = be(&patch[at..at + 2]);
+                at += 3;
+                vec![patch[at - 1]; run]
+            } else {
+                at += len;
+                patch[at - len..at].to_vec()
+            };
+            if out.len() < offset + data.len() {
+                out.resize(offset + data.len(), 0);
+            }
+            out[offset..offset + data.len()].copy_from_slice(&data);
+        }
+        at += 3;
+        if at < patch.len() {
+            out.truncate(be(&patch[at..at + 3]));
+        }
+        (out, offsets)
+    }
+
+    fn bps_number(patch: &[u8], at: &mut usize) -> usize {
+        let (mut value, mut shift) = (0, 1);
+        loop {
+            let b = patch[*at];
+            *at += 1;
+            value += (b as usize & 0x7f) * shift;
+            if b & 0x80 != 0 {
+                return value;
+            }
+            shift <<= 7;
+            value += shift;
+        }
+    }
+
+    /// Applies a BPS patch of source and target reads, checking its checksums.
+    fn apply_bps(base: &[u8], patch: &[u8]) -> Vec<u8> {
+        assert_eq!(&patch[..4], b"BPS1");
+        let footer = patch.len() - 12;
+        let crc = |at: usize| u32::from_le_bytes(patch[at..at + 4].try_into().unwrap());
+        assert_eq!(crc(footer + 8), crc32(&patch[..footer + 8]));
+        assert_eq!(crc(footer), crc32(base));
+
+        let mut at = 4;
+        assert_eq!(bps_number(patch, &mut at), base.len());
+        let target_len = bps_number(patch, &mut at);
+        let metadata = bps_number(patch, &mut at);
+        at += metadata;
+        let mut out = vec![];
+        while at < footer {
+            let action = bps_number(patch, &mut at);
+            let len = (action >> 2) + 1;
+            match action & 3 {
+                BPS_SOURCE_READ => out.extend_from_slice(&base[out.len()..out.len() + len]),
+                BPS_TARGET_READ => {
+                    out.extend_from_slice(&patch[at..at + len]);
+                    at += len;
+                }
+                action => panic!("unexpected action {action}"),
+            }
+        }
+        assert_eq!(out.len(), target_len);
+        assert_eq!(crc(footer + 4), crc32(&out));
+        out
+    }
+
+    fn sample(len: usize, seed: u8) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+            .collect()
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn ips_of_equal_files_is_empty() {
+        let base = sample(0x100, 0);
+        assert_eq!(ips(&base, &base).unwrap(), b"PATCHEOF");
+    }
+
+    #[test]
+    fn ips_stores_runs_as_rle() {
+        let base = vec![0; 0x40];
+        let mut target = base.clone();
+        target[0x10..0x10 + RLE_MIN].fill(0xaa);
+        let patch = ips(&base, &target).unwrap();
+        assert_eq!(patch, b"PATCH\x00\x00\x10\x00\x00\x00\x09\xaaEOF");
+
+        // Shorter runs are stored as they are.
+        target[0x10 + RLE_MIN - 1] = 0;
+        let patch = ips(&base, &target).unwrap();
+        assert_eq!(&patch[5..10], b"\x00\x00\x10\x00\x08");
+        assert_eq!(apply_ips(&base, &patch).0, target);
+    }
+
+    #[test]
+    fn ips_records_never_start_at_the_eof_offset() {
+        let base = vec![0; IPS_EOF + 0x20];
+        let mut target = base.clone();
+        target[IPS_EOF] = 1;
+        target[IPS_EOF + 3] = 2;
+        let patch = ips(&base, &target).unwrap();
+        let (patched, offsets) = apply_ips(&base, &patch);
+        assert_eq!(patched, target);
+        assert!(!offsets.contains(&IPS_EOF));
+
+        // Nor the record following one ending there.
+        let mut target = base.clone();
+        target[IPS_EOF - 4..IPS_EOF + 10].copy_from_slice(&sample(14, 1));
+        let patch = ips(&base, &target).unwrap();
+        let (patched, offsets) = apply_ips(&base, &patch);
+        assert_eq!(patched, target);
+        assert!(!offsets.contains(&IPS_EOF));
+    }
+
+    #[test]
+    fn ips_grows_and_truncates() {
+        let base = sample(0x80, 0);
+        let mut longer = base.clone();
+        longer.extend_from_slice(&sample(0x30, 7));
+        let patch = ips(&base, &longer).unwrap();
+        assert!(patch.ends_with(b"EOF"));
+        assert_eq!(apply_ips(&base, &patch).0, longer);
+
+        let shorter = &base[..0x50];
+        let patch = ips(&base, shorter).unwrap();
+        assert_eq!(patch, b"PATCHEOF\x00\x00\x50");
+        assert_eq!(apply_ips(&base, &patch).0, shorter);
+    }
+
+    #[test]
+    fn ips_rejects_files_past_16_mib() {
+        assert!(ips(&[], &vec![0; IPS_MAX_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn bps_numbers_use_the_offset_encoding() {
+        let encode = |value| {
+            let mut out = vec![];
+            push_bps_number(&mut out, value);
+            out
+        };
+        assert_eq!(encode(0), [0x80]);
+        assert_eq!(encode(0x7f), [0xff]);
+        assert_eq!(encode(0x80), [0x00, 0x80]);
+        assert_eq!(encode(0x407f), [0x7f, 0xff]);
+        assert_eq!(encode(0x4080), [0x00, 0x00, 0x80]);
+    }
+
+    #[test]
+    fn bps_round_trips() {
+        let base = sample(0x200, 0);
+        let mut target = base.clone();
+        target[0x10..0x20].copy_from_slice(&sample(0x10, 3));
+        target[0x101] ^= 0xff;
+        target.extend_from_slice(&sample(0x40, 9));
+        for (base, target) in [
+            (&base[..], &target[..]),
+            (&target[..], &base[..]),
+            (&[][..], &target[..]),
+            (&base[..], &[][..]),
+        ] {
+            assert_eq!(apply_bps(base, &bps(base, target)), target);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! The stages linked executables go through after linking, declared per cargo profile in
+//! the `[pipeline]` table of `rbrew.toml`:
+//!
+//! ```toml
+//! [pipeline]
+//! dev = ["convert"]
+//! release = ["strip", "convert", { stage = "compress", format = "yaz0" }, "package"]
+//! ```
+//!
+//! Stages run in the order they are listed. `strip` removes debug information from the
+//! ELF, so it comes before `convert`, which runs the output type's converter and is
+//! required. The stages after it work on the outputs: `compress` compresses each output
+//! and `package` zips them up into a single output, so listing `package` first
+//! compresses the zip. Size budgets are checked on the converted outputs, before these
+//! stages run. Profiles without a pipeline only convert.
+//!
+//! Intermediate files are kept in a `rbrew-pipeline` directory next to the linked
+//! executables, and stages are skipped when their input did not change since the build
+//! that wrote their output, see [`Cache`].
+
+use super::compress::Compression;
+use crate::config;
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// The directory intermediate files are kept in, next to the linked executables.
+pub const CACHE_DIR: &str = "rbrew-pipeline";
+
+pub enum Stage {
+    Strip,
+    Convert,
+    Compress(Compression),
+    Package,
+}
+
+impl Stage {
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::Strip => "strip",
+            Stage::Convert => "convert",
+            Stage::Compress(_) => "compress",
+            Stage::Package => "package",
+        }
+    }
+
+    /// Whether the stage works on the linked ELF rather than on the outputs.
+    fn works_on_elf(&self) -> bool {
+        matches!(self, Stage::Strip)
+    }
+
+    fn parse(value: &toml::Value, key: &str) -> io::Result<Self> {
+        let invalid = |msg: &str| config::invalid(format!("'pipeline.{key}': {msg}"));
+        let (name, table) = match value {
+            toml::Value::String(name) => (name.as_str(), None),
+            toml::Value::Table(table) => match table.get("stage") {
+                Some(toml::Value::String(name)) => (name.as_str(), Some(table)),
+                _ => return Err(invalid("stage tables must name their stage with 'stage'")),
+            },
+            _ => return Err(invalid("stages must be names or tables")),
+        };
+        Ok(match name {
+            "strip" => Stage::Strip,
+            "convert" => Stage::Convert,
+            "compress" => {
+                let format = match table.and_then(|table| table.get("format")) {
+                    None => Compression::Zlib,
+                    Some(format) => format
+                        .as_str()
+                        .and_then(Compression::from_name)
+                        .ok_or_else(|| {
+                            invalid("the compression format must be \"yaz0\" or \"zlib\"")
+                        })?,
+                };
+                Stage::Compress(format)
+            }
+            "package" => Stage::Package,
+            _ => return Err(invalid(&format!("unknown stage '{name}'"))),
+        })
+    }
+}
+
+/// The stages of one profile, in order.
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self {
+            stages: vec![Stage::Convert],
+        }
+    }
+}
+
+impl Pipeline {
+    /// Reads the pipeline of a cargo profile from `rbrew.toml`, only converting if it
+    /// declares none.
+    pub fn load(profile: &str) -> io::Result<Self> {
+        let Some(stages) =
+            config::load_table("pipeline")?.and_then(|mut pipeline| pipeline.remove(profile))
+        else {
+            return Ok(Self::default());
+        };
+        let stages = match stages {
+            toml::Value::Array(stages) => stages
+                .iter()
+                .map(|stage| Stage::parse(stage, profile))
+                .collect::<io::Result<Vec<_>>>()?,
+            _ => {
+                return Err(config::invalid(format!(
+                    "'pipeline.{profile}' must be a list of stages"
+                )))
+            }
+        };
+
+        let converts = stages
+            .iter()
+            .filter(|stage| matches!(stage, Stage::Convert))
+            .count();
+        if converts != 1 {
+            return Err(config::invalid(format!(
+                "'pipeline.{profile}' must have a single 'convert' stage"
+            )));
+        }
+        let pipeline = Self { stages };
+        if let Some(stage) = pipeline
+            .before_convert()
+            .iter()
+            .find(|stage| !stage.works_on_elf())
+        {
+            return Err(config::invalid(format!(
+                "'pipeline.{profile}' runs '{}' before 'convert', which it works on the outputs of",
+                stage.name()
+            )));
+        }
+        if let Some(stage) = pipeline
+            .after_convert()
+            .iter()
+            .find(|stage| stage.works_on_elf())
+        {
+            return Err(config::invalid(format!(
+                "'pipeline.{profile}' runs '{}' after 'convert', but it works on the ELF",
+                stage.name()
+            )));
+        }
+        Ok(pipeline)
+    }
+
+    fn convert_index(&self) -> usize {
+        self.stages
+            .iter()
+            .position(|stage| matches!(stage, Stage::Convert))
+            .unwrap_or(self.stages.len())
+    }
+
+    /// The stages run on each linked ELF, in order.
+    pub fn before_convert(&self) -> &[Stage] {
+        &self.stages[..self.convert_index()]
+    }
+
+    /// The stages run on the outputs, in order.
+    pub fn after_convert(&self) -> &[Stage] {
+        &self.stages[(self.convert_index() + 1).min(self.stages.len())..]
+    }
+
+    /// Whether a stage zips the outputs up.
+    pub fn packages(&self) -> bool {
+        self.after_convert()
+            .iter()
+            .any(|stage| matches!(stage, Stage::Package))
+    }
+}
+
+fn hash(parts: &[&[u8]]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    parts.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Skips stages whose output is still the one written from the same input.
+///
+/// Every output written through the cache is recorded in a key file holding the hashes
+/// of the stage's input and of the output.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn key_path(&self, stage: &str, output: &Path) -> PathBuf {
+        let name = output.file_name().unwrap_or_default().to_string_lossy();
+        self.dir.join(format!("{name}.{stage}.key"))
+    }
+
+    /// Writes what `run` makes of `input` to `output`, unless the stage already did.
+    /// Returns whether `run` was called.
+    pub fn run(
+        &self,
+        stage: &str,
+        input: &[u8],
+        output: &Path,
+        run: impl FnOnce(&[u8]) -> io::Result<Vec<u8>>,
+    ) -> io::Result<bool> {
+        let key_path = self.key_path(stage, output);
+        let input_hash = hash(&[stage.as_bytes(), input]);
+        if let (Ok(key), Ok(existing)) = (std::fs::read_to_string(&key_path), std::fs::read(output))
+        {
+            if key == format!("{input_hash:016x} {:016x}", hash(&[&existing[..]])) {
+                return Ok(false);
+            }
+        }
+
+        let data = run(input)?;
+        std::fs::write(output, &data)?;
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(
+            key_path,
+            format!("{input_hash:016x} {:016x}", hash(&[&data[..]])),
+        )?;
+        Ok(true)
+    }
+}
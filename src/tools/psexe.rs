@@ -0,0 +1,63 @@
+//! Conversion of PlayStation executables to the PS-EXE format the BIOS loads.
+//!
+//! A PS-EXE is a 2 KiB header followed by the memory image of the program, padded to
+//! whole CD sectors. The BIOS copies the image to its load address, zeroes the bss named
+//! in the header and jumps to the entry point.
+
+use crate::elf::{self, Elf};
+use std::{io, path::Path};
+
+const HEADER_SIZE: usize = 0x800;
+/// The image is read from CD a sector at a time.
+const SECTOR_SIZE: usize = 0x800;
+/// The stack the BIOS sets up, at the top of the 2 MiB of main RAM.
+const STACK_TOP: u32 = 0x801f_fff0;
+/// The region marker the BIOS expects at the end of the header.
+const MARKER: &[u8] = b"Sony Computer Entertainment Inc. for North America area";
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn put_u32(exe: &mut [u8], offset: usize, value: u32) {
+    exe[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Converts a linked ELF to a PS-EXE.
+pub fn elf2psexe(input: impl AsRef<Path>, output: impl AsRef<Path>) -> io::Result<()> {
+    let elf = Elf::read(input)?;
+    let (base, mut image) = elf.load_image()?;
+    if image.is_empty() {
+        return Err(invalid("the ELF has nothing to load"));
+    }
+    let end = base + image.len() as u64;
+    if !(base..end).contains(&elf.entry) {
+        return Err(invalid(format!(
+            "the entry point 0x{:08x} lies outside the image at 0x{base:08x}..0x{end:08x}",
+            elf.entry
+        )));
+    }
+    image.resize(image.len().next_multiple_of(SECTOR_SIZE), 0);
+
+    // The register $gp is set from the header, for code addressing small data through it.
+    let gp = elf
+        .symbols()?
+        .into_iter()
+        .find(|symbol| symbol.name == "_gp" && symbol.shndx != elf::SHN_UNDEF)
+        .map_or(0, |symbol| symbol.value);
+    let (bss_start, bss_end) = elf.zero_fill_range().unwrap_or_default();
+
+    let mut exe = vec![0; HEADER_SIZE];
+    exe[..8].copy_from_slice(b"PS-X EXE");
+    put_u32(&mut exe, 0x10, elf.entry as u32);
+    put_u32(&mut exe, 0x14, gp as u32);
+    put_u32(&mut exe, 0x18, base as u32);
+    put_u32(&mut exe, 0x1c, image.len() as u32);
+    put_u32(&mut exe, 0x28, bss_start as u32);
+    put_u32(&mut exe, 0x2c, (bss_end - bss_start) as u32);
+    put_u32(&mut exe, 0x30, STACK_TOP);
+    exe[0x4c..0x4c + MARKER.len()].copy_from_slice(MARKER);
+    exe.extend_from_slice(&image);
+
+    std::fs::write(output, exe)
+}
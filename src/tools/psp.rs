@@ -0,0 +1,294 @@
+//! Packaging of PSP executables as the `EBOOT.PBP` the XMB launches.
+//!
+//! A PBP bundles the `PARAM.SFO` metadata, optional artwork and the program itself, which
+//! is converted to a PRX: a relocatable module the kernel loads at any address. The ELF
+//! has to be linked at 0 with `--emit-relocs`. The metadata is read from `rbrew.toml`:
+//!
+//! ```toml
+//! [sfo]
+//! title = "Game"
+//! disc_id = "UCJS10041"
+//! version = "1.00"
+//! icon = "icon0.png"
+//! ```
+
+use crate::{
+    config,
+    elf::{self, Elf},
+};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// The file name the XMB looks for in `PSP/GAME/<name>/`.
+pub const EBOOT_NAME: &str = "EBOOT.PBP";
+
+/// The ELF type of PRX modules.
+const ET_SCE_PRX: u16 = 0xffa0;
+/// The section type of the relocations the kernel applies when loading a PRX.
+const SHT_SCE_REL: u32 = 0x7000_00a0;
+const MODULE_INFO: &str = ".rodata.sceModuleInfo";
+
+const PBP_HEADER_SIZE: usize = 0x28;
+const SFO_HEADER_SIZE: usize = 0x14;
+const SFO_ENTRY_SIZE: usize = 0x10;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn put_u32(data: &mut [u8], offset: usize, value: u32) {
+    data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn le_u32(data: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+fn le_u16(data: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap()) as usize
+}
+
+/// The metadata the XMB displays for an application.
+pub struct Sfo {
+    pub title: String,
+    pub disc_id: String,
+    pub version: String,
+    /// A 144x80 PNG shown as the icon, stored as `ICON0.PNG`.
+    pub icon: Option<PathBuf>,
+}
+
+enum SfoValue<'a> {
+    String(&'a str, usize),
+    Integer(u32),
+}
+
+impl Sfo {
+    /// Reads the metadata from `rbrew.toml`, using `title` unless it names one.
+    pub fn load(title: &str) -> io::Result<Self> {
+        let sfo = config::load_table("sfo")?.unwrap_or_default();
+        let field = |name: &str| match sfo.get(name) {
+            None => Ok(None),
+            Some(toml::Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(config::invalid(format!("'sfo.{name}' must be a string"))),
+        };
+        Ok(Self {
+            title: field("title")?.unwrap_or_else(|| title.to_string()),
+            // The disc ID homebrew conventionally uses.
+            disc_id: field("disc_id")?.unwrap_or_else(|| "UCJS10041".to_string()),
+            version: field("version")?.unwrap_or_else(|| "1.00".to_string()),
+            icon: field("icon")?.map(PathBuf::from),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // A bootable game, region free and for all ages. The keys are sorted, and strings
+        // get a fixed capacity as the XMB expects.
+        let entries = [
+            ("BOOTABLE", SfoValue::Integer(1)),
+            ("CATEGORY", SfoValue::String("MG", 4)),
+            ("DISC_ID", SfoValue::String(&self.disc_id, 16)),
+            ("DISC_VERSION", SfoValue::String(&self.version, 8)),
+            ("PARENTAL_LEVEL", SfoValue::Integer(1)),
+            ("PSP_SYSTEM_VER", SfoValue::String("1.00", 8)),
+            ("REGION", SfoValue::Integer(0x8000)),
+            ("TITLE", SfoValue::String(&self.title, 128)),
+        ];
+
+        let mut index = vec![0; SFO_HEADER_SIZE + entries.len() * SFO_ENTRY_SIZE];
+        let mut keys = vec![];
+        let mut values = vec![];
+        for (i, (key, value)) in entries.iter().enumerate() {
+            let entry = SFO_HEADER_SIZE + i * SFO_ENTRY_SIZE;
+            put_u16(&mut index, entry, keys.len() as u16);
+            keys.extend_from_slice(key.as_bytes());
+            keys.push(0);
+            put_u32(&mut index, entry + 12, values.len() as u32);
+            let (format, len, capacity) = match value {
+                SfoValue::String(text, capacity) => {
+                    // Leave room for the terminator without splitting a character.
+                    let mut end = text.len().min(capacity - 1);
+                    while !text.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    values.extend_from_slice(&text.as_bytes()[..end]);
+                    values.resize(values.len() + capacity - end, 0);
+                    (0x0204, end + 1, *capacity)
+                }
+                SfoValue::Integer(value) => {
+                    values.extend_from_slice(&value.to_le_bytes());
+                    (0x0404, 4, 4)
+                }
+            };
+            put_u16(&mut index, entry + 2, format);
+            put_u32(&mut index, entry + 4, len as u32);
+            put_u32(&mut index, entry + 8, capacity as u32);
+        }
+        keys.resize(keys.len().next_multiple_of(4), 0);
+
+        index[..4].copy_from_slice(b"\0PSF");
+        put_u32(&mut index, 0x04, 0x0101);
+        let key_offset = index.len();
+        put_u32(&mut index, 0x08, key_offset as u32);
+        put_u32(&mut index, 0x0c, (key_offset + keys.len()) as u32);
+        put_u32(&mut index, 0x10, entries.len() as u32);
+
+        let mut sfo = index;
+        sfo.extend_from_slice(&keys);
+        sfo.extend_from_slice(&values);
+        sfo
+    }
+}
+
+/// Converts a linked ELF to a PRX module.
+///
+/// The loadable sections are merged into one segment, and the relocations against them
+/// are turned into the kind the kernel applies, made relative to that segment.
+fn elf2prx(input: &Path) -> io::Result<Vec<u8>> {
+    let elf = Elf::read(input)?;
+    if elf.class != elf::Class::Elf32
+        || elf.endian != elf::Endian::Little
+        || elf.machine != elf::EM_MIPS
+        || elf.kind != elf::ET_EXEC
+    {
+        return Err(invalid("the ELF is not a little endian MIPS executable"));
+    }
+
+    let segments: Vec<&elf::Segment> = elf
+        .segments
+        .iter()
+        .filter(|segment| segment.kind == elf::PT_LOAD && segment.memsz != 0)
+        .collect();
+    let &[segment] = segments.as_slice() else {
+        return Err(invalid(format!(
+            "the ELF has {} segments instead of one, link it with targets/psp.ld",
+            segments.len()
+        )));
+    };
+    if segment.vaddr != 0 {
+        return Err(invalid(format!(
+            "the segment is at 0x{:08x} instead of 0, link it with targets/psp.ld",
+            segment.vaddr
+        )));
+    }
+    let segment = segment.clone();
+    let module_info = elf
+        .section_by_name(MODULE_INFO)
+        .ok_or_else(|| {
+            invalid(format!(
+                "the ELF has no '{MODULE_INFO}' section, link it with rbrew-psp"
+            ))
+        })?
+        .offset;
+    let symbols = elf.symbols()?;
+
+    // Only the relocations against the image are kept, minus the GP and PC relative ones
+    // which stay valid wherever it is loaded.
+    let mut relocations = vec![];
+    for (index, section) in elf.sections.iter().enumerate() {
+        if section.kind != elf::SHT_REL {
+            continue;
+        }
+        let targets_image = elf
+            .sections
+            .get(section.info as usize)
+            .is_some_and(elf::Section::is_alloc);
+        if !targets_image {
+            continue;
+        }
+        let kept: Vec<elf::Relocation> = elf
+            .relocations(section)?
+            .into_iter()
+            .filter(|relocation| {
+                !matches!(relocation.kind, elf::R_MIPS_GPREL16 | elf::R_MIPS_PC16)
+                    && symbols
+                        .get(relocation.sym as usize)
+                        .is_some_and(|symbol| !symbol.is_undefined())
+            })
+            .collect();
+        relocations.push((index, section.offset as usize, kept));
+    }
+
+    let mut prx = elf.into_data();
+    let (phoff, shoff) = (le_u32(&prx, 0x1c), le_u32(&prx, 0x20));
+    let shentsize = le_u16(&prx, 0x2e);
+    put_u16(&mut prx, 0x10, ET_SCE_PRX);
+    put_u16(&mut prx, 0x2c, 1);
+
+    for (index, offset, kept) in relocations {
+        for (i, relocation) in kept.iter().enumerate() {
+            put_u32(&mut prx, offset + i * 8, relocation.offset as u32);
+            // Both the position and the target are relative to segment 0.
+            put_u32(&mut prx, offset + i * 8 + 4, relocation.kind);
+        }
+        let header = shoff + index * shentsize;
+        put_u32(&mut prx, header + 4, SHT_SCE_REL);
+        put_u32(&mut prx, header + 20, (kept.len() * 8) as u32);
+    }
+
+    // The kernel finds the module info through the physical address of the segment.
+    let header = phoff;
+    for (offset, value) in [
+        (0x00, elf::PT_LOAD as u64),
+        (0x04, segment.offset),
+        (0x08, 0),
+        (0x0c, module_info),
+        (0x10, segment.filesz),
+        (0x14, segment.memsz),
+        (0x18, (elf::PF_X | elf::PF_R) as u64),
+        (0x1c, 0x10),
+    ] {
+        put_u32(&mut prx, header + offset, value as u32);
+    }
+    Ok(prx)
+}
+
+/// Packages a linked ELF as an `EBOOT.PBP` with the given metadata.
+pub fn elf2pbp(input: impl AsRef<Path>, output: impl AsRef<Path>, sfo: &Sfo) -> io::Result<()> {
+    let prx = elf2prx(input.as_ref())?;
+    let icon = match &sfo.icon {
+        Some(path) => {
+            let icon = std::fs::read(path).map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("failed to read the icon '{}': {err}", path.display()),
+                )
+            })?;
+            if !icon.starts_with(b"\x89PNG") {
+                return Err(invalid(format!(
+                    "the icon '{}' is not a PNG",
+                    path.display()
+                )));
+            }
+            icon
+        }
+        None => vec![],
+    };
+
+    // PARAM.SFO, ICON0.PNG, ICON1.PMF, PIC0.PNG, PIC1.PNG, SND0.AT3, DATA.PSP and
+    // DATA.PSAR. Missing files are empty and start where the next one does.
+    let files = [
+        sfo.to_bytes(),
+        icon,
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        prx,
+        vec![],
+    ];
+    let mut pbp = vec![0; PBP_HEADER_SIZE];
+    pbp[..4].copy_from_slice(b"\0PBP");
+    put_u32(&mut pbp, 0x04, 0x0001_0000);
+    for (i, file) in files.iter().enumerate() {
+        let offset = pbp.len() as u32;
+        put_u32(&mut pbp, 0x08 + i * 4, offset);
+        pbp.extend_from_slice(file);
+    }
+    std::fs::write(output, pbp)
+}
@@ -0,0 +1,264 @@
+//! Emitting of linked ELFs as Intel HEX and Motorola S-record files, the text formats
+//! flash programmers read.
+//!
+//! Every loadable segment is written at its load address, so gaps between segments are
+//! left unprogrammed. The number of data bytes per record, and an offset added to every
+//! address to move the image to where the programmer expects it, are read from the
+//! `[hex]` or `[srec]` table of `rbrew.toml`:
+//!
+//! ```toml
+//! [hex]
+//! record_size = 32
+//! offset = "-0x08000000"
+//! ```
+
+use crate::{
+    config,
+    elf::{self, Elf},
+};
+use std::{fmt::Write, io, path::Path};
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// The record formats.
+#[derive(Clone, Copy)]
+pub enum RecordFormat {
+    IntelHex,
+    Srec,
+}
+
+impl RecordFormat {
+    fn table(self) -> &'static str {
+        match self {
+            RecordFormat::IntelHex => "hex",
+            RecordFormat::Srec => "srec",
+        }
+    }
+
+    /// The most data bytes a record can hold, as the byte count also covers the address
+    /// and checksum for S-records.
+    fn max_record_size(self) -> usize {
+        match self {
+            RecordFormat::IntelHex => 255,
+            RecordFormat::Srec => 250,
+        }
+    }
+}
+
+/// How the records are laid out.
+pub struct RecordOptions {
+    /// The number of data bytes per record.
+    pub record_size: usize,
+    /// Added to every address, including the entry point.
+    pub offset: i64,
+}
+
+impl RecordOptions {
+    /// Reads the options for a format from `rbrew.toml`, with 16 bytes per record and no
+    /// offset by default.
+    pub fn load(format: RecordFormat) -> io::Result<Self> {
+        let name = format.table();
+        let table = config::load_table(name)?.unwrap_or_default();
+        let record_size = match table.get("record_size") {
+            None => 16,
+            Some(value) => value
+                .as_integer()
+                .and_then(|size| usize::try_from(size).ok())
+                .filter(|size| (1..=format.max_record_size()).contains(size))
+                .ok_or_else(|| {
+                    config::invalid(format!(
+                        "'{name}.record_size' must be between 1 and {}",
+                        format.max_record_size()
+                    ))
+                })?,
+        };
+        let offset = match table.get("offset") {
+            None => 0,
+            Some(toml::Value::Integer(offset)) => *offset,
+            // TOML has no negative hexadecimal integers, so a sign is allowed in strings.
+            Some(toml::Value::String(str)) if str.trim().starts_with('-') => {
+                config::parse_size(&toml::Value::String(str.trim()[1..].into()))
+                    .and_then(|offset| i64::try_from(offset).ok())
+                    .map(|offset| -offset)
+                    .ok_or_else(|| {
+                        config::invalid(format!("'{name}.offset' is not a valid offset"))
+                    })?
+            }
+            Some(value) => config::parse_size(value)
+                .and_then(|offset| i64::try_from(offset).ok())
+                .ok_or_else(|| config::invalid(format!("'{name}.offset' is not a valid offset")))?,
+        };
+        Ok(Self {
+            record_size,
+            offset,
+        })
+    }
+}
+
+/// Appends a record, made of its fields and the checksum over them, as hex digits.
+fn push_record(out: &mut String, start: &str, fields: &[u8], checksum: u8) {
+    out.push_str(start);
+    for b in fields {
+        write!(out, "{b:02X}").unwrap();
+    }
+    writeln!(out, "{checksum:02X}").unwrap();
+}
+
+fn sum(fields: &[u8]) -> u8 {
+    fields.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn intel_hex(chunks: &[(u32, &[u8])], entry: u32) -> String {
+    let mut out = String::new();
+    let record = |out: &mut String, address: u16, kind: u8, data: &[u8]| {
+        let mut fields = vec![data.len() as u8];
+        fields.extend_from_slice(&address.to_be_bytes());
+        fields.push(kind);
+        fields.extend_from_slice(data);
+        push_record(out, ":", &fields, sum(&fields).wrapping_neg());
+    };
+    // Addresses beyond 64 KiB are reached through the extended linear address record.
+    let mut upper = 0;
+    for &(address, data) in chunks {
+        if address >> 16 != upper {
+            upper = address >> 16;
+            record(&mut out, 0, 0x04, &(upper as u16).to_be_bytes());
+        }
+        record(&mut out, address as u16, 0x00, data);
+    }
+    record(&mut out, 0, 0x05, &entry.to_be_bytes());
+    record(&mut out, 0, 0x01, &[]);
+    out
+}
+
+fn srec(chunks: &[(u32, &[u8])], entry: u32, name: &str) -> String {
+    let mut out = String::new();
+    let record = |out: &mut String, kind: u8, address: &[u8], data: &[u8]| {
+        let mut fields = vec![(address.len() + data.len() + 1) as u8];
+        fields.extend_from_slice(address);
+        fields.extend_from_slice(data);
+        push_record(out, &format!("S{kind}"), &fields, !sum(&fields));
+    };
+    // The narrowest addresses that fit every record, which also select the termination
+    // record.
+    let end = chunks
+        .iter()
+        .map(|&(address, data)| address as u64 + data.len() as u64)
+        .chain([entry as u64 + 1])
+        .max()
+        .unwrap_or_default();
+    let width = match end - 1 {
+        0..=0xffff => 2,
+        0x1_0000..=0xff_ffff => 3,
+        _ => 4,
+    };
+    let address = |address: u32| address.to_be_bytes()[4 - width..].to_vec();
+
+    let name: Vec<u8> = name.bytes().take(64).collect();
+    record(&mut out, 0, &[0, 0], &name);
+    for &(start, data) in chunks {
+        record(&mut out, width as u8 - 1, &address(start), data);
+    }
+    match chunks.len() {
+        count @ 0..=0xffff => record(&mut out, 5, &(count as u16).to_be_bytes(), &[]),
+        count @ 0x1_0000..=0xff_ffff => {
+            record(&mut out, 6, &(count as u32).to_be_bytes()[1..], &[])
+        }
+        _ => {}
+    }
+    record(&mut out, 11 - width as u8, &address(entry), &[]);
+    out
+}
+
+/// Writes a linked ELF as records in the given format.
+pub fn elf2records(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    format: RecordFormat,
+    options: &RecordOptions,
+    name: &str,
+) -> io::Result<()> {
+    let elf = Elf::read(input)?;
+    let relocate = |address: u64| {
+        u32::try_from(address as i64 + options.offset).map_err(|_| {
+            invalid(format!(
+                "0x{address:08x} offset by {} lies outside the 32-bit address space",
+                options.offset
+            ))
+        })
+    };
+
+    let mut chunks = vec![];
+    let mut segments: Vec<&elf::Segment> = elf
+        .segments
+        .iter()
+        .filter(|segment| segment.kind == elf::PT_LOAD && segment.filesz != 0)
+        .collect();
+    segments.sort_by_key(|segment| segment.paddr);
+    for segment in segments {
+        let data = elf.segment_data(segment)?;
+        let start = relocate(segment.paddr)?;
+        relocate(segment.paddr + data.len() as u64 - 1)?;
+        let mut offset = 0;
+        while offset < data.len() {
+            let address = start + offset as u32;
+            // Intel HEX records cannot cross a 64 KiB boundary.
+            let to_boundary = 0x1_0000 - (address & 0xffff) as usize;
+            let len = options
+                .record_size
+                .min(to_boundary)
+                .min(data.len() - offset);
+            chunks.push((address, &data[offset..offset + len]));
+            offset += len;
+        }
+    }
+    let entry = relocate(elf.entry)?;
+
+    let text = match format {
+        RecordFormat::IntelHex => intel_hex(&chunks, entry),
+        RecordFormat::Srec => srec(&chunks, entry, name),
+    };
+    std::fs::write(output, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intel_hex_switches_the_upper_address() {
+        let text = intel_hex(&[(0xfffe, &[1, 2]), (0x1_0000, &[3])], 0x0801_0000);
+        assert_eq!(
+            text,
+            ":02FFFE000102FE\n\
+             :020000040001F9\n\
+             :0100000003FC\n\
+             :0400000508010000EE\n\
+             :00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn intel_hex_starts_in_the_first_64_kib() {
+        let text = intel_hex(&[(0x0800_0000, &[0xaa])], 0x0800_0000);
+        assert!(text.starts_with(":020000040800F2\n:01000000AA55\n"));
+    }
+
+    #[test]
+    fn srec_widens_the_addresses_to_fit() {
+        let text = srec(&[(0x1000, &[0xde, 0xad])], 0x1000, "a");
+        assert_eq!(
+            text,
+            "S0040000619A\n\
+             S1051000DEAD5F\n\
+             S5030001FB\n\
+             S9031000EC\n"
+        );
+
+        let text = srec(&[(0x0100_0000, &[0])], 0x0100_0000, "");
+        assert_eq!(text.lines().nth(1), Some("S3060100000000F8"));
+        assert_eq!(text.lines().last(), Some("S70501000000F9"));
+    }
+}
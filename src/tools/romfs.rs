@@ -0,0 +1,127 @@
+//! Packing of a directory into the read-only filesystem `rbrew build --romfs` adds to
+//! DOLs, see [`rbrew_shared_types::romfs`] for its layout.
+//!
+//! The image is loaded by an extra data section right after the program's bss, and its
+//! address is written into the slot the runtime reserves, which `rbrew_gc::romfs::get`
+//! reads back.
+
+use super::{validate::MemoryRegion, Dol};
+use rbrew_shared_types::romfs::{
+    encode_slot, DATA_ALIGN, ENTRY_SIZE, HEADER_SIZE, MAGIC, SLOT_MAGIC, SLOT_SIZE,
+};
+use std::{io, path::Path};
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Collects the files below `dir`, with their paths relative to `root`.
+fn collect(root: &Path, dir: &Path, files: &mut Vec<(String, Vec<u8>)>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect(root, &path, files)?;
+            continue;
+        }
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        let name = relative
+            .iter()
+            .map(|component| {
+                component
+                    .to_str()
+                    .ok_or_else(|| invalid(format!("'{}' is not named in UTF-8", path.display())))
+            })
+            .collect::<io::Result<Vec<_>>>()?
+            .join("/");
+        files.push((name, std::fs::read(&path)?));
+    }
+    Ok(())
+}
+
+/// Packs the files below `dir` into an image, sorted by path.
+pub fn pack(dir: &Path) -> io::Result<Vec<u8>> {
+    let mut files = vec![];
+    collect(dir, dir, &mut files)?;
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let too_large = || invalid("the romfs passes 4 GiB");
+    let offset = |len: usize| u32::try_from(len).map_err(|_| too_large());
+    let entries_end = HEADER_SIZE + files.len() * ENTRY_SIZE;
+    let paths_len: usize = files.iter().map(|(name, _)| name.len()).sum();
+    let mut out = Vec::with_capacity(entries_end + paths_len);
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&offset(files.len())?.to_be_bytes());
+    out.resize(entries_end, 0);
+
+    let mut path_offsets = vec![];
+    for (name, _) in &files {
+        path_offsets.push(offset(out.len())?);
+        out.extend_from_slice(name.as_bytes());
+    }
+    for (index, (name, data)) in files.iter().enumerate() {
+        out.resize(out.len().next_multiple_of(DATA_ALIGN), 0);
+        let entry = HEADER_SIZE + index * ENTRY_SIZE;
+        let fields = [
+            path_offsets[index],
+            offset(name.len())?,
+            offset(out.len())?,
+            offset(data.len())?,
+        ];
+        for (i, field) in fields.into_iter().enumerate() {
+            out[entry + i * 4..entry + i * 4 + 4].copy_from_slice(&field.to_be_bytes());
+        }
+        out.extend_from_slice(data);
+    }
+    out.resize(out.len().next_multiple_of(DATA_ALIGN), 0);
+    offset(out.len())?;
+    Ok(out)
+}
+
+/// Adds `image` to `dol` as a data section after the memory it occupies, filling the
+/// romfs slot of its data with the image's address. Returns the address.
+///
+/// The image has to fit in the region of `regions` the DOL ends in.
+pub fn embed(dol: &mut Dol, image: Vec<u8>, regions: &[MemoryRegion]) -> io::Result<u32> {
+    let slots: Vec<(usize, usize)> = dol
+        .data
+        .iter()
+        .enumerate()
+        .flat_map(|(index, section)| {
+            section
+                .data
+                .windows(SLOT_SIZE)
+                .enumerate()
+                .filter(|(_, window)| window.starts_with(&SLOT_MAGIC))
+                .map(move |(start, _)| (index, start))
+        })
+        .collect();
+    let (section, start) = match slots[..] {
+        [slot] => slot,
+        [] => {
+            return Err(invalid(
+                "the DOL has no romfs slot, reference `rbrew_gc::romfs::get` to keep it",
+            ))
+        }
+        _ => return Err(invalid("the DOL has more than one romfs slot")),
+    };
+
+    let (_, end) = dol.extent();
+    let address = (end as usize).next_multiple_of(DATA_ALIGN) as u32;
+    let image_end = address as u64 + image.len() as u64;
+    if let Some(region) = regions
+        .iter()
+        .find(|region| (region.start..=region.end).contains(&(end as u64)))
+    {
+        if image_end > region.end {
+            return Err(invalid(format!(
+                "the romfs of 0x{:x} bytes at 0x{address:08x} passes the end of {region}",
+                image.len()
+            )));
+        }
+    }
+
+    dol.data[section].data[start..start + SLOT_SIZE]
+        .copy_from_slice(&encode_slot(address, image.len() as u32));
+    super::dol::inject(dol, address, image, false)?;
+    Ok(address)
+}
@@ -0,0 +1,373 @@
+//! Conversion of Wii U executables to the RPX format the system loader runs.
+//!
+//! An RPX is an ELF without program headers. The loader places the code, data and its own
+//! load region wherever it sees fit, telling them apart by the addresses the sections were
+//! linked at, and applies the relocations kept with `--emit-relocs`. Sections are
+//! compressed with zlib, and two sections are added: the CRCs of all sections and the
+//! file info, which sizes the regions and the program's stack and heap.
+//!
+//! Calls into the system libraries go through import sections, which rbrew does not
+//! generate.
+
+use crate::elf::{self, Elf};
+use std::{io, path::Path};
+
+/// The ELF type and ABI of RPL modules, which RPX executables are a kind of.
+const ET_CAFE_RPL: u16 = 0xfe01;
+const ELFOSABI_CAFE: u8 = 0xca;
+const ELFABIVERSION_CAFE: u8 = 0xfe;
+const SHT_RPL_CRCS: u32 = 0x8000_0003;
+const SHT_RPL_FILEINFO: u32 = 0x8000_0004;
+/// Marks sections holding their uncompressed size and a zlib stream.
+const SHF_RPL_ZLIB: u64 = 0x0800_0000;
+
+const CODE_BASE: u64 = 0x0200_0000;
+const DATA_BASE: u64 = 0x1000_0000;
+const LOAD_BASE: u64 = 0xc000_0000;
+
+/// The loader applies the 16-bit halves of PC relative addresses, but not whole ones.
+const R_PPC_GHS_REL16_HI: u32 = 252;
+const R_PPC_GHS_REL16_LO: u32 = 253;
+/// The relocation types the loader applies.
+const RELOCATIONS: &[u32] = &[
+    elf::R_PPC_NONE,
+    elf::R_PPC_ADDR32,
+    elf::R_PPC_ADDR16_LO,
+    elf::R_PPC_ADDR16_HI,
+    elf::R_PPC_ADDR16_HA,
+    elf::R_PPC_REL24,
+    elf::R_PPC_REL14,
+    R_PPC_GHS_REL16_HI,
+    R_PPC_GHS_REL16_LO,
+];
+
+const HEADER_SIZE: usize = 0x40;
+const SECTION_HEADER_SIZE: usize = 0x28;
+const FILE_INFO_SIZE: usize = 0x60;
+/// Section data starts on 64-byte boundaries in the file.
+const FILE_ALIGN: usize = 64;
+/// Smaller sections are not worth compressing.
+const COMPRESS_MIN_SIZE: usize = 0x18;
+const COMPRESSION_LEVEL: u8 = 6;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn put_u32(data: &mut [u8], offset: usize, value: u64) {
+    data[offset..offset + 4].copy_from_slice(&(value as u32).to_be_bytes());
+}
+
+fn put_u16(data: &mut [u8], offset: usize, value: u16) {
+    data[offset..offset + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+/// A section of the RPX being built, with its contents uncompressed.
+struct RpxSection {
+    header: elf::Section,
+    data: Vec<u8>,
+}
+
+impl RpxSection {
+    fn null() -> Self {
+        Self {
+            header: elf::Section {
+                name: String::new(),
+                kind: elf::SHT_NULL,
+                flags: 0,
+                addr: 0,
+                offset: 0,
+                size: 0,
+                link: 0,
+                info: 0,
+                addralign: 0,
+                entsize: 0,
+            },
+            data: vec![],
+        }
+    }
+
+    /// The size the section occupies in memory once loaded.
+    fn memory_size(&self) -> u64 {
+        if self.header.is_nobits() {
+            self.header.size
+        } else {
+            self.data.len() as u64
+        }
+    }
+}
+
+/// Rewrites relocations to the types the loader applies.
+fn convert_relocations(relocations: &[elf::Relocation]) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    let mut push = |offset: u64, sym: u32, kind: u32, addend: i64| {
+        out.extend_from_slice(&(offset as u32).to_be_bytes());
+        out.extend_from_slice(&((sym << 8) | kind).to_be_bytes());
+        out.extend_from_slice(&(addend as i32).to_be_bytes());
+    };
+    for relocation in relocations {
+        let elf::Relocation {
+            offset,
+            sym,
+            kind,
+            addend,
+        } = *relocation;
+        match kind {
+            // Each half is relative to its own position, so the low one is adjusted by
+            // the two bytes it lies further.
+            elf::R_PPC_REL32 => {
+                push(offset, sym, R_PPC_GHS_REL16_HI, addend);
+                push(offset + 2, sym, R_PPC_GHS_REL16_LO, addend + 2);
+            }
+            kind if RELOCATIONS.contains(&kind) => push(offset, sym, kind, addend),
+            kind => {
+                return Err(invalid(format!(
+                    "relocation type {kind} at 0x{offset:08x} cannot be applied by the loader"
+                )))
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// The file info section, derived from the sizes of the regions.
+fn file_info(sections: &[RpxSection], sda_base: u64, sda2_base: u64) -> Vec<u8> {
+    let (mut text_size, mut data_size, mut load_size, mut temp_size) = (0, 0, 0, 0);
+    let (mut text_align, mut data_align, mut load_align) = (32, 4096, 4);
+    for section in sections {
+        let addr = section.header.addr;
+        let end = addr + section.memory_size();
+        let align = section.header.addralign;
+        if (CODE_BASE..DATA_BASE).contains(&addr) {
+            text_size = u64::max(text_size, end - CODE_BASE);
+            text_align = u64::max(text_align, align);
+        } else if (DATA_BASE..LOAD_BASE).contains(&addr) {
+            data_size = u64::max(data_size, end - DATA_BASE);
+            data_align = u64::max(data_align, align);
+        } else if addr >= LOAD_BASE {
+            load_size = u64::max(load_size, end - LOAD_BASE);
+            load_align = u64::max(load_align, align);
+        } else if !matches!(
+            section.header.kind,
+            elf::SHT_NULL | SHT_RPL_CRCS | SHT_RPL_FILEINFO
+        ) {
+            // Everything else, like the relocations, is only needed while loading.
+            temp_size += section.memory_size() + 128;
+        }
+    }
+
+    let mut info = vec![0; FILE_INFO_SIZE];
+    for (offset, value) in [
+        (0x00, 0xcafe_0402),
+        (0x04, text_size),
+        (0x08, text_align),
+        (0x0c, data_size),
+        (0x10, data_align),
+        (0x14, load_size),
+        (0x18, load_align),
+        (0x1c, temp_size),
+        (0x24, sda_base),
+        (0x28, sda2_base),
+        // The stack size, the flags marking an RPX rather than a library, and the heap
+        // size.
+        (0x2c, 0x1_0000),
+        (0x34, 0x2),
+        (0x38, 0x8000),
+        // The minimum system version and the SDK the module claims to be built with.
+        (0x40, 0x5078),
+        (0x44, COMPRESSION_LEVEL as u64),
+        (0x50, 0x5335),
+        (0x54, 0x10d4b),
+    ] {
+        put_u32(&mut info, offset, value);
+    }
+    info
+}
+
+/// Converts a linked ELF to an RPX.
+pub fn elf2rpx(input: impl AsRef<Path>, output: impl AsRef<Path>) -> io::Result<()> {
+    let elf = Elf::read(input)?;
+    if elf.class != elf::Class::Elf32
+        || elf.endian != elf::Endian::Big
+        || elf.machine != elf::EM_PPC
+        || elf.kind != elf::ET_EXEC
+    {
+        return Err(invalid("the ELF is not a big endian PowerPC executable"));
+    }
+    if !elf
+        .sections
+        .iter()
+        .any(|section| section.kind == elf::SHT_RELA)
+    {
+        return Err(invalid(
+            "the ELF has no relocations for the loader, link it with --emit-relocs",
+        ));
+    }
+    let symbols = elf.symbols()?;
+    let symbol = |name: &str| {
+        symbols
+            .iter()
+            .find(|symbol| symbol.name == name && !symbol.is_undefined())
+            .map_or(0, |symbol| symbol.value)
+    };
+    let (sda_base, sda2_base) = (symbol("_SDA_BASE_"), symbol("_SDA2_BASE_"));
+
+    // Sections keep their indices, so the symbols and relocations referring to them stay
+    // valid. What the loader has no use for, like debug info, is replaced by null
+    // sections.
+    let mut sections = vec![];
+    let mut load_end = LOAD_BASE;
+    for header in &elf.sections {
+        let targets_image = || {
+            elf.sections
+                .get(header.info as usize)
+                .is_some_and(elf::Section::is_alloc)
+        };
+        let mut section = RpxSection {
+            header: header.clone(),
+            data: vec![],
+        };
+        match header.kind {
+            elf::SHT_NULL => section = RpxSection::null(),
+            elf::SHT_RELA if targets_image() => {
+                section.data = convert_relocations(&elf.relocations(header)?)?;
+            }
+            // Filled in once all sections are known.
+            elf::SHT_STRTAB if header.name == ".shstrtab" => {}
+            // The symbols and their names are read by the loader from its load region.
+            elf::SHT_SYMTAB | elf::SHT_STRTAB => {
+                section.data = elf.section_data(header)?.to_vec();
+                let align = header.addralign.max(4);
+                section.header.addr = load_end.next_multiple_of(align);
+                section.header.flags |= elf::SHF_ALLOC;
+                load_end = section.header.addr + section.data.len() as u64;
+            }
+            elf::SHT_NOBITS if header.is_alloc() => {}
+            _ if header.is_alloc() => section.data = elf.section_data(header)?.to_vec(),
+            _ => section = RpxSection::null(),
+        }
+        sections.push(section);
+    }
+
+    let mut crcs = RpxSection::null();
+    crcs.header.kind = SHT_RPL_CRCS;
+    crcs.header.addralign = 4;
+    crcs.header.entsize = 4;
+    let mut info = RpxSection::null();
+    info.header.kind = SHT_RPL_FILEINFO;
+    info.header.addralign = 4;
+    sections.push(crcs);
+    sections.push(info);
+
+    // The section names, which the loader needs neither loaded nor compressed.
+    let mut shstrtab = vec![0];
+    let mut names = vec![];
+    for section in &sections {
+        names.push(match section.header.name.as_str() {
+            "" => 0,
+            name => {
+                let offset = shstrtab.len();
+                shstrtab.extend_from_slice(name.as_bytes());
+                shstrtab.push(0);
+                offset
+            }
+        });
+    }
+    let shstrndx = sections
+        .iter()
+        .position(|section| section.header.name == ".shstrtab")
+        .ok_or_else(|| invalid("the ELF has no section names"))?;
+    let names_section = &mut sections[shstrndx];
+    names_section.header.kind = elf::SHT_STRTAB;
+    names_section.header.flags = 0;
+    names_section.header.addr = 0;
+    names_section.data = shstrtab;
+
+    let (crc_index, info_index) = (sections.len() - 2, sections.len() - 1);
+    sections[info_index].data = file_info(&sections, sda_base, sda2_base);
+
+    // The CRCs cover every section but their own, before compression.
+    let mut crc_data = vec![];
+    for section in &sections {
+        let crc = match section.header.kind {
+            SHT_RPL_CRCS => 0,
+            _ if section.data.is_empty() => 0,
+            _ => super::patch::crc32(&section.data),
+        };
+        crc_data.extend_from_slice(&crc.to_be_bytes());
+    }
+    sections[crc_index].data = crc_data;
+
+    // The CRCs, the file info, then data, load region, code and the rest, as the loader
+    // reads them in that order.
+    let rank = |section: &RpxSection| match section.header.kind {
+        SHT_RPL_CRCS => 0,
+        SHT_RPL_FILEINFO => 1,
+        _ if (DATA_BASE..LOAD_BASE).contains(&section.header.addr) => 2,
+        _ if section.header.addr >= LOAD_BASE => 3,
+        _ if section.header.is_exec() => 4,
+        _ => 5,
+    };
+    let mut order: Vec<usize> = (0..sections.len()).collect();
+    order.sort_by_key(|&index| rank(&sections[index]));
+
+    let mut rpx = vec![0; HEADER_SIZE + sections.len() * SECTION_HEADER_SIZE];
+    for index in order {
+        let section = &mut sections[index];
+        if section.data.is_empty() {
+            section.header.offset = 0;
+            continue;
+        }
+        let compress = section.data.len() >= COMPRESS_MIN_SIZE
+            && !matches!(section.header.kind, SHT_RPL_CRCS | SHT_RPL_FILEINFO)
+            && index != shstrndx;
+        let contents = if compress {
+            section.header.flags |= SHF_RPL_ZLIB;
+            let mut contents = (section.data.len() as u32).to_be_bytes().to_vec();
+            contents.extend(miniz_oxide::deflate::compress_to_vec_zlib(
+                &section.data,
+                COMPRESSION_LEVEL,
+            ));
+            contents
+        } else {
+            std::mem::take(&mut section.data)
+        };
+        rpx.resize(rpx.len().next_multiple_of(FILE_ALIGN), 0);
+        section.header.offset = rpx.len() as u64;
+        section.header.size = contents.len() as u64;
+        rpx.extend_from_slice(&contents);
+    }
+
+    rpx[..4].copy_from_slice(b"\x7fELF");
+    rpx[4..9].copy_from_slice(&[1, 2, 1, ELFOSABI_CAFE, ELFABIVERSION_CAFE]);
+    put_u16(&mut rpx, 0x10, ET_CAFE_RPL);
+    put_u16(&mut rpx, 0x12, elf::EM_PPC);
+    put_u32(&mut rpx, 0x14, 1);
+    put_u32(&mut rpx, 0x18, elf.entry);
+    put_u32(&mut rpx, 0x20, HEADER_SIZE as u64);
+    put_u16(&mut rpx, 0x28, 0x34);
+    put_u16(&mut rpx, 0x2a, 0x20);
+    put_u16(&mut rpx, 0x2e, SECTION_HEADER_SIZE as u16);
+    put_u16(&mut rpx, 0x30, sections.len() as u16);
+    put_u16(&mut rpx, 0x32, shstrndx as u16);
+    for (i, section) in sections.iter().enumerate() {
+        let header = HEADER_SIZE + i * SECTION_HEADER_SIZE;
+        let h = &section.header;
+        for (offset, value) in [
+            (0x00, names[i] as u64),
+            (0x04, h.kind as u64),
+            (0x08, h.flags),
+            (0x0c, h.addr),
+            (0x10, h.offset),
+            (0x14, h.size),
+            (0x18, h.link as u64),
+            (0x1c, h.info as u64),
+            (0x20, h.addralign),
+            (0x24, h.entsize),
+        ] {
+            put_u32(&mut rpx, header + offset, value);
+        }
+    }
+
+    std::fs::write(output, rpx)
+}
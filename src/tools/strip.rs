@@ -0,0 +1,147 @@
+//! Stripping of debug information from linked ELFs.
+//!
+//! Debug sections are emptied rather than removed, so section indices, and with them
+//! symbols and relocations, stay valid. Everything the program loads keeps its place in
+//! the file; the remaining non-allocated sections are packed after it.
+
+use crate::elf::{self, Class, Elf, Endian, Section};
+use std::io;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+fn is_debug(section: &Section) -> bool {
+    !section.is_alloc()
+        && (section.name.starts_with(".debug")
+            || section.name.starts_with(".zdebug")
+            || section.name == ".comment")
+}
+
+fn read_word(data: &[u8], offset: usize, class: Class, endian: Endian) -> u64 {
+    match (class, endian) {
+        (Class::Elf32, Endian::Little) => {
+            u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as u64
+        }
+        (Class::Elf32, Endian::Big) => {
+            u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as u64
+        }
+        (Class::Elf64, Endian::Little) => {
+            u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+        }
+        (Class::Elf64, Endian::Big) => {
+            u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap())
+        }
+    }
+}
+
+fn write_word(data: &mut [u8], offset: usize, value: u64, class: Class, endian: Endian) {
+    match (class, endian) {
+        (Class::Elf32, Endian::Little) => {
+            data[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes())
+        }
+        (Class::Elf32, Endian::Big) => {
+            data[offset..offset + 4].copy_from_slice(&(value as u32).to_be_bytes())
+        }
+        (Class::Elf64, Endian::Little) => {
+            data[offset..offset + 8].copy_from_slice(&value.to_le_bytes())
+        }
+        (Class::Elf64, Endian::Big) => {
+            data[offset..offset + 8].copy_from_slice(&value.to_be_bytes())
+        }
+    }
+}
+
+/// Returns a copy of a linked ELF without its debug information and the relocations
+/// against it.
+pub fn strip_debug(elf: &Elf) -> io::Result<Vec<u8>> {
+    let (class, endian) = (elf.class, elf.endian);
+    let data = elf.data();
+    let (word, shoff_at, shentsize_at) = match class {
+        Class::Elf32 => (4, 0x20, 0x2e),
+        Class::Elf64 => (8, 0x28, 0x3a),
+    };
+    let half = |offset: usize| {
+        let b = [data[offset], data[offset + 1]];
+        match endian {
+            Endian::Little => u16::from_le_bytes(b),
+            Endian::Big => u16::from_be_bytes(b),
+        }
+    };
+    let shoff = read_word(data, shoff_at, class, endian) as usize;
+    let shentsize = half(shentsize_at) as usize;
+    let phoff = read_word(data, shoff_at - word, class, endian) as usize;
+    let phentsize = half(shentsize_at - 4) as usize;
+    let headers_end = shoff + elf.sections.len() * shentsize;
+    if data.len() < headers_end {
+        return Err(invalid("the section headers lie outside the file"));
+    }
+    // Where the section offset and size are kept in a section header.
+    let (offset_at, size_at) = match class {
+        Class::Elf32 => (16, 20),
+        Class::Elf64 => (24, 32),
+    };
+
+    let stripped: Vec<bool> = elf
+        .sections
+        .iter()
+        .map(|section| {
+            is_debug(section)
+                || (matches!(section.kind, elf::SHT_REL | elf::SHT_RELA)
+                    && elf
+                        .sections
+                        .get(section.info as usize)
+                        .is_some_and(is_debug))
+        })
+        .collect();
+
+    // The headers and everything loaded are kept as they are.
+    let segments_end = elf
+        .segments
+        .iter()
+        .map(|segment| segment.offset + segment.filesz)
+        .chain(
+            elf.sections
+                .iter()
+                .filter(|section| section.is_alloc() && !section.is_nobits())
+                .map(|section| section.offset + section.size),
+        )
+        .max()
+        .unwrap_or_default() as usize;
+    let kept_end = segments_end.max(phoff + elf.segments.len() * phentsize);
+    let mut out = data[..kept_end.min(data.len())].to_vec();
+    out.resize(kept_end, 0);
+
+    let mut headers = data[shoff..headers_end].to_vec();
+    let mut order: Vec<usize> = (0..elf.sections.len()).collect();
+    order.sort_by_key(|&index| elf.sections[index].offset);
+    for index in order {
+        let section = &elf.sections[index];
+        let header = index * shentsize;
+        if stripped[index] {
+            write_word(&mut headers, header + offset_at, 0, class, endian);
+            write_word(&mut headers, header + size_at, 0, class, endian);
+            continue;
+        }
+        let end = (section.offset + section.size) as usize;
+        if section.kind == elf::SHT_NULL || section.is_nobits() || end <= kept_end {
+            continue;
+        }
+        let align = section.addralign.max(1) as usize;
+        out.resize(out.len().next_multiple_of(align), 0);
+        write_word(
+            &mut headers,
+            header + offset_at,
+            out.len() as u64,
+            class,
+            endian,
+        );
+        out.extend_from_slice(elf.section_data(section)?);
+    }
+
+    out.resize(out.len().next_multiple_of(word), 0);
+    let new_shoff = out.len() as u64;
+    out.extend_from_slice(&headers);
+    write_word(&mut out, shoff_at, new_shoff, class, endian);
+    Ok(out)
+}
@@ -0,0 +1,325 @@
+//! Conversion of 3DS executables to the `.3dsx` format homebrew loaders run.
+//!
+//! A 3DSX holds the code, rodata and data segments along with relocation tables, as the
+//! loader may map the segments anywhere. The relocations are taken from the ELF, which
+//! has to be linked with `--emit-relocs`. The SMDH metadata shown by the loader is read
+//! from `rbrew.toml`:
+//!
+//! ```toml
+//! [smdh]
+//! title = "Game"
+//! description = "A game"
+//! author = "Someone"
+//! ```
+
+use crate::{
+    config,
+    elf::{self, Elf},
+};
+use std::{collections::BTreeSet, io, path::Path};
+
+/// The address the segments are linked at.
+pub const BASE: u64 = 0x0010_0000;
+const PAGE: u64 = 0x1000;
+
+const HEADER_SIZE: u16 = 0x20;
+const EXTENDED_HEADER_SIZE: u16 = 0x0c;
+const RELOCATION_HEADER_SIZE: u16 = 8;
+const SMDH_SIZE: usize = 0x36c0;
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// The metadata homebrew loaders display for an application.
+pub struct Smdh {
+    pub title: String,
+    pub description: String,
+    pub author: String,
+}
+
+impl Smdh {
+    /// Reads the metadata from `rbrew.toml`, using `title` unless it names one.
+    pub fn load(title: &str) -> io::Result<Self> {
+        let smdh = config::load_table("smdh")?.unwrap_or_default();
+        let field = |name: &str, default: &str| match smdh.get(name) {
+            None => Ok(default.to_string()),
+            Some(toml::Value::String(value)) => Ok(value.clone()),
+            Some(_) => Err(config::invalid(format!("'smdh.{name}' must be a string"))),
+        };
+        Ok(Self {
+            title: field("title", title)?,
+            description: field("description", "Built with rbrew")?,
+            author: field("author", "Unknown")?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut smdh = vec![0; SMDH_SIZE];
+        smdh[..4].copy_from_slice(b"SMDH");
+        let put = |smdh: &mut [u8], offset: usize, len: usize, text: &str| {
+            for (i, c) in text.encode_utf16().take(len / 2 - 1).enumerate() {
+                smdh[offset + i * 2..offset + i * 2 + 2].copy_from_slice(&c.to_le_bytes());
+            }
+        };
+        // Every language gets the same strings.
+        for language in 0..16 {
+            let start = 0x08 + language * 0x200;
+            put(&mut smdh, start, 0x80, &self.title);
+            put(&mut smdh, start + 0x80, 0x100, &self.description);
+            put(&mut smdh, start + 0x180, 0x80, &self.author);
+        }
+        // Region free, visible in the menu, 3D allowed and play time recorded. The icons
+        // stay blank.
+        smdh[0x2018..0x201c].copy_from_slice(&0x7fff_ffff_u32.to_le_bytes());
+        smdh[0x2028..0x202c].copy_from_slice(&0x0105_u32.to_le_bytes());
+        smdh
+    }
+}
+
+struct Segment {
+    address: u64,
+    /// The size reserved for the segment, including the zero-initialized tail.
+    size: u64,
+    data: Vec<u8>,
+    absolute: BTreeSet<u64>,
+    relative: BTreeSet<u64>,
+}
+
+/// Splits a set of word indices into the `(skip, patch)` runs of a 3DSX relocation table.
+fn relocation_table(words: &BTreeSet<u64>) -> Vec<(u16, u16)> {
+    let mut table = vec![];
+    let mut cursor = 0;
+    let mut words = words.iter().copied().peekable();
+    while let Some(start) = words.next() {
+        let mut end = start + 1;
+        while words.next_if_eq(&end).is_some() {
+            end += 1;
+        }
+        let mut skip = start - cursor;
+        while skip > u16::MAX as u64 {
+            table.push((u16::MAX, 0));
+            skip -= u16::MAX as u64;
+        }
+        let mut patch = end - start;
+        while patch > u16::MAX as u64 {
+            table.push((skip as u16, u16::MAX));
+            skip = 0;
+            patch -= u16::MAX as u64;
+        }
+        table.push((skip as u16, patch as u16));
+        cursor = end;
+    }
+    table
+}
+
+/// Converts a linked ELF to a `.3dsx` executable with the given metadata.
+pub fn elf2threedsx(
+    input: impl AsRef<Path>,
+    output: impl AsRef<Path>,
+    smdh: &Smdh,
+) -> io::Result<()> {
+    let elf = Elf::read(input)?;
+    if elf.entry != BASE {
+        return Err(invalid(format!(
+            "the entry point 0x{:08x} must be at the start of the code, 0x{BASE:08x}",
+            elf.entry
+        )));
+    }
+
+    // The code, rodata and data segments, told apart by their permissions.
+    let mut segments: [Option<Segment>; 3] = [None, None, None];
+    for segment in &elf.segments {
+        if segment.kind != elf::PT_LOAD || segment.memsz == 0 {
+            continue;
+        }
+        let index = match (
+            segment.flags & elf::PF_X != 0,
+            segment.flags & elf::PF_W != 0,
+        ) {
+            (true, false) => 0,
+            (false, false) => 1,
+            (false, true) => 2,
+            (true, true) => return Err(invalid("a segment is both writable and executable")),
+        };
+        if segments[index].is_some() {
+            return Err(invalid(
+                "the ELF must have at most one code, rodata and data segment each",
+            ));
+        }
+        segments[index] = Some(Segment {
+            address: segment.vaddr,
+            size: segment.memsz,
+            data: elf.segment_data(segment)?.to_vec(),
+            absolute: BTreeSet::new(),
+            relative: BTreeSet::new(),
+        });
+    }
+
+    // The loader expects the segments back to back, each starting on a page.
+    let mut address = BASE;
+    let mut segments = segments.map(|segment| {
+        segment.unwrap_or(Segment {
+            address: 0,
+            size: 0,
+            data: vec![],
+            absolute: BTreeSet::new(),
+            relative: BTreeSet::new(),
+        })
+    });
+    for (segment, name) in segments.iter_mut().zip(["code", "rodata", "data"]) {
+        if segment.size == 0 {
+            segment.address = address;
+            continue;
+        }
+        if segment.address != address {
+            return Err(invalid(format!(
+                "the {name} segment is at 0x{:08x} instead of 0x{address:08x}, link it with targets/3ds.ld",
+                segment.address
+            )));
+        }
+        address = (segment.address + segment.size).next_multiple_of(PAGE);
+    }
+    let top = address;
+    let segment_of = |address: u64| {
+        segments.iter().position(|segment| {
+            (segment.address..segment.address + segment.size).contains(&address)
+        })
+    };
+
+    // Rebase every pointer into the image, storing it relative to the base. The loader
+    // translates it back once it knows where each segment is mapped.
+    let mut patches = vec![];
+    for section in &elf.sections {
+        if section.kind != elf::SHT_REL && section.kind != elf::SHT_RELA {
+            continue;
+        }
+        let target = elf
+            .sections
+            .get(section.info as usize)
+            .ok_or_else(|| invalid(format!("'{}' has an invalid target", section.name)))?;
+        if !target.is_alloc() {
+            continue;
+        }
+        for relocation in elf.relocations(section)? {
+            let (kind, position) = (relocation.kind, relocation.offset);
+            if !matches!(
+                kind,
+                elf::R_ARM_ABS32 | elf::R_ARM_TARGET1 | elf::R_ARM_REL32 | elf::R_ARM_PREL31
+            ) {
+                continue;
+            }
+            let Some(index) = segment_of(position) else {
+                continue;
+            };
+            if !position.is_multiple_of(4) {
+                return Err(invalid(format!(
+                    "the relocation at 0x{position:08x} is not word aligned"
+                )));
+            }
+            let segment = &segments[index];
+            let Some(word) = segment
+                .data
+                .get((position - segment.address) as usize..)
+                .and_then(|data| data.get(..4))
+            else {
+                // Relocations in the zero-initialized tail have nothing to patch.
+                continue;
+            };
+            let word = u32::from_le_bytes(word.try_into().unwrap());
+            let (pointer, subtype, relative) = match kind {
+                elf::R_ARM_REL32 => (position.wrapping_add(word as u64) as u32 as u64, 0, true),
+                elf::R_ARM_PREL31 => {
+                    let offset = ((word << 1) as i32 >> 1) as i64;
+                    (position.wrapping_add_signed(offset) as u32 as u64, 1, true)
+                }
+                _ => (word as u64, 0, false),
+            };
+            if !(BASE..top).contains(&pointer) {
+                continue;
+            }
+            // References within a segment stay valid wherever it is mapped.
+            if relative && segment_of(pointer) == Some(index) {
+                continue;
+            }
+            let value = (subtype << 28) | (pointer - BASE) as u32;
+            patches.push((index, position, value, relative));
+        }
+    }
+    for (index, position, value, relative) in patches {
+        let segment = &mut segments[index];
+        let offset = (position - segment.address) as usize;
+        segment.data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        let word = (position - segment.address) / 4;
+        match relative {
+            true => segment.relative.insert(word),
+            false => segment.absolute.insert(word),
+        };
+    }
+
+    // Code and rodata are stored in full up to the next segment, data only as far as it
+    // has contents.
+    let mut stored = vec![];
+    for (index, segment) in segments.iter().enumerate() {
+        let size = match index {
+            2 => (segment.data.len() as u64).next_multiple_of(4),
+            _ => segment.size.next_multiple_of(PAGE),
+        };
+        let mut data = segment.data.clone();
+        data.resize(size as usize, 0);
+        stored.push(data);
+    }
+    let data_size = segments[2]
+        .size
+        .next_multiple_of(4)
+        .max(stored[2].len() as u64);
+    let bss_size = data_size - stored[2].len() as u64;
+
+    let tables: Vec<[Vec<(u16, u16)>; 2]> = segments
+        .iter()
+        .map(|segment| {
+            [
+                relocation_table(&segment.absolute),
+                relocation_table(&segment.relative),
+            ]
+        })
+        .collect();
+
+    let mut out = vec![];
+    let header_size = HEADER_SIZE + EXTENDED_HEADER_SIZE;
+    out.extend_from_slice(b"3DSX");
+    out.extend_from_slice(&header_size.to_le_bytes());
+    out.extend_from_slice(&RELOCATION_HEADER_SIZE.to_le_bytes());
+    // The format version and flags.
+    out.extend_from_slice(&[0; 8]);
+    for size in [
+        stored[0].len() as u64,
+        stored[1].len() as u64,
+        data_size,
+        bss_size,
+    ] {
+        out.extend_from_slice(&(size as u32).to_le_bytes());
+    }
+    let extended_header = out.len();
+    out.extend_from_slice(&[0; EXTENDED_HEADER_SIZE as usize]);
+    for [absolute, relative] in &tables {
+        out.extend_from_slice(&(absolute.len() as u32).to_le_bytes());
+        out.extend_from_slice(&(relative.len() as u32).to_le_bytes());
+    }
+    for data in &stored {
+        out.extend_from_slice(data);
+    }
+    for (skip, patch) in tables.iter().flatten().flatten() {
+        out.extend_from_slice(&skip.to_le_bytes());
+        out.extend_from_slice(&patch.to_le_bytes());
+    }
+
+    // The SMDH follows the tables. There is no RomFS.
+    let smdh_offset = out.len() as u32;
+    out.extend_from_slice(&smdh.to_bytes());
+    out[extended_header..extended_header + 4].copy_from_slice(&smdh_offset.to_le_bytes());
+    out[extended_header + 4..extended_header + 8]
+        .copy_from_slice(&(SMDH_SIZE as u32).to_le_bytes());
+
+    std::fs::write(output, out)
+}
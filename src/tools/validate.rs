@@ -0,0 +1,204 @@
+use crate::elf::{self, Elf};
+use std::{borrow::Cow, fmt::Display, io, path::Path};
+
+/// A range of memory a program may be loaded into.
+#[derive(Clone)]
+pub struct MemoryRegion {
+    pub name: Cow<'static, str>,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Display for MemoryRegion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} 0x{:08x}..0x{:08x}", self.name, self.start, self.end)
+    }
+}
+
+/// What a platform requires of a linked ELF before it can be converted.
+pub struct LayoutRules {
+    pub machine: u16,
+    pub regions: Cow<'static, [MemoryRegion]>,
+    /// Required alignment of every load address.
+    pub load_alignment: u64,
+    pub relocations: &'static [u32],
+    /// Whether programs are position independent and relocate themselves at startup, so
+    /// they keep their dynamic relocations.
+    pub position_independent: bool,
+}
+
+struct Range {
+    what: String,
+    start: u64,
+    end: u64,
+}
+
+/// Describes every range overlapping one before it. A range is compared with the one
+/// reaching the furthest among those starting before it, which a range nested in a
+/// larger one does not hide.
+fn overlaps(ranges: &mut [Range]) -> Vec<String> {
+    ranges.sort_by_key(|range| range.start);
+    let mut issues = vec![];
+    let mut furthest: Option<&Range> = None;
+    for range in ranges.iter() {
+        match furthest {
+            Some(before) if range.start < before.end => {
+                issues.push(format!(
+                    "{} (0x{:08x}..0x{:08x}) overlaps {} (0x{:08x}..0x{:08x})",
+                    before.what, before.start, before.end, range.what, range.start, range.end
+                ));
+                if range.end > before.end {
+                    furthest = Some(range);
+                }
+            }
+            _ => furthest = Some(range),
+        }
+    }
+    issues
+}
+
+/// Checks a linked ELF against the platform's layout rules.
+///
+/// Returns every problem found, so that a single build reports all of them at once.
+/// `relocatable` selects the checks for partially linked objects, such as REL modules,
+/// which have no load addresses yet.
+pub fn validate(path: &Path, rules: &LayoutRules, relocatable: bool) -> io::Result<Vec<String>> {
+    let elf = Elf::read(path)?;
+    let mut issues = vec![];
+
+    if elf.machine != rules.machine {
+        issues.push(format!(
+            "machine type is {} but the platform expects {}",
+            elf.machine, rules.machine
+        ));
+    }
+    match (elf.kind, relocatable, rules.position_independent) {
+        (elf::ET_EXEC, false, false) | (elf::ET_DYN, false, true) | (elf::ET_REL, true, _) => {}
+        (elf::ET_DYN, false, false) => {
+            issues.push("the ELF is position independent, which cannot be loaded".to_string())
+        }
+        (elf::ET_EXEC, false, true) => issues.push(
+            "the ELF is not position independent, but the platform loads it anywhere".to_string(),
+        ),
+        (kind, _, _) => issues.push(format!("unexpected ELF type {kind}")),
+    }
+
+    let mut ranges = vec![];
+    if !relocatable {
+        for (i, segment) in elf.segments.iter().enumerate() {
+            if segment.kind != elf::PT_LOAD || segment.memsz == 0 {
+                continue;
+            }
+            ranges.push(Range {
+                what: format!("segment {i}"),
+                start: segment.vaddr,
+                end: segment.vaddr + segment.memsz,
+            });
+            if !segment.vaddr.is_multiple_of(rules.load_alignment) {
+                issues.push(format!(
+                    "segment {i} loads at 0x{:08x}, which is not {}-byte aligned",
+                    segment.vaddr, rules.load_alignment
+                ));
+            }
+        }
+
+        issues.extend(overlaps(&mut ranges));
+
+        for section in &elf.sections {
+            if !section.is_alloc() || section.size == 0 {
+                continue;
+            }
+            let (start, end) = (section.addr, section.addr + section.size);
+            let inside = rules
+                .regions
+                .iter()
+                .any(|region| region.start <= start && end <= region.end);
+            if !inside {
+                issues.push(format!(
+                    "section '{}' (0x{start:08x}..0x{end:08x}) lies outside the usable memory ({})",
+                    section.name,
+                    rules
+                        .regions
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        }
+
+        let entry_mapped = elf.segments.iter().any(|segment| {
+            segment.kind == elf::PT_LOAD
+                && segment.flags & elf::PF_X != 0
+                && segment.vaddr <= elf.entry
+                && elf.entry < segment.vaddr + segment.memsz
+        });
+        if !entry_mapped {
+            issues.push(format!(
+                "the entry point 0x{:08x} is not inside an executable segment",
+                elf.entry
+            ));
+        }
+    }
+
+    for section in &elf.sections {
+        if section.kind != elf::SHT_RELA && section.kind != elf::SHT_REL {
+            continue;
+        }
+        if section.is_alloc() && !relocatable && !rules.position_independent {
+            issues.push(format!(
+                "'{}' holds dynamic relocations, which nothing will apply at load time",
+                section.name
+            ));
+            continue;
+        }
+        let mut unexpected: Vec<u32> = elf
+            .relocations(section)?
+            .iter()
+            .map(|reloc| reloc.kind)
+            .filter(|kind| !rules.relocations.contains(kind))
+            .collect();
+        unexpected.sort_unstable();
+        unexpected.dedup();
+        if !unexpected.is_empty() {
+            issues.push(format!(
+                "'{}' uses unsupported relocation types {unexpected:?}",
+                section.name
+            ));
+        }
+    }
+
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(what: &str, start: u64, end: u64) -> Range {
+        Range {
+            what: what.to_string(),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn overlaps_past_nested_ranges() {
+        let mut ranges = [
+            range("a", 0x50, 0x60),
+            range("b", 0x0, 0x100),
+            range("c", 0x10, 0x20),
+        ];
+        let issues = overlaps(&mut ranges);
+        assert_eq!(issues.len(), 2);
+        assert!(issues[0].starts_with("b ") && issues[0].contains("overlaps c "));
+        assert!(issues[1].starts_with("b ") && issues[1].contains("overlaps a "));
+    }
+
+    #[test]
+    fn touching_ranges_do_not_overlap() {
+        let mut ranges = [range("a", 0x0, 0x10), range("b", 0x10, 0x20)];
+        assert!(overlaps(&mut ranges).is_empty());
+    }
+}